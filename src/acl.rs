@@ -0,0 +1,240 @@
+/*
+Filters on the POSIX access control lists of files, on Linux.
+
+Permission bits only say what the owner, the group and everyone else may do.
+An ACL can grant more users and groups access, which filters on the mode
+can't see. 'HasAcl' retains the files with an ACL beyond their mode, or, for
+directories, a default ACL handed down to new files: those ls -l shows with
+a '+'. 'AclGrants' retains the files whose ACL grants a user or group at
+least some permissions, written like setfacl does, e.g. 'user:alice:rw' or
+'g:admins:x'. Only the entries naming the user or group count, not the owner
+or the group of the file, and permissions taken away by the mask of the ACL
+aren't granted.
+
+ACLs are read from the extended attributes they are kept in, see the 'xattr'
+module. Users and groups given by name are looked up in /etc/passwd and
+/etc/group.
+
+Only available with the 'acl' feature, on Linux.
+
+ */
+
+use std::fs;
+use std::io::{self, Error};
+use std::path::Path;
+use crate::filter::{FileInfo, Filter, MetadataMode};
+use crate::trace::{self, Level};
+use crate::xattr;
+
+
+const ACCESS: &str = "system.posix_acl_access";
+const DEFAULT: &str = "system.posix_acl_default";
+const VERSION: u32 = 2;
+
+const USER_OBJ: u16 = 0x01;
+const USER: u16 = 0x02;
+const GROUP_OBJ: u16 = 0x04;
+const GROUP: u16 = 0x08;
+const MASK: u16 = 0x10;
+const OTHER: u16 = 0x20;
+
+/// The entries of an ACL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Acl {
+    entries: Vec<AclEntry>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct AclEntry {
+    tag: u16,
+    perms: u16,
+    id: u32,
+}
+
+/// A user or a group, by id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Principal {
+    User(u32),
+    Group(u32),
+}
+
+/// Permissions an ACL grants a user or group, see `AclGrants`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Grant {
+    pub principal: Principal,
+    /// The permission bits, read 4, write 2 and execute 1.
+    pub perms: u16,
+    arg: String,
+}
+
+impl Acl {
+
+    /// Parses an ACL as the kernel keeps it in an extended attribute.
+    pub fn parse(bytes: &[u8]) -> Result<Acl, Error> {
+        let invalid = || Error::new(io::ErrorKind::InvalidData, "invalid ACL");
+        let (version, rest) = bytes.split_first_chunk::<4>().ok_or_else(invalid)?;
+        if u32::from_le_bytes(*version) != VERSION || rest.len() % 8 != 0 {
+            return Err(invalid());
+        }
+        let entries = rest.chunks_exact(8)
+            .map(|entry| AclEntry {
+                tag: u16::from_le_bytes([entry[0], entry[1]]),
+                perms: u16::from_le_bytes([entry[2], entry[3]]),
+                id: u32::from_le_bytes([entry[4], entry[5], entry[6], entry[7]]),
+            })
+            .collect();
+        Ok(Acl { entries })
+    }
+
+    /// The access ACL of `path`, if it has one.
+    pub fn of(path: &Path, mode: MetadataMode) -> Result<Option<Acl>, Error> {
+        load(path, ACCESS, mode)
+    }
+
+    /// Returns true if this ACL says more than permission bits can, i.e. it
+    /// has entries for named users or groups, or a mask.
+    pub fn is_extended(&self) -> bool {
+        self.entries.iter().any(|entry| !matches!(entry.tag, USER_OBJ | GROUP_OBJ | OTHER))
+    }
+
+    /// Returns true if an entry naming `principal` grants at least `perms`
+    /// once masked.
+    pub fn grants(&self, principal: Principal, perms: u16) -> bool {
+        let mask = self.entries.iter().find(|entry| entry.tag == MASK).map_or(0o7, |entry| entry.perms);
+        self.entries.iter()
+            .filter(|entry| match principal {
+                Principal::User(uid) => entry.tag == USER && entry.id == uid,
+                Principal::Group(gid) => entry.tag == GROUP && entry.id == gid,
+            })
+            .any(|entry| entry.perms & mask & perms == perms)
+    }
+}
+
+impl Grant {
+
+    /// Parses `user:NAME:PERMS` or `group:NAME:PERMS`, where `user` and
+    /// `group` can be shortened to `u` and `g`, NAME can be an id, and
+    /// PERMS is made of `r`, `w` and `x`, like `rw`.
+    pub fn parse(arg: &str) -> Result<Grant, Error> {
+        let invalid = |reason: String| Error::new(io::ErrorKind::InvalidInput, format!("Invalid ACL grant '{}': {}", arg, reason));
+        let mut parts = arg.splitn(3, ':');
+        let (kind, name, perms) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(kind), Some(name), Some(perms)) if !name.is_empty() => (kind, name, perms),
+            _ => return Err(invalid(String::from("expected user:NAME:PERMS or group:NAME:PERMS."))),
+        };
+        let principal = match kind {
+            "u" | "user" => Principal::User(lookup("/etc/passwd", name).ok_or_else(|| invalid(format!("no user {}.", name)))?),
+            "g" | "group" => Principal::Group(lookup("/etc/group", name).ok_or_else(|| invalid(format!("no group {}.", name)))?),
+            _ => return Err(invalid(format!("expected user or group, not '{}'.", kind))),
+        };
+        let perms = perms.chars().try_fold(0, |perms, c| match c {
+            'r' => Ok(perms | 4),
+            'w' => Ok(perms | 2),
+            'x' => Ok(perms | 1),
+            '-' => Ok(perms),
+            _ => Err(invalid(format!("unknown permission '{}', expected r, w or x.", c))),
+        })?;
+        Ok(Grant { principal, perms, arg: arg.to_string() })
+    }
+}
+
+/// The id of `name` in `file`, /etc/passwd or /etc/group, whose lines start
+/// with the name and the id, or `name` itself if it is an id.
+fn lookup(file: &str, name: &str) -> Option<u32> {
+    if let Ok(id) = name.parse() {
+        return Some(id);
+    }
+    fs::read_to_string(file).ok()?.lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| fields.len() > 2 && fields[0] == name)
+        .and_then(|fields| fields[2].parse().ok())
+}
+
+/// The ACL of `path` kept in the extended attribute `name`, if any.
+fn load(path: &Path, name: &str, mode: MetadataMode) -> Result<Option<Acl>, Error> {
+    xattr::get(path, name, mode)?.map(|bytes| Acl::parse(&bytes)).transpose()
+}
+
+/// Reads the ACL of `file` kept in `name`, tracing why it couldn't be.
+fn read(file: &FileInfo, name: &str) -> Option<Acl> {
+    match load(file.path(), name, file.metadata_mode()) {
+        Ok(acl) => acl,
+        Err(e) => {
+            trace::event(Level::Debug, || format!("cannot read the ACL of {}: {}", file.path_str(), e));
+            None
+        }
+    }
+}
+
+/// Retains files with an ACL beyond their permission bits, or a default ACL.
+pub struct HasAcl;
+
+impl Filter for HasAcl {
+    fn name(&self) -> String {
+        String::from("has_acl")
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        read(file, ACCESS).is_some_and(|acl| acl.is_extended())
+            || file.metadata().is_some_and(|meta| meta.is_dir()) && read(file, DEFAULT).is_some()
+    }
+}
+
+/// Retains files whose ACL grants a user or group some permissions.
+pub struct AclGrants(pub Grant);
+
+impl Filter for AclGrants {
+    fn name(&self) -> String {
+        format!("acl_grants({})", self.0.arg)
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        read(file, ACCESS).is_some_and(|acl| acl.grants(self.0.principal, self.0.perms))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn acl(entries: &[(u16, u16, u32)]) -> Vec<u8> {
+        let mut bytes = VERSION.to_le_bytes().to_vec();
+        for &(tag, perms, id) in entries {
+            bytes.extend(tag.to_le_bytes());
+            bytes.extend(perms.to_le_bytes());
+            bytes.extend(id.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn reads_acls() {
+        const UNDEFINED: u32 = u32::MAX;
+        let plain = Acl::parse(&acl(&[(USER_OBJ, 6, UNDEFINED), (GROUP_OBJ, 4, UNDEFINED), (OTHER, 4, UNDEFINED)])).unwrap();
+        assert!(!plain.is_extended());
+        let shared = Acl::parse(&acl(&[
+            (USER_OBJ, 6, UNDEFINED), (USER, 7, 1000), (GROUP_OBJ, 4, UNDEFINED), (GROUP, 6, 50), (MASK, 6, UNDEFINED), (OTHER, 0, UNDEFINED),
+        ])).unwrap();
+        assert!(shared.is_extended());
+        assert!(shared.grants(Principal::User(1000), 6));
+        // Execute is masked out.
+        assert!(!shared.grants(Principal::User(1000), 1));
+        assert!(shared.grants(Principal::Group(50), 2));
+        assert!(!shared.grants(Principal::User(50), 2));
+        assert!(Acl::parse(&[1, 0, 0, 0]).is_err());
+        assert!(Acl::parse(&acl(&[(USER_OBJ, 6, UNDEFINED)])[..7]).is_err());
+    }
+
+    #[test]
+    fn parses_grants() {
+        let grant = Grant::parse("user:root:rw").unwrap();
+        assert_eq!((Principal::User(0), 6), (grant.principal, grant.perms));
+        assert_eq!(Principal::Group(42), Grant::parse("g:42:x").unwrap().principal);
+        assert_eq!(4, Grant::parse("u:1000:r--").unwrap().perms);
+        for arg in ["user:root", "other::r", "user:root:rwz", "user::r", "user:no-such-user-ffind:r"] {
+            assert!(Grant::parse(arg).is_err(), "{}", arg);
+        }
+    }
+
+}
@@ -0,0 +1,181 @@
+/*
+A record of what actions changed on disk.
+
+Actions that change files, like replacing text with 'Replace::apply()', can
+record every change they make in an 'AuditLog', a JSON Lines file with one
+object per change:
+
+    {"time":1760523000,"action":"replace","path":"src/lib.rs",
+     "before":{"size":1204,"mtime":1760000000,"perm":"0644","hash":"9f86..."},
+     "after":{"size":1210,"mtime":1760523000,"perm":"0644","hash":"60303..."},
+     "replacements":3}
+
+'before' and 'after' describe the file with the fields of the 'fields' module,
+so the hashes tell whether a file is still the one an action left behind, and
+the backups an action kept are named in the record. A file that doesn't exist
+on either side, e.g. the old name of a renamed file afterwards, is null.
+Changes that failed are recorded too, with their error.
+
+Logs are only ever appended to, one write per line, so several runs can share
+one log and a run that is interrupted leaves whole lines behind.
+
+ */
+
+use std::fs::{File, OpenOptions};
+use std::io::{Error, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::fields::{Field, Fields};
+use crate::filter::{FileInfo, MetadataMode};
+use serde_json::{Map, Value};
+
+
+/// What a record says about a file before and after a change.
+const SNAPSHOT: [Field; 4] = [Field::Size, Field::Mtime, Field::Perm, Field::Hash];
+
+/// A JSON Lines file that changes are appended to, see the module
+/// documentation.
+#[derive(Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+/// A change an action is about to make to a file, recorded with
+/// `AuditLog::record()` once it is made.
+#[derive(Debug, Clone)]
+pub struct Mutation {
+    action: String,
+    path: PathBuf,
+    /// Where the file is once changed, if it moves.
+    to: Option<PathBuf>,
+    time: u64,
+    before: Value,
+    details: Vec<(String, Value)>,
+}
+
+impl AuditLog {
+
+    /// Opens the log at `path` to append to it, creating it if it doesn't
+    /// exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<AuditLog, Error> {
+        let path = path.as_ref();
+        let file = OpenOptions::new().create(true).append(true).open(path)
+            .map_err(|e| Error::new(e.kind(), format!("Cannot open the audit log {}: {}.", path.display(), e)))?;
+        Ok(AuditLog { path: path.to_path_buf(), file: Mutex::new(file) })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends the record of `mutation`, describing the file as it is now as
+    /// what the change left behind.
+    pub fn record(&self, mutation: Mutation) -> Result<(), Error> {
+        let after = snapshot(mutation.to.as_deref().unwrap_or(&mutation.path));
+        let mut line = mutation.into_json(after).to_string();
+        line.push('\n');
+        let mut file = self.file.lock().unwrap();
+        file.write_all(line.as_bytes())?;
+        file.flush()
+    }
+}
+
+impl Mutation {
+
+    /// Describes `path` before `action` changes it. Call it right before
+    /// making the change.
+    pub fn begin(action: &str, path: impl Into<PathBuf>) -> Mutation {
+        let path = path.into();
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or(0);
+        let before = snapshot(&path);
+        Mutation { action: action.to_string(), path, to: None, time, before, details: Vec::new() }
+    }
+
+    /// Records that the change moves the file to `to`, which the record then
+    /// describes as the file afterwards.
+    pub fn to(mut self, to: impl Into<PathBuf>) -> Mutation {
+        self.to = Some(to.into());
+        self
+    }
+
+    /// Adds `name` to the record, like the number of replacements made.
+    pub fn detail(mut self, name: &str, value: impl Into<Value>) -> Mutation {
+        self.details.push((name.to_string(), value.into()));
+        self
+    }
+
+    /// Records that the change failed with `error`.
+    pub fn failed(self, error: &Error) -> Mutation {
+        self.detail("error", error.to_string())
+    }
+
+    fn into_json(self, after: Value) -> Value {
+        let path = |path: &Path| Value::from(path.to_string_lossy().into_owned());
+        let mut members = Map::new();
+        members.insert(String::from("time"), Value::from(self.time));
+        members.insert(String::from("action"), Value::from(self.action));
+        members.insert(String::from("path"), path(&self.path));
+        if let Some(to) = &self.to {
+            members.insert(String::from("to"), path(to));
+        }
+        members.insert(String::from("before"), self.before);
+        members.insert(String::from("after"), after);
+        members.extend(self.details);
+        Value::Object(members)
+    }
+}
+
+/// The size, modification time, permissions and hash of `path`, or null if
+/// it doesn't exist. Symlinks are described themselves.
+fn snapshot(path: &Path) -> Value {
+    let file = FileInfo::with_metadata_mode(path.to_path_buf(), MetadataMode::NoFollow);
+    if file.metadata().is_none() {
+        return Value::Null;
+    }
+    Fields::new(SNAPSHOT.to_vec()).entry(&file)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn records_changes() {
+        let dir = std::env::temp_dir().join(format!("ffind-audit-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (a, b) = (dir.join("a.txt"), dir.join("b.txt"));
+        fs::write(&a, "abc").unwrap();
+        let log = AuditLog::open(dir.join("audit.jsonl")).unwrap();
+
+        let mutation = Mutation::begin("replace", &a).detail("replacements", 2u64);
+        fs::write(&a, "abcd").unwrap();
+        log.record(mutation).unwrap();
+        let mutation = Mutation::begin("rename", &a).to(&b);
+        fs::rename(&a, &b).unwrap();
+        log.record(mutation).unwrap();
+        let mutation = Mutation::begin("rename", &a).to(&b);
+        log.record(mutation.failed(&Error::from(std::io::ErrorKind::NotFound))).unwrap();
+
+        let text = fs::read_to_string(log.path()).unwrap();
+        let records = text.lines().map(|line| serde_json::from_str::<Value>(line).unwrap()).collect::<Vec<_>>();
+        assert_eq!(3, records.len());
+        let field = |record: &Value, name: &str| record.get(name).unwrap().to_string();
+        assert_eq!(r#""replace""#, field(&records[0], "action"));
+        assert_eq!(Value::from(a.to_str().unwrap()), *records[0].get("path").unwrap());
+        let before = records[0].get("before").unwrap();
+        assert_eq!("3", field(before, "size"));
+        assert_eq!(r#""ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad""#, field(before, "hash"));
+        assert_eq!("4", field(records[0].get("after").unwrap(), "size"));
+        assert_eq!("2", field(&records[0], "replacements"));
+        assert_eq!(Value::from(b.to_str().unwrap()), *records[1].get("to").unwrap());
+        assert_eq!("4", field(records[1].get("after").unwrap(), "size"));
+        assert_eq!(Value::Null, *records[2].get("before").unwrap());
+        assert!(records[2].get("error").is_some());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+}
@@ -0,0 +1,148 @@
+/*
+Reporting only the matches a baseline doesn't know about.
+
+A search used as a policy check, like no file over 5 MB in the repository,
+fails from the day it is introduced unless the violations already there are
+accepted. A 'Baseline' is the list of those: the matches of an earlier
+search, relative to the root they were found below, one per line, so that
+the file can be checked in next to the code. 'NotInBaseline' retains the
+files it doesn't list, i.e. the new violations, and, given a
+'BaselineUpdate', records every file it sees, to write the baseline anew
+once the search is done: violations fixed since drop out of it.
+
+ */
+
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use std::io::{Error, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use crate::filter::{FileInfo, Filter};
+use crate::output::AtomicFile;
+
+
+/// The matches of an earlier search, by their path relative to the root they
+/// were found below.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Baseline {
+    paths: HashSet<String>,
+}
+
+/// The matches of a search, for a new baseline. Like `DiskUsage`, it is a
+/// cheap handle: clone it, hand one copy to the filter and write the other
+/// after the search completes.
+#[derive(Debug, Clone, Default)]
+pub struct BaselineUpdate {
+    paths: Arc<Mutex<BTreeSet<String>>>,
+}
+
+impl Baseline {
+
+    /// Parses a baseline, one path per line. Empty lines are skipped.
+    pub fn parse(text: &str) -> Baseline {
+        let paths = text.lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| line.to_string())
+            .collect();
+        Baseline { paths }
+    }
+
+    pub fn read(path: &Path) -> Result<Baseline, Error> {
+        fs::read_to_string(path).map(|text| Baseline::parse(&text))
+    }
+
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    /// Returns true if `file` was found before.
+    pub fn contains(&self, file: &FileInfo) -> bool {
+        self.paths.contains(key(file).as_ref())
+    }
+}
+
+impl BaselineUpdate {
+
+    pub fn new() -> BaselineUpdate {
+        BaselineUpdate::default()
+    }
+
+    /// Records that `file` matched.
+    pub fn record(&self, file: &FileInfo) {
+        self.paths.lock().unwrap().insert(key(file).into_owned());
+    }
+
+    /// The baseline of the files recorded so far.
+    pub fn baseline(&self) -> Baseline {
+        Baseline { paths: self.paths.lock().unwrap().iter().cloned().collect() }
+    }
+
+    /// Replaces the baseline at `path` with the files recorded, in order.
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        let mut file = AtomicFile::create(path, false)?;
+        for path in self.paths.lock().unwrap().iter() {
+            writeln!(file, "{}", path)?;
+        }
+        file.commit()
+    }
+}
+
+/// What a baseline records `file` as: its path relative to the root it was
+/// found below, or its whole path for a root itself.
+fn key(file: &FileInfo) -> Cow<'_, str> {
+    match file.relative_path() {
+        relative if relative.as_os_str().is_empty() => file.path_str().into(),
+        relative => relative.to_string_lossy(),
+    }
+}
+
+/// Retains files that aren't in a baseline, recording every file it sees if
+/// `update` is set. Files it doesn't see can't be recorded, so it is the
+/// last filter of a search.
+pub struct NotInBaseline {
+    pub baseline: Baseline,
+    pub update: Option<BaselineUpdate>,
+}
+
+impl Filter for NotInBaseline {
+    fn name(&self) -> String {
+        format!("not_in_baseline({} files)", self.baseline.len())
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        if let Some(update) = &self.update {
+            update.record(file);
+        }
+        !self.baseline.contains(file)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn reports_new_files() {
+        let root: Arc<Path> = Arc::from(Path::new("repo"));
+        let file = |path: &str| FileInfo::new(PathBuf::from(path)).found_below(root.clone(), 2);
+        let update = BaselineUpdate::new();
+        let filter = NotInBaseline { baseline: Baseline::parse("assets/intro.mp4\n\nassets/fixed.bin\n"), update: Some(update.clone()) };
+        assert!(!filter.matches(&file("repo/assets/intro.mp4")));
+        assert!(filter.matches(&file("repo/assets/outro.mp4")));
+        assert_eq!(Baseline::parse("assets/outro.mp4\nassets/intro.mp4"), update.baseline());
+
+        let dir = std::env::temp_dir().join(format!("ffind-baseline-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        update.write(&dir.join("baseline")).unwrap();
+        assert_eq!("assets/intro.mp4\nassets/outro.mp4\n", fs::read_to_string(dir.join("baseline")).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+}
@@ -0,0 +1,322 @@
+/*
+BLAKE3, a cryptographic hash that hashes large files on many threads.
+
+A plain implementation of BLAKE3 with a 256-bit output, as b3sum prints it.
+It splits its input into chunks of 1 KiB, hashes every chunk on its own and
+combines them as the leaves of a binary tree. 'Blake3::update()' hashes
+large enough inputs as whole subtrees, splitting them over threads, so that
+a file read in large buffers, see 'Blake3::read()', is hashed on all the
+CPUs. Short inputs are hashed on the calling thread.
+
+ */
+
+use std::io::{self, Read};
+use std::thread;
+
+
+const IV: [u32; 8] = [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19];
+const MSG_PERMUTATION: [usize; 16] = [2, 6, 3, 10, 7, 0, 4, 13, 1, 11, 12, 5, 9, 14, 15, 8];
+
+const CHUNK_START: u32 = 1;
+const CHUNK_END: u32 = 2;
+const PARENT: u32 = 4;
+const ROOT: u32 = 8;
+
+const BLOCK_LEN: usize = 64;
+const CHUNK_LEN: usize = 1024;
+
+/// The largest subtree `Blake3::update()` hashes at once, which is also how
+/// much `Blake3::read()` reads at once.
+const MAX_SUBTREE_LEN: usize = 8 << 20;
+/// The shortest subtree split over two threads.
+const MIN_PARALLEL_LEN: usize = 256 << 10;
+
+fn g(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize, mx: u32, my: u32) {
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(mx);
+    state[d] = (state[d] ^ state[a]).rotate_right(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(12);
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(my);
+    state[d] = (state[d] ^ state[a]).rotate_right(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(7);
+}
+
+fn compress(cv: &[u32; 8], block: &[u32; 16], counter: u64, block_len: u32, flags: u32) -> [u32; 16] {
+    let mut state = [
+        cv[0], cv[1], cv[2], cv[3], cv[4], cv[5], cv[6], cv[7],
+        IV[0], IV[1], IV[2], IV[3], counter as u32, (counter >> 32) as u32, block_len, flags,
+    ];
+    let mut m = *block;
+    for round in 0..7 {
+        g(&mut state, 0, 4, 8, 12, m[0], m[1]);
+        g(&mut state, 1, 5, 9, 13, m[2], m[3]);
+        g(&mut state, 2, 6, 10, 14, m[4], m[5]);
+        g(&mut state, 3, 7, 11, 15, m[6], m[7]);
+        g(&mut state, 0, 5, 10, 15, m[8], m[9]);
+        g(&mut state, 1, 6, 11, 12, m[10], m[11]);
+        g(&mut state, 2, 7, 8, 13, m[12], m[13]);
+        g(&mut state, 3, 4, 9, 14, m[14], m[15]);
+        if round < 6 {
+            let mut permuted = [0; 16];
+            for (i, &from) in MSG_PERMUTATION.iter().enumerate() {
+                permuted[i] = m[from];
+            }
+            m = permuted;
+        }
+    }
+    for i in 0..8 {
+        state[i] ^= state[i + 8];
+        state[i + 8] ^= cv[i];
+    }
+    state
+}
+
+fn first_8(words: [u32; 16]) -> [u32; 8] {
+    let mut first = [0; 8];
+    first.copy_from_slice(&words[..8]);
+    first
+}
+
+fn words(bytes: &[u8; BLOCK_LEN]) -> [u32; 16] {
+    let mut words = [0; 16];
+    for (word, bytes) in words.iter_mut().zip(bytes.chunks(4)) {
+        *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    words
+}
+
+/// What the last compression of a chunk or parent takes, to make either the
+/// chaining value of a node or the root hash.
+struct Output {
+    cv: [u32; 8],
+    block: [u32; 16],
+    counter: u64,
+    block_len: u32,
+    flags: u32,
+}
+
+impl Output {
+
+    fn chaining_value(&self) -> [u32; 8] {
+        first_8(compress(&self.cv, &self.block, self.counter, self.block_len, self.flags))
+    }
+
+    fn root_hash(&self) -> [u8; 32] {
+        let words = compress(&self.cv, &self.block, 0, self.block_len, self.flags | ROOT);
+        let mut hash = [0; 32];
+        for (bytes, word) in hash.chunks_mut(4).zip(&words) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+        hash
+    }
+}
+
+fn parent(left: [u32; 8], right: [u32; 8]) -> Output {
+    let mut block = [0; 16];
+    block[..8].copy_from_slice(&left);
+    block[8..].copy_from_slice(&right);
+    Output { cv: IV, block, counter: 0, block_len: BLOCK_LEN as u32, flags: PARENT }
+}
+
+#[derive(Debug, Clone)]
+struct ChunkState {
+    cv: [u32; 8],
+    counter: u64,
+    block: [u8; BLOCK_LEN],
+    block_len: usize,
+    blocks: usize,
+}
+
+impl ChunkState {
+
+    fn new(counter: u64) -> ChunkState {
+        ChunkState { cv: IV, counter, block: [0; BLOCK_LEN], block_len: 0, blocks: 0 }
+    }
+
+    fn len(&self) -> usize {
+        BLOCK_LEN * self.blocks + self.block_len
+    }
+
+    fn start_flag(&self) -> u32 {
+        if self.blocks == 0 { CHUNK_START } else { 0 }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            if self.block_len == BLOCK_LEN {
+                self.cv = first_8(compress(&self.cv, &words(&self.block), self.counter, BLOCK_LEN as u32, self.start_flag()));
+                self.blocks += 1;
+                self.block = [0; BLOCK_LEN];
+                self.block_len = 0;
+            }
+            let n = data.len().min(BLOCK_LEN - self.block_len);
+            self.block[self.block_len..self.block_len + n].copy_from_slice(&data[..n]);
+            self.block_len += n;
+            data = &data[n..];
+        }
+    }
+
+    fn output(&self) -> Output {
+        Output {
+            cv: self.cv,
+            block: words(&self.block),
+            counter: self.counter,
+            block_len: self.block_len as u32,
+            flags: self.start_flag() | CHUNK_END,
+        }
+    }
+}
+
+/// The chaining value of the subtree of whole chunks `data`, a power of two
+/// of them, whose first chunk is the `counter`th. Halves of large subtrees
+/// are hashed on two threads.
+fn subtree(data: &[u8], counter: u64) -> [u32; 8] {
+    if data.len() == CHUNK_LEN {
+        let mut chunk = ChunkState::new(counter);
+        chunk.update(data);
+        return chunk.output().chaining_value();
+    }
+    let (left, right) = data.split_at(data.len() / 2);
+    let right_counter = counter + (left.len() / CHUNK_LEN) as u64;
+    let (left, right) = if data.len() >= MIN_PARALLEL_LEN {
+        thread::scope(|scope| {
+            let right = scope.spawn(|| subtree(right, right_counter));
+            (subtree(left, counter), right.join().unwrap())
+        })
+    } else {
+        (subtree(left, counter), subtree(right, right_counter))
+    };
+    parent(left, right).chaining_value()
+}
+
+/// A BLAKE3 hash being computed.
+#[derive(Debug, Clone)]
+pub struct Blake3 {
+    chunk: ChunkState,
+    /// The chaining values of the complete subtrees left of the chunk, the
+    /// largest first, one per bit set in the number of chunks before it.
+    stack: Vec<[u32; 8]>,
+}
+
+impl Default for Blake3 {
+    fn default() -> Blake3 {
+        Blake3::new()
+    }
+}
+
+impl Blake3 {
+
+    pub fn new() -> Blake3 {
+        Blake3 { chunk: ChunkState::new(0), stack: Vec::new() }
+    }
+
+    /// Hashes everything read from `reader`, reading large buffers to hash
+    /// them on many threads.
+    pub fn read(mut reader: impl Read) -> io::Result<[u8; 32]> {
+        let mut blake3 = Blake3::new();
+        let mut buf = vec![0; MAX_SUBTREE_LEN];
+        loop {
+            let mut filled = 0;
+            while filled < buf.len() {
+                match reader.read(&mut buf[filled..]) {
+                    Ok(0) => break,
+                    Ok(n) => filled += n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            blake3.update(&buf[..filled]);
+            if filled < buf.len() {
+                return Ok(blake3.finish());
+            }
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            // A chunk is only done with once more input follows it, since
+            // the last one is hashed as, or into, the root.
+            if self.chunk.len() == CHUNK_LEN {
+                let cv = self.chunk.output().chaining_value();
+                self.push(cv, self.chunk.counter + 1);
+                self.chunk = ChunkState::new(self.chunk.counter + 1);
+            }
+            if self.chunk.len() == 0 {
+                let len = self.subtree_len(data.len());
+                if len > CHUNK_LEN {
+                    let cv = subtree(&data[..len], self.chunk.counter);
+                    let chunks = (len / CHUNK_LEN) as u64;
+                    self.push_subtree(cv, self.chunk.counter + chunks, chunks.trailing_zeros());
+                    self.chunk = ChunkState::new(self.chunk.counter + chunks);
+                    data = &data[len..];
+                    continue;
+                }
+            }
+            let n = data.len().min(CHUNK_LEN - self.chunk.len());
+            self.chunk.update(&data[..n]);
+            data = &data[n..];
+        }
+    }
+
+    pub fn finish(self) -> [u8; 32] {
+        let mut output = self.chunk.output();
+        for &left in self.stack.iter().rev() {
+            output = parent(left, output.chaining_value());
+        }
+        output.root_hash()
+    }
+
+    /// How much of `available` bytes, starting at a chunk, can be hashed as
+    /// one subtree: a power of two of chunks that the chunks before it are a
+    /// multiple of, with input left after it.
+    fn subtree_len(&self, available: usize) -> usize {
+        let mut len = MAX_SUBTREE_LEN;
+        while len > CHUNK_LEN && (len >= available || !self.chunk.counter.is_multiple_of((len / CHUNK_LEN) as u64)) {
+            len /= 2;
+        }
+        len
+    }
+
+    fn push(&mut self, cv: [u32; 8], chunks: u64) {
+        self.push_subtree(cv, chunks, 0);
+    }
+
+    /// Pushes `cv`, of a subtree of 2^`height` chunks ending with the
+    /// `chunks`th, merging it with the subtrees of its size left of it.
+    fn push_subtree(&mut self, mut cv: [u32; 8], chunks: u64, height: u32) {
+        let mut total = chunks >> height;
+        while total & 1 == 0 {
+            cv = parent(self.stack.pop().unwrap(), cv).chaining_value();
+            total >>= 1;
+        }
+        self.stack.push(cv);
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sha256::hex;
+
+    #[test]
+    fn known_hashes() {
+        // The official test vectors, of the bytes 0, 1, ..., 250, 0, 1, ...
+        let input: Vec<u8> = (0..(4 << 20) + 1).map(|i| (i % 251) as u8).collect();
+        let blake3 = |len: usize| hex(&Blake3::read(&input[..len]).unwrap());
+        assert_eq!("af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262", blake3(0));
+        assert_eq!("2d3adedff11b61f14c886e35afa036736dcd87a74d27b5c1510225d0f592e213", blake3(1));
+        assert_eq!("42214739f095a406f3fc83deb889744ac00df831c10daa55189b5d121c855af7", blake3(1024));
+        assert_eq!("d00278ae47eb27b34faecf67b4fe263f82d5412916c1ffd97c8cb7fb814b8444", blake3(1025));
+        assert_eq!("e776b6028c7cd22a4d0ba182a8bf62205d2ef576467e838ed6f2529b85fba24a", blake3(2048));
+        // Hashing a large input at once, as subtrees on many threads, is the
+        // same as hashing it a piece at a time.
+        let mut pieces = Blake3::new();
+        for piece in input.chunks(1000) {
+            pieces.update(piece);
+        }
+        assert_eq!(hex(&pieces.finish()), blake3(input.len()));
+    }
+
+}
@@ -0,0 +1,241 @@
+/*
+Bounding how much content a search reads.
+
+Searches with content filters read every candidate file, which on huge trees
+can go on for hours. With 'Finder::scan_budget()', the contents read by
+filters are counted against a 'ScanBudget' of bytes shared by the whole
+search: once it is spent, files are no longer opened, so the content filters
+reject them, and the search finishes walking the tree with the filters that
+only look at names and metadata. The budget tells afterwards whether it ran
+out and how many files went unread.
+
+Only contents count: directory listings, metadata and ignore files don't.
+
+Like ripgrep's '--max-filesize', 'Finder::max_file_size()' keeps content
+filters from reading files larger than a size at all, so that a stray core
+dump doesn't stall a search; they reject those files without opening them.
+Filters on names and metadata still see them.
+
+ */
+
+use std::io::{self, Error, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::filter::MetadataMode;
+use crate::mmap::Mmap;
+use crate::vfs::{DirEntries, FileSystem, Metadata};
+
+
+/// A number of bytes of contents a search may read. It is a cheap handle:
+/// clone it, hand one copy to `Finder::scan_budget()` and read the results
+/// from the other after the search completes.
+#[derive(Debug, Clone)]
+pub struct ScanBudget {
+    inner: Arc<Spent>,
+}
+
+#[derive(Debug)]
+struct Spent {
+    bytes: u64,
+    read: AtomicU64,
+    unread_files: AtomicU64,
+}
+
+impl ScanBudget {
+
+    pub fn new(bytes: u64) -> ScanBudget {
+        ScanBudget { inner: Arc::new(Spent { bytes, read: AtomicU64::new(0), unread_files: AtomicU64::new(0) }) }
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.inner.bytes
+    }
+
+    /// The bytes of contents read so far.
+    pub fn read(&self) -> u64 {
+        self.inner.read.load(Ordering::Relaxed)
+    }
+
+    /// Returns true once the budget is spent.
+    pub fn exhausted(&self) -> bool {
+        self.read() >= self.inner.bytes
+    }
+
+    /// How many files weren't opened, or not read to the end, because the
+    /// budget was spent.
+    pub fn unread_files(&self) -> u64 {
+        self.inner.unread_files.load(Ordering::Relaxed)
+    }
+
+    fn refuse(&self, path: &Path) -> Error {
+        self.inner.unread_files.fetch_add(1, Ordering::Relaxed);
+        Error::other(format!("the scan budget of {} bytes is spent, not reading {}", self.inner.bytes, path.display()))
+    }
+}
+
+/// A `FileSystem` whose contents are read from another one within a
+/// `ScanBudget`, if any, and only for files of at most `max_file_size` bytes,
+/// if set.
+pub struct Budgeted {
+    inner: Arc<dyn FileSystem>,
+    budget: Option<ScanBudget>,
+    max_file_size: Option<u64>,
+}
+
+impl Budgeted {
+    pub fn new(inner: Arc<dyn FileSystem>, budget: Option<ScanBudget>, max_file_size: Option<u64>) -> Budgeted {
+        Budgeted { inner, budget, max_file_size }
+    }
+
+    fn check_size(&self, path: &Path) -> Result<(), Error> {
+        if let Some(max) = self.max_file_size {
+            let len = self.inner.metadata(path, MetadataMode::Follow)?.len();
+            if len > max {
+                return Err(Error::other(format!("{} has {} bytes, more than the maximum of {}", path.display(), len, max)));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FileSystem for Budgeted {
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>, Error> {
+        self.inner.read_dir(path)
+    }
+
+    fn read_dir_iter(&self, path: &Path) -> Result<DirEntries<'_>, Error> {
+        self.inner.read_dir_iter(path)
+    }
+
+    fn metadata(&self, path: &Path, mode: MetadataMode) -> Result<Metadata, Error> {
+        self.inner.metadata(path, mode)
+    }
+
+    fn open(&self, path: &Path) -> Result<Box<dyn Read + Send>, Error> {
+        self.check_size(path)?;
+        let budget = match &self.budget {
+            Some(budget) if budget.exhausted() => return Err(budget.refuse(path)),
+            Some(budget) => budget.clone(),
+            None => return self.inner.open(path),
+        };
+        let inner = self.inner.open(path)?;
+        Ok(Box::new(BudgetedRead { inner, budget, path: path.to_path_buf(), refused: false }))
+    }
+
+    /// A mapped file counts as read entirely.
+    fn map(&self, path: &Path) -> Result<Option<Mmap>, Error> {
+        self.check_size(path)?;
+        match &self.budget {
+            Some(budget) if budget.exhausted() => Err(budget.refuse(path)),
+            Some(budget) => {
+                let map = self.inner.map(path)?;
+                if let Some(map) = &map {
+                    budget.inner.read.fetch_add(map.len() as u64, Ordering::Relaxed);
+                }
+                Ok(map)
+            }
+            None => self.inner.map(path),
+        }
+    }
+
+    fn id(&self, path: &Path, mode: MetadataMode) -> Option<(u64, u64)> {
+        self.inner.id(path, mode)
+    }
+
+    fn fs_type(&self, path: &Path) -> Option<String> {
+        self.inner.fs_type(path)
+    }
+}
+
+struct BudgetedRead {
+    inner: Box<dyn Read + Send>,
+    budget: ScanBudget,
+    path: PathBuf,
+    refused: bool,
+}
+
+impl Read for BudgetedRead {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.budget.exhausted() {
+            if self.refused {
+                return Err(Error::other("the scan budget is spent"));
+            }
+            self.refused = true;
+            return Err(self.budget.refuse(&self.path));
+        }
+        let n = self.inner.read(buf)?;
+        self.budget.inner.read.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+/// Parses a number of bytes with an optional `k`, `M`, `G` or `T` suffix for
+/// powers of 1024, like `10G`.
+pub fn parse_bytes(arg: &str) -> Result<u64, Error> {
+    let (digits, unit) = match arg.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => {
+            let unit: u64 = match c {
+                'k' | 'K' => 1 << 10,
+                'M' => 1 << 20,
+                'G' => 1 << 30,
+                'T' => 1 << 40,
+                _ => return Err(invalid_bytes(arg)),
+            };
+            (&arg[..i], unit)
+        }
+        _ => (arg, 1),
+    };
+    digits.parse::<u64>().ok().and_then(|n| n.checked_mul(unit)).ok_or_else(|| invalid_bytes(arg))
+}
+
+fn invalid_bytes(arg: &str) -> Error {
+    Error::new(io::ErrorKind::InvalidInput, format!("Invalid size '{}', expected a number of bytes like 512k, 50M or 10G.", arg))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vfs::MemoryFileSystem;
+
+    #[test]
+    fn reads_stop_once_spent() {
+        let mut fs = MemoryFileSystem::new();
+        fs.add_file("a.bin", vec![0; 1000]).add_file("b.bin", vec![0; 1000]);
+        let budget = ScanBudget::new(1500);
+        let budgeted = Budgeted::new(Arc::new(fs), Some(budget.clone()), None);
+        let mut contents = Vec::new();
+        budgeted.open(Path::new("a.bin")).unwrap().read_to_end(&mut contents).unwrap();
+        assert_eq!(1000, contents.len());
+        assert!(!budget.exhausted());
+        // However much of b.bin is read in one go, nothing more is after that.
+        assert!(budgeted.open(Path::new("b.bin")).unwrap().read_to_end(&mut contents).is_err());
+        assert!(budget.exhausted());
+        assert!(budgeted.open(Path::new("a.bin")).is_err());
+        assert_eq!(2, budget.unread_files());
+        assert!(budgeted.metadata(Path::new("a.bin"), MetadataMode::Follow).is_ok());
+    }
+
+    #[test]
+    fn large_files_are_not_read() {
+        let mut fs = MemoryFileSystem::new();
+        fs.add_file("core", vec![0; 1001]).add_file("small.txt", vec![0; 1000]);
+        let budgeted = Budgeted::new(Arc::new(fs), None, Some(1000));
+        assert!(budgeted.open(Path::new("core")).is_err());
+        assert!(budgeted.open(Path::new("small.txt")).is_ok());
+        assert!(budgeted.metadata(Path::new("core"), MetadataMode::Follow).is_ok());
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(512, parse_bytes("512").unwrap());
+        assert_eq!(50 * 1024 * 1024, parse_bytes("50M").unwrap());
+        assert_eq!(10 << 30, parse_bytes("10G").unwrap());
+        assert_eq!(2048, parse_bytes("2k").unwrap());
+        assert!(parse_bytes("10X").is_err());
+        assert!(parse_bytes("G").is_err());
+        assert!(parse_bytes("99999999999T").is_err());
+    }
+
+}
@@ -0,0 +1,55 @@
+/*
+Checking a Finder before it runs.
+
+Builder methods can't fail, so that they chain: a regex or glob that doesn't
+compile is recorded in the 'Finder' instead, and together with the options
+that can't go together, like a minimum depth below the maximum one, and
+roots that don't exist, reported by 'Finder::validate()' as a 'BuildError'
+before anything is walked. Terminal operators validate first, returning the
+error as an 'io::Error' like everything else.
+
+ */
+
+use std::error;
+use std::fmt;
+use std::io;
+
+
+/// What is wrong with the configuration of a `Finder`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildError {
+    /// A root of the search doesn't exist.
+    MissingRoot(String),
+    /// A filter's regex doesn't compile.
+    InvalidRegex { pattern: String, reason: String },
+    /// A filter's glob doesn't compile.
+    InvalidGlob { pattern: String, reason: String },
+    /// The minimum depth is below the maximum one.
+    DepthRange { min: u32, max: u32 },
+    /// Options that can't be used together.
+    Conflict(String),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildError::MissingRoot(root) => write!(f, "Root directory {} does not exists.", root),
+            BuildError::InvalidRegex { pattern, reason } => write!(f, "Invalid regex '{}': {}", pattern, reason),
+            BuildError::InvalidGlob { pattern, reason } => write!(f, "Invalid glob '{}': {}", pattern, reason),
+            BuildError::DepthRange { min, max } => write!(f, "The minimum depth {} is deeper than the maximum depth {}.", min, max),
+            BuildError::Conflict(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl error::Error for BuildError {}
+
+impl From<BuildError> for io::Error {
+    fn from(e: BuildError) -> io::Error {
+        let kind = match e {
+            BuildError::MissingRoot(_) => io::ErrorKind::NotFound,
+            _ => io::ErrorKind::InvalidInput,
+        };
+        io::Error::new(kind, e)
+    }
+}
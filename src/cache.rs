@@ -0,0 +1,394 @@
+/*
+Caching the results of searches on disk.
+
+Editors and other tools run the same searches over and over, on trees that
+barely change in between. With 'Finder::result_cache()', a search saves what
+it found in every directory it read, keyed by the path and the modification
+time of the directory, to a file of the cache directory named after the
+search. The same search run again only looks up the modification time of the
+directories: those that didn't change are answered from the cache, without
+listing them or looking up their entries, and the others are read again,
+replacing what was cached of them.
+
+The modification time of a directory only changes when entries are added to
+it, removed or renamed, not when a file in it changes. Only searches whose
+filters look at names and paths alone are cached, see 'Filter::cacheable()',
+and not those with 'Finder::prune_if()' callbacks, which run as usual. An
+ignore file that changed makes every directory below it read again. A
+directory modified less than two seconds before the search started isn't
+cached, since on filesystems with coarse timestamps it could change again
+without getting a new modification time.
+
+Cache files are JSON, written to a temporary file first and then renamed,
+like checkpoints.
+
+ */
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Error};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::{Finder, FileId};
+use crate::filter::MetadataMode;
+use crate::ignore;
+use serde_json::{json, Value};
+use crate::sha256::{self, Sha256};
+use crate::trace::{self, Level};
+use crate::vfs::FileSystem;
+
+
+/// How recently modified a directory can be and still be cached.
+const RACY: Duration = Duration::from_secs(2);
+
+/// A modification time, in seconds and nanoseconds since the epoch.
+type Stamp = (u64, u32);
+
+/// The cached results of one search, see `Finder::result_cache()`.
+pub(crate) struct ResultCache {
+    file: PathBuf,
+    key: String,
+    started: SystemTime,
+    /// What earlier searches found, by directory.
+    cached: HashMap<PathBuf, CachedDir>,
+    /// What this search found, by directory, to save once it completes.
+    found: HashMap<PathBuf, CachedDir>,
+    /// The directories with entries that couldn't be looked up or that were
+    /// skipped, which aren't saved.
+    incomplete: HashSet<PathBuf>,
+}
+
+/// What a search found in a directory.
+#[derive(Debug, Clone, PartialEq)]
+struct CachedDir {
+    modified: Stamp,
+    /// The modification time of its ignore file, if it has one.
+    ignore_file: Option<Stamp>,
+    entries: Vec<CachedEntry>,
+}
+
+/// An entry of a directory that matched or is a directory to walk into.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct CachedEntry {
+    pub(crate) name: String,
+    /// Whether it passed the filters.
+    pub(crate) matched: bool,
+    /// Whether it is a directory to walk into, unless pruned.
+    pub(crate) dir: bool,
+    /// What identifies it, if matches are deduplicated.
+    pub(crate) id: Option<FileId>,
+}
+
+/// Whether the entries of a directory are cached.
+pub(crate) enum Lookup {
+    Hit(Vec<CachedEntry>),
+    /// They aren't, or the directory changed. If `stale`, the ignore files
+    /// applying to the entries below it changed too, so what is cached of
+    /// them isn't used either.
+    Miss { stale: bool },
+}
+
+impl ResultCache {
+
+    /// The cache of the search of `finder` up to `depth` in `dir`, or `None`
+    /// if it can't be cached.
+    pub(crate) fn open(dir: &Path, finder: &Finder, depth: u32) -> Option<ResultCache> {
+        let key = match key(finder, depth) {
+            Ok(key) => key,
+            Err(reason) => {
+                trace::event(Level::Debug, || format!("not caching the results: {}", reason));
+                return None;
+            }
+        };
+        let mut hasher = Sha256::new();
+        hasher.update(key.as_bytes());
+        let file = dir.join(format!("{}.json", sha256::hex(&hasher.finish())));
+        let cached = match fs::read_to_string(&file) {
+            Ok(text) => from_json(&text, &key).unwrap_or_else(|e| {
+                trace::event(Level::Warn, || format!("ignoring the result cache {}: {}", file.display(), e));
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        trace::event(Level::Debug, || format!("{} directories in the result cache {}", cached.len(), file.display()));
+        Some(ResultCache { file, key, started: SystemTime::now(), cached, found: HashMap::new(), incomplete: HashSet::new() })
+    }
+
+    /// Returns the cached entries of the directory at `path` of `file_system`
+    /// if it didn't change and `trusted` is set, i.e. no ignore file above it
+    /// changed. Otherwise, starts caching what is found in it.
+    pub(crate) fn lookup(&mut self, path: &Path, file_system: &dyn FileSystem, ignore_files: bool, trusted: bool) -> Lookup {
+        let (modified, ignore_file) = match stamps(path, file_system, ignore_files) {
+            Some(stamps) => stamps,
+            None => return Lookup::Miss { stale: !trusted },
+        };
+        let cached = self.cached.remove(path).filter(|_| trusted);
+        let stale = match &cached {
+            Some(dir) => dir.ignore_file != ignore_file,
+            None => !trusted || ignore_file.is_some(),
+        };
+        match cached {
+            Some(dir) if dir.modified == modified && !stale => {
+                let entries = dir.entries.clone();
+                self.found.insert(path.to_path_buf(), dir);
+                Lookup::Hit(entries)
+            }
+            _ => {
+                trace::event(Level::Debug, || format!("{} isn't cached or changed", path.display()));
+                self.found.insert(path.to_path_buf(), CachedDir { modified, ignore_file, entries: Vec::new() });
+                Lookup::Miss { stale }
+            }
+        }
+    }
+
+    /// Caches what was found of the entry at `path`, if its directory is
+    /// being cached: whether it matched, with what identifies it, and whether
+    /// it is a directory to walk into.
+    pub(crate) fn record(&mut self, path: &Path, matched: Option<Option<FileId>>, dir: bool) {
+        let (parent, name) = match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => (parent, name),
+            _ => return,
+        };
+        if matched.is_none() && !dir {
+            return;
+        }
+        let Some(cached) = self.found.get_mut(parent) else {
+            return;
+        };
+        match name.to_str() {
+            Some(name) => cached.entries.push(CachedEntry {
+                name: name.to_string(),
+                matched: matched.is_some(),
+                dir,
+                id: matched.flatten(),
+            }),
+            None => {
+                self.incomplete.insert(parent.to_path_buf());
+            }
+        }
+    }
+
+    /// Doesn't cache the directory at `path`, nor the one it is in, since an
+    /// entry of theirs was skipped.
+    pub(crate) fn forget(&mut self, path: &Path) {
+        self.incomplete.insert(path.to_path_buf());
+        if let Some(parent) = path.parent() {
+            self.incomplete.insert(parent.to_path_buf());
+        }
+    }
+
+    /// Saves what this search found, replacing what earlier ones did.
+    pub(crate) fn save(self) -> Result<(), Error> {
+        let racy = self.started.checked_sub(RACY).and_then(stamp);
+        let dirs: Vec<Value> = self.found.iter()
+            .filter(|(path, dir)| !self.incomplete.contains(*path) && racy.is_some_and(|racy| dir.modified < racy))
+            .filter_map(|(path, dir)| Some(dir_to_json(path.to_str()?, dir)))
+            .collect();
+        trace::event(Level::Debug, || format!("saving {} directories to the result cache {}", dirs.len(), self.file.display()));
+        let value = json!({ "key": self.key, "dirs": dirs });
+        if let Some(dir) = self.file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut tmp = self.file.as_os_str().to_owned();
+        tmp.push(".tmp");
+        fs::write(&tmp, value.to_string())?;
+        fs::rename(&tmp, &self.file)
+    }
+}
+
+/// Where `--cache` keeps the results of searches,
+/// `$XDG_CACHE_HOME/ffind/results` or `~/.cache/ffind/results`, whether or
+/// not it exists.
+pub fn default_dir() -> Option<PathBuf> {
+    let cache = std::env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+    Some(cache.join("ffind").join("results"))
+}
+
+/// Describes everything that decides what the search of `finder` up to
+/// `depth` finds, or returns why it can't be cached.
+fn key(finder: &Finder, depth: u32) -> Result<String, String> {
+    if let Some(filter) = finder.filters.iter().find(|filter| !filter.cacheable()) {
+        return Err(format!("{} doesn't only look at names", filter.name()));
+    }
+    if !finder.prune.is_empty() {
+        return Err(String::from("prune_if() callbacks can't be compared"));
+    }
+    if finder.usage.is_some() {
+        return Err(String::from("the disk usage needs every file looked up"));
+    }
+    let global_ignore_file = finder.global_ignore_file.as_ref()
+        .filter(|_| finder.ignore_files)
+        .map(|file| (file, fs::metadata(file).ok().and_then(|meta| meta.modified().ok()).and_then(stamp)));
+    let excludes: Vec<String> = finder.excludes.rules().iter().map(|rule| rule.to_string()).collect();
+    let filters: Vec<String> = finder.filters.iter().map(|filter| filter.name()).collect();
+    Ok(format!(
+        "ffind {} roots={:?} depth={} min_depth={} include_dirs={} dedup={} metadata={:?} ignore_files={} global_ignore_file={:?} excludes={:?} markers={:?} max_entries_per_dir={:?} filters={:?}",
+        env!("CARGO_PKG_VERSION"), finder.roots, depth, finder.min_depth, finder.include_dirs, finder.dedup, finder.metadata_mode,
+        finder.ignore_files, global_ignore_file, excludes, finder.prune_markers, finder.max_entries_per_dir.as_ref().map(|(max, _)| max), filters))
+}
+
+/// The modification times of the directory at `path` of `file_system` and of
+/// its ignore file, if it has one and `ignore_files` is set.
+fn stamps(path: &Path, file_system: &dyn FileSystem, ignore_files: bool) -> Option<(Stamp, Option<Stamp>)> {
+    let modified = stamp(file_system.metadata(path, MetadataMode::Follow).ok()?.modified()?)?;
+    let ignore_file = match ignore_files {
+        true => match file_system.metadata(&path.join(ignore::IGNORE_FILE), MetadataMode::NoFollow) {
+            Ok(meta) => Some(stamp(meta.modified()?)?),
+            Err(_) => None,
+        },
+        false => None,
+    };
+    Some((modified, ignore_file))
+}
+
+fn stamp(time: SystemTime) -> Option<Stamp> {
+    let since = time.duration_since(UNIX_EPOCH).ok()?;
+    Some((since.as_secs(), since.subsec_nanos()))
+}
+
+fn stamp_to_json(stamp: Stamp) -> Value {
+    json!([stamp.0, stamp.1])
+}
+
+fn stamp_from_json(value: &Value) -> Option<Stamp> {
+    match value.as_array()?.as_slice() {
+        [secs, nanos] => Some((secs.as_u64()?, nanos.as_u64().filter(|&n| n < 1_000_000_000)? as u32)),
+        _ => None,
+    }
+}
+
+fn dir_to_json(path: &str, dir: &CachedDir) -> Value {
+    let entries = dir.entries.iter().map(|entry| {
+        let mut fields = json!({ "name": entry.name, "matched": entry.matched, "dir": entry.dir });
+        match &entry.id {
+            Some(FileId::Inode(dev, ino)) => fields["inode"] = Value::from(format!("{}:{}", dev, ino)),
+            Some(FileId::Path(path)) => fields["canonical"] = Value::from(path.to_string_lossy()),
+            None => {}
+        }
+        fields
+    });
+    json!({
+        "path": path,
+        "modified": stamp_to_json(dir.modified),
+        "ignore_file": dir.ignore_file.map_or(Value::Null, stamp_to_json),
+        "entries": entries.collect::<Vec<_>>(),
+    })
+}
+
+fn from_json(text: &str, key: &str) -> Result<HashMap<PathBuf, CachedDir>, Error> {
+    let value = serde_json::from_str::<Value>(text)?;
+    if value.get("key").and_then(|k| k.as_str()) != Some(key) {
+        return Err(invalid("it is of another search"));
+    }
+    let entry = |value: &Value| -> Option<CachedEntry> {
+        let id = match (value.get("inode").and_then(|v| v.as_str()), value.get("canonical").and_then(|v| v.as_str())) {
+            (Some(inode), _) => {
+                let (dev, ino) = inode.split_once(':')?;
+                Some(FileId::Inode(dev.parse().ok()?, ino.parse().ok()?))
+            }
+            (None, Some(path)) => Some(FileId::Path(PathBuf::from(path))),
+            (None, None) => None,
+        };
+        Some(CachedEntry {
+            name: value.get("name")?.as_str()?.to_string(),
+            matched: value.get("matched")?.as_bool()?,
+            dir: value.get("dir")?.as_bool()?,
+            id,
+        })
+    };
+    let dir = |value: &Value| -> Option<(PathBuf, CachedDir)> {
+        let ignore_file = match value.get("ignore_file")? {
+            Value::Null => None,
+            stamp => Some(stamp_from_json(stamp)?),
+        };
+        Some((PathBuf::from(value.get("path")?.as_str()?), CachedDir {
+            modified: stamp_from_json(value.get("modified")?)?,
+            ignore_file,
+            entries: value.get("entries")?.as_array()?.iter().map(entry).collect::<Option<_>>()?,
+        }))
+    };
+    value.get("dirs")
+        .and_then(|dirs| dirs.as_array())
+        .ok_or_else(|| invalid("missing array 'dirs'"))?
+        .iter()
+        .map(|value| dir(value).ok_or_else(|| invalid("malformed directory")))
+        .collect()
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::new(io::ErrorKind::InvalidData, format!("Invalid result cache: {}.", reason))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Read;
+    use std::sync::{Arc, Mutex};
+    use crate::{dir, file};
+    use crate::vfs::{MemoryFileSystem, Metadata};
+
+    /// Records the directories read.
+    struct Reads {
+        fs: MemoryFileSystem,
+        dirs: Arc<Mutex<Vec<PathBuf>>>,
+    }
+
+    impl FileSystem for Reads {
+        fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>, Error> {
+            self.dirs.lock().unwrap().push(path.to_path_buf());
+            self.fs.read_dir(path)
+        }
+
+        fn metadata(&self, path: &Path, mode: MetadataMode) -> Result<Metadata, Error> {
+            self.fs.metadata(path, mode)
+        }
+
+        fn open(&self, path: &Path) -> Result<Box<dyn Read + Send>, Error> {
+            self.fs.open(path)
+        }
+    }
+
+    #[test]
+    fn unchanged_directories_are_not_read_again() {
+        let mut fs = dir! {
+            "src" => { file!("lib.rs"), file!("notes.md"), "bin" => { file!("main.rs") } },
+            "docs" => { file!("guide.md") },
+        };
+        let an_hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        for dir in [".", "src", "src/bin", "docs"] {
+            fs.set_modified(dir, an_hour_ago);
+        }
+        let cache = std::env::temp_dir().join(format!("ffind-cache-{}", std::process::id()));
+        let search = |fs: &MemoryFileSystem| {
+            let dirs = Arc::new(Mutex::new(Vec::new()));
+            let found = Finder::new(String::from("."))
+                .file_system(Reads { fs: fs.clone(), dirs: dirs.clone() })
+                .has_extension(String::from(".rs"))
+                .result_cache(&cache)
+                .find(99)
+                .unwrap();
+            let dirs = dirs.lock().unwrap().clone();
+            (found, dirs)
+        };
+        let (found, dirs) = search(&fs);
+        assert_eq!(vec!["./src/lib.rs", "./src/bin/main.rs"], found);
+        assert_eq!(4, dirs.len());
+        assert_eq!((found.clone(), Vec::new()), search(&fs));
+
+        // Only the directory a file was added to is read again.
+        fs.add_file("src/bin/cli.rs", "");
+        fs.set_modified("src/bin", an_hour_ago + Duration::from_secs(60));
+        let uncached = Finder::new(String::from(".")).file_system(fs.clone()).has_extension(String::from(".rs")).find(99).unwrap();
+        assert_eq!(vec!["./src/lib.rs", "./src/bin/cli.rs", "./src/bin/main.rs"], uncached);
+        assert_eq!((uncached, vec![PathBuf::from("./src/bin")]), search(&fs));
+
+        let finder = Finder::new(String::from(".")).size_less_than_or_eq(100);
+        assert!(key(&finder, 99).is_err());
+        fs::remove_dir_all(&cache).unwrap();
+    }
+
+}
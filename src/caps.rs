@@ -0,0 +1,123 @@
+/*
+File capabilities on Linux.
+
+Executables can be given capabilities, like 'cap_net_raw' for ping, instead
+of being setuid root: running them grants the capabilities set on the file.
+They are kept in the extended attribute 'security.capability', see the
+'xattr' module, which 'FileCapabilities' parses. 'HasFileCapabilities'
+retains the files with any capability set, permitted or inheritable, which
+belong in a security review next to setuid binaries.
+
+Only available on Linux.
+
+ */
+
+use std::io::{self, Error};
+use crate::filter::{FileInfo, Filter};
+use crate::trace::{self, Level};
+use crate::xattr;
+
+
+const NAME: &str = "security.capability";
+const REVISION_MASK: u32 = 0xff00_0000;
+const REVISION_1: u32 = 0x0100_0000;
+const REVISION_2: u32 = 0x0200_0000;
+const REVISION_3: u32 = 0x0300_0000;
+/// The effective flag: the permitted capabilities are raised on exec.
+const EFFECTIVE: u32 = 0x1;
+
+/// The capabilities set on a file, one bit per capability.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileCapabilities {
+    pub permitted: u64,
+    pub inheritable: u64,
+    pub effective: bool,
+    /// The user id that is root in the user namespace the capabilities
+    /// apply in, for those set from within one.
+    pub root_id: Option<u32>,
+}
+
+impl FileCapabilities {
+
+    /// Parses the capabilities as the kernel keeps them in their extended
+    /// attribute.
+    pub fn parse(bytes: &[u8]) -> Result<FileCapabilities, Error> {
+        let invalid = || Error::new(io::ErrorKind::InvalidData, "invalid file capabilities");
+        if !bytes.len().is_multiple_of(4) {
+            return Err(invalid());
+        }
+        let words = bytes.chunks_exact(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+            .collect::<Vec<_>>();
+        let magic = *words.first().ok_or_else(invalid)?;
+        let (sets, root_id) = match (magic & REVISION_MASK, words.len()) {
+            (REVISION_1, 3) => (&words[1..3], None),
+            (REVISION_2, 5) => (&words[1..5], None),
+            (REVISION_3, 6) => (&words[1..5], Some(words[5])),
+            _ => return Err(invalid()),
+        };
+        // The sets are stored 32 bits at a time, permitted then inheritable.
+        let (mut permitted, mut inheritable) = (0, 0);
+        for (half, set) in sets.chunks(2).enumerate() {
+            permitted |= (set[0] as u64) << (32 * half);
+            inheritable |= (set[1] as u64) << (32 * half);
+        }
+        Ok(FileCapabilities { permitted, inheritable, effective: magic & EFFECTIVE != 0, root_id })
+    }
+
+    /// The capabilities of `file`, if it has any set.
+    pub fn of(file: &FileInfo) -> Result<Option<FileCapabilities>, Error> {
+        xattr::get(file.path(), NAME, file.metadata_mode())?
+            .map(|bytes| FileCapabilities::parse(&bytes))
+            .transpose()
+    }
+
+    /// Returns true if no capability is set.
+    pub fn is_empty(&self) -> bool {
+        self.permitted == 0 && self.inheritable == 0
+    }
+}
+
+/// Retains files with capabilities set.
+pub struct HasFileCapabilities;
+
+impl Filter for HasFileCapabilities {
+    fn name(&self) -> String {
+        String::from("has_file_capabilities")
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        match FileCapabilities::of(file) {
+            Ok(caps) => caps.is_some_and(|caps| !caps.is_empty()),
+            Err(e) => {
+                trace::event(Level::Debug, || format!("cannot read the capabilities of {}: {}", file.path_str(), e));
+                false
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn xattr(words: &[u32]) -> Vec<u8> {
+        words.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn parses_capabilities() {
+        // cap_net_raw+ep, as setcap sets it for ping.
+        let ping = FileCapabilities::parse(&xattr(&[REVISION_2 | EFFECTIVE, 1 << 13, 0, 0, 0])).unwrap();
+        assert_eq!(FileCapabilities { permitted: 1 << 13, inheritable: 0, effective: true, root_id: None }, ping);
+        // cap_bpf+i, above the first 32 bits, set in a user namespace.
+        let bpf = FileCapabilities::parse(&xattr(&[REVISION_3, 0, 0, 0, 1 << 7, 100000])).unwrap();
+        assert_eq!((1 << 39, Some(100000)), (bpf.inheritable, bpf.root_id));
+        assert!(!bpf.is_empty());
+        assert!(FileCapabilities::parse(&xattr(&[REVISION_2, 0, 0, 0, 0])).unwrap().is_empty());
+        assert!(FileCapabilities::parse(&xattr(&[REVISION_2, 0, 0])).is_err());
+        assert!(FileCapabilities::parse(&[1, 0, 0]).is_err());
+    }
+
+}
@@ -0,0 +1,130 @@
+/*
+Attributes set with chattr(1), on Linux.
+
+Files on ext4, XFS, btrfs and other Linux filesystems have flags beyond their
+permissions, which lsattr shows: an immutable file can't be changed, renamed
+or deleted, even by root, and an append-only one can only grow. Both get in
+the way of deployments and cleanups in ways that are hard to tell apart
+from permission errors. 'HasAttribute' retains the files with one of them
+set, read with the FS_IOC_GETFLAGS ioctl.
+
+Only regular files and directories are looked at: opening anything else,
+like a device, could have side effects. Files on filesystems without such
+flags have none.
+
+Only available on Linux.
+
+ */
+
+use std::fs::OpenOptions;
+use std::io::Error;
+use std::os::raw::{c_int, c_long, c_ulong};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use crate::filter::{FileInfo, Filter, MetadataMode};
+use crate::trace::{self, Level};
+
+
+/// _IOR('f', 1, long)
+const FS_IOC_GETFLAGS: c_ulong = 0x8000_6601 | ((std::mem::size_of::<c_long>() as c_ulong) << 16);
+const FS_IMMUTABLE_FL: c_long = 0x10;
+const FS_APPEND_FL: c_long = 0x20;
+const O_NONBLOCK: c_int = 0o4000;
+const O_NOFOLLOW: c_int = 0o400000;
+const ENOTTY: i32 = 25;
+const EOPNOTSUPP: i32 = 95;
+
+extern "C" {
+    fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+}
+
+/// An attribute set with chattr.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Attribute {
+    /// `chattr +i`: the file can't be changed, renamed or deleted.
+    Immutable,
+    /// `chattr +a`: the file can only be appended to.
+    AppendOnly,
+}
+
+impl Attribute {
+
+    fn flag(self) -> c_long {
+        match self {
+            Attribute::Immutable => FS_IMMUTABLE_FL,
+            Attribute::AppendOnly => FS_APPEND_FL,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Attribute::Immutable => "immutable",
+            Attribute::AppendOnly => "append_only",
+        }
+    }
+}
+
+/// Returns true if `path` has `attribute` set, following a symlink unless
+/// `mode` is `NoFollow`. Fails if the file can't be opened; files on
+/// filesystems without attributes don't have any.
+pub fn has(path: &Path, attribute: Attribute, mode: MetadataMode) -> Result<bool, Error> {
+    let flags = match mode {
+        MetadataMode::Follow => O_NONBLOCK,
+        MetadataMode::NoFollow => O_NONBLOCK | O_NOFOLLOW,
+    };
+    let file = OpenOptions::new().read(true).custom_flags(flags).open(path)?;
+    let mut attributes: c_long = 0;
+    if unsafe { ioctl(file.as_raw_fd(), FS_IOC_GETFLAGS, &mut attributes as *mut c_long) } < 0 {
+        let e = Error::last_os_error();
+        return match e.raw_os_error() {
+            Some(ENOTTY) | Some(EOPNOTSUPP) => Ok(false),
+            _ => Err(e),
+        };
+    }
+    Ok(attributes & attribute.flag() != 0)
+}
+
+/// Retains regular files and directories with an attribute set.
+pub struct HasAttribute(pub Attribute);
+
+impl Filter for HasAttribute {
+    fn name(&self) -> String {
+        format!("has_attribute({})", self.0.as_str())
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        if !file.metadata().is_some_and(|meta| meta.is_file() || meta.is_dir()) {
+            return false;
+        }
+        match has(file.path(), self.0, file.metadata_mode()) {
+            Ok(has) => has,
+            Err(e) => {
+                trace::event(Level::Debug, || format!("cannot read the attributes of {}: {}", file.path_str(), e));
+                false
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn reads_attributes() {
+        if cfg!(target_pointer_width = "64") {
+            assert_eq!(0x8008_6601, FS_IOC_GETFLAGS);
+        }
+        let dir = std::env::temp_dir().join(format!("ffind-chattr-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.log"), "").unwrap();
+        assert!(!has(&dir.join("a.log"), Attribute::Immutable, MetadataMode::Follow).unwrap());
+        assert!(!has(&dir, Attribute::AppendOnly, MetadataMode::Follow).unwrap());
+        assert!(has(&dir.join("missing"), Attribute::Immutable, MetadataMode::Follow).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+}
@@ -0,0 +1,220 @@
+/*
+Checkpoints of long searches.
+
+A search of a big tree, like a NAS, can take hours. With
+'Finder::checkpoint()', the state of a breadth first search, i.e. the
+entries still queued and the matches so far, is saved to a file every once in
+a while, and an interrupted search picks up from there with
+'Finder::resume()' instead of starting over. The checkpoint is removed once
+the search completes.
+
+The same state serves to fetch the matches of a search a page at a time
+with 'Finder::find_page()', each page returning a 'Cursor' to fetch the next
+one from.
+
+Checkpoints are JSON files, written to a temporary file first and then
+renamed, so an interruption while saving doesn't lose the previous
+checkpoint.
+
+ */
+
+use std::fs;
+use std::io::{self, Error};
+use std::path::{Path, PathBuf};
+use serde_json::{json, Value};
+
+
+/// The state of a search that can be resumed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    /// The roots of the search, which a resumed search has to have too.
+    pub roots: Vec<String>,
+    /// The maximum depth of the search, see `Finder::find()`.
+    pub depth: u32,
+    /// The entries queued, in order.
+    pub pending: Vec<Pending>,
+    /// The matches found so far.
+    pub results: Vec<String>,
+}
+
+/// An entry queued by a search.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pending {
+    pub path: PathBuf,
+    pub depth: u32,
+    /// The index of the root the entry is below.
+    pub root: usize,
+}
+
+impl Checkpoint {
+
+    /// Reads the checkpoint saved in `file`.
+    pub fn load(file: &Path) -> Result<Checkpoint, Error> {
+        Checkpoint::from_json(&fs::read_to_string(file)?)
+    }
+
+    /// Saves this checkpoint to `file`, replacing any previous one.
+    pub fn save(&self, file: &Path) -> Result<(), Error> {
+        let mut tmp = file.as_os_str().to_owned();
+        tmp.push(".tmp");
+        fs::write(&tmp, format!("{:#}", self.to_json()))?;
+        fs::rename(&tmp, file)
+    }
+
+    pub fn from_json(text: &str) -> Result<Checkpoint, Error> {
+        let value = serde_json::from_str::<Value>(text)?;
+        let strings = |key: &str| -> Result<Vec<String>, Error> {
+            value.get(key)
+                .and_then(|v| v.as_array())
+                .ok_or_else(|| invalid(&format!("missing array '{}'", key)))?
+                .iter()
+                .map(|s| s.as_str().map(|s| s.to_string()).ok_or_else(|| invalid(&format!("'{}' must only contain strings", key))))
+                .collect()
+        };
+        let depth = |value: &Value| value.get("depth")
+            .and_then(|d| d.as_u64())
+            .filter(|&d| d <= u32::MAX as u64)
+            .map(|d| d as u32)
+            .ok_or_else(|| invalid("'depth' must be a non-negative integer"));
+        let pending = value.get("pending")
+            .and_then(|v| v.as_array())
+            .ok_or_else(|| invalid("missing array 'pending'"))?
+            .iter()
+            .map(|entry| Ok(Pending {
+                path: PathBuf::from(entry.get("path").and_then(|p| p.as_str()).ok_or_else(|| invalid("pending entry without a path"))?),
+                depth: depth(entry)?,
+                root: entry.get("root").and_then(|r| r.as_u64()).ok_or_else(|| invalid("pending entry without a root"))? as usize,
+            }))
+            .collect::<Result<_, Error>>()?;
+        Ok(Checkpoint { roots: strings("roots")?, depth: depth(&value)?, pending, results: strings("results")? })
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "roots": self.roots,
+            "depth": self.depth,
+            "pending": self.pending.iter().map(|entry| json!({
+                "path": entry.path.to_string_lossy(),
+                "depth": entry.depth,
+                "root": entry.root,
+            })).collect::<Vec<_>>(),
+            "results": self.results,
+        })
+    }
+}
+
+/// Where a paged search stopped, to fetch the next page from, see
+/// `Finder::find_page()`. Like a checkpoint it holds the entries still queued,
+/// not the matches so far, and can be handed out as JSON, e.g. to the client
+/// of a server asking for the next page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor {
+    checkpoint: Checkpoint,
+}
+
+impl Cursor {
+
+    pub(crate) fn new(checkpoint: Checkpoint) -> Cursor {
+        Cursor { checkpoint }
+    }
+
+    pub(crate) fn checkpoint(&self) -> &Checkpoint {
+        &self.checkpoint
+    }
+
+    pub fn from_json(text: &str) -> Result<Cursor, Error> {
+        Checkpoint::from_json(text).map(Cursor::new)
+    }
+
+    pub fn to_json(&self) -> Value {
+        self.checkpoint.to_json()
+    }
+}
+
+/// A page of the matches of a search, see `Finder::find_page()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page {
+    pub matches: Vec<String>,
+    /// Where to fetch the next page from, or `None` if this is the last one.
+    pub next: Option<Cursor>,
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::new(io::ErrorKind::InvalidData, format!("Invalid checkpoint: {}.", reason))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{dir, file, Finder};
+    use std::time::Duration;
+
+    #[test]
+    fn json_round_trip() {
+        let checkpoint = Checkpoint {
+            roots: vec![String::from("."), String::from("/mnt/nas")],
+            depth: 7,
+            pending: vec![Pending { path: PathBuf::from("/mnt/nas/photos"), depth: 1, root: 1 }],
+            results: vec![String::from("./a.rs")],
+        };
+        assert_eq!(checkpoint, Checkpoint::from_json(&serde_json::to_string_pretty(&checkpoint.to_json()).unwrap()).unwrap());
+        assert!(Checkpoint::from_json(r#"{"roots": ["."]}"#).is_err());
+    }
+
+    #[test]
+    fn resumed_search_finds_the_rest() {
+        let fs = dir! {
+            file!(".ffindignore" => "*.log\n"),
+            "a" => { file!("1.rs"), file!("1.log") },
+            "b" => { "c" => { file!("2.rs"), file!("2.log") } },
+            file!("3.rs"),
+        };
+        let file = std::env::temp_dir().join(format!("ffind-checkpoint-{}.json", std::process::id()));
+        let finder = || Finder::new(String::from(".")).file_system(fs.clone()).ignore_files(true).global_ignore_file(None);
+        let expected = finder().find(99).unwrap();
+
+        // A search interrupted after the first levels, as saved.
+        let checkpoint = Checkpoint {
+            roots: vec![String::from(".")],
+            depth: 99,
+            pending: vec![Pending { path: PathBuf::from("./b/c"), depth: 2, root: 0 }],
+            results: vec![String::from("./.ffindignore"), String::from("./3.rs"), String::from("./a/1.rs")],
+        };
+        checkpoint.save(&file).unwrap();
+        let resumed = finder().resume(&file).unwrap().find(99).unwrap();
+        assert_eq!(expected, resumed);
+        assert!(finder().resume(&file).unwrap().find(3).is_err());
+
+        // Saving after every directory, then removing the checkpoint at the end.
+        assert_eq!(expected, finder().checkpoint(&file, Duration::ZERO).find(99).unwrap());
+        assert!(!file.exists());
+    }
+
+    #[test]
+    fn pages_add_up_to_the_search() {
+        let fs = dir! {
+            "a" => { file!("1.rs"), file!("2.rs") },
+            "b" => { "c" => { file!("3.rs"), file!("4.rs") } },
+            file!("5.rs"),
+        };
+        let finder = || Finder::new(String::from(".")).file_system(fs.clone());
+        let mut pages = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = finder().find_page(99, cursor.as_ref(), 2).unwrap();
+            pages.push(page.matches);
+            match page.next {
+                // As handed to a client and back.
+                Some(next) => cursor = Some(Cursor::from_json(&next.to_json().to_string()).unwrap()),
+                None => break,
+            }
+        }
+        assert!(pages.iter().all(|page| page.len() <= 2));
+        assert_eq!(finder().find(99).unwrap(), pages.concat());
+        assert!(finder().find_page(99, None, 0).is_err());
+        let first = finder().find_page(99, None, 2).unwrap();
+        assert!(finder().find_page(3, first.next.as_ref(), 2).is_err());
+    }
+
+}
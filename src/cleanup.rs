@@ -0,0 +1,151 @@
+/*
+Reports of what takes space, to decide what to delete.
+
+A 'CleanupReport' lists the matches of a search by the space they take on
+disk, largest first, with the running total and the share of the whole tree
+it adds up to: the space the files the search walked take, matched or not,
+as recorded by a 'DiskUsage' handed to the 'Finder', see
+'Finder::cleanup_report()'. The space a file takes is its allocated size,
+like du reports, rather than its length.
+
+ */
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+
+/// The space taken by the files a search walked. Like `SkippedDirs`, it is a
+/// cheap handle: clone it, hand one copy to the `Finder` and read the other
+/// after the search completes.
+#[derive(Debug, Clone, Default)]
+pub struct DiskUsage {
+    inner: Arc<Usage>,
+}
+
+#[derive(Debug, Default)]
+struct Usage {
+    files: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl DiskUsage {
+
+    pub fn new() -> DiskUsage {
+        DiskUsage::default()
+    }
+
+    pub(crate) fn record(&self, allocated: u64) {
+        self.inner.files.fetch_add(1, Ordering::Relaxed);
+        self.inner.bytes.fetch_add(allocated, Ordering::Relaxed);
+    }
+
+    /// How many files were walked.
+    pub fn files(&self) -> u64 {
+        self.inner.files.load(Ordering::Relaxed)
+    }
+
+    /// The space they take on disk, in bytes.
+    pub fn bytes(&self) -> u64 {
+        self.inner.bytes.load(Ordering::Relaxed)
+    }
+}
+
+/// A match and the space it takes, see `CleanupReport`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Row {
+    pub path: String,
+    /// The space the file takes on disk, in bytes.
+    pub allocated: u64,
+    /// The space this file and the ones before it take.
+    pub cumulative: u64,
+}
+
+/// The matches of a search by the space they take, largest first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CleanupReport {
+    pub rows: Vec<Row>,
+    /// The space taken by every file the search walked, in bytes.
+    pub scanned: u64,
+}
+
+impl CleanupReport {
+
+    /// Orders `files`, paths with the space they take, largest first and
+    /// then by path.
+    pub fn new(mut files: Vec<(String, u64)>, scanned: u64) -> CleanupReport {
+        files.sort_by(|(path, allocated), (other, other_allocated)| other_allocated.cmp(allocated).then_with(|| path.cmp(other)));
+        let mut cumulative = 0;
+        let rows = files.into_iter()
+            .map(|(path, allocated)| {
+                cumulative += allocated;
+                Row { path, allocated, cumulative }
+            })
+            .collect();
+        CleanupReport { rows, scanned }
+    }
+
+    /// The space all the matches take.
+    pub fn total(&self) -> u64 {
+        self.rows.last().map_or(0, |row| row.cumulative)
+    }
+
+    /// The share of the scanned tree `bytes` is, in percent.
+    pub fn percent(&self, bytes: u64) -> f64 {
+        if self.scanned == 0 {
+            0.0
+        } else {
+            bytes as f64 * 100.0 / self.scanned as f64
+        }
+    }
+
+    /// The report as a table, with a summary line at the end.
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{:>8}  {:>10}  {:>6}  PATH\n", "SIZE", "CUMULATIVE", "TREE");
+        for row in &self.rows {
+            text.push_str(&format!("{:>8}  {:>10}  {:>5.1}%  {}\n",
+                                   human(row.allocated), human(row.cumulative), self.percent(row.cumulative), row.path));
+        }
+        text.push_str(&format!("{} files take {} of the {} scanned ({:.1}%)\n",
+                               self.rows.len(), human(self.total()), human(self.scanned), self.percent(self.total())));
+        text
+    }
+}
+
+/// Writes `bytes` in powers of 1024 with one decimal, like `1.5G`, as
+/// `du -h` does.
+pub fn human(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["", "K", "M", "G", "T", "P"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{}B", bytes)
+    } else {
+        format!("{:.1}{}", value, UNITS[unit])
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn orders_by_space_taken() {
+        let files = vec![(String::from("./b"), 1024), (String::from("./c"), 3 << 20), (String::from("./a"), 1024)];
+        let report = CleanupReport::new(files, 4 << 20);
+        let rows: Vec<(&str, u64)> = report.rows.iter().map(|row| (row.path.as_str(), row.cumulative)).collect();
+        assert_eq!(vec![("./c", 3 << 20), ("./a", (3 << 20) + 1024), ("./b", (3 << 20) + 2048)], rows);
+        assert_eq!("    SIZE  CUMULATIVE    TREE  PATH\n\
+                    \x20   3.0M        3.0M   75.0%  ./c\n\
+                    \x20   1.0K        3.0M   75.0%  ./a\n\
+                    \x20   1.0K        3.0M   75.0%  ./b\n\
+                    3 files take 3.0M of the 4.0M scanned (75.0%)\n", report.to_text());
+        assert_eq!("512B", human(512));
+        assert_eq!("1.5G", human(3 << 29));
+    }
+
+}
@@ -0,0 +1,152 @@
+/*
+Asking before every change, like rm -i.
+
+Actions that change files, like replacing text or renaming, can ask before
+changing each one through a 'Confirmer', which answers every 'Proposal' with
+yes, no, yes to all the rest or quit. 'Prompt' asks on a terminal, reading
+y/N/a/q answers line by line; embedders supply their own UI by implementing
+the trait. 'Confirmations' remembers the answers that settle the rest, so
+that after "all" or "quit" the confirmer isn't asked again.
+
+ */
+
+use std::fmt;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+
+/// An answer to a `Proposal`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Answer {
+    /// Makes this change.
+    Yes,
+    /// Skips this change.
+    No,
+    /// Makes this change and all the following ones without asking.
+    All,
+    /// Skips this change and all the following ones.
+    Quit,
+}
+
+/// A change about to be made to a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Proposal<'a> {
+    /// What is done, like "rename" or "replace 3 matches in".
+    pub action: &'a str,
+    pub path: &'a Path,
+    /// Where the file goes, for moves.
+    pub to: Option<&'a Path>,
+}
+
+/// Decides whether changes are made, see the module documentation.
+pub trait Confirmer {
+    fn confirm(&mut self, proposal: &Proposal) -> Answer;
+}
+
+/// Asks on `output`, like stderr, and reads the answers from `input`. Answers
+/// other than y, a and q, or their words, are no; the end of the input quits.
+#[derive(Debug)]
+pub struct Prompt<R, W> {
+    input: R,
+    output: W,
+}
+
+/// A `Confirmer` that isn't asked again once its answer settles the rest.
+pub struct Confirmations {
+    confirmer: Box<dyn Confirmer>,
+    settled: Option<bool>,
+}
+
+impl fmt::Display for Proposal<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.action, self.path.display())?;
+        match self.to {
+            Some(to) => write!(f, " to {}", to.display()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<R: BufRead, W: Write> Prompt<R, W> {
+
+    pub fn new(input: R, output: W) -> Prompt<R, W> {
+        Prompt { input, output }
+    }
+}
+
+impl<R: BufRead, W: Write> Confirmer for Prompt<R, W> {
+    fn confirm(&mut self, proposal: &Proposal) -> Answer {
+        let _ = write!(self.output, "{}? [y/N/a/q] ", proposal);
+        let _ = self.output.flush();
+        let mut line = String::new();
+        match self.input.read_line(&mut line) {
+            Ok(0) | Err(_) => Answer::Quit,
+            Ok(_) => match line.trim().to_lowercase().as_str() {
+                "y" | "yes" => Answer::Yes,
+                "a" | "all" => Answer::All,
+                "q" | "quit" => Answer::Quit,
+                _ => Answer::No,
+            },
+        }
+    }
+}
+
+impl Confirmations {
+
+    pub fn new(confirmer: impl Confirmer + 'static) -> Confirmations {
+        Confirmations { confirmer: Box::new(confirmer), settled: None }
+    }
+
+    /// Returns true if the change of `proposal` is to be made, asking unless
+    /// an earlier answer settled it.
+    pub fn allows(&mut self, proposal: &Proposal) -> bool {
+        if let Some(settled) = self.settled {
+            return settled;
+        }
+        match self.confirmer.confirm(proposal) {
+            Answer::Yes => true,
+            Answer::No => false,
+            Answer::All => {
+                self.settled = Some(true);
+                true
+            }
+            Answer::Quit => {
+                self.settled = Some(false);
+                false
+            }
+        }
+    }
+
+    /// Returns true once told to quit, when nothing more will be allowed.
+    pub fn quit(&self) -> bool {
+        self.settled == Some(false)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn asks_until_settled() {
+        let proposal = |path| Proposal { action: "rename", path: Path::new(path), to: Some(Path::new("b.jpg")) };
+        let mut prompt = Prompt::new(Cursor::new("y\nnope\nA\n"), Vec::new());
+        assert_eq!(Answer::Yes, prompt.confirm(&proposal("a.jpeg")));
+        assert_eq!(Answer::No, prompt.confirm(&proposal("a.jpeg")));
+        assert_eq!(Answer::All, prompt.confirm(&proposal("a.jpeg")));
+        assert_eq!(Answer::Quit, prompt.confirm(&proposal("a.jpeg")));
+        assert!(String::from_utf8(prompt.output).unwrap().starts_with("rename a.jpeg to b.jpg? [y/N/a/q] "));
+
+        let mut confirmations = Confirmations::new(Prompt::new(Cursor::new("n\na\n"), std::io::sink()));
+        assert!(!confirmations.allows(&proposal("1")));
+        assert!(confirmations.allows(&proposal("2")));
+        assert!(confirmations.allows(&proposal("3")));
+        let mut confirmations = Confirmations::new(Prompt::new(Cursor::new("q\ny\n"), std::io::sink()));
+        assert!(!confirmations.allows(&proposal("1")));
+        assert!(confirmations.quit());
+        assert!(!confirmations.allows(&proposal("2")));
+    }
+
+}
@@ -0,0 +1,423 @@
+/*
+Filters looking inside files.
+
+Unlike the filters in the 'filter' module, which only need a file's name and
+metadata, these read the file itself, through the 'FileSystem' it is on. Files
+are streamed, so big files don't have to fit in memory, and reading stops as
+soon as the outcome is known.
+
+With 'Finder::mmap()', large files are mapped into memory and searched by
+several threads at once instead, see the 'mmap' module.
+
+Like zgrep, compressed files ('.gz', '.xz' and '.zst') can be searched as if
+they were decompressed. Decompression is done by the usual command line tools
+('gzip', 'xz' and 'zstd'), which have to be installed; a file that can't be
+decompressed is treated as unreadable.
+
+Matching contents with a regex needs the 'regex' feature.
+
+ */
+
+use std::io::{self, Error, Read};
+#[cfg(feature = "regex")]
+use std::io::{BufRead, BufReader};
+use std::process::{Child, ChildStdout, Command, Stdio};
+#[cfg(feature = "regex")]
+use std::sync::Arc;
+use std::thread::JoinHandle;
+#[cfg(feature = "regex")]
+use regex::bytes::Regex;
+use crate::expr::{self, Cmp};
+use crate::filter::{FileInfo, Filter};
+#[cfg(feature = "regex")]
+use crate::mmap;
+use crate::trace::{self, Level};
+
+
+/// Retains files with at least one line matching `re`. With `search_compressed`
+/// set, compressed files are matched by their decompressed contents.
+#[cfg(feature = "regex")]
+pub struct ContentMatches {
+    pub re: Regex,
+    pub search_compressed: bool,
+}
+
+#[cfg(feature = "regex")]
+impl Filter for ContentMatches {
+    fn name(&self) -> String {
+        if self.search_compressed {
+            format!("content_matches_compressed({:?})", self.re.as_str())
+        } else {
+            format!("content_matches({:?})", self.re.as_str())
+        }
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        // Large files are scanned in memory, unless they need decompressing.
+        let compressed = self.search_compressed && file.file_name().and_then(decompressor).is_some();
+        let large = file.metadata().is_some_and(|meta| meta.len() >= mmap::MIN_MAP_SIZE);
+        if large && !compressed {
+            match file.file_system().map(file.path()) {
+                Ok(Some(map)) => return mmap::is_match(Arc::new(map), &self.re),
+                Ok(None) => {}
+                Err(e) => {
+                    trace::event(Level::Warn, || format!("cannot read {}: {}", file.path_str(), e));
+                    return false;
+                }
+            }
+        }
+        let reader = match open(file, self.search_compressed) {
+            Ok(reader) => reader,
+            Err(e) => {
+                trace::event(Level::Warn, || format!("cannot read {}: {}", file.path_str(), e));
+                return false;
+            }
+        };
+        for line in BufReader::new(reader).split(b'\n') {
+            match line {
+                Ok(line) if self.re.is_match(&line) => return true,
+                Ok(_) => {}
+                Err(e) => {
+                    trace::event(Level::Warn, || format!("cannot read {}: {}", file.path_str(), e));
+                    return false;
+                }
+            }
+        }
+        false
+    }
+}
+
+#[cfg(feature = "regex")]
+impl ContentMatches {
+
+    /// Counts the non-overlapping occurrences of `re` in the contents of
+    /// `file`, like `rg --count-matches`, reading the whole file.
+    pub fn count(&self, file: &FileInfo) -> Result<u64, Error> {
+        let reader = open(file, self.search_compressed)?;
+        let mut count = 0;
+        for line in BufReader::new(reader).split(b'\n') {
+            count += self.re.find_iter(&line?).count() as u64;
+        }
+        Ok(count)
+    }
+}
+
+
+/// Retains text files with more (`+N`), less (`-N`) or exactly (`N`) `lines`
+/// lines. A last line without a newline counts too. Binary files, i.e. files
+/// with a NUL byte early on, never match.
+pub struct LineCount {
+    pub arg: String,
+    pub cmp: Cmp,
+    pub lines: u64,
+}
+
+/// How much of a file is looked at to tell whether it is binary, as in git.
+const BINARY_PROBE: usize = 8000;
+
+impl LineCount {
+
+    /// Parses a find-like line count, e.g. `+1000`.
+    pub fn parse(arg: &str) -> Result<LineCount, Error> {
+        let (cmp, digits) = expr::parse_cmp(arg);
+        let lines = digits.parse().map_err(|_| Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid line count '{}', expected [+-]N.", arg)))?;
+        Ok(LineCount { arg: arg.to_string(), cmp, lines })
+    }
+
+    /// Counts the lines read from `reader`, giving up as soon as there are more
+    /// than `self.lines`. Returns `None` for binary contents.
+    fn count(&self, mut reader: impl Read) -> Result<Option<u64>, Error> {
+        let mut buf = vec![0; 64 * 1024];
+        let (mut lines, mut read, mut last) = (0, 0, b'\n');
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            let chunk = &buf[..n];
+            if read < BINARY_PROBE && chunk[..n.min(BINARY_PROBE - read)].contains(&0) {
+                return Ok(None);
+            }
+            read += n;
+            lines += chunk.iter().filter(|&&b| b == b'\n').count() as u64;
+            last = chunk[n - 1];
+            if lines > self.lines {
+                return Ok(Some(lines));
+            }
+        }
+        Ok(Some(if last == b'\n' { lines } else { lines + 1 }))
+    }
+}
+
+impl Filter for LineCount {
+    fn name(&self) -> String {
+        format!("line_count({})", self.arg)
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        match open(file, false).and_then(|reader| self.count(reader)) {
+            Ok(Some(lines)) => self.cmp.compare(lines, self.lines),
+            Ok(None) => false,
+            Err(e) => {
+                trace::event(Level::Warn, || format!("cannot read {}: {}", file.path_str(), e));
+                false
+            }
+        }
+    }
+}
+
+
+/// Retains files whose contents are valid UTF-8 (or, if `valid` is false, the
+/// files that aren't). With a `sample`, only that many bytes at the start of
+/// each file are checked; a character cut in half at the end of the sample
+/// doesn't count as invalid.
+pub struct Utf8Validity {
+    pub valid: bool,
+    pub sample: Option<u64>,
+}
+
+impl Utf8Validity {
+
+    /// Returns true if everything read from `reader` (up to the sample size)
+    /// is valid UTF-8.
+    fn check(&self, reader: impl Read) -> Result<bool, Error> {
+        let mut reader: Box<dyn Read> = match self.sample {
+            Some(bytes) => Box::new(reader.take(bytes)),
+            None => Box::new(reader),
+        };
+        let mut buf = vec![0; 64 * 1024];
+        // The start of a character split across two reads.
+        let mut pending = 0;
+        loop {
+            let n = match reader.read(&mut buf[pending..]) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            let end = pending + n;
+            match std::str::from_utf8(&buf[..end]) {
+                Ok(_) => pending = 0,
+                Err(e) if e.error_len().is_none() => {
+                    let valid = e.valid_up_to();
+                    buf.copy_within(valid..end, 0);
+                    pending = end - valid;
+                }
+                Err(_) => return Ok(false),
+            }
+        }
+        // A character cut off by the end of the file is invalid, by the end of
+        // the sample it isn't.
+        Ok(pending == 0 || self.sample.is_some())
+    }
+}
+
+impl Filter for Utf8Validity {
+    fn name(&self) -> String {
+        let name = if self.valid { "valid_utf8" } else { "invalid_utf8" };
+        match self.sample {
+            Some(bytes) => format!("{}(sample = {})", name, bytes),
+            None => name.to_string(),
+        }
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        match open(file, false).and_then(|reader| self.check(reader)) {
+            Ok(valid) => valid == self.valid,
+            Err(e) => {
+                trace::event(Level::Warn, || format!("cannot read {}: {}", file.path_str(), e));
+                false
+            }
+        }
+    }
+}
+
+
+/// The command decompressing files with the given name to stdout, if it is
+/// the name of a compressed file.
+fn decompressor(file_name: &str) -> Option<&'static str> {
+    if file_name.ends_with(".gz") {
+        Some("gzip")
+    } else if file_name.ends_with(".xz") {
+        Some("xz")
+    } else if file_name.ends_with(".zst") {
+        Some("zstd")
+    } else {
+        None
+    }
+}
+
+/// Opens the contents of `file` for reading, decompressing them on the way if
+/// `decompress` is set and `file` is compressed.
+pub fn open(file: &FileInfo, decompress: bool) -> Result<Box<dyn Read>, Error> {
+    let reader = file.file_system().open(file.path())?;
+    match file.file_name().and_then(decompressor) {
+        Some(program) if decompress => Ok(Box::new(Decompressed::spawn(program, reader)?)),
+        _ => Ok(reader),
+    }
+}
+
+/// The output of a decompressor fed with a file's contents.
+struct Decompressed {
+    child: Child,
+    stdout: ChildStdout,
+    feeder: Option<JoinHandle<()>>,
+}
+
+impl Decompressed {
+    fn spawn(program: &str, mut input: Box<dyn Read + Send>) -> Result<Decompressed, Error> {
+        let mut child = Command::new(program)
+            .args(["-d", "-c"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| Error::new(e.kind(), format!("cannot run {}: {}", program, e)))?;
+        let mut stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        // Feed the input from another thread so neither pipe can fill up and
+        // block the other. A write error just means the reader stopped early.
+        let feeder = std::thread::spawn(move || {
+            let _ = io::copy(&mut input, &mut stdin);
+        });
+        Ok(Decompressed { child, stdout, feeder: Some(feeder) })
+    }
+}
+
+impl Read for Decompressed {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.stdout.read(buf)?;
+        if n == 0 && !buf.is_empty() && !self.child.wait()?.success() {
+            return Err(Error::new(io::ErrorKind::InvalidData, "corrupt compressed file"));
+        }
+        Ok(n)
+    }
+}
+
+impl Drop for Decompressed {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        if let Some(feeder) = self.feeder.take() {
+            let _ = feeder.join();
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use crate::filter::MetadataMode;
+    use crate::{dir, file};
+    use crate::vfs::MemoryFileSystem;
+
+    fn in_memory(fs: MemoryFileSystem, path: &str) -> FileInfo {
+        FileInfo::with_file_system(PathBuf::from(path), MetadataMode::Follow, Arc::new(fs))
+    }
+
+    #[cfg(feature = "regex")]
+    fn gzip(text: &str) -> Vec<u8> {
+        let mut child = Command::new("gzip").arg("-c")
+            .stdin(Stdio::piped()).stdout(Stdio::piped())
+            .spawn().unwrap();
+        io::Write::write_all(&mut child.stdin.take().unwrap(), text.as_bytes()).unwrap();
+        child.wait_with_output().unwrap().stdout
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn matches_lines() {
+        let fs = dir! { file!("app.log" => "starting\nERROR: disk full\n") };
+        let filter = |re| ContentMatches { re: Regex::new(re).unwrap(), search_compressed: false };
+        assert!(filter("^ERROR").matches(&in_memory(fs.clone(), "app.log")));
+        assert!(!filter("^disk").matches(&in_memory(fs.clone(), "app.log")));
+        assert!(!filter("x").matches(&in_memory(fs, "missing.log")));
+        assert_eq!("content_matches(\"^ERROR\")", filter("^ERROR").name());
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn matches_lines_of_large_files() {
+        let path = std::env::temp_dir().join(format!("ffind-content-{}.log", std::process::id()));
+        let mut text = "starting\n".repeat(200_000);
+        text.push_str("ERROR: disk full\n");
+        std::fs::write(&path, &text).unwrap();
+        let filter = |re| ContentMatches { re: Regex::new(re).unwrap(), search_compressed: false };
+        assert!(filter("^ERROR").matches(&FileInfo::new(path.clone())));
+        assert!(!filter("^disk").matches(&FileInfo::new(path.clone())));
+        assert!(!filter(r"starting\sERROR").matches(&FileInfo::new(path.clone())));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn counts_matches() {
+        let fs = dir! { file!("app.log" => "ERROR: disk full\nok\nERROR: ERROR\n") };
+        let filter = |re| ContentMatches { re: Regex::new(re).unwrap(), search_compressed: false };
+        assert_eq!(3, filter("ERROR").count(&in_memory(fs.clone(), "app.log")).unwrap());
+        assert_eq!(0, filter("^disk").count(&in_memory(fs.clone(), "app.log")).unwrap());
+        assert!(filter("x").count(&in_memory(fs, "missing.log")).is_err());
+    }
+
+    #[test]
+    fn counts_lines() {
+        let fs = dir! {
+            file!("three.txt" => "a\nb\nc"),
+            file!("two.txt" => "a\nb\n"),
+            file!("empty.txt"),
+            file!("binary" => vec![b'a', b'\n', 0, b'\n']),
+        };
+        let count = |arg, path| LineCount::parse(arg).unwrap().matches(&in_memory(fs.clone(), path));
+        assert!(count("3", "three.txt"));
+        assert!(count("+2", "three.txt"));
+        assert!(!count("+2", "two.txt"));
+        assert!(count("-1", "empty.txt"));
+        assert!(!count("-5", "binary"));
+        assert!(!count("+0", "binary"));
+        assert!(LineCount::parse("lots").is_err());
+    }
+
+    #[test]
+    fn checks_utf8() {
+        let fs = dir! {
+            file!("utf8.txt" => "grüße"),
+            file!("latin1.txt" => b"gr\xfc\xdfe".to_vec()),
+            file!("cut.txt" => "grü".as_bytes()[..3].to_vec()),
+        };
+        let check = |valid, sample, path| Utf8Validity { valid, sample }.matches(&in_memory(fs.clone(), path));
+        assert!(check(true, None, "utf8.txt"));
+        assert!(check(false, None, "latin1.txt"));
+        assert!(!check(true, None, "latin1.txt"));
+        assert!(check(false, None, "cut.txt"));
+        // The sample ends in the middle of the 'ü'.
+        assert!(check(true, Some(3), "utf8.txt"));
+        assert!(check(true, Some(2), "latin1.txt"));
+        assert!(check(true, Some(3), "cut.txt"));
+        let mut check_chunks = Utf8Validity { valid: true, sample: None };
+        let long = "ü".repeat(100_000);
+        assert!(check_chunks.check(long.as_bytes()).unwrap());
+        check_chunks.sample = Some(1);
+        assert!(check_chunks.check(long.as_bytes()).unwrap());
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn matches_compressed_lines() {
+        let fs = dir! {
+            file!("app.log.1.gz" => gzip("starting\nERROR: disk full\n")),
+            file!("bogus.gz" => "not gzip\nERROR\n"),
+        };
+        let filter = |search_compressed| ContentMatches { re: Regex::new("^ERROR").unwrap(), search_compressed };
+        assert!(filter(true).matches(&in_memory(fs.clone(), "app.log.1.gz")));
+        assert!(!filter(false).matches(&in_memory(fs.clone(), "app.log.1.gz")));
+        assert!(!filter(true).matches(&in_memory(fs, "bogus.gz")));
+    }
+
+}
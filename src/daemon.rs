@@ -0,0 +1,329 @@
+/*
+A daemon answering searches over a Unix domain socket.
+
+The daemon keeps an 'Index' of a tree warm in memory and answers queries from
+clients connecting to its socket. Requests and responses are JSON documents,
+one per line:
+
+    {"command": "query", "spec": { ...a SearchSpec... }}
+        -> {"ok": true, "matches": ["src/lib.rs", ...]}
+    {"command": "refresh"}   -> {"ok": true, "files": 1234}
+    {"command": "status"}    -> {"ok": true, "root": "src", "files": 1234}
+    {"command": "shutdown"}  -> {"ok": true}
+
+Any failure is reported as {"ok": false, "error": "..."}. A connection can
+send any number of requests; they are answered in order.
+
+A daemon given a sandbox, see 'Daemon::sandbox()', only answers requests with
+the "api_key" of a tenant, searching the roots it is allowed, and can't be
+shut down by its clients.
+
+ */
+
+use std::io::{self, BufRead, BufReader, Error, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use crate::index::Index;
+use serde_json::{json, Value};
+use crate::metrics::Metrics;
+use crate::sandbox::Sandbox;
+use crate::spec::SearchSpec;
+use crate::trace::{self, Level};
+
+
+/// The socket used when none is given: `$XDG_RUNTIME_DIR/ffind.sock`, or a
+/// per-user socket in the temp directory.
+pub fn default_socket() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("ffind.sock"),
+        None => {
+            let user = std::env::var("USER").unwrap_or_else(|_| String::from("user"));
+            std::env::temp_dir().join(format!("ffind-{}.sock", user))
+        }
+    }
+}
+
+pub struct Daemon {
+    index: Index,
+    listener: UnixListener,
+    socket: PathBuf,
+    metrics: Option<Metrics>,
+    sandbox: Option<Sandbox>,
+}
+
+impl Daemon {
+
+    /// Indexes `root` and binds the daemon to `socket`. A stale socket file
+    /// left behind by a daemon that is no longer running is replaced, but an
+    /// error is returned if another daemon is still listening on it.
+    pub fn bind(root: String, socket: &Path) -> Result<Daemon, Error> {
+        if socket.exists() {
+            if UnixStream::connect(socket).is_ok() {
+                return Err(Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("A daemon is already listening on {}.", socket.display())));
+            }
+            std::fs::remove_file(socket)?;
+        }
+        let index = Index::build(root)?;
+        let listener = UnixListener::bind(socket)?;
+        Ok(Daemon { index, listener, socket: socket.to_path_buf(), metrics: None, sandbox: None })
+    }
+
+    /// Counts the directories read refreshing the index and the matches of
+    /// queries in `metrics`, and times the queries, see the `metrics` module.
+    pub fn metrics(mut self, metrics: &Metrics) -> Self {
+        self.index.set_metrics(metrics);
+        self.metrics = Some(metrics.clone());
+        self
+    }
+
+    /// Only answers the clients whose "api_key" the sandbox knows, searching
+    /// the roots it allows them, see the `sandbox` module.
+    pub fn sandbox(mut self, sandbox: Sandbox) -> Self {
+        self.sandbox = Some(sandbox);
+        self
+    }
+
+    pub fn index(&self) -> &Index {
+        &self.index
+    }
+
+    /// Serves clients one connection at a time until a client asks the daemon
+    /// to shut down. Errors talking to one client don't stop the daemon.
+    pub fn run(mut self) -> Result<(), Error> {
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    trace::event(Level::Warn, || format!("could not accept a client: {}", e));
+                    continue;
+                }
+            };
+            if let Ok(Flow::Shutdown) = serve_connection(&mut self.index, self.metrics.as_ref(), self.sandbox.as_ref(), stream) {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.socket);
+    }
+}
+
+#[derive(PartialEq)]
+enum Flow {
+    Continue,
+    Shutdown,
+}
+
+fn serve_connection(index: &mut Index, metrics: Option<&Metrics>, sandbox: Option<&Sandbox>, stream: UnixStream) -> Result<Flow, Error> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (response, flow) = match serde_json::from_str::<Value>(&line) {
+            Ok(request) => handle(index, metrics, sandbox, &request),
+            Err(e) => (failure(&e.into()), Flow::Continue),
+        };
+        let written = writeln!(writer, "{}", response);
+        // Shut down even if the client went away without reading the answer.
+        if flow == Flow::Shutdown {
+            return Ok(flow);
+        }
+        written?;
+    }
+    Ok(Flow::Continue)
+}
+
+/// Answers a single request.
+fn handle(index: &mut Index, metrics: Option<&Metrics>, sandbox: Option<&Sandbox>, request: &Value) -> (Value, Flow) {
+    let _span = trace::span("daemon");
+    trace::event(Level::Debug, || format!("request {}", request));
+    let tenant = match sandbox.map(|sandbox| sandbox.tenant(request.get("api_key").and_then(|key| key.as_str()))).transpose() {
+        Ok(tenant) => tenant,
+        Err(e) => return (failure(&e), Flow::Continue),
+    };
+    let response = match request.get("command").and_then(|c| c.as_str()) {
+        Some("query") => {
+            let started = Instant::now();
+            let result = match request.get("spec") {
+                Some(spec) => SearchSpec::from_value(spec).and_then(|spec| match &tenant {
+                    Some(tenant) => index.query_sandboxed(&spec, tenant),
+                    None => index.query(&spec),
+                }),
+                None => Err(Error::new(io::ErrorKind::InvalidInput, "Missing 'spec' in query.")),
+            };
+            if let Some(metrics) = metrics {
+                metrics.observe_query(started.elapsed(), result.is_ok());
+            }
+            match result {
+                Ok(matches) => json!({ "ok": true, "matches": matches }),
+                Err(e) => failure(&e),
+            }
+        }
+        Some("refresh") => match index.refresh() {
+            Ok(()) => json!({ "ok": true, "files": index.len() }),
+            Err(e) => failure(&e),
+        },
+        Some("status") => json!({ "ok": true, "root": index.root(), "files": index.len() }),
+        Some("shutdown") if tenant.is_some() => failure(&Error::new(io::ErrorKind::PermissionDenied, "Clients can't shut down a sandboxed daemon.")),
+        Some("shutdown") => return (json!({ "ok": true }), Flow::Shutdown),
+        _ => failure(&Error::new(io::ErrorKind::InvalidInput, format!("Unknown request {}.", request))),
+    };
+    (response, Flow::Continue)
+}
+
+fn failure(e: &Error) -> Value {
+    json!({ "ok": false, "error": e.to_string() })
+}
+
+
+/// A client for a running daemon.
+pub struct Client {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl Client {
+
+    pub fn connect(socket: &Path) -> Result<Client, Error> {
+        let stream = UnixStream::connect(socket).map_err(|e| Error::new(
+            e.kind(),
+            format!("Could not connect to the daemon at {}: {}.", socket.display(), e)))?;
+        Ok(Client { writer: stream.try_clone()?, reader: BufReader::new(stream) })
+    }
+
+    /// Sends `request` and waits for the response. A response that isn't
+    /// `"ok"` is turned into an error.
+    pub fn request(&mut self, request: &Value) -> Result<Value, Error> {
+        writeln!(self.writer, "{}", request)?;
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(Error::new(io::ErrorKind::UnexpectedEof, "The daemon closed the connection."));
+        }
+        let response = serde_json::from_str::<Value>(&line)?;
+        match response.get("ok").and_then(|ok| ok.as_bool()) {
+            Some(true) => Ok(response),
+            _ => {
+                let reason = response.get("error").and_then(|e| e.as_str()).unwrap_or("unknown error");
+                Err(Error::other(format!("The daemon failed: {}", reason)))
+            }
+        }
+    }
+
+    /// Runs the search described by `spec` on the daemon.
+    pub fn query(&mut self, spec: &SearchSpec) -> Result<Vec<String>, Error> {
+        let response = self.request(&json!({ "command": "query", "spec": spec }))?;
+        let matches = response.get("matches")
+            .and_then(|m| m.as_array())
+            .ok_or_else(|| Error::new(io::ErrorKind::InvalidData, "Missing 'matches' in the response."))?;
+        Ok(matches.iter().filter_map(|m| m.as_str()).map(|m| m.to_string()).collect())
+    }
+
+    pub fn command(&mut self, command: &str) -> Result<Value, Error> {
+        self.request(&json!({ "command": command }))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::spec::FilterSpec;
+    use std::thread;
+
+    /// Writes 'src/lib.rs' and 'src/main.rs' into a temporary directory named
+    /// after `name`, returning the path of 'src'.
+    fn tree(name: &str) -> String {
+        let src = std::env::temp_dir().join(format!("ffind-daemon-{}-{}", name, std::process::id())).join("src");
+        std::fs::create_dir_all(&src).unwrap();
+        std::fs::write(src.join("lib.rs"), "").unwrap();
+        std::fs::write(src.join("main.rs"), "").unwrap();
+        src.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn query_over_socket() {
+        let src = tree("socket");
+        let socket = std::env::temp_dir().join(format!("ffind-test-{}.sock", std::process::id()));
+        let daemon = Daemon::bind(src.clone(), &socket).unwrap();
+        assert!(Daemon::bind(src.clone(), &socket).is_err(), "The socket is in use.");
+        let files = daemon.index().len() as u64;
+        let server = thread::spawn(move || daemon.run());
+
+        let mut client = Client::connect(&socket).unwrap();
+        let mut spec = SearchSpec::new(src.clone(), 1);
+        spec.filters.push(FilterSpec::NameStartsWith(String::from("lib")));
+        assert_eq!(vec![format!("{}/lib.rs", src)], client.query(&spec).unwrap());
+
+        spec.filters.push(FilterSpec::MatchesRegex(String::from("(")));
+        assert!(client.query(&spec).is_err());
+        assert!(client.command("bogus").is_err());
+        let status = client.command("status").unwrap();
+        assert_eq!(Some(files), status.get("files").and_then(|f| f.as_u64()));
+
+        client.command("shutdown").unwrap();
+        server.join().unwrap().unwrap();
+        assert!(!socket.exists());
+        std::fs::remove_dir_all(Path::new(&src).parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn sandboxed_requests_need_a_key() {
+        let src = tree("key");
+        let mut index = Index::build(src.clone()).unwrap();
+        let sandbox = Sandbox::new().allow("k1", &src).unwrap();
+        let ok = |(response, _): (Value, Flow)| response.get("ok").and_then(|ok| ok.as_bool()).unwrap();
+        let query = |key: &str, root: &str| json!({
+            "command": "query",
+            "api_key": key,
+            "spec": { "root": root, "depth": 1, "filters": [{ "name_starts_with": "lib" }] },
+        });
+        assert!(ok(handle(&mut index, None, Some(&sandbox), &query("k1", &src))));
+        assert!(!ok(handle(&mut index, None, Some(&sandbox), &query("k2", &src))));
+        assert!(!ok(handle(&mut index, None, Some(&sandbox), &query("k1", &format!("{}/..", src)))));
+        let (response, flow) = handle(&mut index, None, Some(&sandbox), &json!({ "command": "shutdown", "api_key": "k1" }));
+        assert!(flow == Flow::Continue && !ok((response, flow)));
+        std::fs::remove_dir_all(Path::new(&src).parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn sandboxed_queries_read_no_file_outside() {
+        use std::fs::{self, FileTimes};
+        use std::os::unix::fs::symlink;
+        use std::time::{Duration, SystemTime};
+        let dir = std::env::temp_dir().join(format!("ffind-daemon-sandbox-{}", std::process::id()));
+        let allowed = dir.join("allowed");
+        fs::create_dir_all(&allowed).unwrap();
+        fs::create_dir_all(dir.join("secret")).unwrap();
+        fs::write(allowed.join("a.txt"), "a\n").unwrap();
+        fs::write(dir.join("secret/key.txt"), "key\n").unwrap();
+        symlink(dir.join("secret/key.txt"), allowed.join("key.txt")).unwrap();
+        // Reading the file would move its access time, two days old, to now.
+        let long_ago = SystemTime::now() - Duration::from_secs(2 * 24 * 60 * 60);
+        fs::File::options().write(true).open(dir.join("secret/key.txt")).unwrap()
+            .set_times(FileTimes::new().set_accessed(long_ago)).unwrap();
+        let accessed = || fs::metadata(dir.join("secret/key.txt")).unwrap().accessed().unwrap();
+        assert_eq!(long_ago, accessed());
+
+        let root = allowed.to_str().unwrap().to_string();
+        let mut index = Index::build(root.clone()).unwrap();
+        let sandbox = Sandbox::new().allow("k1", &root).unwrap();
+        let query = json!({
+            "command": "query",
+            "api_key": "k1",
+            "spec": { "root": root, "depth": 1, "filters": [{ "line_count": "+0" }] },
+        });
+        let (response, _) = handle(&mut index, None, Some(&sandbox), &query);
+        assert_eq!(Some(&json!([allowed.join("a.txt")])), response.get("matches"));
+        assert_eq!(long_ago, accessed());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
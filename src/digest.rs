@@ -0,0 +1,126 @@
+/*
+Choosing how the contents of files are hashed.
+
+Hashes tell whether files have the same contents, to find copies, see
+'Finder::find_unique_by()', and whether files changed since a snapshot, see
+'Finder::snapshot()'. A 'HashAlgo' picks the hash, trading strength for
+speed:
+
+  - SHA-256, the default, is what sha256sum prints and what snapshots taken
+    before the choice was given hold. It is also the slowest.
+  - BLAKE3 is cryptographic too, several times faster, and hashes large
+    files on all the CPUs, see the 'blake3' module.
+  - XXH3 is faster still, but not cryptographic: files nobody crafted to
+    collide are told apart just as well, which is enough to find copies,
+    but not to check that nobody tampered with files, see the 'xxh3' module.
+
+Hashes are written in lowercase hexadecimal. A 'Hasher' takes the contents a
+piece at a time, for whichever hash was picked.
+
+ */
+
+use std::io::{self, Error, Read};
+use crate::blake3::Blake3;
+use crate::sha256::{self, Sha256};
+use crate::xxh3::Xxh3;
+
+
+/// Which hash contents are hashed with, see the module documentation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HashAlgo {
+    #[default]
+    Sha256,
+    Blake3,
+    Xxh3,
+}
+
+impl HashAlgo {
+
+    /// Parses a hash as given to `--hash-algo`: `sha256`, `blake3` or `xxh3`.
+    pub fn parse(arg: &str) -> Result<HashAlgo, Error> {
+        match arg {
+            "sha256" => Ok(HashAlgo::Sha256),
+            "blake3" => Ok(HashAlgo::Blake3),
+            "xxh3" => Ok(HashAlgo::Xxh3),
+            _ => Err(Error::new(io::ErrorKind::InvalidInput, format!("Unknown hash '{}', expected sha256, blake3 or xxh3.", arg))),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HashAlgo::Sha256 => "sha256",
+            HashAlgo::Blake3 => "blake3",
+            HashAlgo::Xxh3 => "xxh3",
+        }
+    }
+
+    pub fn hasher(self) -> Hasher {
+        match self {
+            HashAlgo::Sha256 => Hasher::Sha256(Sha256::new()),
+            HashAlgo::Blake3 => Hasher::Blake3(Blake3::new()),
+            HashAlgo::Xxh3 => Hasher::Xxh3(Xxh3::new()),
+        }
+    }
+
+    /// Hashes everything read from `reader`, in hexadecimal.
+    pub fn read(self, reader: impl Read) -> io::Result<String> {
+        match self {
+            HashAlgo::Sha256 => Sha256::read(reader).map(|digest| sha256::hex(&digest)),
+            HashAlgo::Blake3 => Blake3::read(reader).map(|hash| sha256::hex(&hash)),
+            HashAlgo::Xxh3 => Xxh3::read(reader).map(|hash| format!("{:016x}", hash)),
+        }
+    }
+}
+
+/// A hash of any `HashAlgo` being computed.
+#[derive(Debug, Clone)]
+pub enum Hasher {
+    Sha256(Sha256),
+    Blake3(Blake3),
+    Xxh3(Xxh3),
+}
+
+impl Hasher {
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(sha) => sha.update(data),
+            Hasher::Blake3(blake3) => blake3.update(data),
+            Hasher::Xxh3(xxh) => xxh.update(data),
+        }
+    }
+
+    /// The hash, in hexadecimal.
+    pub fn finish(self) -> String {
+        match self {
+            Hasher::Sha256(sha) => sha256::hex(&sha.finish()),
+            Hasher::Blake3(blake3) => sha256::hex(&blake3.finish()),
+            Hasher::Xxh3(xxh) => format!("{:016x}", xxh.finish()),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hashes_with_every_algo() {
+        let expected = [
+            (HashAlgo::Sha256, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+            (HashAlgo::Blake3, "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"),
+            (HashAlgo::Xxh3, "78af5f94892f3950"),
+        ];
+        for &(algo, hash) in expected.iter() {
+            assert_eq!(algo, HashAlgo::parse(algo.as_str()).unwrap());
+            assert_eq!(hash, algo.read(&b"abc"[..]).unwrap());
+            let mut hasher = algo.hasher();
+            hasher.update(b"a");
+            hasher.update(b"bc");
+            assert_eq!(hash, hasher.finish());
+        }
+        assert!(HashAlgo::parse("md5").is_err());
+    }
+
+}
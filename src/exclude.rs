@@ -0,0 +1,282 @@
+/*
+Named sets of directories to keep searches out of.
+
+Searching a source tree mostly means walking what tools put there: version
+control metadata, build output, installed dependencies and caches. An
+'Excludes' is a set of globs matched against the names of directories, which
+'Finder::excludes()' doesn't walk into, like 'Finder::prune_if()' does. The
+'dev' profile, 'Excludes::dev()', leaves out `.git`, `target`,
+`node_modules`, `__pycache__`, `.venv` and the like.
+
+Profiles are extended and toggled in '$XDG_CONFIG_HOME/ffind/profiles/NAME'
+(or '~/.config/ffind/profiles/NAME'): one glob per line adds a directory
+name to the profile called NAME, and a line starting with '!' takes one out,
+so that `!target` searches the `target` directories again with the 'dev'
+profile. Blank lines and lines starting with '#' are skipped. A file with
+another name than a built-in profile makes a profile of its own.
+
+A glob can come with conditions, as an 'ExcludeRule': `only_if_contains=FILE`
+only leaves out the directories with a FILE in them, like
+`target only_if_contains=CACHEDIR.TAG` for cargo's build output but not a
+`target` directory of sources, and `max_depth=N` only the directories up to
+N levels below the root of the search, like `build max_depth=1`. A rule
+replaces the one of the same glob before it, built-in ones included.
+
+ */
+
+use std::fmt;
+use std::fs;
+use std::io::{self, Error};
+use std::path::{Path, PathBuf};
+use crate::glob::Glob;
+use crate::ignore;
+use crate::vfs::FileSystem;
+use crate::walk;
+
+
+/// The directories the `dev` profile leaves out.
+const DEV: &[&str] = &[
+    ".git", ".hg", ".svn", ".jj",
+    "target", "node_modules", "bower_components",
+    "__pycache__", ".venv", "venv", ".tox", ".nox", ".eggs", "*.egg-info",
+    ".mypy_cache", ".pytest_cache", ".ruff_cache", ".gradle", ".cache",
+    ".next", ".nuxt", ".parcel-cache", ".terraform", ".stack-work", "zig-cache", "zig-out",
+];
+
+/// A set of directory names to leave out of searches.
+#[derive(Debug, Clone, Default)]
+pub struct Excludes {
+    pub(crate) rules: Vec<ExcludeRule>,
+}
+
+/// A glob matched against the names of directories to leave out, with the
+/// conditions they also have to meet.
+#[derive(Debug, Clone)]
+pub struct ExcludeRule {
+    glob: Glob,
+    only_if_contains: Option<String>,
+    max_depth: Option<u32>,
+}
+
+impl ExcludeRule {
+
+    /// Leaves out the directories whose name matches the glob `pattern`.
+    /// Returns an error if it doesn't compile.
+    pub fn new(pattern: &str) -> Result<ExcludeRule, Error> {
+        Ok(ExcludeRule { glob: Glob::new(pattern)?, only_if_contains: None, max_depth: None })
+    }
+
+    /// Parses a rule as written in profile files: a glob, then the
+    /// conditions, like `target only_if_contains=CACHEDIR.TAG max_depth=2`.
+    pub fn parse(line: &str) -> Result<ExcludeRule, Error> {
+        let invalid = |reason: String| Error::new(io::ErrorKind::InvalidInput, reason);
+        let mut words = line.split_whitespace();
+        let mut rule = ExcludeRule::new(words.next().ok_or_else(|| invalid(String::from("Empty exclude rule.")))?)?;
+        for condition in words {
+            rule = match condition.split_once('=') {
+                Some(("only_if_contains", marker)) if !marker.is_empty() => rule.only_if_contains(marker),
+                Some(("max_depth", depth)) => rule.max_depth(depth.parse().map_err(|e| invalid(format!("Invalid max_depth '{}': {}.", depth, e)))?),
+                _ => return Err(invalid(format!("Unknown condition '{}', expected only_if_contains=FILE or max_depth=N.", condition))),
+            };
+        }
+        Ok(rule)
+    }
+
+    /// Only leaves out the directories that contain a file named `marker`,
+    /// which for a `CACHEDIR.TAG` has to be one, see `Finder::prune_if_contains()`.
+    pub fn only_if_contains(mut self, marker: &str) -> Self {
+        self.only_if_contains = Some(marker.to_string());
+        self
+    }
+
+    /// Only leaves out the directories at most `depth` levels below the root
+    /// of the search.
+    pub fn max_depth(mut self, depth: u32) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// The glob of this rule.
+    pub fn pattern(&self) -> &str {
+        self.glob.as_str()
+    }
+
+    /// Returns true if this rule leaves out the directory at `path` of
+    /// `file_system`, `depth` levels below the root of the search.
+    pub fn is_excluded(&self, path: &Path, depth: u32, file_system: &dyn FileSystem) -> bool {
+        let name_matches = path.file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| self.glob.is_match(name));
+        name_matches
+            && self.max_depth.is_none_or(|max| depth <= max)
+            && self.only_if_contains.as_ref().is_none_or(|marker| walk::contains_marker(file_system, path, marker))
+    }
+}
+
+/// Writes the rule as in profile files.
+impl fmt::Display for ExcludeRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.pattern())?;
+        if let Some(marker) = &self.only_if_contains {
+            write!(f, " only_if_contains={}", marker)?;
+        }
+        if let Some(depth) = self.max_depth {
+            write!(f, " max_depth={}", depth)?;
+        }
+        Ok(())
+    }
+}
+
+impl Excludes {
+
+    /// Leaves nothing out.
+    pub fn new() -> Excludes {
+        Excludes::default()
+    }
+
+    /// Leaves out version control metadata, build output, dependencies and
+    /// caches of the usual languages and tools.
+    pub fn dev() -> Excludes {
+        // The built-in globs compile.
+        DEV.iter().fold(Excludes::new(), |excludes, name| excludes.exclude(name).unwrap())
+    }
+
+    /// The profile called `name`: a built-in one, as extended and toggled by
+    /// its file in the configuration directory, or else the one the file
+    /// makes. Returns an error if there is neither.
+    pub fn profile(name: &str) -> Result<Excludes, Error> {
+        if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".." {
+            return Err(Error::new(io::ErrorKind::InvalidInput, format!("Invalid profile name '{}'.", name)));
+        }
+        Excludes::profile_from(name, profile_file(name))
+    }
+
+    fn profile_from(name: &str, file: Option<PathBuf>) -> Result<Excludes, Error> {
+        let builtin = match name {
+            "dev" => Some(Excludes::dev()),
+            _ => None,
+        };
+        let unknown = || Error::new(io::ErrorKind::NotFound, format!("Unknown profile '{}'.", name));
+        let file = match file {
+            Some(file) => file,
+            None => return builtin.ok_or_else(unknown),
+        };
+        match fs::read_to_string(&file) {
+            Ok(text) => builtin.unwrap_or_default()
+                .apply(&text)
+                .map_err(|e| Error::new(e.kind(), format!("{}: {}", file.display(), e))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => builtin.ok_or_else(unknown),
+            Err(e) => Err(Error::new(e.kind(), format!("Could not read {}: {}", file.display(), e))),
+        }
+    }
+
+    /// Also leaves out the directories whose name matches the glob
+    /// `pattern`. Returns an error if it doesn't compile.
+    pub fn exclude(self, pattern: &str) -> Result<Excludes, Error> {
+        Ok(self.rule(ExcludeRule::new(pattern)?))
+    }
+
+    /// Also leaves out what `rule` does, instead of what an earlier rule of
+    /// the same glob did.
+    pub fn rule(mut self, rule: ExcludeRule) -> Excludes {
+        self = self.include(rule.pattern());
+        self.rules.push(rule);
+        self
+    }
+
+    /// No longer leaves out what the rule of `pattern` did.
+    pub fn include(mut self, pattern: &str) -> Excludes {
+        self.rules.retain(|rule| rule.pattern() != pattern);
+        self
+    }
+
+    /// Adds and removes the patterns of a profile file, see the module
+    /// documentation.
+    pub fn apply(self, text: &str) -> Result<Excludes, Error> {
+        text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .try_fold(self, |excludes, line| match line.strip_prefix('!') {
+                Some(pattern) => Ok(excludes.include(pattern.trim())),
+                None => Ok(excludes.rule(ExcludeRule::parse(line)?)),
+            })
+    }
+
+    /// The rules, in the order they were added.
+    pub fn rules(&self) -> &[ExcludeRule] {
+        &self.rules
+    }
+
+    /// The globs of the rules, in the order they were added.
+    pub fn patterns(&self) -> impl Iterator<Item = &str> {
+        self.rules.iter().map(ExcludeRule::pattern)
+    }
+
+    /// Returns true if a rule leaves out the directory at `path` of
+    /// `file_system`, `depth` levels below the root of the search.
+    pub fn is_excluded(&self, path: &Path, depth: u32, file_system: &dyn FileSystem) -> bool {
+        self.rules.iter().any(|rule| rule.is_excluded(path, depth, file_system))
+    }
+}
+
+/// The location of the file of the profile called `name`, whether or not it
+/// exists.
+pub fn profile_file(name: &str) -> Option<PathBuf> {
+    Some(ignore::config_dir()?.join("profiles").join(name))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{dir, file, Finder};
+
+    #[test]
+    fn dev_profile_skips_tool_output() {
+        let fs = dir! {
+            file!("Cargo.toml"),
+            "src" => { file!("lib.rs") },
+            "target" => { "debug" => { file!("build.rs") } },
+            "web" => { "node_modules" => { "left-pad" => { file!("index.js") } }, file!("app.js") },
+            "ffind.egg-info" => { file!("PKG-INFO") },
+        };
+        let found = Finder::new(String::from(".")).file_system(fs.clone()).excludes(Excludes::dev()).find(99).unwrap();
+        assert_eq!(vec!["./Cargo.toml", "./src/lib.rs", "./web/app.js"], found);
+        let excludes = Excludes::dev().apply("# search the build output\n!target\ndist\n").unwrap();
+        assert!(!excludes.patterns().any(|pattern| pattern == "target"));
+        assert!(excludes.is_excluded(Path::new("web/dist"), 1, &fs));
+        let found = Finder::new(String::from(".")).file_system(fs).excludes(excludes).find(99).unwrap();
+        assert_eq!(vec!["./Cargo.toml", "./src/lib.rs", "./web/app.js", "./target/debug/build.rs"], found);
+    }
+
+    #[test]
+    fn rules_have_conditions() {
+        let fs = dir! {
+            "target" => { file!("CACHEDIR.TAG" => "Signature: 8a477f597d28d172789f06886806bc55\n"), file!("out.rs") },
+            "docs" => { "target" => { file!("index.md") }, "build" => { file!("guide.md") } },
+            "build" => { file!("app.js") },
+        };
+        let excludes = Excludes::new().apply("target only_if_contains=CACHEDIR.TAG\nbuild max_depth=1\n").unwrap();
+        let found = Finder::new(String::from(".")).file_system(fs).excludes(excludes).find(99).unwrap();
+        assert_eq!(vec!["./docs/build/guide.md", "./docs/target/index.md"], found);
+        assert!(ExcludeRule::parse("target max_depth=-1").is_err());
+        assert!(ExcludeRule::parse("target only_if=CACHEDIR.TAG").is_err());
+    }
+
+    #[test]
+    fn profiles_come_from_files() {
+        let file = std::env::temp_dir().join(format!("ffind-profile-{}", std::process::id()));
+        fs::write(&file, "photos\n*.tmp\n").unwrap();
+        let profile = Excludes::profile_from("media", Some(file.clone())).unwrap();
+        assert_eq!(vec!["photos", "*.tmp"], profile.patterns().collect::<Vec<_>>());
+        let dev = Excludes::profile_from("dev", Some(file.clone())).unwrap();
+        assert!(dev.patterns().any(|pattern| pattern == ".git") && dev.patterns().any(|pattern| pattern == "photos"));
+        fs::write(&file, "[\n").unwrap();
+        assert!(Excludes::profile_from("media", Some(file.clone())).is_err());
+        fs::remove_file(&file).unwrap();
+        assert!(Excludes::profile_from("media", Some(file)).is_err());
+        assert!(Excludes::profile_from("dev", None).is_ok());
+        assert!(Excludes::profile("../ignore").is_err());
+    }
+
+}
@@ -0,0 +1,552 @@
+/*
+A parser for classic find(1) expressions.
+
+Parses expressions such as "-name '*.log' -a ( -size +1M -o -mtime -7 )" into
+a tree of filters (see the 'All', 'Any' and 'Not' combinators in the 'filter'
+module), so scripts written for GNU find can be reused as is. The grammar and
+operator precedence follow GNU find:
+
+    expr    := and ( ( -o | -or ) and )*
+    and     := unary ( [ -a | -and ] unary )*
+    unary   := ( ! | -not ) unary | primary
+    primary := ( expr ) | -name PATTERN | -iname PATTERN | -path PATTERN
+             | -ipath PATTERN | -regex REGEX | -size [+-]N[cwbkMG]
+             | -mtime [+-]N | -mmin [+-]N | -newer FILE | -type f|d|l
+             | -empty | -true | -false | -print
+
+As in find, '-print' is accepted (and always true) so existing expressions
+parse, but it doesn't change what is printed. '-regex' needs the 'regex'
+feature.
+
+ */
+
+use std::fs;
+use std::io::{self, Error};
+use std::time::SystemTime;
+#[cfg(feature = "regex")]
+use regex::Regex;
+use crate::filter::{All, Any, FileInfo, Filter, MatchesGlob, MetadataMode, Not};
+use crate::glob::Glob;
+
+
+/// A parsed find expression. It is itself a `Filter`, retaining the files the
+/// whole expression evaluates to true for.
+pub struct Expression {
+    root: Box<dyn Filter>,
+}
+
+impl Expression {
+
+    /// Parses an expression given as a single string. The string is split
+    /// into words the way a shell would, so quoting (`'*.log'`) and escaping
+    /// (`\(`) work as they do on the command line.
+    pub fn parse(expr: &str) -> Result<Expression, Error> {
+        Expression::from_args(&split(expr)?)
+    }
+
+    /// Parses an expression that has already been split into words, e.g. the
+    /// trailing arguments of a command line.
+    pub fn from_args<S: AsRef<str>>(args: &[S]) -> Result<Expression, Error> {
+        let tokens: Vec<&str> = args.iter().map(|s| s.as_ref()).collect();
+        let mut parser = Parser { tokens: &tokens, pos: 0, now: SystemTime::now() };
+        if tokens.is_empty() {
+            return Ok(Expression { root: Box::new(All(Vec::new())) });
+        }
+        let root = parser.parse_or()?;
+        if let Some(token) = parser.peek() {
+            return Err(invalid(format!("unexpected '{}'", token)));
+        }
+        Ok(Expression { root })
+    }
+}
+
+impl Filter for Expression {
+    fn name(&self) -> String {
+        self.root.name()
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        self.root.matches(file)
+    }
+}
+
+
+/// Splits `expr` into words using shell quoting rules: single quotes are taken
+/// literally, double quotes allow backslash escapes, and a backslash outside of
+/// quotes escapes the next character.
+pub fn split(expr: &str) -> Result<Vec<String>, Error> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = expr.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(invalid(String::from("unterminated single quote"))),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) => word.push(c),
+                            None => return Err(invalid(String::from("unterminated double quote"))),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(invalid(String::from("unterminated double quote"))),
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                match chars.next() {
+                    Some(c) => word.push(c),
+                    None => return Err(invalid(String::from("trailing backslash"))),
+                }
+            }
+            c => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// The inverse of `split()`: joins `words` into a single string, quoting the
+/// words that need it.
+pub fn join<S: AsRef<str>>(words: &[S]) -> String {
+    let quoted: Vec<String> = words.iter()
+        .map(|word| {
+            let word = word.as_ref();
+            let plain = |c: char| c.is_alphanumeric() || "-_+./:=@%,".contains(c);
+            if !word.is_empty() && word.chars().all(plain) {
+                word.to_string()
+            } else {
+                format!("'{}'", word.replace('\'', "'\\''"))
+            }
+        })
+        .collect();
+    quoted.join(" ")
+}
+
+
+struct Parser<'a> {
+    tokens: &'a [&'a str],
+    pos: usize,
+    now: SystemTime,
+}
+
+impl<'a> Parser<'a> {
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<&'a str> {
+        let token = self.peek();
+        self.pos += 1;
+        token
+    }
+
+    fn argument(&mut self, primary: &str) -> Result<&'a str, Error> {
+        self.next().ok_or_else(|| invalid(format!("missing argument to '{}'", primary)))
+    }
+
+    fn parse_or(&mut self) -> Result<Box<dyn Filter>, Error> {
+        let mut operands = vec![self.parse_and()?];
+        while let Some("-o") | Some("-or") = self.peek() {
+            self.next();
+            operands.push(self.parse_and()?);
+        }
+        if operands.len() == 1 {
+            return Ok(operands.pop().unwrap());
+        }
+        Ok(Box::new(Any(operands)))
+    }
+
+    fn parse_and(&mut self) -> Result<Box<dyn Filter>, Error> {
+        let mut operands = vec![self.parse_unary()?];
+        loop {
+            match self.peek() {
+                Some("-a") | Some("-and") => {
+                    self.next();
+                }
+                // Juxtaposed expressions are implicitly and-ed together.
+                Some(token) if token != "-o" && token != "-or" && token != ")" => {}
+                _ => break,
+            }
+            operands.push(self.parse_unary()?);
+        }
+        if operands.len() == 1 {
+            return Ok(operands.pop().unwrap());
+        }
+        Ok(Box::new(All(operands)))
+    }
+
+    fn parse_unary(&mut self) -> Result<Box<dyn Filter>, Error> {
+        match self.peek() {
+            Some("!") | Some("-not") => {
+                self.next();
+                Ok(Box::new(Not(self.parse_unary()?)))
+            }
+            _ => self.parse_primary(),
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Box<dyn Filter>, Error> {
+        let token = match self.next() {
+            Some(token) => token,
+            None => return Err(invalid(String::from("expected an expression at the end"))),
+        };
+        let filter: Box<dyn Filter> = match token {
+            "(" => {
+                let inner = self.parse_or()?;
+                if self.next() != Some(")") {
+                    return Err(invalid(String::from("missing ')'")));
+                }
+                inner
+            }
+            "-name" | "-iname" | "-path" | "-ipath" => {
+                let glob = Glob::new(self.argument(token)?)?
+                    .case_insensitive(token.starts_with("-i"));
+                Box::new(MatchesGlob { glob, full_path: token.ends_with("path") })
+            }
+            #[cfg(feature = "regex")]
+            "-regex" => {
+                let pattern = self.argument(token)?;
+                // Like find, the regex has to match the whole path.
+                let re = Regex::new(&format!("^(?:{})$", pattern))
+                    .map_err(|e| invalid(format!("invalid regex '{}': {}", pattern, e)))?;
+                Box::new(PathRegex { pattern: pattern.to_string(), re })
+            }
+            #[cfg(not(feature = "regex"))]
+            "-regex" => return Err(Error::new(io::ErrorKind::Unsupported,
+                                              "-regex needs ffind built with the regex feature.")),
+            "-size" => Box::new(Size::parse(self.argument(token)?)?),
+            "-mtime" => Box::new(Age::parse(token, self.argument(token)?, 24 * 60 * 60, self.now)?),
+            "-mmin" => Box::new(Age::parse(token, self.argument(token)?, 60, self.now)?),
+            "-newer" => {
+                let reference = self.argument(token)?;
+                let modified = fs::metadata(reference)
+                    .and_then(|meta| meta.modified())
+                    .map_err(|e| invalid(format!("cannot read '{}' for -newer: {}", reference, e)))?;
+                Box::new(Newer { reference: reference.to_string(), modified })
+            }
+            "-type" => {
+                let kind = self.argument(token)?;
+                match kind {
+                    "f" | "d" | "l" => Box::new(Type(kind.chars().next().unwrap())),
+                    _ => return Err(invalid(format!("unsupported -type '{}'", kind))),
+                }
+            }
+            "-empty" => Box::new(Empty),
+            "-true" | "-print" => Box::new(All(Vec::new())),
+            "-false" => Box::new(Any(Vec::new())),
+            ")" => return Err(invalid(String::from("unexpected ')'"))),
+            _ => return Err(invalid(format!("unknown primary or operator '{}'", token))),
+        };
+        Ok(filter)
+    }
+}
+
+fn invalid(reason: String) -> Error {
+    Error::new(io::ErrorKind::InvalidInput, format!("Invalid expression: {}.", reason))
+}
+
+
+/// How a numeric argument such as `+7`, `-7` or `7` compares.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cmp {
+    GreaterThan,
+    LessThan,
+    Equal,
+}
+
+/// Splits the leading `+` or `-` off a numeric argument.
+pub fn parse_cmp(arg: &str) -> (Cmp, &str) {
+    if let Some(rest) = arg.strip_prefix('+') {
+        (Cmp::GreaterThan, rest)
+    } else if let Some(rest) = arg.strip_prefix('-') {
+        (Cmp::LessThan, rest)
+    } else {
+        (Cmp::Equal, arg)
+    }
+}
+
+impl Cmp {
+    /// Returns true if `value` is greater than, less than or equal to `n`.
+    pub fn compare(self, value: u64, n: u64) -> bool {
+        match self {
+            Cmp::GreaterThan => value > n,
+            Cmp::LessThan => value < n,
+            Cmp::Equal => value == n,
+        }
+    }
+}
+
+
+/// `-size [+-]N[cwbkMG]`. As in find, the file size is rounded _up_ to the
+/// unit before comparing, and the default unit is 512 byte blocks.
+struct Size {
+    arg: String,
+    cmp: Cmp,
+    n: u64,
+    unit: u64,
+}
+
+impl Size {
+    fn parse(arg: &str) -> Result<Size, Error> {
+        let (cmp, rest) = parse_cmp(arg);
+        let (digits, unit) = match rest.char_indices().last() {
+            Some((i, c)) if c.is_ascii_alphabetic() => {
+                let unit = match c {
+                    'c' => 1,
+                    'w' => 2,
+                    'b' => 512,
+                    'k' => 1024,
+                    'M' => 1024 * 1024,
+                    'G' => 1024 * 1024 * 1024,
+                    _ => return Err(invalid(format!("invalid -size unit in '{}'", arg))),
+                };
+                (&rest[..i], unit)
+            }
+            _ => (rest, 512),
+        };
+        let n = digits.parse()
+            .map_err(|_| invalid(format!("invalid argument '{}' to -size", arg)))?;
+        Ok(Size { arg: arg.to_string(), cmp, n, unit })
+    }
+}
+
+impl Filter for Size {
+    fn name(&self) -> String {
+        format!("size({})", self.arg)
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        match file.metadata() {
+            Some(meta) => self.cmp.compare(meta.len().div_ceil(self.unit), self.n),
+            None => false
+        }
+    }
+}
+
+
+/// `-mtime [+-]N` and `-mmin [+-]N`: the time since the last modification, in
+/// whole units (any fraction is ignored, as in find).
+struct Age {
+    primary: &'static str,
+    arg: String,
+    cmp: Cmp,
+    n: u64,
+    unit_secs: u64,
+    now: SystemTime,
+}
+
+impl Age {
+    fn parse(primary: &str, arg: &str, unit_secs: u64, now: SystemTime) -> Result<Age, Error> {
+        let (cmp, digits) = parse_cmp(arg);
+        let n = digits.parse()
+            .map_err(|_| invalid(format!("invalid argument '{}' to {}", arg, primary)))?;
+        let primary = if unit_secs == 60 { "mmin" } else { "mtime" };
+        Ok(Age { primary, arg: arg.to_string(), cmp, n, unit_secs, now })
+    }
+}
+
+impl Filter for Age {
+    fn name(&self) -> String {
+        format!("{}({})", self.primary, self.arg)
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        match file.metadata().and_then(|meta| meta.modified()) {
+            Some(modified) => {
+                // Files modified in the future count as modified just now.
+                let age = self.now.duration_since(modified).map(|d| d.as_secs()).unwrap_or(0);
+                self.cmp.compare(age / self.unit_secs, self.n)
+            }
+            None => false
+        }
+    }
+}
+
+
+/// `-newer FILE`: modified more recently than FILE was when parsing.
+struct Newer {
+    reference: String,
+    modified: SystemTime,
+}
+
+impl Filter for Newer {
+    fn name(&self) -> String {
+        format!("newer({:?})", self.reference)
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        match file.metadata().and_then(|meta| meta.modified()) {
+            Some(modified) => modified > self.modified,
+            None => false
+        }
+    }
+}
+
+
+/// `-type f|d|l`.
+struct Type(char);
+
+impl Filter for Type {
+    fn name(&self) -> String {
+        format!("type({})", self.0)
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        match self.0 {
+            'l' => file.file_system()
+                .metadata(file.path(), MetadataMode::NoFollow)
+                .map(|meta| meta.is_symlink())
+                .unwrap_or(false),
+            'd' => file.metadata().map(|meta| meta.is_dir()).unwrap_or(false),
+            _ => file.metadata().map(|meta| meta.is_file()).unwrap_or(false),
+        }
+    }
+}
+
+
+/// `-empty`: an empty file or directory.
+struct Empty;
+
+impl Filter for Empty {
+    fn name(&self) -> String {
+        String::from("empty")
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        match file.metadata() {
+            Some(meta) if meta.is_dir() => file.file_system()
+                .read_dir(file.path())
+                .map(|entries| entries.is_empty())
+                .unwrap_or(false),
+            Some(meta) => meta.is_empty(),
+            None => false
+        }
+    }
+}
+
+
+/// `-regex REGEX`: the regex has to match the whole path.
+#[cfg(feature = "regex")]
+struct PathRegex {
+    pattern: String,
+    re: Regex,
+}
+
+#[cfg(feature = "regex")]
+impl Filter for PathRegex {
+    fn name(&self) -> String {
+        format!("regex({:?})", self.pattern)
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        self.re.is_match(file.path_str())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn lib_rs() -> FileInfo {
+        FileInfo::new(PathBuf::from("src/lib.rs"))
+    }
+
+    #[test]
+    fn split_quoted_words() {
+        let words = split(r#"-name '*.log' -o -name "a \"b\"" \( x\ y \)"#).unwrap();
+        assert_eq!(vec!["-name", "*.log", "-o", "-name", "a \"b\"", "(", "x y", ")"], words);
+        assert!(split("-name '*.log").is_err());
+    }
+
+    #[test]
+    fn join_is_inverse_of_split() {
+        let words = vec!["-name", "*.log", "(", "it's", "", "-size", "+1M", ")"];
+        assert_eq!(words, split(&join(&words)).unwrap());
+        assert_eq!("-name '*.log' -size +1M", join(&["-name", "*.log", "-size", "+1M"]));
+    }
+
+    #[test]
+    fn precedence_and_grouping() {
+        let expr = Expression::parse("-name '*.log' -a ( -size +1M -o -mtime -7 )").unwrap();
+        assert_eq!("all(matches_glob(\"*.log\"), any(size(+1M), mtime(-7)))", expr.name());
+        let expr = Expression::parse("-name a -o -name b -name c").unwrap();
+        assert_eq!("any(matches_glob(\"a\"), all(matches_glob(\"b\"), matches_glob(\"c\")))", expr.name());
+        let expr = Expression::parse("! -name a").unwrap();
+        assert_eq!("not(matches_glob(\"a\"))", expr.name());
+    }
+
+    #[test]
+    fn evaluates_against_files() {
+        assert!(Expression::parse("-name '*.rs' -type f").unwrap().matches(&lib_rs()));
+        assert!(Expression::parse("-iname 'LIB.*' -size -2M").unwrap().matches(&lib_rs()));
+        assert!(Expression::parse("-path 'src/*'").unwrap().matches(&lib_rs()));
+        #[cfg(feature = "regex")]
+        assert!(Expression::parse("-regex '.*/l[a-z]+\\.rs'").unwrap().matches(&lib_rs()));
+        #[cfg(not(feature = "regex"))]
+        assert!(Expression::parse("-regex '.*/l[a-z]+\\.rs'").is_err());
+        assert!(!Expression::parse("-name '*.rs' ! -size +1c").unwrap().matches(&lib_rs()));
+        assert!(!Expression::parse("-empty -o -false").unwrap().matches(&lib_rs()));
+        assert!(Expression::parse("").unwrap().matches(&lib_rs()));
+    }
+
+    #[test]
+    fn size_rounds_up_to_unit() {
+        let size = Size::parse("1k").unwrap();
+        assert_eq!((Cmp::Equal, 1, 1024), (size.cmp, size.n, size.unit));
+        // Any non-empty file is at least one 512 byte block.
+        assert!(Size::parse("+0").unwrap().matches(&lib_rs()));
+        assert!(!Size::parse("-1").unwrap().matches(&lib_rs()));
+        assert!(!Size::parse("-1M").unwrap().matches(&lib_rs()));
+        assert!(Size::parse("12x").is_err());
+    }
+
+    #[test]
+    fn age_in_whole_units() {
+        let modified = lib_rs().metadata().unwrap().modified().unwrap();
+        let now = modified + std::time::Duration::from_secs(3 * 24 * 60 * 60 + 60 * 60);
+        let days = |arg| Age::parse("-mtime", arg, 24 * 60 * 60, now).unwrap().matches(&lib_rs());
+        assert!(days("3"));
+        assert!(days("+2"));
+        assert!(!days("-3"));
+        assert!(days("-4"));
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert!(Expression::parse("-name").is_err());
+        assert!(Expression::parse("( -name a").is_err());
+        assert!(Expression::parse("-name a )").is_err());
+        assert!(Expression::parse("-bogus").is_err());
+        assert!(Expression::parse("-name a -o").is_err());
+        assert!(Expression::parse("-type x").is_err());
+    }
+
+}
@@ -0,0 +1,167 @@
+/*
+Choosing what is reported about every match.
+
+JSON output reports the path of every match; 'Fields' adds its size, its
+modification time, its permissions, its device and inode numbers, the type
+of its filesystem or the SHA-256 of its contents. Only the
+fields asked for are computed: metadata is looked up once if any of them
+needs it, and files are only read to be hashed.
+
+ */
+
+use std::io::{self, Error};
+use std::time::UNIX_EPOCH;
+use crate::filter::FileInfo;
+use serde_json::Value;
+use crate::sha256::{self, Sha256};
+use crate::trace::{self, Level};
+
+
+/// Something reported about a match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Path,
+    /// The size in bytes.
+    Size,
+    /// The modification time, in seconds since the epoch.
+    Mtime,
+    /// The permissions in octal, like "0644".
+    Perm,
+    /// The SHA-256 of the contents, in hexadecimal.
+    Hash,
+    /// The device number, or on Windows the volume serial number.
+    Dev,
+    /// The inode number, or on Windows the file index.
+    Inode,
+    /// The type of the filesystem, like "ext4", see the `fstype` module.
+    FsType,
+}
+
+impl Field {
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Field::Path => "path",
+            Field::Size => "size",
+            Field::Mtime => "mtime",
+            Field::Perm => "perm",
+            Field::Hash => "hash",
+            Field::Dev => "dev",
+            Field::Inode => "inode",
+            Field::FsType => "fstype",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Field> {
+        [Field::Path, Field::Size, Field::Mtime, Field::Perm, Field::Hash, Field::Dev, Field::Inode, Field::FsType].iter()
+            .copied()
+            .find(|field| field.as_str() == name)
+    }
+}
+
+/// The fields reported about every match, in order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fields {
+    fields: Vec<Field>,
+}
+
+impl Default for Fields {
+    /// Only the path.
+    fn default() -> Fields {
+        Fields { fields: vec![Field::Path] }
+    }
+}
+
+impl Fields {
+
+    pub fn new(fields: Vec<Field>) -> Fields {
+        Fields { fields }
+    }
+
+    /// Parses a comma separated list of fields, like `path,size,hash`.
+    pub fn parse(arg: &str) -> Result<Fields, Error> {
+        let fields = arg.split(',')
+            .map(|name| Field::parse(name.trim()).ok_or_else(|| Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unknown field '{}', expected path, size, mtime, perm, hash, dev, inode or fstype.", name))))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Fields { fields })
+    }
+
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    /// The fields of `file` as a JSON object. Fields that can't be computed,
+    /// e.g. the hash of a file that can't be read, are null.
+    pub fn entry(&self, file: &FileInfo) -> Value {
+        let members = self.fields.iter()
+            .map(|&field| {
+                let value = match field {
+                    Field::Path => Some(Value::from(file.path_str())),
+                    Field::Size => file.metadata().map(|meta| Value::from(meta.len())),
+                    Field::Mtime => file.metadata()
+                        .and_then(|meta| meta.modified())
+                        .map(|modified| match modified.duration_since(UNIX_EPOCH) {
+                            Ok(since) => Value::from(since.as_secs()),
+                            Err(e) => Value::from(-(e.duration().as_secs() as i64)),
+                        }),
+                    Field::Perm => file.metadata()
+                        .and_then(|meta| meta.permissions())
+                        .map(|mode| Value::from(format!("{:04o}", mode))),
+                    Field::Hash => hash(file).map(Value::from),
+                    Field::Dev => file.id().map(|(dev, _)| Value::from(dev)),
+                    Field::Inode => file.id().map(|(_, ino)| Value::from(ino)),
+                    Field::FsType => file.file_system().fs_type(file.path()).map(Value::from),
+                };
+                (field.as_str().to_string(), value.unwrap_or(Value::Null))
+            })
+            .collect();
+        Value::Object(members)
+    }
+}
+
+/// The SHA-256 of the contents of `file`, if it is a file that can be read.
+fn hash(file: &FileInfo) -> Option<String> {
+    if !file.metadata()?.is_file() {
+        return None;
+    }
+    match file.file_system().open(file.path()).and_then(Sha256::read) {
+        Ok(digest) => Some(sha256::hex(&digest)),
+        Err(e) => {
+            trace::event(Level::Warn, || format!("cannot hash {}: {}", file.path_str(), e));
+            None
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use crate::filter::MetadataMode;
+    use crate::vfs::MemoryFileSystem;
+
+    #[test]
+    fn computes_fields() {
+        let mut fs = MemoryFileSystem::new();
+        fs.add_file("a.txt", "abc").set_modified("a.txt", UNIX_EPOCH + Duration::from_secs(1000)).add_dir("dir");
+        let fs = Arc::new(fs);
+        let entry = |fields, path: &str| {
+            let file = FileInfo::with_file_system(PathBuf::from(path), MetadataMode::Follow, fs.clone());
+            Fields::parse(fields).unwrap().entry(&file).to_string()
+        };
+        assert_eq!(r#"{"path":"a.txt"}"#, entry("path", "a.txt"));
+        assert_eq!(r#"{"size":3,"mtime":1000,"perm":null,"path":"a.txt"}"#, entry("size,mtime,perm,path", "a.txt"));
+        assert_eq!(r#"{"hash":"ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"}"#, entry("hash", "a.txt"));
+        assert_eq!(r#"{"hash":null}"#, entry("hash", "dir"));
+        assert_eq!(r#"{"size":null}"#, entry("size", "missing"));
+        assert_eq!(r#"{"dev":0,"inode":2}"#, entry("dev,inode", "a.txt"));
+        assert!(Fields::parse("path,owner").is_err());
+        assert_eq!(vec![Field::Path], Fields::default().fields());
+    }
+
+}
@@ -0,0 +1,906 @@
+/*
+Filters used by the 'Finder' to narrow down search results.
+
+Every filter implements the 'Filter' trait, which gives it a name alongside the
+predicate itself. The name is what makes a filter chain introspectable: it is
+used when logging, explaining or reporting on a search. Plain closures taking a
+'&FileInfo' are filters too, through the blanket implementation at the bottom
+of this file.
+
+ */
+
+use std::cell::OnceCell;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use memchr::memmem;
+#[cfg(feature = "regex")]
+use regex::{Regex, RegexSet};
+use serde::{Deserialize, Serialize};
+use crate::fuzzy::Fuzzy;
+use crate::glob::Glob;
+use crate::vfs::{FileSystem, Metadata, StdFileSystem};
+
+
+/// How the metadata of a symlink is looked up, and so which file size and time
+/// filters look at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MetadataMode {
+    /// Uses the metadata of the file a symlink points to, like `fs::metadata()`.
+    #[default]
+    Follow,
+    /// Uses the metadata of the symlink itself, like `fs::symlink_metadata()`.
+    NoFollow,
+}
+
+/// A candidate file handed to each `Filter`. Metadata is looked up lazily the
+/// first time a filter asks for it and is shared by every filter after that.
+pub struct FileInfo {
+    path: PathBuf,
+    path_str: String,
+    mode: MetadataMode,
+    file_system: Arc<dyn FileSystem>,
+    metadata: OnceCell<Option<Metadata>>,
+    /// The root of the search this file was found below, if any.
+    root: Option<Arc<Path>>,
+    depth: u32,
+}
+
+impl FileInfo {
+
+    pub fn new(path: PathBuf) -> FileInfo {
+        FileInfo::with_metadata_mode(path, MetadataMode::Follow)
+    }
+
+    /// Creates a `FileInfo` whose `metadata()` is looked up according to `mode`.
+    pub fn with_metadata_mode(path: PathBuf, mode: MetadataMode) -> FileInfo {
+        static STD: OnceLock<Arc<dyn FileSystem>> = OnceLock::new();
+        let file_system = STD.get_or_init(|| Arc::new(StdFileSystem)).clone();
+        FileInfo::with_file_system(path, mode, file_system)
+    }
+
+    /// Creates a `FileInfo` for a file of the given filesystem.
+    pub fn with_file_system(path: PathBuf, mode: MetadataMode, file_system: Arc<dyn FileSystem>) -> FileInfo {
+        let path_str = path.to_string_lossy().into_owned();
+        FileInfo {
+            path,
+            path_str,
+            mode,
+            file_system,
+            metadata: OnceCell::new(),
+            root: None,
+            depth: 0,
+        }
+    }
+
+    /// Records that a search found this file `depth` levels below `root`.
+    pub(crate) fn found_below(mut self, root: Arc<Path>, depth: u32) -> FileInfo {
+        self.root = Some(root);
+        self.depth = depth;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The full path of this file as it was found, e.g. `src/lib.rs`.
+    pub fn path_str(&self) -> &str {
+        &self.path_str
+    }
+
+    /// How many levels below the root of the search this file was found, like
+    /// the depths of `Finder::find()`: 0 for a root itself, or for a file
+    /// that wasn't found by a search.
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    /// The path of this file relative to the root of the search it was found
+    /// below, e.g. `lib.rs` for `src/lib.rs` found searching `src`. It is
+    /// empty for a root itself, and the whole path for a file that wasn't
+    /// found by a search.
+    pub fn relative_path(&self) -> &Path {
+        self.root.as_deref()
+            .and_then(|root| self.path.strip_prefix(root).ok())
+            .unwrap_or(&self.path)
+    }
+
+    /// The last component of the path, or `None` if it isn't valid UTF-8.
+    pub fn file_name(&self) -> Option<&str> {
+        self.path.file_name().and_then(|name| name.to_str())
+    }
+
+    pub fn metadata_mode(&self) -> MetadataMode {
+        self.mode
+    }
+
+    /// The filesystem this file is on, for filters that need more than its
+    /// metadata.
+    pub fn file_system(&self) -> &dyn FileSystem {
+        self.file_system.as_ref()
+    }
+
+    /// Returns the metadata of this file, or `None` if it could not be read.
+    /// Symlinks are followed or not depending on the `MetadataMode`.
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata
+            .get_or_init(|| self.file_system.metadata(&self.path, self.mode).ok())
+            .as_ref()
+    }
+
+    /// What identifies this file whichever path it is found through, and
+    /// across renames: its device and inode numbers, or on Windows its
+    /// volume serial number and file index, if the filesystem has them.
+    pub fn id(&self) -> Option<(u64, u64)> {
+        self.metadata()?.id().or_else(|| self.file_system.id(&self.path, self.mode))
+    }
+
+    pub fn into_path_string(self) -> String {
+        self.path_str
+    }
+}
+
+
+/// A named predicate over a `FileInfo`. A file is retained by a `Finder` only
+/// if every one of its filters `matches()` it. Filters are `Send` and `Sync`,
+/// like the `Finder` holding them, so that it can be moved to another thread.
+pub trait Filter: Send + Sync {
+
+    /// A short, human readable description of this filter and its arguments,
+    /// e.g. `size_less_than_or_eq(100)`.
+    fn name(&self) -> String;
+
+    /// Returns true if the given file passes this filter.
+    fn matches(&self, file: &FileInfo) -> bool;
+
+    /// Returns false if no file below the directory at `dir` can pass this
+    /// filter, which keeps the walk out of it, see the `plan` module. By
+    /// default, any file might.
+    fn may_match_below(&self, _dir: &Path) -> bool {
+        true
+    }
+
+    /// Returns true if `may_match_below()` can rule out directories, i.e.
+    /// this filter narrows down what is walked, not only what is reported.
+    fn prunes(&self) -> bool {
+        false
+    }
+
+    /// Describes the filters this one was rewritten from to run faster, like
+    /// a regex turned into a comparison, see the `plan` module. By default,
+    /// there are none.
+    fn rewrites(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Returns true if this filter only looks at the names and paths of
+    /// files, so that its results don't change unless entries are added,
+    /// removed or renamed, which the result cache relies on, see the `cache`
+    /// module. By default, it might look at anything.
+    fn cacheable(&self) -> bool {
+        false
+    }
+}
+
+
+/// Retains files with a size less than or equal to `bytes`.
+pub struct SizeLessThanOrEq {
+    pub bytes: u64,
+}
+
+impl Filter for SizeLessThanOrEq {
+    fn name(&self) -> String {
+        format!("size_less_than_or_eq({})", self.bytes)
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        match file.metadata() {
+            Some(meta) => meta.len() <= self.bytes,
+            None => false
+        }
+    }
+}
+
+/// Retains files with a size greater than or equal to `bytes`.
+pub struct SizeGreaterThanOrEq {
+    pub bytes: u64,
+}
+
+impl Filter for SizeGreaterThanOrEq {
+    fn name(&self) -> String {
+        format!("size_greater_than_or_eq({})", self.bytes)
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        match file.metadata() {
+            Some(meta) => meta.len() >= self.bytes,
+            None => false
+        }
+    }
+}
+
+/// Retains files whose path ends with `ext`.
+pub struct HasExtension {
+    pub ext: String,
+    pub case_sensitive: bool,
+}
+
+impl Filter for HasExtension {
+    fn name(&self) -> String {
+        if self.case_sensitive {
+            format!("has_extension({:?})", self.ext)
+        } else {
+            format!("has_extension_case_insensitive({:?})", self.ext)
+        }
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        if self.case_sensitive {
+            file.path_str().ends_with(&self.ext)
+        } else {
+            file.path_str().to_lowercase().ends_with(&self.ext.to_lowercase())
+        }
+    }
+
+    fn cacheable(&self) -> bool {
+        true
+    }
+}
+
+/// Retains files whose name starts with `prefix`.
+pub struct NameStartsWith {
+    pub prefix: String,
+}
+
+impl Filter for NameStartsWith {
+    fn name(&self) -> String {
+        format!("name_starts_with({:?})", self.prefix)
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        match file.file_name() {
+            Some(name) => name.starts_with(&self.prefix),
+            None => false
+        }
+    }
+
+    fn cacheable(&self) -> bool {
+        true
+    }
+}
+
+/// Retains files whose name ends with `suffix`. Unlike `HasExtension`, only
+/// the name is looked at, so a suffix never spans a `/`.
+pub struct NameEndsWith {
+    pub suffix: String,
+}
+
+impl Filter for NameEndsWith {
+    fn name(&self) -> String {
+        format!("name_ends_with({:?})", self.suffix)
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        match file.file_name() {
+            Some(name) => name.ends_with(&self.suffix),
+            None => false
+        }
+    }
+
+    fn cacheable(&self) -> bool {
+        true
+    }
+}
+
+/// Retains files whose name without its extension is `stem`, e.g. `lib` for
+/// `lib.rs`, or `archive.tar` for `archive.tar.gz`.
+pub struct StemIs {
+    pub stem: String,
+}
+
+impl Filter for StemIs {
+    fn name(&self) -> String {
+        format!("stem_is({:?})", self.stem)
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        file.path().file_stem().and_then(|stem| stem.to_str()) == Some(self.stem.as_str())
+    }
+
+    fn cacheable(&self) -> bool {
+        true
+    }
+}
+
+/// Retains files for which `re` is found somewhere in the file name (or the
+/// whole path, if `full_path` is set).
+#[cfg(feature = "regex")]
+pub struct MatchesRegex {
+    pub re: Regex,
+    pub full_path: bool,
+}
+
+#[cfg(feature = "regex")]
+impl Filter for MatchesRegex {
+    fn name(&self) -> String {
+        if self.full_path {
+            format!("path_matches_regex({:?})", self.re.as_str())
+        } else {
+            format!("matches_regex({:?})", self.re.as_str())
+        }
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        match matched_text(file, self.full_path) {
+            Some(text) => self.re.is_match(text),
+            None => false
+        }
+    }
+
+    fn cacheable(&self) -> bool {
+        true
+    }
+}
+
+/// Stands in for a regex anchored at the end of the name and looking for an
+/// extension, like `\.rs$` or `\.tar\.gz$`: retains the same files by
+/// comparing the end of the name (or whole path, if `full_path` is set)
+/// instead of running the regex, see `extension_regex()`.
+pub struct ExtensionRegex {
+    pub pattern: String,
+    pub ext: String,
+    pub full_path: bool,
+}
+
+impl Filter for ExtensionRegex {
+    fn name(&self) -> String {
+        format!("has_extension({:?})", self.ext)
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        match matched_text(file, self.full_path) {
+            Some(text) => text.ends_with(&self.ext),
+            None => false
+        }
+    }
+
+    fn rewrites(&self) -> Vec<String> {
+        let regex = if self.full_path { "path_matches_regex" } else { "matches_regex" };
+        vec![format!("{}({:?}) as {}", regex, self.pattern, self.name())]
+    }
+
+    fn cacheable(&self) -> bool {
+        true
+    }
+}
+
+/// Returns the `ExtensionRegex` standing in for `pattern` if it is a `\.`,
+/// then letters, digits, `_` and more `\.`, then a `$`.
+pub fn extension_regex(pattern: &str, full_path: bool) -> Option<ExtensionRegex> {
+    let rest = pattern.strip_prefix(r"\.")?.strip_suffix('$')?;
+    let mut ext = String::from(".");
+    let mut chars = rest.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.next() == Some('.') => ext.push('.'),
+            c if c.is_ascii_alphanumeric() || c == '_' => ext.push(c),
+            _ => return None,
+        }
+    }
+    Some(ExtensionRegex { pattern: pattern.to_string(), ext, full_path })
+}
+
+/// Retains files whose name (or whole path, if `full_path` is set) contains
+/// `text`, found with a substring search rather than a regex.
+pub struct NameContains {
+    text: String,
+    finder: memmem::Finder<'static>,
+    full_path: bool,
+}
+
+impl NameContains {
+    pub fn new(text: &str, full_path: bool) -> NameContains {
+        NameContains { text: text.to_string(), finder: memmem::Finder::new(text).into_owned(), full_path }
+    }
+}
+
+impl Filter for NameContains {
+    fn name(&self) -> String {
+        if self.full_path {
+            format!("path_contains({:?})", self.text)
+        } else {
+            format!("name_contains({:?})", self.text)
+        }
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        match matched_text(file, self.full_path) {
+            Some(text) => self.finder.find(text.as_bytes()).is_some(),
+            None => false
+        }
+    }
+
+    fn cacheable(&self) -> bool {
+        true
+    }
+}
+
+/// Returns true if `pattern` has no regex metacharacters, i.e. only matches
+/// itself, so a `NameContains` can stand in for a `MatchesRegex`.
+pub fn is_literal(pattern: &str) -> bool {
+    // The characters `regex::escape()` escapes.
+    !pattern.contains(|c| r"\.+*?()|[]{}^$#&-~".contains(c))
+}
+
+/// Retains files for which any of the regexes of `set`, or all of them if
+/// `all` is set, are found in the file name (or the whole path, if
+/// `full_path` is set). The regexes are matched in a single pass.
+#[cfg(feature = "regex")]
+pub struct MatchesRegexSet {
+    pub set: RegexSet,
+    pub all: bool,
+    pub full_path: bool,
+}
+
+#[cfg(feature = "regex")]
+impl Filter for MatchesRegexSet {
+    fn name(&self) -> String {
+        let prefix = if self.full_path { "path_" } else { "" };
+        if self.all {
+            format!("{}matches_all_regexes({:?})", prefix, self.set.patterns())
+        } else {
+            format!("{}matches_any_regex({:?})", prefix, self.set.patterns())
+        }
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        match matched_text(file, self.full_path) {
+            Some(text) if self.all => self.set.matches(text).iter().count() == self.set.len(),
+            Some(text) => self.set.is_match(text),
+            None => false
+        }
+    }
+
+    fn cacheable(&self) -> bool {
+        true
+    }
+}
+
+/// Retains files whose name (or whole path, if `full_path` is set) matches
+/// `glob` in its entirety.
+pub struct MatchesGlob {
+    pub glob: Glob,
+    pub full_path: bool,
+}
+
+impl Filter for MatchesGlob {
+    fn name(&self) -> String {
+        if self.full_path {
+            format!("path_matches_glob({:?})", self.glob.as_str())
+        } else {
+            format!("matches_glob({:?})", self.glob.as_str())
+        }
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        match matched_text(file, self.full_path) {
+            Some(text) => self.glob.is_match(text),
+            None => false
+        }
+    }
+
+    /// The paths below `dir` start with it and a `/`.
+    fn may_match_below(&self, dir: &Path) -> bool {
+        if !self.full_path {
+            return true;
+        }
+        let mut prefix = dir.to_string_lossy().into_owned();
+        if !prefix.ends_with('/') {
+            prefix.push('/');
+        }
+        self.glob.may_match_prefix(&prefix)
+    }
+
+    fn prunes(&self) -> bool {
+        self.full_path
+    }
+
+    fn cacheable(&self) -> bool {
+        true
+    }
+}
+
+/// Retains files whose name is within the maximum distance of a fuzzy
+/// query, see the `fuzzy` module.
+pub struct FuzzyName {
+    pub query: Fuzzy,
+}
+
+impl Filter for FuzzyName {
+    fn name(&self) -> String {
+        format!("fuzzy_name({:?}, {})", self.query.as_str(), self.query.max_distance())
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        match file.file_name() {
+            Some(name) => self.query.distance(name).is_some(),
+            None => false
+        }
+    }
+
+    fn cacheable(&self) -> bool {
+        true
+    }
+}
+
+/// Retains files with a directory named like `glob` somewhere above them in
+/// their path as found, e.g. `tests` for `./tests/fixtures/a.json`.
+pub struct HasAncestor {
+    pub glob: Glob,
+}
+
+impl Filter for HasAncestor {
+    fn name(&self) -> String {
+        format!("has_ancestor_named({:?})", self.glob.as_str())
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        let dirs = match file.path().parent() {
+            Some(dirs) => dirs,
+            None => return false,
+        };
+        dirs.components().any(|component| match component {
+            Component::Normal(name) => name.to_str().is_some_and(|name| self.glob.is_match(name)),
+            _ => false,
+        })
+    }
+
+    fn cacheable(&self) -> bool {
+        true
+    }
+}
+
+/// Retains files modified before the file named `sibling` in the same
+/// directory, like generated code older than the schema it is generated from.
+/// Files without such a sibling, and the sibling itself, aren't retained.
+pub struct OlderThanSibling {
+    pub sibling: String,
+}
+
+impl Filter for OlderThanSibling {
+    fn name(&self) -> String {
+        format!("older_than_sibling({:?})", self.sibling)
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        if file.file_name().is_none_or(|name| name == self.sibling) {
+            return false;
+        }
+        let modified = match file.metadata().and_then(|meta| meta.modified()) {
+            Some(modified) => modified,
+            None => return false,
+        };
+        let sibling = file.path().with_file_name(&self.sibling);
+        file.file_system().metadata(&sibling, file.metadata_mode()).ok()
+            .and_then(|meta| meta.modified())
+            .is_some_and(|sibling_modified| modified < sibling_modified)
+    }
+}
+
+/// Retains files last modified before `time`, like those of `-mtime +N` but
+/// to the second. Files whose modification time isn't known aren't retained.
+pub struct ModifiedBefore {
+    pub time: SystemTime,
+}
+
+impl Filter for ModifiedBefore {
+    fn name(&self) -> String {
+        let secs = self.time.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+        format!("modified_before({})", secs)
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        file.metadata().and_then(|meta| meta.modified()).is_some_and(|modified| modified < self.time)
+    }
+}
+
+/// What the name filters look at: the file name, or the whole path as found
+/// (like `./src/lib.rs`) if `full_path` is set.
+fn matched_text(file: &FileInfo, full_path: bool) -> Option<&str> {
+    if full_path {
+        Some(file.path_str())
+    } else {
+        file.file_name()
+    }
+}
+
+/// Retains files that pass every one of the given filters. An empty `All`
+/// retains everything.
+pub struct All(pub Vec<Box<dyn Filter>>);
+
+impl Filter for All {
+    fn name(&self) -> String {
+        let names: Vec<String> = self.0.iter().map(|f| f.name()).collect();
+        format!("all({})", names.join(", "))
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        self.0.iter().all(|f| f.matches(file))
+    }
+
+    fn may_match_below(&self, dir: &Path) -> bool {
+        self.0.iter().all(|f| f.may_match_below(dir))
+    }
+
+    fn prunes(&self) -> bool {
+        self.0.iter().any(|f| f.prunes())
+    }
+
+    fn rewrites(&self) -> Vec<String> {
+        self.0.iter().flat_map(|f| f.rewrites()).collect()
+    }
+
+    fn cacheable(&self) -> bool {
+        self.0.iter().all(|f| f.cacheable())
+    }
+}
+
+/// Retains files that pass at least one of the given filters. An empty `Any`
+/// retains nothing.
+pub struct Any(pub Vec<Box<dyn Filter>>);
+
+impl Filter for Any {
+    fn name(&self) -> String {
+        let names: Vec<String> = self.0.iter().map(|f| f.name()).collect();
+        format!("any({})", names.join(", "))
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        self.0.iter().any(|f| f.matches(file))
+    }
+
+    fn may_match_below(&self, dir: &Path) -> bool {
+        self.0.iter().any(|f| f.may_match_below(dir))
+    }
+
+    fn prunes(&self) -> bool {
+        !self.0.is_empty() && self.0.iter().all(|f| f.prunes())
+    }
+
+    fn rewrites(&self) -> Vec<String> {
+        self.0.iter().flat_map(|f| f.rewrites()).collect()
+    }
+
+    fn cacheable(&self) -> bool {
+        self.0.iter().all(|f| f.cacheable())
+    }
+}
+
+/// Retains files that the given filter rejects.
+pub struct Not(pub Box<dyn Filter>);
+
+impl Filter for Not {
+    fn name(&self) -> String {
+        format!("not({})", self.0.name())
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        !self.0.matches(file)
+    }
+
+    fn rewrites(&self) -> Vec<String> {
+        self.0.rewrites()
+    }
+
+    fn cacheable(&self) -> bool {
+        self.0.cacheable()
+    }
+}
+
+
+/// Counters for a single filter collected by `FilterStats`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterStat {
+    pub filter: String,
+    /// Number of files this filter was evaluated against. Filters are evaluated
+    /// in order and evaluation stops at the first rejection, so later filters
+    /// usually see fewer files.
+    pub evaluated: u64,
+    pub rejected: u64,
+    /// Cumulative time spent in `matches()`.
+    pub elapsed: Duration,
+}
+
+/// An opt-in statistics collector for the filters of a `Finder`. It is a
+/// cheap handle: clone it, hand one copy to `Finder::filter_stats()` and read
+/// the results from the other after the search completes.
+#[derive(Debug, Clone, Default)]
+pub struct FilterStats {
+    stats: Arc<Mutex<Vec<FilterStat>>>,
+}
+
+impl FilterStats {
+
+    pub fn new() -> FilterStats {
+        FilterStats::default()
+    }
+
+    /// Clears any previous results and starts counting for the given filters.
+    pub(crate) fn reset<'a>(&self, filters: impl Iterator<Item = &'a dyn Filter>) {
+        let mut stats = self.stats.lock().unwrap();
+        *stats = filters
+            .map(|f| FilterStat { filter: f.name(), evaluated: 0, rejected: 0, elapsed: Duration::ZERO })
+            .collect();
+    }
+
+    /// Evaluates `filter` (the `index`th filter of the chain) against `file`
+    /// and records the outcome.
+    pub(crate) fn record(&self, index: usize, filter: &dyn Filter, file: &FileInfo) -> bool {
+        let start = Instant::now();
+        let passed = filter.matches(file);
+        let elapsed = start.elapsed();
+        let mut stats = self.stats.lock().unwrap();
+        if let Some(stat) = stats.get_mut(index) {
+            stat.evaluated += 1;
+            stat.elapsed += elapsed;
+            if !passed {
+                stat.rejected += 1;
+            }
+        }
+        passed
+    }
+
+    /// Returns a snapshot of the statistics collected so far, one entry per
+    /// filter in the order they are applied.
+    pub fn report(&self) -> Vec<FilterStat> {
+        self.stats.lock().unwrap().clone()
+    }
+}
+
+
+/// Any closure over a `&FileInfo` can be used as an (unnamed) filter.
+impl<F> Filter for F
+where
+    F: Fn(&FileInfo) -> bool + Send + Sync,
+{
+    fn name(&self) -> String {
+        String::from("filter")
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        self(file)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vfs::MemoryFileSystem;
+
+    #[test]
+    fn filter_names() {
+        assert_eq!("size_less_than_or_eq(10)", SizeLessThanOrEq { bytes: 10 }.name());
+        let ext = HasExtension { ext: String::from(".rs"), case_sensitive: false };
+        assert_eq!("has_extension_case_insensitive(\".rs\")", ext.name());
+        let closure = |file: &FileInfo| file.path_str().is_empty();
+        assert_eq!("filter", closure.name());
+    }
+
+    #[test]
+    fn file_info_file_name() {
+        let file = FileInfo::new(PathBuf::from("src/lib.rs"));
+        assert_eq!(Some("lib.rs"), file.file_name());
+        assert_eq!("src/lib.rs", file.path_str());
+        assert!(file.metadata().is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn metadata_mode_of_symlinks() {
+        use std::fs;
+        let link = std::env::temp_dir().join(format!("ffind-link-{}", std::process::id()));
+        let _ = fs::remove_file(&link);
+        std::os::unix::fs::symlink(fs::canonicalize("src/lib.rs").unwrap(), &link).unwrap();
+        let bytes = fs::metadata("src/lib.rs").unwrap().len();
+        let follow = FileInfo::new(link.clone());
+        let no_follow = FileInfo::with_metadata_mode(link.clone(), MetadataMode::NoFollow);
+        assert_eq!(Some(bytes), follow.metadata().map(|m| m.len()));
+        assert!(no_follow.metadata().unwrap().is_symlink());
+        assert!(SizeLessThanOrEq { bytes: 200 }.matches(&no_follow));
+        assert!(!SizeLessThanOrEq { bytes: 200 }.matches(&follow));
+        fs::remove_file(&link).unwrap();
+    }
+
+    #[test]
+    fn filter_stats_counts_rejections() {
+        let stats = FilterStats::new();
+        let filter = SizeLessThanOrEq { bytes: 10 };
+        stats.reset(std::iter::once(&filter as &dyn Filter));
+        assert!(!stats.record(0, &filter, &FileInfo::new(PathBuf::from("src/lib.rs"))));
+        assert!(!stats.record(0, &filter, &FileInfo::new(PathBuf::from("src/main.rs"))));
+        let report = stats.report();
+        assert_eq!(1, report.len());
+        assert_eq!(2, report[0].evaluated);
+        assert_eq!(2, report[0].rejected);
+    }
+
+    #[test]
+    fn combinators() {
+        let file = FileInfo::new(PathBuf::from("src/lib.rs"));
+        let rs = || Box::new(MatchesGlob { glob: Glob::new("*.rs").unwrap(), full_path: false });
+        let tiny = || Box::new(SizeLessThanOrEq { bytes: 10 });
+        assert!(!All(vec![rs(), tiny()]).matches(&file));
+        assert!(Any(vec![rs(), tiny()]).matches(&file));
+        assert!(Not(tiny()).matches(&file));
+        assert_eq!("any(matches_glob(\"*.rs\"), not(size_less_than_or_eq(10)))",
+                   Any(vec![rs(), Box::new(Not(tiny()))]).name());
+    }
+
+    #[test]
+    fn literal_patterns() {
+        assert!(is_literal("lib"));
+        assert!(is_literal("test_data"));
+        assert!(!is_literal("lib.rs"));
+        assert!(!is_literal("^lib"));
+        assert!(!is_literal("a-b"));
+        let file = FileInfo::new(PathBuf::from("src/lib.rs"));
+        assert!(NameContains::new("ib", false).matches(&file));
+        assert!(!NameContains::new("src", false).matches(&file));
+        assert!(NameContains::new("src/l", true).matches(&file));
+        assert_eq!("name_contains(\"ib\")", NameContains::new("ib", false).name());
+    }
+
+    #[test]
+    fn name_parts() {
+        let file = FileInfo::new(PathBuf::from("src/archive.tar.gz"));
+        assert!(NameStartsWith { prefix: String::from("arch") }.matches(&file));
+        assert!(!NameStartsWith { prefix: String::from("src") }.matches(&file));
+        assert!(NameEndsWith { suffix: String::from(".tar.gz") }.matches(&file));
+        assert!(!NameEndsWith { suffix: String::from("/archive.tar.gz") }.matches(&file));
+        assert!(StemIs { stem: String::from("archive.tar") }.matches(&file));
+        assert!(!StemIs { stem: String::from("archive") }.matches(&file));
+    }
+
+    #[test]
+    fn ancestors() {
+        let file = FileInfo::new(PathBuf::from("./tests/fixtures/a.json"));
+        let ancestor = |glob: &str| HasAncestor { glob: Glob::new(glob).unwrap() };
+        assert!(ancestor("tests").matches(&file));
+        assert!(ancestor("fix*").matches(&file));
+        assert!(!ancestor("a.json").matches(&file));
+        assert!(!ancestor(".").matches(&file));
+        assert!(!ancestor("tests").matches(&FileInfo::new(PathBuf::from("src/tests.rs"))));
+    }
+
+    #[test]
+    fn older_than_siblings() {
+        let mut fs = MemoryFileSystem::new();
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        fs.add_file("gen/schema.json", "").set_modified("gen/schema.json", at(2000))
+            .add_file("gen/types.rs", "").set_modified("gen/types.rs", at(1000))
+            .add_file("gen/fresh.rs", "").set_modified("gen/fresh.rs", at(3000))
+            .add_file("src/lib.rs", "").set_modified("src/lib.rs", at(1000));
+        let fs: Arc<dyn FileSystem> = Arc::new(fs);
+        let file = |path: &str| FileInfo::with_file_system(PathBuf::from(path), MetadataMode::Follow, fs.clone());
+        let older = OlderThanSibling { sibling: String::from("schema.json") };
+        assert!(older.matches(&file("gen/types.rs")));
+        assert!(!older.matches(&file("gen/fresh.rs")));
+        assert!(!older.matches(&file("gen/schema.json")));
+        assert!(!older.matches(&file("src/lib.rs")));
+        let before = ModifiedBefore { time: at(2000) };
+        assert!(before.matches(&file("gen/types.rs")));
+        assert!(!before.matches(&file("gen/schema.json")));
+        assert_eq!("modified_before(2000)", before.name());
+    }
+
+    #[test]
+    fn closure_as_filter() {
+        let file = FileInfo::new(PathBuf::from("src/lib.rs"));
+        let filter = |file: &FileInfo| file.path_str().starts_with("src");
+        assert!(filter.matches(&file));
+    }
+
+}
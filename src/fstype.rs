@@ -0,0 +1,155 @@
+/*
+The type of the filesystem files are on.
+
+A search of / walks into every mount below it: network shares that answer
+every lookup after a round trip, and pseudo filesystems like /proc whose
+files aren't files. 'of()' tells which filesystem a path is on, like ext4,
+nfs or tmpfs, which 'Finder::exclude_fs_type()' uses to stay out of whole
+classes of them, and which verbose output reports for every root.
+
+On Linux the type is read with statfs(2), whose magic numbers don't tell
+ext2, ext3 and ext4 apart, which are all reported as ext4. On Windows it is
+the name of the file system of the volume, like ntfs, refs or fat32, in
+lowercase. Elsewhere, it isn't known.
+
+ */
+
+use std::io::Error;
+use std::path::Path;
+
+
+/// The type of the filesystem `path` is on, like `ext4`, `nfs` or `ntfs`.
+/// Filesystems without a known name are named by their magic number, like
+/// `0x1badface`.
+#[cfg(target_os = "linux")]
+pub fn of(path: &Path) -> Result<String, Error> {
+    linux::magic(path).map(name)
+}
+
+/// The type of the filesystem `path` is on, like `ntfs`.
+#[cfg(windows)]
+pub fn of(path: &Path) -> Result<String, Error> {
+    windows::file_system_name(path).map(|name| name.to_lowercase())
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+pub fn of(_path: &Path) -> Result<String, Error> {
+    Err(Error::new(std::io::ErrorKind::Unsupported, "The type of filesystems is only known on Linux and Windows."))
+}
+
+/// The name of the Linux filesystem with the magic number `magic`, see
+/// statfs(2).
+#[cfg(target_os = "linux")]
+fn name(magic: u64) -> String {
+    let name = match magic {
+        0xef53 => "ext4",
+        0x5846_5342 => "xfs",
+        0x9123_683e => "btrfs",
+        0x2fc1_2fc1 => "zfs",
+        0xf2f5_2010 => "f2fs",
+        0x0102_1994 => "tmpfs",
+        0x8584_58f6 => "ramfs",
+        0x794c_7630 => "overlay",
+        0x7371_7368 => "squashfs",
+        0x9660 => "iso9660",
+        0x4d44 => "vfat",
+        0x2011_bab0 => "exfat",
+        0x5346_544e => "ntfs",
+        0x6969 => "nfs",
+        0xff53_4d42 => "cifs",
+        0xfe53_4d42 => "smb3",
+        0x00c3_6400 => "ceph",
+        0x6573_5546 => "fuse",
+        0x0187 => "autofs",
+        0x9fa0 => "proc",
+        0x6265_6572 => "sysfs",
+        0x6367_7270 => "cgroup2",
+        0x1cd1 => "devpts",
+        0x6462_6720 => "debugfs",
+        0x7472_6163 => "tracefs",
+        _ => return format!("{:#x}", magic),
+    };
+    String::from(name)
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::ffi::CString;
+    use std::io::{self, Error};
+    use std::os::raw::{c_char, c_int, c_long};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    extern "C" {
+        fn statfs(path: *const c_char, buf: *mut c_long) -> c_int;
+    }
+
+    /// The magic number of the filesystem `path` is on.
+    pub fn magic(path: &Path) -> Result<u64, Error> {
+        let path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| Error::new(io::ErrorKind::InvalidInput, "path contains a NUL byte"))?;
+        // A `struct statfs` starts with its type, and is smaller than this.
+        let mut buf: [c_long; 64] = [0; 64];
+        // SAFETY: `path` is NUL terminated and `buf` is large enough.
+        if unsafe { statfs(path.as_ptr(), buf.as_mut_ptr()) } < 0 {
+            return Err(Error::last_os_error());
+        }
+        // The type is a word, which is sign extended on 32 bit systems.
+        Ok(buf[0] as u64 & 0xffff_ffff)
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::ffi::OsString;
+    use std::io::Error;
+    use std::os::windows::ffi::{OsStrExt, OsStringExt};
+    use std::path::Path;
+
+    const MAX_PATH: usize = 261;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetVolumePathNameW(file_name: *const u16, volume_path_name: *mut u16, buffer_length: u32) -> i32;
+        fn GetVolumeInformationW(root_path_name: *const u16, volume_name: *mut u16, volume_name_size: u32,
+                                 serial_number: *mut u32, maximum_component_length: *mut u32,
+                                 file_system_flags: *mut u32, file_system_name: *mut u16, file_system_name_size: u32) -> i32;
+    }
+
+    /// The name of the file system of the volume `path` is on, like NTFS.
+    pub fn file_system_name(path: &Path) -> Result<String, Error> {
+        let name: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+        let mut volume = vec![0u16; path.as_os_str().len() + MAX_PATH];
+        // SAFETY: `name` is NUL terminated and `volume` as long as told.
+        if unsafe { GetVolumePathNameW(name.as_ptr(), volume.as_mut_ptr(), volume.len() as u32) } == 0 {
+            return Err(Error::last_os_error());
+        }
+        let mut file_system = [0u16; MAX_PATH];
+        // SAFETY: `volume` is NUL terminated, `file_system` as long as told,
+        // and the other outputs are optional.
+        let got = unsafe {
+            GetVolumeInformationW(volume.as_ptr(), std::ptr::null_mut(), 0, std::ptr::null_mut(), std::ptr::null_mut(),
+                                  std::ptr::null_mut(), file_system.as_mut_ptr(), file_system.len() as u32)
+        };
+        if got == 0 {
+            return Err(Error::last_os_error());
+        }
+        let len = file_system.iter().position(|&c| c == 0).unwrap_or(file_system.len());
+        Ok(OsString::from_wide(&file_system[..len]).to_string_lossy().into_owned())
+    }
+}
+
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn names_filesystems() {
+        assert_eq!("proc", of(Path::new("/proc")).unwrap());
+        assert!(of(&std::env::temp_dir()).is_ok());
+        assert!(of(Path::new("/no/such/dir")).is_err());
+        assert_eq!(("nfs", "0x1badface"), (name(0x6969).as_str(), name(0x1bad_face).as_str()));
+    }
+
+}
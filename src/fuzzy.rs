@@ -0,0 +1,94 @@
+/*
+Fuzzy matching of file names.
+
+A 'Fuzzy' query matches the names that contain it with at most a few typos:
+its distance to a name is the Levenshtein distance between the query and the
+closest part of the name, so 'finderrs' is at distance 1 of 'finder.rs' (a
+missing '.'), a query whose letters all appear in order, like 'fndr', only
+costs the letters it skips within the part it matches, and a query found as
+is costs nothing. Case is ignored.
+
+Searches for a fuzzy name report the closest names first, see
+'Finder::fuzzy_name()'.
+
+ */
+
+/// A compiled fuzzy query.
+#[derive(Debug, Clone)]
+pub struct Fuzzy {
+    query: String,
+    chars: Vec<char>,
+    max_distance: u32,
+}
+
+impl Fuzzy {
+
+    /// Creates a query matching the names within `max_distance` edits of
+    /// `query`.
+    pub fn new(query: &str, max_distance: u32) -> Fuzzy {
+        Fuzzy { query: query.to_string(), chars: query.to_lowercase().chars().collect(), max_distance }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.query
+    }
+
+    pub fn max_distance(&self) -> u32 {
+        self.max_distance
+    }
+
+    /// The distance of `name` to this query, or `None` if it is farther than
+    /// the maximum distance.
+    pub fn distance(&self, name: &str) -> Option<u32> {
+        // The edit distance of the query to the best substring of `name`
+        // (Sellers' algorithm): row `j` holds, for every prefix of the query,
+        // the distance to the best substring ending at the `j`th character.
+        let mut row: Vec<u32> = (0..=self.chars.len() as u32).collect();
+        let mut best = row[self.chars.len()];
+        for c in name.chars().flat_map(char::to_lowercase) {
+            let mut diagonal = row[0];
+            row[0] = 0;
+            for (i, &q) in self.chars.iter().enumerate() {
+                let substituted = diagonal + (q != c) as u32;
+                diagonal = row[i + 1];
+                row[i + 1] = substituted.min(row[i] + 1).min(diagonal + 1);
+            }
+            best = best.min(row[self.chars.len()]);
+        }
+        Some(best).filter(|&distance| distance <= self.max_distance)
+    }
+
+    /// How well `name` matches, lowest first: the closest names and, at the
+    /// same distance, those with the fewest other characters. Names that
+    /// don't match come last.
+    pub fn score(&self, name: &str) -> (u32, usize) {
+        (self.distance(name).unwrap_or(u32::MAX), name.chars().count())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn distances() {
+        let fuzzy = Fuzzy::new("finderrs", 3);
+        assert_eq!(Some(1), fuzzy.distance("finder.rs"));
+        assert_eq!(Some(0), fuzzy.distance("my_finderrs.txt"));
+        assert_eq!(Some(0), fuzzy.distance("FinderRS"));
+        assert_eq!(Some(2), fuzzy.distance("fnderr"));
+        assert_eq!(None, fuzzy.distance("lib.rs"));
+        assert_eq!(Some(2), Fuzzy::new("fndr", 3).distance("finder.rs"));
+        assert_eq!(Some(0), Fuzzy::new("", 0).distance("anything"));
+    }
+
+    #[test]
+    fn closest_names_first() {
+        let fuzzy = Fuzzy::new("finderrs", 2);
+        let mut names = vec!["lib.rs", "finder_rs_old.txt", "finder.rs", "finderrs"];
+        names.sort_by_key(|name| fuzzy.score(name));
+        assert_eq!(vec!["finderrs", "finder.rs", "finder_rs_old.txt", "lib.rs"], names);
+    }
+
+}
@@ -0,0 +1,193 @@
+/*
+Filters on the git status of files.
+
+These filters ask git which files of a repository are tracked, untracked (and
+not ignored) or modified, by running 'git ls-files' once, the first time they
+are evaluated. The repository is the one containing the first file that is
+evaluated, normally one found in the search root; files outside of it never
+match. Git has to be installed, and only files on the real filesystem can be
+looked up.
+
+ */
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Error};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::OnceLock;
+use serde::{Deserialize, Serialize};
+use crate::filter::{FileInfo, Filter};
+use crate::trace::{self, Level};
+
+
+/// Which files of a repository a `GitStatus` filter retains.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GitState {
+    /// Files in the index.
+    Tracked,
+    /// Files that are neither in the index nor ignored.
+    Untracked,
+    /// Tracked files with changes, staged or not.
+    Modified,
+}
+
+impl GitState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            GitState::Tracked => "tracked",
+            GitState::Untracked => "untracked",
+            GitState::Modified => "modified",
+        }
+    }
+
+    pub fn parse(state: &str) -> Option<GitState> {
+        match state {
+            "tracked" => Some(GitState::Tracked),
+            "untracked" => Some(GitState::Untracked),
+            "modified" => Some(GitState::Modified),
+            _ => None,
+        }
+    }
+}
+
+/// Retains the files of a git repository in the given `GitState`.
+pub struct GitStatus {
+    state: GitState,
+    repo: OnceLock<Option<Repo>>,
+}
+
+/// The files of a repository in some state, relative to its top level directory.
+struct Repo {
+    toplevel: PathBuf,
+    files: HashSet<PathBuf>,
+}
+
+impl GitStatus {
+
+    pub fn new(state: GitState) -> GitStatus {
+        GitStatus { state, repo: OnceLock::new() }
+    }
+
+    pub fn state(&self) -> GitState {
+        self.state
+    }
+}
+
+impl Filter for GitStatus {
+    fn name(&self) -> String {
+        format!("git_{}", self.state.as_str())
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        let path = match canonical(file.path()) {
+            Some(path) => path,
+            None => return false,
+        };
+        let repo = self.repo.get_or_init(|| {
+            let dir = path.parent().unwrap_or(Path::new("/"));
+            Repo::load(dir, self.state)
+                .map_err(|e| trace::event(Level::Warn, || format!("cannot get the git status of {}: {}", dir.display(), e)))
+                .ok()
+        });
+        match repo {
+            Some(repo) => path.strip_prefix(&repo.toplevel)
+                .map(|relative| repo.files.contains(relative))
+                .unwrap_or(false),
+            None => false,
+        }
+    }
+}
+
+impl Repo {
+
+    /// Lists the files in `state` of the repository containing `dir`.
+    fn load(dir: &Path, state: GitState) -> Result<Repo, Error> {
+        let toplevel = git(dir, &["rev-parse", "--show-toplevel"])?;
+        let toplevel = canonical(Path::new(String::from_utf8_lossy(&toplevel).trim_end()))
+            .ok_or_else(|| Error::new(io::ErrorKind::NotFound, "the repository has no top level directory"))?;
+        let mut files = HashSet::new();
+        let mut add = |output: Vec<u8>| files.extend(paths(&output));
+        match state {
+            GitState::Tracked => add(git(&toplevel, &["ls-files", "-z"])?),
+            GitState::Untracked => add(git(&toplevel, &["ls-files", "-z", "--others", "--exclude-standard"])?),
+            GitState::Modified => {
+                add(git(&toplevel, &["ls-files", "-z", "--modified"])?);
+                // Staged changes. There is nothing to compare to before the first commit.
+                if let Ok(staged) = git(&toplevel, &["diff", "--cached", "--name-only", "-z"]) {
+                    add(staged);
+                }
+            }
+        }
+        trace::event(Level::Debug, || format!("{} {} files in {}", files.len(), state.as_str(), toplevel.display()));
+        Ok(Repo { toplevel, files })
+    }
+}
+
+/// Runs git in `dir` and returns its output.
+fn git(dir: &Path, args: &[&str]) -> Result<Vec<u8>, Error> {
+    let output = Command::new("git").arg("-C").arg(dir).args(args).output()?;
+    if !output.status.success() {
+        return Err(Error::other(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+    Ok(output.stdout)
+}
+
+/// Splits NUL separated paths, as printed by `git ls-files -z`.
+fn paths(output: &[u8]) -> impl Iterator<Item = PathBuf> + '_ {
+    output.split(|&b| b == 0)
+        .filter(|path| !path.is_empty())
+        .map(|path| PathBuf::from(&*String::from_utf8_lossy(path)))
+}
+
+/// The canonical path of `path`, without resolving `path` itself if it is a
+/// symlink: git tracks symlinks, not what they point to.
+fn canonical(path: &Path) -> Option<PathBuf> {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => {
+            let parent = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+            fs::canonicalize(parent).ok().map(|parent| parent.join(name))
+        }
+        _ => fs::canonicalize(path).ok(),
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn git_states() {
+        let repo = std::env::temp_dir().join(format!("ffind-git-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&repo);
+        fs::create_dir_all(repo.join("sub")).unwrap();
+        let run = |args: &[&str]| git(&repo, args).unwrap();
+        run(&["init", "-q"]);
+        fs::write(repo.join("committed.txt"), "a").unwrap();
+        fs::write(repo.join("sub/changed.txt"), "a").unwrap();
+        fs::write(repo.join(".gitignore"), "*.log\n").unwrap();
+        run(&["add", "."]);
+        run(&["-c", "user.name=test", "-c", "user.email=test@example.com", "commit", "-q", "-m", "init"]);
+        fs::write(repo.join("sub/changed.txt"), "b").unwrap();
+        fs::write(repo.join("new.txt"), "a").unwrap();
+        fs::write(repo.join("ignored.log"), "a").unwrap();
+
+        let check = |state, path: &str| GitStatus::new(state).matches(&FileInfo::new(repo.join(path)));
+        assert!(check(GitState::Tracked, "committed.txt"));
+        assert!(check(GitState::Tracked, "sub/changed.txt"));
+        assert!(!check(GitState::Tracked, "new.txt"));
+        assert!(check(GitState::Untracked, "new.txt"));
+        assert!(!check(GitState::Untracked, "ignored.log"));
+        assert!(!check(GitState::Untracked, "committed.txt"));
+        assert!(check(GitState::Modified, "sub/changed.txt"));
+        assert!(!check(GitState::Modified, "committed.txt"));
+        // Files outside of the repository never match.
+        let filter = GitStatus::new(GitState::Untracked);
+        assert!(filter.matches(&FileInfo::new(repo.join("new.txt"))));
+        assert!(!filter.matches(&FileInfo::new(PathBuf::from("Cargo.toml"))));
+        fs::remove_dir_all(&repo).unwrap();
+    }
+
+}
@@ -0,0 +1,319 @@
+/*
+Shell style glob patterns.
+
+Supports the same wildcards as find's '-name': '*' matches any sequence of
+characters, '?' matches a single character and '[...]' matches one character
+out of a set or range ('[!...]' or '[^...]' negates the set). A backslash
+escapes the next character.
+
+With 'literal_separator', globs match paths the way gitignore patterns do:
+wildcards don't match '/', except '**', which matches across directories
+('a/**/b' matches 'a/b' and 'a/x/y/b').
+
+ */
+
+use std::io::{self, Error};
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Char(char),
+    AnyChar,
+    AnySequence,
+    /// `**` with a literal separator: any sequence, including `/`.
+    AnyPath,
+    /// `**/` with a literal separator: nothing, or any sequence ending in `/`.
+    AnyDirs,
+    Class { negated: bool, ranges: Vec<(char, char)> },
+}
+
+/// A compiled glob pattern.
+#[derive(Debug, Clone)]
+pub struct Glob {
+    pattern: String,
+    tokens: Vec<Token>,
+    case_insensitive: bool,
+    literal_separator: bool,
+}
+
+impl Glob {
+
+    /// Compiles the given glob `pattern`. Returns an error if it contains an
+    /// unterminated character class or a trailing backslash.
+    pub fn new(pattern: &str) -> Result<Glob, Error> {
+        Ok(Glob {
+            pattern: pattern.to_string(),
+            tokens: compile(pattern, false)?,
+            case_insensitive: false,
+            literal_separator: false,
+        })
+    }
+
+    /// Makes this glob ignore case when matching.
+    pub fn case_insensitive(mut self, yes: bool) -> Self {
+        self.case_insensitive = yes;
+        self
+    }
+
+    /// Makes wildcards stop at `/`, so the glob matches paths one component
+    /// at a time, and lets `**` match across directories.
+    pub fn literal_separator(mut self, yes: bool) -> Self {
+        self.literal_separator = yes;
+        // The pattern compiled once already, so it compiles again.
+        self.tokens = compile(&self.pattern, yes).unwrap();
+        self
+    }
+
+    /// The pattern this glob was compiled from.
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Returns true if the _whole_ of `text` matches this glob.
+    pub fn is_match(&self, text: &str) -> bool {
+        let text: Vec<char> = if self.case_insensitive {
+            text.to_lowercase().chars().collect()
+        } else {
+            text.chars().collect()
+        };
+        if self.literal_separator {
+            self.match_path(0, &text, 0)
+        } else {
+            self.match_from(0, &text, 0)
+        }
+    }
+
+    /// Returns false if no text starting with `prefix` matches this glob, like
+    /// `./tests/` for `./src/**`, and true if some might.
+    pub fn may_match_prefix(&self, prefix: &str) -> bool {
+        // Each token up to the first wildcard sequence matches one character.
+        for (token, c) in self.tokens.iter().zip(prefix.chars()) {
+            let matches = match token {
+                Token::Char(t) => self.eq(*t, c),
+                Token::AnyChar => true,
+                Token::Class { negated, ranges } => self.class_matches(*negated, ranges, c),
+                Token::AnySequence | Token::AnyPath | Token::AnyDirs => return true,
+            };
+            if !matches {
+                return false;
+            }
+        }
+        // Without wildcards, the glob can't be shorter than the prefix.
+        self.tokens.len() >= prefix.chars().count()
+    }
+
+    fn eq(&self, a: char, b: char) -> bool {
+        if self.case_insensitive {
+            a.to_lowercase().eq(b.to_lowercase())
+        } else {
+            a == b
+        }
+    }
+
+    fn class_matches(&self, negated: bool, ranges: &[(char, char)], c: char) -> bool {
+        let found = ranges.iter().any(|&(start, end)| {
+            if self.case_insensitive {
+                let c = c.to_lowercase().next().unwrap_or(c);
+                let start = start.to_lowercase().next().unwrap_or(start);
+                let end = end.to_lowercase().next().unwrap_or(end);
+                start <= c && c <= end
+            } else {
+                start <= c && c <= end
+            }
+        });
+        found != negated
+    }
+
+    /// Matches the tokens starting at `t` against the text starting at `i`.
+    /// Backtracks over `*` by remembering the last star seen, which keeps the
+    /// matching linear for the patterns used in practice.
+    fn match_from(&self, mut t: usize, text: &[char], mut i: usize) -> bool {
+        let mut star: Option<(usize, usize)> = None;
+        loop {
+            if t < self.tokens.len() {
+                let advanced = match &self.tokens[t] {
+                    Token::AnySequence => {
+                        star = Some((t, i));
+                        t += 1;
+                        continue;
+                    }
+                    // Only compiled with a literal separator, see `match_path()`.
+                    Token::AnyPath | Token::AnyDirs => unreachable!(),
+                    _ if i >= text.len() => false,
+                    Token::AnyChar => true,
+                    Token::Char(c) => self.eq(*c, text[i]),
+                    Token::Class { negated, ranges } => self.class_matches(*negated, ranges, text[i]),
+                };
+                if advanced {
+                    t += 1;
+                    i += 1;
+                    continue;
+                }
+            } else if i == text.len() {
+                return true;
+            }
+            // Mismatch: let the last star swallow one more character, if any.
+            match star {
+                Some((star_t, star_i)) if star_i < text.len() => {
+                    star = Some((star_t, star_i + 1));
+                    t = star_t + 1;
+                    i = star_i + 1;
+                }
+                _ => return false,
+            }
+        }
+    }
+
+    /// Like `match_from()`, for globs with a literal separator. Wildcards of
+    /// different kinds can't share the linear backtracking, so this one
+    /// recurses; ignore patterns are short enough for it not to matter.
+    fn match_path(&self, t: usize, text: &[char], i: usize) -> bool {
+        let token = match self.tokens.get(t) {
+            Some(token) => token,
+            None => return i == text.len(),
+        };
+        match token {
+            Token::AnySequence => (i..=text.len())
+                .take_while(|&j| j == i || text[j - 1] != '/')
+                .any(|j| self.match_path(t + 1, text, j)),
+            Token::AnyPath => (i..=text.len()).any(|j| self.match_path(t + 1, text, j)),
+            Token::AnyDirs => self.match_path(t + 1, text, i)
+                || (i..text.len()).any(|j| text[j] == '/' && self.match_path(t + 1, text, j + 1)),
+            _ if i >= text.len() || text[i] == '/' && *token != Token::Char('/') => false,
+            Token::AnyChar => self.match_path(t + 1, text, i + 1),
+            Token::Char(c) => self.eq(*c, text[i]) && self.match_path(t + 1, text, i + 1),
+            Token::Class { negated, ranges } =>
+                self.class_matches(*negated, ranges, text[i]) && self.match_path(t + 1, text, i + 1),
+        }
+    }
+}
+
+/// Turns `pattern` into tokens, see `Glob::new()`.
+fn compile(pattern: &str, literal_separator: bool) -> Result<Vec<Token>, Error> {
+    let mut tokens = Vec::new();
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            '*' => {
+                // Consecutive stars are equivalent to a single star,
+                // unless they match across separators.
+                let mut double = false;
+                while chars.peek() == Some(&'*') {
+                    chars.next();
+                    double = true;
+                }
+                if !double || !literal_separator {
+                    Token::AnySequence
+                } else if chars.peek() == Some(&'/') {
+                    chars.next();
+                    Token::AnyDirs
+                } else {
+                    Token::AnyPath
+                }
+            }
+            '?' => Token::AnyChar,
+            '\\' => match chars.next() {
+                Some(escaped) => Token::Char(escaped),
+                None => return Err(invalid(pattern, "trailing backslash")),
+            },
+            '[' => {
+                let negated = matches!(chars.peek(), Some('!') | Some('^'));
+                if negated {
+                    chars.next();
+                }
+                let mut ranges = Vec::new();
+                let mut first = true;
+                loop {
+                    let start = match chars.next() {
+                        // A ']' right after the opening bracket is a literal.
+                        Some(']') if !first => break,
+                        Some(c) => c,
+                        None => return Err(invalid(pattern, "unterminated character class")),
+                    };
+                    first = false;
+                    let mut lookahead = chars.clone();
+                    if lookahead.next() == Some('-') && !matches!(lookahead.peek(), Some(']') | None) {
+                        chars.next();
+                        let end = chars.next().unwrap();
+                        ranges.push((start, end));
+                    } else {
+                        ranges.push((start, start));
+                    }
+                }
+                Token::Class { negated, ranges }
+            }
+            c => Token::Char(c),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+fn invalid(pattern: &str, reason: &str) -> Error {
+    Error::new(io::ErrorKind::InvalidInput, format!("Invalid glob '{}': {}.", pattern, reason))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn star_and_question_mark() {
+        let glob = Glob::new("*.l?g").unwrap();
+        assert!(glob.is_match("app.log"));
+        assert!(glob.is_match(".lag"));
+        assert!(!glob.is_match("app.logs"));
+        assert!(Glob::new("a*b*c").unwrap().is_match("aXXbYYbc"));
+    }
+
+    #[test]
+    fn matching_prefixes() {
+        let glob = Glob::new("./src/**").unwrap();
+        assert!(glob.may_match_prefix("./"));
+        assert!(glob.may_match_prefix("./src/bin/"));
+        assert!(!glob.may_match_prefix("./tests/"));
+        assert!(Glob::new("./[st]*/lib.rs").unwrap().may_match_prefix("./tests/"));
+        assert!(!Glob::new("./a.rs").unwrap().may_match_prefix("./a.rs/"));
+    }
+
+    #[test]
+    fn character_classes() {
+        let glob = Glob::new("file[0-9][!a-z].txt").unwrap();
+        assert!(glob.is_match("file1A.txt"));
+        assert!(!glob.is_match("file1a.txt"));
+        assert!(Glob::new("[]]").unwrap().is_match("]"));
+        assert!(Glob::new("[a-]").unwrap().is_match("-"));
+    }
+
+    #[test]
+    fn escapes_and_case() {
+        assert!(Glob::new(r"\*").unwrap().is_match("*"));
+        assert!(!Glob::new(r"\*").unwrap().is_match("a"));
+        assert!(Glob::new("*.RS").unwrap().case_insensitive(true).is_match("lib.rs"));
+        assert!(!Glob::new("*.RS").unwrap().is_match("lib.rs"));
+    }
+
+    #[test]
+    fn literal_separator() {
+        let path = |pattern| Glob::new(pattern).unwrap().literal_separator(true);
+        assert!(path("src/*.rs").is_match("src/lib.rs"));
+        assert!(!path("src/*.rs").is_match("src/bin/tool.rs"));
+        assert!(Glob::new("src/*.rs").unwrap().is_match("src/bin/tool.rs"));
+        assert!(!path("a?b").is_match("a/b"));
+        assert!(!path("a[/]b").is_match("a/b"));
+        assert!(path("**/tool.rs").is_match("tool.rs"));
+        assert!(path("**/tool.rs").is_match("src/bin/tool.rs"));
+        assert!(path("src/**/*.rs").is_match("src/lib.rs"));
+        assert!(path("src/**/*.rs").is_match("src/bin/tool.rs"));
+        assert!(path("src/**").is_match("src/bin/tool.rs"));
+        assert!(!path("src/**").is_match("docs/guide.md"));
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        assert!(Glob::new("[abc").is_err());
+        assert!(Glob::new("abc\\").is_err());
+    }
+
+}
@@ -0,0 +1,169 @@
+/*
+Keeping one match per group of matches.
+
+Directories of logs and backups pile up dated copies of the same file, of
+which only the latest one is usually wanted. 'Finder::latest_per()' keeps
+the most recently modified match among those sharing an extension or a
+directory, see 'Per', so that the latest backup of each of many directories
+is found in one pass.
+
+'Finder::find_unique_by()' collapses the matches sharing a name, a stem or
+contents into the first of them, with how many there are, see 'UniqueBy':
+how many copies of config.yaml a monorepo has, and where one of them is.
+
+Matches are grouped once the search is complete, and the ones kept are
+reported in the order they were found.
+
+ */
+
+use std::collections::HashMap;
+use std::io::{self, Error};
+use std::path::Path;
+use std::time::SystemTime;
+
+
+/// What groups matches for `Finder::latest_per()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Per {
+    /// The extension of the file name, like `.log`. Files without one are a
+    /// group of their own.
+    Extension,
+    /// The directory the file is in.
+    Dir,
+}
+
+impl Per {
+
+    /// Parses a group as given to `--latest-per`: `ext` or `dir`.
+    pub fn parse(arg: &str) -> Result<Per, Error> {
+        match arg {
+            "ext" => Ok(Per::Extension),
+            "dir" => Ok(Per::Dir),
+            _ => Err(Error::new(io::ErrorKind::InvalidInput, format!("Unknown group '{}', expected ext or dir.", arg))),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Per::Extension => "ext",
+            Per::Dir => "dir",
+        }
+    }
+
+    fn key(self, path: &str) -> &str {
+        let path = Path::new(path);
+        let key = match self {
+            Per::Extension => path.extension(),
+            Per::Dir => path.parent().map(Path::as_os_str),
+        };
+        key.and_then(|key| key.to_str()).unwrap_or("")
+    }
+}
+
+/// What collapses matches for `Finder::find_unique_by()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UniqueBy {
+    /// The file name, like `config.yaml`.
+    Name,
+    /// The file name without its extension, see `filter::StemIs`.
+    Stem,
+    /// The SHA-256 of the contents, which only files have.
+    Hash,
+}
+
+impl UniqueBy {
+
+    /// Parses a key as given to `--unique-by`: `name`, `stem` or `hash`.
+    pub fn parse(arg: &str) -> Result<UniqueBy, Error> {
+        match arg {
+            "name" => Ok(UniqueBy::Name),
+            "stem" => Ok(UniqueBy::Stem),
+            "hash" => Ok(UniqueBy::Hash),
+            _ => Err(Error::new(io::ErrorKind::InvalidInput, format!("Unknown key '{}', expected name, stem or hash.", arg))),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            UniqueBy::Name => "name",
+            UniqueBy::Stem => "stem",
+            UniqueBy::Hash => "hash",
+        }
+    }
+}
+
+/// Keeps the most recently modified of `files` in every group, according to
+/// `modified`, in the order they come in. Files whose modification time is
+/// unknown are only kept if they are alone in their group, and of files
+/// modified at the same time, the first one.
+pub fn latest(files: Vec<String>, per: Per, modified: impl Fn(&str) -> Option<SystemTime>) -> Vec<String> {
+    let mut latest: HashMap<&str, (usize, Option<SystemTime>)> = HashMap::new();
+    for (i, file) in files.iter().enumerate() {
+        let time = modified(file);
+        latest.entry(per.key(file))
+            .and_modify(|kept| if time > kept.1 { *kept = (i, time) })
+            .or_insert((i, time));
+    }
+    let mut kept: Vec<usize> = latest.into_values().map(|(i, _)| i).collect();
+    kept.sort_unstable();
+    let mut kept = kept.into_iter().peekable();
+    files.into_iter()
+        .enumerate()
+        .filter(|&(i, _)| kept.next_if_eq(&i).is_some())
+        .map(|(_, file)| file)
+        .collect()
+}
+
+/// Collapses the `files` sharing a key, according to `key`, into the first of
+/// them, with how many share it, in the order they come in. Files without a
+/// key are left out.
+pub fn unique(files: Vec<String>, key: impl Fn(&str) -> Option<String>) -> Vec<(String, usize)> {
+    let mut unique: Vec<(String, usize)> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for file in files {
+        if let Some(key) = key(&file) {
+            match index.get(&key) {
+                Some(&i) => unique[i].1 += 1,
+                None => {
+                    index.insert(key, unique.len());
+                    unique.push((file, 1));
+                }
+            }
+        }
+    }
+    unique
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn keeps_latest() {
+        let files = ["logs/a.log", "logs/b.log", "logs/b.gz", "backup/1.tar", "backup/2.tar", "backup/README", "NOTES"]
+            .iter().map(|file| file.to_string()).collect::<Vec<_>>();
+        let modified = |file: &str| match file {
+            "logs/b.log" | "backup/1.tar" => Some(UNIX_EPOCH + Duration::from_secs(2000)),
+            "backup/README" => None,
+            _ => Some(UNIX_EPOCH + Duration::from_secs(1000)),
+        };
+        assert_eq!(vec!["logs/b.log", "backup/1.tar", "NOTES"], latest(files.clone(), Per::Dir, modified));
+        assert_eq!(vec!["logs/b.log", "logs/b.gz", "backup/1.tar", "NOTES"], latest(files, Per::Extension, modified));
+        assert!(Per::parse("name").is_err());
+    }
+
+    #[test]
+    fn collapses_copies() {
+        let files = ["a/config.yaml", "b/config.yaml", "b/config.json", "c/config.yaml", "c/nameless"]
+            .iter().map(|file| file.to_string()).collect::<Vec<_>>();
+        let name = |file: &str| Path::new(file).file_name().map(|name| name.to_string_lossy().into_owned());
+        let expected = vec![(String::from("a/config.yaml"), 3), (String::from("b/config.json"), 1), (String::from("c/nameless"), 1)];
+        assert_eq!(expected, unique(files.clone(), name));
+        let extension = |file: &str| Path::new(file).extension().map(|ext| ext.to_string_lossy().into_owned());
+        assert_eq!(vec![(String::from("a/config.yaml"), 3), (String::from("b/config.json"), 1)], unique(files, extension));
+        assert_eq!(Ok(UniqueBy::Hash), UniqueBy::parse("hash").map_err(|e| e.kind()));
+    }
+
+}
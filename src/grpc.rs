@@ -0,0 +1,1178 @@
+/*
+A gRPC service answering searches, for fleets of hosts.
+
+'ffind serve --grpc :7070' listens for gRPC calls over plaintext HTTP/2
+(h2c, with prior knowledge, as gRPC clients speak it without TLS), so that
+fleet management tooling can fan a search out to agents on many hosts and
+stream the matches back. The service is described by proto/ffind.proto:
+
+    service Finder {
+      rpc Query(Query) returns (stream Entry);
+      rpc Cancel(Cancel) returns (Cancelled);
+    }
+
+    message Query {
+      string id = 1;     // names the query for Cancel, optional
+      string spec = 2;   // a 'SearchSpec' as JSON, see the 'spec' module
+    }
+    message Entry {
+      string path = 1;
+      uint64 size = 2;
+      int64 mtime = 3;   // seconds since the Unix epoch
+      bool is_dir = 4;
+    }
+    message Cancel { string id = 1; }
+    message Cancelled { bool cancelled = 1; }
+
+Entries are streamed as they are found, neither ranked nor sorted, and the
+stream ends with the status of the search: OK, CANCELLED once it was stopped
+by a Cancel call of its id (from any connection) or by the client resetting
+the stream, INVALID_ARGUMENT for a spec that can't be run, NOT_FOUND for a
+root that doesn't exist, and PERMISSION_DENIED for one a sandboxed server
+doesn't allow, see 'GrpcServer::sandbox()'. Calls are compressed with neither gzip nor anything
+else, and headers Huffman coded by the client may only hold printable ASCII,
+which is all gRPC sends.
+
+Every connection is read by a thread of its own, and every call answered by
+another, so that a long search holds up neither the others nor its
+cancellation. A connection has at most 'MAX_STREAMS' calls at once, as the
+server tells clients in its SETTINGS: calls past them are refused with
+REFUSED_STREAM, for the client to retry later. Header blocks are at most
+'MAX_HEADER_LIST' bytes, encoded or decoded, or else the connection is
+closed, so that a client can't have the server buffer headers without end.
+
+ */
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Error, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Instant, UNIX_EPOCH};
+use crate::Finder;
+use crate::filter::FileInfo;
+use crate::metrics::{self, Metrics};
+use crate::sandbox::{Sandbox, Tenant};
+use crate::spec::SearchSpec;
+use crate::trace::{self, Level};
+use crate::walk::Cancel;
+
+
+/// What an HTTP/2 client sends before anything else.
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// The types of HTTP/2 frames.
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+/// The flags of HTTP/2 frames.
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY: u8 = 0x20;
+
+/// The settings of HTTP/2 connections read or sent by the server.
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+/// The error code of HTTP/2 resetting a stream the server won't answer.
+const REFUSED_STREAM: u32 = 0x7;
+
+/// The size of frames and flow control windows until the client says
+/// otherwise.
+const DEFAULT_MAX_FRAME: usize = 16384;
+const DEFAULT_WINDOW: i64 = 65535;
+
+/// The largest request accepted, like the default of gRPC servers.
+const MAX_MESSAGE: usize = 4 << 20;
+
+/// The most calls a connection has at once, each answered by a thread.
+const MAX_STREAMS: u32 = 100;
+
+/// The largest header block accepted, like the default of gRPC servers.
+const MAX_HEADER_LIST: usize = 16 << 10;
+
+/// The status codes of gRPC.
+const OK: u32 = 0;
+const CANCELLED: u32 = 1;
+const UNKNOWN: u32 = 2;
+const INVALID_ARGUMENT: u32 = 3;
+const NOT_FOUND: u32 = 5;
+const ALREADY_EXISTS: u32 = 6;
+const PERMISSION_DENIED: u32 = 7;
+const RESOURCE_EXHAUSTED: u32 = 8;
+const UNIMPLEMENTED: u32 = 12;
+const UNAUTHENTICATED: u32 = 16;
+
+/// A frame read, as its type, flags, stream and payload.
+type Frame = (u8, u8, u32, Vec<u8>);
+
+/// A gRPC server answering searches, see the module documentation.
+pub struct GrpcServer {
+    listener: TcpListener,
+    result_cache: Option<PathBuf>,
+    metrics: Option<Metrics>,
+    sandbox: Option<Sandbox>,
+}
+
+/// What the connections of a server share.
+struct Service {
+    result_cache: Option<PathBuf>,
+    metrics: Option<Metrics>,
+    sandbox: Option<Sandbox>,
+    /// The queries running, by the API key they were made with and the id
+    /// they were given.
+    queries: Mutex<HashMap<(String, String), Cancel>>,
+}
+
+impl GrpcServer {
+
+    /// Listens on `addr`, like `127.0.0.1:7070`. An address without a host,
+    /// like `:7070`, listens on every interface.
+    pub fn bind(addr: &str) -> Result<GrpcServer, Error> {
+        let listener = metrics::bind(addr)?;
+        Ok(GrpcServer { listener, result_cache: None, metrics: None, sandbox: None })
+    }
+
+    /// Saves the results of searches in `dir`, so that queries run again only
+    /// read the directories that changed, see the `cache` module.
+    pub fn result_cache(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.result_cache = Some(dir.into());
+        self
+    }
+
+    /// Counts what the searches do and times them in `metrics`, see the
+    /// `metrics` module.
+    pub fn metrics(mut self, metrics: &Metrics) -> Self {
+        self.metrics = Some(metrics.clone());
+        self
+    }
+
+    /// Only answers the calls whose x-api-key metadata the sandbox knows,
+    /// searching the roots it allows them, see the `sandbox` module. Calls
+    /// can only cancel the queries made with the same key.
+    pub fn sandbox(mut self, sandbox: Sandbox) -> Self {
+        self.sandbox = Some(sandbox);
+        self
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        self.listener.local_addr()
+    }
+
+    /// Serves every client connecting, each in a thread of its own. Errors
+    /// talking to one client don't stop the server, which runs until the
+    /// process ends.
+    pub fn run(self) -> Result<(), Error> {
+        let service = Arc::new(Service {
+            result_cache: self.result_cache,
+            metrics: self.metrics,
+            sandbox: self.sandbox,
+            queries: Mutex::new(HashMap::new()),
+        });
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    trace::event(Level::Warn, || format!("could not accept a client: {}", e));
+                    continue;
+                }
+            };
+            let service = service.clone();
+            thread::spawn(move || {
+                let _span = trace::span("grpc");
+                let peer = stream.peer_addr().map(|addr| addr.to_string()).unwrap_or_default();
+                trace::event(Level::Debug, || format!("connection from {}", peer));
+                if let Err(e) = serve_connection(stream, service) {
+                    trace::event(Level::Warn, || format!("connection from {} failed: {}", peer, e));
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+
+/// The state of a connection shared by the threads answering its calls.
+struct Connection {
+    writer: Mutex<TcpStream>,
+    state: Mutex<State>,
+    /// Signalled when the windows grow, or streams or the connection close.
+    changed: Condvar,
+}
+
+struct State {
+    /// How much more the client accepts on the connection and the streams.
+    window: i64,
+    initial_window: i64,
+    max_frame: usize,
+    streams: HashMap<u32, Stream>,
+    closed: bool,
+}
+
+struct Stream {
+    window: i64,
+    cancel: Cancel,
+}
+
+/// A call whose request is still being read.
+#[derive(Default)]
+struct Call {
+    path: String,
+    api_key: Option<String>,
+    body: Vec<u8>,
+    too_large: bool,
+}
+
+/// How a call ended, sent in the trailers of its response.
+struct Status {
+    code: u32,
+    message: String,
+}
+
+impl Status {
+    fn new(code: u32, message: impl Into<String>) -> Status {
+        Status { code, message: message.into() }
+    }
+
+    fn ok() -> Status {
+        Status::new(OK, "")
+    }
+}
+
+impl Connection {
+
+    fn write_frame(&self, kind: u8, flags: u8, stream: u32, payload: &[u8]) -> Result<(), Error> {
+        let mut frame = Vec::with_capacity(9 + payload.len());
+        put_frame(&mut frame, kind, flags, stream, payload);
+        self.writer.lock().unwrap().write_all(&frame)
+    }
+
+    /// Writes a header block, in as many frames as it takes, at once since
+    /// nothing may come between them.
+    fn write_headers(&self, stream: u32, block: &[u8], end_stream: bool) -> Result<(), Error> {
+        let max_frame = self.state.lock().unwrap().max_frame;
+        let mut frames = Vec::new();
+        let mut chunks = block.chunks(max_frame).peekable();
+        let mut kind = HEADERS;
+        let mut flags = if end_stream { END_STREAM } else { 0 };
+        while let Some(chunk) = chunks.next() {
+            if chunks.peek().is_none() {
+                flags |= END_HEADERS;
+            }
+            put_frame(&mut frames, kind, flags, stream, chunk);
+            kind = CONTINUATION;
+            flags = 0;
+        }
+        self.writer.lock().unwrap().write_all(&frames)
+    }
+
+    /// Writes `data` on `stream` as the flow control windows allow, waiting
+    /// for the client to make room. Fails once the stream or the connection
+    /// is closed.
+    fn write_data(&self, stream: u32, mut data: &[u8]) -> Result<(), Error> {
+        while !data.is_empty() {
+            let mut state = self.state.lock().unwrap();
+            let n = loop {
+                if state.closed {
+                    return Err(Error::new(io::ErrorKind::BrokenPipe, "The connection is closed."));
+                }
+                let window = match state.streams.get(&stream) {
+                    Some(s) => s.window,
+                    None => return Err(Error::new(io::ErrorKind::ConnectionReset, "The stream was reset.")),
+                };
+                let n = window.min(state.window).min(state.max_frame as i64).min(data.len() as i64);
+                if n > 0 {
+                    break n;
+                }
+                state = self.changed.wait(state).unwrap();
+            };
+            state.window -= n;
+            if let Some(s) = state.streams.get_mut(&stream) {
+                s.window -= n;
+            }
+            drop(state);
+            let (chunk, rest) = data.split_at(n as usize);
+            self.write_frame(DATA, 0, stream, chunk)?;
+            data = rest;
+        }
+        Ok(())
+    }
+
+    /// Sends the headers starting the response of `stream`.
+    fn respond(&self, stream: u32) -> Result<(), Error> {
+        let mut block = vec![0x88]; // :status 200, from the static table.
+        encode_header(&mut block, "content-type", "application/grpc");
+        self.write_headers(stream, &block, false)
+    }
+
+    /// Ends the response of `stream` with `status`. Unless the headers of the
+    /// response were sent already, they go with the trailers.
+    fn finish(&self, stream: u32, status: &Status, headers_sent: bool) -> Result<(), Error> {
+        let mut block = Vec::new();
+        if !headers_sent {
+            block.push(0x88);
+            encode_header(&mut block, "content-type", "application/grpc");
+        }
+        encode_header(&mut block, "grpc-status", &status.code.to_string());
+        if !status.message.is_empty() {
+            encode_header(&mut block, "grpc-message", &percent_encode(&status.message));
+        }
+        let written = self.write_headers(stream, &block, true);
+        self.state.lock().unwrap().streams.remove(&stream);
+        written
+    }
+
+    /// Stops the calls of the connection, which is going away.
+    fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        for stream in state.streams.values() {
+            stream.cancel.cancel();
+        }
+        self.changed.notify_all();
+    }
+}
+
+/// Reads the frames of a connection until the client goes away, answering
+/// every call in a thread of its own.
+fn serve_connection(stream: TcpStream, service: Arc<Service>) -> Result<(), Error> {
+    let connection = Arc::new(Connection {
+        writer: Mutex::new(stream.try_clone()?),
+        state: Mutex::new(State {
+            window: DEFAULT_WINDOW,
+            initial_window: DEFAULT_WINDOW,
+            max_frame: DEFAULT_MAX_FRAME,
+            streams: HashMap::new(),
+            closed: false,
+        }),
+        changed: Condvar::new(),
+    });
+    let result = read_frames(&connection, io::BufReader::new(stream), &service);
+    connection.close();
+    result
+}
+
+fn read_frames(connection: &Arc<Connection>, mut input: impl Read, service: &Arc<Service>) -> Result<(), Error> {
+    let mut preface = [0; PREFACE.len()];
+    input.read_exact(&mut preface)?;
+    if preface != PREFACE {
+        return Err(invalid("The client doesn't speak HTTP/2 with prior knowledge."));
+    }
+    let mut settings = Vec::new();
+    for (setting, value) in [(SETTINGS_MAX_CONCURRENT_STREAMS, MAX_STREAMS), (SETTINGS_MAX_HEADER_LIST_SIZE, MAX_HEADER_LIST as u32)] {
+        settings.extend_from_slice(&setting.to_be_bytes());
+        settings.extend_from_slice(&value.to_be_bytes());
+    }
+    connection.write_frame(SETTINGS, 0, 0, &settings)?;
+    let mut decoder = Decoder::new();
+    let mut calls: HashMap<u32, Call> = HashMap::new();
+    while let Some((kind, flags, id, payload)) = read_frame(&mut input)? {
+        match kind {
+            SETTINGS if flags & ACK == 0 => {
+                let mut state = connection.state.lock().unwrap();
+                for setting in payload.chunks_exact(6) {
+                    let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+                    match u16::from_be_bytes([setting[0], setting[1]]) {
+                        SETTINGS_INITIAL_WINDOW_SIZE => {
+                            let delta = value as i64 - state.initial_window;
+                            state.initial_window = value as i64;
+                            for stream in state.streams.values_mut() {
+                                stream.window += delta;
+                            }
+                        }
+                        SETTINGS_MAX_FRAME_SIZE => state.max_frame = value as usize,
+                        _ => {}
+                    }
+                }
+                connection.changed.notify_all();
+                drop(state);
+                connection.write_frame(SETTINGS, ACK, 0, &[])?;
+            }
+            PING if flags & ACK == 0 => connection.write_frame(PING, ACK, 0, &payload)?,
+            WINDOW_UPDATE if payload.len() == 4 => {
+                let increment = (u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]) & 0x7fff_ffff) as i64;
+                let mut state = connection.state.lock().unwrap();
+                match id {
+                    0 => state.window += increment,
+                    _ => if let Some(stream) = state.streams.get_mut(&id) {
+                        stream.window += increment;
+                    },
+                }
+                connection.changed.notify_all();
+            }
+            HEADERS => {
+                let mut block = unpad(&payload, flags)?;
+                if flags & PRIORITY != 0 {
+                    block = block.get(5..).ok_or_else(|| invalid("A HEADERS frame is too short."))?.to_vec();
+                }
+                let mut end_headers = flags & END_HEADERS != 0;
+                while !end_headers {
+                    match read_frame(&mut input)? {
+                        Some((CONTINUATION, flags, continued, payload)) if continued == id => {
+                            if block.len() + payload.len() > MAX_HEADER_LIST {
+                                return Err(invalid("A header block is larger than the server accepts."));
+                            }
+                            block.extend_from_slice(&payload);
+                            end_headers = flags & END_HEADERS != 0;
+                        }
+                        _ => return Err(invalid("Expected a CONTINUATION frame.")),
+                    }
+                }
+                // Decoded even if refused, to keep the table of the decoder that of the client.
+                let headers = decoder.decode(&block)?;
+                let mut state = connection.state.lock().unwrap();
+                if !state.streams.contains_key(&id) && state.streams.len() >= MAX_STREAMS as usize {
+                    drop(state);
+                    trace::event(Level::Debug, || format!("refusing stream {}, {} are open", id, MAX_STREAMS));
+                    connection.write_frame(RST_STREAM, 0, id, &REFUSED_STREAM.to_be_bytes())?;
+                    continue;
+                }
+                let window = state.initial_window;
+                state.streams.entry(id).or_insert_with(|| Stream { window, cancel: Cancel::new() });
+                drop(state);
+                let call = calls.entry(id).or_default();
+                for (name, value) in headers {
+                    match name.as_str() {
+                        ":path" => call.path = value,
+                        "x-api-key" => call.api_key = Some(value),
+                        _ => {}
+                    }
+                }
+                if flags & END_STREAM != 0 {
+                    dispatch(connection, id, calls.remove(&id).unwrap_or_default(), service);
+                }
+            }
+            DATA => {
+                let data = unpad(&payload, flags)?;
+                if !payload.is_empty() {
+                    let increment = (payload.len() as u32).to_be_bytes();
+                    connection.write_frame(WINDOW_UPDATE, 0, 0, &increment)?;
+                    if flags & END_STREAM == 0 {
+                        connection.write_frame(WINDOW_UPDATE, 0, id, &increment)?;
+                    }
+                }
+                if let Some(call) = calls.get_mut(&id) {
+                    if call.body.len() + data.len() > MAX_MESSAGE + 5 {
+                        call.too_large = true;
+                        call.body.clear();
+                    } else if !call.too_large {
+                        call.body.extend_from_slice(&data);
+                    }
+                }
+                if flags & END_STREAM != 0 {
+                    if let Some(call) = calls.remove(&id) {
+                        dispatch(connection, id, call, service);
+                    }
+                }
+            }
+            RST_STREAM => {
+                calls.remove(&id);
+                if let Some(stream) = connection.state.lock().unwrap().streams.remove(&id) {
+                    trace::event(Level::Debug, || format!("stream {} reset by the client", id));
+                    stream.cancel.cancel();
+                }
+                connection.changed.notify_all();
+            }
+            GOAWAY => break,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Answers a call whose request was read, in a thread of its own.
+fn dispatch(connection: &Arc<Connection>, id: u32, call: Call, service: &Arc<Service>) {
+    let (connection, service) = (connection.clone(), service.clone());
+    thread::spawn(move || {
+        let _span = trace::span("grpc");
+        trace::event(Level::Debug, || format!("call {} on stream {}", call.path, id));
+        let tenant = service.sandbox.as_ref().map(|sandbox| sandbox.tenant(call.api_key.as_deref())).transpose();
+        let key = call.api_key.clone().unwrap_or_default();
+        let result = if call.too_large {
+            connection.finish(id, &Status::new(RESOURCE_EXHAUSTED, format!("The request is larger than {} bytes.", MAX_MESSAGE)), false)
+        } else {
+            match (call.path.as_str(), unframe(&call.body), tenant) {
+                (_, _, Err(e)) => connection.finish(id, &Status::new(UNAUTHENTICATED, e.to_string()), false),
+                (_, Err(status), _) => connection.finish(id, &status, false),
+                ("/ffind.Finder/Query", Ok(message), Ok(tenant)) => query(&connection, id, message, &service, key, tenant),
+                ("/ffind.Finder/Cancel", Ok(message), _) => {
+                    let cancelled = match fields(message).and_then(|fields| string_field(&fields, 1)) {
+                        Ok(query) => service.queries.lock().unwrap().get(&(key, query)).map(Cancel::cancel).is_some(),
+                        Err(status) => return log_failure(connection.finish(id, &status, false)),
+                    };
+                    let mut reply = Vec::new();
+                    if cancelled {
+                        put_varint_field(&mut reply, 1, 1);
+                    }
+                    connection.respond(id)
+                        .and_then(|_| connection.write_data(id, &frame_message(&reply)))
+                        .and_then(|_| connection.finish(id, &Status::ok(), true))
+                }
+                (path, Ok(_), _) => connection.finish(id, &Status::new(UNIMPLEMENTED, format!("Unknown method {}.", path)), false),
+            }
+        };
+        log_failure(result);
+    });
+}
+
+fn log_failure(result: Result<(), Error>) {
+    if let Err(e) = result {
+        trace::event(Level::Debug, || format!("could not answer a call: {}", e));
+    }
+}
+
+/// Runs the search of a Query message, streaming its matches, for the client
+/// of an API key and, with a sandbox, the tenant of that key.
+fn query(connection: &Connection, id: u32, message: &[u8], service: &Service, key: String, tenant: Option<Tenant>) -> Result<(), Error> {
+    let fields = match fields(message) {
+        Ok(fields) => fields,
+        Err(status) => return connection.finish(id, &status, false),
+    };
+    let (name, spec) = match (string_field(&fields, 1), string_field(&fields, 2)) {
+        (Ok(name), Ok(spec)) => (name, spec),
+        (Err(status), _) | (_, Err(status)) => return connection.finish(id, &status, false),
+    };
+    let finder = SearchSpec::from_json(&spec).and_then(|spec| {
+        let finder = match &tenant {
+            Some(tenant) => tenant.finder(&spec)?,
+            None => Finder::from_spec(&spec)?,
+        };
+        Ok((finder, spec.depth))
+    });
+    let (mut finder, depth) = match finder {
+        Ok(finder) => finder,
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => return connection.finish(id, &Status::new(PERMISSION_DENIED, e.to_string()), false),
+        Err(e) => return connection.finish(id, &Status::new(INVALID_ARGUMENT, e.to_string()), false),
+    };
+    let cancel = match connection.state.lock().unwrap().streams.get(&id) {
+        Some(stream) => stream.cancel.clone(),
+        None => return Ok(()),
+    };
+    let name = (key, name);
+    if !name.1.is_empty() {
+        let mut queries = service.queries.lock().unwrap();
+        if queries.contains_key(&name) {
+            drop(queries);
+            return connection.finish(id, &Status::new(ALREADY_EXISTS, format!("A query named '{}' is running.", name.1)), false);
+        }
+        queries.insert(name.clone(), cancel.clone());
+    }
+    finder = finder.cancel_with(&cancel);
+    if let Some(dir) = &service.result_cache {
+        finder = finder.result_cache(dir);
+    }
+    if let Some(metrics) = &service.metrics {
+        finder = finder.metrics(metrics);
+    }
+    let started = Instant::now();
+    let mut written = connection.respond(id);
+    let found = if written.is_ok() {
+        finder.for_each(depth, |file| {
+            written = connection.write_data(id, &frame_message(&entry(file)));
+            match written {
+                Ok(()) => ControlFlow::Continue(()),
+                Err(_) => ControlFlow::Break(()),
+            }
+        })
+    } else {
+        Ok(())
+    };
+    if !name.1.is_empty() {
+        service.queries.lock().unwrap().remove(&name);
+    }
+    if let Some(metrics) = &service.metrics {
+        metrics.observe_query(started.elapsed(), found.is_ok());
+    }
+    written?;
+    let status = match found {
+        Ok(()) => Status::ok(),
+        Err(e) => Status::new(match e.kind() {
+            io::ErrorKind::Interrupted if cancel.is_cancelled() => CANCELLED,
+            io::ErrorKind::InvalidInput => INVALID_ARGUMENT,
+            io::ErrorKind::NotFound => NOT_FOUND,
+            io::ErrorKind::PermissionDenied => PERMISSION_DENIED,
+            _ => UNKNOWN,
+        }, e.to_string()),
+    };
+    connection.finish(id, &status, true)
+}
+
+/// The Entry message of a match.
+fn entry(file: &FileInfo) -> Vec<u8> {
+    let mut message = Vec::new();
+    put_bytes_field(&mut message, 1, file.path_str().as_bytes());
+    if let Some(metadata) = file.metadata() {
+        if !metadata.is_empty() {
+            put_varint_field(&mut message, 2, metadata.len());
+        }
+        if let Some(secs) = metadata.modified().and_then(|time| time.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_secs()) {
+            put_varint_field(&mut message, 3, secs);
+        }
+        if metadata.is_dir() {
+            put_varint_field(&mut message, 4, 1);
+        }
+    }
+    message
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+/// Reads the next frame, as its type, flags, stream and payload, or `None`
+/// once the client closed the connection.
+fn read_frame(input: &mut impl Read) -> Result<Option<Frame>, Error> {
+    let mut header = [0; 9];
+    match input.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+    if length > DEFAULT_MAX_FRAME {
+        return Err(invalid("A frame is larger than the server accepts."));
+    }
+    let stream = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff;
+    let mut payload = vec![0; length];
+    input.read_exact(&mut payload)?;
+    Ok(Some((header[3], header[4], stream, payload)))
+}
+
+fn put_frame(out: &mut Vec<u8>, kind: u8, flags: u8, stream: u32, payload: &[u8]) {
+    out.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+    out.push(kind);
+    out.push(flags);
+    out.extend_from_slice(&stream.to_be_bytes());
+    out.extend_from_slice(payload);
+}
+
+/// The payload of a DATA or HEADERS frame without its padding.
+fn unpad(payload: &[u8], flags: u8) -> Result<Vec<u8>, Error> {
+    if flags & PADDED == 0 {
+        return Ok(payload.to_vec());
+    }
+    let padding = *payload.first().ok_or_else(|| invalid("A padded frame is empty."))? as usize;
+    if padding + 1 > payload.len() {
+        return Err(invalid("A frame has more padding than payload."));
+    }
+    Ok(payload[1..payload.len() - padding].to_vec())
+}
+
+
+/// The message of a request, without the 5 bytes framing it.
+fn unframe(body: &[u8]) -> Result<&[u8], Status> {
+    if body.len() < 5 {
+        return Err(Status::new(INVALID_ARGUMENT, "The request has no message."));
+    }
+    if body[0] != 0 {
+        return Err(Status::new(UNIMPLEMENTED, "Compressed requests are not supported."));
+    }
+    let length = u32::from_be_bytes([body[1], body[2], body[3], body[4]]) as usize;
+    body.get(5..5 + length).ok_or_else(|| Status::new(INVALID_ARGUMENT, "The request message is truncated."))
+}
+
+/// Frames a message: not compressed, then its length.
+fn frame_message(message: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(5 + message.len());
+    framed.push(0);
+    framed.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    framed.extend_from_slice(message);
+    framed
+}
+
+/// A field of a protobuf message.
+enum Field<'a> {
+    Bytes(&'a [u8]),
+    Other,
+}
+
+/// The fields of a protobuf message, by number.
+fn fields(mut message: &[u8]) -> Result<Vec<(u64, Field<'_>)>, Status> {
+    let malformed = || Status::new(INVALID_ARGUMENT, "The request message is malformed.");
+    let mut fields = Vec::new();
+    while !message.is_empty() {
+        let key = read_varint(&mut message).ok_or_else(malformed)?;
+        let field = match key & 7 {
+            0 => {
+                read_varint(&mut message).ok_or_else(malformed)?;
+                Field::Other
+            }
+            1 | 5 => {
+                let size = if key & 7 == 1 { 8 } else { 4 };
+                message = message.get(size..).ok_or_else(malformed)?;
+                Field::Other
+            }
+            2 => {
+                let length = read_varint(&mut message).ok_or_else(malformed)? as usize;
+                let bytes = message.get(..length).ok_or_else(malformed)?;
+                message = &message[length..];
+                Field::Bytes(bytes)
+            }
+            _ => return Err(malformed()),
+        };
+        fields.push((key >> 3, field));
+    }
+    Ok(fields)
+}
+
+/// The string field `number`, empty if it isn't set, like in proto3.
+fn string_field(fields: &[(u64, Field)], number: u64) -> Result<String, Status> {
+    match fields.iter().rev().find(|(n, _)| *n == number) {
+        Some((_, Field::Bytes(bytes))) => String::from_utf8(bytes.to_vec())
+            .map_err(|_| Status::new(INVALID_ARGUMENT, format!("Field {} isn't UTF-8.", number))),
+        Some(_) => Err(Status::new(INVALID_ARGUMENT, format!("Field {} isn't a string.", number))),
+        None => Ok(String::new()),
+    }
+}
+
+fn read_varint(input: &mut &[u8]) -> Option<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = input.split_first()?;
+        *input = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn put_varint_field(out: &mut Vec<u8>, number: u64, value: u64) {
+    put_varint(out, number << 3);
+    put_varint(out, value);
+}
+
+fn put_bytes_field(out: &mut Vec<u8>, number: u64, bytes: &[u8]) {
+    put_varint(out, number << 3 | 2);
+    put_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+
+/// Encodes a header as a literal that isn't indexed, with neither its name
+/// nor its value Huffman coded.
+fn encode_header(block: &mut Vec<u8>, name: &str, value: &str) {
+    block.push(0);
+    for string in [name, value] {
+        encode_integer(block, 0, 7, string.len());
+        block.extend_from_slice(string.as_bytes());
+    }
+}
+
+/// Encodes `value` with a prefix of `bits` bits, after the flags in `first`.
+fn encode_integer(block: &mut Vec<u8>, first: u8, bits: u32, mut value: usize) {
+    let max = (1 << bits) - 1;
+    if value < max {
+        block.push(first | value as u8);
+        return;
+    }
+    block.push(first | max as u8);
+    value -= max;
+    while value >= 0x80 {
+        block.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    block.push(value as u8);
+}
+
+/// Percent-encodes the text of grpc-message, as gRPC wants it.
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::new();
+    for byte in text.bytes() {
+        match byte {
+            b' '..=b'~' if byte != b'%' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// The static table of HPACK, RFC 7541 appendix A.
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""), (":method", "GET"), (":method", "POST"), (":path", "/"),
+    (":path", "/index.html"), (":scheme", "http"), (":scheme", "https"), (":status", "200"),
+    (":status", "204"), (":status", "206"), (":status", "304"), (":status", "400"),
+    (":status", "404"), (":status", "500"), ("accept-charset", ""), ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""), ("accept-ranges", ""), ("accept", ""), ("access-control-allow-origin", ""),
+    ("age", ""), ("allow", ""), ("authorization", ""), ("cache-control", ""),
+    ("content-disposition", ""), ("content-encoding", ""), ("content-language", ""), ("content-length", ""),
+    ("content-location", ""), ("content-range", ""), ("content-type", ""), ("cookie", ""),
+    ("date", ""), ("etag", ""), ("expect", ""), ("expires", ""),
+    ("from", ""), ("host", ""), ("if-match", ""), ("if-modified-since", ""),
+    ("if-none-match", ""), ("if-range", ""), ("if-unmodified-since", ""), ("last-modified", ""),
+    ("link", ""), ("location", ""), ("max-forwards", ""), ("proxy-authenticate", ""),
+    ("proxy-authorization", ""), ("range", ""), ("referer", ""), ("refresh", ""),
+    ("retry-after", ""), ("server", ""), ("set-cookie", ""), ("strict-transport-security", ""),
+    ("transfer-encoding", ""), ("user-agent", ""), ("vary", ""), ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// The symbols of the HPACK Huffman code (RFC 7541 appendix B) with codes of
+/// 5 to 15 bits, by the length of their codes. The code is canonical, so the
+/// codes follow from the lengths. The symbols with longer codes are neither
+/// printable ASCII nor decoded.
+const HUFFMAN_SYMBOLS: [&[u8]; 11] = [
+    b"012aceiost",
+    b" %-./3456789=A_bdfghlmnpru",
+    b":BCDEFGHIJKLMNOPQRSTUVWYjkqvwxyz",
+    b"&*,;XZ",
+    b"",
+    b"!\"()?",
+    b"'+|",
+    b"#>",
+    b"\0$@[]~",
+    b"^}",
+    b"<`{",
+];
+
+/// Decodes the header blocks of a connection, whose dynamic table they share.
+struct Decoder {
+    table: VecDeque<(String, String)>,
+    size: usize,
+    max_size: usize,
+}
+
+impl Decoder {
+
+    fn new() -> Decoder {
+        Decoder { table: VecDeque::new(), size: 0, max_size: 4096 }
+    }
+
+    /// Decodes the headers of `block`, failing if they add up to more than
+    /// `MAX_HEADER_LIST` bytes, counted like SETTINGS_MAX_HEADER_LIST_SIZE.
+    fn decode(&mut self, mut block: &[u8]) -> Result<Vec<(String, String)>, Error> {
+        let mut headers = Vec::new();
+        let mut size = 0;
+        while let Some(&first) = block.first() {
+            let (name, value) = if first & 0x80 != 0 {
+                let index = decode_integer(&mut block, 7)?;
+                self.entry(index)?
+            } else if first & 0xe0 == 0x20 {
+                let size = decode_integer(&mut block, 5)?;
+                if size > 4096 {
+                    return Err(invalid("The dynamic table is larger than allowed."));
+                }
+                self.max_size = size;
+                self.evict(0);
+                continue;
+            } else {
+                let (bits, indexed) = if first & 0x40 != 0 { (6, true) } else { (4, false) };
+                let index = decode_integer(&mut block, bits)?;
+                let name = match index {
+                    0 => decode_string(&mut block)?,
+                    _ => self.entry(index)?.0,
+                };
+                let value = decode_string(&mut block)?;
+                if indexed {
+                    self.insert(name.clone(), value.clone());
+                }
+                (name, value)
+            };
+            size += name.len() + value.len() + 32;
+            if size > MAX_HEADER_LIST {
+                return Err(invalid("The headers are larger than the server accepts."));
+            }
+            headers.push((name, value));
+        }
+        Ok(headers)
+    }
+
+    fn entry(&self, index: usize) -> Result<(String, String), Error> {
+        match index {
+            1..=61 => {
+                let (name, value) = STATIC_TABLE[index - 1];
+                Ok((name.to_string(), value.to_string()))
+            }
+            _ => self.table.get(index.wrapping_sub(62)).cloned().ok_or_else(|| invalid("A header index is out of range.")),
+        }
+    }
+
+    fn insert(&mut self, name: String, value: String) {
+        let size = name.len() + value.len() + 32;
+        self.evict(size);
+        if size <= self.max_size {
+            self.size += size;
+            self.table.push_front((name, value));
+        }
+    }
+
+    /// Evicts entries until there is room for `size` more bytes.
+    fn evict(&mut self, size: usize) {
+        while self.size + size > self.max_size {
+            match self.table.pop_back() {
+                Some((name, value)) => self.size -= name.len() + value.len() + 32,
+                None => break,
+            }
+        }
+    }
+}
+
+fn decode_integer(block: &mut &[u8], bits: u32) -> Result<usize, Error> {
+    let truncated = || invalid("A header block is truncated.");
+    let max = (1 << bits) - 1;
+    let (&first, rest) = block.split_first().ok_or_else(truncated)?;
+    *block = rest;
+    let mut value = (first as usize) & max;
+    if value < max {
+        return Ok(value);
+    }
+    for shift in (0..28).step_by(7) {
+        let (&byte, rest) = block.split_first().ok_or_else(truncated)?;
+        *block = rest;
+        value += ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid("A header block has an integer too large."))
+}
+
+fn decode_string(block: &mut &[u8]) -> Result<String, Error> {
+    let huffman = block.first().is_some_and(|first| first & 0x80 != 0);
+    let length = decode_integer(block, 7)?;
+    let bytes = block.get(..length).ok_or_else(|| invalid("A header block is truncated."))?;
+    *block = &block[length..];
+    let bytes = if huffman { huffman_decode(bytes)? } else { bytes.to_vec() };
+    String::from_utf8(bytes).map_err(|_| invalid("A header isn't UTF-8."))
+}
+
+fn huffman_decode(bytes: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut decoded = Vec::new();
+    let (mut code, mut length) = (0u32, 0);
+    for bit in bytes.iter().flat_map(|&byte| (0..8).rev().map(move |i| (byte >> i) as u32 & 1)) {
+        code = code << 1 | bit;
+        length += 1;
+        if let Some(symbol) = huffman_symbol(code, length) {
+            decoded.push(symbol);
+            code = 0;
+            length = 0;
+        } else if length > 15 {
+            return Err(invalid("A header holds characters that aren't printable ASCII."));
+        }
+    }
+    // What is left can only be the padding, the first bits of the code of EOS.
+    if length > 7 || code != (1 << length) - 1 {
+        return Err(invalid("A Huffman coded header is padded wrongly."));
+    }
+    Ok(decoded)
+}
+
+/// The symbol whose code is `code`, `length` bits long, if any.
+fn huffman_symbol(code: u32, length: u32) -> Option<u8> {
+    let mut first = 0;
+    for (bits, symbols) in (5..).zip(HUFFMAN_SYMBOLS) {
+        if bits == length {
+            return code.checked_sub(first).and_then(|i| symbols.get(i as usize)).copied();
+        }
+        first = (first + symbols.len() as u32) << 1;
+    }
+    None
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    use std::time::Duration;
+
+    fn hex(text: &str) -> Vec<u8> {
+        (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap()).collect()
+    }
+
+    #[test]
+    fn decodes_header_blocks() {
+        // The examples of RFC 7541 appendix C.4, whose entries are indexed.
+        let mut decoder = Decoder::new();
+        let headers = decoder.decode(&hex("828684418cf1e3c2e5f23a6ba0ab90f4ff")).unwrap();
+        assert_eq!((String::from(":authority"), String::from("www.example.com")), headers[3]);
+        let headers = decoder.decode(&hex("828684be5886a8eb10649cbf")).unwrap();
+        assert_eq!((String::from(":authority"), String::from("www.example.com")), headers[3]);
+        assert_eq!((String::from("cache-control"), String::from("no-cache")), headers[4]);
+        let headers = decoder.decode(&hex("828785bf408825a849e95ba97d7f8925a849e95bb8e8b4bf")).unwrap();
+        assert_eq!((String::from("custom-key"), String::from("custom-value")), headers[4]);
+        assert_eq!(164, decoder.size);
+
+        let mut block = Vec::new();
+        encode_header(&mut block, "grpc-message", &"x".repeat(300));
+        assert_eq!(vec![(String::from("grpc-message"), "x".repeat(300))], Decoder::new().decode(&block).unwrap());
+        assert!(Decoder::new().decode(&[0xff, 0x00]).is_err());
+
+        // A few bytes naming a large entry of the table again and again.
+        let mut block = Vec::new();
+        encode_header(&mut block, "x-padding", &"x".repeat(4000));
+        block[0] = 0x40;
+        let mut decoder = Decoder::new();
+        assert_eq!(1, decoder.decode(&block).unwrap().len());
+        assert!(decoder.decode(&[0xbe; 5]).is_err());
+    }
+
+    /// Sends a call on `stream`, with the headers literal and not indexed.
+    fn call(client: &mut TcpStream, stream: u32, path: &str, message: &[u8]) {
+        let mut block = vec![0x83, 0x86];
+        encode_header(&mut block, ":path", path);
+        encode_header(&mut block, "content-type", "application/grpc");
+        encode_header(&mut block, "te", "trailers");
+        let mut frames = Vec::new();
+        put_frame(&mut frames, HEADERS, END_HEADERS, stream, &block);
+        put_frame(&mut frames, DATA, END_STREAM, stream, &frame_message(message));
+        client.write_all(&frames).unwrap();
+    }
+
+    /// Reads the response on `stream`, as its messages and trailers.
+    fn response(client: &mut TcpStream, decoder: &mut Decoder, stream: u32) -> (Vec<Vec<u8>>, Vec<(String, String)>) {
+        let mut data = Vec::new();
+        let mut trailers = Vec::new();
+        while let Some((kind, flags, id, payload)) = read_frame(client).unwrap() {
+            match kind {
+                HEADERS => trailers = decoder.decode(&payload).unwrap(),
+                DATA if id == stream => data.extend_from_slice(&payload),
+                _ => {}
+            }
+            if id == stream && flags & END_STREAM != 0 {
+                break;
+            }
+        }
+        let mut messages = Vec::new();
+        let mut rest = &data[..];
+        while !rest.is_empty() {
+            let message = unframe(rest).ok().unwrap();
+            messages.push(message.to_vec());
+            rest = &rest[5 + message.len()..];
+        }
+        (messages, trailers)
+    }
+
+    /// Reads frames until one of `kind` on `stream`, returning its flags and
+    /// payload, or `None` if the server closes the connection first.
+    fn wait_for(client: &mut TcpStream, kind: u8, stream: u32) -> Option<(u8, Vec<u8>)> {
+        loop {
+            match read_frame(client) {
+                Ok(Some((k, flags, id, payload))) if k == kind && id == stream => return Some((flags, payload)),
+                Ok(Some(_)) => {}
+                Ok(None) | Err(_) => return None,
+            }
+        }
+    }
+
+    fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+        headers.iter().find(|(n, _)| n == name).map(|(_, value)| value.as_str())
+    }
+
+    #[test]
+    fn streams_matches() {
+        let root = std::env::temp_dir().join(format!("ffind-grpc-{}", std::process::id()));
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/lib.rs"), "fn main() {}").unwrap();
+        fs::write(root.join("README.md"), "").unwrap();
+        let server = GrpcServer::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        client.write_all(PREFACE).unwrap();
+        client.write_all(&[0, 0, 0, SETTINGS, 0, 0, 0, 0, 0]).unwrap();
+        let mut decoder = Decoder::new();
+
+        let spec = format!(r#"{{"root":{},"depth":5,"filters":[{{"has_extension":{{"ext":".rs"}}}}]}}"#,
+                           serde_json::Value::from(root.to_str().unwrap()));
+        let mut query = Vec::new();
+        put_bytes_field(&mut query, 1, b"q1");
+        put_bytes_field(&mut query, 2, spec.as_bytes());
+        call(&mut client, 1, "/ffind.Finder/Query", &query);
+        let (messages, trailers) = response(&mut client, &mut decoder, 1);
+        assert_eq!(Some("0"), header(&trailers, "grpc-status"));
+        assert_eq!(1, messages.len());
+        let entry = fields(&messages[0]).ok().unwrap();
+        assert_eq!(root.join("src/lib.rs").to_str().unwrap(), string_field(&entry, 1).ok().unwrap());
+        assert!(messages[0].windows(2).any(|size| size == [2 << 3, 12]));
+
+        let mut cancel = Vec::new();
+        put_bytes_field(&mut cancel, 1, b"q1");
+        call(&mut client, 3, "/ffind.Finder/Cancel", &cancel);
+        let (messages, trailers) = response(&mut client, &mut decoder, 3);
+        assert_eq!(Some("0"), header(&trailers, "grpc-status"));
+        assert_eq!(vec![Vec::<u8>::new()], messages, "q1 was done already");
+
+        let mut query = Vec::new();
+        put_bytes_field(&mut query, 2, b"{\"depth\":");
+        call(&mut client, 5, "/ffind.Finder/Query", &query);
+        let (_, trailers) = response(&mut client, &mut decoder, 5);
+        assert_eq!(Some("3"), header(&trailers, "grpc-status"));
+        call(&mut client, 7, "/ffind.Finder/Rename", &[]);
+        let (_, trailers) = response(&mut client, &mut decoder, 7);
+        assert_eq!(Some("12"), header(&trailers, "grpc-status"));
+        assert!(header(&trailers, "grpc-message").unwrap().contains("Rename"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn limits_streams_and_headers() {
+        let server = GrpcServer::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+        let connect = || {
+            let mut client = TcpStream::connect(addr).unwrap();
+            client.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+            client.write_all(PREFACE).unwrap();
+            client.write_all(&[0, 0, 0, SETTINGS, 0, 0, 0, 0, 0]).unwrap();
+            client
+        };
+
+        let mut client = connect();
+        let (_, settings) = wait_for(&mut client, SETTINGS, 0).unwrap();
+        let settings: Vec<_> = settings.chunks_exact(6)
+            .map(|setting| (u16::from_be_bytes([setting[0], setting[1]]), u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]])))
+            .collect();
+        assert_eq!(vec![(SETTINGS_MAX_CONCURRENT_STREAMS, MAX_STREAMS), (SETTINGS_MAX_HEADER_LIST_SIZE, MAX_HEADER_LIST as u32)], settings);
+        // Calls whose requests never end keep their streams open.
+        let mut block = vec![0x83, 0x86];
+        encode_header(&mut block, ":path", "/ffind.Finder/Query");
+        let mut frames = Vec::new();
+        for stream in (1..).step_by(2).take(MAX_STREAMS as usize + 1) {
+            put_frame(&mut frames, HEADERS, END_HEADERS, stream, &block);
+        }
+        client.write_all(&frames).unwrap();
+        let (_, error) = wait_for(&mut client, RST_STREAM, 2 * MAX_STREAMS + 1).unwrap();
+        assert_eq!(REFUSED_STREAM.to_be_bytes().to_vec(), error);
+
+        let mut client = connect();
+        let mut frames = Vec::new();
+        put_frame(&mut frames, HEADERS, 0, 1, &block);
+        for _ in 0..2 {
+            put_frame(&mut frames, CONTINUATION, 0, 1, &[0x83; DEFAULT_MAX_FRAME]);
+        }
+        client.write_all(&frames).unwrap();
+        let closed = loop {
+            match read_frame(&mut client) {
+                Ok(Some(_)) => {}
+                Ok(None) => break true,
+                Err(e) => break e.kind() == io::ErrorKind::ConnectionReset,
+            }
+        };
+        assert!(closed);
+    }
+
+}
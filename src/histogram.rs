@@ -0,0 +1,208 @@
+/*
+How the matches of a search spread over sizes and ages.
+
+Planning for capacity asks how much is in small files and how much in huge
+ones, and how much was written last month against a year ago, rather than
+which files those are. A 'Histogram' counts the matches, and adds up their
+length, per bucket of size or per month they were last modified in, see
+'HistogramBy' and 'Finder::histogram()'.
+
+Size buckets are fixed, and all of them are reported, empty or not, so that
+reports of different trees line up. Months are those of the UTC calendar,
+oldest first, and only those with matches are reported; matches whose
+modification time isn't known are counted last.
+
+ */
+
+use std::collections::BTreeMap;
+use std::io::{self, Error};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::cleanup::human;
+use serde_json::{json, Value};
+
+
+/// The upper bounds of the size buckets but the last, which has none, with
+/// their names.
+const SIZE_BUCKETS: [(u64, &str); 3] = [(1 << 10, "<1K"), (1 << 20, "1K-1M"), (100 << 20, "1M-100M")];
+const LARGEST: &str = "100M+";
+const UNKNOWN: &str = "unknown";
+
+/// How wide the bar of the bucket with the most matches is.
+const BAR_WIDTH: u64 = 40;
+
+/// What buckets matches for `Finder::histogram()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistogramBy {
+    /// The length of files: under 1K, up to 1M, up to 100M and over.
+    Size,
+    /// The month files were last modified in, like `2024-06`.
+    Age,
+}
+
+impl HistogramBy {
+
+    /// Parses a key as given to `--histogram`: `size` or `age`.
+    pub fn parse(arg: &str) -> Result<HistogramBy, Error> {
+        match arg {
+            "size" => Ok(HistogramBy::Size),
+            "age" => Ok(HistogramBy::Age),
+            _ => Err(Error::new(io::ErrorKind::InvalidInput, format!("Unknown histogram '{}', expected size or age.", arg))),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HistogramBy::Size => "size",
+            HistogramBy::Age => "age",
+        }
+    }
+}
+
+/// The matches in one bucket of a `Histogram`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bucket {
+    /// The name of the bucket, like `1K-1M` or `2024-06`.
+    pub label: String,
+    pub files: u64,
+    /// The length of the files, in bytes.
+    pub bytes: u64,
+}
+
+/// How many matches, and how many bytes, are in every bucket of sizes or
+/// months.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    by: HistogramBy,
+    /// The buckets by the order they are reported in.
+    buckets: BTreeMap<u64, Bucket>,
+}
+
+impl Histogram {
+
+    pub fn new(by: HistogramBy) -> Histogram {
+        let mut buckets = BTreeMap::new();
+        if by == HistogramBy::Size {
+            let labels = SIZE_BUCKETS.iter().map(|&(_, label)| label).chain(Some(LARGEST));
+            for (order, label) in labels.enumerate() {
+                buckets.insert(order as u64, Bucket { label: label.to_string(), files: 0, bytes: 0 });
+            }
+        }
+        Histogram { by, buckets }
+    }
+
+    pub fn by(&self) -> HistogramBy {
+        self.by
+    }
+
+    /// Counts a match of `len` bytes, last modified at `modified` if known.
+    pub fn add(&mut self, len: u64, modified: Option<SystemTime>) {
+        let (order, label) = match self.by {
+            HistogramBy::Size => match SIZE_BUCKETS.iter().position(|&(upper, _)| len < upper) {
+                Some(i) => (i as u64, SIZE_BUCKETS[i].1.to_string()),
+                None => (SIZE_BUCKETS.len() as u64, LARGEST.to_string()),
+            },
+            HistogramBy::Age => match modified.map(month) {
+                Some((year, month)) => (((year + (1 << 32)) * 12 + month) as u64, format!("{:04}-{:02}", year, month)),
+                None => (u64::MAX, UNKNOWN.to_string()),
+            },
+        };
+        let bucket = self.buckets.entry(order).or_insert(Bucket { label, files: 0, bytes: 0 });
+        bucket.files += 1;
+        bucket.bytes += len;
+    }
+
+    /// The buckets, in order.
+    pub fn buckets(&self) -> impl Iterator<Item = &Bucket> {
+        self.buckets.values()
+    }
+
+    /// How many matches were counted.
+    pub fn files(&self) -> u64 {
+        self.buckets().map(|bucket| bucket.files).sum()
+    }
+
+    /// The histogram as a table, with a bar for the share of the matches in
+    /// every bucket.
+    pub fn to_text(&self) -> String {
+        let most = self.buckets().map(|bucket| bucket.files).max().unwrap_or(0).max(1);
+        let header = match self.by {
+            HistogramBy::Size => "SIZE",
+            HistogramBy::Age => "MONTH",
+        };
+        let mut text = format!("{:<8}  {:>8}  {:>8}\n", header, "FILES", "TOTAL");
+        for bucket in self.buckets() {
+            let bar = "#".repeat((bucket.files * BAR_WIDTH).div_ceil(most) as usize);
+            text.push_str(format!("{:<8}  {:>8}  {:>8}  {}", bucket.label, bucket.files, human(bucket.bytes), bar).trim_end());
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Every bucket as a JSON object, with its label under `bucket`, and its
+    /// `files` and `bytes`.
+    pub fn to_values(&self) -> Vec<Value> {
+        self.buckets()
+            .map(|bucket| json!({
+                "bucket": bucket.label,
+                "files": bucket.files,
+                "bytes": bucket.bytes,
+            }))
+            .collect()
+    }
+}
+
+/// The year and month of `time`, in UTC.
+pub(crate) fn month(time: SystemTime) -> (i64, i64) {
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    };
+    // The civil date of the days since the epoch, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = secs.div_euclid(86400) + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn buckets_sizes_and_months() {
+        let mut sizes = Histogram::new(HistogramBy::Size);
+        for &len in [0, 1023, 1024, 5 << 20, 100 << 20].iter() {
+            sizes.add(len, None);
+        }
+        let counts: Vec<(&str, u64)> = sizes.buckets().map(|bucket| (bucket.label.as_str(), bucket.files)).collect();
+        assert_eq!(vec![("<1K", 2), ("1K-1M", 1), ("1M-100M", 1), ("100M+", 1)], counts);
+        assert_eq!("SIZE         FILES     TOTAL\n\
+                    <1K              2     1023B  ########################################\n\
+                    1K-1M            1      1.0K  ####################\n\
+                    1M-100M          1      5.0M  ####################\n\
+                    100M+            1    100.0M  ####################\n", sizes.to_text());
+
+        let mut ages = Histogram::new(HistogramBy::Age);
+        let at = |secs: u64| Some(UNIX_EPOCH + Duration::from_secs(secs));
+        // 2024-06-30T23:59:59Z, 2024-02-29T12:00:00Z and 1999-12-31T23:59:59Z.
+        ages.add(10, at(1_719_791_999));
+        ages.add(20, at(1_709_208_000));
+        ages.add(30, at(946_684_799));
+        ages.add(40, None);
+        ages.add(50, at(1_709_208_000));
+        let counts: Vec<(&str, u64, u64)> = ages.buckets().map(|bucket| (bucket.label.as_str(), bucket.files, bucket.bytes)).collect();
+        assert_eq!(vec![("1999-12", 1, 30), ("2024-02", 2, 70), ("2024-06", 1, 10), ("unknown", 1, 40)], counts);
+        assert_eq!((1969, 12), month(UNIX_EPOCH - Duration::from_secs(1)));
+        assert_eq!(5, ages.files());
+        assert!(HistogramBy::parse("count").is_err());
+    }
+
+}
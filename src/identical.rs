@@ -0,0 +1,207 @@
+/*
+Telling files with the same contents apart cheaply.
+
+Hashing every match to find copies reads all of them, although most files
+can't have a copy: no other file is as long. 'content_keys()' only reads
+what it must, in three rounds:
+
+  1. Files are grouped by length. A file of a length no other has is
+     unique without being read.
+  2. Files sharing a length are hashed by their first and last 'EDGE'
+     bytes, which tells most different files apart, and all of small ones.
+  3. Only files sharing those too are hashed in full.
+
+Both rounds hash with the 'HashAlgo' given, see the 'digest' module: XXH3
+is enough to find copies, and reads files as fast as disks give them.
+
+Every round reads its files on a pool of threads, one file each at a time.
+Files are keyed by what the last round they took part in found, so that
+files with the same key have the same contents, and files with different
+keys don't.
+
+ */
+
+use std::collections::HashMap;
+use std::io::Error;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use crate::digest::HashAlgo;
+use crate::trace::{self, Level};
+use crate::vfs::FileSystem;
+
+
+/// How many bytes of each end of a file the second round hashes.
+pub const EDGE: u64 = 4096;
+
+/// Keys `files`, paths with their length, by their contents hashed with
+/// `algo`, in the same order. Files that can't be read have no key.
+pub fn content_keys(file_system: &dyn FileSystem, files: &[(String, u64)], algo: HashAlgo) -> Vec<Option<String>> {
+    let mut keys: Vec<Option<String>> = vec![None; files.len()];
+    let by_len = group((0..files.len()).map(|i| (files[i].1, i)));
+    let mut same_len = Vec::new();
+    for (len, members) in by_len {
+        match members.as_slice() {
+            &[i] => keys[i] = Some(format!("{}", len)),
+            _ => same_len.extend(members),
+        }
+    }
+
+    let edges = in_parallel(&same_len, |&i| edges(file_system, &files[i].0, files[i].1, algo));
+    let by_edges = group(same_len.iter().zip(edges).filter_map(|(&i, edges)| match edges {
+        Ok(edges) => Some(((files[i].1, edges), i)),
+        Err(e) => {
+            trace::event(Level::Warn, || format!("cannot hash {}: {}", files[i].0, e));
+            None
+        }
+    }));
+    let mut same_edges = Vec::new();
+    for ((len, edges), members) in by_edges {
+        // The edges of small files are all of them.
+        if members.len() == 1 || len <= 2 * EDGE {
+            for i in members {
+                keys[i] = Some(format!("{}:{}", len, edges));
+            }
+        } else {
+            same_edges.extend(members);
+        }
+    }
+
+    let hashes = in_parallel(&same_edges, |&i| file_system.open(Path::new(&files[i].0)).and_then(|file| algo.read(file)));
+    for (&i, hash) in same_edges.iter().zip(hashes) {
+        match hash {
+            Ok(hash) => keys[i] = Some(hash),
+            Err(e) => trace::event(Level::Warn, || format!("cannot hash {}: {}", files[i].0, e)),
+        }
+    }
+    trace::event(Level::Info, || format!("of {} files, {} share a length and {} their first and last {} bytes too",
+                                         files.len(), same_len.len(), same_edges.len(), EDGE));
+    keys
+}
+
+/// The hash of the first and last `EDGE` bytes of the file at `path`, of
+/// length `len`, or of all of it if it is shorter.
+fn edges(file_system: &dyn FileSystem, path: &str, len: u64, algo: HashAlgo) -> Result<String, Error> {
+    let path = Path::new(path);
+    let mut hasher = algo.hasher();
+    hasher.update(&file_system.read_at(path, 0, EDGE as usize)?);
+    if len > EDGE {
+        let tail = len.saturating_sub(EDGE).max(EDGE);
+        hasher.update(&file_system.read_at(path, tail, EDGE as usize)?);
+    }
+    Ok(hasher.finish())
+}
+
+/// Groups the values of `pairs` by their key, in the order keys first come.
+fn group<K: Eq + std::hash::Hash + Clone>(pairs: impl Iterator<Item = (K, usize)>) -> Vec<(K, Vec<usize>)> {
+    let mut groups: Vec<(K, Vec<usize>)> = Vec::new();
+    let mut index: HashMap<K, usize> = HashMap::new();
+    for (key, value) in pairs {
+        match index.get(&key) {
+            Some(&g) => groups[g].1.push(value),
+            None => {
+                index.insert(key.clone(), groups.len());
+                groups.push((key, vec![value]));
+            }
+        }
+    }
+    groups
+}
+
+/// Maps `items` with `f` on as many threads as there are CPUs, keeping their
+/// order.
+fn in_parallel<T: Sync, R: Send>(items: &[T], f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let threads = thread::available_parallelism().map_or(1, |n| n.get()).min(items.len());
+    if threads <= 1 {
+        return items.iter().map(f).collect();
+    }
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::with_capacity(items.len()));
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                match items.get(i) {
+                    Some(item) => {
+                        let result = f(item);
+                        results.lock().unwrap().push((i, result));
+                    }
+                    None => break,
+                }
+            });
+        }
+    });
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|&(i, _)| i);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Read;
+    use std::path::PathBuf;
+    use crate::filter::MetadataMode;
+    use crate::vfs::{MemoryFileSystem, Metadata};
+
+    /// Counts the files opened, to tell which rounds read them.
+    struct Counted {
+        fs: MemoryFileSystem,
+        opened: Mutex<Vec<PathBuf>>,
+    }
+
+    impl FileSystem for Counted {
+        fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>, Error> {
+            self.fs.read_dir(path)
+        }
+
+        fn metadata(&self, path: &Path, mode: MetadataMode) -> Result<Metadata, Error> {
+            self.fs.metadata(path, mode)
+        }
+
+        fn open(&self, path: &Path) -> Result<Box<dyn Read + Send>, Error> {
+            let file = self.fs.open(path)?;
+            self.opened.lock().unwrap().push(path.to_path_buf());
+            Ok(file)
+        }
+    }
+
+    #[test]
+    fn reads_only_what_it_must() {
+        let big = |middle: u8| {
+            let mut contents = vec![0; 3 * EDGE as usize];
+            contents[EDGE as usize + 1] = middle;
+            contents
+        };
+        let mut fs = MemoryFileSystem::new();
+        fs.add_file("alone", "a")
+            .add_file("small1", "abc").add_file("small2", "abc").add_file("small3", "xyz")
+            .add_file("big1", big(1)).add_file("big2", big(1)).add_file("big3", big(2))
+            .add_file("tail", { let mut tail = big(1); tail[3 * EDGE as usize - 1] = 9; tail });
+        let counted = Counted { fs, opened: Mutex::new(Vec::new()) };
+        let names = ["alone", "small1", "small2", "small3", "big1", "big2", "big3", "tail"];
+        let files: Vec<(String, u64)> = names.iter()
+            .map(|&name| (name.to_string(), counted.fs.metadata(Path::new(name), MetadataMode::Follow).unwrap().len()))
+            .chain(Some((String::from("missing"), 3 * EDGE)))
+            .collect();
+        let keys = content_keys(&counted, &files, HashAlgo::Sha256);
+
+        let same = |a: usize, b: usize| keys[a].is_some() && keys[a] == keys[b];
+        assert!(same(1, 2) && !same(1, 3) && !same(0, 1));
+        assert!(same(4, 5) && !same(4, 6) && !same(4, 7));
+        assert_eq!(None, keys[8]);
+        // Any hash tells the same files apart.
+        let xxh3 = content_keys(&counted.fs, &files, HashAlgo::Xxh3);
+        let groups = |keys: &[Option<String>]| (0..keys.len()).map(|a| (0..keys.len()).filter(|&b| keys[a].is_some() && keys[a] == keys[b]).count()).collect::<Vec<_>>();
+        assert_eq!(groups(&keys), groups(&xxh3));
+        // Only the big files sharing their edges are read in full, after
+        // their edges; the rest only have their edges read, and alone not
+        // even those.
+        let mut opened: Vec<String> = counted.opened.into_inner().unwrap().iter().map(|path| path.display().to_string()).collect();
+        opened.sort();
+        assert_eq!(vec!["big1", "big1", "big1", "big2", "big2", "big2", "big3", "big3", "big3", "small1", "small2", "small3", "tail", "tail"], opened);
+    }
+
+}
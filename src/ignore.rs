@@ -0,0 +1,180 @@
+/*
+Ignore files.
+
+A '.ffindignore' file excludes files and directories from searches started
+anywhere above it, without touching git's configuration. The rules of a
+global ignore file, '$XDG_CONFIG_HOME/ffind/ignore' (or '~/.config/ffind/ignore'),
+apply to every search root.
+
+The syntax is the one of gitignore files: one glob per line (see the 'glob'
+module), with blank lines and lines starting with '#' skipped. A pattern
+ending in '/' only matches directories. A pattern without any other '/'
+matches the name of a file at any depth below the ignore file, otherwise it
+matches the path relative to the directory of the ignore file, where '**'
+matches any number of directories. A leading '!' re-includes what an earlier
+pattern excluded. The last matching pattern wins, and the rules of an ignore
+file take precedence over those of the directories above it. An ignored
+directory isn't searched at all, so nothing below it can be re-included.
+
+ */
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use crate::glob::Glob;
+use crate::trace::{self, Level};
+
+
+/// The name of the ignore files read in every searched directory.
+pub const IGNORE_FILE: &str = ".ffindignore";
+
+/// The location of the global ignore file, whether or not it exists.
+pub fn global_ignore_file() -> Option<PathBuf> {
+    Some(config_dir()?.join("ignore"))
+}
+
+/// The configuration directory of ffind, `$XDG_CONFIG_HOME/ffind` or
+/// `~/.config/ffind`, whether or not it exists.
+pub(crate) fn config_dir() -> Option<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config.join("ffind"))
+}
+
+/// The rules of one ignore file.
+#[derive(Debug, Clone, Default)]
+pub struct Ignore {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    glob: Glob,
+    negated: bool,
+    dir_only: bool,
+    /// Whether the glob matches the whole relative path, rather than the name.
+    anchored: bool,
+}
+
+impl Ignore {
+
+    /// Parses the contents of an ignore file. Invalid patterns are skipped.
+    pub fn parse(text: &str) -> Ignore {
+        let rules = text.lines().filter_map(|line| {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                return None;
+            }
+            let (negated, line) = match line.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, line),
+            };
+            let (dir_only, line) = match line.strip_suffix('/') {
+                Some(rest) => (true, rest),
+                None => (false, line),
+            };
+            let anchored = line.contains('/');
+            let pattern = line.strip_prefix('/').unwrap_or(line);
+            match Glob::new(pattern) {
+                Ok(glob) => Some(Rule { glob: glob.literal_separator(true), negated, dir_only, anchored }),
+                Err(e) => {
+                    trace::event(Level::Warn, || format!("skipping ignore pattern: {}", e));
+                    None
+                }
+            }
+        }).collect();
+        Ignore { rules }
+    }
+
+    /// Reads the ignore file at `path` on the real filesystem, if there is one.
+    pub fn read(path: &Path) -> Option<Ignore> {
+        let text = fs::read_to_string(path).ok()?;
+        trace::event(Level::Debug, || format!("reading ignore file {}", path.display()));
+        Some(Ignore::parse(&text))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Returns `Some(true)` if `relative`, a path relative to the directory
+    /// of the ignore file, is ignored, `Some(false)` if it is re-included and
+    /// `None` if no rule matches it.
+    pub fn matched(&self, relative: &Path, is_dir: bool) -> Option<bool> {
+        let path = relative.to_string_lossy().replace(std::path::MAIN_SEPARATOR, "/");
+        let name = match relative.file_name() {
+            Some(name) => name.to_string_lossy(),
+            None => return None,
+        };
+        self.rules.iter().rev()
+            .find(|rule| (is_dir || !rule.dir_only)
+                && rule.glob.is_match(if rule.anchored { &path } else { &name }))
+            .map(|rule| !rule.negated)
+    }
+}
+
+/// The ignore files applying to a directory: its own and those above it,
+/// each with the directory its patterns are relative to.
+pub(crate) struct IgnoreStack {
+    base: PathBuf,
+    ignore: Arc<Ignore>,
+    parent: Option<Arc<IgnoreStack>>,
+}
+
+impl IgnoreStack {
+
+    pub(crate) fn push(parent: Option<Arc<IgnoreStack>>, base: &Path, ignore: Arc<Ignore>) -> Arc<IgnoreStack> {
+        Arc::new(IgnoreStack { base: base.to_path_buf(), ignore, parent })
+    }
+
+    /// Returns true if the innermost ignore file with a rule matching `path`
+    /// ignores it.
+    pub(crate) fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let mut stack = Some(self);
+        while let Some(ignores) = stack {
+            if let Ok(relative) = path.strip_prefix(&ignores.base) {
+                if let Some(ignored) = ignores.ignore.matched(relative, is_dir) {
+                    return ignored;
+                }
+            }
+            stack = ignores.parent.as_deref();
+        }
+        false
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn gitignore_rules() {
+        let ignore = Ignore::parse("# build output\n\ntarget/\n*.log\n!keep.log\n/Cargo.lock\ndocs/**/*.md\n");
+        let matched = |path: &str, is_dir| ignore.matched(Path::new(path), is_dir);
+        assert_eq!(Some(true), matched("target", true));
+        assert_eq!(Some(true), matched("sub/target", true));
+        assert_eq!(None, matched("target", false));
+        assert_eq!(Some(true), matched("logs/app.log", false));
+        assert_eq!(Some(false), matched("logs/keep.log", false));
+        assert_eq!(Some(true), matched("Cargo.lock", false));
+        assert_eq!(None, matched("sub/Cargo.lock", false));
+        assert_eq!(Some(true), matched("docs/guide.md", false));
+        assert_eq!(Some(true), matched("docs/a/b/guide.md", false));
+        assert_eq!(None, matched("README.md", false));
+        assert!(Ignore::parse("# nothing\n").is_empty());
+    }
+
+    #[test]
+    fn inner_files_take_precedence() {
+        let outer = IgnoreStack::push(None, Path::new("root"), Arc::new(Ignore::parse("*.log\n")));
+        let inner = IgnoreStack::push(Some(outer.clone()), Path::new("root/logs"), Arc::new(Ignore::parse("!app.log\n")));
+        assert!(outer.is_ignored(Path::new("root/logs/app.log"), false));
+        assert!(!inner.is_ignored(Path::new("root/logs/app.log"), false));
+        assert!(inner.is_ignored(Path::new("root/logs/other.log"), false));
+        assert!(!inner.is_ignored(Path::new("root/logs/app.txt"), false));
+    }
+
+}
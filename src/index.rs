@@ -0,0 +1,275 @@
+/*
+An in-memory index of a directory tree.
+
+The 'Index' walks a tree once, keeping every file and directory it finds
+together with its metadata, and then answers searches ('SearchSpec's) from memory. Repeated
+queries over the same big tree don't have to walk it again, which is what the
+daemon (see the 'daemon' module) is built on. The index doesn't notice changes
+to the tree by itself; call 'Index::refresh()' to walk it again.
+
+ */
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Error};
+use std::path::{Path, PathBuf};
+use crate::Finder;
+use crate::filter::{FileInfo, MetadataMode};
+use crate::metrics::Metrics;
+use crate::sandbox::Tenant;
+use crate::spec::SearchSpec;
+use crate::trace::{self, Level};
+use crate::vfs::StdFileSystem;
+use crate::walk;
+
+
+pub struct Index {
+    root: String,
+    canonical_root: PathBuf,
+    files: Vec<FileInfo>,
+    metrics: Option<Metrics>,
+}
+
+impl Index {
+
+    /// Walks the whole tree under `root` and indexes every file and directory
+    /// in it.
+    pub fn build(root: String) -> Result<Index, Error> {
+        let mut index = Index {
+            canonical_root: fs::canonicalize(&root)?,
+            root,
+            files: Vec::new(),
+            metrics: None,
+        };
+        index.refresh()?;
+        Ok(index)
+    }
+
+    /// Walks the tree again, replacing everything indexed so far.
+    pub fn refresh(&mut self) -> Result<(), Error> {
+        let _span = trace::span("index");
+        let mut finder = Finder::new(self.root.clone()).include_dirs(true);
+        if let Some(metrics) = &self.metrics {
+            finder = finder.metrics(metrics);
+        }
+        let paths = finder.find_all()?;
+        self.files = paths.into_iter()
+            .map(|path| {
+                let file = FileInfo::new(PathBuf::from(path));
+                // Fetch the metadata up front so queries don't have to.
+                file.metadata();
+                file
+            })
+            .collect();
+        trace::event(Level::Info, || format!("indexed {} entries in {}", self.files.len(), self.root));
+        Ok(())
+    }
+
+    /// Counts the directories read by `refresh()` and the matches of queries
+    /// in `metrics`, see the `metrics` module.
+    pub fn set_metrics(&mut self, metrics: &Metrics) {
+        self.metrics = Some(metrics.clone());
+    }
+
+    pub fn root(&self) -> &str {
+        &self.root
+    }
+
+    /// Returns the number of files and directories in the index.
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Runs the search described by `spec` against the index, returning the
+    /// matches in the order they were indexed, or closest first for a fuzzy
+    /// name (see `Finder::fuzzy_name()`). Every root of `spec` has to be the
+    /// indexed root or a directory below it; matched paths are reported
+    /// relative to the indexed root, as they were found. Each file is reported
+    /// at most once, even if the roots overlap, and the files below the
+    /// directories `spec` prunes not at all, as in a search.
+    pub fn query(&self, spec: &SearchSpec) -> Result<Vec<String>, Error> {
+        self.query_with(spec, Finder::from_spec(spec)?)
+    }
+
+    /// Like `query()`, for a tenant of a sandbox: the files outside its roots
+    /// are left out before any filter of `spec` looks at them, see
+    /// `Tenant::finder()`.
+    pub fn query_sandboxed(&self, spec: &SearchSpec, tenant: &Tenant) -> Result<Vec<String>, Error> {
+        self.query_with(spec, tenant.finder(spec)?)
+    }
+
+    fn query_with(&self, spec: &SearchSpec, finder: Finder) -> Result<Vec<String>, Error> {
+        let _span = trace::span("query");
+        let prefixes = std::iter::once(&spec.root)
+            .chain(&spec.extra_roots)
+            .map(|root| self.prefix_of(root))
+            .collect::<Result<Vec<PathBuf>, Error>>()?;
+        let pruned = self.pruned(spec);
+        // A file directly in a root is at depth 1 (see `Finder::find()`), and
+        // is found unless a directory from the root down to it is pruned.
+        let found_below = |file: &FileInfo, prefix: &PathBuf| match file.path().strip_prefix(prefix) {
+            Ok(relative) => (spec.min_depth..=spec.depth).contains(&(relative.components().count() as u32))
+                && !file.path().ancestors().skip(1).take_while(|dir| dir.starts_with(prefix)).any(|dir| pruned.contains(dir)),
+            Err(_) => false,
+        };
+        let mut result: Vec<String> = self.files.iter()
+            .filter(|file| spec.include_dirs || !file.metadata().is_some_and(|meta| meta.is_dir()))
+            .filter(|file| prefixes.iter().any(|prefix| found_below(file, prefix)))
+            .filter(|file| match spec.metadata_mode {
+                // The index holds followed metadata, anything else is looked up again.
+                MetadataMode::Follow => finder.meets_filter_criteria(file),
+                mode => finder.meets_filter_criteria(&FileInfo::with_metadata_mode(file.path().to_path_buf(), mode)),
+            })
+            .map(|file| file.path_str().to_string())
+            .collect();
+        finder.rank(&mut result);
+        if let Some(metrics) = &self.metrics {
+            metrics.matched(result.len() as u64);
+        }
+        Ok(result)
+    }
+
+    /// The indexed directories that the walk of `spec` wouldn't go into: those
+    /// with a marker of `prune_if_contains`, and those with more entries
+    /// than `max_entries_per_dir`, see `Finder::prune_if_contains()` and
+    /// `Finder::max_entries_per_dir()`.
+    fn pruned(&self, spec: &SearchSpec) -> HashSet<&Path> {
+        if spec.prune_if_contains.is_empty() && spec.max_entries_per_dir.is_none() {
+            return HashSet::new();
+        }
+        let mut entries: HashMap<&Path, usize> = HashMap::new();
+        for file in &self.files {
+            if let Some(dir) = file.path().parent() {
+                *entries.entry(dir).or_default() += 1;
+            }
+        }
+        entries.into_iter()
+            .filter(|&(dir, count)| spec.max_entries_per_dir.is_some_and(|max| count > max)
+                || spec.prune_if_contains.iter().any(|marker| walk::contains_marker(&StdFileSystem, dir, marker)))
+            .map(|(dir, _)| dir)
+            .collect()
+    }
+
+    /// Translates a search root into the corresponding prefix of the indexed
+    /// paths, e.g. `/home/me/src/sub` into `src/sub` for an index of `src`.
+    fn prefix_of(&self, root: &str) -> Result<PathBuf, Error> {
+        let outside = || Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Root directory '{}' is not within the indexed directory '{}'.", root, self.root));
+        let canonical = fs::canonicalize(root).map_err(|_| outside())?;
+        let relative = canonical.strip_prefix(&self.canonical_root).map_err(|_| outside())?;
+        Ok(Path::new(&self.root).join(relative))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::spec::FilterSpec;
+
+    /// Writes a small tree into a temporary directory named after `name`,
+    /// 2 '.rs' files in 'src' and 1 below it.
+    fn tree(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ffind-index-{}-{}", name, std::process::id()));
+        for file in ["Cargo.toml", "src/lib.rs", "src/main.rs", "src/notes.md", "src/bin/tool.rs"] {
+            fs::create_dir_all(dir.join(file).parent().unwrap()).unwrap();
+            fs::write(dir.join(file), "").unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn query_matches_find() {
+        let dir = tree("find");
+        let root = dir.to_str().unwrap().to_string();
+        let index = Index::build(format!("{}/.", root)).unwrap();
+        let mut spec = SearchSpec::new(format!("{}/src", root), 1);
+        spec.filters.push(FilterSpec::HasExtension { ext: String::from(".rs"), case_sensitive: true });
+        let mut expected = Finder::from_spec(&spec).unwrap().find(1).unwrap();
+        let mut result = index.query(&spec).unwrap();
+        assert_eq!(2, result.len());
+        // The index reports paths as found from its own root.
+        expected.sort();
+        result.sort();
+        let expected: Vec<String> = expected.iter().map(|p| p.replacen(&root, &format!("{}/.", root), 1)).collect();
+        assert_eq!(expected, result);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn query_respects_depth() {
+        let dir = tree("depth");
+        let root = dir.to_str().unwrap().to_string();
+        let index = Index::build(root.clone()).unwrap();
+        let in_dir = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let spec = SearchSpec::new(root.clone(), 1);
+        let result = index.query(&spec).unwrap();
+        assert!(result.contains(&in_dir("Cargo.toml")));
+        assert!(!result.iter().any(|p| p.starts_with(&in_dir("src/"))));
+        let mut spec = SearchSpec::new(root.clone(), 2);
+        spec.min_depth = 2;
+        let result = index.query(&spec).unwrap();
+        assert!(result.contains(&in_dir("src/lib.rs")));
+        assert!(!result.contains(&in_dir("Cargo.toml")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn query_directories() {
+        let dir = tree("dirs");
+        let index = Index::build(dir.to_str().unwrap().to_string()).unwrap();
+        let src = dir.join("src").to_str().unwrap().to_string();
+        let mut spec = SearchSpec::new(src.clone(), 1);
+        assert!(!index.query(&spec).unwrap().contains(&src));
+        spec.include_dirs = true;
+        assert!(index.query(&spec).unwrap().contains(&src));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn query_overlapping_roots() {
+        let dir = tree("overlap");
+        let index = Index::build(dir.to_str().unwrap().to_string()).unwrap();
+        let mut spec = SearchSpec::new(dir.join("src").to_str().unwrap().to_string(), 1);
+        let once = index.query(&spec).unwrap();
+        spec.extra_roots.push(dir.join("./src").to_str().unwrap().to_string());
+        assert_eq!(once, index.query(&spec).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn query_prunes_like_a_search() {
+        let dir = std::env::temp_dir().join(format!("ffind-index-prune-{}", std::process::id()));
+        for file in ["kept.txt", "backup/.nobackup", "backup/a.txt", "big/1.txt", "big/2.txt", "big/3.txt", "big/4.txt"] {
+            fs::create_dir_all(dir.join(file).parent().unwrap()).unwrap();
+            fs::write(dir.join(file), "").unwrap();
+        }
+        let root = dir.to_str().unwrap().to_string();
+        let index = Index::build(root.clone()).unwrap();
+        let mut spec = SearchSpec::new(root.clone(), 2);
+        spec.prune_if_contains.push(String::from(".nobackup"));
+        spec.max_entries_per_dir = Some(3);
+        let mut expected = Finder::from_spec(&spec).unwrap().find(2).unwrap();
+        let mut result = index.query(&spec).unwrap();
+        expected.sort();
+        result.sort();
+        assert_eq!(vec![dir.join("kept.txt").to_str().unwrap().to_string()], result);
+        assert_eq!(expected, result);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn query_outside_of_index() {
+        let dir = tree("outside");
+        let index = Index::build(dir.join("src").to_str().unwrap().to_string()).unwrap();
+        assert!(index.query(&SearchSpec::new(dir.to_str().unwrap().to_string(), 1)).is_err());
+        assert!(index.query(&SearchSpec::new(dir.join("non_existing_dir").to_str().unwrap().to_string(), 1)).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+}
@@ -0,0 +1,183 @@
+/*
+Files held open by processes.
+
+Deleting a log a daemon still writes to frees nothing until the daemon closes
+it, and on Windows fails outright. 'InUse' retains the files that some
+process holds open, so that they can be left out of, or looked at before, a
+mass deletion.
+
+On Linux, 'OpenFiles::scan()' takes a snapshot of the files open by every
+process, from the file descriptors in /proc/PID/fd, keyed by device and inode
+so that hard links and renamed files are recognized, and remembers which
+processes hold them. Only the processes that can be looked at are seen:
+unless run as root, those of the same user. Files opened after the snapshot
+aren't seen either.
+
+Windows doesn't list the files a process has open. There, a file is in use
+if it can't be opened without sharing it with the handles already open on
+it, and which processes hold it isn't known.
+
+Only available on Linux and Windows.
+
+ */
+
+#[cfg(target_os = "linux")]
+use std::collections::HashMap;
+#[cfg(target_os = "linux")]
+use std::fs;
+use std::io::Error;
+#[cfg(target_os = "linux")]
+use std::sync::Arc;
+use crate::filter::{FileInfo, Filter};
+#[cfg(target_os = "linux")]
+use crate::trace::{self, Level};
+
+
+/// The files open by processes, see the module documentation. Clones share
+/// the same snapshot.
+#[derive(Debug, Clone, Default)]
+pub struct OpenFiles {
+    /// The processes holding every open file, by device and inode.
+    #[cfg(target_os = "linux")]
+    pids: Arc<HashMap<(u64, u64), Vec<u32>>>,
+}
+
+impl OpenFiles {
+
+    /// Looks for the files open by processes. Only fails if /proc can't be
+    /// listed; processes that can't be looked at, or exit meanwhile, are
+    /// skipped.
+    #[cfg(target_os = "linux")]
+    pub fn scan() -> Result<OpenFiles, Error> {
+        use std::os::unix::fs::MetadataExt;
+
+        let mut pids: HashMap<(u64, u64), Vec<u32>> = HashMap::new();
+        for process in fs::read_dir("/proc")? {
+            let process = process?;
+            let Some(pid) = process.file_name().to_str().and_then(|name| name.parse::<u32>().ok()) else {
+                continue;
+            };
+            let fds = match fs::read_dir(process.path().join("fd")) {
+                Ok(fds) => fds,
+                Err(e) => {
+                    trace::event(Level::Debug, || format!("cannot list the open files of process {}: {}", pid, e));
+                    continue;
+                }
+            };
+            // The descriptors are links to the open files, which stat follows.
+            for fd in fds.flatten() {
+                if let Ok(meta) = fs::metadata(fd.path()) {
+                    let holders = pids.entry((meta.dev(), meta.ino())).or_default();
+                    if holders.last() != Some(&pid) {
+                        holders.push(pid);
+                    }
+                }
+            }
+        }
+        pids.values_mut().for_each(|holders| holders.sort_unstable());
+        trace::event(Level::Info, || format!("found {} files open by processes", pids.len()));
+        Ok(OpenFiles { pids: Arc::new(pids) })
+    }
+
+    /// Nothing to look for on Windows: files are probed as they are checked.
+    #[cfg(windows)]
+    pub fn scan() -> Result<OpenFiles, Error> {
+        Ok(OpenFiles::default())
+    }
+
+    /// Returns true if a process holds `file` open.
+    #[cfg(target_os = "linux")]
+    pub fn is_open(&self, file: &FileInfo) -> bool {
+        file.id().is_some_and(|id| self.pids.contains_key(&id))
+    }
+
+    /// Returns true if a process holds `file` open. Only regular files are
+    /// probed.
+    #[cfg(windows)]
+    pub fn is_open(&self, file: &FileInfo) -> bool {
+        file.metadata().is_some_and(|meta| meta.is_file()) && windows::is_locked(file.path())
+    }
+
+    /// The processes holding `file` open, in increasing order.
+    #[cfg(target_os = "linux")]
+    pub fn pids(&self, file: &FileInfo) -> Vec<u32> {
+        file.id().and_then(|id| self.pids.get(&id)).cloned().unwrap_or_default()
+    }
+
+    /// Which processes hold a file isn't known on Windows.
+    #[cfg(windows)]
+    pub fn pids(&self, _file: &FileInfo) -> Vec<u32> {
+        Vec::new()
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::os::raw::c_void;
+    use std::os::windows::ffi::OsStrExt;
+    use std::path::Path;
+
+    type Handle = *mut c_void;
+
+    const INVALID_HANDLE_VALUE: Handle = -1isize as Handle;
+    const GENERIC_READ: u32 = 0x8000_0000;
+    const OPEN_EXISTING: u32 = 3;
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn CreateFileW(file_name: *const u16, desired_access: u32, share_mode: u32, security_attributes: *mut c_void,
+                       creation_disposition: u32, flags_and_attributes: u32, template_file: Handle) -> Handle;
+        fn CloseHandle(object: Handle) -> i32;
+    }
+
+    /// Returns true if `path` can't be opened without sharing it, i.e. some
+    /// process has it open.
+    pub fn is_locked(path: &Path) -> bool {
+        let name: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+        // SAFETY: `name` is NUL terminated.
+        let file = unsafe {
+            CreateFileW(name.as_ptr(), GENERIC_READ, 0, std::ptr::null_mut(), OPEN_EXISTING, 0, std::ptr::null_mut())
+        };
+        if file == INVALID_HANDLE_VALUE {
+            return std::io::Error::last_os_error().raw_os_error() == Some(ERROR_SHARING_VIOLATION);
+        }
+        unsafe { CloseHandle(file) };
+        false
+    }
+}
+
+/// Retains files held open by a process.
+pub struct InUse(pub OpenFiles);
+
+impl Filter for InUse {
+    fn name(&self) -> String {
+        String::from("in_use")
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        self.0.is_open(file)
+    }
+}
+
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn finds_open_files() {
+        let dir = std::env::temp_dir().join(format!("ffind-inuse-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("closed.log"), "").unwrap();
+        let _held = fs::File::create(dir.join("open.log")).unwrap();
+        let open = OpenFiles::scan().unwrap();
+        let file = |name| FileInfo::new(dir.join(name));
+        assert!(open.is_open(&file("open.log")));
+        assert_eq!(vec![std::process::id()], open.pids(&file("open.log")));
+        assert!(!open.is_open(&file("closed.log")));
+        assert!(open.pids(&file("closed.log")).is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+}
@@ -8,117 +8,1128 @@ The core functionality of the 'Finder' struct is to be able to find files relati
 to some given directory. The Finder object utilizes the Builder pattern to allow
 users to add filter criteria to narrow down the search results.
 
+Finders are checked before they search, see the 'build' module.
+
+Filters:
+
+  - The filters themselves live in the 'filter' module, with fuzzy name
+    matching in the 'fuzzy' module.
+  - Those reading the contents of files are in the 'content' module, which
+    can scan large files through the memory maps of the 'mmap' module.
+  - Those asking git about files are in the 'git' module.
+  - On Linux, ACLs are read in the 'acl' module, capabilities in the 'caps'
+    module, both through the 'xattr' module, chattr attributes in the
+    'chattr' module, and the files processes hold open in the 'inuse' module.
+  - Filters can also be built from a find(1) expression, see the 'expr'
+    module, or from a 'SearchSpec' describing a whole search as data, see
+    the 'spec' module.
+
+What is left out:
+
+  - Ignore files, see the 'ignore' module, and named profiles like the one
+    for source trees, see the 'exclude' module.
+  - Filesystems of some types, see the 'fstype' module, and snapshots, see
+    the 'snapshot' module.
+  - Trees mounted twice are walked once, see the 'mounts' module.
+
+Where results come from:
+
+  - An in-memory 'Index' of a tree, kept warm by the 'daemon' module.
+  - The results of the same search saved on disk, for the directories that
+    didn't change, see the 'cache' module.
+  - On macOS, the Spotlight index, see the 'spotlight' module.
+
+Servers:
+
+  - Editors and GUIs search over JSON-RPC, see the 'rpc' module, and fleets
+    of hosts over gRPC, see the 'grpc' module.
+  - What servers do is counted for Prometheus in the 'metrics' module, and
+    what their clients may search is restricted in the 'sandbox' module.
+
+Reporting matches:
+
+  - The 'fields' module chooses what is reported about every match and the
+    'output' module writes it as JSON, CSV or TSV.
+  - The 'sort' module chooses the order, by locale with the 'collate' module.
+  - The 'group' module picks the latest of every group, or one of every set
+    of copies, which the 'identical' module tells apart by the hashes of the
+    'digest' module.
+  - The 'baseline' module leaves out what an earlier search reported.
+  - Summaries: the space matches take, see the 'cleanup' module, whose files
+    take it, see the 'owners' module, how they spread over sizes and ages,
+    see the 'histogram' module, and what deleting the oldest would free, see
+    the 'retention' module.
+  - Snapshots to check matches against later are taken in the 'manifest'
+    module.
+
+Changing files:
+
+  - The 'sync' module plans to make one tree match another.
+  - The 'replace' module replaces text in matched files, like sed.
+  - The 'rename' module renames or moves files, and moves them back.
+  - The 'audit' module records every change, and the 'confirm' module asks
+    before making it, if asked to.
+
+Following a search:
+
+  - The 'plan' module describes what a search is going to walk and check.
+  - The 'trace' module reports what it is doing, and the 'timings' module
+    where it spends its time.
+  - The 'walk' module chooses the order it walks the tree in, and the
+    'budget' module bounds how much content it reads.
+
+Filesystems:
+
+  - Searches run against the real filesystem by default, through the statx
+    fast path of the 'linux' module where it is enabled and the fast
+    enumeration of the 'windows' module on Windows.
+  - Any other 'FileSystem' of the 'vfs' module can be searched instead, like
+    the io_uring backend of the 'uring' module, the NTFS Master File Table
+    of the 'mft' module, remote trees, see the 'remote' module, or buckets,
+    see the 's3' module.
+  - The 'testfs' module builds in-memory trees to test searches with.
+  - The library also builds for WASI, searching the directories preopened
+    for it with the 'StdFileSystem'.
+
+Matching with regexes needs the 'regex' feature; without it, names are
+matched with globs and plain text, and regexes that are plain text still
+work in search specs.
+
  */
 
-use std::collections::VecDeque;
-use std::path::PathBuf;
+#[cfg(all(feature = "acl", target_os = "linux"))]
+pub mod acl;
+pub mod audit;
+pub mod baseline;
+pub mod blake3;
+pub mod budget;
+pub mod build;
+pub mod cache;
+#[cfg(target_os = "linux")]
+pub mod caps;
+#[cfg(target_os = "linux")]
+pub mod chattr;
+pub mod checkpoint;
+pub mod cleanup;
+pub mod confirm;
+#[cfg(feature = "collation")]
+pub mod collate;
+pub mod content;
+#[cfg(unix)]
+pub mod daemon;
+pub mod digest;
+pub mod exclude;
+pub mod expr;
+pub mod fields;
+pub mod filter;
+pub mod fstype;
+pub mod fuzzy;
+pub mod git;
+pub mod glob;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod group;
+pub mod histogram;
+pub mod identical;
+pub mod ignore;
+pub mod index;
+#[cfg(any(target_os = "linux", windows))]
+pub mod inuse;
+pub mod manifest;
+pub mod metrics;
+pub mod mmap;
+pub mod mounts;
+pub mod output;
+pub mod owners;
+pub mod plan;
+#[cfg(windows)]
+pub mod mft;
+#[cfg(all(feature = "statx", target_os = "linux"))]
+pub mod linux;
+#[cfg(feature = "remote")]
+pub mod remote;
+#[cfg(feature = "regex")]
+pub mod rename;
+#[cfg(feature = "regex")]
+pub mod replace;
+pub mod retention;
+pub mod retry;
+pub mod rpc;
+pub mod sandbox;
+#[cfg(feature = "s3")]
+pub mod s3;
+pub mod sha256;
+pub mod snapshot;
+pub mod sort;
+pub mod spec;
+pub mod spotlight;
+pub mod sync;
+pub mod testfs;
+pub mod throttle;
+pub mod timings;
+pub mod trace;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub mod uring;
+pub mod vfs;
+pub mod walk;
+#[cfg(windows)]
+pub mod windows;
+#[cfg(target_os = "linux")]
+pub mod xattr;
+pub mod xxh3;
+
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::{io, fs};
+use std::io::Read;
 use io::Error;
-use regex::Regex;
+#[cfg(feature = "regex")]
+use regex::{Regex, RegexSet};
+use baseline::{Baseline, BaselineUpdate, NotInBaseline};
+use budget::{Budgeted, ScanBudget};
+use build::BuildError;
+use fields::Fields;
+use manifest::{Entry, Manifest};
+use metrics::Metrics;
+use mmap::Unmapped;
+use mounts::{DuplicateMounts, MountTable};
+use plan::Plan;
+use retention::RetentionReport;
+use retry::Retries;
+use checkpoint::{Checkpoint, Cursor, Page};
+use cleanup::{CleanupReport, DiskUsage};
+use digest::HashAlgo;
+use owners::UsageByOwner;
+#[cfg(feature = "regex")]
+use content::ContentMatches;
+use content::{LineCount, Utf8Validity};
+use exclude::Excludes;
+use expr::Expression;
+use fuzzy::Fuzzy;
+use git::{GitState, GitStatus};
+use glob::Glob;
+use group::{Per, UniqueBy};
+use histogram::{Histogram, HistogramBy};
+use ignore::{Ignore, IgnoreStack};
+use sort::SortKey;
+use spec::{FilterSpec, SearchSpec};
+use throttle::Throttled;
+use timings::Timings;
+use trace::Level;
+use vfs::FileSystem;
+use walk::{Cancel, FindOutcome, Ignores, OnError, Prune, SkippedDirs, Traversal, TraversalTuning, Walk, WalkEvent};
+use filter::{FileInfo, Filter, FilterStats, FuzzyName, HasAncestor, HasExtension, MetadataMode, MatchesGlob, ModifiedBefore, NameContains, NameEndsWith, NameStartsWith, OlderThanSibling, SizeGreaterThanOrEq, SizeLessThanOrEq, StemIs};
+#[cfg(feature = "regex")]
+use filter::{MatchesRegex, MatchesRegexSet};
+
 
+/// The depth of searches without a limit, see `Finder::find_all()`.
+pub const UNLIMITED_DEPTH: u32 = u32::MAX;
 
+/// A search, configured with its builder methods and run by one of its
+/// terminal operators, like `find()`. Cloning a Finder is cheap, filters and
+/// filesystems are shared, so one base configuration can be cloned and
+/// specialized per search; handles like a `FilterStats` are shared too.
+#[derive(Clone)]
 pub struct Finder {
-    directory: String,
-    filters: Vec<Box<dyn Fn(&str) -> bool>>,
+    roots: Vec<String>,
+    filters: Vec<Arc<dyn Filter>>,
+    stats: Option<FilterStats>,
+    dedup: bool,
+    dedup_mounts: bool,
+    /// The mount points to stay out of, once the search is prepared.
+    duplicate_mounts: Option<Arc<DuplicateMounts>>,
+    metadata_mode: MetadataMode,
+    file_system: Arc<dyn FileSystem>,
+    ignore_files: bool,
+    global_ignore_file: Option<PathBuf>,
+    min_depth: u32,
+    include_dirs: bool,
+    traversal: Traversal,
+    max_queued: Option<usize>,
+    max_open_dirs: usize,
+    tuning: TraversalTuning,
+    max_entries_per_dir: Option<(usize, SkippedDirs)>,
+    on_error: Option<OnError>,
+    cancel: Option<Cancel>,
+    prune: Vec<Prune>,
+    prune_markers: Vec<String>,
+    excluded_fs_types: Vec<String>,
+    skip_snapshots: bool,
+    excludes: Excludes,
+    usage: Option<DiskUsage>,
+    metrics: Option<Metrics>,
+    timings: Option<Timings>,
+    retries: Retries,
+    throttle: Option<u64>,
+    scan_budget: Option<ScanBudget>,
+    max_file_size: Option<u64>,
+    mmap: bool,
+    /// What filters read contents through, if not `file_system`.
+    contents: Option<Arc<dyn FileSystem>>,
+    checkpoint: Option<(PathBuf, Duration)>,
+    result_cache: Option<PathBuf>,
+    resume: Option<Checkpoint>,
+    rank: Option<Fuzzy>,
+    sort: Option<SortKey>,
+    latest_per: Option<Per>,
+    hash_algo: HashAlgo,
+    /// What builder methods couldn't configure, see `validate()`.
+    invalid: Vec<BuildError>,
+}
+
+/// The outcome of evaluating a single filter against a file, as reported by
+/// `Finder::explain()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Verdict {
+    pub filter: String,
+    pub passed: bool,
 }
 
 impl Finder {
 
     pub fn new(dir: String) -> Finder {
         Finder {
-            directory: dir,
+            roots: vec![dir],
             filters: Vec::new(),
+            stats: None,
+            dedup: true,
+            dedup_mounts: false,
+            duplicate_mounts: None,
+            metadata_mode: MetadataMode::Follow,
+            file_system: default_file_system(),
+            ignore_files: false,
+            global_ignore_file: ignore::global_ignore_file(),
+            min_depth: 0,
+            include_dirs: false,
+            traversal: Traversal::BreadthFirst,
+            max_queued: None,
+            max_open_dirs: 64,
+            tuning: TraversalTuning::default(),
+            max_entries_per_dir: None,
+            on_error: None,
+            cancel: None,
+            prune: Vec::new(),
+            prune_markers: Vec::new(),
+            excluded_fs_types: Vec::new(),
+            skip_snapshots: false,
+            excludes: Excludes::new(),
+            usage: None,
+            metrics: None,
+            timings: None,
+            retries: Retries::new(0, Duration::ZERO),
+            throttle: None,
+            scan_budget: None,
+            max_file_size: None,
+            mmap: false,
+            contents: None,
+            checkpoint: None,
+            result_cache: None,
+            resume: None,
+            rank: None,
+            sort: None,
+            latest_per: None,
+            hash_algo: HashAlgo::Sha256,
+            invalid: Vec::new(),
+        }
+    }
+
+    /// Adds another directory to search, like passing several paths to find.
+    /// Roots are searched in the order they were added.
+    pub fn add_root(mut self, dir: String) -> Self {
+        self.roots.push(dir);
+        self
+    }
+
+    /// Controls whether a file reached more than once, through overlapping
+    /// roots or symlinked directories, is reported only the first time (the
+    /// default). Files are identified by device and inode where available, so
+    /// hard links to the same file are reported once too.
+    pub fn dedup(mut self, yes: bool) -> Self {
+        self.dedup = yes;
+        self
+    }
+
+    /// Walks the trees mounted at several paths below the roots, like bind
+    /// mounts, only once, rather than finding the same files again through
+    /// every path, see the `mounts` module. Only Linux knows its mounts.
+    pub fn dedup_mounts(mut self) -> Self {
+        self.dedup_mounts = true;
+        self
+    }
+
+    /// Chooses whether the size and time filters of this look at the file a
+    /// symlink points to (`MetadataMode::Follow`, the default) or at the
+    /// symlink itself (`MetadataMode::NoFollow`). With `NoFollow`, a small
+    /// symlink to a big file is filtered by its own size.
+    pub fn metadata_mode(mut self, mode: MetadataMode) -> Self {
+        self.metadata_mode = mode;
+        self
+    }
+
+    /// Only reports files at least `depth` levels below their root, like
+    /// find's `-mindepth`. Together with the maximum depth given to `find()`,
+    /// `min_depth(n).find(n)` reports the files exactly `n` levels down.
+    pub fn min_depth(mut self, depth: u32) -> Self {
+        self.min_depth = depth;
+        self
+    }
+
+    /// Controls whether directories matching the filters are reported too,
+    /// roots included, each before anything below it. Off by default, so
+    /// only files are reported.
+    pub fn include_dirs(mut self, yes: bool) -> Self {
+        self.include_dirs = yes;
+        self
+    }
+
+    /// Chooses the order in which entries are visited, see the `walk`
+    /// module. Breadth first by default.
+    pub fn traversal(mut self, traversal: Traversal) -> Self {
+        self.traversal = traversal;
+        self
+    }
+
+    /// Queues at most `entries` entries during a breadth first traversal,
+    /// going depth first below the directories that don't fit. This bounds
+    /// the memory used by searches of huge directories.
+    pub fn max_queued(mut self, entries: usize) -> Self {
+        self.max_queued = Some(entries);
+        self
+    }
+
+    /// Keeps at most `dirs` directories open during a depth first traversal
+    /// (64 by default), reading deeper ones into memory at once. Running out
+    /// of file descriptors nonetheless doesn't fail the search, see the
+    /// `walk` module.
+    pub fn max_open_dirs(mut self, dirs: usize) -> Self {
+        self.max_open_dirs = dirs;
+        self
+    }
+
+    /// Tunes how entries are read and looked up, like on how many threads, to
+    /// suit the storage searched, see the `walk` module.
+    pub fn tuning(mut self, tuning: TraversalTuning) -> Self {
+        self.tuning = tuning;
+        self
+    }
+
+    /// Skips the directories with more than `entries` entries, rather than
+    /// searching them, and records them in `skipped`, see the `walk` module.
+    pub fn max_entries_per_dir(mut self, entries: usize, skipped: &SkippedDirs) -> Self {
+        self.max_entries_per_dir = Some((entries, skipped.clone()));
+        self
+    }
+
+    /// Doesn't walk into the directories for which `prune` returns true, e.g.
+    /// those with a `.skipme` file in them, like find's `-prune`. Filters
+    /// only decide what is reported, this decides what is walked; the pruned
+    /// directories themselves are still reported by `include_dirs()`.
+    pub fn prune_if(mut self, prune: impl Fn(&Path) -> bool + Send + Sync + 'static) -> Self {
+        self.prune.push(Arc::new(prune));
+        self
+    }
+
+    /// Doesn't walk into the directories on a filesystem of type `fs_type`,
+    /// like `nfs` or `tmpfs`, roots included, see the `fstype` module. Only
+    /// the filesystems whose type is known can be excluded.
+    pub fn exclude_fs_type(mut self, fs_type: &str) -> Self {
+        self.excluded_fs_types.push(fs_type.to_lowercase());
+        self
+    }
+
+    /// Doesn't walk into the snapshots of ZFS, btrfs and NetApp filers below
+    /// the roots, which would find every match once more per snapshot, see
+    /// the `snapshot` module.
+    pub fn skip_snapshots(mut self) -> Self {
+        self.skip_snapshots = true;
+        self
+    }
+
+    /// Doesn't walk into the directories `excludes` leaves out, like the
+    /// build output and dependencies with `Excludes::dev()`, see the
+    /// `exclude` module.
+    pub fn excludes(mut self, excludes: Excludes) -> Self {
+        self.excludes.rules.extend(excludes.rules);
+        self
+    }
+
+    /// Doesn't walk into the directories that contain a file named `marker`,
+    /// like `.nobackup`. A `CACHEDIR.TAG` only counts if it starts with the
+    /// signature of the Cache Directory Tagging spec, which is how cargo, pip
+    /// and others tag their caches, see the `walk` module.
+    pub fn prune_if_contains(mut self, marker: &str) -> Self {
+        self.prune_markers.push(marker.to_string());
+        self
+    }
+
+    /// Calls `callback` with every path whose metadata can't be looked up or,
+    /// for a directory, that can't be read, and goes on without it rather
+    /// than failing the search, see the `walk` module.
+    pub fn on_error(mut self, callback: impl Fn(&Path, &Error) + Send + Sync + 'static) -> Self {
+        self.on_error = Some(Arc::new(callback));
+        self
+    }
+
+    /// Stops the search once `cancel` is cancelled, from another thread, and
+    /// fails it with `io::ErrorKind::Interrupted`, see the `walk` module.
+    pub fn cancel_with(mut self, cancel: &Cancel) -> Self {
+        self.cancel = Some(cancel.clone());
+        self
+    }
+
+    /// Records the space taken by every file the search walks in `usage`,
+    /// matched or not, see the `cleanup` module.
+    pub fn disk_usage(mut self, usage: &DiskUsage) -> Self {
+        self.usage = Some(usage.clone());
+        self
+    }
+
+    /// Counts the directories the search reads, its matches and the paths it
+    /// can't read in `metrics`, see the `metrics` module.
+    pub fn metrics(mut self, metrics: &Metrics) -> Self {
+        self.metrics = Some(metrics.clone());
+        self
+    }
+
+    /// Adds up the time the search spends listing directories, looking up and
+    /// filtering entries and handing out matches in `timings`, and which
+    /// directories it spends it in, see the `timings` module.
+    pub fn timings(mut self, timings: &Timings) -> Self {
+        self.timings = Some(timings.clone());
+        self
+    }
+
+    /// Retries listing directories and looking up metadata when they fail
+    /// with a transient error, see the `retry` module. Nothing is retried by
+    /// default.
+    pub fn retry(mut self, retries: Retries) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Reads at most `bytes_per_second` from the file system, counting both
+    /// contents and directory listings, see the `throttle` module.
+    pub fn throttle(mut self, bytes_per_second: u64) -> Self {
+        self.throttle = Some(bytes_per_second);
+        self
+    }
+
+    /// Stops reading contents for content filters once `budget` is spent,
+    /// rejecting the remaining files that need them, see the `budget` module.
+    pub fn scan_budget(mut self, budget: &ScanBudget) -> Self {
+        self.scan_budget = Some(budget.clone());
+        self
+    }
+
+    /// Keeps content filters from reading files larger than `bytes`, so they
+    /// reject them, see the `budget` module. Other filters still see them.
+    pub fn max_file_size(mut self, bytes: u64) -> Self {
+        self.max_file_size = Some(bytes);
+        self
+    }
+
+    /// Lets content filters map large files into memory and search them on
+    /// several threads, see the `mmap` module. Off by default, since a file
+    /// truncated while it is mapped kills the process.
+    pub fn mmap(mut self, yes: bool) -> Self {
+        self.mmap = yes;
+        self
+    }
+
+    /// Saves the state of the search to `file` every `interval`, so that it
+    /// can be resumed with `resume()` if interrupted, see the `checkpoint`
+    /// module. Only for breadth first traversals.
+    pub fn checkpoint(mut self, file: impl Into<PathBuf>, interval: Duration) -> Self {
+        self.checkpoint = Some((file.into(), interval));
+        self
+    }
+
+    /// Saves what the search finds in every directory to a file in `dir`,
+    /// and answers the same search run again from there for the directories
+    /// that didn't change since, see the `cache` module. Only breadth first
+    /// searches whose filters look at names and paths alone are cached.
+    pub fn result_cache(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.result_cache = Some(dir.into());
+        self
+    }
+
+    /// Continues the search saved in the checkpoint `file`, instead of
+    /// starting from the roots. The roots and depth of this `Finder` have to
+    /// be those of the saved search. Matches found before the checkpoint are
+    /// returned again, but not printed again. Returns an error if the
+    /// checkpoint can't be read.
+    pub fn resume(mut self, file: &Path) -> Result<Self, Error> {
+        self.resume = Some(Checkpoint::load(file)?);
+        Ok(self)
+    }
+
+    /// Controls whether `.ffindignore` files in the searched directories and
+    /// the global ignore file exclude files and directories from the search,
+    /// see the `ignore` module. Off by default.
+    pub fn ignore_files(mut self, yes: bool) -> Self {
+        self.ignore_files = yes;
+        self
+    }
+
+    /// Reads the global ignore rules from `path` rather than from the
+    /// configuration directory, or from nowhere if `path` is `None`. Only
+    /// used together with `ignore_files()`.
+    pub fn global_ignore_file(mut self, path: Option<PathBuf>) -> Self {
+        self.global_ignore_file = path;
+        self
+    }
+
+    /// Searches `file_system` instead of the real filesystem. All roots are
+    /// paths within `file_system`.
+    pub fn file_system(mut self, file_system: impl FileSystem + 'static) -> Self {
+        self.file_system = Arc::new(file_system);
+        self
+    }
+
+    /// Creates a `Finder` rooted at the current directory that retains the files
+    /// matching the given find(2) expression, e.g. `-name '*.log' -size +1M`.
+    /// Returns an error if the expression is invalid.
+    pub fn from_expression(expr: &str) -> Result<Finder, Error> {
+        Finder::new(String::from(".")).matches_expression(expr)
+    }
+
+    /// Creates the `Finder` described by `spec`, rooted at `spec.root` and with
+    /// all of its filters. The depth and actions of the spec are left to the
+    /// caller, see `SearchSpec::run()`. Returns an error if any filter in the
+    /// spec is invalid.
+    pub fn from_spec(spec: &SearchSpec) -> Result<Finder, Error> {
+        let mut finder = Finder::new(spec.root.clone())
+            .dedup(spec.dedup)
+            .metadata_mode(spec.metadata_mode)
+            .ignore_files(spec.ignore_files)
+            .min_depth(spec.min_depth)
+            .include_dirs(spec.include_dirs);
+        for root in &spec.extra_roots {
+            finder = finder.add_root(root.clone());
+        }
+        for marker in &spec.prune_if_contains {
+            finder = finder.prune_if_contains(marker);
+        }
+        if let Some(entries) = spec.max_entries_per_dir {
+            finder = finder.max_entries_per_dir(entries, &SkippedDirs::new());
+        }
+        for filter in &spec.filters {
+            if let FilterSpec::FuzzyName { query, max_distance } = filter {
+                finder.rank = Some(Fuzzy::new(query, *max_distance));
+            }
+            finder.filters.push(Arc::from(filter.to_filter()?));
         }
+        Ok(finder)
+    }
+
+    /// Adds the given find(2) expression to this as a single filter. See the
+    /// `expr` module for the supported syntax. Returns an error if the
+    /// expression is invalid.
+    pub fn matches_expression(self, expr: &str) -> Result<Self, Error> {
+        Ok(self.with_filter(Expression::parse(expr)?))
     }
 
     /// Adds the given filter (closure) to this. Does _not_ evaluate it
     /// until a terminal operator is called (lazy). The closure passed to
     /// this function will be used as a filter when searching for files with
-    /// the `find()` of `print_find()` function.
-    pub fn filter(mut self, predicate: impl Fn(&str) -> bool + 'static) -> Self {
-        self.filters.push(Box::new(predicate));
+    /// the `find()` of `print_find()` function. The closure is given the
+    /// full path of each candidate file.
+    pub fn filter(self, predicate: impl Fn(&str) -> bool + Send + Sync + 'static) -> Self {
+        self.with_filter(move |file: &FileInfo| predicate(file.path_str()))
+    }
+
+    /// Adds the given `Filter` to this. Like `filter()`, it is not evaluated
+    /// until a terminal operator is called.
+    pub fn with_filter(mut self, filter: impl Filter + 'static) -> Self {
+        self.filters.push(Arc::new(filter));
+        self
+    }
+
+    /// Like `with_filter()`, but applied before the filters added so far, so
+    /// that they only see the files `filter` retains.
+    pub fn with_first_filter(mut self, filter: impl Filter + 'static) -> Self {
+        self.filters.insert(0, Arc::new(filter));
+        self
+    }
+
+    /// Returns the filters currently in Self, in the order they are applied.
+    pub fn filters(&self) -> impl Iterator<Item = &dyn Filter> {
+        self.filters.iter().map(|f| f.as_ref())
+    }
+
+    /// Evaluates every filter currently in Self against the file at `path` and
+    /// returns each filter's verdict, in order. Unlike a search, evaluation does
+    /// not stop at the first rejecting filter, so the full picture is reported.
+    /// Does not consume this `Finder`.
+    pub fn explain(&self, path: &str) -> Result<Vec<Verdict>, Error> {
+        let path = PathBuf::from(path);
+        if self.file_system.metadata(&path, MetadataMode::NoFollow).is_err() {
+            return Err(Error::new(
+                io::ErrorKind::NotFound,
+                format!("File {} does not exists.", path.display())));
+        }
+        let file = self.file_info(path);
+        Ok(self.filters.iter()
+            .map(|f| Verdict { filter: f.name(), passed: f.matches(&file) })
+            .collect())
+    }
+
+    /// Describes what a search of `depth` levels is going to walk and check,
+    /// without walking anything, see the `plan` module.
+    pub fn plan(&self, depth: u32) -> Plan {
+        let mut pruning: Vec<String> = self.filters.iter()
+            .filter(|f| f.prunes())
+            .map(|f| format!("directories nothing below can pass {}", f.name()))
+            .collect();
+        pruning.extend(self.prune_markers.iter().map(|marker| format!("directories containing {}", marker)));
+        if self.dedup_mounts {
+            pruning.push(String::from("mounts of trees walked through another mount"));
+        }
+        if !self.excluded_fs_types.is_empty() {
+            pruning.push(format!("directories on {} filesystems", self.excluded_fs_types.join(", ")));
+        }
+        if self.skip_snapshots {
+            pruning.push(String::from("snapshots of ZFS, btrfs and NetApp filers"));
+        }
+        if !self.excludes.rules().is_empty() {
+            let rules: Vec<String> = self.excludes.rules().iter().map(|rule| rule.to_string()).collect();
+            pruning.push(format!("directories named like {}", rules.join(", ")));
+        }
+        if !self.prune.is_empty() {
+            pruning.push(format!("directories picked by {} prune_if() callbacks", self.prune.len()));
+        }
+        if let Some((entries, _)) = &self.max_entries_per_dir {
+            pruning.push(format!("directories with more than {} entries", entries));
+        }
+        if self.ignore_files {
+            pruning.push(String::from("what ignore files leave out"));
+        }
+        Plan {
+            roots: self.roots.clone(),
+            traversal: self.traversal,
+            max_queued: self.max_queued,
+            max_depth: depth,
+            pruning,
+            rewrites: self.filters.iter().flat_map(|f| f.rewrites()).collect(),
+            filters: self.filters.iter().map(|f| f.name()).collect(),
+        }
+    }
+
+    /// Collects per-filter statistics (files evaluated, files rejected and time
+    /// spent) into `stats` during the search. Collecting adds a small timing
+    /// overhead to every filter evaluation, so it is off by default.
+    pub fn filter_stats(mut self, stats: &FilterStats) -> Self {
+        self.stats = Some(stats.clone());
         self
     }
 
-    /// Returns true if file represented by the given &String passes
-    /// all of the filters currently in Self.
-    fn meets_filter_criteria(&self, file_str: &String) -> bool {
-        self.filters.iter().all(|f| f(file_str))
+    /// Returns true if the given file passes all of the filters currently in Self.
+    pub(crate) fn meets_filter_criteria(&self, file: &FileInfo) -> bool {
+        let rejected_by = match &self.stats {
+            Some(stats) => self.filters.iter()
+                .enumerate()
+                .position(|(i, f)| !stats.record(i, f.as_ref(), file)),
+            None => self.filters.iter().position(|f| !f.matches(file)),
+        };
+        match rejected_by {
+            Some(i) => {
+                trace::event(Level::Trace, || format!("{} rejected by {}", file.path_str(), self.filters[i].name()));
+                false
+            }
+            None => {
+                trace::event(Level::Trace, || format!("{} matches", file.path_str()));
+                true
+            }
+        }
     }
 
 
+    /// Runs the search, going at most `depth` levels below the roots, like
+    /// find's `-maxdepth`: a root itself is at depth 0, the entries directly
+    /// in it at depth 1 and so on. `find(0)` thus only reports roots that are
+    /// files, and `find(1)` the files directly in the roots.
     pub fn find(self, depth: u32) -> Result<Vec<String>, Error> {
         self.do_find(depth, false)
     }
 
+    /// Like `find()`, without a limit on the depth.
+    pub fn find_all(self) -> Result<Vec<String>, Error> {
+        self.find(UNLIMITED_DEPTH)
+    }
+
+    /// Like `find()`, calling `f` with every match as it is found instead of
+    /// collecting them, until it returns `ControlFlow::Break`. Matches come
+    /// in the order they are found, neither ranked nor sorted.
+    pub fn for_each(mut self, depth: u32, mut f: impl FnMut(&FileInfo) -> ControlFlow<()>) -> Result<(), Error> {
+        let _span = trace::span("find");
+        let roots = self.prepare(depth)?;
+        let mut visitor = |event: WalkEvent| match event {
+            WalkEvent::File(file) => f(file),
+            _ => ControlFlow::Continue(()),
+        };
+        Walk::new(&self, depth, false).visitor(&mut visitor).run(roots)?;
+        Ok(())
+    }
+
+    /// Like `for_each()`, walking depth first and calling `f` as well when
+    /// entering a directory, before its entries, and when leaving it, after
+    /// them, so that the structure of the tree needn't be worked out from
+    /// the paths of the matches, see the `walk` module. Directories are
+    /// entered whether or not there are matches below them.
+    pub fn for_each_event(mut self, depth: u32, mut f: impl FnMut(WalkEvent) -> ControlFlow<()>) -> Result<(), Error> {
+        let _span = trace::span("find");
+        self.traversal = Traversal::DepthFirst;
+        let roots = self.prepare(depth)?;
+        Walk::new(&self, depth, false).visitor(&mut f).run(roots)?;
+        Ok(())
+    }
+
+    /// Like `find()`, also printing every match as it is found.
     pub fn print_find(self, depth: u32) -> Result<Vec<String>, Error> {
         self.do_find(depth, true)
     }
 
+    /// Like `find()`, also counting the occurrences of `pattern` in the
+    /// contents of every match, like `rg --count-matches`. The pattern doesn't
+    /// filter anything by itself: add `content_matches()` with it to only
+    /// keep the files it occurs in. Files whose contents can't be read are
+    /// left out.
+    #[cfg(feature = "regex")]
+    pub fn find_counting(self, depth: u32, pattern: &str, search_compressed: bool) -> Result<Vec<(String, u64)>, Error> {
+        let re = regex::bytes::Regex::new(pattern)
+            .map_err(|e| BuildError::InvalidRegex { pattern: pattern.to_string(), reason: e.to_string() })?;
+        let counter = ContentMatches { re, search_compressed };
+        let (file_system, metadata_mode) = (self.file_system.clone(), self.metadata_mode);
+        let found = self.find(depth)?;
+        Ok(found.into_iter()
+            .filter_map(|path| {
+                let file = FileInfo::with_file_system(PathBuf::from(&path), metadata_mode, file_system.clone());
+                match counter.count(&file) {
+                    Ok(count) => Some((path, count)),
+                    Err(e) => {
+                        trace::event(Level::Warn, || format!("cannot read {}: {}", path, e));
+                        None
+                    }
+                }
+            })
+            .collect())
+    }
+
+    /// Like `find()`, returning the given fields of every match as a JSON
+    /// object, see the `fields` module.
+    pub fn find_entries(self, depth: u32, fields: &Fields) -> Result<Vec<serde_json::Value>, Error> {
+        let (file_system, metadata_mode) = (self.file_system.clone(), self.metadata_mode);
+        let found = self.find(depth)?;
+        Ok(found.into_iter()
+            .map(|path| fields.entry(&FileInfo::with_file_system(PathBuf::from(path), metadata_mode, file_system.clone())))
+            .collect())
+    }
+
+    /// Like `find()`, collapsing the matches that share a name, a stem or
+    /// contents into the first of them, with how many share it, see the
+    /// `group` module. Only the matches as long as another are read to
+    /// compare their contents, see the `identical` module; directories, and
+    /// files that can't be read, have no contents and are left out.
+    pub fn find_unique_by(self, depth: u32, by: UniqueBy) -> Result<Vec<(String, usize)>, Error> {
+        let (file_system, metadata_mode, algo) = (self.file_system.clone(), self.metadata_mode, self.hash_algo);
+        let found = self.find(depth)?;
+        let unique = match by {
+            UniqueBy::Name => group::unique(found, |file| Path::new(file).file_name().map(|name| name.to_string_lossy().into_owned())),
+            UniqueBy::Stem => group::unique(found, |file| Path::new(file).file_stem().map(|stem| stem.to_string_lossy().into_owned())),
+            UniqueBy::Hash => {
+                let files: Vec<(String, u64)> = found.iter()
+                    .filter_map(|file| match file_system.metadata(Path::new(file), metadata_mode) {
+                        Ok(meta) if meta.is_file() => Some((file.clone(), meta.len())),
+                        _ => None,
+                    })
+                    .collect();
+                let keys = identical::content_keys(file_system.as_ref(), &files, algo);
+                let keys: HashMap<String, String> = files.into_iter().zip(keys)
+                    .filter_map(|((file, _), key)| Some((file, key?)))
+                    .collect();
+                group::unique(found, |file| keys.get(file).cloned())
+            }
+        };
+        Ok(unique)
+    }
+
+    /// Like `find()`, recording the size, modification time and, if `hash` is
+    /// set, hash of every match in a `Manifest`, see the `manifest` module.
+    /// Matches that can't be looked up or read are left out.
+    pub fn snapshot(self, depth: u32, hash: bool) -> Result<Manifest, Error> {
+        let (file_system, metadata_mode, roots) = (self.file_system.clone(), self.metadata_mode, self.roots.clone());
+        let algo = Some(self.hash_algo).filter(|_| hash);
+        let found = self.find(depth)?;
+        let mut manifest = Manifest::new();
+        manifest.hash_algo = algo.unwrap_or_default();
+        for path in found {
+            let file = FileInfo::with_file_system(PathBuf::from(&path), metadata_mode, file_system.clone());
+            match Entry::of(&file, algo) {
+                Ok(entry) => manifest.insert(&roots, &path, entry),
+                Err(e) => trace::event(Level::Warn, || format!("cannot snapshot {}: {}", path, e)),
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// Like `find()`, ordering the matches by the space they take on disk,
+    /// largest first, against the space taken by all the files walked, see
+    /// the `cleanup` module. Matches that can't be looked up are left out.
+    pub fn cleanup_report(mut self, depth: u32) -> Result<CleanupReport, Error> {
+        let usage = self.usage.get_or_insert_with(DiskUsage::new).clone();
+        let (file_system, metadata_mode) = (self.file_system.clone(), self.metadata_mode);
+        let found = self.find(depth)?;
+        let files = found.into_iter()
+            .filter_map(|path| match file_system.metadata(Path::new(&path), metadata_mode) {
+                Ok(meta) => Some((path, meta.allocated())),
+                Err(e) => {
+                    trace::event(Level::Warn, || format!("cannot look up {}: {}", path, e));
+                    None
+                }
+            })
+            .collect();
+        Ok(CleanupReport::new(files, usage.bytes()))
+    }
+
+    /// Like `find()`, adding up the space the matches take on disk and how
+    /// many there are per user owning them, largest first, see the `owners`
+    /// module. Matches that can't be looked up, or whose owner isn't known,
+    /// are left out.
+    pub fn usage_by_owner(self, depth: u32) -> Result<UsageByOwner, Error> {
+        let (file_system, metadata_mode) = (self.file_system.clone(), self.metadata_mode);
+        let found = self.find(depth)?;
+        let files: Vec<(u32, u64)> = found.into_iter()
+            .filter_map(|path| match file_system.metadata(Path::new(&path), metadata_mode) {
+                Ok(meta) => meta.owner().map(|(uid, _)| (uid, meta.allocated())),
+                Err(e) => {
+                    trace::event(Level::Warn, || format!("cannot look up {}: {}", path, e));
+                    None
+                }
+            })
+            .collect();
+        Ok(UsageByOwner::new(files, &owners::read_user_names()))
+    }
+
+    /// Like `find()`, grouping the matches by the month they were last
+    /// modified in, oldest first, with the space they take on disk and how
+    /// much deleting them and the older ones would free, see the `retention`
+    /// module. Matches that can't be looked up are left out.
+    pub fn retention_report(self, depth: u32) -> Result<RetentionReport, Error> {
+        let (file_system, metadata_mode) = (self.file_system.clone(), self.metadata_mode);
+        let found = self.find(depth)?;
+        let files = found.into_iter()
+            .filter_map(|path| match file_system.metadata(Path::new(&path), metadata_mode) {
+                Ok(meta) => Some((path, meta.allocated(), meta.modified())),
+                Err(e) => {
+                    trace::event(Level::Warn, || format!("cannot look up {}: {}", path, e));
+                    None
+                }
+            })
+            .collect();
+        Ok(RetentionReport::new(files))
+    }
+
+    /// Like `find()`, counting the matches per bucket of size or month they
+    /// were last modified in, see the `histogram` module. Matches that can't
+    /// be looked up are left out.
+    pub fn histogram(self, depth: u32, by: HistogramBy) -> Result<Histogram, Error> {
+        let (file_system, metadata_mode) = (self.file_system.clone(), self.metadata_mode);
+        let mut histogram = Histogram::new(by);
+        for path in self.find(depth)? {
+            match file_system.metadata(Path::new(&path), metadata_mode) {
+                Ok(meta) => histogram.add(meta.len(), meta.modified()),
+                Err(e) => trace::event(Level::Warn, || format!("cannot look up {}: {}", path, e)),
+            }
+        }
+        Ok(histogram)
+    }
+
+    /// Like `find()`, returning the first `limit` matches after `cursor`, or
+    /// the first ones if it is `None`, and where the search stopped, to fetch
+    /// the next page from, see `Cursor`. The walk picks up where the last page
+    /// stopped rather than starting over, and only the matches of the page are
+    /// held in memory. Pages need a breadth first traversal without a cap on
+    /// the queue, are neither ranked nor sorted, and a file reachable through
+    /// several paths may come up on several pages.
+    pub fn find_page(mut self, depth: u32, cursor: Option<&Cursor>, limit: usize) -> Result<Page, Error> {
+        if limit == 0 {
+            return Err(Error::new(io::ErrorKind::InvalidInput, "Pages must hold at least one match."));
+        }
+        let _span = trace::span("find");
+        let roots = self.prepare(depth)?;
+        let (matches, next) = Walk::new(&self, depth, false).page(roots, cursor.map(Cursor::checkpoint), limit)?;
+        trace::event(Level::Info, || format!("found {} matches for the page", matches.len()));
+        Ok(Page { matches, next: next.map(Cursor::new) })
+    }
+
+    /// Like `find()`, going on without the directories that can't be read
+    /// rather than failing, and returning what was left out with the matches:
+    /// those directories, and the ones skipped for `max_entries_per_dir()`.
+    /// The outcome is truncated if the scan budget ran out during the search.
+    pub fn find_outcome(self, depth: u32) -> Result<FindOutcome, Error> {
+        self.search(depth, false, true)
+    }
+
+    fn do_find(self, depth: u32, print: bool) -> Result<Vec<String>, Error> {
+        self.search(depth, print, false).map(|outcome| outcome.matches)
+    }
+
     /// Consumes this Finder (terminal operator). Searches for files starting
-    /// from self.root, up to a max depth. Returns the files that
+    /// from each of self.roots, up to a max depth. Returns the files that
     /// pass all of the filters currently in Self. If print is true, prints
-    /// the matches to the terminal as they are found.
-    fn do_find(self, depth: u32, print: bool) -> Result<Vec<String>, Error> {
-        // Error check for the root dir to exits before starting.
-        let root = PathBuf::from(&self.directory);
-        if !root.exists() {
-            return Err(Error::new(
-                io::ErrorKind::NotFound,
-                format!("Root directory {} does not exists.", self.directory)));
-        }
-        let mut result = Vec::new();
-        let mut queue: VecDeque<PathBuf> = VecDeque::new();
-        queue.push_back(root);
-        let mut curr_depth = 0;
-
-        // Use BFS to search files one depth layer at a time. For a given item found,
-        // If it's a dir, add it's children to the queue as long as max depth not reached.
-        // If it's a file, add it to result if it passes our filters.
-        while !queue.is_empty() {
-            for _ in 0..queue.len() {
-                let path = queue.pop_front().unwrap();
-                if path.is_dir() && curr_depth <= depth {
-                    for entry in fs::read_dir(path)? {
-                        let entry = entry?;
-                        let child = entry.path();
-                        queue.push_back(child);
-                    }
-                } else if path.is_file() {
-                    let path_string = String::from(path.into_os_string().into_string().unwrap());
-                    if self.meets_filter_criteria(&path_string) {
-                        if print {
-                            println!("matching file: {}", &path_string);
-                        }
-                        result.push(path_string);
-                    }
+    /// the matches to the terminal as they are found. If keep_going is true,
+    /// directories that can't be read are left out rather than failing the
+    /// search.
+    fn search(mut self, depth: u32, print: bool, keep_going: bool) -> Result<FindOutcome, Error> {
+        let _span = trace::span("find");
+        let roots = self.prepare(depth)?;
+        let unread = self.scan_budget.as_ref().map_or(0, ScanBudget::unread_files);
+        // Ranked, sorted or grouped matches can only be printed once they are
+        // all found.
+        let ordered = self.rank.is_some() || self.sort.is_some() || self.latest_per.is_some();
+        let mut walk = Walk::new(&self, depth, print && !ordered);
+        if keep_going {
+            walk = walk.keep_going();
+        }
+        let mut outcome = walk.run(roots)?;
+        trace::event(Level::Info, || format!("found {} matches", outcome.matches.len()));
+        if ordered {
+            if let Some(per) = self.latest_per {
+                let (file_system, metadata_mode) = (&self.file_system, self.metadata_mode);
+                outcome.matches = group::latest(outcome.matches, per, |file| {
+                    file_system.metadata(Path::new(file), metadata_mode).ok()?.modified()
+                });
+            }
+            self.rank(&mut outcome.matches);
+            if print {
+                for file in &outcome.matches {
+                    println!("matching file: {}", file);
+                }
+            }
+        }
+        outcome.truncated = self.scan_budget.as_ref().is_some_and(|budget| budget.unread_files() > unread);
+        Ok(outcome)
+    }
+
+    /// Checks that this Finder can search up to `depth`: that its regexes and
+    /// globs compiled, that its options go together and that its roots exist,
+    /// see the `build` module. Terminal operators check it first.
+    pub fn validate(&self, depth: u32) -> Result<(), BuildError> {
+        if let Some(e) = self.invalid.first() {
+            return Err(e.clone());
+        }
+        if self.min_depth > depth {
+            return Err(BuildError::DepthRange { min: self.min_depth, max: depth });
+        }
+        if (self.checkpoint.is_some() || self.resume.is_some()) && self.traversal != Traversal::BreadthFirst {
+            return Err(BuildError::Conflict(String::from("Checkpoints need a breadth first traversal.")));
+        }
+        for root in &self.roots {
+            if self.retries.run(|| self.file_system.metadata(Path::new(root), MetadataMode::Follow)).is_err() {
+                return Err(BuildError::MissingRoot(root.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Gets this Finder ready to walk its roots up to `depth`, returning them
+    /// with the ignore files applying to them. Returns an error if one of them
+    /// doesn't exist.
+    fn prepare(&mut self, depth: u32) -> Result<Vec<(PathBuf, Ignores)>, Error> {
+        if let Some(bytes_per_second) = self.throttle {
+            self.file_system = Arc::new(Throttled::new(self.file_system.clone(), bytes_per_second));
+        }
+        if self.scan_budget.is_some() || self.max_file_size.is_some() {
+            let contents = Budgeted::new(self.file_system.clone(), self.scan_budget.clone(), self.max_file_size);
+            self.contents = Some(Arc::new(contents));
+        }
+        if !self.mmap {
+            let contents = self.contents.clone().unwrap_or_else(|| self.file_system.clone());
+            self.contents = Some(Arc::new(Unmapped::new(contents)));
+        }
+        self.validate(depth)?;
+        trace::event(Level::Info, || format!("searching {} up to depth {}", self.roots.join(", "), depth));
+        if trace::enabled(Level::Info) {
+            for root in &self.roots {
+                if let Some(fs_type) = self.file_system.fs_type(Path::new(root)) {
+                    trace::event(Level::Info, || format!("{} is on {}", root, fs_type));
                 }
             }
-            curr_depth += 1;
         }
-        Ok(result)
+        if trace::enabled(Level::Debug) {
+            for filter in self.filters() {
+                trace::event(Level::Debug, || format!("filter {}", filter.name()));
+            }
+        }
+        if let Some(stats) = &self.stats {
+            stats.reset(self.filters());
+        }
+        if self.dedup_mounts {
+            let roots: Vec<PathBuf> = self.roots.iter().filter_map(|root| fs::canonicalize(root).ok()).collect();
+            let duplicates = MountTable::read()?.duplicates(&roots);
+            for (point, of) in duplicates.points() {
+                trace::event(Level::Info, || format!("skipping {}, which shows {} again", point.display(), of.display()));
+            }
+            self.duplicate_mounts = Some(Arc::new(duplicates)).filter(|duplicates| !duplicates.is_empty());
+        }
+        let global = match (&self.global_ignore_file, self.ignore_files) {
+            (Some(path), true) => Ignore::read(path).filter(|ignore| !ignore.is_empty()).map(Arc::new),
+            _ => None,
+        };
+        Ok(self.roots.iter()
+            .map(|root| {
+                let ignores = global.clone().map(|global| IgnoreStack::push(None, Path::new(root), global));
+                (PathBuf::from(root), ignores)
+            })
+            .collect())
+    }
+
+    /// Orders `files` by the key of `sort_by()`, if any, or else by how well
+    /// their names match the query of `fuzzy_name()`, if any. Doesn't reorder
+    /// files that match equally well.
+    pub(crate) fn rank(&self, files: &mut [String]) {
+        if let Some(key) = self.sort {
+            key.sort(files);
+        } else if let Some(fuzzy) = &self.rank {
+            files.sort_by_cached_key(|file| fuzzy.score(&Path::new(file).file_name().unwrap_or_default().to_string_lossy()));
+        }
+    }
+
+    /// Adds the ignore file of `dir`, if it has one, to the ignore files
+    /// applying to `dir`.
+    fn read_ignore_file(&self, dir: &Path, ignores: Option<Arc<IgnoreStack>>) -> Option<Arc<IgnoreStack>> {
+        let path = dir.join(ignore::IGNORE_FILE);
+        if self.file_system.metadata(&path, MetadataMode::NoFollow).is_err() {
+            return ignores;
+        }
+        let mut text = String::new();
+        if let Err(e) = self.file_system.open(&path).and_then(|mut file| file.read_to_string(&mut text)) {
+            trace::event(Level::Warn, || format!("cannot read {}: {}", path.display(), e));
+            return ignores;
+        }
+        trace::event(Level::Debug, || format!("reading ignore file {}", path.display()));
+        Some(IgnoreStack::push(ignores, dir, Arc::new(Ignore::parse(&text))))
+    }
+
+    fn file_info(&self, path: PathBuf) -> FileInfo {
+        let file_system = self.contents.as_ref().unwrap_or(&self.file_system);
+        FileInfo::with_file_system(path, self.metadata_mode, file_system.clone())
     }
 
     /// Adds a filter to this `Finder` that retains files with a size less
     /// than or equal to the given size `bytes`.
     pub fn size_less_than_or_eq(self, bytes: u32) -> Finder {
-        self.filter(move |s| {
-            match fs::metadata(s) {
-                Ok(meta) => meta.len() <= bytes as u64,
-                Err(_) => false
-            }
-        })
+        self.with_filter(SizeLessThanOrEq { bytes: bytes as u64 })
     }
 
     /// Adds a filter to this `Finder` that retains files with a size greater
     /// than or equal to the given size `bytes`.
     pub fn size_greater_than_or_eq(self, bytes: u32) -> Finder {
-        self.filter(move |s| {
-            match fs::metadata(s) {
-                Ok(meta) => meta.len() >= bytes as u64,
-                Err(_) => false
-            }
-        })
+        self.with_filter(SizeGreaterThanOrEq { bytes: bytes as u64 })
     }
 
     /// Adds a filter to this `Finder` that retains files with the given extension `ext`
@@ -126,7 +1137,7 @@ impl Finder {
     ///
     /// This filter is lazy and isn't actually applied until this `Finder` is consumed.
     pub fn has_extension(self, ext: String) -> Self {
-        self.filter(move |s| s.ends_with(&ext))
+        self.with_filter(HasExtension { ext, case_sensitive: true })
     }
 
     /// Adds a filter to this `Finder` that retains files with the given extension `ext`
@@ -135,152 +1146,868 @@ impl Finder {
     ///
     /// This filter is lazy and isn't actually applied until this `Finder` is consumed.
     pub fn has_extension_case_insensitive(self, ext: String) -> Self {
-        self.filter(move |s| s.to_lowercase().ends_with(&ext.to_lowercase()))
+        self.with_filter(HasExtension { ext, case_sensitive: false })
+    }
+
+    /// Adds a filter to this `Finder` that retains files whose name starts
+    /// with `prefix`.
+    pub fn name_starts_with(self, prefix: &str) -> Finder {
+        self.with_filter(NameStartsWith { prefix: prefix.to_string() })
+    }
+
+    /// Adds a filter to this `Finder` that retains files whose name ends with
+    /// `suffix`. Unlike `has_extension()`, only the file name is looked at.
+    pub fn name_ends_with(self, suffix: &str) -> Finder {
+        self.with_filter(NameEndsWith { suffix: suffix.to_string() })
+    }
+
+    /// Adds a filter to this `Finder` that retains files whose name without
+    /// its extension is `stem`, like `lib` for `lib.rs`.
+    pub fn stem_is(self, stem: &str) -> Finder {
+        self.with_filter(StemIs { stem: stem.to_string() })
     }
 
     /// Adds a filter to this `Finder` that retains files for which the given regex pattern
     /// is found in the file name. Does not need to match the entire file name.
+    /// Patterns without regex metacharacters are matched like `name_contains()`,
+    /// and those looking for an extension, like `\.rs$`, like `has_extension()`.
+    #[cfg(feature = "regex")]
     pub fn matches_regex(self, pattern: &str) -> Finder {
-        let re = Regex::new(pattern).unwrap();
-        self.filter(move |s| {
-            if let Some(name) = PathBuf::from(s).file_name() {
-                if let Some(name) = name.to_str() {
-                    return re.is_match(name);
-                }
-            }
-            false
-        })
+        self.with_regex(pattern, false)
+    }
+
+    /// Like `matches_regex()`, but the pattern is looked for in the whole path of
+    /// files as found, like `./src/lib.rs`, rather than only in their name.
+    #[cfg(feature = "regex")]
+    pub fn matches_regex_full_path(self, pattern: &str) -> Finder {
+        self.with_regex(pattern, true)
+    }
+
+    #[cfg(feature = "regex")]
+    fn with_regex(self, pattern: &str, full_path: bool) -> Finder {
+        if filter::is_literal(pattern) {
+            return self.with_filter(NameContains::new(pattern, full_path));
+        }
+        if let Some(ext) = filter::extension_regex(pattern, full_path) {
+            return self.with_filter(ext);
+        }
+        match Regex::new(pattern) {
+            Ok(re) => self.with_filter(MatchesRegex { re, full_path }),
+            Err(e) => self.invalid_regex(pattern, e),
+        }
+    }
+
+    /// Adds a filter to this `Finder` that retains files whose name contains
+    /// `text`. This is a plain substring search, faster than a regex.
+    pub fn name_contains(self, text: &str) -> Finder {
+        self.with_filter(NameContains::new(text, false))
+    }
+
+    /// Adds a filter to this `Finder` that retains files for which any of the
+    /// given regex patterns is found in the file name. The patterns are matched
+    /// in a single pass, rather than one `matches_regex()` filter each.
+    #[cfg(feature = "regex")]
+    pub fn matches_any_regex(self, patterns: &[&str]) -> Finder {
+        match RegexSet::new(patterns) {
+            Ok(set) => self.with_filter(MatchesRegexSet { set, all: false, full_path: false }),
+            Err(e) => self.invalid_regex(&patterns.join(", "), e),
+        }
+    }
+
+    /// Like `matches_any_regex()`, but retains files for which every one of
+    /// the patterns is found in the file name.
+    #[cfg(feature = "regex")]
+    pub fn matches_all_regexes(self, patterns: &[&str]) -> Finder {
+        match RegexSet::new(patterns) {
+            Ok(set) => self.with_filter(MatchesRegexSet { set, all: true, full_path: false }),
+            Err(e) => self.invalid_regex(&patterns.join(", "), e),
+        }
+    }
+
+    /// Adds a filter to this `Finder` that retains files whose name is within
+    /// `max_distance` edits of `query`, like `fuzzy_name("finderrs", 2)` for
+    /// `finder.rs`, see the `fuzzy` module. Results are then reported closest
+    /// first, once the search is complete.
+    pub fn fuzzy_name(mut self, query: &str, max_distance: u32) -> Finder {
+        let fuzzy = Fuzzy::new(query, max_distance);
+        self.rank = Some(fuzzy.clone());
+        self.with_filter(FuzzyName { query: fuzzy })
+    }
+
+    /// Reports the matches ordered by `key`, once the search is complete,
+    /// rather than in the order they are found, see the `sort` module. Takes
+    /// precedence over the ranking of `fuzzy_name()`.
+    pub fn sort_by(mut self, key: SortKey) -> Finder {
+        self.sort = Some(key);
+        self
+    }
+
+    /// Reports only the most recently modified match of every group of
+    /// matches sharing an extension or a directory, once the search is
+    /// complete, see the `group` module. Matches handed out as they are found,
+    /// like by `for_each()`, aren't grouped.
+    pub fn latest_per(mut self, per: Per) -> Finder {
+        self.latest_per = Some(per);
+        self
+    }
+
+    /// Hashes contents with `algo`, rather than SHA-256, to find copies with
+    /// `find_unique_by()` and to take snapshots, see the `digest` module.
+    pub fn hash_algo(mut self, algo: HashAlgo) -> Finder {
+        self.hash_algo = algo;
+        self
+    }
+
+    /// Adds a filter to this `Finder` that retains files whose name matches the
+    /// given glob in its entirety, see the `glob` module for the syntax.
+    pub fn matches_glob(self, glob: &str) -> Finder {
+        match Glob::new(glob) {
+            Ok(compiled) => self.with_filter(MatchesGlob { glob: compiled, full_path: false }),
+            Err(e) => self.invalid_glob(glob, e),
+        }
+    }
+
+    /// Like `matches_glob()`, but the glob has to match the whole path of files
+    /// as found, like `./src/lib.rs`.
+    pub fn matches_glob_full_path(self, glob: &str) -> Finder {
+        match Glob::new(glob) {
+            Ok(compiled) => self.with_filter(MatchesGlob { glob: compiled, full_path: true }),
+            Err(e) => self.invalid_glob(glob, e),
+        }
+    }
+
+    /// Adds a filter to this `Finder` that retains files below a directory
+    /// named like `glob`, at any depth, e.g. `has_ancestor_named("tests")`
+    /// for the files of every `tests` directory.
+    pub fn has_ancestor_named(self, glob: &str) -> Finder {
+        match Glob::new(glob) {
+            Ok(compiled) => self.with_filter(HasAncestor { glob: compiled }),
+            Err(e) => self.invalid_glob(glob, e),
+        }
+    }
+
+    /// Adds a filter to this `Finder` that retains files modified before the file named
+    /// `sibling` in the same directory, e.g. `older_than_sibling("Makefile")` for build
+    /// outputs older than the Makefile next to them.
+    pub fn older_than_sibling(self, sibling: &str) -> Finder {
+        self.with_filter(OlderThanSibling { sibling: sibling.to_string() })
+    }
+
+    /// Adds a filter to this `Finder` that retains files last modified more than `age`
+    /// ago, e.g. `older_than(Duration::from_secs(365 * 24 * 60 * 60))` for the files
+    /// nobody touched in a year. See `retention::parse_age()` for ages like `365d`.
+    pub fn older_than(self, age: Duration) -> Finder {
+        let time = SystemTime::now().checked_sub(age).unwrap_or(UNIX_EPOCH);
+        self.with_filter(ModifiedBefore { time })
+    }
+
+    /// Adds a filter to this `Finder` that retains files with a line matching the given
+    /// regex pattern. This filter has to read the files, so it is best added after any
+    /// cheaper filter.
+    #[cfg(feature = "regex")]
+    pub fn content_matches(self, pattern: &str) -> Finder {
+        match regex::bytes::Regex::new(pattern) {
+            Ok(re) => self.with_filter(ContentMatches { re, search_compressed: false }),
+            Err(e) => self.invalid_regex(pattern, e),
+        }
+    }
+
+    /// Adds a filter to this `Finder` that retains text files with more than `lines`
+    /// lines. Binary files are skipped. Like `content_matches()`, it has to read the
+    /// files, but stops as soon as enough lines were counted.
+    pub fn line_count_greater_than(self, lines: u64) -> Finder {
+        self.with_filter(LineCount { arg: format!("+{}", lines), cmp: expr::Cmp::GreaterThan, lines })
+    }
+
+    /// Adds a filter to this `Finder` that retains files whose contents are entirely
+    /// valid UTF-8. See `content::Utf8Validity` to only check the start of each file.
+    pub fn valid_utf8(self) -> Finder {
+        self.with_filter(Utf8Validity { valid: true, sample: None })
+    }
+
+    /// Adds a filter to this `Finder` that retains files whose contents are not valid
+    /// UTF-8, e.g. latin-1 text.
+    pub fn invalid_utf8(self) -> Finder {
+        self.with_filter(Utf8Validity { valid: false, sample: None })
+    }
+
+    /// Adds a filter to this `Finder` that retains the files tracked by git, in the
+    /// repository containing the search root.
+    pub fn git_tracked(self) -> Finder {
+        self.with_filter(GitStatus::new(GitState::Tracked))
+    }
+
+    /// Adds a filter to this `Finder` that retains the files that are neither tracked
+    /// nor ignored by git, in the repository containing the search root.
+    pub fn git_untracked(self) -> Finder {
+        self.with_filter(GitStatus::new(GitState::Untracked))
+    }
+
+    /// Adds a filter to this `Finder` that retains the tracked files with changes,
+    /// staged or not, in the repository containing the search root.
+    pub fn git_modified(self) -> Finder {
+        self.with_filter(GitStatus::new(GitState::Modified))
+    }
+
+    /// Adds a filter to this `Finder` that retains files with an ACL granting more than
+    /// their permission bits, and directories with a default ACL. See the `acl` module.
+    #[cfg(all(feature = "acl", target_os = "linux"))]
+    pub fn has_acl(self) -> Finder {
+        self.with_filter(acl::HasAcl)
+    }
+
+    /// Adds a filter to this `Finder` that retains files whose ACL grants the user or
+    /// group of `grant` at least its permissions, e.g. `user:alice:rw`.
+    #[cfg(all(feature = "acl", target_os = "linux"))]
+    pub fn acl_grants(self, grant: acl::Grant) -> Finder {
+        self.with_filter(acl::AclGrants(grant))
+    }
+
+    /// Adds a filter to this `Finder` that retains files with Linux capabilities set,
+    /// like `cap_net_raw` on ping. See the `caps` module.
+    #[cfg(target_os = "linux")]
+    pub fn has_file_capabilities(self) -> Finder {
+        self.with_filter(caps::HasFileCapabilities)
+    }
+
+    /// Adds a filter to this `Finder` that retains regular files and directories with
+    /// `attribute` set with chattr, like immutable files. See the `chattr` module.
+    #[cfg(target_os = "linux")]
+    pub fn has_attribute(self, attribute: chattr::Attribute) -> Finder {
+        self.with_filter(chattr::HasAttribute(attribute))
+    }
+
+    /// Adds a filter to this `Finder` that retains files not in `baseline`, recording
+    /// every file it sees in `update`, if given, to write a new baseline from. See the
+    /// `baseline` module: it only sees the files passing the filters added before it.
+    pub fn not_in_baseline(self, baseline: Baseline, update: Option<&BaselineUpdate>) -> Finder {
+        self.with_filter(NotInBaseline { baseline, update: update.cloned() })
+    }
+
+    /// Adds a filter to this `Finder` that retains files held open by a process, among
+    /// `open`. See the `inuse` module.
+    #[cfg(any(target_os = "linux", windows))]
+    pub fn in_use(self, open: &inuse::OpenFiles) -> Finder {
+        self.with_filter(inuse::InUse(open.clone()))
     }
 
+    /// Like `content_matches()`, but `.gz`, `.xz` and `.zst` files are decompressed
+    /// before matching, like zgrep does. See the `content` module.
+    #[cfg(feature = "regex")]
+    pub fn content_matches_compressed(self, pattern: &str) -> Finder {
+        match regex::bytes::Regex::new(pattern) {
+            Ok(re) => self.with_filter(ContentMatches { re, search_compressed: true }),
+            Err(e) => self.invalid_regex(pattern, e),
+        }
+    }
+
+    #[cfg(feature = "regex")]
+    fn invalid_regex(mut self, pattern: &str, e: regex::Error) -> Finder {
+        self.invalid.push(BuildError::InvalidRegex { pattern: pattern.to_string(), reason: e.to_string() });
+        self
+    }
+
+    fn invalid_glob(mut self, pattern: &str, e: Error) -> Finder {
+        self.invalid.push(BuildError::InvalidGlob { pattern: pattern.to_string(), reason: e.to_string() });
+        self
+    }
+
+}
+
+/// What identifies a file when deduplicating results.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum FileId {
+    Inode(u64, u64),
+    Path(PathBuf),
+}
+
+/// Identifies a file by device and inode if its filesystem has them, or else
+/// by its canonical path.
+fn file_id(file: &FileInfo) -> Option<FileId> {
+    file.metadata()?;
+    match file.id() {
+        Some((dev, ino)) => Some(FileId::Inode(dev, ino)),
+        None => fs::canonicalize(file.path()).ok().map(FileId::Path),
+    }
+}
+
+/// The real filesystem searches run against unless told otherwise.
+fn default_file_system() -> Arc<dyn FileSystem> {
+    #[cfg(all(feature = "statx", target_os = "linux"))]
+    return Arc::new(linux::LinuxFileSystem::new());
+    #[cfg(windows)]
+    return Arc::new(windows::WindowsFileSystem::new());
+    #[cfg(not(any(all(feature = "statx", target_os = "linux"), windows)))]
+    return Arc::new(vfs::StdFileSystem);
 }
 
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::{dir, file};
+    use testfs::Entry;
+
+    /// The tree most tests search, 4 '.rs' files in 'src' and 1 below it.
+    fn tree() -> vfs::MemoryFileSystem {
+        dir! {
+            file!("Cargo.toml", 300),
+            file!("Cargo.lock", 2000),
+            "src" => {
+                file!("lib.rs", 5000),
+                file!("main.rs", 3000),
+                file!("filter.rs", 800),
+                file!("glob.rs", 400),
+                "bin" => { file!("tool.rs", 50) },
+            },
+            "docs" => { file!("guide.md", 10) },
+        }
+    }
+
+    fn finder(root: &str) -> Finder {
+        Finder::new(root.to_string()).file_system(tree())
+    }
 
     #[test]
     fn finds_src_files() {
         let files = Finder::new("src".to_string())
-            .find(0)
+            .find(1)
             .unwrap();
         println!("files = {:#?}", files);
-        assert_eq!(true, files.contains(&"src/lib.rs".to_string()));
+        assert!(files.contains(&"src/lib.rs".to_string()));
+    }
+
+    #[test]
+    fn depth_counts_like_find() {
+        let count = |finder: Finder, depth| finder.find(depth).unwrap().len();
+        // The root itself is at depth 0, so only a root that is a file is found.
+        assert_eq!(0, count(finder("."), 0));
+        assert_eq!(vec!["Cargo.toml"], finder("Cargo.toml").find(0).unwrap());
+        assert_eq!(2, count(finder("."), 1));
+        assert_eq!(7, count(finder("."), 2));
+        assert_eq!(8, count(finder("."), 3));
+        // Exactly at depth 2: the four sources and the guide.
+        assert_eq!(5, count(finder(".").min_depth(2), 2));
+        assert_eq!(1, count(finder(".").min_depth(3), 99));
+        assert_eq!(8, finder(".").find_all().unwrap().len());
+    }
+
+    #[test]
+    fn roots_and_directories_in_results() {
+        assert_eq!(vec!["src/lib.rs"], finder("src/lib.rs").find(1).unwrap());
+        assert!(finder("src/lib.rs").matches_glob("main*").find(1).unwrap().is_empty());
+        let found = finder("src").include_dirs(true).find(2).unwrap();
+        assert_eq!(7, found.len());
+        assert_eq!("src", found[0]);
+        assert!(found.contains(&String::from("src/bin")));
+        let dirs = finder(".").include_dirs(true).matches_expression("-type d").unwrap().find(99).unwrap();
+        assert_eq!(vec![".", "./docs", "./src", "./src/bin"], dirs);
     }
 
     #[test]
     fn non_existing_root_dir() {
         let result = Finder::new("non_existing_dir/".to_string())
-            .find(0);
+            .find(1);
         assert!(result.is_err());
+        assert!(finder("non_existing_dir/").find(1).is_err());
     }
 
     #[test]
     fn print_find() {
-        let result = Finder::new("src/".to_string())
-            .print_find(1);
+        let result = finder("src/")
+            .print_find(2);
         assert!(result.is_ok());
     }
 
     #[test]
     fn has_extension_lock() {
-        let result = Finder::new("./".to_string())
+        let result = finder("./")
             .has_extension(String::from(".lock"))
-            .find(0)
+            .find(1)
             .unwrap();
         assert_eq!(1, result.len());
     }
 
     #[test]
     fn files_gt_10_b() {
-        let result = Finder::new("src/".to_string())
+        let result = finder("src/")
             .has_extension(String::from(".rs"))
             .size_greater_than_or_eq(10)
-            .find(0)
+            .find(1)
             .unwrap();
-        assert_eq!(2, result.len(), "There should be 3 source files with size >= 10 B.")
+        assert_eq!(4, result.len(), "There should be 4 source files with size >= 10 B.")
     }
 
     #[test]
     fn files_gt_1_mb() {
-        let result = Finder::new("src/".to_string())
+        let result = finder("src/")
             .has_extension(String::from(".rs"))
             .size_greater_than_or_eq(1_000_000)
-            .find(0)
+            .find(1)
             .unwrap();
         assert_eq!(0, result.len(), "There should be 0 source files with size >= 1 MB.")
     }
 
     #[test]
     fn files_lt_10_b() {
-        let result = Finder::new("src/".to_string())
+        let result = finder("src/")
             .has_extension(String::from(".rs"))
             .size_less_than_or_eq(10)
-            .find(0)
+            .find(1)
             .unwrap();
         assert_eq!(0, result.len(), "There should be 0 source files with size <= 10 B.")
     }
 
     #[test]
     fn files_lt_1_mb() {
-        let result = Finder::new("src/".to_string())
+        let result = finder("src/")
             .has_extension(String::from(".rs"))
             .size_less_than_or_eq(1_000_000)
-            .find(0)
+            .find(1)
             .unwrap();
-        assert_eq!(2, result.len(), "There should be 3 source files with size <= 1 MB.")
+        assert_eq!(4, result.len(), "There should be 4 source files with size <= 1 MB.")
     }
 
     #[test]
     fn custom_filter_for_letter_n() {
-        let finder = Finder::new("src/".to_string());
+        let finder = finder("src/");
         let result = finder
             .filter(|file_name| file_name.contains("n"))
-            .find(3)
+            .find(4)
             .unwrap();
-        assert_eq!(1, result.len(), "There should be 2 src/ files with 'n' in name.")
+        assert_eq!(2, result.len(), "There should be 2 src/ files with 'n' in name.")
     }
 
     #[test]
     fn has_extension_rs_case_sensitive() {
-        let result = Finder::new("./".to_string())
+        let result = finder("./")
             .has_extension(String::from(".rs"))
-            .find(1)
+            .find(2)
             .unwrap();
-        assert_eq!(2, result.len(), "There should be 3 source files with '.rs' extension.");
-        let result = Finder::new("./".to_string())
+        assert_eq!(4, result.len(), "There should be 4 source files with '.rs' extension.");
+        let result = finder("./")
             .has_extension(String::from(".RS"))
-            .find(1)
+            .find(2)
             .unwrap();
         assert_eq!(0, result.len(), "There should be 0 source files with '.RS' extension.");
     }
 
     #[test]
     fn has_extension_rs_case_insensitive() {
-        let result = Finder::new("./".to_string())
+        let result = finder("./")
             .has_extension_case_insensitive(String::from(".rs"))
-            .find(1)
+            .find(2)
             .unwrap();
-        assert_eq!(2, result.len(), "There should be 3 source files with '.rs' extension.");
-        let result = Finder::new("./".to_string())
+        assert_eq!(4, result.len(), "There should be 4 source files with '.rs' extension.");
+        let result = finder("./")
             .has_extension_case_insensitive(String::from(".RS"))
+            .find(2)
+            .unwrap();
+        assert_eq!(4, result.len(), "There should be 4 source files matching '.RS' extension.");
+    }
+
+    #[test]
+    fn explain_reports_every_filter() {
+        let verdicts = finder("src/")
+            .has_extension(String::from(".rs"))
+            .size_less_than_or_eq(10)
+            .matches_glob("lib*")
+            .explain("src/lib.rs")
+            .unwrap();
+        let passed: Vec<bool> = verdicts.iter().map(|v| v.passed).collect();
+        assert_eq!(vec![true, false, true], passed);
+        assert_eq!("size_less_than_or_eq(10)", verdicts[1].filter);
+    }
+
+    #[test]
+    fn explain_non_existing_file() {
+        let result = finder("src/")
+            .explain("src/non_existing.rs");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn filter_stats_collected_during_find() {
+        let stats = FilterStats::new();
+        let result = finder("src/")
+            .has_extension(String::from(".rs"))
+            .matches_glob("lib*")
+            .filter_stats(&stats)
+            .find(1)
+            .unwrap();
+        let report = stats.report();
+        assert_eq!(2, report.len());
+        assert_eq!(0, report[0].rejected);
+        assert_eq!(report[0].evaluated, report[1].evaluated);
+        assert_eq!(report[1].evaluated - report[1].rejected, result.len() as u64);
+    }
+
+    #[test]
+    fn expression_filters() {
+        let result = finder("src/")
+            .matches_expression("-name 'l*' -a ( -size +0 -o -empty )")
+            .unwrap()
             .find(1)
             .unwrap();
-        assert_eq!(2, result.len(), "There should be 3 source files matching '.RS' extension.");
+        assert_eq!(vec!["src/lib.rs".to_string()], result);
+        assert!(Finder::from_expression("-name").is_err());
     }
 
     #[test]
+    #[cfg(feature = "regex")]
+    fn content_filters() {
+        let fs = dir! { file!("a.txt" => "hello\nworld\n"), file!("b.txt" => "hello") };
+        let result = Finder::new(".".to_string()).file_system(fs).content_matches("^wor").find(1).unwrap();
+        assert_eq!(vec!["./a.txt"], result);
+        let fs = dir! { file!("a.txt" => "hello\nworld\n"), file!("b.txt" => "hello") };
+        let result = Finder::new(".".to_string()).file_system(fs).line_count_greater_than(1).find(1).unwrap();
+        assert_eq!(vec!["./a.txt"], result);
+    }
+
+    #[test]
+    fn symlinked_files_are_deduplicated() {
+        let fs = dir! {
+            "root" => {
+                file!("a.rs", 100),
+                "sub" => { file!("b.rs" => "fn b() {}"), file!("c.txt") },
+                Entry::symlink("link", "sub"),
+            },
+        };
+        let finder = || Finder::new("root".to_string()).file_system(fs.clone()).has_extension(String::from(".rs"));
+        let mut result = finder().find(2).unwrap();
+        result.sort();
+        assert_eq!(vec!["root/a.rs", "root/link/b.rs"], result);
+        assert_eq!(3, finder().dedup(false).find(2).unwrap().len());
+        assert_eq!(vec!["root/a.rs"], finder().size_greater_than_or_eq(50).find(2).unwrap());
+    }
+
+    #[test]
+    fn overlapping_roots_are_deduplicated() {
+        let both = || finder("src")
+            .add_root("./src".to_string())
+            .has_extension(String::from(".rs"));
+        assert_eq!(4, both().find(1).unwrap().len());
+        assert_eq!(8, both().dedup(false).find(1).unwrap().len());
+        assert!(finder("src").add_root("non_existing_dir".to_string()).find(1).is_err());
+    }
+
+    #[test]
+    fn ignore_files_exclude_files_and_directories() {
+        let fs = dir! {
+            file!(".ffindignore" => "target/\n*.log\n"),
+            file!("main.rs"),
+            file!("build.log"),
+            "target" => { file!("out.rs") },
+            "logs" => {
+                file!(".ffindignore" => "!keep.log\n"),
+                file!("keep.log"),
+                file!("old.log"),
+            },
+        };
+        let global = std::env::temp_dir().join(format!("ffind-ignore-{}", std::process::id()));
+        fs::write(&global, "main.*\n").unwrap();
+        let search = |global_ignore_file| {
+            let mut found = Finder::new(String::from("."))
+                .file_system(fs.clone())
+                .ignore_files(true)
+                .global_ignore_file(global_ignore_file)
+                .find(2)
+                .unwrap();
+            found.sort();
+            found
+        };
+        assert_eq!(vec!["./.ffindignore", "./logs/.ffindignore", "./logs/keep.log", "./main.rs"], search(None));
+        assert_eq!(vec!["./.ffindignore", "./logs/.ffindignore", "./logs/keep.log"], search(Some(global.clone())));
+        fs::remove_file(&global).unwrap();
+        let all = Finder::new(String::from(".")).file_system(fs).find(2).unwrap();
+        assert_eq!(7, all.len());
+    }
+
+    #[test]
+    #[cfg(feature = "regex")]
     fn matches_regex_test() {
-        let result = Finder::new("./".to_string())
+        let result = finder("./")
             .matches_regex(r".*\.rs")
-            .find(1)
+            .find(2)
             .unwrap();
-        assert_eq!(2, result.len());
-        let result = Finder::new("./".to_string())
+        assert_eq!(4, result.len());
+        let result = finder("./")
             .matches_regex(r"^l.*\.rs")
-            .find(1)
+            .find(2)
             .unwrap();
         assert_eq!(1, result.len());
     }
 
+    #[test]
+    #[cfg(feature = "regex")]
+    fn matches_regex_set_test() {
+        let fs = dir! { file!("lib.rs"), file!("main.rs"), file!("lib.md"), file!("notes.txt") };
+        let mut any = Finder::new(String::from(".")).file_system(fs.clone())
+            .matches_any_regex(&[r"^lib", r"\.txt$"])
+            .find(1)
+            .unwrap();
+        any.sort();
+        assert_eq!(vec!["./lib.md", "./lib.rs", "./notes.txt"], any);
+        let all = Finder::new(String::from(".")).file_system(fs)
+            .matches_all_regexes(&[r"^lib", r"\.rs$"])
+            .find(1)
+            .unwrap();
+        assert_eq!(vec!["./lib.rs"], all);
+    }
+
+    #[test]
+    fn fuzzy_names_closest_first() {
+        let fs = dir! { file!("lib.rs"), file!("finder_rs_old.txt"), "src" => { file!("finder.rs"), file!("finderrs") } };
+        let found = Finder::new(String::from(".")).file_system(fs).fuzzy_name("finderrs", 2).find(99).unwrap();
+        assert_eq!(vec!["./src/finderrs", "./src/finder.rs", "./finder_rs_old.txt"], found);
+    }
+
+    #[test]
+    #[cfg(feature = "regex")]
+    fn content_within_scan_budget() {
+        let fs = dir! { file!("a.txt" => "x".repeat(100)), file!("b.txt" => "x".repeat(100)), file!("c.txt" => "x") };
+        let budget = ScanBudget::new(150);
+        let found = Finder::new(String::from(".")).file_system(fs).scan_budget(&budget).content_matches("x").find(99).unwrap();
+        // b.txt spends the budget before it is read to the end.
+        assert_eq!(vec!["./a.txt"], found);
+        assert!(budget.exhausted());
+        assert_eq!(2, budget.unread_files());
+    }
+
+    #[test]
+    #[cfg(feature = "regex")]
+    fn content_of_large_files_is_not_read() {
+        let fs = dir! { file!("core" => "x".repeat(100)), file!("small.txt" => "x") };
+        let finder = || Finder::new(String::from(".")).file_system(fs.clone()).max_file_size(10);
+        assert_eq!(vec!["./small.txt"], finder().content_matches("x").find(99).unwrap());
+        assert_eq!(vec!["./core", "./small.txt"], finder().find(99).unwrap());
+    }
+
+    /// A tree that records the files mapped from it, without mapping them.
+    #[cfg(feature = "regex")]
+    struct Mapped {
+        fs: vfs::MemoryFileSystem,
+        mapped: Arc<std::sync::Mutex<Vec<PathBuf>>>,
+    }
+
+    #[cfg(feature = "regex")]
+    impl FileSystem for Mapped {
+        fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>, Error> {
+            self.fs.read_dir(path)
+        }
+
+        fn metadata(&self, path: &Path, mode: MetadataMode) -> Result<vfs::Metadata, Error> {
+            self.fs.metadata(path, mode)
+        }
+
+        fn open(&self, path: &Path) -> Result<Box<dyn Read + Send>, Error> {
+            self.fs.open(path)
+        }
+
+        fn map(&self, path: &Path) -> Result<Option<mmap::Mmap>, Error> {
+            self.mapped.lock().unwrap().push(path.to_path_buf());
+            Ok(None)
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "regex")]
+    fn large_files_are_mapped_only_if_asked() {
+        let fs = dir! { file!("big.log" => "x".repeat(mmap::MIN_MAP_SIZE as usize)) };
+        let search = |mmap| {
+            let mapped = Arc::new(std::sync::Mutex::new(Vec::new()));
+            let found = Finder::new(String::from("."))
+                .file_system(Mapped { fs: fs.clone(), mapped: mapped.clone() })
+                .mmap(mmap)
+                .content_matches("x")
+                .find(99)
+                .unwrap();
+            assert_eq!(vec!["./big.log"], found);
+            let mapped = mapped.lock().unwrap().clone();
+            mapped
+        };
+        assert!(search(false).is_empty());
+        assert_eq!(vec![PathBuf::from("./big.log")], search(true));
+    }
+
+    #[test]
+    #[cfg(feature = "regex")]
+    fn match_counts() {
+        let fs = dir! { file!("a.log" => "ERROR\nok\nERROR ERROR\n"), file!("b.log" => "ok\n"), file!("c.log" => "ERROR\n") };
+        let finder = || Finder::new(String::from(".")).file_system(fs.clone());
+        let counts = finder().content_matches("ERROR").find_counting(99, "ERROR", false).unwrap();
+        assert_eq!(vec![(String::from("./a.log"), 3), (String::from("./c.log"), 1)], counts);
+        assert_eq!(3, finder().find_counting(99, "ERROR", false).unwrap().len());
+    }
+
+    #[test]
+    fn snapshots() {
+        let fs = dir! { "src" => { file!("lib.rs" => "abc"), "bin" => { file!("main.rs") } } };
+        let manifest = Finder::new(String::from("src")).file_system(fs.clone()).snapshot(99, true).unwrap();
+        assert_eq!(vec!["bin/main.rs", "lib.rs"], manifest.files.keys().collect::<Vec<_>>());
+        let lib = &manifest.files["lib.rs"];
+        assert_eq!(3, lib.size);
+        assert_eq!(Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"), lib.hash.as_deref());
+        let manifest = Finder::new(String::from("src")).file_system(fs).hash_algo(HashAlgo::Xxh3).snapshot(99, true).unwrap();
+        assert_eq!(HashAlgo::Xxh3, manifest.hash_algo);
+        assert_eq!(Some("78af5f94892f3950"), manifest.files["lib.rs"].hash.as_deref());
+    }
+
+    #[test]
+    fn pruned_directories_are_not_walked() {
+        let fs = dir! {
+            file!("a.rs"),
+            "build" => { file!(".skipme"), file!("out.rs"), "deps" => { file!("dep.rs") } },
+            "src" => { file!("lib.rs") },
+        };
+        let marked = fs.clone();
+        let found = Finder::new(String::from(".")).file_system(fs.clone())
+            .include_dirs(true)
+            .prune_if(move |dir| marked.metadata(&dir.join(".skipme"), MetadataMode::NoFollow).is_ok())
+            .find(99)
+            .unwrap();
+        assert_eq!(vec![".", "./a.rs", "./build", "./src", "./src/lib.rs"], found);
+        let found = Finder::new(String::from("src")).file_system(fs).prune_if(|_| true).find(99).unwrap();
+        assert!(found.is_empty());
+    }
+
+    #[test]
+    fn tagged_caches_are_not_walked() {
+        let fs = dir! {
+            "target" => { file!("CACHEDIR.TAG" => "Signature: 8a477f597d28d172789f06886806bc55\n# cargo\n"), file!("out.rs") },
+            "notes" => { file!("CACHEDIR.TAG" => "not a cache\n"), file!("a.rs") },
+            "old" => { file!(".nobackup"), file!("b.rs") },
+        };
+        let found = Finder::new(String::from(".")).file_system(fs.clone())
+            .prune_if_contains(walk::CACHEDIR_TAG)
+            .prune_if_contains(".nobackup")
+            .matches_glob("*.rs")
+            .find(99)
+            .unwrap();
+        assert_eq!(vec!["./notes/a.rs"], found);
+        // As does a spec.
+        let mut spec = SearchSpec::new(String::from("."), 99);
+        spec.prune_if_contains.push(String::from(".nobackup"));
+        let mut found = Finder::from_spec(&spec).unwrap().file_system(fs.clone()).find(99).unwrap();
+        found.sort();
+        assert_eq!(vec!["./notes/CACHEDIR.TAG", "./notes/a.rs", "./target/CACHEDIR.TAG", "./target/out.rs"], found);
+        spec.max_entries_per_dir = Some(2);
+        assert!(Finder::from_spec(&spec).unwrap().file_system(fs).find(99).unwrap().is_empty());
+    }
+
+    #[test]
+    fn visits_matches_until_told_to_stop() {
+        let fs = dir! { file!("a.rs"), "b" => { file!("c.rs"), file!("d.txt") }, file!("e.rs") };
+        let finder = || Finder::new(String::from(".")).file_system(fs.clone()).has_extension(String::from("rs"));
+        let mut visited = Vec::new();
+        finder().for_each(99, |file| {
+            visited.push(file.path_str().to_string());
+            ControlFlow::Continue(())
+        }).unwrap();
+        assert_eq!(finder().find(99).unwrap(), visited);
+        let mut first = None;
+        finder().traversal(Traversal::DepthFirst).for_each(99, |file| {
+            first = Some(file.path_str().to_string());
+            ControlFlow::Break(())
+        }).unwrap();
+        assert_eq!(Some(String::from("./a.rs")), first);
+    }
+
+    #[test]
+    fn matches_know_where_they_were_found() {
+        let fs = dir! { "src" => { file!("lib.rs"), "walk" => { file!("mod.rs") } }, "tests" => { file!("cli.rs") } };
+        for traversal in [Traversal::BreadthFirst, Traversal::DepthFirst] {
+            let mut found = Vec::new();
+            Finder::new(String::from("src")).add_root(String::from("tests")).file_system(fs.clone())
+                .traversal(traversal)
+                .include_dirs(true)
+                .for_each(99, |file| {
+                    found.push((file.relative_path().to_string_lossy().into_owned(), file.depth()));
+                    ControlFlow::Continue(())
+                })
+                .unwrap();
+            found.sort();
+            let expected = vec![("", 0), ("", 0), ("cli.rs", 1), ("lib.rs", 1), ("walk", 1), ("walk/mod.rs", 2)];
+            assert_eq!(expected.into_iter().map(|(path, depth)| (path.to_string(), depth)).collect::<Vec<_>>(), found);
+        }
+    }
+
+    #[test]
+    fn events_follow_the_tree() {
+        let fs = dir! { file!("a.rs"), "b" => { file!("c.rs"), "d" => { file!("e.txt") } }, file!("f.rs") };
+        let mut events = Vec::new();
+        Finder::new(String::from(".")).file_system(fs).has_extension(String::from("rs")).for_each_event(99, |event| {
+            events.push(match event {
+                WalkEvent::EnterDir(dir) => format!("enter {}", dir.display()),
+                WalkEvent::LeaveDir(dir) => format!("leave {}", dir.display()),
+                WalkEvent::File(file) => file.path_str().to_string(),
+            });
+            ControlFlow::Continue(())
+        }).unwrap();
+        assert_eq!(vec!["enter .", "./a.rs", "enter ./b", "./b/c.rs", "enter ./b/d", "leave ./b/d", "leave ./b", "./f.rs", "leave ."], events);
+    }
+
+    #[test]
+    fn finders_move_to_other_threads() {
+        fn is_send_and_sync<T: Send + Sync>(_: &T) {}
+        let fs = dir! { "src" => { file!("lib.rs"), file!("main.rs") } };
+        let finder = Finder::new(String::from("src")).file_system(fs).filter(|path| path.ends_with("lib.rs"));
+        is_send_and_sync(&finder);
+        let found = std::thread::spawn(move || finder.find(99).unwrap()).join().unwrap();
+        assert_eq!(vec!["src/lib.rs"], found);
+    }
+
+    #[test]
+    fn clones_specialize_a_base_finder() {
+        let fs = dir! { "src" => { file!("lib.rs"), file!("main.rs"), file!("notes.txt") }, "target" => { file!("out.rs") } };
+        let base = Finder::new(String::from(".")).file_system(fs).filter(|path| !path.starts_with("./target"));
+        let mut rust = base.clone().has_extension(String::from("rs")).find(99).unwrap();
+        rust.sort();
+        assert_eq!(vec!["./src/lib.rs", "./src/main.rs"], rust);
+        assert_eq!(vec!["./src/notes.txt"], base.clone().has_extension(String::from("txt")).find(99).unwrap());
+        assert_eq!(3, base.find(99).unwrap().len());
+    }
+
+    #[test]
+    fn validates_before_walking() {
+        let fs = dir! { "src" => { file!("lib.rs") } };
+        let finder = || Finder::new(String::from("src")).file_system(fs.clone());
+        assert_eq!(Ok(()), finder().matches_glob("*.rs").validate(99));
+        #[cfg(feature = "regex")]
+        assert!(matches!(finder().matches_regex("(").validate(99), Err(BuildError::InvalidRegex { .. })));
+        assert!(matches!(finder().matches_glob("[a").validate(99), Err(BuildError::InvalidGlob { .. })));
+        assert_eq!(Err(BuildError::DepthRange { min: 3, max: 2 }), finder().min_depth(3).validate(2));
+        assert_eq!(Err(BuildError::MissingRoot(String::from("tests"))), finder().add_root(String::from("tests")).validate(99));
+        #[cfg(feature = "regex")]
+        assert_eq!(io::ErrorKind::InvalidInput, finder().content_matches("[").find(99).unwrap_err().kind());
+    }
+
+    #[test]
+    fn cleanup_reports() {
+        let fs = dir! { "logs" => { file!("a.log" => "abc"), file!("b.log" => "abcdef"), file!("notes.txt" => "a") } };
+        let report = Finder::new(String::from("logs")).file_system(fs).has_extension(String::from("log")).cleanup_report(99).unwrap();
+        let rows: Vec<(&str, u64)> = report.rows.iter().map(|row| (row.path.as_str(), row.allocated)).collect();
+        assert_eq!(vec![("logs/b.log", 6), ("logs/a.log", 3)], rows);
+        assert_eq!((9, 10), (report.total(), report.scanned));
+    }
+
+    #[test]
+    fn full_path_matching() {
+        let fs = dir! { "src" => { file!("lib.rs"), "bin" => { file!("main.rs") } }, "tests" => { file!("src_test.rs") } };
+        let search = |finder: Finder| {
+            let mut found = finder.file_system(fs.clone()).find(99).unwrap();
+            found.sort();
+            found
+        };
+        let finder = || Finder::new(String::from("."));
+        #[cfg(feature = "regex")]
+        assert_eq!(vec!["./tests/src_test.rs"], search(finder().matches_regex("^src")));
+        #[cfg(feature = "regex")]
+        assert_eq!(vec!["./src/bin/main.rs", "./src/lib.rs"], search(finder().matches_regex_full_path(r"^\./src/")));
+        #[cfg(feature = "regex")]
+        assert_eq!(vec!["./src/bin/main.rs", "./src/lib.rs", "./tests/src_test.rs"], search(finder().matches_regex_full_path("src")));
+        assert_eq!(vec!["./src/lib.rs"], search(finder().matches_glob("l*.rs")));
+        assert_eq!(vec!["./src/bin/main.rs"], search(finder().matches_glob_full_path("*/bin/*")));
+    }
+
 }
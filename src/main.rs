@@ -13,7 +13,7 @@ FLAGS:
     -V, --version    Prints version information
 
 OPTIONS:
-    -d, --depth <DEPTH>                Configures the max depth this recursive search will explore [default: 99999]
+    -d, --depth <DEPTH>                Configures the max depth this recursive search will explore [default: no limit]
     -e, --extension <EXT>              Looks for files that have this file extension
     -p, --pattern <REGEX>              Looks for files that contain this REGEX
     -g, --size-greater-than <BYTES>    filters files where file size is not >= BYTES
@@ -25,147 +25,2291 @@ ARGS:
  */
 
 use rustfind::Finder;
-use clap::{Arg, App};
-use std::path::PathBuf;
+use rustfind::audit::{AuditLog, Mutation};
+use rustfind::baseline::{Baseline, BaselineUpdate};
+use rustfind::budget::{self, ScanBudget};
+use rustfind::cache;
+use rustfind::confirm::{Confirmations, Prompt, Proposal};
+use rustfind::content::LineCount;
+use rustfind::digest::HashAlgo;
+use rustfind::exclude::Excludes;
+use rustfind::expr;
+use rustfind::git::GitState;
+use rustfind::group::{Per, UniqueBy};
+use rustfind::histogram::HistogramBy;
+use rustfind::fields::{Field, Fields};
+use rustfind::manifest::Manifest;
+use rustfind::metrics::{self, Metrics};
+use rustfind::filter::{FileInfo, FilterStats, MetadataMode};
+use rustfind::output::{self, AtomicFile, Format};
+use rustfind::sort::SortKey;
+use rustfind::spec::{ActionSpec, FilterSpec, SearchSpec};
+use rustfind::sync::SyncPlan;
+use rustfind::trace::{self, Level, StderrSubscriber};
+use rustfind::rename::{self, Destination, Journal};
+use rustfind::replace::Replace;
+use rustfind::rpc::Server;
+use rustfind::sandbox::Sandbox;
+use rustfind::retention;
+use rustfind::retry::Retries;
+use rustfind::throttle;
+use rustfind::timings::Timings;
+use rustfind::walk::{SkippedDirs, Traversal, TraversalTuning};
+use clap::{Arg, App, AppSettings, ArgMatches, SubCommand};
+use serde_json::{json, Value};
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Error, Write};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[cfg(all(feature = "acl", target_os = "linux"))]
+use rustfind::acl;
+#[cfg(target_os = "linux")]
+use rustfind::chattr;
+#[cfg(unix)]
+use rustfind::daemon::{self, Client, Daemon};
+#[cfg(any(target_os = "linux", windows))]
+use rustfind::inuse::OpenFiles;
+#[cfg(feature = "grpc")]
+use rustfind::grpc::GrpcServer;
+#[cfg(feature = "remote")]
+use rustfind::remote::{self, RemoteFs};
+#[cfg(feature = "s3")]
+use rustfind::s3::{self, S3Fs};
+
+/// How often --checkpoint saves the state of a search.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How many of the slowest directories --profile reports.
+const HOT_SPOTS: usize = 10;
+
+/// The values of --backend in this build.
+const BACKENDS: &[&str] = &[
+    "std",
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    "uring",
+    #[cfg(windows)]
+    "mft",
+    #[cfg(target_os = "macos")]
+    "spotlight",
+];
+
+/// The arguments describing a search, shared by the top level command and the
+/// `query` subcommand.
+fn search_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    let mut args = vec![
+        Arg::with_name("PATH")
+            .help("Initial location to begin the search. More paths may follow, before the expression")
+            .required_unless_one(&["explain", "spec"])
+            .index(1),
+        Arg::with_name("EXPRESSION")
+            .help("A classic find expression, e.g. -name '*.log' -a ( -size +1M -o -mtime -7 ). Must come after all options")
+            .multiple(true)
+            .allow_hyphen_values(true)
+            .index(2),
+        Arg::with_name("size-less-than")
+            .short("l")
+            .long("size-less-than")
+            .takes_value(true)
+            .value_name("BYTES")
+            .multiple(false)
+            .help("filters files where file size is not <= BYTES"),
+        Arg::with_name("size-greater-than")
+            .short("g")
+            .long("size-greater-than")
+            .takes_value(true)
+            .value_name("BYTES")
+            .multiple(false)
+            .help("filters files where file size is not >= BYTES"),
+        Arg::with_name("depth")
+            .short("d")
+            .long("depth")
+            .visible_alias("max-depth")
+            .takes_value(true)
+            .value_name("DEPTH")
+            .multiple(false)
+            .help("Configures the max depth this recursive search will explore, like find's -maxdepth: 0 is PATH itself, 1 its entries [default: no limit]"),
+        Arg::with_name("exact-depth")
+            .long("exact-depth")
+            .takes_value(true)
+            .value_name("DEPTH")
+            .multiple(false)
+            .conflicts_with("depth")
+            .help("Only looks for files exactly DEPTH levels below PATH"),
+        Arg::with_name("pattern")
+            .short("p")
+            .long("pattern")
+            .takes_value(true)
+            .value_name("REGEX")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Looks for files that contain this REGEX. Repeat to look for files that contain any of them"),
+        Arg::with_name("all-patterns")
+            .long("all-patterns")
+            .requires("pattern")
+            .help("Looks for files that contain all of the --pattern REGEXes instead of any"),
+        Arg::with_name("prefix")
+            .long("prefix")
+            .takes_value(true)
+            .value_name("TEXT")
+            .multiple(false)
+            .help("Looks for files whose name starts with TEXT"),
+        Arg::with_name("suffix")
+            .long("suffix")
+            .takes_value(true)
+            .value_name("TEXT")
+            .multiple(false)
+            .help("Looks for files whose name ends with TEXT, which unlike --extension can't span a directory"),
+        Arg::with_name("in-dir")
+            .long("in-dir")
+            .takes_value(true)
+            .value_name("GLOB")
+            .multiple(false)
+            .help("Looks for files below a directory named like GLOB, at any depth, like --in-dir tests"),
+        Arg::with_name("older-than-sibling")
+            .long("older-than-sibling")
+            .takes_value(true)
+            .value_name("NAME")
+            .multiple(false)
+            .help("Looks for files modified before the file NAME in the same directory, like generated files older than their source"),
+        Arg::with_name("older-than")
+            .long("older-than")
+            .takes_value(true)
+            .value_name("AGE")
+            .multiple(false)
+            .help("Looks for files last modified more than AGE ago, in seconds (s), minutes (m), hours (h), days (d), weeks (w) or years (y), like 365d"),
+        Arg::with_name("glob")
+            .long("glob")
+            .takes_value(true)
+            .value_name("GLOB")
+            .multiple(false)
+            .help("Looks for files whose name matches GLOB, like '*.rs' or 'test_?.{c,h}'"),
+        Arg::with_name("full-path")
+            .long("full-path")
+            .help("Matches --pattern and --glob against the whole path of files, like ./src/lib.rs, instead of their name"),
+        Arg::with_name("fuzzy")
+            .long("fuzzy")
+            .takes_value(true)
+            .value_name("QUERY")
+            .multiple(false)
+            .help("Looks for files whose name roughly matches QUERY, like finderrs for finder.rs, and lists the closest first"),
+        Arg::with_name("fuzzy-distance")
+            .long("fuzzy-distance")
+            .takes_value(true)
+            .value_name("EDITS")
+            .multiple(false)
+            .requires("fuzzy")
+            .help("How many typos --fuzzy tolerates [default: 2]"),
+        Arg::with_name("sort")
+            .long("sort")
+            .takes_value(true)
+            .value_name("KEY")
+            .multiple(false)
+            .help("Lists the matches ordered by path, name, name:natural, which orders the numbers in names by value, like file2.log before file10.log, or name:locale, as the locale does (if built with the collation feature)"),
+        Arg::with_name("latest-per")
+            .long("latest-per")
+            .takes_value(true)
+            .value_name("GROUP")
+            .possible_values(&["ext", "dir"])
+            .multiple(false)
+            .conflicts_with_all(&["strip-prefix", "show-inode"])
+            .help("Lists only the most recently modified match of every extension or directory, like the latest backup in each of many directories"),
+        Arg::with_name("extension")
+            .short("e")
+            .long("extension")
+            .takes_value(true)
+            .value_name("EXT")
+            .multiple(false)
+            .help("Looks for files that have this file extension"),
+        Arg::with_name("contains")
+            .short("c")
+            .long("contains")
+            .takes_value(true)
+            .value_name("REGEX")
+            .multiple(false)
+            .help("Looks for files with a line matching REGEX in their contents"),
+        Arg::with_name("search-compressed")
+            .long("search-compressed")
+            .requires("contains")
+            .help("Decompresses .gz, .xz and .zst files before matching --contains, like zgrep"),
+        Arg::with_name("count-matches")
+            .long("count-matches")
+            .requires("contains")
+            .help("Prints how many times --contains occurs in every match, as PATH:COUNT"),
+        Arg::with_name("cleanup-report")
+            .long("cleanup-report")
+            .conflicts_with_all(&["count-matches", "replace", "json", "output"])
+            .help("Lists the matches by the space they take on disk, largest first, with the running total and its share of all the files searched"),
+        Arg::with_name("usage-by-owner")
+            .long("usage-by-owner")
+            .conflicts_with_all(&["cleanup-report", "changed-owner-since", "group-by", "unique-by", "count-matches", "replace", "rename", "move-to",
+                                  "strip-prefix", "show-inode", "json", "output"])
+            .help("Lists the users owning matches by the space their matches take on disk, largest first, with how many there are"),
+        Arg::with_name("retention-report")
+            .long("retention-report")
+            .conflicts_with_all(&["cleanup-report", "usage-by-owner", "changed-owner-since", "group-by", "unique-by", "count-matches", "replace", "rename",
+                                  "move-to", "strip-prefix", "show-inode", "json", "output"])
+            .help("Lists the matches by the month they were last modified in, oldest first, with the space deleting them and the older ones would free, like with --older-than 365d"),
+        Arg::with_name("histogram")
+            .long("histogram")
+            .takes_value(true)
+            .value_name("KEY")
+            .possible_values(&["size", "age"])
+            .multiple(false)
+            .conflicts_with_all(&["cleanup-report", "usage-by-owner", "retention-report", "changed-owner-since", "group-by", "unique-by", "count-matches",
+                                  "replace", "rename", "move-to", "strip-prefix", "show-inode"])
+            .help("Counts the matches, and adds up their size, per bucket of size (<1K, 1K-1M, 1M-100M, 100M+) or month last modified"),
+        Arg::with_name("fail-if-more-than")
+            .long("fail-if-more-than")
+            .takes_value(true)
+            .value_name("N")
+            .multiple(false)
+            .help("Exits with 1 if more than N matches are reported, like for a CI check. A file found through several paths counts once, unless --no-dedup is given"),
+        Arg::with_name("fail-if-any")
+            .long("fail-if-any")
+            .conflicts_with("fail-if-more-than")
+            .help("Exits with 1 if any match is reported, like --fail-if-more-than 0"),
+        Arg::with_name("baseline")
+            .long("baseline")
+            .takes_value(true)
+            .value_name("FILE")
+            .multiple(false)
+            .help("Leaves out the matches listed in FILE, one path relative to PATH per line, to only report new ones"),
+        Arg::with_name("update-baseline")
+            .long("update-baseline")
+            .requires("baseline")
+            .help("Writes all the matches to the --baseline FILE once the search is done, new or not"),
+        Arg::with_name("changed-owner-since")
+            .long("changed-owner-since")
+            .takes_value(true)
+            .value_name("MANIFEST")
+            .multiple(false)
+            .conflicts_with_all(&["cleanup-report", "count-matches", "replace", "rename", "move-to", "json", "output"])
+            .help("Lists the matches whose owner isn't the one recorded by snapshot in MANIFEST, as PATH: UID:GID -> UID:GID. Exits with 1 if there are any"),
+        Arg::with_name("group-by")
+            .long("group-by")
+            .takes_value(true)
+            .value_name("KEY")
+            .possible_values(&["dir"])
+            .multiple(false)
+            .conflicts_with_all(&["cleanup-report", "count-matches", "replace", "json", "output"])
+            .help("Prints every directory with matches once, with their count, and the matches in it indented below"),
+        Arg::with_name("unique-by")
+            .long("unique-by")
+            .takes_value(true)
+            .value_name("KEY")
+            .possible_values(&["name", "stem", "hash"])
+            .multiple(false)
+            .conflicts_with_all(&["cleanup-report", "changed-owner-since", "group-by", "count-matches", "replace", "rename", "move-to", "json", "output"])
+            .help("Lists only the first match of those sharing a name, a stem or contents"),
+        Arg::with_name("count-copies")
+            .long("count-copies")
+            .requires("unique-by")
+            .help("Prints how many matches share the key of --unique-by before every match, like uniq -c"),
+        Arg::with_name("hash-algo")
+            .long("hash-algo")
+            .takes_value(true)
+            .value_name("ALGO")
+            .possible_values(&["sha256", "blake3", "xxh3"])
+            .multiple(false)
+            .help("What --unique-by hash and snapshots hash contents with: sha256, blake3, as strong but faster and on all CPUs for large files, or xxh3, fastest but not cryptographic [default: sha256]"),
+        Arg::with_name("replace")
+            .long("replace")
+            .takes_value(true)
+            .value_name("OLD=>NEW")
+            .multiple(false)
+            .help("Shows the diff of replacing the REGEX OLD with NEW, which can use groups like $1, in every matching text file"),
+        Arg::with_name("rename")
+            .long("rename")
+            .takes_value(true)
+            .value_name("OLD=>NEW")
+            .multiple(false)
+            .conflicts_with_all(&["replace", "cleanup-report", "group-by", "count-matches", "json", "output"])
+            .help("Shows how replacing the REGEX OLD with NEW, which can use groups like $1, renames every match, without moving it to another directory"),
+        Arg::with_name("move-to")
+            .long("move-to")
+            .takes_value(true)
+            .value_name("DIR")
+            .multiple(false)
+            .conflicts_with_all(&["rename", "replace", "cleanup-report", "group-by", "count-matches", "json", "output"])
+            .help("Shows how every match would be moved into DIR, keeping its name"),
+        Arg::with_name("apply")
+            .long("apply")
+            .help("Writes the files changed by --replace, or renames and moves them with --rename and --move-to, instead of only showing what would change"),
+        Arg::with_name("backup-suffix")
+            .long("backup-suffix")
+            .takes_value(true)
+            .value_name("SUFFIX")
+            .multiple(false)
+            .requires("apply")
+            .help("Keeps the originals of the files changed by --replace --apply as their name plus SUFFIX, like .bak"),
+        Arg::with_name("audit-log")
+            .long("audit-log")
+            .takes_value(true)
+            .value_name("FILE")
+            .multiple(false)
+            .requires("apply")
+            .help("Appends a JSON line to FILE for every file changed, renamed or moved with --apply, with its size, modification time, permissions and hash before and after"),
+        Arg::with_name("interactive")
+            .short("i")
+            .long("interactive")
+            .requires("apply")
+            .help("Asks on the terminal before changing, renaming or moving every file with --apply: y to go ahead, n to skip it, a for all the rest, q to stop"),
+        Arg::with_name("journal")
+            .long("journal")
+            .takes_value(true)
+            .value_name("FILE")
+            .multiple(false)
+            .requires("apply")
+            .help("Where --rename and --move-to record what they move, to undo it with 'undo FILE' [default: a new file in $XDG_STATE_HOME/ffind/undo]"),
+        Arg::with_name("lines")
+            .long("lines")
+            .takes_value(true)
+            .value_name("[+-]N")
+            .allow_hyphen_values(true)
+            .multiple(false)
+            .help("Looks for text files with more than (+N), less than (-N) or exactly N lines"),
+        Arg::with_name("valid-utf8")
+            .long("valid-utf8")
+            .conflicts_with("invalid-utf8")
+            .help("Looks for files whose contents are valid UTF-8"),
+        Arg::with_name("invalid-utf8")
+            .long("invalid-utf8")
+            .help("Looks for files whose contents are not valid UTF-8"),
+        Arg::with_name("utf8-sample")
+            .long("utf8-sample")
+            .takes_value(true)
+            .value_name("BYTES")
+            .multiple(false)
+            .help("Only checks the first BYTES of each file for --valid-utf8 and --invalid-utf8"),
+        Arg::with_name("git-tracked")
+            .long("git-tracked")
+            .conflicts_with("git-untracked")
+            .help("Looks for files tracked by git, in the repository containing PATH"),
+        Arg::with_name("git-untracked")
+            .long("git-untracked")
+            .conflicts_with("git-modified")
+            .help("Looks for files neither tracked nor ignored by git, in the repository containing PATH"),
+        Arg::with_name("git-modified")
+            .long("git-modified")
+            .help("Looks for tracked files with changes, staged or not, in the repository containing PATH"),
+        Arg::with_name("explain")
+            .long("explain")
+            .takes_value(true)
+            .value_name("FILE")
+            .multiple(false)
+            .help("Shows which of the given filters accept or reject FILE instead of searching"),
+        Arg::with_name("explain-plan")
+            .long("explain-plan")
+            .conflicts_with("explain")
+            .help("Shows what the search would walk and check, and which directories it stays out of, instead of searching"),
+        Arg::with_name("filter-stats")
+            .long("filter-stats")
+            .help("Reports how many files each filter rejected and the time spent in it"),
+        Arg::with_name("no-dedup")
+            .long("no-dedup")
+            .help("Reports files found more than once (through several paths or symlinks) every time"),
+        Arg::with_name("depth-first")
+            .long("depth-first")
+            .help("Searches every directory entirely before the next one, reading huge directories without holding their entries in memory"),
+        Arg::with_name("max-queued")
+            .long("max-queued")
+            .takes_value(true)
+            .value_name("ENTRIES")
+            .multiple(false)
+            .help("Queues at most ENTRIES entries at once, searching depth first below the directories that don't fit"),
+        Arg::with_name("readdir-batch")
+            .long("readdir-batch")
+            .takes_value(true)
+            .value_name("ENTRIES")
+            .multiple(false)
+            .conflicts_with("depth-first")
+            .help("Reads ENTRIES entries of a directory before looking them up with --stat-threads [default: 256]"),
+        Arg::with_name("queue-capacity")
+            .long("queue-capacity")
+            .takes_value(true)
+            .value_name("ENTRIES")
+            .multiple(false)
+            .conflicts_with("depth-first")
+            .help("Makes room for ENTRIES queued entries from the start [default: 1024]"),
+        Arg::with_name("stat-threads")
+            .long("stat-threads")
+            .alias("threads")
+            .takes_value(true)
+            .value_name("THREADS")
+            .multiple(false)
+            .conflicts_with("depth-first")
+            .help("Looks up the entries of a directory on THREADS threads at once, which speeds up network filesystems, or with auto on as many as the storage keeps busy [default: 1]"),
+        Arg::with_name("json")
+            .long("json")
+            .conflicts_with("output")
+            .help("Prints every match as a JSON object on its own line, with its count of matches with --count-matches. Same as --output json"),
+        Arg::with_name("output")
+            .long("output")
+            .takes_value(true)
+            .value_name("FORMAT")
+            .possible_values(&["json", "csv", "tsv"])
+            .multiple(false)
+            .help("Prints the matches as JSON objects, one per line, or as the rows of a CSV or TSV table"),
+        Arg::with_name("out")
+            .short("o")
+            .long("out")
+            .takes_value(true)
+            .value_name("FILE")
+            .multiple(false)
+            .help("Writes the results to FILE instead of stdout, replacing it only once they are complete"),
+        Arg::with_name("append")
+            .long("append")
+            .requires("out")
+            .help("Adds the results to the end of the --out FILE instead of replacing its contents"),
+        Arg::with_name("fields")
+            .long("fields")
+            .takes_value(true)
+            .value_name("FIELDS")
+            .multiple(false)
+            .conflicts_with("count-matches")
+            .help("What --json or --output prints about every match, out of path, size, mtime, perm, hash (SHA-256), dev, inode and fstype, like path,size [default: path]"),
+        Arg::with_name("max-entries-per-dir")
+            .long("max-entries-per-dir")
+            .takes_value(true)
+            .value_name("N")
+            .multiple(false)
+            .help("Skips the directories with more than N entries, like huge caches or maildirs, and lists them at the end"),
+        Arg::with_name("prune-if-contains")
+            .long("prune-if-contains")
+            .takes_value(true)
+            .value_name("FILE")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Doesn't walk into the directories that contain FILE, like CACHEDIR.TAG for the caches of cargo, pip and others. Repeat for more"),
+        Arg::with_name("exclude-fs-type")
+            .long("exclude-fs-type")
+            .takes_value(true)
+            .value_name("TYPES")
+            .multiple(true)
+            .number_of_values(1)
+            .use_delimiter(true)
+            .help("Doesn't walk into the directories on filesystems of the given comma separated TYPES, like nfs,cifs, as -v shows them for PATH"),
+        Arg::with_name("skip-snapshots")
+            .long("skip-snapshots")
+            .help("Doesn't walk into the snapshots of ZFS (.zfs/snapshot), btrfs and NetApp filers (.snapshot)"),
+        Arg::with_name("profile")
+            .long("profile")
+            .takes_value(true)
+            .value_name("NAME")
+            .multiple(false)
+            .help("Doesn't walk into the directories the profile NAME leaves out: dev for .git, target, node_modules, __pycache__, .venv and other tool output, as changed in ~/.config/ffind/profiles/NAME. Without NAME, followed by another option or nothing, reports the time spent listing directories, looking up entries, filtering them and writing matches, and the directories it was spent in"),
+        // What a --profile without NAME becomes, see `profile_without_name()`.
+        Arg::with_name("timings")
+            .long("timings")
+            .hidden(true),
+        Arg::with_name("scan-budget")
+            .long("scan-budget")
+            .takes_value(true)
+            .value_name("BYTES")
+            .multiple(false)
+            .help("Stops reading contents for --contains and other content filters after BYTES, like 10G, and finishes the search on names"),
+        Arg::with_name("max-filesize")
+            .long("max-filesize")
+            .takes_value(true)
+            .value_name("BYTES")
+            .multiple(false)
+            .help("Doesn't search the contents of files larger than BYTES, like 50M, with --contains and other content filters"),
+        Arg::with_name("mmap")
+            .long("mmap")
+            .help("Maps files of 1 MiB or more into memory to search their contents faster; a file truncated meanwhile kills the search"),
+        Arg::with_name("retries")
+            .long("retries")
+            .takes_value(true)
+            .value_name("N")
+            .multiple(false)
+            .help("Retries reading a directory up to N times on transient errors, like network filesystem timeouts"),
+        Arg::with_name("throttle")
+            .long("throttle")
+            .takes_value(true)
+            .value_name("MBps")
+            .multiple(false)
+            .help("Reads at most MBps megabytes per second, counting directory listings and file contents"),
+        Arg::with_name("idle")
+            .long("idle")
+            .help("Only uses the CPU and the disk when nothing else does (with renice and ionice)"),
+        Arg::with_name("checkpoint")
+            .long("checkpoint")
+            .takes_value(true)
+            .value_name("FILE")
+            .multiple(false)
+            .conflicts_with("depth-first")
+            .help("Saves the state of the search to FILE every 30 seconds, so it can be resumed if interrupted"),
+        Arg::with_name("resume")
+            .long("resume")
+            .takes_value(true)
+            .value_name("FILE")
+            .multiple(false)
+            .conflicts_with("depth-first")
+            .help("Continues the interrupted search saved in FILE by --checkpoint, given the same PATH and options"),
+        Arg::with_name("cache")
+            .long("cache")
+            .conflicts_with_all(&["depth-first", "max-queued", "checkpoint", "resume"])
+            .help("Saves the matches in every directory to ~/.cache/ffind/results, answering the same search from there for the directories that didn't change"),
+        Arg::with_name("backend")
+            .long("backend")
+            .takes_value(true)
+            .value_name("NAME")
+            .multiple(false)
+            .possible_values(BACKENDS)
+            .default_value("std")
+            .help("How the tree is read: std (the default), uring for io_uring (experimental, Linux), \
+                   mft for the NTFS Master File Table (Windows, as administrator) or spotlight for the Spotlight \
+                   index (macOS)"),
+        Arg::with_name("include-dirs")
+            .long("include-dirs")
+            .help("Also reports the directories matching the filters, PATH included"),
+        Arg::with_name("strip-prefix")
+            .long("strip-prefix")
+            .conflicts_with_all(&["sort", "fuzzy", "cleanup-report", "group-by", "count-matches", "replace", "json", "output"])
+            .help("Prints the matches relative to the PATH they were found in, like src/lib.rs for /home/ana/app/src/lib.rs below /home/ana/app"),
+        Arg::with_name("show-inode")
+            .long("show-inode")
+            .conflicts_with_all(&["sort", "fuzzy", "cleanup-report", "group-by", "count-matches", "replace", "json", "output"])
+            .help("Prints the device and inode numbers (the volume serial number and file index on Windows) of every match before it, as DEV:INODE"),
+        Arg::with_name("no-ignore")
+            .long("no-ignore")
+            .help("Doesn't skip what .ffindignore files and ~/.config/ffind/ignore exclude"),
+        Arg::with_name("no-follow")
+            .long("no-follow")
+            .help("Size and time filters look at symlinks themselves rather than the files they point to"),
+        verbose_arg(),
+        Arg::with_name("spec")
+            .long("spec")
+            .takes_value(true)
+            .value_name("FILE")
+            .multiple(false)
+            .help("Loads a search from a JSON spec FILE. Other options add to or override it"),
+        Arg::with_name("save-spec")
+            .long("save-spec")
+            .takes_value(true)
+            .value_name("FILE")
+            .multiple(false)
+            .help("Saves the search as a JSON spec to FILE instead of running it"),
+    ];
+    args.extend(acl_args());
+    args.extend(linux_args());
+    args.extend(in_use_args());
+    args
+}
+
+#[cfg(target_os = "linux")]
+fn linux_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("has-capability")
+            .long("has-capability")
+            .help("Looks for files with capabilities set, like cap_net_raw on ping, which grant privileges like setuid does"),
+        Arg::with_name("attr-immutable")
+            .long("attr-immutable")
+            .help("Looks for files and directories made immutable with chattr +i, which can't be changed, renamed or deleted even by root"),
+        Arg::with_name("attr-append-only")
+            .long("attr-append-only")
+            .help("Looks for files and directories made append-only with chattr +a"),
+        Arg::with_name("dedup-mounts")
+            .long("dedup-mounts")
+            .help("Walks the trees mounted at several paths, like bind mounts, only once, so that they aren't counted twice"),
+    ]
+}
+
+#[cfg(not(target_os = "linux"))]
+fn linux_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    Vec::new()
+}
+
+#[cfg(any(target_os = "linux", windows))]
+fn in_use_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("in-use")
+            .long("in-use")
+            .help("Looks for files held open by a process, which deleting wouldn't free. On Linux, only the processes of the same user are seen unless run as root"),
+        Arg::with_name("show-pids")
+            .long("show-pids")
+            .requires("in-use")
+            .conflicts_with_all(&["sort", "fuzzy", "cleanup-report", "group-by", "count-matches", "replace", "json", "output"])
+            .help("Prints the processes holding every match open after it, on Linux"),
+    ]
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+fn in_use_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    Vec::new()
+}
+
+#[cfg(all(feature = "acl", target_os = "linux"))]
+fn acl_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("has-acl")
+            .long("has-acl")
+            .help("Looks for files with an ACL granting more than their permissions, and directories with a default ACL, like the + of ls -l"),
+        Arg::with_name("acl-grants")
+            .long("acl-grants")
+            .takes_value(true)
+            .value_name("WHO:NAME:PERMS")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Looks for files whose ACL grants a user or group at least some permissions, like user:alice:rw or group:admins:x"),
+    ]
+}
+
+#[cfg(not(all(feature = "acl", target_os = "linux")))]
+fn acl_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    Vec::new()
+}
+
+fn metrics_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("metrics")
+        .long("metrics")
+        .takes_value(true)
+        .value_name("ADDR")
+        .multiple(false)
+        .help("Serves Prometheus metrics of the searches at http://ADDR/metrics, like :9100 for every interface")
+}
+
+fn sandbox_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("sandbox")
+        .long("sandbox")
+        .takes_value(true)
+        .value_name("FILE")
+        .multiple(false)
+        .help("Only answers clients with an API key of FILE, a JSON object mapping keys to the directories they may search")
+}
+
+fn verbose_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("verbose")
+        .short("v")
+        .long("verbose")
+        .multiple(true)
+        .help("Reports what the search is doing on stderr. Repeat (-vv) for more detail")
+}
+
+fn app<'a, 'b>() -> App<'a, 'b> {
+    let search = App::new("find")
+        .version("0.1.0")
+        .author("Jack D. <jrd666@protonmail.com>")
+        .about("finds files")
+        .setting(AppSettings::TrailingVarArg)
+        .setting(AppSettings::AllowLeadingHyphen)
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .args(&search_args());
+    add_daemon_subcommands(search)
+        .subcommand(SubCommand::with_name("snapshot")
+            .about("Records the size, modification time and optionally hash of every match in a manifest, to check them later with verify")
+            .setting(AppSettings::TrailingVarArg)
+            .setting(AppSettings::AllowLeadingHyphen)
+            .args(&search_args())
+            .arg(Arg::with_name("hash")
+                .long("hash")
+                .help("Also records the hash of every match, SHA-256 unless --hash-algo picks another, so that verify compares contents rather than modification times")))
+        .subcommand(SubCommand::with_name("diff")
+            .about("Runs the same search in two trees, given as PATH and the path after it, and lists the files only in either or differing. Exits with 1 if there are any")
+            .setting(AppSettings::TrailingVarArg)
+            .setting(AppSettings::AllowLeadingHyphen)
+            .args(&search_args())
+            .arg(Arg::with_name("compare")
+                .long("compare")
+                .takes_value(true)
+                .value_name("FIELDS")
+                .multiple(false)
+                .help("What files found in both trees are compared by, out of size, mtime and hash (SHA-256 unless --hash-algo picks another), like size,hash [default: size,mtime]"))
+            .arg(Arg::with_name("emit-sync-plan")
+                .long("emit-sync-plan")
+                .help("Prints the copies and deletions that would make the second tree match the first for the files found, as a shell script, or as JSON with --json. Nothing is changed")))
+        .subcommand(SubCommand::with_name("verify")
+            .about("Runs the search of a manifest again and lists the files added, removed or changed since. Exits with 1 if there are any")
+            .arg(Arg::with_name("MANIFEST")
+                .help("The manifest written by snapshot")
+                .required(true)
+                .index(1))
+            .arg(Arg::with_name("PATH")
+                .help("Checks this copy of the tree instead of the one the snapshot was taken of")
+                .index(2))
+            .arg(Arg::with_name("json")
+                .long("json")
+                .help("Prints the lists of added, removed and changed files as a JSON object"))
+            .arg(verbose_arg()))
+        .subcommand(SubCommand::with_name("undo")
+            .about("Moves the files renamed or moved by --rename or --move-to back, as recorded in a journal, except those changed since. Exits with 1 if any are left")
+            .arg(Arg::with_name("JOURNAL")
+                .help("The journal written by --apply")
+                .required(true)
+                .index(1))
+            .arg(verbose_arg()))
+        .subcommand(add_grpc_arg(SubCommand::with_name("serve")
+            .about("Answers searches over JSON-RPC, framed like in the Language Server Protocol, for editors and GUIs running ffind as a child process")
+            .arg(Arg::with_name("stdio")
+                .long("stdio")
+                .required_unless("grpc")
+                .help("Reads requests from stdin and writes responses and notifications to stdout"))
+            .arg(Arg::with_name("cache")
+                .long("cache")
+                .help("Saves the matches in every directory to ~/.cache/ffind/results, so that searches run again and subscriptions only read the directories that changed"))
+            .arg(metrics_arg())
+            .arg(sandbox_arg())
+            .arg(verbose_arg())))
+}
+
+#[cfg(feature = "grpc")]
+fn add_grpc_arg<'a, 'b>(serve: App<'a, 'b>) -> App<'a, 'b> {
+    serve.arg(Arg::with_name("grpc")
+        .long("grpc")
+        .takes_value(true)
+        .value_name("ADDR")
+        .multiple(false)
+        .conflicts_with("stdio")
+        .help("Answers searches over gRPC on ADDR instead, like :7070 for every interface, see proto/ffind.proto"))
+}
+
+#[cfg(not(feature = "grpc"))]
+fn add_grpc_arg<'a, 'b>(serve: App<'a, 'b>) -> App<'a, 'b> {
+    serve
+}
+
+#[cfg(unix)]
+fn add_daemon_subcommands<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    let socket = Arg::with_name("socket")
+        .long("socket")
+        .takes_value(true)
+        .value_name("FILE")
+        .multiple(false)
+        .help("The daemon's Unix domain socket [default: $XDG_RUNTIME_DIR/ffind.sock]");
+    app.subcommand(SubCommand::with_name("daemon")
+            .about("Indexes PATH and answers queries for it over a Unix domain socket")
+            .arg(Arg::with_name("PATH")
+                .help("The directory to index")
+                .required(true)
+                .index(1))
+            .arg(socket.clone())
+            .arg(metrics_arg())
+            .arg(sandbox_arg())
+            .arg(verbose_arg()))
+        .subcommand(SubCommand::with_name("query")
+            .about("Runs a search, optionally on a running daemon")
+            .setting(AppSettings::TrailingVarArg)
+            .setting(AppSettings::AllowLeadingHyphen)
+            .args(&search_args())
+            .arg(Arg::with_name("daemon")
+                .long("daemon")
+                .conflicts_with_all(&["explain", "explain-plan", "filter-stats", "save-spec"])
+                .help("Sends the search to the daemon instead of walking the tree"))
+            .arg(socket))
+}
+
+#[cfg(not(unix))]
+fn add_daemon_subcommands<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    app
+}
+
 
 struct Config {
     root: String,
+    extra_roots: Vec<String>,
+    dedup: bool,
+    include_dirs: bool,
+    depth_first: bool,
+    backend: String,
+    max_queued: Option<usize>,
+    tuning: TraversalTuning,
+    max_entries_per_dir: Option<usize>,
+    prune_markers: Vec<String>,
+    excluded_fs_types: Vec<String>,
+    skip_snapshots: bool,
+    profile: Option<Excludes>,
+    scan_budget: Option<u64>,
+    max_file_size: Option<u64>,
+    mmap: bool,
+    retries: Option<u32>,
+    throttle: Option<u64>,
+    idle: bool,
+    checkpoint: Option<String>,
+    resume: Option<String>,
+    cache: bool,
+    no_ignore: bool,
+    no_follow: bool,
+    min_depth: u32,
     depth: u32,
     file_extension: Option<String>,
-    pattern: Option<String>,
+    patterns: Vec<String>,
+    all_patterns: bool,
+    prefix: Option<String>,
+    suffix: Option<String>,
+    glob: Option<String>,
+    in_dir: Option<String>,
+    older_than_sibling: Option<String>,
+    older_than: Option<Duration>,
+    full_path: bool,
+    fuzzy: Option<(String, u32)>,
+    contains: Option<String>,
+    search_compressed: bool,
+    count_matches: bool,
+    cleanup_report: bool,
+    usage_by_owner: bool,
+    retention_report: bool,
+    group_by_dir: bool,
+    unique_by: Option<UniqueBy>,
+    histogram: Option<HistogramBy>,
+    count_copies: bool,
+    hash_algo: HashAlgo,
+    strip_prefix: bool,
+    show_inode: bool,
+    in_use: bool,
+    show_pids: bool,
+    sort: Option<SortKey>,
+    latest_per: Option<Per>,
+    replace: Option<Replace>,
+    apply: bool,
+    backup_suffix: Option<String>,
+    audit_log: Option<String>,
+    destination: Option<Destination>,
+    journal: Option<String>,
+    changed_owner_since: Option<String>,
+    fail_if_more_than: Option<usize>,
+    baseline: Option<String>,
+    update_baseline: bool,
+    #[cfg(target_os = "linux")]
+    has_capability: bool,
+    #[cfg(target_os = "linux")]
+    attributes: Vec<chattr::Attribute>,
+    #[cfg(target_os = "linux")]
+    dedup_mounts: bool,
+    #[cfg(all(feature = "acl", target_os = "linux"))]
+    has_acl: bool,
+    #[cfg(all(feature = "acl", target_os = "linux"))]
+    acl_grants: Vec<acl::Grant>,
+    interactive: bool,
+    output: Option<Format>,
+    fields: Fields,
+    out: Option<String>,
+    append: bool,
+    lines: Option<String>,
+    utf8: Option<FilterSpec>,
+    git: Vec<GitState>,
     size_greater_than: Option<u32>,
     size_less_than: Option<u32>,
+    explain: Option<String>,
+    explain_plan: bool,
+    filter_stats: bool,
+    timings: bool,
+    expression: Vec<String>,
+    spec: Option<SearchSpec>,
+    save_spec: Option<String>,
 }
 
 
 impl Config {
 
-    fn new() -> Config {
-        let matches = App::new("find")
-            .version("0.1.0")
-            .author("Jack D. <jrd666@protonmail.com>")
-            .about("finds files")
-            .arg(Arg::with_name("PATH")
-                .help("Initial location to begin the search")
-                .required(true)
-                .index(1))
-            .arg(Arg::with_name("size-less-than")
-                .short("l")
-                .long("size-less-than")
-                .takes_value(true)
-                .value_name("BYTES")
-                .multiple(false)
-                .help("filters files where file size is not <= BYTES"))
-            .arg(Arg::with_name("size-greater-than")
-                .short("g")
-                .long("size-greater-than")
-                .takes_value(true)
-                .value_name("BYTES")
-                .multiple(false)
-                .help("filters files where file size is not >= BYTES"))
-            .arg(Arg::with_name("depth")
-                .short("d")
-                .long("depth")
-                .takes_value(true)
-                .value_name("DEPTH")
-                .default_value("99999")
-                .multiple(false)
-                .help("Configures the max depth this recursive search will explore"))
-            .arg(Arg::with_name("pattern")
-                .short("p")
-                .long("pattern")
-                .takes_value(true)
-                .value_name("REGEX")
-                .multiple(false)
-                .help("Looks for files that contain this REGEX"))
-            .arg(Arg::with_name("extension")
-                .short("e")
-                .long("extension")
-                .takes_value(true)
-                .value_name("EXT")
-                .multiple(false)
-                .help("Looks for files that have this file extension"))
-            .get_matches();
+    fn from_matches(matches: &ArgMatches) -> Config {
+        let explain = matches.value_of("explain").map(|s| s.to_string());
+
+        // As in find, more paths may follow the first one. The expression starts
+        // at the first word that looks like a primary, operator or parenthesis.
+        let mut expression: Vec<String> = matches.values_of("EXPRESSION")
+            .map(|values| values.map(|s| s.to_string()).collect())
+            .unwrap_or_default();
+        let paths = expression.iter()
+            .take_while(|word| !(word.starts_with('-') || word.starts_with('(') || word.starts_with(')') || word.starts_with('!')))
+            .count();
+        let extra_roots: Vec<String> = expression.drain(..paths).collect();
+        for root in &extra_roots {
+            if !is_remote(root) && !PathBuf::from(root).exists() {
+                eprintln!("ERROR: Invalid argument for PATH: <{}>. Make sure search path exists.", root);
+                std::process::exit(1);
+            }
+        }
+
+        let spec = matches.value_of("spec").map(|file| {
+            fs::read_to_string(file)
+                .and_then(|text| SearchSpec::from_json(&text))
+                .unwrap_or_else(|e| {
+                    eprintln!("ERROR: Invalid argument --spec: {}", e);
+                    std::process::exit(1);
+                })
+        });
 
         // Extract the search root. Check to make sure it exists.
-        let root = matches.value_of("PATH").unwrap().to_string();
-        if !PathBuf::from(&root).exists() {
+        let root = match (matches.value_of("PATH"), &spec) {
+            (Some(root), _) => root.to_string(),
+            (None, Some(spec)) => spec.root.clone(),
+            (None, None) => String::from("."),
+        };
+        if explain.is_none() && !is_remote(&root) && !PathBuf::from(&root).exists() {
             eprintln!("ERROR: Invalid argument for PATH: <{}>. Make sure search path exists.", root);
             std::process::exit(1);
         }
 
-        // Extract the depth argument and check for errors.
-        let depth: u32 = match matches.value_of("depth").unwrap().parse() {
-            Ok(depth) => depth,
-            Err(e) => {
-                eprintln!("ERROR: Invalid argument --depth: {}.", e);
+        // Extract the depth argument and check for errors. A depth from a spec
+        // file is only overridden when --depth is given explicitly.
+        let depth: u32 = match (&spec, matches.value_of("depth")) {
+            (Some(spec), None) => spec.depth,
+            (None, None) => rustfind::UNLIMITED_DEPTH,
+            (_, Some(depth)) => match depth.parse() {
+                Ok(depth) => depth,
+                Err(e) => {
+                    eprintln!("ERROR: Invalid argument --depth: {}.", e);
+                    std::process::exit(1);
+                }
+            },
+        };
+
+        // --exact-depth N is short for a minimum and maximum depth of N.
+        let exact_depth: Option<u32> = matches.value_of("exact-depth").map(|depth| {
+            depth.parse().unwrap_or_else(|e| {
+                eprintln!("ERROR: Invalid argument --exact-depth: {}.", e);
+                std::process::exit(1);
+            })
+        });
+        let (min_depth, depth) = match (exact_depth, &spec) {
+            (Some(depth), _) => (depth, depth),
+            (None, Some(spec)) => (spec.min_depth, depth),
+            (None, None) => (0, depth),
+        };
+
+        let max_queued: Option<usize> = matches.value_of("max-queued").map(|entries| {
+            entries.parse().unwrap_or_else(|e| {
+                eprintln!("ERROR: Invalid argument --max-queued: {}.", e);
+                std::process::exit(1);
+            })
+        });
+
+        let count_or = |name: &str, least: usize, default: usize| match matches.value_of(name).map(str::parse::<usize>) {
+            None => default,
+            Some(Ok(parsed)) if parsed >= least => parsed,
+            Some(Ok(_)) => {
+                eprintln!("ERROR: Invalid argument --{}: it must be at least {}.", name, least);
                 std::process::exit(1);
             }
+            Some(Err(e)) => {
+                eprintln!("ERROR: Invalid argument --{}: {}.", name, e);
+                std::process::exit(1);
+            }
+        };
+        let defaults = match matches.value_of("stat-threads") {
+            Some("auto") => TraversalTuning::auto(),
+            _ => TraversalTuning::default(),
+        };
+        let tuning = TraversalTuning {
+            readdir_batch: count_or("readdir-batch", 1, defaults.readdir_batch),
+            queue_capacity: count_or("queue-capacity", 0, defaults.queue_capacity),
+            stat_threads: if defaults.adaptive { defaults.stat_threads } else { count_or("stat-threads", 1, defaults.stat_threads) },
+            adaptive: defaults.adaptive,
         };
 
-        let file_extension = if let Some(s) = matches.value_of("extension") {
-            Some(s.to_string())
-        } else {
-            None
+        let profile = matches.value_of("profile").map(|name| {
+            Excludes::profile(name).unwrap_or_else(|e| {
+                eprintln!("ERROR: Invalid argument --profile: {}", e);
+                std::process::exit(1);
+            })
+        });
+
+        let max_entries_per_dir: Option<usize> = matches.value_of("max-entries-per-dir").map(|entries| {
+            entries.parse().unwrap_or_else(|e| {
+                eprintln!("ERROR: Invalid argument --max-entries-per-dir: {}.", e);
+                std::process::exit(1);
+            })
+        });
+
+        let scan_budget: Option<u64> = matches.value_of("scan-budget").map(|bytes| {
+            budget::parse_bytes(bytes).unwrap_or_else(|e| {
+                eprintln!("ERROR: Invalid argument --scan-budget: {}", e);
+                std::process::exit(1);
+            })
+        });
+
+        let max_file_size: Option<u64> = matches.value_of("max-filesize").map(|bytes| {
+            budget::parse_bytes(bytes).unwrap_or_else(|e| {
+                eprintln!("ERROR: Invalid argument --max-filesize: {}", e);
+                std::process::exit(1);
+            })
+        });
+
+        let retries: Option<u32> = matches.value_of("retries").map(|retries| {
+            retries.parse().unwrap_or_else(|e| {
+                eprintln!("ERROR: Invalid argument --retries: {}.", e);
+                std::process::exit(1);
+            })
+        });
+
+        let throttle: Option<u64> = matches.value_of("throttle").map(|rate| {
+            match rate.parse::<f64>() {
+                Ok(mbps) if mbps > 0.0 => (mbps * 1_000_000.0) as u64,
+                _ => {
+                    eprintln!("ERROR: Invalid argument --throttle: expected a positive number of MB per second.");
+                    std::process::exit(1);
+                }
+            }
+        });
+
+        let file_extension = matches.value_of("extension").map(|s| s.to_string());
+
+        let patterns = matches.values_of("pattern")
+            .map(|values| values.map(|s| s.to_string()).collect())
+            .unwrap_or_default();
+
+        let fuzzy = matches.value_of("fuzzy").map(|query| {
+            let distance = matches.value_of("fuzzy-distance").unwrap_or("2").parse().unwrap_or_else(|e| {
+                eprintln!("ERROR: Invalid argument --fuzzy-distance: {}.", e);
+                std::process::exit(1);
+            });
+            (query.to_string(), distance)
+        });
+
+        let sort = matches.value_of("sort").map(|key| {
+            SortKey::parse(key).unwrap_or_else(|e| {
+                eprintln!("ERROR: Invalid argument --sort: {}", e);
+                std::process::exit(1);
+            })
+        });
+
+        let fail_if_more_than = match matches.value_of("fail-if-more-than") {
+            Some(n) => Some(n.parse().unwrap_or_else(|e| {
+                eprintln!("ERROR: Invalid argument --fail-if-more-than: {}.", e);
+                std::process::exit(1);
+            })),
+            None => matches.is_present("fail-if-any").then_some(0),
         };
 
-        let pattern = if let Some(s) = matches.value_of("pattern") {
-            Some(s.to_string())
+        let latest_per = matches.value_of("latest-per").map(|group| {
+            Per::parse(group).unwrap_or_else(|e| {
+                eprintln!("ERROR: Invalid argument --latest-per: {}", e);
+                std::process::exit(1);
+            })
+        });
+
+        let fields = matches.value_of("fields").map_or_else(|| Ok(Fields::default()), Fields::parse).unwrap_or_else(|e| {
+            eprintln!("ERROR: Invalid argument --fields: {}", e);
+            std::process::exit(1);
+        });
+
+        let output = if matches.is_present("json") {
+            Some(Format::Json)
         } else {
-            None
+            matches.value_of("output").map(|name| Format::parse(name).unwrap())
         };
+        if output.is_none() && matches.is_present("fields") {
+            eprintln!("ERROR: --fields only applies to --json and --output.");
+            std::process::exit(1);
+        }
 
-        let size_less_than: Option<u32> = match matches.value_of("size-less-than") {
-            Some(bytes) => Some(bytes.parse().unwrap_or_else(|e| {
-                eprintln!("ERROR: Invalid argument --size-less-than: {}.", e);
+        let replace = matches.value_of("replace").map(|arg| {
+            Replace::parse(arg).unwrap_or_else(|e| {
+                eprintln!("ERROR: Invalid argument --replace: {}", e);
+                std::process::exit(1);
+            })
+        });
+
+        let destination = match (matches.value_of("rename"), matches.value_of("move-to")) {
+            (Some(arg), _) => Some(Destination::rename(arg).unwrap_or_else(|e| {
+                eprintln!("ERROR: Invalid argument --rename: {}", e);
                 std::process::exit(1);
             })),
-            None => None,
+            (None, Some(dir)) => Some(Destination::move_to(dir)),
+            (None, None) => None,
         };
+        if matches.is_present("apply") && replace.is_none() && destination.is_none() {
+            eprintln!("ERROR: --apply only applies to --replace, --rename and --move-to.");
+            std::process::exit(1);
+        }
+        if matches.is_present("journal") && destination.is_none() {
+            eprintln!("ERROR: --journal only applies to --rename and --move-to.");
+            std::process::exit(1);
+        }
 
-        let size_greater_than: Option<u32> = match matches.value_of("size-greater-than") {
-            Some(bytes) => Some(bytes.parse().unwrap_or_else(|e| {
-                eprintln!("ERROR: Invalid argument --size-greater-than: {}.", e);
+        let lines = matches.value_of("lines").map(|arg| {
+            if let Err(e) = LineCount::parse(arg) {
+                eprintln!("ERROR: Invalid argument --lines: {}", e);
                 std::process::exit(1);
-            })),
-            None => None,
+            }
+            arg.to_string()
+        });
+
+        let sample: Option<u64> = matches.value_of("utf8-sample").map(|bytes| {
+            bytes.parse().unwrap_or_else(|e| {
+                eprintln!("ERROR: Invalid argument --utf8-sample: {}.", e);
+                std::process::exit(1);
+            })
+        });
+        let utf8 = match (matches.is_present("valid-utf8"), matches.is_present("invalid-utf8")) {
+            (false, false) => None,
+            (valid, _) => Some(FilterSpec::Utf8 { valid, sample }),
         };
 
+        let size_less_than: Option<u32> = matches.value_of("size-less-than").map(|bytes| {
+            bytes.parse().unwrap_or_else(|e| {
+                eprintln!("ERROR: Invalid argument --size-less-than: {}.", e);
+                std::process::exit(1);
+            })
+        });
+
+        let size_greater_than: Option<u32> = matches.value_of("size-greater-than").map(|bytes| {
+            bytes.parse().unwrap_or_else(|e| {
+                eprintln!("ERROR: Invalid argument --size-greater-than: {}.", e);
+                std::process::exit(1);
+            })
+        });
+
         // Return the Config struct with the fields now that error checking is complete.
         Config {
             root,
+            min_depth,
             depth,
             file_extension,
-            pattern,
+            patterns,
+            all_patterns: matches.is_present("all-patterns"),
+            prefix: matches.value_of("prefix").map(|s| s.to_string()),
+            suffix: matches.value_of("suffix").map(|s| s.to_string()),
+            glob: matches.value_of("glob").map(|s| s.to_string()),
+            in_dir: matches.value_of("in-dir").map(|s| s.to_string()),
+            older_than_sibling: matches.value_of("older-than-sibling").map(|s| s.to_string()),
+            older_than: matches.value_of("older-than").map(|age| retention::parse_age(age).unwrap_or_else(|e| {
+                eprintln!("ERROR: Invalid argument --older-than: {}", e);
+                std::process::exit(1);
+            })),
+            full_path: matches.is_present("full-path"),
+            fuzzy,
+            contains: matches.value_of("contains").map(|s| s.to_string()),
+            search_compressed: matches.is_present("search-compressed"),
+            count_matches: matches.is_present("count-matches"),
+            cleanup_report: matches.is_present("cleanup-report"),
+            usage_by_owner: matches.is_present("usage-by-owner"),
+            retention_report: matches.is_present("retention-report"),
+            group_by_dir: matches.value_of("group-by") == Some("dir"),
+            unique_by: matches.value_of("unique-by").map(|key| UniqueBy::parse(key).unwrap_or_else(|e| {
+                eprintln!("ERROR: Invalid argument --unique-by: {}", e);
+                std::process::exit(1);
+            })),
+            histogram: matches.value_of("histogram").map(|key| HistogramBy::parse(key).unwrap_or_else(|e| {
+                eprintln!("ERROR: Invalid argument --histogram: {}", e);
+                std::process::exit(1);
+            })),
+            count_copies: matches.is_present("count-copies"),
+            hash_algo: matches.value_of("hash-algo").map_or(HashAlgo::Sha256, |algo| HashAlgo::parse(algo).unwrap_or_else(|e| {
+                eprintln!("ERROR: Invalid argument --hash-algo: {}", e);
+                std::process::exit(1);
+            })),
+            strip_prefix: matches.is_present("strip-prefix"),
+            show_inode: matches.is_present("show-inode"),
+            in_use: matches.is_present("in-use"),
+            show_pids: matches.is_present("show-pids"),
+            sort,
+            latest_per,
+            replace,
+            apply: matches.is_present("apply"),
+            backup_suffix: matches.value_of("backup-suffix").map(|s| s.to_string()),
+            audit_log: matches.value_of("audit-log").map(|s| s.to_string()),
+            destination,
+            journal: matches.value_of("journal").map(|s| s.to_string()),
+            changed_owner_since: matches.value_of("changed-owner-since").map(|s| s.to_string()),
+            fail_if_more_than,
+            baseline: matches.value_of("baseline").map(|s| s.to_string()),
+            update_baseline: matches.is_present("update-baseline"),
+            #[cfg(target_os = "linux")]
+            has_capability: matches.is_present("has-capability"),
+            #[cfg(target_os = "linux")]
+            attributes: [("attr-immutable", chattr::Attribute::Immutable), ("attr-append-only", chattr::Attribute::AppendOnly)]
+                .iter()
+                .filter(|(arg, _)| matches.is_present(arg))
+                .map(|&(_, attribute)| attribute)
+                .collect(),
+            #[cfg(target_os = "linux")]
+            dedup_mounts: matches.is_present("dedup-mounts"),
+            #[cfg(all(feature = "acl", target_os = "linux"))]
+            has_acl: matches.is_present("has-acl"),
+            #[cfg(all(feature = "acl", target_os = "linux"))]
+            acl_grants: matches.values_of("acl-grants").into_iter().flatten()
+                .map(|arg| acl::Grant::parse(arg).unwrap_or_else(|e| {
+                    eprintln!("ERROR: Invalid argument --acl-grants: {}", e);
+                    std::process::exit(1);
+                }))
+                .collect(),
+            interactive: matches.is_present("interactive"),
+            output,
+            out: matches.value_of("out").map(|s| s.to_string()),
+            append: matches.is_present("append"),
+            fields,
+            lines,
+            utf8,
+            git: [("git-tracked", GitState::Tracked), ("git-untracked", GitState::Untracked), ("git-modified", GitState::Modified)]
+                .iter()
+                .filter(|(arg, _)| matches.is_present(arg))
+                .map(|(_, state)| *state)
+                .collect(),
             size_greater_than,
-            size_less_than
+            size_less_than,
+            explain,
+            explain_plan: matches.is_present("explain-plan"),
+            filter_stats: matches.is_present("filter-stats"),
+            timings: matches.is_present("timings"),
+            expression,
+            extra_roots,
+            dedup: !matches.is_present("no-dedup"),
+            include_dirs: matches.is_present("include-dirs"),
+            depth_first: matches.is_present("depth-first"),
+            backend: matches.value_of("backend").unwrap().to_string(),
+            max_queued,
+            tuning,
+            max_entries_per_dir,
+            profile,
+            prune_markers: matches.values_of("prune-if-contains")
+                .map(|values| values.map(|s| s.to_string()).collect())
+                .unwrap_or_default(),
+            excluded_fs_types: matches.values_of("exclude-fs-type")
+                .map(|values| values.map(|s| s.to_string()).collect())
+                .unwrap_or_default(),
+            skip_snapshots: matches.is_present("skip-snapshots"),
+            scan_budget,
+            max_file_size,
+            mmap: matches.is_present("mmap"),
+            retries,
+            throttle,
+            idle: matches.is_present("idle"),
+            checkpoint: matches.value_of("checkpoint").map(|s| s.to_string()),
+            resume: matches.value_of("resume").map(|s| s.to_string()),
+            cache: matches.is_present("cache"),
+            no_ignore: matches.is_present("no-ignore"),
+            no_follow: matches.is_present("no-follow"),
+            spec,
+            save_spec: matches.value_of("save-spec").map(|s| s.to_string()),
+        }
+    }
+
+    /// Describes the search given on the command line as a `SearchSpec`,
+    /// starting from the spec loaded with --spec if there is one.
+    fn search_spec(&mut self) -> SearchSpec {
+        let mut spec = self.spec.take().unwrap_or_else(|| SearchSpec::new(String::new(), 0));
+        spec.root = self.root.clone();
+        spec.extra_roots.append(&mut self.extra_roots);
+        spec.depth = self.depth;
+        spec.min_depth = self.min_depth;
+        spec.dedup = spec.dedup && self.dedup;
+        spec.ignore_files = spec.ignore_files && !self.no_ignore;
+        spec.include_dirs = spec.include_dirs || self.include_dirs;
+        spec.prune_if_contains.extend(self.prune_markers.iter().cloned());
+        spec.max_entries_per_dir = self.max_entries_per_dir.or(spec.max_entries_per_dir);
+        if self.no_follow {
+            spec.metadata_mode = MetadataMode::NoFollow;
+        }
+
+        if let Some(size) = self.size_less_than {
+            spec.filters.push(FilterSpec::SizeLessThanOrEq(size as u64));
+        };
+
+        if let Some(size) = self.size_greater_than {
+            spec.filters.push(FilterSpec::SizeGreaterThanOrEq(size as u64));
+        };
+
+        if let Some(ext) = self.file_extension.take() {
+            spec.filters.push(FilterSpec::HasExtension { ext, case_sensitive: false });
+        };
+
+        // Several patterns are matched as one set, in a single pass.
+        let mut patterns = std::mem::take(&mut self.patterns);
+        match patterns.len() {
+            0 => {}
+            1 if self.full_path => spec.filters.push(FilterSpec::PathMatchesRegex(patterns.remove(0))),
+            1 => spec.filters.push(FilterSpec::MatchesRegex(patterns.remove(0))),
+            _ => spec.filters.push(FilterSpec::MatchesRegexSet { patterns, all: self.all_patterns, full_path: self.full_path }),
+        };
+
+        if let Some(prefix) = self.prefix.take() {
+            spec.filters.push(FilterSpec::NameStartsWith(prefix));
+        };
+
+        if let Some(suffix) = self.suffix.take() {
+            spec.filters.push(FilterSpec::NameEndsWith(suffix));
+        };
+
+        if let Some(glob) = self.in_dir.take() {
+            spec.filters.push(FilterSpec::HasAncestor(glob));
+        };
+
+        if let Some(sibling) = self.older_than_sibling.take() {
+            spec.filters.push(FilterSpec::OlderThanSibling(sibling));
+        };
+
+        if let Some(age) = self.older_than {
+            spec.filters.push(FilterSpec::OlderThan(age.as_secs()));
+        };
+
+        if let Some(glob) = self.glob.take() {
+            spec.filters.push(FilterSpec::MatchesGlob { glob, full_path: self.full_path, case_insensitive: false });
+        };
+
+        if let Some((query, max_distance)) = self.fuzzy.take() {
+            spec.filters.push(FilterSpec::FuzzyName { query, max_distance });
+        };
+
+        if !self.expression.is_empty() {
+            spec.filters.push(FilterSpec::Expression(expr::join(&self.expression)));
+        };
+
+        for state in self.git.drain(..) {
+            spec.filters.push(FilterSpec::Git(state));
         }
+
+        // Contents are read last, so the cheaper filters can rule files out first.
+        if let Some(lines) = self.lines.take() {
+            spec.filters.push(FilterSpec::LineCount(lines));
+        };
+
+        if let Some(utf8) = self.utf8.take() {
+            spec.filters.push(utf8);
+        };
+
+        if let Some(pattern) = self.contains.clone() {
+            spec.filters.push(FilterSpec::ContentMatches { pattern, search_compressed: self.search_compressed });
+        };
+
+        if !spec.actions.contains(&ActionSpec::Print) {
+            spec.actions.push(ActionSpec::Print);
+        }
+        spec
     }
 }
 
-fn main() {
-    let config = Config::new();
-    let mut finder = Finder::new(config.root);
+/// Returns true if `path` is a remote location, like `user@host:/var/log` or
+/// `s3://bucket/logs`, which can't be checked up front.
+fn is_remote(path: &str) -> bool {
+    #[cfg(feature = "remote")]
+    {
+        if remote::parse_location(path).is_some() {
+            return true;
+        }
+    }
+    #[cfg(feature = "s3")]
+    {
+        if s3::parse_location(path).is_some() {
+            return true;
+        }
+    }
+    let _ = path;
+    false
+}
 
-    if let Some(size) = config.size_less_than {
-        finder = finder.size_less_than_or_eq(size);
-    };
+/// Connects to the host of a remote search, if the root of `spec` is a remote
+/// location, and turns the roots of `spec` into paths on that host.
+#[cfg(feature = "remote")]
+fn connect_remote(spec: &mut SearchSpec) -> Option<RemoteFs> {
+    let destination = remote::parse_location(&spec.root)?.0.to_string();
+    for root in std::iter::once(&mut spec.root).chain(spec.extra_roots.iter_mut()) {
+        let path = match remote::parse_location(root) {
+            Some((host, path)) if host == destination => path.to_string(),
+            _ => {
+                eprintln!("ERROR: Invalid argument for PATH: <{}>. All paths must be on {}.", root, destination);
+                std::process::exit(1);
+            }
+        };
+        *root = path;
+    }
+    match RemoteFs::connect(&destination) {
+        Ok(fs) => Some(fs),
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Returns the bucket to search if the root of `spec` is an `s3://` location.
+#[cfg(feature = "s3")]
+fn s3_bucket(spec: &SearchSpec) -> Option<S3Fs> {
+    let bucket = s3::parse_location(&spec.root)?;
+    for root in &spec.extra_roots {
+        if s3::parse_location(root) != Some(bucket) {
+            eprintln!("ERROR: Invalid argument for PATH: <{}>. All paths must be in s3://{}.", root, bucket);
+            std::process::exit(1);
+        }
+    }
+    Some(S3Fs::new(bucket))
+}
+
+/// Prints the verdict of every filter in `finder` for `file`, followed by the
+/// overall result. Exits with a non-zero status if `file` would be rejected.
+fn explain(finder: &Finder, file: &str) {
+    let verdicts = finder.explain(file).unwrap_or_else(|e| {
+        eprintln!("ERROR: Invalid argument --explain: {}", e);
+        std::process::exit(1);
+    });
+    for verdict in &verdicts {
+        println!("{}: {}", verdict.filter, if verdict.passed { "pass" } else { "REJECT" });
+    }
+    if verdicts.iter().all(|v| v.passed) {
+        println!("{} matches", file);
+    } else {
+        println!("{} is rejected", file);
+        std::process::exit(1);
+    }
+}
+
+/// Prints the collected filter statistics as a table on stderr, so they don't
+/// get mixed up with the matches on stdout.
+fn print_filter_stats(stats: &FilterStats) {
+    let report = stats.report();
+    let width = report.iter().map(|s| s.filter.len()).max().unwrap_or(0).max("FILTER".len());
+    eprintln!("{:<width$}  {:>10}  {:>10}  {:>12}", "FILTER", "EVALUATED", "REJECTED", "TIME", width = width);
+    for stat in report {
+        eprintln!("{:<width$}  {:>10}  {:>10}  {:>12?}",
+                  stat.filter, stat.evaluated, stat.rejected, stat.elapsed, width = width);
+    }
+}
+
+/// Prints where the search spent its time on stderr: every phase, then the
+/// directories most of it was spent in.
+fn print_timings(timings: &Timings) {
+    let report = timings.report(HOT_SPOTS);
+    let total = report.total();
+    let share = |spent: Duration| if total.is_zero() { 0.0 } else { 100.0 * spent.as_secs_f64() / total.as_secs_f64() };
+    eprintln!("{:<10}  {:>12}  {:>6}", "PHASE", "TIME", "SHARE");
+    for (phase, spent) in &report.phases {
+        eprintln!("{:<10}  {:>12?}  {:>5.1}%", phase.as_str(), spent, share(*spent));
+    }
+    eprintln!("{} entries in {} directories, {:?} in all", report.entries, report.directories, total);
+    if !report.hot_spots.is_empty() {
+        eprintln!("{:>12}  {:>6}  DIRECTORY", "TIME", "SHARE");
+        for (dir, spent) in &report.hot_spots {
+            eprintln!("{:>12?}  {:>5.1}%  {}", spent, share(*spent), dir.display());
+        }
+    }
+}
+
+/// Runs `write` on stdout, or on the file `out` if given, which is only
+/// replaced (or appended to) once `write` succeeds. `write` is also told
+/// whether to start with a header: not when appending to results.
+fn with_output(out: Option<&str>, append: bool, write: impl FnOnce(&mut dyn Write, bool) -> Result<(), Error>) -> Result<(), Error> {
+    match out {
+        Some(path) => {
+            let header = !append || fs::metadata(path).map_or(true, |meta| meta.len() == 0);
+            let mut file = AtomicFile::create(Path::new(path), append)
+                .map_err(|e| Error::new(e.kind(), format!("Could not write {}: {}", path, e)))?;
+            write(&mut file, header)?;
+            file.commit().map_err(|e| Error::new(e.kind(), format!("Could not write {}: {}", path, e)))
+        }
+        None => {
+            let stdout = io::stdout();
+            let mut stdout = io::BufWriter::new(stdout.lock());
+            write(&mut stdout, true)?;
+            stdout.flush()
+        }
+    }
+}
+
+/// Writes `entries` to `out` in `format`, after a header naming their
+/// fields if given.
+fn write_entries(format: Format, names: Option<&[&str]>, entries: impl IntoIterator<Item = Value>, out: &mut dyn Write) -> Result<(), Error> {
+    let mut writer = format.writer(out);
+    if let Some(names) = names {
+        writer.begin(names)?;
+    }
+    for entry in entries {
+        writer.write(&entry)?;
+    }
+    writer.finish()
+}
+
+/// Writes to `out` what `replace` changes in the files `found`, and makes the
+/// changes if `apply` is set, those `confirmations` allow if given, recording
+/// them in `audit` if given.
+fn replace_in(replace: &Replace, found: &[String], apply: bool, backup_suffix: Option<&str>, audit: Option<&AuditLog>,
+              mut confirmations: Option<&mut Confirmations>, out: &mut dyn Write) -> Result<(), Error> {
+    let (mut files, mut replacements) = (0, 0);
+    for file in found {
+        if let Some(confirmations) = confirmations.as_deref_mut() {
+            if confirmations.quit() {
+                break;
+            }
+            // Only the files that change are asked about.
+            if let Ok(Some(changes)) = replace.preview(Path::new(file)) {
+                let action = format!("replace {} matches in", changes.replacements);
+                if !confirmations.allows(&Proposal { action: &action, path: Path::new(file), to: None }) {
+                    continue;
+                }
+            }
+        }
+        let changes = if apply {
+            let mutation = audit.map(|_| Mutation::begin("replace", file));
+            let changes = replace.apply(Path::new(file), backup_suffix);
+            if let (Some(audit), Some(mutation)) = (audit, mutation) {
+                match &changes {
+                    Ok(Some(changes)) => {
+                        let mutation = mutation.detail("replacements", changes.replacements as u64);
+                        audit.record(match backup_suffix {
+                            Some(suffix) => mutation.detail("backup", format!("{}{}", file, suffix)),
+                            None => mutation,
+                        })?;
+                    }
+                    Ok(None) => {}
+                    Err(e) => audit.record(mutation.failed(e))?,
+                }
+            }
+            changes
+        } else {
+            replace.preview(Path::new(file))
+        };
+        match changes {
+            Ok(Some(changes)) => {
+                write!(out, "{}", changes.diff(file))?;
+                files += 1;
+                replacements += changes.replacements;
+            }
+            Ok(None) => {}
+            Err(e) => eprintln!("WARNING: Could not replace in {}: {}", file, e),
+        }
+    }
+    if apply {
+        eprintln!("replaced {} matches in {} files", replacements, files);
+    } else {
+        eprintln!("would replace {} matches in {} files, run again with --apply to write them", replacements, files);
+    }
+    Ok(())
+}
 
-    if let Some(size) = config.size_greater_than {
-        finder = finder.size_greater_than_or_eq(size);
+/// Writes to `out` where `destination` moves the files `found`, and moves
+/// them if `apply` is set, those `confirmations` allow if given, recording
+/// the moves in the journal at `journal`, or a new one, and in `audit` if
+/// given.
+fn move_files(destination: &Destination, found: &[String], apply: bool, journal: Option<&str>, audit: Option<&AuditLog>,
+              mut confirmations: Option<&mut Confirmations>, out: &mut dyn Write) -> Result<(), Error> {
+    let moves = destination.plan(found)?;
+    if !apply {
+        for mv in &moves {
+            writeln!(out, "{} -> {}", mv.from.display(), mv.to.display())?;
+        }
+        eprintln!("would move {} files, run again with --apply to move them", moves.len());
+        return Ok(());
+    }
+    let path = journal.map(PathBuf::from).or_else(rename::default_journal).ok_or_else(|| Error::new(
+        io::ErrorKind::NotFound,
+        "Could not find the home directory to keep the undo journal in, give one with --journal."))?;
+    let mut journal = Journal::create(path)?;
+    let action = match destination {
+        Destination::Rename { .. } => "rename",
+        Destination::MoveTo(_) => "move",
     };
+    let mut moved = 0;
+    for mv in &moves {
+        if let Some(confirmations) = confirmations.as_deref_mut() {
+            if !confirmations.allows(&Proposal { action, path: &mv.from, to: Some(&mv.to) }) {
+                match confirmations.quit() {
+                    true => break,
+                    false => continue,
+                }
+            }
+        }
+        let mutation = audit.map(|_| Mutation::begin(action, &mv.from).to(&mv.to));
+        let result = journal.apply(mv);
+        if let (Some(audit), Some(mutation)) = (audit, mutation) {
+            audit.record(match &result {
+                Ok(()) => mutation,
+                Err(e) => mutation.failed(e),
+            })?;
+        }
+        match result {
+            Ok(()) => {
+                writeln!(out, "{} -> {}", mv.from.display(), mv.to.display())?;
+                moved += 1;
+            }
+            Err(e) => eprintln!("WARNING: Could not move {}: {}", mv.from.display(), e),
+        }
+    }
+    eprintln!("moved {} files, undo with 'rustfind undo {}'", moved, journal.path().display());
+    Ok(())
+}
+
+/// Runs the search described by the command line in this process.
+fn search(mut config: Config) {
+    let spec = config.search_spec();
 
-    if let Some(ext) = config.file_extension {
-        finder = finder.has_extension_case_insensitive(ext)
+    if let Some(file) = &config.save_spec {
+        if let Err(e) = fs::write(file, format!("{:#}\n", spec.to_json())) {
+            eprintln!("ERROR: Could not write --save-spec {}: {}", file, e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Replacements and moves are made in local files, which a remote root
+    // isn't made of.
+    if let Some(root) = std::iter::once(&spec.root).chain(&spec.extra_roots).find(|root| is_remote(root)) {
+        if config.replace.is_some() {
+            eprintln!("ERROR: --replace only changes local files, not those in {}.", root);
+            std::process::exit(1);
+        }
+        if config.destination.is_some() {
+            eprintln!("ERROR: --rename and --move-to only move local files, not those in {}.", root);
+            std::process::exit(1);
+        }
+    }
+
+    #[cfg(feature = "remote")]
+    let mut spec = spec;
+    #[cfg(feature = "remote")]
+    let remote = connect_remote(&mut spec);
+
+    let mut finder = Finder::from_spec(&spec).unwrap_or_else(|e| {
+        eprintln!("ERROR: {}", e);
+        std::process::exit(1);
+    });
+
+    // Backends that aren't available fall back to the default one.
+    match config.backend.as_str() {
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        "uring" => match rustfind::uring::UringFileSystem::new() {
+            Ok(fs) => finder = finder.file_system(fs),
+            Err(e) => eprintln!("WARNING: io_uring isn't available, using the std backend: {}", e),
+        },
+        #[cfg(windows)]
+        "mft" => match rustfind::mft::MftFileSystem::open(Path::new(&spec.root)) {
+            Ok(fs) => finder = finder.file_system(fs),
+            Err(e) => eprintln!("WARNING: Could not read the Master File Table (is the search elevated?), using the std backend: {}", e),
+        },
+        _ => {}
+    }
+
+    #[cfg(feature = "remote")]
+    if let Some(fs) = remote {
+        finder = finder.file_system(fs);
+    }
+
+    #[cfg(feature = "s3")]
+    if let Some(fs) = s3_bucket(&spec) {
+        finder = finder.file_system(fs);
+    }
+
+    // Extended attributes aren't described by specs, and are checked after the
+    // filters that are.
+    #[cfg(target_os = "linux")]
+    {
+        if config.has_capability {
+            finder = finder.has_file_capabilities();
+        }
+        for attribute in config.attributes.drain(..) {
+            finder = finder.has_attribute(attribute);
+        }
+        if config.dedup_mounts {
+            finder = finder.dedup_mounts();
+        }
+    }
+    #[cfg(any(target_os = "linux", windows))]
+    let open_files = config.in_use.then(|| OpenFiles::scan().unwrap_or_else(|e| {
+        eprintln!("ERROR: Could not look for open files: {}", e);
+        std::process::exit(1);
+    }));
+    #[cfg(any(target_os = "linux", windows))]
+    if let Some(open) = &open_files {
+        finder = finder.in_use(open);
+    }
+    #[cfg(all(feature = "acl", target_os = "linux"))]
+    {
+        if config.has_acl {
+            finder = finder.has_acl();
+        }
+        for grant in config.acl_grants.drain(..) {
+            finder = finder.acl_grants(grant);
+        }
+    }
+    // Last, so that it sees every file the other filters retain.
+    let baseline_update = config.update_baseline.then(BaselineUpdate::new);
+    if let Some(path) = &config.baseline {
+        let baseline = match Baseline::read(Path::new(path)) {
+            Ok(baseline) => baseline,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && config.update_baseline => Baseline::default(),
+            Err(e) => {
+                eprintln!("ERROR: Could not read the baseline {}: {}", path, e);
+                std::process::exit(1);
+            }
+        };
+        finder = finder.not_in_baseline(baseline, baseline_update.as_ref());
+    }
+
+    if let Some(key) = config.sort {
+        finder = finder.sort_by(key);
+    }
+    if let Some(per) = config.latest_per {
+        finder = finder.latest_per(per);
+    }
+    if config.depth_first {
+        finder = finder.traversal(Traversal::DepthFirst);
+    }
+    if let Some(entries) = config.max_queued {
+        finder = finder.max_queued(entries);
+    }
+    finder = finder.tuning(config.tuning);
+    finder = finder.hash_algo(config.hash_algo);
+    // The spec skips them too, but without telling which.
+    let skipped = SkippedDirs::new();
+    if let Some(entries) = spec.max_entries_per_dir {
+        finder = finder.max_entries_per_dir(entries, &skipped);
+    }
+    if let Some(excludes) = config.profile.take() {
+        finder = finder.excludes(excludes);
+    }
+    for fs_type in &config.excluded_fs_types {
+        finder = finder.exclude_fs_type(fs_type);
+    }
+    if config.skip_snapshots {
+        finder = finder.skip_snapshots();
+    }
+    let budget = config.scan_budget.map(ScanBudget::new);
+    if let Some(budget) = &budget {
+        finder = finder.scan_budget(budget);
+    }
+    if let Some(bytes) = config.max_file_size {
+        finder = finder.max_file_size(bytes);
+    }
+    finder = finder.mmap(config.mmap);
+    if let Some(retries) = config.retries {
+        finder = finder.retry(Retries::new(retries, Duration::from_millis(50)));
+    }
+    if let Some(bytes_per_second) = config.throttle {
+        finder = finder.throttle(bytes_per_second);
+    }
+    if let Some(file) = config.checkpoint {
+        finder = finder.checkpoint(file, CHECKPOINT_INTERVAL);
+    }
+    if let Some(file) = config.resume {
+        finder = finder.resume(Path::new(&file)).unwrap_or_else(|e| {
+            eprintln!("ERROR: Invalid argument --resume: {}", e);
+            std::process::exit(1);
+        });
+    }
+    if config.cache {
+        match cache::default_dir() {
+            Some(dir) => finder = finder.result_cache(dir),
+            None => eprintln!("WARNING: Could not find the home directory, not caching the results."),
+        }
+    }
+    if config.idle {
+        if let Err(e) = throttle::set_idle_priority() {
+            eprintln!("WARNING: Could not lower the priority of the search: {}", e);
+        }
+    }
+
+    if let Some(file) = config.explain {
+        explain(&finder, &file);
+        return;
+    }
+    if config.explain_plan {
+        print!("{}", finder.plan(config.depth));
+        return;
+    }
+
+    let stats = FilterStats::new();
+    if config.filter_stats {
+        finder = finder.filter_stats(&stats);
+    }
+    let timings = Timings::new();
+    if config.timings {
+        finder = finder.timings(&timings);
+    }
+
+    // Spotlight answers what it can, the rest walks the tree.
+    #[cfg(target_os = "macos")]
+    if config.backend == "spotlight" {
+        match rustfind::spotlight::search(&finder, &spec) {
+            Some(Ok(found)) => {
+                for file in found {
+                    println!("matching file: {}", file);
+                }
+                if config.filter_stats {
+                    print_filter_stats(&stats);
+                }
+                return;
+            }
+            Some(Err(e)) => {
+                eprintln!("ERROR: {}", e);
+                std::process::exit(1);
+            }
+            None => eprintln!("WARNING: Spotlight can't answer this search, walking the tree instead."),
+        }
+    }
+
+    // Consume the finder and write the results. Plain matches are printed as
+    // they are found, everything else once the search is complete.
+    let (depth, output, fields) = (config.depth, config.output, &config.fields);
+    let (out_file, append) = (config.out.as_deref(), config.append);
+    let mut owners_changed = false;
+    // What was reported, for --fail-if-more-than.
+    let mut reported = 0;
+    let mut confirmations = config.interactive.then(|| Confirmations::new(Prompt::new(io::stdin().lock(), io::stderr())));
+    let result = if let Some(replace) = &config.replace {
+        let (apply, backup_suffix) = (config.apply, config.backup_suffix.as_deref());
+        let audit = config.audit_log.as_ref().map(AuditLog::open).transpose();
+        audit.and_then(|audit| finder.find(depth).and_then(|found| with_output(out_file, append, |out, _| {
+            reported = found.len();
+            replace_in(replace, &found, apply, backup_suffix, audit.as_ref(), confirmations.as_mut(), out)
+        })))
+    } else if let Some(destination) = &config.destination {
+        let (apply, journal) = (config.apply, config.journal.as_deref());
+        let audit = config.audit_log.as_ref().map(AuditLog::open).transpose();
+        audit.and_then(|audit| finder.find(depth).and_then(|found| with_output(out_file, append, |out, _| {
+            reported = found.len();
+            move_files(destination, &found, apply, journal, audit.as_ref(), confirmations.as_mut(), out)
+        })))
+    } else if config.cleanup_report {
+        finder.cleanup_report(depth).and_then(|report| with_output(out_file, append, |out, _| {
+            reported = report.rows.len();
+            out.write_all(report.to_text().as_bytes())
+        }))
+    } else if let Some(by) = config.histogram {
+        finder.histogram(depth, by).and_then(|histogram| with_output(out_file, append, |out, header| {
+            reported = histogram.files() as usize;
+            match output {
+                Some(format) => write_entries(format, header.then_some(&["bucket", "files", "bytes"]), histogram.to_values(), out),
+                None => out.write_all(histogram.to_text().as_bytes()),
+            }
+        }))
+    } else if config.retention_report {
+        finder.retention_report(depth).and_then(|report| with_output(out_file, append, |out, _| {
+            reported = report.files();
+            out.write_all(report.to_text().as_bytes())
+        }))
+    } else if config.usage_by_owner {
+        finder.usage_by_owner(depth).and_then(|usage| with_output(out_file, append, |out, _| {
+            reported = usage.files() as usize;
+            out.write_all(usage.to_text().as_bytes())
+        }))
+    } else if let Some(path) = &config.changed_owner_since {
+        let before = fs::read_to_string(path)
+            .and_then(|text| Manifest::from_json(&text))
+            .map_err(|e| Error::new(e.kind(), format!("Could not read the manifest {}: {}", path, e)));
+        before.and_then(|before| finder.snapshot(depth, false).map(|current| before.owner_changes(&current)))
+            .and_then(|changes| with_output(out_file, append, |out, _| {
+                owners_changed = !changes.is_empty();
+                reported = changes.len();
+                changes.iter().try_for_each(|change| {
+                    let ((uid, gid), (new_uid, new_gid)) = (change.before, change.after);
+                    writeln!(out, "{}: {}:{} -> {}:{}", change.path, uid, gid, new_uid, new_gid)
+                })
+            }))
+    } else if let Some(by) = config.unique_by {
+        let count_copies = config.count_copies;
+        finder.find_unique_by(depth, by).and_then(|unique| with_output(out_file, append, |out, _| {
+            reported = unique.len();
+            unique.iter().try_for_each(|(file, copies)| if count_copies {
+                writeln!(out, "{:7} {}", copies, file)
+            } else {
+                writeln!(out, "matching file: {}", file)
+            })
+        }))
+    } else if config.group_by_dir {
+        finder.find(depth).and_then(|found| with_output(out_file, append, |out, _| {
+            reported = found.len();
+            output::group_by_dir(&found).iter().try_for_each(|(dir, names)| {
+                writeln!(out, "{} ({})", dir, names.len())?;
+                names.iter().try_for_each(|name| writeln!(out, "    {}", name))
+            })
+        }))
+    } else if config.count_matches {
+        let pattern = config.contains.as_deref().unwrap();
+        finder.find_counting(depth, pattern, config.search_compressed).and_then(|counts| {
+            reported = counts.len();
+            with_output(out_file, append, |out, header| match output {
+                Some(format) => {
+                    let entries = counts.into_iter().map(|(file, count)| json!({ "path": file, "matches": count }));
+                    write_entries(format, header.then_some(&["path", "matches"]), entries, out)
+                }
+                None => counts.iter().try_for_each(|(file, count)| writeln!(out, "{}:{}", file, count)),
+            })
+        })
+    } else if let Some(format) = output {
+        let names: Vec<&str> = fields.fields().iter().map(|field| field.as_str()).collect();
+        finder.find_entries(depth, fields).and_then(|entries| {
+            reported = entries.len();
+            with_output(out_file, append, |out, header| {
+                write_entries(format, header.then_some(&names), entries, out)
+            })
+        })
+    } else if config.strip_prefix || config.show_inode || config.show_pids {
+        let (strip_prefix, show_inode) = (config.strip_prefix, config.show_inode);
+        #[cfg(any(target_os = "linux", windows))]
+        let pids_of = {
+            let open_files = if config.show_pids { open_files } else { None };
+            move |file: &FileInfo| open_files.as_ref().map(|open| open.pids(file))
+        };
+        #[cfg(not(any(target_os = "linux", windows)))]
+        let pids_of = |_: &FileInfo| None::<Vec<u32>>;
+        with_output(out_file, append, |out, _| {
+            let mut written = Ok(());
+            finder.for_each(depth, |file| {
+                let path = if strip_prefix { file.relative_path() } else { file.path() };
+                let path = if path.as_os_str().is_empty() { Path::new(".") } else { path };
+                let id = match show_inode.then(|| file.id()) {
+                    Some(Some((dev, ino))) => format!("{}:{} ", dev, ino),
+                    Some(None) => String::from("-:- "),
+                    None => String::new(),
+                };
+                written = match pids_of(file).filter(|pids| !pids.is_empty()) {
+                    Some(pids) => {
+                        let pids = pids.iter().map(|pid| pid.to_string()).collect::<Vec<_>>().join(", ");
+                        writeln!(out, "matching file: {}{} (pid {})", id, path.display(), pids)
+                    }
+                    None => writeln!(out, "matching file: {}{}", id, path.display()),
+                };
+                reported += 1;
+                if written.is_ok() { ControlFlow::Continue(()) } else { ControlFlow::Break(()) }
+            })?;
+            written
+        })
+    } else if out_file.is_some() {
+        finder.find(depth).and_then(|found| with_output(out_file, append, |out, _| {
+            reported = found.len();
+            found.iter().try_for_each(|file| writeln!(out, "matching file: {}", file))
+        }))
+    } else {
+        finder.print_find(depth).map(|found| reported = found.len())
     };
+    if let Err(e) = result {
+        eprintln!("ERROR: {}", e);
+        std::process::exit(1);
+    }
+    if let (Some(update), Some(path)) = (&baseline_update, &config.baseline) {
+        if let Err(e) = update.write(Path::new(path)) {
+            eprintln!("ERROR: Could not write the baseline {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(budget) = budget.filter(|budget| budget.exhausted()) {
+        eprintln!("WARNING: The scan budget of {} bytes ran out, {} files weren't searched by content.",
+                  budget.bytes(), budget.unread_files());
+    }
+    if let Some(entries) = spec.max_entries_per_dir {
+        for dir in skipped.report() {
+            eprintln!("WARNING: Skipped {}, which has more than {} entries.", dir.display(), entries);
+        }
+    }
+
+    if config.filter_stats {
+        print_filter_stats(&stats);
+    }
+    if config.timings {
+        print_timings(&timings);
+    }
+    if owners_changed {
+        std::process::exit(1);
+    }
+    if let Some(most) = config.fail_if_more_than.filter(|&most| reported > most) {
+        eprintln!("ERROR: More than {} matches: found {}.", most, reported);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(unix)]
+fn socket_of(matches: &ArgMatches) -> PathBuf {
+    matches.value_of("socket").map(PathBuf::from).unwrap_or_else(daemon::default_socket)
+}
+
+/// Indexes the given PATH and serves queries for it until asked to shut down.
+#[cfg(unix)]
+fn run_daemon(matches: &ArgMatches) {
+    let root = matches.value_of("PATH").unwrap().to_string();
+    let socket = socket_of(matches);
+    let mut daemon = Daemon::bind(root, &socket).unwrap_or_else(|e| {
+        eprintln!("ERROR: Could not start the daemon: {}", e);
+        std::process::exit(1);
+    });
+    if let Some(metrics) = serve_metrics(matches) {
+        daemon = daemon.metrics(&metrics);
+    }
+    if let Some(sandbox) = read_sandbox(matches) {
+        daemon = daemon.sandbox(sandbox);
+    }
+    eprintln!("indexed {} entries in {}, listening on {}",
+              daemon.index().len(), daemon.index().root(), socket.display());
+    if let Err(e) = daemon.run() {
+        eprintln!("ERROR: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Runs a search, on the daemon if --daemon is given.
+#[cfg(unix)]
+fn run_query(matches: &ArgMatches) {
+    let mut config = Config::from_matches(matches);
+    if !matches.is_present("daemon") {
+        search(config);
+        return;
+    }
+    if config.timings {
+        eprintln!("ERROR: --profile without a NAME times the walk, which --daemon doesn't make.");
+        std::process::exit(1);
+    }
+    let spec = config.search_spec();
+    let mut reported = 0;
+    let result = Client::connect(&socket_of(matches))
+        .and_then(|mut client| client.query(&spec))
+        .and_then(|files| with_output(config.out.as_deref(), config.append, |out, _| {
+            reported = files.len();
+            files.iter().try_for_each(|file| writeln!(out, "matching file: {}", file))
+        }));
+    if let Err(e) = result {
+        eprintln!("ERROR: {}", e);
+        std::process::exit(1);
+    }
+    if let Some(most) = config.fail_if_more_than.filter(|&most| reported > most) {
+        eprintln!("ERROR: More than {} matches: found {}.", most, reported);
+        std::process::exit(1);
+    }
+}
+
+/// Takes a snapshot of the matches of a search.
+fn run_snapshot(matches: &ArgMatches) {
+    let mut config = Config::from_matches(matches);
+    let spec = config.search_spec();
+    let result = Finder::from_spec(&spec)
+        .and_then(|finder| finder.hash_algo(config.hash_algo).snapshot(spec.depth, matches.is_present("hash")))
+        .and_then(|mut manifest| {
+            manifest.search = Some(spec.clone());
+            with_output(config.out.as_deref(), false, |out, _| writeln!(out, "{:#}", manifest.to_json()))
+        });
+    if let Err(e) = result {
+        eprintln!("ERROR: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Compares the matches of a search in two trees, exiting with 1 if they
+/// differ.
+fn run_diff(matches: &ArgMatches) {
+    let compare = Fields::parse(matches.value_of("compare").unwrap_or("size,mtime"))
+        .and_then(|fields| match fields.fields().iter().find(|field| matches!(field, Field::Path | Field::Perm)) {
+            Some(field) => Err(Error::new(io::ErrorKind::InvalidInput, format!("Cannot compare by {}.", field.as_str()))),
+            None => Ok(fields),
+        })
+        .unwrap_or_else(|e| {
+            eprintln!("ERROR: Invalid argument --compare: {}", e);
+            std::process::exit(1);
+        });
+    let mut config = Config::from_matches(matches);
+    let mut spec_a = config.search_spec();
+    if spec_a.extra_roots.len() != 1 {
+        eprintln!("ERROR: diff compares two trees, expected two paths.");
+        std::process::exit(1);
+    }
+    let mut spec_b = spec_a.clone();
+    spec_b.root = spec_a.extra_roots.remove(0);
+    spec_b.extra_roots.clear();
+    let hash = compare.fields().contains(&Field::Hash);
+    let snapshot = |spec: &SearchSpec| Finder::from_spec(spec).and_then(|finder| finder.hash_algo(config.hash_algo).snapshot(spec.depth, hash));
+    let comparison = snapshot(&spec_a).and_then(|a| Ok(a.compare_by(&snapshot(&spec_b)?, compare.fields())));
+    let result = comparison.and_then(|comparison| {
+        let differ = !comparison.is_empty();
+        with_output(config.out.as_deref(), config.append, |out, _| {
+            if matches.is_present("emit-sync-plan") {
+                let plan = SyncPlan::new(&spec_a.root, &spec_b.root, &comparison);
+                if config.output == Some(Format::Json) {
+                    write_entries(Format::Json, None, plan.to_json(), out)
+                } else {
+                    out.write_all(plan.to_shell().as_bytes())
+                }
+            } else if config.output == Some(Format::Json) {
+                writeln!(out, "{}", json!({
+                    "only_in_a": comparison.removed,
+                    "only_in_b": comparison.added,
+                    "differing": comparison.changed,
+                }))
+            } else {
+                for path in &comparison.removed {
+                    writeln!(out, "only in {}: {}", spec_a.root, path)?;
+                }
+                for path in &comparison.added {
+                    writeln!(out, "only in {}: {}", spec_b.root, path)?;
+                }
+                comparison.changed.iter().try_for_each(|path| writeln!(out, "differing: {}", path))
+            }
+        })
+        .map(|_| differ)
+    });
+    match result {
+        Ok(differ) => {
+            if differ {
+                std::process::exit(1);
+            }
+        }
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Checks the files of a snapshot against a new one, exiting with 1 if any
+/// changed.
+fn run_verify(matches: &ArgMatches) {
+    let path = matches.value_of("MANIFEST").unwrap();
+    let manifest = fs::read_to_string(path)
+        .and_then(|text| Manifest::from_json(&text))
+        .unwrap_or_else(|e| {
+            eprintln!("ERROR: Could not read the manifest {}: {}", path, e);
+            std::process::exit(1);
+        });
+    let mut spec = manifest.search.clone().unwrap_or_else(|| {
+        eprintln!("ERROR: The manifest {} doesn't say which search it is a snapshot of.", path);
+        std::process::exit(1);
+    });
+    if let Some(root) = matches.value_of("PATH") {
+        spec.root = root.to_string();
+    }
+    let hash = manifest.files.values().any(|entry| entry.hash.is_some());
+    let current = Finder::from_spec(&spec).and_then(|finder| finder.hash_algo(manifest.hash_algo).snapshot(spec.depth, hash)).unwrap_or_else(|e| {
+        eprintln!("ERROR: {}", e);
+        std::process::exit(1);
+    });
+    let comparison = manifest.compare(&current);
+    if matches.is_present("json") {
+        println!("{}", comparison.to_json());
+    } else {
+        for (change, paths) in [("added", &comparison.added), ("removed", &comparison.removed), ("changed", &comparison.changed)] {
+            for path in paths {
+                println!("{}: {}", change, path);
+            }
+        }
+    }
+    if !comparison.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+fn run_undo(matches: &ArgMatches) {
+    let undone = rename::undo(matches.value_of("JOURNAL").unwrap()).unwrap_or_else(|e| {
+        eprintln!("ERROR: {}", e);
+        std::process::exit(1);
+    });
+    for mv in &undone.restored {
+        println!("restored: {} -> {}", mv.to.display(), mv.from.display());
+    }
+    for (mv, reason) in &undone.skipped {
+        println!("skipped: {} ({})", mv.to.display(), reason);
+    }
+    if !undone.skipped.is_empty() {
+        std::process::exit(1);
+    }
+}
 
-    if let Some(pattern) = config.pattern {
-        finder = finder.matches_regex(&pattern);
+/// Answers searches over JSON-RPC on stdin and stdout until told to exit, or
+/// over gRPC with --grpc.
+fn run_serve(matches: &ArgMatches) {
+    let result_cache = match matches.is_present("cache") {
+        true => cache::default_dir().or_else(|| {
+            eprintln!("WARNING: Could not find the home directory, not caching the results.");
+            None
+        }),
+        false => None,
     };
+    let metrics = serve_metrics(matches);
+    let sandbox = read_sandbox(matches);
+    #[cfg(feature = "grpc")]
+    if let Some(addr) = matches.value_of("grpc") {
+        return run_grpc(addr, result_cache, metrics, sandbox);
+    }
+    let mut server = Server::new();
+    if let Some(dir) = result_cache {
+        server = server.result_cache(dir);
+    }
+    if let Some(metrics) = &metrics {
+        server = server.metrics(metrics);
+    }
+    if let Some(sandbox) = sandbox {
+        server = server.sandbox(sandbox);
+    }
+    if let Err(e) = server.serve(io::stdin().lock(), io::stdout()) {
+        eprintln!("ERROR: {}", e);
+        std::process::exit(1);
+    }
+}
 
-    // Consume the finder and print the results.
-    let _ = finder.print_find(config.depth);
+/// Answers searches over gRPC on `addr` until the process is killed.
+#[cfg(feature = "grpc")]
+fn run_grpc(addr: &str, result_cache: Option<PathBuf>, metrics: Option<Metrics>, sandbox: Option<Sandbox>) {
+    let mut server = GrpcServer::bind(addr).unwrap_or_else(|e| {
+        eprintln!("ERROR: {}", e);
+        std::process::exit(1);
+    });
+    if let Some(dir) = result_cache {
+        server = server.result_cache(dir);
+    }
+    if let Some(metrics) = &metrics {
+        server = server.metrics(metrics);
+    }
+    if let Some(sandbox) = sandbox {
+        server = server.sandbox(sandbox);
+    }
+    if let Ok(addr) = server.local_addr() {
+        eprintln!("answering gRPC calls on {}", addr);
+    }
+    if let Err(e) = server.run() {
+        eprintln!("ERROR: {}", e);
+        std::process::exit(1);
+    }
+}
 
+/// Serves the metrics of the daemon or server on the address of --metrics,
+/// if given.
+fn serve_metrics(matches: &ArgMatches) -> Option<Metrics> {
+    let addr = matches.value_of("metrics")?;
+    let metrics = Metrics::new();
+    match metrics::serve(addr, metrics.clone()) {
+        Ok(addr) => eprintln!("serving metrics on http://{}/metrics", addr),
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            std::process::exit(1);
+        }
+    }
+    Some(metrics)
 }
 
+/// Reads the sandbox of --sandbox, if given.
+fn read_sandbox(matches: &ArgMatches) -> Option<Sandbox> {
+    let path = matches.value_of("sandbox")?;
+    let sandbox = fs::read_to_string(path).and_then(|text| Sandbox::from_json(&text)).unwrap_or_else(|e| {
+        eprintln!("ERROR: Could not read the sandbox {}: {}", path, e);
+        std::process::exit(1);
+    });
+    Some(sandbox)
+}
+
+/// Sets up diagnostics on stderr according to the number of -v flags.
+fn set_verbosity(matches: &ArgMatches) {
+    let max_level = match matches.occurrences_of("verbose") {
+        0 => return,
+        1 => Level::Debug,
+        _ => Level::Trace,
+    };
+    let _ = trace::set_subscriber(StderrSubscriber { max_level });
+}
 
+/// Replaces every `--profile` followed by another option or by nothing, that
+/// is without a NAME, with `--timings`, which clap can tell from a
+/// `--profile NAME`.
+fn profile_without_name(mut args: Vec<OsString>) -> Vec<OsString> {
+    let end = args.iter().position(|arg| arg == "--").unwrap_or(args.len());
+    for i in 0..end {
+        let named = args.get(i + 1).is_some_and(|next| !next.to_string_lossy().starts_with('-'));
+        if args[i] == "--profile" && !named {
+            args[i] = OsString::from("--timings");
+        }
+    }
+    args
+}
+
+fn main() {
+    let matches = app().get_matches_from(profile_without_name(std::env::args_os().collect()));
+    set_verbosity(matches.subcommand().1.unwrap_or(&matches));
+    match matches.subcommand() {
+        #[cfg(unix)]
+        ("daemon", Some(matches)) => run_daemon(matches),
+        #[cfg(unix)]
+        ("query", Some(matches)) => run_query(matches),
+        ("snapshot", Some(matches)) => run_snapshot(matches),
+        ("diff", Some(matches)) => run_diff(matches),
+        ("verify", Some(matches)) => run_verify(matches),
+        ("undo", Some(matches)) => run_undo(matches),
+        ("serve", Some(matches)) => run_serve(matches),
+        _ => search(Config::from_matches(&matches)),
+    }
+}
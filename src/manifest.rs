@@ -0,0 +1,333 @@
+/*
+Snapshots of the files of a search, to check them later.
+
+A 'Manifest' records the size, modification time and, optionally, hash of
+every match of a search, see 'Finder::snapshot()', along with the search
+itself as a 'SearchSpec'. Running the search again and comparing the new
+snapshot with 'Manifest::compare()' tells which files were added, removed or
+changed since, which makes a lightweight integrity checker. Snapshots also
+record the user and group ids of the owner of every file, where the
+filesystem has them, and 'Manifest::owner_changes()' lists the files whose
+owner changed, e.g. to spot tampering or check a migration kept ownership.
+
+Hashes are SHA-256 unless the manifest names another 'HashAlgo', see the
+'digest' module, in which case checking it hashes files with that one too.
+
+Paths are recorded relative to the root they were found under, so that a
+snapshot of one copy of a tree can be compared with another copy elsewhere.
+
+A file has changed if its size did, or, when both snapshots have hashes, its
+hash did: only the contents matter then, not being touched. Without hashes, a
+different modification time counts as a change too. 'Manifest::compare_by()'
+compares files by the given fields instead, e.g. to diff two trees.
+
+ */
+
+use std::collections::BTreeMap;
+use std::io::{self, Error};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+use crate::fields::Field;
+use crate::filter::FileInfo;
+use serde_json::{json, Map, Value};
+use crate::digest::HashAlgo;
+use crate::spec::SearchSpec;
+
+
+/// What a snapshot records about a file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub size: u64,
+    /// The modification time in seconds since the epoch, if known.
+    pub mtime: Option<i64>,
+    /// The hash of the contents in hexadecimal, if hashed.
+    pub hash: Option<String>,
+    /// The user and group ids of the owner, if known.
+    pub owner: Option<(u32, u32)>,
+}
+
+impl Entry {
+
+    /// Describes `file`, hashing its contents with `hash` if set. Returns an
+    /// error if it can't be looked up, or read to be hashed.
+    pub fn of(file: &FileInfo, hash: Option<HashAlgo>) -> Result<Entry, Error> {
+        let meta = file.metadata().ok_or_else(|| Error::new(io::ErrorKind::NotFound, "no metadata"))?;
+        let mtime = meta.modified().map(|modified| match modified.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs() as i64,
+            Err(e) => -(e.duration().as_secs() as i64),
+        });
+        let hash = match hash {
+            Some(algo) if meta.is_file() => Some(file.file_system().open(file.path()).and_then(|contents| algo.read(contents))?),
+            _ => None,
+        };
+        Ok(Entry { size: meta.len(), mtime, hash, owner: meta.owner() })
+    }
+
+    /// Returns true if the file described by `other` isn't the same as this
+    /// one any more.
+    pub fn differs(&self, other: &Entry) -> bool {
+        if self.size != other.size {
+            return true;
+        }
+        match (&self.hash, &other.hash) {
+            (Some(hash), Some(other)) => hash != other,
+            _ => self.mtime != other.mtime,
+        }
+    }
+
+    /// Returns true if `other` differs from this entry in one of `fields`,
+    /// out of the size, the modification time and the hash. Missing hashes
+    /// don't differ.
+    pub fn differs_in(&self, other: &Entry, fields: &[Field]) -> bool {
+        fields.iter().any(|field| match field {
+            Field::Size => self.size != other.size,
+            Field::Mtime => self.mtime != other.mtime,
+            Field::Hash => self.hash.is_some() && other.hash.is_some() && self.hash != other.hash,
+            Field::Path | Field::Perm | Field::Dev | Field::Inode | Field::FsType => false,
+        })
+    }
+}
+
+/// The files of a search and what they were like.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Manifest {
+    /// The search that found the files, to run it again.
+    pub search: Option<SearchSpec>,
+    /// What the files were hashed with, if they were.
+    pub hash_algo: HashAlgo,
+    /// The files by path, relative to their root.
+    pub files: BTreeMap<String, Entry>,
+}
+
+/// A file whose owner changed, see `Manifest::owner_changes()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OwnerChange {
+    pub path: String,
+    /// The user and group ids of the owner before and after.
+    pub before: (u32, u32),
+    pub after: (u32, u32),
+}
+
+/// How a snapshot differs from an earlier one, see `Manifest::compare()`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Comparison {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+impl Comparison {
+
+    /// Returns true if nothing changed.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// Returns the JSON representation of this comparison, an object with
+    /// the lists `added`, `removed` and `changed`.
+    pub fn to_json(&self) -> Value {
+        json!({ "added": self.added, "removed": self.removed, "changed": self.changed })
+    }
+}
+
+impl Manifest {
+
+    pub fn new() -> Manifest {
+        Manifest { search: None, hash_algo: HashAlgo::Sha256, files: BTreeMap::new() }
+    }
+
+    /// Records `path`, found below one of `roots`, as `entry`.
+    pub fn insert(&mut self, roots: &[String], path: &str, entry: Entry) {
+        let relative = roots.iter()
+            .find_map(|root| Path::new(path).strip_prefix(root).ok())
+            .filter(|relative| !relative.as_os_str().is_empty())
+            .map_or_else(|| path.to_string(), |relative| relative.to_string_lossy().into_owned());
+        self.files.insert(relative, entry);
+    }
+
+    /// What changed from `self` to `current`, in path order.
+    pub fn compare(&self, current: &Manifest) -> Comparison {
+        self.compare_with(current, Entry::differs)
+    }
+
+    /// Like `compare()`, with the files that differ in one of `fields` as
+    /// changed, see `Entry::differs_in()`.
+    pub fn compare_by(&self, current: &Manifest, fields: &[Field]) -> Comparison {
+        self.compare_with(current, |entry, now| entry.differs_in(now, fields))
+    }
+
+    /// The files of both `self` and `current` whose owner changed, in path
+    /// order. Files whose owner either snapshot doesn't know are left out.
+    pub fn owner_changes(&self, current: &Manifest) -> Vec<OwnerChange> {
+        self.files.iter()
+            .filter_map(|(path, entry)| {
+                let (before, after) = (entry.owner?, current.files.get(path)?.owner?);
+                Some(OwnerChange { path: path.clone(), before, after }).filter(|_| before != after)
+            })
+            .collect()
+    }
+
+    fn compare_with(&self, current: &Manifest, differs: impl Fn(&Entry, &Entry) -> bool) -> Comparison {
+        let mut comparison = Comparison::default();
+        for (path, entry) in &self.files {
+            match current.files.get(path) {
+                Some(now) if differs(entry, now) => comparison.changed.push(path.clone()),
+                Some(_) => {}
+                None => comparison.removed.push(path.clone()),
+            }
+        }
+        comparison.added = current.files.keys()
+            .filter(|path| !self.files.contains_key(*path))
+            .cloned()
+            .collect();
+        comparison
+    }
+
+    /// Parses a manifest from its JSON representation.
+    pub fn from_json(text: &str) -> Result<Manifest, Error> {
+        let value = serde_json::from_str::<Value>(text)?;
+        let search = value.get("search").map(SearchSpec::from_value).transpose()?;
+        let hash_algo = match value.get("hash_algo") {
+            Some(Value::String(algo)) => HashAlgo::parse(algo).map_err(|_| invalid(&format!("unknown 'hash_algo' {}", algo)))?,
+            Some(_) => return Err(invalid("'hash_algo' must be a string")),
+            None => HashAlgo::Sha256,
+        };
+        let mut files = BTreeMap::new();
+        let list = value.get("files")
+            .and_then(|files| files.as_array())
+            .ok_or_else(|| invalid("missing array 'files'"))?;
+        for file in list {
+            let path = file.get("path")
+                .and_then(|path| path.as_str())
+                .ok_or_else(|| invalid("every file must have a string 'path'"))?;
+            let size = file.get("size")
+                .and_then(|size| size.as_u64())
+                .ok_or_else(|| invalid(&format!("{} must have an integer 'size'", path)))?;
+            let mtime = match file.get("mtime") {
+                Some(Value::Null) | None => None,
+                Some(mtime) => Some(mtime.as_i64()
+                    .ok_or_else(|| invalid(&format!("the 'mtime' of {} must be an integer", path)))?),
+            };
+            let hash = match file.get("hash") {
+                Some(Value::String(hash)) => Some(hash.clone()),
+                Some(Value::Null) | None => None,
+                Some(_) => return Err(invalid(&format!("the 'hash' of {} must be a string", path))),
+            };
+            let id = |name| match file.get(name) {
+                Some(id) => id.as_u64().filter(|&id| id <= u32::MAX as u64).map(|id| Some(id as u32))
+                    .ok_or_else(|| invalid(&format!("the '{}' of {} must be an integer", name, path))),
+                None => Ok(None),
+            };
+            let owner = id("uid")?.zip(id("gid")?);
+            files.insert(path.to_string(), Entry { size, mtime, hash, owner });
+        }
+        Ok(Manifest { search, hash_algo, files })
+    }
+
+    /// Returns the JSON representation of this manifest.
+    pub fn to_json(&self) -> Value {
+        let files = self.files.iter()
+            .map(|(path, entry)| {
+                let mut members = json!({ "path": path, "size": entry.size, "mtime": entry.mtime });
+                if let Some(hash) = &entry.hash {
+                    members["hash"] = Value::from(hash.as_str());
+                }
+                if let Some((uid, gid)) = entry.owner {
+                    members["uid"] = Value::from(uid);
+                    members["gid"] = Value::from(gid);
+                }
+                members
+            })
+            .collect::<Vec<_>>();
+        let mut members = Map::new();
+        if let Some(search) = &self.search {
+            members.insert(String::from("search"), search.to_json());
+        }
+        if self.hash_algo != HashAlgo::Sha256 {
+            members.insert(String::from("hash_algo"), Value::from(self.hash_algo.as_str()));
+        }
+        members.insert(String::from("files"), Value::from(files));
+        Value::Object(members)
+    }
+}
+
+impl Default for Manifest {
+    fn default() -> Manifest {
+        Manifest::new()
+    }
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::new(io::ErrorKind::InvalidData, format!("Invalid manifest: {}.", reason))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(size: u64, mtime: i64, hash: Option<&str>) -> Entry {
+        Entry { size, mtime: Some(mtime), hash: hash.map(|hash| hash.to_string()), owner: None }
+    }
+
+    fn owned(uid: u32, gid: u32) -> Entry {
+        Entry { owner: Some((uid, gid)), ..entry(1, 100, None) }
+    }
+
+    #[test]
+    fn compares_snapshots() {
+        let roots = [String::from("./src")];
+        let mut before = Manifest::new();
+        before.insert(&roots, "./src/lib.rs", entry(10, 100, Some("aa")));
+        before.insert(&roots, "./src/main.rs", entry(20, 100, Some("bb")));
+        before.insert(&roots, "./src/old.rs", entry(30, 100, None));
+        let mut after = Manifest::new();
+        // Touched, but the same contents.
+        after.insert(&roots, "./src/lib.rs", entry(10, 200, Some("aa")));
+        after.insert(&roots, "./src/main.rs", entry(20, 100, Some("cc")));
+        after.insert(&roots, "./src/new.rs", entry(1, 100, None));
+        assert_eq!(vec!["lib.rs", "main.rs", "old.rs"], before.files.keys().collect::<Vec<_>>());
+        let comparison = before.compare(&after);
+        assert_eq!(vec!["new.rs"], comparison.added);
+        assert_eq!(vec!["old.rs"], comparison.removed);
+        assert_eq!(vec!["main.rs"], comparison.changed);
+        assert!(before.compare(&before).is_empty());
+        assert!(entry(1, 100, None).differs(&entry(1, 200, None)));
+        assert_eq!(vec!["lib.rs"], before.compare_by(&after, &[Field::Mtime]).changed);
+        assert_eq!(vec!["main.rs"], before.compare_by(&after, &[Field::Size, Field::Hash]).changed);
+    }
+
+    #[test]
+    fn lists_owner_changes() {
+        let roots = [String::from(".")];
+        let mut before = Manifest::new();
+        before.insert(&roots, "./bin/sh", owned(0, 0));
+        before.insert(&roots, "./etc/shadow", owned(0, 42));
+        before.insert(&roots, "./home/ana", owned(1000, 1000));
+        before.insert(&roots, "./tmp/unknown", entry(1, 100, None));
+        let mut after = before.clone();
+        after.insert(&roots, "./etc/shadow", owned(1000, 42));
+        after.insert(&roots, "./tmp/unknown", owned(1000, 1000));
+        after.files.remove("home/ana");
+        let change = OwnerChange { path: String::from("etc/shadow"), before: (0, 42), after: (1000, 42) };
+        assert_eq!(vec![change], before.owner_changes(&after));
+        assert_eq!(Vec::<OwnerChange>::new(), before.owner_changes(&before));
+    }
+
+    #[test]
+    fn round_trips_through_json() {
+        let mut manifest = Manifest::new();
+        manifest.search = Some(SearchSpec::new(String::from("src"), 3));
+        manifest.insert(&[String::from("src")], "src/a.rs", entry(3, 1000, Some("ab")));
+        manifest.insert(&[String::from("src")], "src/b.rs", Entry { size: 0, mtime: None, hash: None, owner: None });
+        manifest.insert(&[String::from("src")], "src/c.rs", owned(1000, 100));
+        let json = manifest.to_json().to_string();
+        assert_eq!(manifest, Manifest::from_json(&json).unwrap());
+        manifest.hash_algo = HashAlgo::Xxh3;
+        assert_eq!(manifest, Manifest::from_json(&manifest.to_json().to_string()).unwrap());
+        assert!(Manifest::from_json(r#"{"hash_algo":"md5","files":[]}"#).is_err());
+        assert!(json.ends_with(r#"{"path":"b.rs","size":0,"mtime":null},{"path":"c.rs","size":1,"mtime":100,"uid":1000,"gid":100}]}"#));
+        assert!(Manifest::from_json(r#"{"files":[{"path":"a"}]}"#).is_err());
+    }
+
+}
@@ -0,0 +1,228 @@
+/*
+Prometheus metrics of long-running servers.
+
+The daemon and the servers of the 'rpc' and 'grpc' modules count what their
+searches do in a 'Metrics' handed to them, which 'serve()' exposes to
+Prometheus over HTTP, in its text format, at /metrics:
+
+    ffind_directories_scanned_total    directories read by searches
+    ffind_files_matched_total          matches found
+    ffind_errors_total                 paths that couldn't be read, and
+                                       queries that failed
+    ffind_queries_total                queries answered
+    ffind_query_duration_seconds       a histogram of the time queries took
+
+Searches of a 'Finder' given the handle with 'Finder::metrics()' count the
+directories they read, their matches and the paths they couldn't read, and
+servers time their queries with 'Metrics::observe_query()'.
+
+ */
+
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Error, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+use crate::trace::{self, Level};
+
+
+/// The upper bounds of the buckets of the query latency histogram, in
+/// seconds, from a query answered from memory to a walk of a huge tree.
+const BUCKETS: [f64; 11] = [0.001, 0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0, 10.0, 30.0];
+
+/// What searches did, counted for Prometheus. Like `DiskUsage`, it is a cheap
+/// handle: clone it, hand one copy to the searches and expose the other with
+/// `serve()`.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    inner: Arc<Counters>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    directories: AtomicU64,
+    matches: AtomicU64,
+    errors: AtomicU64,
+    queries: AtomicU64,
+    /// How many queries took at most the bound of each bucket.
+    buckets: [AtomicU64; BUCKETS.len()],
+    /// The time all queries took, in microseconds.
+    micros: AtomicU64,
+}
+
+impl Metrics {
+
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    pub(crate) fn directory_scanned(&self) {
+        self.inner.directories.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn matched(&self, files: u64) {
+        self.inner.matches.fetch_add(files, Ordering::Relaxed);
+    }
+
+    pub(crate) fn error(&self) {
+        self.inner.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a query that took `elapsed`, counting an error unless it
+    /// `succeeded`.
+    pub fn observe_query(&self, elapsed: Duration, succeeded: bool) {
+        let inner = &self.inner;
+        inner.queries.fetch_add(1, Ordering::Relaxed);
+        inner.micros.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        for (bound, bucket) in BUCKETS.iter().zip(&inner.buckets) {
+            if elapsed.as_secs_f64() <= *bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        if !succeeded {
+            self.error();
+        }
+    }
+
+    pub fn directories_scanned(&self) -> u64 {
+        self.inner.directories.load(Ordering::Relaxed)
+    }
+
+    pub fn files_matched(&self) -> u64 {
+        self.inner.matches.load(Ordering::Relaxed)
+    }
+
+    pub fn errors(&self) -> u64 {
+        self.inner.errors.load(Ordering::Relaxed)
+    }
+
+    pub fn queries(&self) -> u64 {
+        self.inner.queries.load(Ordering::Relaxed)
+    }
+
+    /// The metrics in the text format of Prometheus.
+    pub fn render(&self) -> String {
+        let inner = &self.inner;
+        let mut text = String::new();
+        let counters = [
+            ("ffind_directories_scanned_total", "Directories read by searches.", &inner.directories),
+            ("ffind_files_matched_total", "Matches found by searches.", &inner.matches),
+            ("ffind_errors_total", "Paths that could not be read, and queries that failed.", &inner.errors),
+            ("ffind_queries_total", "Queries answered.", &inner.queries),
+        ];
+        for (name, help, counter) in counters {
+            let _ = writeln!(text, "# HELP {} {}\n# TYPE {} counter\n{} {}", name, help, name, name, counter.load(Ordering::Relaxed));
+        }
+        let name = "ffind_query_duration_seconds";
+        let _ = writeln!(text, "# HELP {} The time queries took.\n# TYPE {} histogram", name, name);
+        for (bound, bucket) in BUCKETS.iter().zip(&inner.buckets) {
+            let _ = writeln!(text, "{}_bucket{{le=\"{}\"}} {}", name, bound, bucket.load(Ordering::Relaxed));
+        }
+        let queries = inner.queries.load(Ordering::Relaxed);
+        let _ = writeln!(text, "{}_bucket{{le=\"+Inf\"}} {}", name, queries);
+        let _ = writeln!(text, "{}_sum {}", name, inner.micros.load(Ordering::Relaxed) as f64 / 1e6);
+        let _ = writeln!(text, "{}_count {}", name, queries);
+        text
+    }
+}
+
+/// Listens on `addr`, like `127.0.0.1:9100`. An address without a host, like
+/// `:9100`, listens on every interface.
+pub(crate) fn bind(addr: &str) -> Result<TcpListener, Error> {
+    match addr.strip_prefix(':') {
+        Some(port) => TcpListener::bind(format!("0.0.0.0:{}", port)),
+        None => TcpListener::bind(addr),
+    }.map_err(|e| Error::new(e.kind(), format!("Could not listen on {}: {}.", addr, e)))
+}
+
+/// Serves `metrics` at http://`addr`/metrics from a thread of its own, for
+/// as long as the process runs. Returns the address listened on.
+pub fn serve(addr: &str, metrics: Metrics) -> Result<SocketAddr, Error> {
+    let listener = bind(addr)?;
+    let local = listener.local_addr()?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream.and_then(|stream| {
+                stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+                let mut reader = BufReader::new(stream.try_clone()?);
+                let mut request = String::new();
+                reader.read_line(&mut request)?;
+                // The headers aren't needed, but are read so that the client
+                // doesn't see the connection reset.
+                let mut header = String::new();
+                while reader.read_line(&mut header)? > 2 {
+                    header.clear();
+                }
+                respond(stream, &request, &metrics)
+            });
+            if let Err(e) = result {
+                trace::event(Level::Debug, || format!("could not answer a metrics request: {}", e));
+            }
+        }
+    });
+    Ok(local)
+}
+
+fn respond(mut stream: impl Write, request: &str, metrics: &Metrics) -> Result<(), Error> {
+    let mut words = request.split_whitespace();
+    let (status, body) = match (words.next(), words.next().map(|target| target.split('?').next().unwrap_or_default())) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", metrics.render()),
+        (Some("GET"), _) => ("404 Not Found", String::from("Metrics are at /metrics.\n")),
+        _ => ("405 Method Not Allowed", String::new()),
+    };
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+           status, body.len(), body)?;
+    stream.flush()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpStream;
+    use crate::{dir, file, Finder};
+
+    #[test]
+    fn counts_searches() {
+        let metrics = Metrics::new();
+        let fs = dir! {
+            file!("README.md"),
+            "src" => { file!("lib.rs"), "walk" => { file!("mod.rs") } },
+        };
+        let found = Finder::new(String::from(".")).file_system(fs).has_extension(String::from(".rs")).metrics(&metrics).find_all().unwrap();
+        assert_eq!(2, found.len());
+        assert_eq!(3, metrics.directories_scanned());
+        assert_eq!(2, metrics.files_matched());
+        metrics.observe_query(Duration::from_millis(20), true);
+        metrics.observe_query(Duration::from_secs(2), false);
+        assert_eq!(1, metrics.errors());
+        let text = metrics.render();
+        assert!(text.contains("ffind_files_matched_total 2\n"));
+        assert!(text.contains("ffind_query_duration_seconds_bucket{le=\"0.05\"} 1\n"));
+        assert!(text.contains("ffind_query_duration_seconds_bucket{le=\"5\"} 2\n"));
+        assert!(text.contains("ffind_query_duration_seconds_sum 2.02\n"));
+    }
+
+    #[test]
+    fn serves_metrics_over_http() {
+        let metrics = Metrics::new();
+        metrics.matched(7);
+        let addr = serve("127.0.0.1:0", metrics).unwrap();
+        let get = |target: &str| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", target).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        let response = get("/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\r\n\r\n# HELP ffind_directories_scanned_total"));
+        assert!(response.contains("ffind_files_matched_total 7\n"));
+        assert!(get("/").starts_with("HTTP/1.1 404"));
+    }
+
+}
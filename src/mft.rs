@@ -0,0 +1,226 @@
+/*
+Enumerating NTFS volumes from their Master File Table.
+
+Every file of an NTFS volume has a record in its Master File Table, with its
+name and the record of its parent directory. 'MftFileSystem' reads all of
+them at once, like Everything does, with FSCTL_ENUM_USN_DATA on the volume,
+which takes seconds for millions of files, and then answers directory
+listings from memory instead of asking the filesystem directory by
+directory.
+
+Reading the table needs a handle on the volume itself, which Windows only
+gives to elevated processes; 'MftFileSystem::open()' fails otherwise, and
+'--backend mft' falls back to the usual enumeration. The table is read once,
+so files created during a search aren't found, and since its records have no
+sizes or times, metadata is still looked up through 'std::fs'.
+
+Only available on Windows.
+
+ */
+
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io::{self, Error, Read};
+use std::os::raw::c_void;
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use std::path::{Component, Path, PathBuf, Prefix};
+use std::sync::Mutex;
+use crate::filter::MetadataMode;
+use crate::trace::{self, Level};
+use crate::vfs::{FileSystem, Metadata, StdFileSystem};
+
+
+type Handle = *mut c_void;
+
+const INVALID_HANDLE_VALUE: Handle = -1isize as Handle;
+const GENERIC_READ: u32 = 0x8000_0000;
+const FILE_SHARE_READ: u32 = 1;
+const FILE_SHARE_WRITE: u32 = 2;
+const OPEN_EXISTING: u32 = 3;
+const FSCTL_ENUM_USN_DATA: u32 = 0x0009_00b3;
+const ERROR_HANDLE_EOF: i32 = 38;
+
+/// What the file reference numbers of records start with; the rest is a
+/// sequence number.
+const RECORD_MASK: u64 = 0x0000_ffff_ffff_ffff;
+/// The record of the root directory of every NTFS volume.
+const ROOT_RECORD: u64 = 5;
+
+/// How much of the table is read per request.
+const BUFFER: usize = 1024 * 1024;
+
+/// `MFT_ENUM_DATA_V0` from `<winioctl.h>`.
+#[repr(C)]
+struct MftEnumData {
+    start_file_reference_number: u64,
+    low_usn: i64,
+    high_usn: i64,
+}
+
+#[link(name = "kernel32")]
+extern "system" {
+    fn CreateFileW(file_name: *const u16, desired_access: u32, share_mode: u32, security_attributes: *mut c_void,
+                   creation_disposition: u32, flags_and_attributes: u32, template_file: Handle) -> Handle;
+    fn DeviceIoControl(device: Handle, io_control_code: u32, in_buffer: *const c_void, in_buffer_size: u32,
+                       out_buffer: *mut c_void, out_buffer_size: u32, bytes_returned: *mut u32,
+                       overlapped: *mut c_void) -> i32;
+    fn CloseHandle(object: Handle) -> i32;
+}
+
+/// An entry of a directory, as recorded in the table.
+struct Entry {
+    name: OsString,
+    record: u64,
+}
+
+/// An NTFS volume, listed from its Master File Table.
+pub struct MftFileSystem {
+    /// The drive letter of the volume.
+    letter: u8,
+    /// The entries of every directory, by record.
+    children: HashMap<u64, Vec<Entry>>,
+    /// The records of the entries listed so far, by path as listed.
+    listed: Mutex<HashMap<PathBuf, u64>>,
+}
+
+impl MftFileSystem {
+
+    /// Reads the Master File Table of the volume containing `path`.
+    pub fn open(path: &Path) -> Result<MftFileSystem, Error> {
+        let path = path.canonicalize()?;
+        let letter = drive_of(&path).ok_or_else(|| Error::new(io::ErrorKind::InvalidInput, "Not on a local drive."))?;
+        let volume: Vec<u16> = OsString::from(format!("\\\\.\\{}:", letter as char)).encode_wide().chain(Some(0)).collect();
+        // SAFETY: `volume` is NUL terminated.
+        let handle = unsafe {
+            CreateFileW(volume.as_ptr(), GENERIC_READ, FILE_SHARE_READ | FILE_SHARE_WRITE, std::ptr::null_mut(),
+                        OPEN_EXISTING, 0, std::ptr::null_mut())
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(Error::last_os_error());
+        }
+        let children = read_table(handle);
+        // SAFETY: `handle` is open, and closed only here.
+        unsafe { CloseHandle(handle) };
+        let children = children?;
+        trace::event(Level::Info, || format!("read the Master File Table of {}: ({} directories)", letter as char, children.len()));
+        Ok(MftFileSystem { letter, children, listed: Mutex::new(HashMap::new()) })
+    }
+
+    /// The record of the directory at `path`, if it is on this volume.
+    fn record_of(&self, path: &Path) -> Option<u64> {
+        if let Some(&record) = self.listed.lock().unwrap().get(path) {
+            return Some(record);
+        }
+        let path = path.canonicalize().ok()?;
+        if !drive_of(&path)?.eq_ignore_ascii_case(&self.letter) {
+            return None;
+        }
+        let mut record = ROOT_RECORD;
+        for component in path.components().skip_while(|c| matches!(c, Component::Prefix(_) | Component::RootDir)) {
+            let name = component.as_os_str().to_string_lossy().to_lowercase();
+            record = self.children.get(&record)?
+                .iter()
+                .find(|entry| entry.name.to_string_lossy().to_lowercase() == name)?
+                .record;
+        }
+        Some(record)
+    }
+}
+
+/// The drive letter of `path`, if it is on a local drive.
+fn drive_of(path: &Path) -> Option<u8> {
+    match path.components().next()? {
+        Component::Prefix(prefix) => match prefix.kind() {
+            Prefix::Disk(letter) | Prefix::VerbatimDisk(letter) => Some(letter),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Reads every record of the table of the volume open as `handle`, returning
+/// the entries of every directory.
+fn read_table(handle: Handle) -> Result<HashMap<u64, Vec<Entry>>, Error> {
+    let mut children: HashMap<u64, Vec<Entry>> = HashMap::new();
+    let mut request = MftEnumData { start_file_reference_number: 0, low_usn: 0, high_usn: i64::MAX };
+    let mut buffer = vec![0u8; BUFFER];
+    loop {
+        let mut len = 0u32;
+        // SAFETY: `request` is a `MFT_ENUM_DATA_V0` and `buffer` has `BUFFER` bytes.
+        let ok = unsafe {
+            DeviceIoControl(handle, FSCTL_ENUM_USN_DATA, &request as *const MftEnumData as *const c_void,
+                            std::mem::size_of::<MftEnumData>() as u32, buffer.as_mut_ptr() as *mut c_void,
+                            BUFFER as u32, &mut len, std::ptr::null_mut())
+        };
+        if ok == 0 {
+            let e = Error::last_os_error();
+            return if e.raw_os_error() == Some(ERROR_HANDLE_EOF) { Ok(children) } else { Err(e) };
+        }
+        let buffer = &buffer[..len as usize];
+        // The output starts with where the next request starts, followed by
+        // `USN_RECORD_V2`s.
+        request.start_file_reference_number = u64_at(buffer, 0);
+        let mut pos = 8;
+        while pos + 60 <= buffer.len() {
+            let record = &buffer[pos..];
+            let record_len = u32::from_le_bytes([record[0], record[1], record[2], record[3]]) as usize;
+            if record_len == 0 {
+                break;
+            }
+            let name_len = u16::from_le_bytes([record[56], record[57]]) as usize;
+            let name_offset = u16::from_le_bytes([record[58], record[59]]) as usize;
+            let name: Vec<u16> = record[name_offset..name_offset + name_len]
+                .chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .collect();
+            children.entry(u64_at(record, 16) & RECORD_MASK).or_default().push(Entry {
+                name: OsString::from_wide(&name),
+                record: u64_at(record, 8) & RECORD_MASK,
+            });
+            pos += record_len;
+        }
+    }
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    let mut le = [0; 8];
+    le.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(le)
+}
+
+impl FileSystem for MftFileSystem {
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>, Error> {
+        match self.record_of(path) {
+            Some(record) => {
+                let entries: Vec<_> = self.children.get(&record)
+                    .map(|entries| entries.iter().map(|entry| (path.join(&entry.name), entry.record)).collect())
+                    .unwrap_or_default();
+                let mut listed = self.listed.lock().unwrap();
+                listed.remove(path);
+                listed.extend(entries.iter().cloned());
+                Ok(entries.into_iter().map(|(path, _)| path).collect())
+            }
+            None => StdFileSystem.read_dir(path),
+        }
+    }
+
+    fn metadata(&self, path: &Path, mode: MetadataMode) -> Result<Metadata, Error> {
+        StdFileSystem.metadata(path, mode)
+    }
+
+    fn open(&self, path: &Path) -> Result<Box<dyn Read + Send>, Error> {
+        StdFileSystem.open(path)
+    }
+
+    fn read_at(&self, path: &Path, offset: u64, len: usize) -> Result<Vec<u8>, Error> {
+        StdFileSystem.read_at(path, offset, len)
+    }
+
+    fn id(&self, path: &Path, mode: MetadataMode) -> Option<(u64, u64)> {
+        StdFileSystem.id(path, mode)
+    }
+
+    fn fs_type(&self, path: &Path) -> Option<String> {
+        StdFileSystem.fs_type(path)
+    }
+}
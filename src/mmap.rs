@@ -0,0 +1,389 @@
+/*
+Scanning large files through memory maps, in parallel.
+
+Streaming a file through a buffer costs a copy and a system call per 64k, and
+a single thread to look at it all. Large regular files on local disks are
+instead mapped into memory with mmap(2): 'FileSystem::map()' returns an
+'Mmap' of the file where it can, and 'is_match()' splits it into chunks at
+line boundaries and searches them at once on a pool of threads shared by the
+whole process. Each chunk is searched as a whole, which lets the regex engine
+skip ahead with its SIMD literal search rather than starting over on every
+line; a match spanning lines is checked again on its first line alone, so
+the outcome is the same as matching the file line by line.
+
+Mapping is opt-in, with 'Finder::mmap()': a mapped file truncated by another
+process while it is searched kills the whole process with SIGBUS, which a
+search of a tree others write to can't rule out. Otherwise, filters read
+files through 'Unmapped', which never maps them.
+
+Files smaller than 'MIN_MAP_SIZE' aren't worth mapping, and neither are files
+on network or virtual filesystems (NFS, SMB, FUSE, /proc, /sys, ...), which
+might change under the map or not support it: those are read with buffered
+reads as before. Only the real filesystem maps files, on Unix.
+
+Searching maps needs the 'regex' feature; without it, files are only mapped.
+
+ */
+
+use std::fs::File;
+use std::io::{self, Error, Read};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+#[cfg(feature = "regex")]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "regex")]
+use std::sync::{mpsc, Mutex, OnceLock};
+#[cfg(feature = "regex")]
+use std::thread;
+#[cfg(feature = "regex")]
+use memchr::{memchr, memrchr};
+#[cfg(feature = "regex")]
+use regex::bytes::Regex;
+use crate::filter::MetadataMode;
+use crate::vfs::{DirEntries, FileSystem, Metadata};
+
+
+/// Files at least this large are mapped rather than read.
+pub const MIN_MAP_SIZE: u64 = 1 << 20;
+
+/// How much of a mapped file one thread searches at a time.
+#[cfg(feature = "regex")]
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// The contents of a file mapped into memory, read-only.
+#[derive(Debug)]
+pub struct Mmap {
+    ptr: *mut u8,
+    len: usize,
+}
+
+// SAFETY: the mapping is read-only and owned by `Mmap`, which only hands out
+// shared slices of it.
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+#[cfg(unix)]
+mod sys {
+    use std::os::raw::{c_int, c_void};
+
+    pub const PROT_READ: c_int = 1;
+    pub const MAP_PRIVATE: c_int = 2;
+    pub const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+    extern "C" {
+        pub fn mmap(addr: *mut c_void, len: usize, prot: c_int, flags: c_int, fd: c_int, offset: i64) -> *mut c_void;
+        pub fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    }
+}
+
+impl Mmap {
+
+    /// Maps the first `len` bytes of `file`, which must be at least that long
+    /// and not empty.
+    #[cfg(unix)]
+    pub fn map(file: &File, len: usize) -> Result<Mmap, Error> {
+        use std::os::unix::io::AsRawFd;
+        if len == 0 {
+            return Err(Error::new(io::ErrorKind::InvalidInput, "cannot map an empty file"));
+        }
+        // SAFETY: a new private read-only mapping of an open file, which
+        // doesn't alias any memory of ours.
+        let ptr = unsafe {
+            sys::mmap(std::ptr::null_mut(), len, sys::PROT_READ, sys::MAP_PRIVATE, file.as_raw_fd(), 0)
+        };
+        if ptr == sys::MAP_FAILED {
+            return Err(Error::last_os_error());
+        }
+        Ok(Mmap { ptr: ptr as *mut u8, len })
+    }
+
+    #[cfg(not(unix))]
+    pub fn map(_file: &File, _len: usize) -> Result<Mmap, Error> {
+        Err(Error::new(io::ErrorKind::Unsupported, "memory maps are only supported on Unix"))
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: `ptr` points to `len` mapped bytes, which live as long as
+        // `self`.
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        // SAFETY: `ptr` and `len` are those of a mapping nothing borrows any
+        // more.
+        unsafe {
+            sys::munmap(self.ptr as *mut _, self.len);
+        }
+    }
+}
+
+/// A `FileSystem` reading files of another one without ever mapping them,
+/// see the module documentation.
+pub struct Unmapped {
+    inner: Arc<dyn FileSystem>,
+}
+
+impl Unmapped {
+    pub fn new(inner: Arc<dyn FileSystem>) -> Unmapped {
+        Unmapped { inner }
+    }
+}
+
+impl FileSystem for Unmapped {
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>, Error> {
+        self.inner.read_dir(path)
+    }
+
+    fn read_dir_iter(&self, path: &Path) -> Result<DirEntries<'_>, Error> {
+        self.inner.read_dir_iter(path)
+    }
+
+    fn metadata(&self, path: &Path, mode: MetadataMode) -> Result<Metadata, Error> {
+        self.inner.metadata(path, mode)
+    }
+
+    fn open(&self, path: &Path) -> Result<Box<dyn Read + Send>, Error> {
+        self.inner.open(path)
+    }
+
+    fn read_at(&self, path: &Path, offset: u64, len: usize) -> Result<Vec<u8>, Error> {
+        self.inner.read_at(path, offset, len)
+    }
+
+    fn id(&self, path: &Path, mode: MetadataMode) -> Option<(u64, u64)> {
+        self.inner.id(path, mode)
+    }
+
+    fn fs_type(&self, path: &Path) -> Option<String> {
+        self.inner.fs_type(path)
+    }
+}
+
+/// Returns true if the file open as `file` is on a network or virtual
+/// filesystem, which shouldn't be mapped.
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+pub(crate) fn is_special(file: &File) -> bool {
+    use std::os::raw::c_int;
+    use std::os::unix::io::AsRawFd;
+    extern "C" {
+        fn fstatfs(fd: c_int, buf: *mut [i64; 16]) -> c_int;
+    }
+    const SPECIAL: [i64; 9] = [
+        0x6969,       // NFS
+        0x517b,       // SMB
+        0xff53_4d42,  // CIFS
+        0xfe53_4d42,  // SMB2
+        0x6573_5546,  // FUSE
+        0x9fa0,       // /proc
+        0x6265_6572,  // /sys
+        0x6462_6720,  // debugfs
+        0x7472_6163,  // tracefs
+    ];
+    // `struct statfs` is 120 bytes on 64 bit Linux, starting with the type of
+    // the filesystem.
+    let mut buf = [0i64; 16];
+    // SAFETY: `buf` is larger than a `struct statfs`.
+    if unsafe { fstatfs(file.as_raw_fd(), &mut buf) } != 0 {
+        return true;
+    }
+    SPECIAL.contains(&buf[0])
+}
+
+#[cfg(not(all(target_os = "linux", target_pointer_width = "64")))]
+pub(crate) fn is_special(_file: &File) -> bool {
+    false
+}
+
+
+#[cfg(feature = "regex")]
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Threads searching the chunks of mapped files, one per CPU, started on
+/// first use.
+#[cfg(feature = "regex")]
+struct ScanPool {
+    jobs: Mutex<mpsc::Sender<Job>>,
+}
+
+#[cfg(feature = "regex")]
+impl ScanPool {
+
+    fn get() -> &'static ScanPool {
+        static POOL: OnceLock<ScanPool> = OnceLock::new();
+        POOL.get_or_init(|| {
+            let (jobs, queue) = mpsc::channel::<Job>();
+            let queue = Arc::new(Mutex::new(queue));
+            let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+            for i in 0..threads {
+                let queue = queue.clone();
+                // A pool that can't start at least runs jobs on the threads
+                // it has; with none, `run()` runs them itself.
+                let _ = thread::Builder::new().name(format!("ffind-scan-{}", i)).spawn(move || loop {
+                    let job = match queue.lock().unwrap().recv() {
+                        Ok(job) => job,
+                        Err(_) => return,
+                    };
+                    job();
+                });
+            }
+            ScanPool { jobs: Mutex::new(jobs) }
+        })
+    }
+
+    fn run(&self, job: Job) {
+        if let Err(mpsc::SendError(job)) = self.jobs.lock().unwrap().send(job) {
+            job();
+        }
+    }
+}
+
+/// Returns true if a line of `map` matches `re`, searching chunks of it in
+/// parallel.
+#[cfg(feature = "regex")]
+pub fn is_match(map: Arc<Mmap>, re: &Regex) -> bool {
+    let candidates = match multi_line(re) {
+        Some(candidates) => Arc::new(candidates),
+        None => {
+            let text = map.strip_suffix(b"\n").unwrap_or(&map);
+            return text.split(|&b| b == b'\n').any(|line| re.is_match(line));
+        }
+    };
+    let found = Arc::new(AtomicBool::new(false));
+    let (done, results) = mpsc::channel();
+    let chunks = chunks(&map, CHUNK_SIZE);
+    let count = chunks.len();
+    for (start, end) in chunks {
+        let (map, re, candidates, found, done) = (map.clone(), re.clone(), candidates.clone(), found.clone(), done.clone());
+        ScanPool::get().run(Box::new(move || {
+            // Another chunk may already have settled it.
+            if !found.load(Ordering::Relaxed) && find_line(&map[start..end], &candidates, &re) {
+                found.store(true, Ordering::Relaxed);
+            }
+            let _ = done.send(());
+        }));
+    }
+    // Only the jobs hold senders now, so a job that panics can't leave us
+    // waiting forever.
+    drop(done);
+    for _ in 0..count {
+        if results.recv().is_err() || found.load(Ordering::Relaxed) {
+            break;
+        }
+    }
+    found.load(Ordering::Relaxed)
+}
+
+/// `re` with `^` and `$` matching at line boundaries, to search many lines
+/// at once, or `None` if it might anchor to the start or end of the text,
+/// which only a line at a time gets right.
+#[cfg(feature = "regex")]
+fn multi_line(re: &Regex) -> Option<Regex> {
+    let pattern = re.as_str();
+    if pattern.contains("\\A") || pattern.contains("\\z") || pattern.contains("-m") {
+        return None;
+    }
+    Regex::new(&format!("(?m){}", pattern)).ok()
+}
+
+/// Splits `text` into ranges of about `size` bytes that end after a newline,
+/// or at the end of `text`.
+#[cfg(feature = "regex")]
+fn chunks(text: &[u8], size: usize) -> Vec<(usize, usize)> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < text.len() {
+        let end = match memchr(b'\n', &text[(start + size).min(text.len())..]) {
+            Some(newline) if start + size < text.len() => start + size + newline + 1,
+            _ => text.len(),
+        };
+        chunks.push((start, end));
+        start = end;
+    }
+    chunks
+}
+
+/// Returns true if a line of `text` matches `re`, finding lines with the
+/// multi-line `candidates` first.
+#[cfg(feature = "regex")]
+fn find_line(text: &[u8], candidates: &Regex, re: &Regex) -> bool {
+    let mut at = 0;
+    while at <= text.len() {
+        let found = match candidates.find_at(text, at) {
+            Some(found) => found,
+            None => return false,
+        };
+        // After a last newline, there is no line to match anymore.
+        if found.start() == text.len() && (text.is_empty() || text.ends_with(b"\n")) {
+            return false;
+        }
+        let line_start = memrchr(b'\n', &text[..found.start()]).map_or(0, |newline| newline + 1);
+        let line_end = memchr(b'\n', &text[found.start()..]).map_or(text.len(), |newline| found.start() + newline);
+        // A match within a line is a match of the line; one across lines
+        // might not be, and the next chance is on the next line.
+        if found.end() <= line_end || re.is_match(&text[line_start..line_end]) {
+            return true;
+        }
+        at = line_end + 1;
+    }
+    false
+}
+
+
+#[cfg(all(test, feature = "regex"))]
+mod test {
+    use super::*;
+    use std::fs;
+
+    fn find(text: &str, pattern: &str) -> bool {
+        let re = Regex::new(pattern).unwrap();
+        find_line(text.as_bytes(), &multi_line(&re).unwrap(), &re)
+    }
+
+    #[test]
+    fn matches_like_lines() {
+        let text = "starting\nERROR: disk full\nok\n";
+        assert!(find(text, "^ERROR"));
+        assert!(find(text, "full$"));
+        assert!(!find(text, "^disk"));
+        assert!(!find(text, r"starting\sERROR"));
+        assert!(find(text, r"ok\s*"));
+        assert!(find("a\nb c", r"b\sc"));
+        assert!(!find("a\nb", r"a[^x]b"));
+        assert!(!find("", ""));
+        assert!(!find("a\n", "^$"));
+        assert!(find("a\n\n", "^$"));
+        assert!(multi_line(&Regex::new(r"\Afoo").unwrap()).is_none());
+    }
+
+    #[test]
+    fn chunks_end_at_lines() {
+        assert_eq!(vec![(0, 4), (4, 10)], chunks(b"aaa\nb\nccc\n", 3));
+        assert_eq!(vec![(0, 5)], chunks(b"aaaaa", 3));
+        assert_eq!(Vec::<(usize, usize)>::new(), chunks(b"", 3));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn scans_maps_in_parallel() {
+        let path = std::env::temp_dir().join(format!("ffind-mmap-{}", std::process::id()));
+        let mut text = "a line of text\n".repeat(300_000);
+        text.push_str("the needle\n");
+        fs::write(&path, &text).unwrap();
+        let file = File::open(&path).unwrap();
+        let map = Arc::new(Mmap::map(&file, text.len()).unwrap());
+        assert_eq!(text.as_bytes(), &map[..]);
+        assert!(is_match(map.clone(), &Regex::new("^the needle$").unwrap()));
+        assert!(!is_match(map.clone(), &Regex::new("^needle").unwrap()));
+        assert!(is_match(map, &Regex::new(r"\Athe").unwrap()));
+        fs::remove_file(&path).unwrap();
+    }
+
+}
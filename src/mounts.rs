@@ -0,0 +1,231 @@
+/*
+Trees mounted more than once.
+
+Bind mounts, and filesystems mounted twice, expose the same tree at several
+paths: containers see host directories under /var/lib, and /home may be
+reachable through /srv/home too. A search of / then walks those trees once
+per path, and reports of the space files take count them as many times.
+Files found twice are only reported once anyway, see 'Finder::dedup()',
+but they are still walked and counted.
+
+'MountTable' reads the mounts of the process from /proc/self/mountinfo,
+where every mount has the device of its filesystem and the directory of
+that filesystem it exposes, its root. A mount whose root is the root of
+another mount of the same device, or below it, exposes nothing new. With
+'Finder::dedup_mounts()', the search walks the one exposing the most of the
+tree, or the first mounted, and stays out of the others below its roots, see
+'DuplicateMounts'. A duplicate is only left out if what it duplicates is
+walked too, so a search of a bind mount alone still walks it.
+
+Only Linux has a mount table to read; elsewhere no mounts are known.
+
+ */
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+
+
+/// A mount of a filesystem, as /proc/self/mountinfo lists it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mount {
+    pub id: u32,
+    /// The major and minor numbers of the device of the filesystem.
+    pub dev: (u32, u32),
+    /// The directory of the filesystem the mount exposes, `/` for all of it.
+    pub root: PathBuf,
+    pub mount_point: PathBuf,
+    pub fs_type: String,
+}
+
+/// The mounts of the process, in the order they were mounted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MountTable {
+    mounts: Vec<Mount>,
+}
+
+/// The mount points a search stays out of, see the module documentation.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DuplicateMounts {
+    /// Every duplicate mount point, with where the tree it shows is walked.
+    points: HashMap<PathBuf, PathBuf>,
+    /// The names of the duplicate mount points, to only resolve the
+    /// directories that may be one.
+    names: HashSet<OsString>,
+}
+
+impl Mount {
+
+    /// Where this mount shows the tree of `other`, unless it doesn't or it is
+    /// where `other` is mounted, over what this one shows.
+    fn shows(&self, other: &Mount) -> Option<PathBuf> {
+        let below = other.root.strip_prefix(&self.root).ok().filter(|_| self.dev == other.dev)?;
+        let path = match below.as_os_str().is_empty() {
+            true => self.mount_point.clone(),
+            false => self.mount_point.join(below),
+        };
+        Some(path).filter(|path| *path != other.mount_point)
+    }
+}
+
+impl MountTable {
+
+    /// Parses a mount table in the format of /proc/PID/mountinfo. Lines that
+    /// can't be parsed are skipped.
+    pub fn parse(text: &str) -> MountTable {
+        let mounts = text.lines().filter_map(|line| {
+            let fields: Vec<&str> = line.split(' ').collect();
+            let separator = fields.iter().position(|&field| field == "-")?;
+            let (major, minor) = fields.get(2)?.split_once(':')?;
+            Some(Mount {
+                id: fields.first()?.parse().ok()?,
+                dev: (major.parse().ok()?, minor.parse().ok()?),
+                root: PathBuf::from(unescape(fields.get(3)?)),
+                mount_point: PathBuf::from(unescape(fields.get(4)?)),
+                fs_type: fields.get(separator + 1)?.to_string(),
+            })
+        }).collect();
+        MountTable { mounts }
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn read() -> Result<MountTable, Error> {
+        fs::read_to_string("/proc/self/mountinfo").map(|text| MountTable::parse(&text))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn read() -> Result<MountTable, Error> {
+        Ok(MountTable::default())
+    }
+
+    pub fn mounts(&self) -> &[Mount] {
+        &self.mounts
+    }
+
+    /// The mounts below `roots`, which are canonical paths, that expose a tree
+    /// another mount walked from them exposes too, including the mounts the
+    /// roots themselves are in. Of the mounts of a device, those with the
+    /// shortest root are walked first, then the first mounted. Mounts below a
+    /// duplicate aren't walked, so they don't count.
+    pub fn duplicates(&self, roots: &[PathBuf]) -> DuplicateMounts {
+        // What the mounts the roots are in show of them, then the mounts below.
+        let mut mounts: Vec<Mount> = roots.iter().filter_map(|root| {
+            let mount = self.mounts.iter().rev()
+                .filter(|mount| root.starts_with(&mount.mount_point) && *root != mount.mount_point)
+                .max_by_key(|mount| mount.mount_point.components().count())?;
+            let below = root.strip_prefix(&mount.mount_point).ok()?;
+            Some(Mount { root: mount.root.join(below), mount_point: root.clone(), ..mount.clone() })
+        }).collect();
+        mounts.extend(self.mounts.iter()
+            .filter(|mount| roots.iter().any(|root| mount.mount_point.starts_with(root)))
+            .cloned());
+        mounts.sort_by_key(|mount| mount.root.components().count());
+        let mut walked: Vec<&Mount> = Vec::new();
+        let mut duplicates = DuplicateMounts::default();
+        for mount in &mounts {
+            if duplicates.points.keys().any(|point| mount.mount_point.starts_with(point)) {
+                continue;
+            }
+            match walked.iter().find_map(|other| other.shows(mount)) {
+                Some(path) => duplicates.insert(mount.mount_point.clone(), path),
+                None => walked.push(mount),
+            }
+        }
+        duplicates
+    }
+}
+
+impl DuplicateMounts {
+
+    fn insert(&mut self, point: PathBuf, of: PathBuf) {
+        if let Some(name) = point.file_name() {
+            self.names.insert(name.to_os_string());
+        }
+        self.points.insert(point, of);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// The duplicate mount points, with where the trees they show are walked.
+    pub fn points(&self) -> impl Iterator<Item = (&Path, &Path)> {
+        self.points.iter().map(|(point, of)| (point.as_path(), of.as_path()))
+    }
+
+    /// Returns true if the directory at `dir` is a duplicate mount point.
+    /// Only directories named like one are resolved.
+    pub fn contains(&self, dir: &Path) -> bool {
+        match dir.file_name() {
+            Some(name) if !self.names.contains(name) => false,
+            _ => fs::canonicalize(dir).is_ok_and(|dir| self.points.contains_key(&dir)),
+        }
+    }
+}
+
+/// Undoes the octal escapes of spaces, tabs, newlines and backslashes in the
+/// paths of a mount table.
+fn unescape(field: &str) -> String {
+    let mut bytes = Vec::with_capacity(field.len());
+    let mut rest = field.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let octal = tail.get(..3)
+            .filter(|digits| byte == b'\\' && digits.iter().all(|digit| (b'0'..=b'7').contains(digit)))
+            .map(|digits| digits.iter().fold(0u32, |value, digit| value * 8 + (digit - b'0') as u32));
+        match octal {
+            Some(value) if value <= 0xff => {
+                bytes.push(value as u8);
+                rest = &tail[3..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MOUNTINFO: &str = "\
+22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+23 22 0:22 / /proc rw,relatime - proc proc rw
+24 22 8:2 / /home rw,relatime shared:2 - ext4 /dev/sda2 rw
+25 22 8:2 /ana /srv/ana rw,relatime shared:2 - ext4 /dev/sda2 rw
+26 22 8:1 /var/data /mnt/my\\040data rw,relatime - ext4 /dev/sda1 rw
+27 25 8:2 /ana/music /srv/ana/music rw,relatime - ext4 /dev/sda2 rw
+28 22 8:3 /backups /backups rw - xfs /dev/sda3 rw
+29 22 8:3 /backups /srv/backups rw - xfs /dev/sda3 rw
+not a mount
+";
+
+    #[test]
+    fn finds_bind_mounts() {
+        let table = MountTable::parse(MOUNTINFO);
+        assert_eq!(8, table.mounts().len());
+        assert_eq!(Mount { id: 26, dev: (8, 1), root: PathBuf::from("/var/data"), mount_point: PathBuf::from("/mnt/my data"), fs_type: String::from("ext4") },
+                   table.mounts()[4]);
+
+        let points = |duplicates: DuplicateMounts| {
+            let mut points: Vec<_> = duplicates.points().map(|(point, of)| (point.to_str().unwrap().to_string(), of.to_str().unwrap().to_string())).collect();
+            points.sort();
+            points
+        };
+        let pair = |point: &str, of: &str| (point.to_string(), of.to_string());
+        assert_eq!(vec![pair("/mnt/my data", "/var/data"), pair("/srv/ana", "/home/ana"), pair("/srv/backups", "/backups")],
+                   points(table.duplicates(&[PathBuf::from("/")])));
+        // What they duplicate isn't walked, and music is mounted over itself.
+        assert!(table.duplicates(&[PathBuf::from("/srv")]).is_empty());
+        assert_eq!(vec![pair("/srv/ana", "/home/ana")], points(table.duplicates(&[PathBuf::from("/home"), PathBuf::from("/srv/ana")])));
+        // /var is walked as part of /.
+        assert_eq!(vec![pair("/mnt/my data", "/var/data")], points(table.duplicates(&[PathBuf::from("/var"), PathBuf::from("/mnt")])));
+        assert_eq!("tab\there\\", unescape("tab\\011here\\134"));
+    }
+
+}
@@ -0,0 +1,321 @@
+/*
+Writing search results in machine readable formats.
+
+The entries of a search, JSON objects with the fields of every match (see the
+'fields' module), are written by a 'ResultWriter'. 'JsonLines' writes one
+object per line; 'Delimited' writes CSV or TSV, with a header row naming the
+fields, for spreadsheets. Other formats can be plugged in by implementing
+'ResultWriter'. Plain paths can be grouped by directory with 'group_by_dir()'.
+
+Results written to a file go through an 'AtomicFile', which only takes the
+place of the file once everything is written, so that nothing reading the
+file ever sees half of the results.
+
+Fields containing the delimiter, a double quote or a line break are quoted as
+in RFC 4180, doubling the double quotes inside them, in TSV as well as CSV:
+spreadsheets read TSV quoted that way, and a path with a tab in it can't
+shift the columns of a row.
+
+ */
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Error, Write};
+use std::path::{Path, PathBuf};
+use serde_json::Value;
+
+
+/// Something search results are written to, one entry at a time.
+pub trait ResultWriter {
+
+    /// Called once before the first entry with the names of the fields of
+    /// every entry, in order.
+    fn begin(&mut self, _fields: &[&str]) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Writes `entry`, a JSON object.
+    fn write(&mut self, entry: &Value) -> Result<(), Error>;
+
+    /// Called once after the last entry.
+    fn finish(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// The output formats ffind supports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Csv,
+    Tsv,
+}
+
+impl Format {
+
+    pub fn parse(name: &str) -> Result<Format, Error> {
+        match name {
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            "tsv" => Ok(Format::Tsv),
+            _ => Err(Error::new(io::ErrorKind::InvalidInput,
+                                format!("Unknown output format '{}', expected json, csv or tsv.", name))),
+        }
+    }
+
+    /// A writer of this format writing to `out`.
+    pub fn writer<'a>(self, out: impl Write + 'a) -> Box<dyn ResultWriter + 'a> {
+        match self {
+            Format::Json => Box::new(JsonLines::new(out)),
+            Format::Csv => Box::new(Delimited::csv(out)),
+            Format::Tsv => Box::new(Delimited::tsv(out)),
+        }
+    }
+}
+
+/// Writes every entry as a JSON object on its own line.
+pub struct JsonLines<W> {
+    out: W,
+}
+
+impl<W: Write> JsonLines<W> {
+    pub fn new(out: W) -> JsonLines<W> {
+        JsonLines { out }
+    }
+}
+
+impl<W: Write> ResultWriter for JsonLines<W> {
+    fn write(&mut self, entry: &Value) -> Result<(), Error> {
+        writeln!(self.out, "{}", entry)
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        self.out.flush()
+    }
+}
+
+/// Writes every entry as a row of fields separated by a delimiter, after a
+/// header row.
+pub struct Delimited<W> {
+    out: W,
+    delimiter: char,
+}
+
+impl<W: Write> Delimited<W> {
+
+    /// Comma separated values.
+    pub fn csv(out: W) -> Delimited<W> {
+        Delimited { out, delimiter: ',' }
+    }
+
+    /// Tab separated values.
+    pub fn tsv(out: W) -> Delimited<W> {
+        Delimited { out, delimiter: '\t' }
+    }
+
+    fn row<'a>(&mut self, cells: impl Iterator<Item = Cow<'a, str>>) -> Result<(), Error> {
+        let mut line = String::new();
+        for (i, cell) in cells.enumerate() {
+            if i > 0 {
+                line.push(self.delimiter);
+            }
+            if cell.contains([self.delimiter, '"', '\n', '\r']) {
+                line.push('"');
+                line.push_str(&cell.replace('"', "\"\""));
+                line.push('"');
+            } else {
+                line.push_str(&cell);
+            }
+        }
+        line.push_str("\r\n");
+        self.out.write_all(line.as_bytes())
+    }
+}
+
+impl<W: Write> ResultWriter for Delimited<W> {
+    fn begin(&mut self, fields: &[&str]) -> Result<(), Error> {
+        self.row(fields.iter().map(|&field| field.into()))
+    }
+
+    /// Strings are written as is and null as an empty field, other values as
+    /// JSON.
+    fn write(&mut self, entry: &Value) -> Result<(), Error> {
+        let members = match entry {
+            Value::Object(members) => members,
+            _ => return Err(Error::new(io::ErrorKind::InvalidInput, "can only write objects as rows")),
+        };
+        self.row(members.iter().map(|(_, value)| match value {
+            Value::String(s) => s.as_str().into(),
+            Value::Null => "".into(),
+            value => value.to_string().into(),
+        }))
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        self.out.flush()
+    }
+}
+
+/// Groups `paths` by the directory they are in, keeping the order in which
+/// paths and directories first come up.
+pub fn group_by_dir(paths: &[String]) -> Vec<(String, Vec<String>)> {
+    let mut groups: Vec<(String, Vec<String>)> = Vec::new();
+    let mut index = HashMap::new();
+    for path in paths {
+        let path = Path::new(path);
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_string_lossy().into_owned(),
+            _ => String::from("."),
+        };
+        let name = path.file_name().map_or_else(|| path.to_string_lossy(), |name| name.to_string_lossy()).into_owned();
+        let i = *index.entry(dir.clone()).or_insert_with(|| {
+            groups.push((dir, Vec::new()));
+            groups.len() - 1
+        });
+        groups[i].1.push(name);
+    }
+    groups
+}
+
+/// A file written under a temporary name next to its path, which it takes
+/// with a rename once `commit()` is called. If it isn't, e.g. because writing
+/// failed halfway, the file is left as it was.
+pub struct AtomicFile {
+    path: PathBuf,
+    temp: PathBuf,
+    file: Option<BufWriter<File>>,
+    committed: bool,
+}
+
+impl AtomicFile {
+
+    /// Starts writing the file at `path`, after its current contents if
+    /// `append` is set. The file keeps its permissions.
+    pub fn create(path: &Path, append: bool) -> Result<AtomicFile, Error> {
+        let temp = sibling(path, ".ffind-out");
+        let mut atomic = AtomicFile { path: path.to_path_buf(), temp, file: None, committed: false };
+        let mut file = File::create(&atomic.temp)?;
+        match File::open(path) {
+            Ok(mut existing) => {
+                fs::set_permissions(&atomic.temp, existing.metadata()?.permissions())?;
+                if append {
+                    io::copy(&mut existing, &mut file)?;
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        atomic.file = Some(BufWriter::new(file));
+        Ok(atomic)
+    }
+
+    /// Replaces the file with what was written.
+    pub fn commit(mut self) -> Result<(), Error> {
+        let file = self.file.take().unwrap().into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        fs::rename(&self.temp, &self.path)?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.as_mut().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.as_mut().unwrap().flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.temp);
+        }
+    }
+}
+
+/// A hidden temporary file next to `path`.
+pub(crate) fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    path.with_file_name(format!(".{}{}.{}", name, suffix, unique()))
+}
+
+/// The process id, which no other process writing the same file has.
+#[cfg(not(target_os = "wasi"))]
+fn unique() -> u32 {
+    std::process::id()
+}
+
+/// WASI has no process ids, the time has to do.
+#[cfg(target_os = "wasi")]
+fn unique() -> u128 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |time| time.as_nanos())
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn write(format: Format, entries: &[Value]) -> String {
+        let mut out = Vec::new();
+        {
+            let mut writer = format.writer(&mut out);
+            writer.begin(&["path", "size"]).unwrap();
+            for entry in entries {
+                writer.write(entry).unwrap();
+            }
+            writer.finish().unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    fn entry(path: &str, size: Option<u64>) -> Value {
+        json!({ "path": path, "size": size })
+    }
+
+    #[test]
+    fn quotes_delimiters() {
+        let entries = [entry("./a.txt", Some(3)), entry("./b, \"c\".txt", None), entry("./d\te", Some(0))];
+        assert_eq!("path,size\r\n./a.txt,3\r\n\"./b, \"\"c\"\".txt\",\r\n./d\te,0\r\n", write(Format::Csv, &entries));
+        assert_eq!("path\tsize\r\n./a.txt\t3\r\n\"./b, \"\"c\"\".txt\"\t\r\n\"./d\te\"\t0\r\n", write(Format::Tsv, &entries));
+        assert_eq!("{\"path\":\"./a.txt\",\"size\":3}\n", write(Format::Json, &entries[..1]));
+        assert!(Format::parse("xml").is_err());
+    }
+
+    #[test]
+    fn groups_by_dir() {
+        let paths: Vec<String> = ["./a/x.rs", "./b/y.rs", "./a/z.rs", "top.rs"].iter().map(|path| path.to_string()).collect();
+        let groups = group_by_dir(&paths);
+        assert_eq!(vec![(String::from("./a"), vec![String::from("x.rs"), String::from("z.rs")]),
+                        (String::from("./b"), vec![String::from("y.rs")]),
+                        (String::from("."), vec![String::from("top.rs")])], groups);
+    }
+
+    #[test]
+    fn files_are_replaced_once_complete() {
+        let path = std::env::temp_dir().join(format!("ffind-out-{}.txt", std::process::id()));
+        let write = |text: &str, append, commit| {
+            let mut file = AtomicFile::create(&path, append).unwrap();
+            file.write_all(text.as_bytes()).unwrap();
+            if commit {
+                file.commit().unwrap();
+            }
+        };
+        write("a\n", false, true);
+        write("b\n", true, true);
+        assert_eq!("a\nb\n", fs::read_to_string(&path).unwrap());
+        write("c\n", false, false);
+        assert_eq!("a\nb\n", fs::read_to_string(&path).unwrap());
+        write("c\n", false, true);
+        assert_eq!("c\n", fs::read_to_string(&path).unwrap());
+        assert!(!sibling(&path, ".ffind-out").exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+}
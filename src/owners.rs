@@ -0,0 +1,123 @@
+/*
+Reports of whose files take space.
+
+When a shared volume fills up, the question is whose files fill it. A
+'UsageByOwner' adds up the space the matches of a search take, and how many
+there are, per user that owns them, largest first, like quota reports do,
+see 'Finder::usage_by_owner()'. The space a file takes is its allocated
+size, as in the 'cleanup' module.
+
+Users are named as /etc/passwd names them; those it doesn't know are shown
+by their uid. Files whose owner isn't known, as on Windows, are left out.
+
+ */
+
+use std::collections::HashMap;
+use std::fs;
+use crate::cleanup::human;
+
+
+/// The matches owned by one user, see `UsageByOwner`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OwnerUsage {
+    pub uid: u32,
+    /// The name of the user, if known.
+    pub name: Option<String>,
+    pub files: u64,
+    /// The space the files take on disk, in bytes.
+    pub bytes: u64,
+}
+
+/// The space the matches of a search take per owner, largest first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsageByOwner {
+    pub owners: Vec<OwnerUsage>,
+}
+
+impl OwnerUsage {
+
+    /// The name of the user, or their uid if it isn't known.
+    pub fn owner(&self) -> String {
+        self.name.clone().unwrap_or_else(|| self.uid.to_string())
+    }
+}
+
+impl UsageByOwner {
+
+    /// Adds up `files`, the owners of files with the space they take, naming
+    /// the owners after `names`. Owners are ordered by the space their files
+    /// take, then by how many there are, then by uid.
+    pub fn new(files: impl IntoIterator<Item = (u32, u64)>, names: &HashMap<u32, String>) -> UsageByOwner {
+        let mut usage: HashMap<u32, (u64, u64)> = HashMap::new();
+        for (uid, allocated) in files {
+            let owner = usage.entry(uid).or_default();
+            owner.0 += 1;
+            owner.1 += allocated;
+        }
+        let mut owners: Vec<OwnerUsage> = usage.into_iter()
+            .map(|(uid, (files, bytes))| OwnerUsage { uid, name: names.get(&uid).cloned(), files, bytes })
+            .collect();
+        owners.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| b.files.cmp(&a.files)).then_with(|| a.uid.cmp(&b.uid)));
+        UsageByOwner { owners }
+    }
+
+    /// How many files all the owners have.
+    pub fn files(&self) -> u64 {
+        self.owners.iter().map(|owner| owner.files).sum()
+    }
+
+    /// The space all their files take.
+    pub fn bytes(&self) -> u64 {
+        self.owners.iter().map(|owner| owner.bytes).sum()
+    }
+
+    /// The report as a table, with a summary line at the end.
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{:>8}  {:>8}  OWNER\n", "SIZE", "FILES");
+        for owner in &self.owners {
+            text.push_str(&format!("{:>8}  {:>8}  {}\n", human(owner.bytes), owner.files, owner.owner()));
+        }
+        text.push_str(&format!("{} files of {} owners take {}\n", self.files(), self.owners.len(), human(self.bytes())));
+        text
+    }
+}
+
+/// The names of the users in `passwd`, in the format of /etc/passwd, by uid.
+/// Of users sharing a uid, the first one is kept.
+pub fn user_names(passwd: &str) -> HashMap<u32, String> {
+    let mut names = HashMap::new();
+    for fields in passwd.lines().map(|line| line.split(':').collect::<Vec<_>>()) {
+        if let Some(uid) = fields.get(2).and_then(|uid| uid.parse().ok()) {
+            names.entry(uid).or_insert_with(|| fields[0].to_string());
+        }
+    }
+    names
+}
+
+/// The names of the users of this system by uid, empty if /etc/passwd can't
+/// be read.
+pub fn read_user_names() -> HashMap<u32, String> {
+    fs::read_to_string("/etc/passwd").map(|passwd| user_names(&passwd)).unwrap_or_default()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn adds_up_per_owner() {
+        let names = user_names("root:x:0:0:root:/root:/bin/sh\nana:x:1000:1000::/home/ana:/bin/sh\ntoor:x:0:0::/:/bin/sh\n# not a user\n");
+        assert_eq!(2, names.len());
+        let files = vec![(1000, 1024), (0, 4096), (1001, 4096), (1000, 3 << 20), (1001, 0)];
+        let usage = UsageByOwner::new(files, &names);
+        let owners: Vec<(String, u64, u64)> = usage.owners.iter().map(|owner| (owner.owner(), owner.files, owner.bytes)).collect();
+        assert_eq!(vec![(String::from("ana"), 2, (3 << 20) + 1024), (String::from("1001"), 2, 4096), (String::from("root"), 1, 4096)], owners);
+        assert_eq!("    SIZE     FILES  OWNER\n\
+                    \x20   3.0M         2  ana\n\
+                    \x20   4.0K         2  1001\n\
+                    \x20   4.0K         1  root\n\
+                    5 files of 3 owners take 3.0M\n", usage.to_text());
+    }
+
+}
@@ -0,0 +1,142 @@
+/*
+How a search is going to run.
+
+A search walks its roots and checks every entry against its filters. Some
+filters also tell which directories nothing below can pass, like a glob of
+the whole path starting with `./src/`, see 'Filter::may_match_below()': the
+walk then stays out of the other directories instead of reading everything
+in them only to reject it. Filters are also rewritten to run faster, like a
+regex looking for an extension, `\.rs$`, into a comparison of the end of the
+name, see 'filter::extension_regex()'. 'Finder::plan()' describes what a
+search is going to walk and check, which is what '--explain-plan' prints.
+
+ */
+
+use std::fmt;
+use crate::UNLIMITED_DEPTH;
+use crate::walk::Traversal;
+
+
+/// What a search walks and checks, see `Finder::plan()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plan {
+    pub roots: Vec<String>,
+    pub traversal: Traversal,
+    /// The entries queued at most before walking depth first, see
+    /// `Finder::max_queued()`.
+    pub max_queued: Option<usize>,
+    pub max_depth: u32,
+    /// What keeps the walk out of directories, described.
+    pub pruning: Vec<String>,
+    /// The filters rewritten to run faster, described.
+    pub rewrites: Vec<String>,
+    /// The names of the filters every match passes, in the order they are
+    /// checked.
+    pub filters: Vec<String>,
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "roots: {}", self.roots.join(", "))?;
+        let traversal = match (self.traversal, self.max_queued) {
+            (Traversal::DepthFirst, _) => String::from("depth first"),
+            (Traversal::BreadthFirst, None) => String::from("breadth first"),
+            (Traversal::BreadthFirst, Some(entries)) => format!("breadth first, depth first past {} queued entries", entries),
+        };
+        match self.max_depth {
+            UNLIMITED_DEPTH => writeln!(f, "walk: {}, without a limit on the depth", traversal)?,
+            depth => writeln!(f, "walk: {}, up to depth {}", traversal, depth)?,
+        }
+        for pruning in &self.pruning {
+            writeln!(f, "prune: {}", pruning)?;
+        }
+        for rewrite in &self.rewrites {
+            writeln!(f, "rewrite: {}", rewrite)?;
+        }
+        if self.filters.is_empty() {
+            writeln!(f, "filter: none, every file matches")?;
+        }
+        for filter in &self.filters {
+            writeln!(f, "filter: {}", filter)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::io::{Error, Read};
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Mutex};
+    use crate::{dir, file, filter, Finder};
+    use crate::filter::MetadataMode;
+    use crate::spec::{FilterSpec, SearchSpec};
+    use crate::vfs::{FileSystem, MemoryFileSystem, Metadata};
+    use crate::walk::CACHEDIR_TAG;
+
+    /// Records the directories read.
+    struct Reads {
+        fs: MemoryFileSystem,
+        dirs: Arc<Mutex<Vec<PathBuf>>>,
+    }
+
+    impl FileSystem for Reads {
+        fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>, Error> {
+            self.dirs.lock().unwrap().push(path.to_path_buf());
+            self.fs.read_dir(path)
+        }
+
+        fn metadata(&self, path: &Path, mode: MetadataMode) -> Result<Metadata, Error> {
+            self.fs.metadata(path, mode)
+        }
+
+        fn open(&self, path: &Path) -> Result<Box<dyn Read + Send>, Error> {
+            self.fs.open(path)
+        }
+    }
+
+    #[test]
+    fn anchored_globs_narrow_the_walk() {
+        let fs = dir! {
+            "src" => { file!("lib.rs"), "bin" => { file!("main.rs") } },
+            "tests" => { file!("cli.rs") },
+            "docs" => { file!("guide.md") },
+        };
+        let finder = || Finder::new(String::from(".")).matches_glob_full_path("./src/**").has_extension(String::from(".rs"));
+        let plan = finder().prune_if_contains(CACHEDIR_TAG).plan(3);
+        assert_eq!(vec![
+            String::from("directories nothing below can pass path_matches_glob(\"./src/**\")"),
+            String::from("directories containing CACHEDIR.TAG"),
+        ], plan.pruning);
+        assert_eq!("roots: .\nwalk: breadth first, up to depth 3\n\
+                    prune: directories nothing below can pass path_matches_glob(\"./src/**\")\n\
+                    prune: directories containing CACHEDIR.TAG\n\
+                    filter: path_matches_glob(\"./src/**\")\nfilter: has_extension(\".rs\")\n", plan.to_string());
+
+        let dirs = Arc::new(Mutex::new(Vec::new()));
+        let found = finder().file_system(Reads { fs, dirs: dirs.clone() }).find(99).unwrap();
+        assert_eq!(vec!["./src/lib.rs", "./src/bin/main.rs"], found);
+        assert_eq!(vec![PathBuf::from("."), PathBuf::from("./src"), PathBuf::from("./src/bin")], *dirs.lock().unwrap());
+    }
+
+    #[test]
+    fn extension_regexes_become_comparisons() {
+        let fs = dir! { file!("a.rs"), file!("b.rs.bak"), file!("c.tar.gz"), file!("d.targz") };
+        let mut spec = SearchSpec::new(String::from("."), 1);
+        spec.filters.push(FilterSpec::Any(vec![
+            FilterSpec::MatchesRegex(String::from(r"\.rs$")),
+            FilterSpec::PathMatchesRegex(String::from(r"\.tar\.gz$")),
+        ]));
+        let finder = Finder::from_spec(&spec).unwrap().file_system(fs);
+        assert_eq!(vec![
+            String::from(r#"matches_regex("\\.rs$") as has_extension(".rs")"#),
+            String::from(r#"path_matches_regex("\\.tar\\.gz$") as has_extension(".tar.gz")"#),
+        ], finder.plan(1).rewrites);
+        assert_eq!(vec!["./a.rs", "./c.tar.gz"], finder.find(1).unwrap());
+        assert!(filter::extension_regex(r"\.rs\$", false).is_none());
+        assert!(filter::extension_regex(r"\.r[su]$", false).is_none());
+        assert!(filter::extension_regex(r"^\.rs$", false).is_none());
+    }
+
+}
@@ -0,0 +1,303 @@
+/*
+Searching remote trees over SSH.
+
+'RemoteFs' is a 'FileSystem' (see the 'vfs' module) whose files live on another
+machine. It drives a single shell session on the remote host, started with the
+system's ssh client, and answers every request with standard tools run in that
+session: 'find' to list directories, GNU 'stat' for metadata and 'cat' to read
+files. Nothing has to be installed on the remote host besides a POSIX shell
+and GNU coreutils/findutils, and authentication is whatever ssh is configured
+to do (keys, agents, ~/.ssh/config). The session is started in batch mode, so
+ssh fails instead of prompting for a password.
+
+Every request's output is framed by its exit status and length, so file names
+and contents can hold any bytes. Names are kept as the bytes they are on
+Unix; elsewhere, names that aren't valid UTF-8 fail their directory rather
+than turn into paths that don't exist on the host. Paths starting with '-'
+are given to the tools as './-...', so they aren't taken for options or find
+expressions.
+
+Remote locations are written like in scp, e.g. 'user@host:/var/log'.
+
+Only available with the 'remote' feature.
+
+ */
+
+use std::borrow::Cow;
+use std::io::{self, BufRead, BufReader, Error, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use crate::filter::MetadataMode;
+use crate::vfs::{FileSystem, FileType, Metadata};
+
+
+/// Splits an scp-like remote location, `[user@]host:path`, into the ssh
+/// destination and the path on the host. Returns `None` for anything that is
+/// a local path, i.e. has a `/` before the first `:`, for URLs like
+/// `s3://bucket`, and for destinations starting with `-`, which ssh would
+/// take for an option.
+pub fn parse_location(location: &str) -> Option<(&str, &str)> {
+    let colon = location.find(':')?;
+    let (destination, path) = (&location[..colon], &location[colon + 1..]);
+    if destination.is_empty() || destination.contains('/') || destination.starts_with('-') || path.starts_with("//") {
+        return None;
+    }
+    Some((destination, if path.is_empty() { "." } else { path }))
+}
+
+/// A filesystem on a remote host, reached over SSH.
+pub struct RemoteFs {
+    session: Mutex<Session>,
+}
+
+struct Session {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl RemoteFs {
+
+    /// Connects to `destination` (`[user@]host`) with the system's ssh client.
+    pub fn connect(destination: &str) -> Result<RemoteFs, Error> {
+        let mut command = Command::new("ssh");
+        command.args(["-T", "-o", "BatchMode=yes", "--", destination, "sh"]);
+        let fs = RemoteFs::spawn(command)?;
+        // Fail early, rather than on the first request, if the host isn't reachable.
+        fs.run(b"true").map_err(|e| Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("Could not connect to {}: {}", destination, e)))?;
+        Ok(fs)
+    }
+
+    /// Uses the shell started by `command` as the remote session, reading
+    /// requests from its stdin. This allows other transports than ssh, e.g.
+    /// `kubectl exec -i pod -- sh`.
+    pub fn spawn(mut command: Command) -> Result<RemoteFs, Error> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        Ok(RemoteFs { session: Mutex::new(Session { child, stdin, stdout }) })
+    }
+
+    /// Runs `command` in the remote shell and returns its output, or an error
+    /// if it exits with a non-zero status.
+    fn run(&self, command: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut session = self.session.lock().unwrap();
+        // The output goes through a temporary file so it can be sent with its
+        // length up front.
+        session.stdin.write_all(&[
+            b"f=$(mktemp); ( ",
+            command,
+            b" ) > \"$f\" 2>/dev/null; s=$?; echo \"$s $(wc -c < \"$f\")\"; cat \"$f\"; rm -f \"$f\"\n",
+        ].concat())?;
+        session.stdin.flush()?;
+        let mut header = String::new();
+        if session.stdout.read_line(&mut header)? == 0 {
+            return Err(Error::new(io::ErrorKind::UnexpectedEof, "The remote session closed."));
+        }
+        let mut fields = header.split_whitespace().map(|field| field.parse::<u64>());
+        let (status, len) = match (fields.next(), fields.next()) {
+            (Some(Ok(status)), Some(Ok(len))) => (status, len),
+            _ => return Err(Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unexpected answer from the remote session: {:?}", header))),
+        };
+        let mut output = vec![0; len as usize];
+        session.stdout.read_exact(&mut output)?;
+        if status != 0 {
+            return Err(Error::other(format!("'{}' failed with status {}", String::from_utf8_lossy(command), status)));
+        }
+        Ok(output)
+    }
+}
+
+impl Drop for RemoteFs {
+    fn drop(&mut self) {
+        if let Ok(session) = self.session.get_mut() {
+            let _ = writeln!(session.stdin, "exit");
+            let _ = session.child.wait();
+        }
+    }
+}
+
+impl FileSystem for RemoteFs {
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>, Error> {
+        let output = self.run(&[b"find ", &quote(path)[..], b" -mindepth 1 -maxdepth 1 -print0"].concat())?;
+        let mut entries = Vec::new();
+        for entry in output.split(|&b| b == 0).filter(|entry| !entry.is_empty()) {
+            if let Some(name) = path_of(entry)?.file_name() {
+                entries.push(path.join(name));
+            }
+        }
+        Ok(entries)
+    }
+
+    fn metadata(&self, path: &Path, mode: MetadataMode) -> Result<Metadata, Error> {
+        let follow: &[u8] = if mode == MetadataMode::Follow { b"-L " } else { b"" };
+        let output = self.run(&[b"stat ", follow, b"-c '%f %s %Y %d %i' -- ", &quote(path)[..]].concat())
+            .map_err(|_| Error::new(
+                io::ErrorKind::NotFound,
+                format!("No such file or directory: {}", path.display())))?;
+        parse_stat(&String::from_utf8_lossy(&output)).ok_or_else(|| Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unexpected stat output for {}", path.display())))
+    }
+
+    fn open(&self, path: &Path) -> Result<Box<dyn Read + Send>, Error> {
+        let contents = self.run(&[b"cat -- ", &quote(path)[..]].concat())?;
+        Ok(Box::new(io::Cursor::new(contents)))
+    }
+}
+
+/// Parses the `%f %s %Y %d %i` output of stat: the raw mode in hex, the size,
+/// the modification time in seconds since the epoch, the device and the inode.
+fn parse_stat(output: &str) -> Option<Metadata> {
+    let fields: Vec<&str> = output.split_whitespace().collect();
+    if fields.len() != 5 {
+        return None;
+    }
+    let mode = u32::from_str_radix(fields[0], 16).ok()?;
+    let file_type = match mode & 0o170000 {
+        0o100000 => FileType::File,
+        0o040000 => FileType::Dir,
+        0o120000 => FileType::Symlink,
+        _ => FileType::Other,
+    };
+    let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(fields[2].parse().ok()?);
+    Some(Metadata::new(file_type, fields[1].parse().ok()?, Some(modified))
+        .with_id(fields[3].parse().ok()?, fields[4].parse().ok()?)
+        .with_permissions(mode))
+}
+
+/// Quotes `path` for the shell, as `./-...` if it starts with `-`.
+fn quote(path: &Path) -> Vec<u8> {
+    let bytes = path_bytes(path);
+    let mut quoted = Vec::from(&b"'"[..]);
+    if bytes.starts_with(b"-") {
+        quoted.extend_from_slice(b"./");
+    }
+    for &b in bytes.iter() {
+        match b {
+            b'\'' => quoted.extend_from_slice(br"'\''"),
+            b => quoted.push(b),
+        }
+    }
+    quoted.push(b'\'');
+    quoted
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    Cow::Borrowed(path.as_os_str().as_bytes())
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    match path.to_string_lossy() {
+        Cow::Borrowed(path) => Cow::Borrowed(path.as_bytes()),
+        Cow::Owned(path) => Cow::Owned(path.into_bytes()),
+    }
+}
+
+/// The path named `name` by the remote host.
+#[cfg(unix)]
+fn path_of(name: &[u8]) -> Result<PathBuf, Error> {
+    use std::os::unix::ffi::OsStrExt;
+    Ok(PathBuf::from(std::ffi::OsStr::from_bytes(name)))
+}
+
+#[cfg(not(unix))]
+fn path_of(name: &[u8]) -> Result<PathBuf, Error> {
+    std::str::from_utf8(name).map(PathBuf::from).map_err(|_| Error::new(
+        io::ErrorKind::InvalidData,
+        format!("The remote name {} isn't valid UTF-8.", String::from_utf8_lossy(name))))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Finder;
+
+    /// A "remote" session that is just a local shell, working in `dir`.
+    fn local(dir: &Path) -> RemoteFs {
+        let mut shell = Command::new("sh");
+        shell.current_dir(dir);
+        RemoteFs::spawn(shell).unwrap()
+    }
+
+    /// Writes 'src/lib.rs' and 'src/remote.rs' into a temporary directory
+    /// named after `name`.
+    fn tree(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ffind-remote-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(dir.join("src/lib.rs"), "pub mod remote;\n").unwrap();
+        std::fs::write(dir.join("src/remote.rs"), "pub struct RemoteFs;\n").unwrap();
+        dir
+    }
+
+    #[test]
+    fn remote_locations() {
+        assert_eq!(Some(("me@host", "/var/log")), parse_location("me@host:/var/log"));
+        assert_eq!(Some(("host", ".")), parse_location("host:"));
+        assert_eq!(None, parse_location("./a:b"));
+        assert_eq!(None, parse_location("src"));
+        assert_eq!(None, parse_location("s3://bucket/logs"));
+        assert_eq!(None, parse_location("-oProxyCommand=touch pwned:path"));
+    }
+
+    #[test]
+    fn session_answers_requests() {
+        let dir = tree("session");
+        let fs = local(&dir);
+        let entries = fs.read_dir(Path::new("src")).unwrap();
+        assert!(entries.contains(&PathBuf::from("src/lib.rs")));
+        let meta = fs.metadata(Path::new("src/lib.rs"), MetadataMode::Follow).unwrap();
+        assert!(meta.is_file());
+        assert_eq!(std::fs::metadata(dir.join("src/lib.rs")).unwrap().len(), meta.len());
+        assert!(fs.metadata(Path::new("src/it's missing"), MetadataMode::Follow).is_err());
+        let mut contents = String::new();
+        fs.open(Path::new("src/remote.rs")).unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!("pub struct RemoteFs;\n", contents);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn names_reach_the_host_as_they_are() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        let dir = std::env::temp_dir().join(format!("ffind-remote-names-{}", std::process::id()));
+        let latin1 = OsStr::from_bytes(b"caf\xe9.txt");
+        std::fs::create_dir_all(dir.join("-delete")).unwrap();
+        std::fs::write(dir.join("-delete").join(latin1), "x").unwrap();
+        let fs = local(&dir);
+        // Not `find -delete ...`, which would delete everything.
+        let entries = fs.read_dir(Path::new("-delete")).unwrap();
+        assert_eq!(vec![Path::new("-delete").join(latin1)], entries);
+        assert_eq!(1, fs.metadata(&entries[0], MetadataMode::Follow).unwrap().len());
+        assert!(dir.join("-delete").join(latin1).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn search_over_session() {
+        let dir = tree("search");
+        let found = Finder::new(String::from("src"))
+            .file_system(local(&dir))
+            .matches_glob("remote*")
+            .find(1)
+            .unwrap();
+        assert_eq!(vec!["src/remote.rs"], found);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+}
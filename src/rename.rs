@@ -0,0 +1,486 @@
+/*
+Renaming and moving the files of a search, and undoing it.
+
+A 'Destination' says where every match goes: 'Destination::rename()' takes a
+regex and its replacement, written 'old=>new' like for 'Replace', applied to
+file names only, and 'Destination::move_to()' a directory the matches move
+into under their own names. 'Destination::plan()' works out every 'Move'
+before anything moves, and refuses plans that would lose files: two files
+can't take the same name, and existing files are never overwritten, not
+even those created after the plan was made, which fail their move. Files
+are moved deepest first, so renaming a directory doesn't get in the way of
+moving what is in it. Moves across filesystems copy files, then remove the
+originals; directories can't be moved across filesystems.
+
+Every move made is appended to a 'Journal', a JSON Lines file, with what the
+file looks like once moved: its size, modification time and, on Unix, its
+device and inode numbers. Paths that aren't valid UTF-8 are also written as
+the hexadecimal of their bytes, so they are moved back to exactly where they
+were. 'undo()' reads a journal back and moves the files back, last first,
+except those that changed since, or whose old name was taken in the
+meantime, which are left alone and reported.
+
+Regexes only rename valid UTF-8 names: a plan renaming a file with another
+name fails. Moves into a directory keep names as they are.
+
+Renaming needs the 'regex' feature.
+
+ */
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Error, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use regex::Regex;
+use crate::filter::{FileInfo, MetadataMode};
+use serde_json::{json, Value};
+use crate::sha256;
+
+
+/// Where the matches of a search are moved.
+#[derive(Debug, Clone)]
+pub enum Destination {
+    /// Renamed in place, replacing a regex in their names.
+    Rename { re: Regex, replacement: String },
+    /// Moved into a directory, keeping their names.
+    MoveTo(PathBuf),
+}
+
+/// A file to move, and where.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Move {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+/// What a file looked like once moved, to tell whether it changed since.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Stamp {
+    size: u64,
+    mtime: Option<i64>,
+    id: Option<(u64, u64)>,
+}
+
+/// A JSON Lines file recording the moves made, see the module documentation.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+}
+
+/// What `undo()` did.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Undone {
+    /// The moves undone, the file now back at `from`.
+    pub restored: Vec<Move>,
+    /// The moves left alone, and why.
+    pub skipped: Vec<(Move, String)>,
+}
+
+impl Destination {
+
+    /// Parses `old=>new`, renaming files by replacing the regex `old` in
+    /// their names with `new`, which can refer to its groups like `$1`.
+    pub fn rename(arg: &str) -> Result<Destination, Error> {
+        let (old, new) = arg.split_once("=>").ok_or_else(|| Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid renaming '{}', expected OLD=>NEW.", arg)))?;
+        let re = Regex::new(old).map_err(|e| Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid regex '{}': {}", old, e)))?;
+        Ok(Destination::Rename { re, replacement: new.to_string() })
+    }
+
+    pub fn move_to(dir: impl Into<PathBuf>) -> Destination {
+        Destination::MoveTo(dir.into())
+    }
+
+    /// Where `path` goes, or `None` if it stays where it is. Fails if its
+    /// new name isn't a name, or its name isn't valid UTF-8 to be renamed.
+    pub fn of(&self, path: &Path) -> Result<Option<PathBuf>, Error> {
+        let name = match path.file_name() {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        let to = match self {
+            Destination::Rename { re, replacement } => {
+                let name = name.to_str().ok_or_else(|| Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Cannot rename {}, whose name isn't valid UTF-8.", path.display())))?;
+                let new = re.replace_all(name, replacement.as_str());
+                if new == name {
+                    return Ok(None);
+                }
+                if new.is_empty() || new == "." || new == ".." || new.contains('/') || new.contains(std::path::MAIN_SEPARATOR) {
+                    return Err(Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Cannot rename {} to '{}', which isn't a file name.", path.display(), new)));
+                }
+                path.with_file_name(new.as_ref())
+            }
+            Destination::MoveTo(dir) => dir.join(name),
+        };
+        Ok(Some(to).filter(|to| to != path))
+    }
+
+    /// The moves of the files `found`, deepest first. Fails without moving
+    /// anything if two files would go to the same place, or one would
+    /// overwrite an existing file.
+    pub fn plan(&self, found: &[String]) -> Result<Vec<Move>, Error> {
+        let mut moves = Vec::new();
+        let mut taken: HashMap<PathBuf, PathBuf> = HashMap::new();
+        for file in found {
+            let from = PathBuf::from(file);
+            let to = match self.of(&from)? {
+                Some(to) => to,
+                None => continue,
+            };
+            if let Some(other) = taken.insert(to.clone(), from.clone()) {
+                return Err(Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("Both {} and {} would be moved to {}.", other.display(), from.display(), to.display())));
+            }
+            if fs::symlink_metadata(&to).is_ok() {
+                return Err(Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("Moving {} would overwrite {}.", from.display(), to.display())));
+            }
+            moves.push(Move { from, to });
+        }
+        moves.sort_by_key(|mv| std::cmp::Reverse(mv.from.components().count()));
+        Ok(moves)
+    }
+}
+
+impl Journal {
+
+    /// Opens the journal at `path` to append moves to it, creating it and
+    /// the directories it is in if they don't exist.
+    pub fn create(path: impl AsRef<Path>) -> Result<Journal, Error> {
+        let path = path.as_ref();
+        let opened = match path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            Some(dir) => fs::create_dir_all(dir),
+            None => Ok(()),
+        }.and_then(|_| OpenOptions::new().create(true).append(true).open(path));
+        let file = opened.map_err(|e| Error::new(e.kind(), format!("Cannot open the journal {}: {}.", path.display(), e)))?;
+        Ok(Journal { path: path.to_path_buf(), file })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Makes `mv` and records it. Nothing is recorded if it fails.
+    pub fn apply(&mut self, mv: &Move) -> Result<(), Error> {
+        move_file(&mv.from, &mv.to)?;
+        let stamp = Stamp::of(&mv.to).ok_or_else(|| Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} disappeared once moved.", mv.to.display())))?;
+        let mut entry = json!({ "from": mv.from.to_string_lossy(), "to": mv.to.to_string_lossy() });
+        for (name, path) in [("from_bytes", &mv.from), ("to_bytes", &mv.to)] {
+            if path.to_str().is_none() {
+                entry[name] = Value::from(sha256::hex(&path_bytes(path)));
+            }
+        }
+        entry["size"] = Value::from(stamp.size);
+        entry["mtime"] = Value::from(stamp.mtime);
+        if let Some((dev, ino)) = stamp.id {
+            entry["dev"] = Value::from(dev);
+            entry["inode"] = Value::from(ino);
+        }
+        let mut line = entry.to_string();
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.file.flush()
+    }
+}
+
+impl Stamp {
+
+    /// Describes `path`, or a symlink itself, if it exists.
+    fn of(path: &Path) -> Option<Stamp> {
+        let file = FileInfo::with_metadata_mode(path.to_path_buf(), MetadataMode::NoFollow);
+        let meta = file.metadata()?;
+        let mtime = meta.modified().map(|modified| match modified.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs() as i64,
+            Err(e) => -(e.duration().as_secs() as i64),
+        });
+        let id = if cfg!(unix) { file.id() } else { None };
+        Some(Stamp { size: meta.len(), mtime, id })
+    }
+
+    fn from_json(entry: &Value) -> Option<Stamp> {
+        let mtime = entry.get("mtime")?.as_i64();
+        let id = entry.get("dev").and_then(Value::as_u64).zip(entry.get("inode").and_then(Value::as_u64));
+        Some(Stamp { size: entry.get("size")?.as_u64()?, mtime, id })
+    }
+}
+
+/// Moves the files recorded in the journal at `path` back, last first,
+/// except those that changed since they were moved or whose old name was
+/// taken since. Fails only if the journal can't be read.
+pub fn undo(path: impl AsRef<Path>) -> Result<Undone, Error> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path)
+        .map_err(|e| Error::new(e.kind(), format!("Cannot read the journal {}: {}.", path.display(), e)))?;
+    let mut entries = Vec::new();
+    for (number, line) in text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+        let invalid = || Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid journal {}: line {} isn't a move.", path.display(), number + 1));
+        let entry = serde_json::from_str::<Value>(line).map_err(|_| invalid())?;
+        let path_of = |name: &str| match entry.get(format!("{}_bytes", name)) {
+            Some(bytes) => bytes.as_str().and_then(path_from_hex),
+            None => entry.get(name).and_then(Value::as_str).map(PathBuf::from),
+        };
+        let mv = path_of("from").zip(path_of("to")).map(|(from, to)| Move { from, to }).ok_or_else(invalid)?;
+        let stamp = Stamp::from_json(&entry).ok_or_else(invalid)?;
+        entries.push((mv, stamp));
+    }
+    let mut undone = Undone::default();
+    for (mv, stamp) in entries.into_iter().rev() {
+        let skipped = match Stamp::of(&mv.to) {
+            None => Some(String::from("it no longer exists")),
+            Some(now) if now != stamp => Some(String::from("it changed since it was moved")),
+            Some(_) if fs::symlink_metadata(&mv.from).is_ok() => Some(format!("{} exists", mv.from.display())),
+            Some(_) => move_file(&mv.to, &mv.from).err().map(|e| e.to_string()),
+        };
+        match skipped {
+            Some(reason) => undone.skipped.push((mv, reason)),
+            None => undone.restored.push(mv),
+        }
+    }
+    Ok(undone)
+}
+
+/// Where journals are kept unless told otherwise, a new file named after
+/// the time in `$XDG_STATE_HOME/ffind/undo` or `~/.local/state/ffind/undo`.
+pub fn default_journal() -> Option<PathBuf> {
+    let state = std::env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local").join("state")))?;
+    let since = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let name = format!("{}-{}.jsonl", since.as_secs(), std::process::id());
+    Some(state.join("ffind").join("undo").join(name))
+}
+
+/// The bytes of `path` as the system has them, on Windows its UTF-16 code
+/// units, little endian. Elsewhere, paths are taken as UTF-8.
+fn path_bytes(path: &Path) -> Vec<u8> {
+    #[cfg(unix)]
+    return std::os::unix::ffi::OsStrExt::as_bytes(path.as_os_str()).to_vec();
+    #[cfg(windows)]
+    return std::os::windows::ffi::OsStrExt::encode_wide(path.as_os_str()).flat_map(u16::to_le_bytes).collect();
+    #[cfg(not(any(unix, windows)))]
+    return path.to_string_lossy().into_owned().into_bytes();
+}
+
+/// The path whose `path_bytes()` are written in hexadecimal in `hex`.
+fn path_from_hex(hex: &str) -> Option<PathBuf> {
+    let bytes = (0..hex.len()).step_by(2)
+        .map(|i| hex.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect::<Option<Vec<u8>>>()?;
+    #[cfg(unix)]
+    return Some(PathBuf::from(<std::ffi::OsStr as std::os::unix::ffi::OsStrExt>::from_bytes(&bytes)));
+    #[cfg(windows)]
+    return {
+        let wide: Vec<u16> = bytes.chunks_exact(2).map(|unit| u16::from_le_bytes([unit[0], unit[1]])).collect();
+        Some(PathBuf::from(<std::ffi::OsString as std::os::windows::ffi::OsStringExt>::from_wide(&wide)))
+    };
+    #[cfg(not(any(unix, windows)))]
+    return String::from_utf8(bytes).ok().map(PathBuf::from);
+}
+
+/// Moves `from` to `to`, copying a file and removing the original if they
+/// are on different filesystems. Fails with `AlreadyExists` rather than
+/// overwrite whatever took the name `to` since the move was planned.
+fn move_file(from: &Path, to: &Path) -> Result<(), Error> {
+    let result = match rename_no_replace(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices && fs::symlink_metadata(from)?.is_file() => {
+            copy_new(from, to).and_then(|_| fs::remove_file(from))
+        }
+        result => result,
+    };
+    result.map_err(|e| match e.kind() {
+        io::ErrorKind::AlreadyExists => Error::new(e.kind(), format!("{} already exists.", to.display())),
+        _ => e,
+    })
+}
+
+/// Renames `from` to `to` unless `to` exists, at once where the filesystem
+/// can. Elsewhere files are linked to their new name before the old one is
+/// removed, and only directories are renamed after checking that `to` is
+/// free.
+fn rename_no_replace(from: &Path, to: &Path) -> Result<(), Error> {
+    #[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+    match linux::rename_no_replace(from, to) {
+        // Filesystems that can't rename without replacing.
+        Err(e) if matches!(e.raw_os_error(), Some(linux::EINVAL) | Some(linux::ENOSYS)) => {}
+        result => return result,
+    }
+    if !fs::symlink_metadata(from)?.is_dir() {
+        match fs::hard_link(from, to) {
+            Ok(()) => return fs::remove_file(from).inspect_err(|_| {
+                let _ = fs::remove_file(to);
+            }),
+            Err(e) if matches!(e.kind(), io::ErrorKind::AlreadyExists | io::ErrorKind::CrossesDevices) => return Err(e),
+            // Filesystems without hard links.
+            Err(_) => {}
+        }
+    }
+    if fs::symlink_metadata(to).is_ok() {
+        return Err(Error::from(io::ErrorKind::AlreadyExists));
+    }
+    fs::rename(from, to)
+}
+
+/// Copies the file `from` to `to`, with its permissions, unless `to` exists.
+fn copy_new(from: &Path, to: &Path) -> Result<(), Error> {
+    let mut source = File::open(from)?;
+    let mut target = OpenOptions::new().write(true).create_new(true).open(to)?;
+    let copied = io::copy(&mut source, &mut target)
+        .and_then(|_| source.metadata())
+        .and_then(|meta| target.set_permissions(meta.permissions()));
+    if copied.is_err() {
+        let _ = fs::remove_file(to);
+    }
+    copied
+}
+
+#[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod linux {
+    use std::ffi::CString;
+    use std::io::{self, Error};
+    use std::os::raw::c_long;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    #[cfg(target_arch = "x86_64")]
+    const SYS_RENAMEAT2: c_long = 316;
+    #[cfg(target_arch = "aarch64")]
+    const SYS_RENAMEAT2: c_long = 276;
+    const AT_FDCWD: c_long = -100;
+    const RENAME_NOREPLACE: c_long = 1;
+    pub const EINVAL: i32 = 22;
+    pub const ENOSYS: i32 = 38;
+
+    extern "C" {
+        fn syscall(number: c_long, ...) -> c_long;
+    }
+
+    /// renameat2(2) with `RENAME_NOREPLACE`.
+    pub fn rename_no_replace(from: &Path, to: &Path) -> Result<(), Error> {
+        let c_path = |path: &Path| CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| Error::new(io::ErrorKind::InvalidInput, "path contains a NUL byte"));
+        let (from, to) = (c_path(from)?, c_path(to)?);
+        // SAFETY: both paths are NUL-terminated and outlive the call.
+        let result = unsafe { syscall(SYS_RENAMEAT2, AT_FDCWD, from.as_ptr(), AT_FDCWD, to.as_ptr(), RENAME_NOREPLACE) };
+        if result == 0 { Ok(()) } else { Err(Error::last_os_error()) }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn plans_moves() {
+        let rename = Destination::rename(r"(\w+)\.jpeg$=>$1.jpg").unwrap();
+        assert_eq!(Some(PathBuf::from("photos/a.jpg")), rename.of(Path::new("photos/a.jpeg")).unwrap());
+        assert_eq!(None, rename.of(Path::new("photos/a.png")).unwrap());
+        assert!(Destination::rename(r"a=>x/y").unwrap().of(Path::new("a")).is_err());
+        assert!(Destination::rename("a").is_err());
+        let move_to = Destination::move_to("archive");
+        assert_eq!(Some(PathBuf::from("archive/a.png")), move_to.of(Path::new("photos/a.png")).unwrap());
+
+        let found = ["missing/x/b.jpeg", "missing/x.jpeg"].map(String::from);
+        let moves = rename.plan(&found).unwrap();
+        assert_eq!(vec![PathBuf::from("missing/x/b.jpg"), PathBuf::from("missing/x.jpg")],
+                   moves.into_iter().map(|mv| mv.to).collect::<Vec<_>>());
+        let found = ["missing/a/x.jpeg", "missing/b/x.jpeg"].map(String::from);
+        assert!(move_to.plan(&found).is_err());
+    }
+
+    #[test]
+    fn never_overwrites() {
+        let dir = std::env::temp_dir().join(format!("ffind-rename-taken-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        for name in ["a", "b", "sub/c"] {
+            fs::write(dir.join(name), name).unwrap();
+        }
+        // Taken since the move was planned.
+        for (from, to) in [("a", "b"), ("sub", "a"), ("a", "sub")] {
+            let e = move_file(&dir.join(from), &dir.join(to)).unwrap_err();
+            assert_eq!(io::ErrorKind::AlreadyExists, e.kind());
+        }
+        let e = copy_new(&dir.join("a"), &dir.join("b")).unwrap_err();
+        assert_eq!(io::ErrorKind::AlreadyExists, e.kind());
+        assert_eq!("a", fs::read_to_string(dir.join("a")).unwrap());
+        assert_eq!("b", fs::read_to_string(dir.join("b")).unwrap());
+        assert_eq!("sub/c", fs::read_to_string(dir.join("sub/c")).unwrap());
+        move_file(&dir.join("a"), &dir.join("d")).unwrap();
+        move_file(&dir.join("sub"), &dir.join("e")).unwrap();
+        copy_new(&dir.join("b"), &dir.join("f")).unwrap();
+        assert_eq!("a", fs::read_to_string(dir.join("d")).unwrap());
+        assert_eq!("sub/c", fs::read_to_string(dir.join("e/c")).unwrap());
+        assert_eq!("b", fs::read_to_string(dir.join("f")).unwrap());
+        assert!(!dir.join("a").exists() && !dir.join("sub").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn undoes_moves() {
+        let dir = std::env::temp_dir().join(format!("ffind-rename-{}", std::process::id()));
+        fs::create_dir_all(dir.join("photos/2024")).unwrap();
+        for name in ["a.jpeg", "b.jpeg", "2024/c.jpeg"] {
+            fs::write(dir.join("photos").join(name), name).unwrap();
+        }
+        let found = ["a.jpeg", "b.jpeg", "2024/c.jpeg", "2024"].map(|name| dir.join("photos").join(name).to_string_lossy().into_owned());
+        let rename = Destination::rename(r"\.jpeg$=>.jpg").unwrap();
+        let mut journal = Journal::create(dir.join("state/undo.jsonl")).unwrap();
+        let moves = rename.plan(&found).unwrap();
+        assert_eq!(3, moves.len());
+        for mv in &moves {
+            journal.apply(mv).unwrap();
+        }
+        assert!(dir.join("photos/2024/c.jpg").exists());
+        // a.jpg now exists, and is never overwritten.
+        assert!(rename.plan(&found[..1]).is_err());
+        fs::write(dir.join("photos/b.jpg"), "changed").unwrap();
+        fs::write(dir.join("photos/a.jpeg"), "new").unwrap();
+
+        let undone = undo(journal.path()).unwrap();
+        assert_eq!(vec![dir.join("photos/2024/c.jpeg")], undone.restored.iter().map(|mv| mv.from.clone()).collect::<Vec<_>>());
+        assert_eq!(2, undone.skipped.len());
+        assert_eq!("b", undone.skipped.iter().find(|(_, reason)| reason.contains("changed")).unwrap().0.from.file_stem().unwrap());
+        assert_eq!("2024/c.jpeg", fs::read_to_string(dir.join("photos/2024/c.jpeg")).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn keeps_names_that_are_not_utf8() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        let dir = std::env::temp_dir().join(format!("ffind-rename-bytes-{}", std::process::id()));
+        fs::create_dir_all(dir.join("archive")).unwrap();
+        let name = OsStr::from_bytes(b"caf\xe9.jpeg");
+        let from = dir.join(name);
+        fs::write(&from, "latin-1").unwrap();
+        assert!(Destination::rename(r"\.jpeg$=>.jpg").unwrap().of(&from).is_err());
+        let to = Destination::move_to(dir.join("archive")).of(&from).unwrap().unwrap();
+        assert_eq!(dir.join("archive").join(name), to);
+
+        let mut journal = Journal::create(dir.join("undo.jsonl")).unwrap();
+        journal.apply(&Move { from: from.clone(), to: to.clone() }).unwrap();
+        assert!(to.exists());
+        let undone = undo(journal.path()).unwrap();
+        assert_eq!(vec![Move { from: from.clone(), to }], undone.restored);
+        assert_eq!("latin-1", fs::read_to_string(&from).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+}
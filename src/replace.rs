@@ -0,0 +1,195 @@
+/*
+Replacing text in the files of a search, like sed -i.
+
+A 'Replace' is a regex and its replacement, written 'old=>new' on the command
+line. The replacement can refer to the groups of the regex, as '$1' or
+'${name}'. Like sed, the regex is applied to one line at a time, so it can't
+match across lines.
+
+'Replace::preview()' shows what would change as a diff, without touching the
+file; 'Replace::apply()' rewrites it. Files are never written in place: the
+new contents go to a temporary file next to the original, which then takes
+its place with a rename, so a file is either entirely replaced or left
+untouched, even if the search is interrupted. With a backup suffix, the
+original is kept under its name plus the suffix, like 'sed -i.bak'.
+
+Only text files are replaced: files that aren't valid UTF-8, or that have a
+NUL byte, are left alone. Replacing works on the real filesystem only, and
+needs the 'regex' feature.
+
+ */
+
+use std::fs;
+use std::io::{self, Error};
+use std::path::{Path, PathBuf};
+use regex::Regex;
+use crate::output;
+
+
+/// A regex and what to replace its matches with.
+#[derive(Debug, Clone)]
+pub struct Replace {
+    re: Regex,
+    replacement: String,
+}
+
+/// The lines of a file that a `Replace` changes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Changes {
+    /// The line number, counting from 1, the line and its replacement.
+    pub lines: Vec<(usize, String, String)>,
+    /// The number of matches replaced.
+    pub replacements: usize,
+    contents: String,
+}
+
+impl Replace {
+
+    pub fn new(re: Regex, replacement: &str) -> Replace {
+        Replace { re, replacement: replacement.to_string() }
+    }
+
+    /// Parses `old=>new`. Returns an error if there is no `=>` or `old` isn't
+    /// a valid regex.
+    pub fn parse(arg: &str) -> Result<Replace, Error> {
+        let (old, new) = arg.split_once("=>").ok_or_else(|| Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid replacement '{}', expected OLD=>NEW.", arg)))?;
+        let re = Regex::new(old).map_err(|e| Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid regex '{}': {}", old, e)))?;
+        Ok(Replace::new(re, new))
+    }
+
+    /// What replacing would change in `text`, or `None` if nothing.
+    pub fn changes(&self, text: &str) -> Option<Changes> {
+        let mut changes = Changes { lines: Vec::new(), replacements: 0, contents: String::with_capacity(text.len()) };
+        for (i, line) in text.split_inclusive('\n').enumerate() {
+            let (body, end) = match line.strip_suffix('\n') {
+                Some(body) => (body, "\n"),
+                None => (line, ""),
+            };
+            let matches = self.re.find_iter(body).count();
+            if matches == 0 {
+                changes.contents.push_str(line);
+                continue;
+            }
+            let replaced = self.re.replace_all(body, self.replacement.as_str()).into_owned();
+            changes.contents.push_str(&replaced);
+            changes.contents.push_str(end);
+            changes.replacements += matches;
+            if replaced != body {
+                changes.lines.push((i + 1, body.to_string(), replaced));
+            }
+        }
+        Some(changes).filter(|changes| !changes.lines.is_empty())
+    }
+
+    /// What replacing would change in the file at `path`, or `None` if
+    /// nothing, or if it isn't a text file.
+    pub fn preview(&self, path: &Path) -> Result<Option<Changes>, Error> {
+        let bytes = fs::read(path)?;
+        if bytes.contains(&0) {
+            return Ok(None);
+        }
+        match String::from_utf8(bytes) {
+            Ok(text) => Ok(self.changes(&text)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Replaces the matches in the file at `path`, keeping the original as
+    /// `path` plus `backup_suffix` if given. Returns the changes made, if any.
+    pub fn apply(&self, path: &Path, backup_suffix: Option<&str>) -> Result<Option<Changes>, Error> {
+        let changes = match self.preview(path)? {
+            Some(changes) => changes,
+            None => return Ok(None),
+        };
+        let temp = output::sibling(path, ".ffind-replace");
+        let written = fs::write(&temp, &changes.contents)
+            .and_then(|_| fs::set_permissions(&temp, fs::metadata(path)?.permissions()))
+            .and_then(|_| match backup_suffix {
+                Some(suffix) => {
+                    // The original itself becomes the backup, or a copy of it
+                    // if it can't be linked, e.g. if the backup exists.
+                    let backup = suffix_of(path, suffix);
+                    fs::hard_link(path, &backup).or_else(|_| fs::copy(path, &backup).map(|_| ()))
+                }
+                None => Ok(()),
+            })
+            .and_then(|_| fs::rename(&temp, path));
+        if let Err(e) = written {
+            let _ = fs::remove_file(&temp);
+            return Err(e);
+        }
+        Ok(Some(changes))
+    }
+}
+
+impl Changes {
+
+    /// The changes as a diff of `path`, one hunk per changed line.
+    pub fn diff(&self, path: &str) -> String {
+        let mut diff = format!("--- {}\n+++ {}\n", path, path);
+        for (number, old, new) in &self.lines {
+            diff.push_str(&format!("@@ -{} +{} @@\n-{}\n+{}\n", number, number, old, new));
+        }
+        diff
+    }
+}
+
+fn suffix_of(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("ffind-replace-{}-{}", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn changes_lines_with_groups() {
+        let replace = Replace::parse(r"(\w+)_old\(\)=>${1}_new()").unwrap();
+        let changes = replace.changes("a_old();\nkeep\nb_old(); c_old()").unwrap();
+        assert_eq!(3, changes.replacements);
+        assert_eq!(vec![(1, String::from("a_old();"), String::from("a_new();")),
+                        (3, String::from("b_old(); c_old()"), String::from("b_new(); c_new()"))], changes.lines);
+        assert_eq!("a_new();\nkeep\nb_new(); c_new()", changes.contents);
+        assert_eq!("--- f\n+++ f\n@@ -1 +1 @@\n-a_old();\n+a_new();\n@@ -3 +3 @@\n-b_old(); c_old()\n+b_new(); c_new()\n",
+                   changes.diff("f"));
+        assert_eq!(None, replace.changes("nothing to see"));
+        assert!(Replace::parse("no arrow").is_err());
+        assert!(Replace::parse("(=>x").is_err());
+    }
+
+    #[test]
+    fn applies_atomically_with_backups() {
+        let path = temp_file("apply.txt", "version = 1\nname = x\n");
+        let replace = Replace::parse(r"version = \d+=>version = 2").unwrap();
+        assert!(replace.preview(&path).unwrap().is_some());
+        assert_eq!("version = 1\nname = x\n", fs::read_to_string(&path).unwrap());
+        assert!(replace.apply(&path, Some(".bak")).unwrap().is_some());
+        assert_eq!("version = 2\nname = x\n", fs::read_to_string(&path).unwrap());
+        let backup = suffix_of(&path, ".bak");
+        assert_eq!("version = 1\nname = x\n", fs::read_to_string(&backup).unwrap());
+        assert_eq!(None, replace.apply(&path, None).unwrap());
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&backup).unwrap();
+    }
+
+    #[test]
+    fn binary_files_are_left_alone() {
+        let path = temp_file("binary", "version = 1\0");
+        assert_eq!(None, Replace::parse("1=>2").unwrap().apply(&path, None).unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+
+}
@@ -0,0 +1,153 @@
+/*
+Reports of old files, to decide how long to keep them.
+
+A retention policy, like deleting logs after a year, is easier to agree on
+knowing what it would delete: how much the files of every month take, and
+how much deleting them, and everything older, would free. A
+'RetentionReport' lists the matches of a search by the month they were last
+modified in, oldest first, with the space they take on disk and the running
+total, see 'Finder::retention_report()'. Combined with
+'Finder::older_than()', it shows what a policy of that age reclaims.
+
+Months are those of the UTC calendar, as in the 'histogram' module. Ages
+are given like '365d', see 'parse_age()'.
+
+ */
+
+use std::io::{self, Error};
+use std::time::{Duration, SystemTime};
+use crate::cleanup::human;
+use crate::histogram;
+
+
+/// The matches last modified in one month, see `RetentionReport`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Month {
+    /// The month, like `2024-06`, or `unknown` for the files whose
+    /// modification time isn't known.
+    pub label: String,
+    /// The files, in the order they were last modified.
+    pub files: Vec<String>,
+    /// The space they take on disk, in bytes.
+    pub bytes: u64,
+    /// The space this month and the ones before it take.
+    pub cumulative: u64,
+}
+
+/// The matches of a search by the month they were last modified in, oldest
+/// first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetentionReport {
+    pub months: Vec<Month>,
+}
+
+impl RetentionReport {
+
+    /// Groups `files`, paths with the space they take and when they were last
+    /// modified, by month. Files whose modification time isn't known come
+    /// last.
+    pub fn new(mut files: Vec<(String, u64, Option<SystemTime>)>) -> RetentionReport {
+        files.sort_by(|a, b| match (a.2, b.2) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            (a, b) => b.is_some().cmp(&a.is_some()),
+        }.then_with(|| a.0.cmp(&b.0)));
+        let mut months: Vec<Month> = Vec::new();
+        let mut cumulative = 0;
+        for (path, allocated, modified) in files {
+            let label = match modified.map(histogram::month) {
+                Some((year, month)) => format!("{:04}-{:02}", year, month),
+                None => String::from("unknown"),
+            };
+            cumulative += allocated;
+            match months.last_mut() {
+                Some(month) if month.label == label => {
+                    month.files.push(path);
+                    month.bytes += allocated;
+                    month.cumulative = cumulative;
+                }
+                _ => months.push(Month { label, files: vec![path], bytes: allocated, cumulative }),
+            }
+        }
+        RetentionReport { months }
+    }
+
+    /// How many files there are in all months.
+    pub fn files(&self) -> usize {
+        self.months.iter().map(|month| month.files.len()).sum()
+    }
+
+    /// The space they all take.
+    pub fn total(&self) -> u64 {
+        self.months.last().map_or(0, |month| month.cumulative)
+    }
+
+    /// The report as a line for every month, with its files indented below
+    /// it, and a summary line at the end.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for month in &self.months {
+            text.push_str(&format!("{}: {} files, {}, {} reclaimable up to here\n",
+                                   month.label, month.files.len(), human(month.bytes), human(month.cumulative)));
+            for file in &month.files {
+                text.push_str(&format!("    {}\n", file));
+            }
+        }
+        text.push_str(&format!("{} files take {}\n", self.files(), human(self.total())));
+        text
+    }
+}
+
+/// Parses an age given as a number of seconds (`s`), minutes (`m`), hours
+/// (`h`), days (`d`), weeks (`w`) or years of 365 days (`y`), like `365d`.
+pub fn parse_age(arg: &str) -> Result<Duration, Error> {
+    let invalid = || Error::new(io::ErrorKind::InvalidInput, format!("Invalid age '{}', expected a number and a unit like 90m, 12h, 365d or 2y.", arg));
+    let unit = arg.chars().last().ok_or_else(invalid)?;
+    let secs: u64 = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        'y' => 365 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    arg[..arg.len() - 1].parse::<u64>().ok()
+        .and_then(|n| n.checked_mul(secs))
+        .map(Duration::from_secs)
+        .ok_or_else(invalid)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn groups_by_month() {
+        let at = |secs: u64| Some(UNIX_EPOCH + Duration::from_secs(secs));
+        // 2024-02-29T12:00:00Z, 2024-02-01T00:00:00Z and 2023-12-31T23:59:59Z.
+        let files = vec![
+            (String::from("logs/b.log"), 4096, at(1_709_208_000)),
+            (String::from("logs/lost.log"), 1 << 20, None),
+            (String::from("logs/a.log"), 2048, at(1_706_745_600)),
+            (String::from("logs/old.log"), 1024, at(1_704_067_199)),
+        ];
+        let report = RetentionReport::new(files);
+        assert_eq!("2023-12: 1 files, 1.0K, 1.0K reclaimable up to here\n\
+                    \x20   logs/old.log\n\
+                    2024-02: 2 files, 6.0K, 7.0K reclaimable up to here\n\
+                    \x20   logs/a.log\n\
+                    \x20   logs/b.log\n\
+                    unknown: 1 files, 1.0M, 1.0M reclaimable up to here\n\
+                    \x20   logs/lost.log\n\
+                    4 files take 1.0M\n", report.to_text());
+
+        assert_eq!(Duration::from_secs(365 * 86400), parse_age("365d").unwrap());
+        assert_eq!(Duration::from_secs(90 * 60), parse_age("90m").unwrap());
+        assert!(parse_age("365").is_err());
+        assert!(parse_age("d").is_err());
+        assert!(parse_age("").is_err());
+    }
+
+}
@@ -0,0 +1,152 @@
+/*
+Retrying transient IO errors.
+
+Network filesystems have hiccups: a directory listing times out, a handle
+goes stale, a system call is interrupted. With 'Finder::retry()', the
+requests of a search that fail with such an error are tried again after a
+pause, which doubles after every failed attempt, rather than skipping the
+entry or failing the whole search.
+
+ */
+
+use std::io::{Error, ErrorKind};
+use std::thread;
+use std::time::Duration;
+use crate::trace::{self, Level};
+
+
+/// How often, and how patiently, failed requests are retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retries {
+    retries: u32,
+    backoff: Duration,
+}
+
+impl Retries {
+
+    /// Retries a failed request up to `retries` times, waiting `backoff`
+    /// before the first retry and twice as long before every next one.
+    pub fn new(retries: u32, backoff: Duration) -> Retries {
+        Retries { retries, backoff }
+    }
+
+    /// Runs `request`, retrying it while it fails with a transient error.
+    /// Returns the last error if all retries fail.
+    pub fn run<T>(&self, mut request: impl FnMut() -> Result<T, Error>) -> Result<T, Error> {
+        let mut backoff = self.backoff;
+        let mut retries = 0;
+        loop {
+            match request() {
+                Err(e) if retries < self.retries && is_transient(&e) => {
+                    trace::event(Level::Warn, || format!("retrying in {:?} after: {}", backoff, e));
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Returns true if the request failing with `e` may succeed if tried again.
+pub fn is_transient(e: &Error) -> bool {
+    matches!(e.kind(),
+        ErrorKind::Interrupted
+        | ErrorKind::WouldBlock
+        | ErrorKind::TimedOut
+        | ErrorKind::ConnectionReset
+        | ErrorKind::ConnectionAborted
+        | ErrorKind::StaleNetworkFileHandle
+        | ErrorKind::ResourceBusy)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashSet;
+    use std::io::Read;
+    use std::path::{Path, PathBuf};
+    use std::sync::Mutex;
+    use crate::Finder;
+    use crate::filter::MetadataMode;
+    use crate::vfs::{FileSystem, MemoryFileSystem, Metadata};
+
+    #[test]
+    fn retries_transient_errors() {
+        let retries = Retries::new(3, Duration::from_millis(1));
+        let mut failures = 2;
+        let result = retries.run(|| {
+            if failures > 0 {
+                failures -= 1;
+                return Err(Error::from(ErrorKind::Interrupted));
+            }
+            Ok("listed")
+        });
+        assert_eq!("listed", result.unwrap());
+
+        let mut attempts = 0;
+        let result: Result<(), Error> = retries.run(|| {
+            attempts += 1;
+            Err(Error::from(ErrorKind::TimedOut))
+        });
+        assert_eq!(ErrorKind::TimedOut, result.unwrap_err().kind());
+        assert_eq!(4, attempts);
+
+        // Permanent errors are reported right away.
+        let mut attempts = 0;
+        let result: Result<(), Error> = retries.run(|| {
+            attempts += 1;
+            Err(Error::from(ErrorKind::NotFound))
+        });
+        assert!(result.is_err());
+        assert_eq!(1, attempts);
+    }
+
+    /// A tree whose every request fails once before succeeding.
+    struct Flaky {
+        fs: MemoryFileSystem,
+        failed: Mutex<HashSet<(PathBuf, bool)>>,
+    }
+
+    impl Flaky {
+        fn hiccup(&self, path: &Path, listing: bool) -> Result<(), Error> {
+            match self.failed.lock().unwrap().insert((path.to_path_buf(), listing)) {
+                true => Err(Error::from(ErrorKind::TimedOut)),
+                false => Ok(()),
+            }
+        }
+    }
+
+    impl FileSystem for Flaky {
+        fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>, Error> {
+            self.hiccup(path, true)?;
+            self.fs.read_dir(path)
+        }
+
+        fn metadata(&self, path: &Path, mode: MetadataMode) -> Result<Metadata, Error> {
+            self.hiccup(path, false)?;
+            self.fs.metadata(path, mode)
+        }
+
+        fn open(&self, path: &Path) -> Result<Box<dyn Read + Send>, Error> {
+            self.fs.open(path)
+        }
+    }
+
+    #[test]
+    fn searches_retry_requests() {
+        let mut fs = MemoryFileSystem::new();
+        fs.add_file("src/lib.rs", "");
+        let flaky = || Flaky { fs: fs.clone(), failed: Mutex::new(HashSet::new()) };
+        let found = Finder::new(String::from("."))
+            .file_system(flaky())
+            .retry(Retries::new(1, Duration::from_millis(1)))
+            .find(99)
+            .unwrap();
+        assert_eq!(vec!["./src/lib.rs"], found);
+        assert!(Finder::new(String::from(".")).file_system(flaky()).find(99).is_err());
+    }
+
+}
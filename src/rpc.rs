@@ -0,0 +1,596 @@
+/*
+A JSON-RPC server for editors and GUIs.
+
+'ffind serve --stdio' runs as a long-lived child process of an editor plugin
+or a GUI, which drives searches with JSON-RPC 2.0 messages written to its
+stdin, reading the answers from its stdout. Messages are framed like in the
+Language Server Protocol, each preceded by its length in bytes:
+
+    Content-Length: 50\r\n
+    \r\n
+    {"jsonrpc":"2.0","id":1,"method":"initialize",...}
+
+The methods are:
+
+    initialize         {"roots": ["/home/ana/src/ffind"]}
+                       -> {"serverInfo": {...}, "capabilities": {...}}
+                       with an "api_key" too for a server with a sandbox,
+                       see 'Server::sandbox()'
+    ffind/query        a 'SearchSpec' (see the 'spec' module), searching the
+                       roots of initialize unless it has a "root"
+                       -> {"matches": ["/home/ana/src/ffind/src/lib.rs", ...]}
+    $/cancelRequest    {"id": 2}, a notification: the query of that id stops
+                       and fails with the RequestCancelled code, -32800
+    ffind/subscribe    like ffind/query, with an optional "interval_ms"
+                       -> {"subscription": 1, "matches": [...]}
+    ffind/unsubscribe  {"subscription": 1} -> null
+    shutdown           -> null once the queries running are answered, after
+                       which the exit notification stops the server
+
+A subscription sends an ffind/changed notification whenever the matches of
+its search change, {"subscription": 1, "added": [...], "removed": [...]}.
+Without a portable way to be told of changes, it runs its search again every
+"interval_ms" milliseconds, 1000 by default; with a result cache, see
+'Server::result_cache()', only the directories that changed are read again.
+
+Queries and subscriptions run in threads of their own, so that a long search
+holds up neither the others nor its cancellation.
+
+ */
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Error, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use crate::Finder;
+use serde_json::{json, Value};
+use crate::metrics::Metrics;
+use crate::sandbox::{Sandbox, Tenant};
+use crate::spec::SearchSpec;
+use crate::trace::{self, Level};
+use crate::walk::Cancel;
+
+
+/// The error codes of JSON-RPC and the Language Server Protocol.
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_NOT_INITIALIZED: i64 = -32002;
+const REQUEST_CANCELLED: i64 = -32800;
+/// A search that failed, like one of a root that doesn't exist.
+const SEARCH_FAILED: i64 = -32001;
+/// A client of a server with a sandbox that isn't allowed what it asked for.
+const ACCESS_DENIED: i64 = -32003;
+
+/// How often subscriptions search again unless told otherwise.
+const DEFAULT_INTERVAL: Duration = Duration::from_millis(1000);
+
+/// Where messages are written, shared by the threads answering requests.
+type Output = Arc<Mutex<Box<dyn Write + Send>>>;
+
+/// A JSON-RPC server answering searches, see the module documentation.
+#[derive(Debug, Default)]
+pub struct Server {
+    result_cache: Option<PathBuf>,
+    metrics: Option<Metrics>,
+    sandbox: Option<Sandbox>,
+}
+
+/// The state of a server while it runs.
+struct Session {
+    output: Output,
+    result_cache: Option<PathBuf>,
+    metrics: Option<Metrics>,
+    sandbox: Option<Sandbox>,
+    /// What the client may search, once initialized with a sandbox.
+    tenant: Option<Tenant>,
+    roots: Option<Vec<String>>,
+    shut_down: bool,
+    /// The queries running, by the JSON of their request id.
+    running: Arc<Mutex<HashMap<String, Cancel>>>,
+    subscriptions: HashMap<u64, Cancel>,
+    next_subscription: u64,
+    threads: Vec<JoinHandle<()>>,
+}
+
+/// A failed request, answered with a JSON-RPC error.
+#[derive(Debug)]
+struct Failure {
+    code: i64,
+    message: String,
+}
+
+impl Failure {
+    fn new(code: i64, message: impl Into<String>) -> Failure {
+        Failure { code, message: message.into() }
+    }
+}
+
+impl Server {
+
+    pub fn new() -> Server {
+        Server::default()
+    }
+
+    /// Saves the results of searches in `dir`, so that queries run again and
+    /// subscriptions only read the directories that changed, see the `cache`
+    /// module.
+    pub fn result_cache(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.result_cache = Some(dir.into());
+        self
+    }
+
+    /// Counts what the searches do and times the queries in `metrics`, see
+    /// the `metrics` module.
+    pub fn metrics(mut self, metrics: &Metrics) -> Self {
+        self.metrics = Some(metrics.clone());
+        self
+    }
+
+    /// Only lets the client search the roots its API key is allowed, given
+    /// as the "api_key" of initialize, see the `sandbox` module.
+    pub fn sandbox(mut self, sandbox: Sandbox) -> Self {
+        self.sandbox = Some(sandbox);
+        self
+    }
+
+    /// Answers the messages read from `input` on `output` until the exit
+    /// notification or the end of `input`, then stops the queries and
+    /// subscriptions still running. Returns an error if `input` can't be
+    /// read or isn't framed as it should.
+    pub fn serve(self, mut input: impl BufRead, output: impl Write + Send + 'static) -> Result<(), Error> {
+        let _span = trace::span("serve");
+        let mut session = Session {
+            output: Arc::new(Mutex::new(Box::new(output))),
+            result_cache: self.result_cache,
+            metrics: self.metrics,
+            sandbox: self.sandbox,
+            tenant: None,
+            roots: None,
+            shut_down: false,
+            running: Arc::new(Mutex::new(HashMap::new())),
+            subscriptions: HashMap::new(),
+            next_subscription: 1,
+            threads: Vec::new(),
+        };
+        let result = loop {
+            let text = match read_message(&mut input) {
+                Ok(Some(text)) => text,
+                Ok(None) => break Ok(()),
+                Err(e) => break Err(e),
+            };
+            let message = match serde_json::from_str::<Value>(&text) {
+                Ok(message) => message,
+                Err(e) => {
+                    session.answer(&Value::Null, Err(Failure::new(PARSE_ERROR, e.to_string())));
+                    continue;
+                }
+            };
+            if session.handle(&message).is_break() {
+                break Ok(());
+            }
+        };
+        session.stop();
+        result
+    }
+}
+
+impl Session {
+
+    /// Answers a single message, and returns `Break` for the exit notification.
+    fn handle(&mut self, message: &Value) -> std::ops::ControlFlow<()> {
+        use std::ops::ControlFlow::{Break, Continue};
+        trace::event(Level::Debug, || format!("message {}", message));
+        self.threads.retain(|thread| !thread.is_finished());
+        let id = message.get("id").cloned();
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+        let method = match message.get("method").and_then(|m| m.as_str()) {
+            Some(method) => method,
+            None => {
+                self.answer(&id.unwrap_or(Value::Null), Err(Failure::new(INVALID_REQUEST, "Missing 'method'.")));
+                return Continue(());
+            }
+        };
+        // Notifications aren't answered, not even when they fail.
+        let id = match (method, id) {
+            ("exit", _) => return Break(()),
+            ("$/cancelRequest", _) => {
+                if let Some(cancel) = params.get("id").and_then(|id| self.running.lock().unwrap().get(&id.to_string()).cloned()) {
+                    cancel.cancel();
+                }
+                return Continue(());
+            }
+            (_, Some(id)) => id,
+            (_, None) => {
+                trace::event(Level::Debug, || format!("ignoring the notification {}", method));
+                return Continue(());
+            }
+        };
+        if self.shut_down {
+            self.answer(&id, Err(Failure::new(INVALID_REQUEST, "The server is shutting down.")));
+            return Continue(());
+        }
+        if self.roots.is_none() && method != "initialize" {
+            self.answer(&id, Err(Failure::new(SERVER_NOT_INITIALIZED, "The server wasn't initialized.")));
+            return Continue(());
+        }
+        match method {
+            "initialize" => {
+                let result = self.initialize(&params);
+                self.answer(&id, result);
+            }
+            "ffind/query" => match self.spec(&params) {
+                Ok(spec) => self.query(id, spec),
+                Err(failure) => self.answer(&id, Err(failure)),
+            },
+            "ffind/subscribe" => match self.spec(&params) {
+                Ok(spec) => self.subscribe(id, spec, &params),
+                Err(failure) => self.answer(&id, Err(failure)),
+            },
+            "ffind/unsubscribe" => {
+                let result = match params.get("subscription").and_then(|s| s.as_u64()).and_then(|s| self.subscriptions.remove(&s)) {
+                    Some(cancel) => {
+                        cancel.cancel();
+                        Ok(Value::Null)
+                    }
+                    None => Err(Failure::new(INVALID_PARAMS, "Unknown 'subscription'.")),
+                };
+                self.answer(&id, result);
+            }
+            "shutdown" => {
+                self.shut_down = true;
+                self.finish();
+                self.answer(&id, Ok(Value::Null));
+            }
+            _ => self.answer(&id, Err(Failure::new(METHOD_NOT_FOUND, format!("Unknown method '{}'.", method)))),
+        }
+        Continue(())
+    }
+
+    fn initialize(&mut self, params: &Value) -> Result<Value, Failure> {
+        let roots = params.get("roots")
+            .and_then(|roots| roots.as_array())
+            .and_then(|roots| roots.iter().map(|root| root.as_str().map(|root| root.to_string())).collect::<Option<Vec<_>>>())
+            .filter(|roots| !roots.is_empty())
+            .ok_or_else(|| Failure::new(INVALID_PARAMS, "Expected an array of strings 'roots'."))?;
+        if let Some(sandbox) = &self.sandbox {
+            let tenant = sandbox.tenant(params.get("api_key").and_then(|key| key.as_str()))
+                .map_err(|e| Failure::new(ACCESS_DENIED, e.to_string()))?;
+            if let Some(root) = roots.iter().find(|root| !tenant.contains(root)) {
+                return Err(Failure::new(ACCESS_DENIED, format!("Searching '{}' isn't allowed.", root)));
+            }
+            self.tenant = Some(tenant);
+        }
+        trace::event(Level::Info, || format!("serving searches of {}", roots.join(", ")));
+        self.roots = Some(roots);
+        let methods = ["ffind/query", "ffind/subscribe", "ffind/unsubscribe", "$/cancelRequest"];
+        Ok(json!({
+            "serverInfo": { "name": "ffind", "version": env!("CARGO_PKG_VERSION") },
+            "capabilities": { "methods": methods },
+        }))
+    }
+
+    /// The search described by `params`, of the roots of `initialize` unless
+    /// it has a root of its own.
+    fn spec(&self, params: &Value) -> Result<SearchSpec, Failure> {
+        let mut members = params.as_object()
+            .ok_or_else(|| Failure::new(INVALID_PARAMS, "Expected a search spec."))?
+            .clone();
+        if params.get("root").is_none() {
+            let roots = self.roots.as_deref().unwrap_or_default();
+            members.insert(String::from("root"), Value::from(roots[0].as_str()));
+            members.insert(String::from("extra_roots"), Value::from(&roots[1..]));
+        }
+        SearchSpec::from_value(&Value::Object(members)).map_err(|e| Failure::new(INVALID_PARAMS, e.to_string()))
+    }
+
+    /// Runs `spec` in a thread of its own, answering request `id` with its
+    /// matches unless it is cancelled first.
+    fn query(&mut self, id: Value, spec: SearchSpec) {
+        let cancel = Cancel::new();
+        let key = id.to_string();
+        self.running.lock().unwrap().insert(key.clone(), cancel.clone());
+        let (output, running, result_cache, metrics) = (self.output.clone(), self.running.clone(), self.result_cache.clone(), self.metrics.clone());
+        let tenant = self.tenant.clone();
+        self.threads.push(thread::spawn(move || {
+            let started = Instant::now();
+            let result = search(&spec, tenant.as_ref(), result_cache.as_ref(), metrics.as_ref(), &cancel).map(|matches| json!({ "matches": matches }));
+            if let Some(metrics) = &metrics {
+                metrics.observe_query(started.elapsed(), result.is_ok());
+            }
+            running.lock().unwrap().remove(&key);
+            send(&output, &response(&id, result));
+        }));
+    }
+
+    /// Runs `spec` in a thread of its own, answering request `id` with its
+    /// matches, and then again every interval, notifying the changes.
+    fn subscribe(&mut self, id: Value, spec: SearchSpec, params: &Value) {
+        let interval = params.get("interval_ms").and_then(|ms| ms.as_u64()).map_or(DEFAULT_INTERVAL, Duration::from_millis);
+        let subscription = self.next_subscription;
+        self.next_subscription += 1;
+        let cancel = Cancel::new();
+        self.subscriptions.insert(subscription, cancel.clone());
+        let (output, result_cache, metrics) = (self.output.clone(), self.result_cache.clone(), self.metrics.clone());
+        let tenant = self.tenant.clone();
+        self.threads.push(thread::spawn(move || {
+            let mut matches = match search(&spec, tenant.as_ref(), result_cache.as_ref(), metrics.as_ref(), &cancel) {
+                Ok(matches) => matches,
+                Err(failure) => return send(&output, &response(&id, Err(failure))),
+            };
+            send(&output, &response(&id, Ok(json!({ "subscription": subscription, "matches": matches }))));
+            loop {
+                let started = Instant::now();
+                while started.elapsed() < interval {
+                    if cancel.is_cancelled() {
+                        return;
+                    }
+                    thread::sleep(interval.min(Duration::from_millis(50)));
+                }
+                let found = match search(&spec, tenant.as_ref(), result_cache.as_ref(), metrics.as_ref(), &cancel) {
+                    Ok(found) => found,
+                    Err(failure) if failure.code == REQUEST_CANCELLED => return,
+                    Err(failure) => {
+                        trace::event(Level::Warn, || format!("subscription {} failed: {}", subscription, failure.message));
+                        continue;
+                    }
+                };
+                let added: Vec<String> = found.iter().filter(|file| !matches.contains(file)).cloned().collect();
+                let removed: Vec<String> = matches.iter().filter(|file| !found.contains(file)).cloned().collect();
+                if !added.is_empty() || !removed.is_empty() {
+                    send(&output, &notification("ffind/changed", json!({
+                        "subscription": subscription,
+                        "added": added,
+                        "removed": removed,
+                    })));
+                }
+                matches = found;
+            }
+        }));
+    }
+
+    fn answer(&self, id: &Value, result: Result<Value, Failure>) {
+        send(&self.output, &response(id, result));
+    }
+
+    /// Cancels the queries and subscriptions running and waits for them.
+    fn stop(&mut self) {
+        for cancel in self.running.lock().unwrap().values() {
+            cancel.cancel();
+        }
+        self.finish();
+    }
+
+    /// Ends the subscriptions and waits for the queries running to be answered.
+    fn finish(&mut self) {
+        for (_, cancel) in self.subscriptions.drain() {
+            cancel.cancel();
+        }
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Runs the search of `spec`, within the roots of `tenant` if any, returning
+/// its matches.
+fn search(spec: &SearchSpec, tenant: Option<&Tenant>, result_cache: Option<&PathBuf>, metrics: Option<&Metrics>, cancel: &Cancel) -> Result<Vec<String>, Failure> {
+    let finder = match tenant {
+        Some(tenant) => tenant.finder(spec),
+        None => Finder::from_spec(spec),
+    };
+    let mut finder = finder
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::PermissionDenied => Failure::new(ACCESS_DENIED, e.to_string()),
+            _ => Failure::new(INVALID_PARAMS, e.to_string()),
+        })?
+        .cancel_with(cancel);
+    if let Some(dir) = result_cache {
+        finder = finder.result_cache(dir);
+    }
+    if let Some(metrics) = metrics {
+        finder = finder.metrics(metrics);
+    }
+    finder.find(spec.depth).map_err(|e| match e.kind() {
+        io::ErrorKind::Interrupted if cancel.is_cancelled() => Failure::new(REQUEST_CANCELLED, e.to_string()),
+        _ => Failure::new(SEARCH_FAILED, e.to_string()),
+    })
+}
+
+fn response(id: &Value, result: Result<Value, Failure>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(failure) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": failure.code, "message": failure.message },
+        }),
+    }
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+/// Writes `message` to `output`, framed. A client that went away can't be
+/// told anything anymore, so failures are only traced.
+fn send(output: &Output, message: &Value) {
+    let text = message.to_string();
+    let mut output = output.lock().unwrap();
+    let written = write!(output, "Content-Length: {}\r\n\r\n{}", text.len(), text).and_then(|_| output.flush());
+    if let Err(e) = written {
+        trace::event(Level::Warn, || format!("could not write a message: {}", e));
+    }
+}
+
+/// Reads the next framed message of `input`, or `None` at its end.
+fn read_message(input: &mut impl BufRead) -> Result<Option<String>, Error> {
+    let invalid = |reason: String| Error::new(io::ErrorKind::InvalidData, reason);
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return match length {
+                None => Ok(None),
+                Some(_) => Err(invalid(String::from("The input ended within the headers of a message."))),
+            };
+        }
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        match line.split_once(':') {
+            Some((name, value)) if name.eq_ignore_ascii_case("Content-Length") => {
+                length = Some(value.trim().parse::<usize>().map_err(|e| invalid(format!("Invalid Content-Length '{}': {}.", value.trim(), e)))?);
+            }
+            Some(_) => {}
+            None => return Err(invalid(format!("Invalid header '{}'.", line))),
+        }
+    }
+    let mut body = vec![0; length.unwrap_or_default()];
+    input.read_exact(&mut body)?;
+    String::from_utf8(body).map(Some).map_err(|e| invalid(format!("The message isn't UTF-8: {}.", e)))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    use std::io::{BufReader, Read};
+    use std::sync::mpsc::{self, Receiver, Sender};
+
+    /// What the server wrote, shared with the test.
+    #[derive(Clone, Default)]
+    struct Written(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Written {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Written {
+        /// The messages written so far.
+        fn messages(&self) -> Vec<Value> {
+            let bytes = self.0.lock().unwrap().clone();
+            let mut input = &bytes[..];
+            std::iter::from_fn(|| read_message(&mut input).unwrap()).map(|text| serde_json::from_str::<Value>(&text).unwrap()).collect()
+        }
+
+        /// Waits for a message passing `test`.
+        fn wait_for(&self, test: impl Fn(&Value) -> bool) -> Value {
+            let started = Instant::now();
+            loop {
+                if let Some(message) = self.messages().into_iter().find(|message| test(message)) {
+                    return message;
+                }
+                assert!(started.elapsed() < Duration::from_secs(10), "no such message in {:?}", self.messages());
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+    }
+
+    /// Input written by the test while the server reads it.
+    struct Input(Receiver<Vec<u8>>, Vec<u8>);
+
+    impl Read for Input {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.1.is_empty() {
+                match self.0.recv() {
+                    Ok(bytes) => self.1 = bytes,
+                    Err(_) => return Ok(0),
+                }
+            }
+            let n = buf.len().min(self.1.len());
+            buf[..n].copy_from_slice(&self.1[..n]);
+            self.1.drain(..n);
+            Ok(n)
+        }
+    }
+
+    fn request(input: &Sender<Vec<u8>>, message: &str) {
+        input.send(format!("Content-Length: {}\r\n\r\n{}", message.len(), message).into_bytes()).unwrap();
+    }
+
+    fn with_id(id: u64) -> impl Fn(&Value) -> bool {
+        move |message| message.get("id").and_then(|id| id.as_u64()) == Some(id)
+    }
+
+    fn error_code(message: &Value) -> Option<i64> {
+        message.get("error")?.get("code")?.as_i64()
+    }
+
+    fn start(root: &std::path::Path) -> (Sender<Vec<u8>>, Written, JoinHandle<Result<(), Error>>) {
+        let (input, received) = mpsc::channel();
+        let written = Written::default();
+        let output = written.clone();
+        let server = thread::spawn(move || Server::new().serve(BufReader::new(Input(received, Vec::new())), output));
+        request(&input, r#"{"jsonrpc":"2.0","id":1,"method":"ffind/query","params":{}}"#);
+        assert_eq!(Some(SERVER_NOT_INITIALIZED), error_code(&written.wait_for(with_id(1))));
+        request(&input, &format!(r#"{{"jsonrpc":"2.0","id":2,"method":"initialize","params":{{"roots":[{}]}}}}"#,
+                                 Value::from(root.to_str().unwrap())));
+        assert!(written.wait_for(with_id(2)).get("result").and_then(|r| r.get("capabilities")).is_some());
+        (input, written, server)
+    }
+
+    #[test]
+    fn answers_queries() {
+        let root = std::env::temp_dir().join(format!("ffind-rpc-query-{}", std::process::id()));
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/lib.rs"), "").unwrap();
+        fs::write(root.join("README.md"), "").unwrap();
+        let (input, written, server) = start(&root);
+        request(&input, r#"{"jsonrpc":"2.0","id":3,"method":"ffind/query","params":{"depth":5,"filters":[{"has_extension":{"ext":".rs"}}]}}"#);
+        let matches = written.wait_for(with_id(3)).get("result").and_then(|r| r.get("matches")).cloned();
+        assert_eq!(Some(Value::Array(vec![Value::from(root.join("src/lib.rs").to_str().unwrap())])), matches);
+        request(&input, r#"{"jsonrpc":"2.0","id":4,"method":"ffind/rename","params":{}}"#);
+        assert_eq!(Some(METHOD_NOT_FOUND), error_code(&written.wait_for(with_id(4))));
+        request(&input, "{\"jsonrpc\":");
+        request(&input, r#"{"jsonrpc":"2.0","id":5,"method":"shutdown"}"#);
+        written.wait_for(with_id(5));
+        request(&input, r#"{"jsonrpc":"2.0","method":"exit"}"#);
+        server.join().unwrap().unwrap();
+        assert!(written.messages().iter().any(|message| error_code(message) == Some(PARSE_ERROR)));
+
+        let cancel = Cancel::new();
+        cancel.cancel();
+        let spec = SearchSpec::new(root.to_str().unwrap().to_string(), 5);
+        assert_eq!(REQUEST_CANCELLED, search(&spec, None, None, None, &cancel).unwrap_err().code);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn subscriptions_notify_changes() {
+        let root = std::env::temp_dir().join(format!("ffind-rpc-subscribe-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("old.rs"), "").unwrap();
+        let (input, written, server) = start(&root);
+        request(&input, r#"{"jsonrpc":"2.0","id":3,"method":"ffind/subscribe","params":{"depth":1,"interval_ms":10}}"#);
+        let subscribed = written.wait_for(with_id(3));
+        assert_eq!(Some(1), subscribed.get("result").and_then(|r| r.get("subscription")).and_then(|s| s.as_u64()));
+        fs::write(root.join("new.rs"), "").unwrap();
+        fs::remove_file(root.join("old.rs")).unwrap();
+        let changed = written.wait_for(|message| message.get("method").and_then(|m| m.as_str()) == Some("ffind/changed"));
+        let params = changed.get("params").unwrap();
+        assert_eq!(Some(&Value::Array(vec![Value::from(root.join("new.rs").to_str().unwrap())])), params.get("added"));
+        assert_eq!(Some(&Value::Array(vec![Value::from(root.join("old.rs").to_str().unwrap())])), params.get("removed"));
+        request(&input, r#"{"jsonrpc":"2.0","id":4,"method":"ffind/unsubscribe","params":{"subscription":1}}"#);
+        assert_eq!(Some(&Value::Null), written.wait_for(with_id(4)).get("result"));
+        drop(input);
+        server.join().unwrap().unwrap();
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+}
@@ -0,0 +1,253 @@
+/*
+Searching S3 buckets.
+
+'S3Fs' is a 'FileSystem' (see the 'vfs' module) over the objects of a bucket.
+Object storage has no directories, only keys, so keys are mapped onto the
+usual tree by splitting them at '/': 's3://bucket/logs/2021/app.log' is the
+file 'app.log' in the directory 'logs/2021'. Depth counts those prefix
+segments, like directories on disk. Sizes and modification times come from
+the object listings, which are fetched one prefix at a time.
+
+Requests go through the AWS command line client ('aws s3api ...'), so
+credentials, regions and profiles are configured as for the client itself,
+e.g. with 'AWS_PROFILE'.
+
+Only available with the 's3' feature.
+
+ */
+
+use std::collections::HashMap;
+use std::io::{self, Error, Read};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use crate::filter::MetadataMode;
+use serde_json::Value;
+use crate::vfs::{FileSystem, FileType, Metadata};
+
+
+/// Returns the bucket of an `s3://bucket/prefix` location, or `None` if it
+/// isn't one.
+pub fn parse_location(location: &str) -> Option<&str> {
+    let rest = location.strip_prefix("s3://")?;
+    let bucket = rest.split('/').next().unwrap_or("");
+    if bucket.is_empty() { None } else { Some(bucket) }
+}
+
+/// The objects of an S3 bucket. Paths are `s3://bucket/key` locations.
+pub struct S3Fs {
+    bucket: String,
+    program: PathBuf,
+    /// The metadata of every key and prefix listed so far.
+    listed: Mutex<HashMap<String, Metadata>>,
+}
+
+/// The keys directly below a prefix.
+#[derive(Debug, Default, PartialEq)]
+struct Listing {
+    /// Common prefixes, i.e. directories, without the trailing `/`.
+    dirs: Vec<String>,
+    files: Vec<(String, Metadata)>,
+}
+
+impl S3Fs {
+
+    /// Searches `bucket` with the `aws` client found on the `PATH`.
+    pub fn new(bucket: &str) -> S3Fs {
+        S3Fs::with_program(bucket, "aws")
+    }
+
+    /// Searches `bucket` with the given AWS command line client, e.g. a
+    /// wrapper script setting up credentials.
+    pub fn with_program(bucket: &str, program: impl Into<PathBuf>) -> S3Fs {
+        S3Fs {
+            bucket: bucket.to_string(),
+            program: program.into(),
+            listed: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Turns a path into the key it names, e.g. `s3://bucket/logs/` into `logs`.
+    fn key_of(&self, path: &Path) -> String {
+        let path = path.to_string_lossy();
+        let key = path.strip_prefix("s3://")
+            .and_then(|rest| rest.strip_prefix(self.bucket.as_str()))
+            .unwrap_or(&path);
+        key.trim_matches('/').to_string()
+    }
+
+    /// Lists the keys directly below `prefix` (a key without the trailing `/`,
+    /// or the empty string for the whole bucket).
+    fn list(&self, prefix: &str) -> Result<Listing, Error> {
+        let prefix = if prefix.is_empty() { String::new() } else { format!("{}/", prefix) };
+        let output = self.aws(&["s3api", "list-objects-v2", "--bucket", &self.bucket,
+                                "--prefix", &prefix, "--delimiter", "/", "--output", "json"])?;
+        let listing = parse_listing(&String::from_utf8_lossy(&output), &prefix)?;
+        let mut listed = self.listed.lock().unwrap();
+        for dir in &listing.dirs {
+            listed.insert(dir.clone(), Metadata::new(FileType::Dir, 0, None));
+        }
+        for (key, meta) in &listing.files {
+            listed.insert(key.clone(), meta.clone());
+        }
+        Ok(listing)
+    }
+
+    fn aws(&self, args: &[&str]) -> Result<Vec<u8>, Error> {
+        let output = Command::new(&self.program).args(args).output()?;
+        if !output.status.success() {
+            return Err(Error::other(format!(
+                "{} {} failed: {}",
+                self.program.display(), args[..2].join(" "), String::from_utf8_lossy(&output.stderr).trim())));
+        }
+        Ok(output.stdout)
+    }
+}
+
+impl FileSystem for S3Fs {
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>, Error> {
+        let listing = self.list(&self.key_of(path))?;
+        let name = |key: &str| key.rsplit('/').next().unwrap_or(key).to_string();
+        Ok(listing.dirs.iter()
+            .map(|dir| name(dir))
+            .chain(listing.files.iter().map(|(key, _)| name(key)))
+            .map(|name| path.join(name))
+            .collect())
+    }
+
+    /// There are no symlinks in a bucket, so `mode` doesn't matter.
+    fn metadata(&self, path: &Path, _mode: MetadataMode) -> Result<Metadata, Error> {
+        let key = self.key_of(path);
+        if key.is_empty() {
+            return Ok(Metadata::new(FileType::Dir, 0, None));
+        }
+        if let Some(meta) = self.listed.lock().unwrap().get(&key) {
+            return Ok(meta.clone());
+        }
+        // Not seen yet: list the parent prefix, which tells whether the key is
+        // an object, a prefix or neither.
+        let parent = key.rsplit_once('/').map(|(parent, _)| parent).unwrap_or("");
+        self.list(parent)?;
+        self.listed.lock().unwrap().get(&key).cloned().ok_or_else(|| Error::new(
+            io::ErrorKind::NotFound,
+            format!("No such object or prefix: {}", path.display())))
+    }
+
+    fn open(&self, path: &Path) -> Result<Box<dyn Read + Send>, Error> {
+        let location = format!("s3://{}/{}", self.bucket, self.key_of(path));
+        let contents = self.aws(&["s3", "cp", &location, "-"])?;
+        Ok(Box::new(io::Cursor::new(contents)))
+    }
+}
+
+/// Parses the JSON output of `list-objects-v2` for `prefix`. The client prints
+/// nothing at all when there are no keys.
+fn parse_listing(text: &str, prefix: &str) -> Result<Listing, Error> {
+    if text.trim().is_empty() {
+        return Ok(Listing::default());
+    }
+    let invalid = || Error::new(io::ErrorKind::InvalidData, "Invalid S3 listing.");
+    let value = serde_json::from_str::<Value>(text)?;
+    let mut listing = Listing::default();
+    for dir in value.get("CommonPrefixes").and_then(|v| v.as_array()).into_iter().flatten() {
+        let dir = dir.get("Prefix").and_then(|p| p.as_str()).ok_or_else(invalid)?;
+        listing.dirs.push(dir.trim_end_matches('/').to_string());
+    }
+    for object in value.get("Contents").and_then(|v| v.as_array()).into_iter().flatten() {
+        let key = object.get("Key").and_then(|k| k.as_str()).ok_or_else(invalid)?;
+        // Skip the empty "folder" objects some tools create for prefixes.
+        if key == prefix || key.ends_with('/') {
+            continue;
+        }
+        let size = object.get("Size").and_then(|s| s.as_u64()).ok_or_else(invalid)?;
+        let modified = object.get("LastModified").and_then(|m| m.as_str()).and_then(parse_timestamp);
+        listing.files.push((key.to_string(), Metadata::new(FileType::File, size, modified)));
+    }
+    Ok(listing)
+}
+
+/// Parses an ISO 8601 timestamp in UTC as S3 reports them, e.g.
+/// `2021-06-01T12:34:56.000Z` or `2021-06-01T12:34:56+00:00`.
+fn parse_timestamp(text: &str) -> Option<SystemTime> {
+    let number = |range: std::ops::Range<usize>| text.get(range)?.parse::<u64>().ok();
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+    if !(1970..=9999).contains(&year) || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    // Days since the epoch of the civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let (y, m) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * m + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    let secs = days * 86400 + hour * 3600 + minute * 60 + second;
+    Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const LISTING: &str = r#"{
+        "Contents": [
+            {"Key": "logs/", "Size": 0, "LastModified": "2021-01-01T00:00:00.000Z"},
+            {"Key": "logs/app.log", "Size": 1234, "LastModified": "2021-06-01T12:34:56.000Z"}
+        ],
+        "CommonPrefixes": [{"Prefix": "logs/2021/"}]
+    }"#;
+
+    #[test]
+    fn s3_locations() {
+        assert_eq!(Some("bucket"), parse_location("s3://bucket/logs"));
+        assert_eq!(Some("bucket"), parse_location("s3://bucket"));
+        assert_eq!(None, parse_location("s3://"));
+        assert_eq!(None, parse_location("bucket/logs"));
+    }
+
+    #[test]
+    fn listings() {
+        let listing = parse_listing(LISTING, "logs/").unwrap();
+        assert_eq!(vec!["logs/2021".to_string()], listing.dirs);
+        assert_eq!(1, listing.files.len());
+        assert_eq!("logs/app.log", listing.files[0].0);
+        assert_eq!(1234, listing.files[0].1.len());
+        assert_eq!(Listing::default(), parse_listing("", "").unwrap());
+        assert!(parse_listing(r#"{"Contents": [{"Size": 1}]}"#, "").is_err());
+    }
+
+    #[test]
+    fn timestamps() {
+        let secs = |text| parse_timestamp(text).map(|t| t.duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs());
+        assert_eq!(Some(0), secs("1970-01-01T00:00:00Z"));
+        assert_eq!(Some(1622550896), secs("2021-06-01T12:34:56.000Z"));
+        assert_eq!(Some(951782400), secs("2000-02-29T00:00:00+00:00"));
+        assert_eq!(None, secs("yesterday"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn search_bucket() {
+        use std::os::unix::fs::PermissionsExt;
+        // A stand-in for the aws client answering every listing from LISTING,
+        // with a single key at the top of the bucket.
+        let program = std::env::temp_dir().join(format!("ffind-aws-{}", std::process::id()));
+        let top = r#"{"CommonPrefixes": [{"Prefix": "logs/"}]}"#;
+        std::fs::write(&program, format!(
+            "#!/bin/sh\ncase \"$*\" in\n*'--prefix  '*) echo '{}' ;;\n*'--prefix logs/ '*) cat <<'EOF'\n{}\nEOF\n;;\nesac\n",
+            top, LISTING)).unwrap();
+        std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let found = crate::Finder::new(String::from("s3://bucket/logs"))
+            .file_system(S3Fs::with_program("bucket", &program))
+            .size_greater_than_or_eq(1000)
+            .find(1)
+            .unwrap();
+        std::fs::remove_file(&program).unwrap();
+        assert_eq!(vec!["s3://bucket/logs/app.log"], found);
+    }
+
+}
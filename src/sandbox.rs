@@ -0,0 +1,212 @@
+/*
+Directories a server's clients may search.
+
+A daemon or server exposed to semi-trusted callers is given a 'Sandbox': an
+allowlist of root directories per API key, read from a JSON file like
+
+    {
+      "9f2c41d0e7": ["/srv/builds", "/var/log/app"],
+      "c3b8a1f4aa": ["/home/ana"]
+    }
+
+The 'Tenant' of a key only runs searches whose roots are within its
+directories, and keeps them there: paths are compared once resolved, so
+neither `..` nor symlinks lead out, and a symlink below an allowed root
+pointing elsewhere is neither followed nor reported. Searches with an
+unknown key, or without one, are refused.
+
+ */
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Error};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use crate::Finder;
+use crate::filter::{FileInfo, Filter};
+use serde_json::Value;
+use crate::spec::SearchSpec;
+
+
+/// The directories every API key may search, see the module documentation.
+#[derive(Debug, Clone, Default)]
+pub struct Sandbox {
+    tenants: HashMap<String, Tenant>,
+}
+
+/// The directories a single API key may search.
+#[derive(Debug, Clone, Default)]
+pub struct Tenant {
+    /// The allowed roots, resolved.
+    roots: Arc<Vec<PathBuf>>,
+}
+
+impl Sandbox {
+
+    pub fn new() -> Sandbox {
+        Sandbox::default()
+    }
+
+    /// Lets the clients with the API key `key` search `root` and anything
+    /// below it. Fails if `root` doesn't exist.
+    pub fn allow(mut self, key: &str, root: impl AsRef<Path>) -> Result<Self, Error> {
+        let root = root.as_ref();
+        let resolved = fs::canonicalize(root)
+            .map_err(|e| Error::new(e.kind(), format!("Cannot allow {}: {}.", root.display(), e)))?;
+        let tenant = self.tenants.entry(key.to_string()).or_default();
+        Arc::make_mut(&mut tenant.roots).push(resolved);
+        Ok(self)
+    }
+
+    /// Reads a sandbox from JSON mapping API keys to arrays of the roots they
+    /// may search.
+    pub fn from_json(text: &str) -> Result<Sandbox, Error> {
+        let invalid = |reason: &str| Error::new(io::ErrorKind::InvalidData, format!("Invalid sandbox: {}", reason));
+        let value = serde_json::from_str::<Value>(text)?;
+        let keys = value.as_object().ok_or_else(|| invalid("expected an object of API keys."))?;
+        let mut sandbox = Sandbox::new();
+        for (key, roots) in keys {
+            let roots = roots.as_array().ok_or_else(|| invalid("expected an array of roots for every key."))?;
+            for root in roots {
+                let root = root.as_str().ok_or_else(|| invalid("roots are strings."))?;
+                sandbox = sandbox.allow(key, root)?;
+            }
+        }
+        Ok(sandbox)
+    }
+
+    /// The tenant of the API key `key`, or a `PermissionDenied` error for an
+    /// unknown key or none.
+    pub fn tenant(&self, key: Option<&str>) -> Result<Tenant, Error> {
+        key.and_then(|key| self.tenants.get(key))
+            .cloned()
+            .ok_or_else(|| Error::new(io::ErrorKind::PermissionDenied, "Unknown API key."))
+    }
+}
+
+impl Tenant {
+
+    /// Returns true if `path` is within the roots of this tenant once
+    /// resolved. Paths that can't be resolved aren't.
+    pub fn contains(&self, path: impl AsRef<Path>) -> bool {
+        fs::canonicalize(path).is_ok_and(|path| self.roots.iter().any(|root| path.starts_with(root)))
+    }
+
+    /// Fails with `PermissionDenied` unless every root of `spec` is within
+    /// the roots of this tenant.
+    pub fn check(&self, spec: &SearchSpec) -> Result<(), Error> {
+        match std::iter::once(&spec.root).chain(&spec.extra_roots).find(|root| !self.contains(root)) {
+            Some(root) => Err(Error::new(io::ErrorKind::PermissionDenied, format!("Searching '{}' isn't allowed.", root))),
+            None => Ok(()),
+        }
+    }
+
+    /// Like `Finder::from_spec()`, once `spec` was checked, for a `Finder`
+    /// that stays within the roots of this tenant. The filters of `spec`
+    /// come after the one keeping it there, so that none of them reads, or
+    /// decompresses, a file outside the roots.
+    pub fn finder(&self, spec: &SearchSpec) -> Result<Finder, Error> {
+        self.check(spec)?;
+        Ok(Finder::from_spec(spec)?.with_first_filter(Confined(self.clone())))
+    }
+}
+
+/// Retains the files within the roots of a tenant, and keeps the walk out of
+/// the directories that aren't.
+struct Confined(Tenant);
+
+impl Filter for Confined {
+    fn name(&self) -> String {
+        String::from("sandboxed")
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        self.0.contains(file.path())
+    }
+
+    fn may_match_below(&self, dir: &Path) -> bool {
+        self.0.contains(dir)
+    }
+
+    fn prunes(&self) -> bool {
+        true
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_searches_within_roots() {
+        use std::os::unix::fs::symlink;
+        let dir = std::env::temp_dir().join(format!("ffind-sandbox-{}", std::process::id()));
+        fs::create_dir_all(dir.join("allowed/sub")).unwrap();
+        fs::create_dir_all(dir.join("secret")).unwrap();
+        fs::write(dir.join("allowed/sub/a.txt"), "").unwrap();
+        fs::write(dir.join("secret/key.txt"), "").unwrap();
+        symlink(dir.join("secret"), dir.join("allowed/escape")).unwrap();
+        symlink(dir.join("secret/key.txt"), dir.join("allowed/key.txt")).unwrap();
+
+        let config = format!("{{\"k1\": [{}]}}", Value::from(dir.join("allowed").to_str().unwrap()));
+        let sandbox = Sandbox::from_json(&config).unwrap();
+        assert!(sandbox.tenant(Some("k2")).is_err());
+        assert!(sandbox.tenant(None).is_err());
+        let tenant = sandbox.tenant(Some("k1")).unwrap();
+
+        let spec = |root: PathBuf| SearchSpec::new(root.to_str().unwrap().to_string(), 10);
+        let found = tenant.finder(&spec(dir.join("allowed"))).unwrap().find(10).unwrap();
+        assert_eq!(vec![dir.join("allowed/sub/a.txt").to_str().unwrap().to_string()], found);
+        for root in [dir.join("secret"), dir.join("allowed/../secret"), dir.join("allowed/escape")] {
+            let e = tenant.finder(&spec(root)).err().unwrap();
+            assert_eq!(io::ErrorKind::PermissionDenied, e.kind());
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Records the files opened, forwarding to the real filesystem.
+    #[cfg(unix)]
+    struct Opened(Arc<std::sync::Mutex<Vec<PathBuf>>>);
+
+    #[cfg(unix)]
+    impl crate::vfs::FileSystem for Opened {
+        fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>, Error> {
+            crate::vfs::StdFileSystem.read_dir(path)
+        }
+
+        fn metadata(&self, path: &Path, mode: crate::filter::MetadataMode) -> Result<crate::vfs::Metadata, Error> {
+            crate::vfs::StdFileSystem.metadata(path, mode)
+        }
+
+        fn open(&self, path: &Path) -> Result<Box<dyn std::io::Read + Send>, Error> {
+            self.0.lock().unwrap().push(path.to_path_buf());
+            crate::vfs::StdFileSystem.open(path)
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reads_nothing_outside_roots() {
+        use std::os::unix::fs::symlink;
+        use crate::spec::FilterSpec;
+        let dir = std::env::temp_dir().join(format!("ffind-sandbox-reads-{}", std::process::id()));
+        fs::create_dir_all(dir.join("allowed")).unwrap();
+        fs::create_dir_all(dir.join("secret")).unwrap();
+        fs::write(dir.join("allowed/a.txt"), "a\n").unwrap();
+        fs::write(dir.join("secret/key.txt"), "key\n").unwrap();
+        symlink(dir.join("secret/key.txt"), dir.join("allowed/key.txt")).unwrap();
+
+        let tenant = Sandbox::new().allow("k1", dir.join("allowed")).unwrap().tenant(Some("k1")).unwrap();
+        let mut spec = SearchSpec::new(dir.join("allowed").to_str().unwrap().to_string(), 10);
+        spec.filters.push(FilterSpec::LineCount(String::from("+0")));
+        let opened = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let found = tenant.finder(&spec).unwrap().file_system(Opened(opened.clone())).find(10).unwrap();
+        assert_eq!(vec![dir.join("allowed/a.txt").to_str().unwrap().to_string()], found);
+        // The content filter never saw the link out of the root.
+        assert_eq!(vec![dir.join("allowed/a.txt")], *opened.lock().unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+}
@@ -0,0 +1,116 @@
+/*
+Snapshot directories.
+
+Filesystems that take snapshots expose them as directories next to the live
+tree: ZFS below the .zfs/snapshot directory of every dataset, NetApp filers
+in a .snapshot directory in every directory of a share, and btrfs as
+subvolumes anywhere, like the .snapshots directory of snapper. Each one is a
+full copy of the tree, so a search walking into them finds every match once
+more per snapshot, and takes as much longer.
+
+'is_snapshot()' tells those directories apart, which
+'Finder::skip_snapshots()' stays out of. ZFS and NetApp snapshots are
+recognized by their names, btrfs ones on Linux by asking btrfs whether the
+subvolume a directory is the root of was made from another one. A root of
+the search is walked even if it is a snapshot.
+
+ */
+
+use std::ffi::OsStr;
+use std::path::Path;
+use crate::filter::MetadataMode;
+use crate::vfs::FileSystem;
+
+
+/// The inode number of the root directory of every btrfs subvolume.
+const BTRFS_SUBVOLUME_INODE: u64 = 256;
+
+/// Returns true if the directory at `dir` is a snapshot, or holds snapshots
+/// like .zfs/snapshot, see the module documentation.
+pub fn is_snapshot(file_system: &dyn FileSystem, dir: &Path) -> bool {
+    let name = dir.file_name().and_then(OsStr::to_str);
+    match name {
+        Some(".snapshot") | Some("~snapshot") => true,
+        Some("snapshot") if dir.parent().and_then(Path::file_name) == Some(OsStr::new(".zfs")) => true,
+        _ => is_btrfs_snapshot(file_system, dir),
+    }
+}
+
+/// Returns true if `dir` is the root of a btrfs subvolume made as a snapshot
+/// of another one. Only the roots of subvolumes are asked about.
+fn is_btrfs_snapshot(file_system: &dyn FileSystem, dir: &Path) -> bool {
+    let subvolume = file_system.metadata(dir, MetadataMode::NoFollow)
+        .is_ok_and(|meta| meta.id().is_some_and(|(_, inode)| inode == BTRFS_SUBVOLUME_INODE));
+    subvolume && file_system.fs_type(dir).as_deref() == Some("btrfs") && btrfs::made_from_another(dir)
+}
+
+#[cfg(target_os = "linux")]
+mod btrfs {
+    use std::fs::File;
+    use std::os::raw::{c_int, c_ulong};
+    use std::os::unix::io::AsRawFd;
+    use std::path::Path;
+
+    extern "C" {
+        fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+    }
+
+    /// BTRFS_IOC_GET_SUBVOL_INFO, which reads a `struct
+    /// btrfs_ioctl_get_subvol_info_args` of 504 bytes.
+    const GET_SUBVOL_INFO: c_ulong = 0x81f8_943c;
+
+    /// Where the UUID of the subvolume a snapshot was made from is in that
+    /// struct, in words.
+    const PARENT_UUID: std::ops::Range<usize> = 39..41;
+
+    /// Returns true if the subvolume whose root is `dir` has a parent, which
+    /// only snapshots have. Asking needs Linux 4.18, but no privileges.
+    pub fn made_from_another(dir: &Path) -> bool {
+        let dir = match File::open(dir) {
+            Ok(dir) => dir,
+            Err(_) => return false,
+        };
+        let mut info = [0u64; 63];
+        // SAFETY: `dir` is open and `info` is as large as the struct read.
+        if unsafe { ioctl(dir.as_raw_fd(), GET_SUBVOL_INFO, info.as_mut_ptr()) } < 0 {
+            return false;
+        }
+        info[PARENT_UUID].iter().any(|&word| word != 0)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod btrfs {
+    use std::path::Path;
+
+    pub fn made_from_another(_dir: &Path) -> bool {
+        false
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vfs::MemoryFileSystem;
+    use crate::Finder;
+
+    #[test]
+    fn skips_snapshots() {
+        let mut fs = MemoryFileSystem::new();
+        fs.add_file("data/a.txt", "")
+            .add_file("data/.zfs/snapshot/daily/a.txt", "")
+            .add_file("data/.zfs/shares/b.txt", "")
+            .add_file("share/.snapshot/hourly.0/a.txt", "")
+            .add_file("share/snapshot/c.txt", "");
+        assert!(is_snapshot(&fs, Path::new("data/.zfs/snapshot")));
+        assert!(!is_snapshot(&fs, Path::new("share/snapshot")));
+        let mut found = Finder::new(String::from(".")).file_system(fs.clone()).skip_snapshots().name_ends_with(".txt").find(99).unwrap();
+        found.sort();
+        assert_eq!(vec!["./data/.zfs/shares/b.txt", "./data/a.txt", "./share/snapshot/c.txt"], found);
+        // Unless it is searched itself.
+        let found = Finder::new(String::from("share/.snapshot")).file_system(fs).skip_snapshots().name_ends_with(".txt").find(99).unwrap();
+        assert_eq!(vec!["share/.snapshot/hourly.0/a.txt"], found);
+    }
+
+}
@@ -0,0 +1,145 @@
+/*
+Ordering the matches of a search.
+
+Matches are reported in the order the walk finds them unless a 'SortKey' is
+given to 'Finder::sort_by()'. Sorting by name or path compares strings as they
+are, so `file10.log` comes before `file2.log`; 'SortKey::NaturalName' compares
+runs of digits by their value instead, the way people number logs and
+snapshots, so that `file2.log` comes first. With the 'collation' feature,
+'SortKey::LocaleName' orders names like the user's locale does, see the
+'collate' module.
+
+ */
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::io::{self, Error};
+use std::path::Path;
+#[cfg(feature = "collation")]
+use crate::collate::Collator;
+
+
+/// What the matches of a search are ordered by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    /// The whole path.
+    Path,
+    /// The file name, then the path.
+    Name,
+    /// The file name with the numbers in it compared by value, then the path.
+    NaturalName,
+    /// The file name as ordered by the locale of the environment, then the
+    /// path.
+    #[cfg(feature = "collation")]
+    LocaleName,
+}
+
+impl SortKey {
+
+    /// Parses a sort key as given to `--sort`: `path`, `name`,
+    /// `name:natural` or `name:locale`.
+    pub fn parse(arg: &str) -> Result<SortKey, Error> {
+        match arg {
+            "path" => Ok(SortKey::Path),
+            "name" => Ok(SortKey::Name),
+            "name:natural" => Ok(SortKey::NaturalName),
+            #[cfg(feature = "collation")]
+            "name:locale" => Ok(SortKey::LocaleName),
+            #[cfg(not(feature = "collation"))]
+            "name:locale" => Err(Error::new(io::ErrorKind::Unsupported,
+                                            "Sorting by locale needs ffind built with the collation feature.")),
+            _ => Err(Error::new(io::ErrorKind::InvalidInput,
+                                format!("Unknown sort key '{}', expected path, name, name:natural or name:locale.", arg))),
+        }
+    }
+
+    /// Orders the paths `a` and `b`.
+    pub fn compare(self, a: &str, b: &str) -> Ordering {
+        match self {
+            SortKey::Path => a.cmp(b),
+            SortKey::Name => name(a).cmp(&name(b)).then_with(|| a.cmp(b)),
+            SortKey::NaturalName => natural_cmp(&name(a), &name(b)).then_with(|| a.cmp(b)),
+            #[cfg(feature = "collation")]
+            SortKey::LocaleName => Collator::from_env().compare(&name(a), &name(b)).then_with(|| a.cmp(b)),
+        }
+    }
+
+    /// Sorts `paths` by this key.
+    pub fn sort(self, paths: &mut [String]) {
+        match self {
+            // Keys are cheaper than collating every comparison.
+            #[cfg(feature = "collation")]
+            SortKey::LocaleName => {
+                let collator = Collator::from_env();
+                paths.sort_by_cached_key(|path| (collator.key(&name(path)), path.clone()));
+            }
+            _ => paths.sort_by(|a, b| self.compare(a, b)),
+        }
+    }
+}
+
+fn name(path: &str) -> Cow<'_, str> {
+    Path::new(path).file_name().map_or_else(|| path.into(), |name| name.to_string_lossy())
+}
+
+/// Compares `a` and `b` with the runs of ASCII digits in them compared by
+/// value, like `sort -V`: `file2` comes before `file10`. Numbers written with
+/// more leading zeros come after the same number with fewer, so that only
+/// equal strings are equal.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+    let mut zeros = Ordering::Equal;
+    loop {
+        match (a.first(), b.first()) {
+            (None, None) => return zeros,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let (x, rest_a) = split_digits(a);
+                let (y, rest_b) = split_digits(b);
+                let (x_value, y_value) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                let order = x_value.len().cmp(&y_value.len()).then_with(|| x_value.cmp(y_value));
+                if order != Ordering::Equal {
+                    return order;
+                }
+                zeros = zeros.then_with(|| x.len().cmp(&y.len()));
+                a = rest_a;
+                b = rest_b;
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(y);
+                }
+                a = &a[1..];
+                b = &b[1..];
+            }
+        }
+    }
+}
+
+/// Splits the leading digits off `text`.
+fn split_digits(text: &[u8]) -> (&str, &[u8]) {
+    let end = text.iter().position(|b| !b.is_ascii_digit()).unwrap_or(text.len());
+    // Only ASCII digits, so valid UTF-8.
+    (std::str::from_utf8(&text[..end]).unwrap(), &text[end..])
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn natural_order() {
+        let mut paths: Vec<String> = ["./b/file10.log", "./a/file2.log", "./file1.log", "./file02.log", "./file.log", "./file2a.log"]
+            .iter().map(|path| path.to_string()).collect();
+        SortKey::NaturalName.sort(&mut paths);
+        assert_eq!(vec!["./file.log", "./file1.log", "./a/file2.log", "./file02.log", "./file2a.log", "./b/file10.log"], paths);
+        SortKey::Name.sort(&mut paths);
+        assert_eq!(vec!["./file.log", "./file02.log", "./file1.log", "./b/file10.log", "./a/file2.log", "./file2a.log"], paths);
+        assert_eq!(Ordering::Less, natural_cmp("v1.9.2", "v1.10.0"));
+        assert_eq!(Ordering::Equal, natural_cmp("a007", "a007"));
+        assert!(SortKey::parse("name:random").is_err());
+    }
+
+}
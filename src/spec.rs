@@ -0,0 +1,442 @@
+/*
+Search definitions as data.
+
+A 'SearchSpec' describes a complete search (root, depth, filters and actions)
+as plain data, so it can be stored in a JSON file, sent to another machine and
+turned back into a 'Finder' with 'Finder::from_spec()'. The JSON layout uses
+externally tagged enums, e.g.
+
+    {
+      "root": "src",
+      "depth": 3,
+      "filters": [
+        { "has_extension": { "ext": ".rs", "case_sensitive": true } },
+        { "size_greater_than_or_eq": 10 },
+        { "not": { "expression": "-name 'test*'" } }
+      ],
+      "actions": ["print"]
+    }
+
+The layout is the one serde derives, so with the 'toml' feature the same
+spec can be written as TOML, see 'SearchSpec::from_toml()'.
+
+Specs with regexes parse in every build, but without the 'regex' feature
+only those regexes that are plain text, or that look for an extension like
+`\.rs$`, can be turned into filters.
+
+ */
+
+use std::io::{self, Error};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+#[cfg(feature = "regex")]
+use regex::{Regex, RegexSet};
+use crate::{Finder, UNLIMITED_DEPTH};
+#[cfg(feature = "regex")]
+use crate::content::ContentMatches;
+use crate::content::{LineCount, Utf8Validity};
+use crate::expr::Expression;
+use crate::git::{GitState, GitStatus};
+use crate::filter::{self, All, Any, Filter, FuzzyName, HasAncestor, HasExtension, MatchesGlob, MetadataMode, ModifiedBefore, NameContains, NameEndsWith, NameStartsWith, Not, OlderThanSibling, SizeGreaterThanOrEq, SizeLessThanOrEq, StemIs};
+#[cfg(feature = "regex")]
+use crate::filter::{MatchesRegex, MatchesRegexSet};
+use crate::fuzzy::Fuzzy;
+use crate::glob::Glob;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+
+/// A filter described as data. See the filters of the same name in the
+/// `filter` module.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterSpec {
+    SizeLessThanOrEq(u64),
+    SizeGreaterThanOrEq(u64),
+    HasExtension {
+        ext: String,
+        #[serde(default = "yes")]
+        case_sensitive: bool,
+    },
+    /// A regex found in the file name. Regexes without metacharacters are
+    /// matched as a `NameContains`.
+    MatchesRegex(String),
+    /// Like `MatchesRegex`, but the regex is looked for in the whole path.
+    PathMatchesRegex(String),
+    NameContains(String),
+    NameStartsWith(String),
+    NameEndsWith(String),
+    /// The name without its extension, see `filter::StemIs`.
+    StemIs(String),
+    /// Any of the regexes, or all of them, see `filter::MatchesRegexSet`.
+    MatchesRegexSet {
+        patterns: Vec<String>,
+        #[serde(default)]
+        all: bool,
+        #[serde(default)]
+        full_path: bool,
+    },
+    MatchesGlob {
+        glob: String,
+        #[serde(default)]
+        full_path: bool,
+        #[serde(default)]
+        case_insensitive: bool,
+    },
+    /// A directory named like the glob above the file, see `filter::HasAncestor`.
+    #[serde(rename = "has_ancestor_named")]
+    HasAncestor(String),
+    /// Modified before the file of that name next to it, see `filter::OlderThanSibling`.
+    OlderThanSibling(String),
+    /// Last modified more than this many seconds before the filter is made,
+    /// see `filter::ModifiedBefore`.
+    OlderThan(u64),
+    /// A name within `max_distance` edits of `query`, see the `fuzzy` module.
+    /// Searches with one at the top level report the closest names first.
+    FuzzyName { query: String, max_distance: u32 },
+    /// A find(1) expression, see the `expr` module.
+    Expression(String),
+    /// A regex matching a line of the contents, see the `content` module.
+    ContentMatches {
+        pattern: String,
+        #[serde(default)]
+        search_compressed: bool,
+    },
+    /// A find-like line count, e.g. `+1000`, see `content::LineCount`.
+    LineCount(String),
+    /// Whether the contents are valid UTF-8, see `content::Utf8Validity`.
+    Utf8 {
+        #[serde(default = "yes")]
+        valid: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sample: Option<u64>,
+    },
+    /// The git status of the file, see the `git` module.
+    Git(GitState),
+    All(Vec<FilterSpec>),
+    Any(Vec<FilterSpec>),
+    Not(Box<FilterSpec>),
+}
+
+/// What to do with every match.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionSpec {
+    /// Print the match to stdout as it is found.
+    Print,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SearchSpec {
+    pub root: String,
+    /// More directories to search after `root`, see `Finder::add_root()`.
+    #[serde(default)]
+    pub extra_roots: Vec<String>,
+    /// The maximum depth, see `Finder::find()`, or `UNLIMITED_DEPTH`, which
+    /// the JSON representation leaves out.
+    #[serde(default = "unlimited", skip_serializing_if = "is_unlimited")]
+    pub depth: u32,
+    /// The minimum depth, see `Finder::min_depth()`.
+    #[serde(default)]
+    pub min_depth: u32,
+    /// Whether files found more than once are reported once, see `Finder::dedup()`.
+    #[serde(default = "yes")]
+    pub dedup: bool,
+    /// Whether size and time filters follow symlinks, see `Finder::metadata_mode()`.
+    #[serde(rename = "metadata", default)]
+    pub metadata_mode: MetadataMode,
+    /// Whether matching directories are reported too, see `Finder::include_dirs()`.
+    #[serde(default)]
+    pub include_dirs: bool,
+    /// Whether ignore files are respected, see `Finder::ignore_files()`.
+    /// Queries answered by the daemon aren't affected by ignore files.
+    #[serde(default = "yes")]
+    pub ignore_files: bool,
+    /// Directories containing a file of one of these names aren't walked,
+    /// see `Finder::prune_if_contains()`.
+    #[serde(default)]
+    pub prune_if_contains: Vec<String>,
+    /// Directories with more entries than this aren't walked, see
+    /// `Finder::max_entries_per_dir()`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_entries_per_dir: Option<usize>,
+    #[serde(default)]
+    pub filters: Vec<FilterSpec>,
+    #[serde(default)]
+    pub actions: Vec<ActionSpec>,
+}
+
+impl SearchSpec {
+
+    /// Creates a spec searching `root` up to `depth`, without any filters or actions.
+    pub fn new(root: String, depth: u32) -> SearchSpec {
+        SearchSpec {
+            root,
+            extra_roots: Vec::new(),
+            depth,
+            min_depth: 0,
+            dedup: true,
+            metadata_mode: MetadataMode::Follow,
+            include_dirs: false,
+            ignore_files: true,
+            prune_if_contains: Vec::new(),
+            max_entries_per_dir: None,
+            filters: Vec::new(),
+            actions: Vec::new(),
+        }
+    }
+
+    /// Parses a spec from its JSON representation.
+    pub fn from_json(text: &str) -> Result<SearchSpec, Error> {
+        serde_json::from_str(text).map_err(|e| invalid(&e.to_string()))
+    }
+
+    /// Reads a spec from its JSON representation, e.g. a member of a larger
+    /// document.
+    pub fn from_value(value: &Value) -> Result<SearchSpec, Error> {
+        SearchSpec::deserialize(value).map_err(|e| invalid(&e.to_string()))
+    }
+
+    /// Returns the JSON representation of this spec.
+    pub fn to_json(&self) -> Value {
+        serde_json::to_value(self).expect("search specs are JSON")
+    }
+
+    /// Parses a spec from its TOML representation, laid out like the JSON
+    /// one.
+    #[cfg(feature = "toml")]
+    pub fn from_toml(text: &str) -> Result<SearchSpec, Error> {
+        toml::from_str(text).map_err(|e| invalid(&e.to_string()))
+    }
+
+    /// Returns the TOML representation of this spec.
+    #[cfg(feature = "toml")]
+    pub fn to_toml(&self) -> Result<String, Error> {
+        toml::to_string_pretty(self).map_err(|e| invalid(&e.to_string()))
+    }
+
+    /// Builds the `Finder` described by this spec and runs it.
+    pub fn run(&self) -> Result<Vec<String>, Error> {
+        let finder = Finder::from_spec(self)?;
+        if self.actions.contains(&ActionSpec::Print) {
+            finder.print_find(self.depth)
+        } else {
+            finder.find(self.depth)
+        }
+    }
+}
+
+impl FilterSpec {
+
+    /// Builds the filter described by this spec. Returns an error if a
+    /// pattern or expression in it is invalid.
+    pub fn to_filter(&self) -> Result<Box<dyn Filter>, Error> {
+        let filter: Box<dyn Filter> = match self {
+            FilterSpec::SizeLessThanOrEq(bytes) => Box::new(SizeLessThanOrEq { bytes: *bytes }),
+            FilterSpec::SizeGreaterThanOrEq(bytes) => Box::new(SizeGreaterThanOrEq { bytes: *bytes }),
+            FilterSpec::HasExtension { ext, case_sensitive } => Box::new(HasExtension {
+                ext: ext.clone(),
+                case_sensitive: *case_sensitive,
+            }),
+            FilterSpec::MatchesRegex(pattern) => regex_filter(pattern, false)?,
+            FilterSpec::PathMatchesRegex(pattern) => regex_filter(pattern, true)?,
+            FilterSpec::NameContains(text) => Box::new(NameContains::new(text, false)),
+            FilterSpec::NameStartsWith(prefix) => Box::new(NameStartsWith { prefix: prefix.clone() }),
+            FilterSpec::NameEndsWith(suffix) => Box::new(NameEndsWith { suffix: suffix.clone() }),
+            FilterSpec::StemIs(stem) => Box::new(StemIs { stem: stem.clone() }),
+            #[cfg(feature = "regex")]
+            FilterSpec::MatchesRegexSet { patterns, all, full_path } => {
+                let set = RegexSet::new(patterns)
+                    .map_err(|e| invalid(&format!("invalid regexes {:?}: {}", patterns, e)))?;
+                Box::new(MatchesRegexSet { set, all: *all, full_path: *full_path })
+            }
+            #[cfg(not(feature = "regex"))]
+            FilterSpec::MatchesRegexSet { patterns, .. } => return Err(needs_regex(&patterns.join(", "))),
+            FilterSpec::MatchesGlob { glob, full_path, case_insensitive } => Box::new(MatchesGlob {
+                glob: Glob::new(glob)?.case_insensitive(*case_insensitive),
+                full_path: *full_path,
+            }),
+            FilterSpec::HasAncestor(glob) => Box::new(HasAncestor { glob: Glob::new(glob)? }),
+            FilterSpec::OlderThanSibling(sibling) => Box::new(OlderThanSibling { sibling: sibling.clone() }),
+            FilterSpec::OlderThan(secs) => Box::new(ModifiedBefore {
+                time: SystemTime::now().checked_sub(Duration::from_secs(*secs)).unwrap_or(UNIX_EPOCH),
+            }),
+            FilterSpec::FuzzyName { query, max_distance } => Box::new(FuzzyName { query: Fuzzy::new(query, *max_distance) }),
+            FilterSpec::Expression(expr) => Box::new(Expression::parse(expr)?),
+            #[cfg(feature = "regex")]
+            FilterSpec::ContentMatches { pattern, search_compressed } => {
+                let re = regex::bytes::Regex::new(pattern)
+                    .map_err(|e| invalid(&format!("invalid regex '{}': {}", pattern, e)))?;
+                Box::new(ContentMatches { re, search_compressed: *search_compressed })
+            }
+            #[cfg(not(feature = "regex"))]
+            FilterSpec::ContentMatches { pattern, .. } => return Err(needs_regex(pattern)),
+            FilterSpec::LineCount(arg) => Box::new(LineCount::parse(arg)?),
+            FilterSpec::Utf8 { valid, sample } => Box::new(Utf8Validity { valid: *valid, sample: *sample }),
+            FilterSpec::Git(state) => Box::new(GitStatus::new(*state)),
+            FilterSpec::All(specs) => Box::new(All(to_filters(specs)?)),
+            FilterSpec::Any(specs) => Box::new(Any(to_filters(specs)?)),
+            FilterSpec::Not(spec) => Box::new(Not(spec.to_filter()?)),
+        };
+        Ok(filter)
+    }
+
+    pub fn to_json(&self) -> Value {
+        serde_json::to_value(self).expect("filter specs are JSON")
+    }
+
+    pub fn from_json(value: &Value) -> Result<FilterSpec, Error> {
+        FilterSpec::deserialize(value).map_err(|e| invalid(&e.to_string()))
+    }
+}
+
+/// A regex filter on names or paths, matched as a substring if the pattern
+/// is plain text and as an extension if it looks for one, like `\.rs$`.
+fn regex_filter(pattern: &str, full_path: bool) -> Result<Box<dyn Filter>, Error> {
+    if filter::is_literal(pattern) {
+        return Ok(Box::new(NameContains::new(pattern, full_path)));
+    }
+    if let Some(ext) = filter::extension_regex(pattern, full_path) {
+        return Ok(Box::new(ext));
+    }
+    #[cfg(feature = "regex")]
+    {
+        let re = Regex::new(pattern).map_err(|e| invalid(&format!("invalid regex '{}': {}", pattern, e)))?;
+        Ok(Box::new(MatchesRegex { re, full_path }))
+    }
+    #[cfg(not(feature = "regex"))]
+    Err(needs_regex(pattern))
+}
+
+#[cfg(not(feature = "regex"))]
+fn needs_regex(pattern: &str) -> Error {
+    Error::new(io::ErrorKind::Unsupported, format!("The regex '{}' needs ffind built with the regex feature.", pattern))
+}
+
+fn to_filters(specs: &[FilterSpec]) -> Result<Vec<Box<dyn Filter>>, Error> {
+    specs.iter().map(|spec| spec.to_filter()).collect()
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::new(io::ErrorKind::InvalidData, format!("Invalid search spec: {}.", reason))
+}
+
+fn yes() -> bool {
+    true
+}
+
+fn unlimited() -> u32 {
+    UNLIMITED_DEPTH
+}
+
+fn is_unlimited(depth: &u32) -> bool {
+    *depth == UNLIMITED_DEPTH
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn example() -> SearchSpec {
+        SearchSpec {
+            root: String::from("src"),
+            extra_roots: vec![String::from("./src")],
+            depth: 1,
+            min_depth: 1,
+            dedup: true,
+            metadata_mode: MetadataMode::NoFollow,
+            include_dirs: true,
+            ignore_files: false,
+            prune_if_contains: vec![String::from(".nomedia")],
+            max_entries_per_dir: Some(1000),
+            filters: vec![
+                FilterSpec::HasExtension { ext: String::from(".rs"), case_sensitive: true },
+                FilterSpec::Any(vec![
+                    FilterSpec::MatchesRegex(String::from("^l")),
+                    FilterSpec::MatchesGlob { glob: String::from("M*"), full_path: false, case_insensitive: true },
+                ]),
+                FilterSpec::MatchesRegexSet { patterns: vec![String::from("s$"), String::from(r"\.")], all: true, full_path: false },
+                FilterSpec::PathMatchesRegex(String::from("^src/")),
+                FilterSpec::NameContains(String::from(".rs")),
+                FilterSpec::NameEndsWith(String::from("rs")),
+                FilterSpec::Not(Box::new(FilterSpec::NameStartsWith(String::from("x")))),
+                FilterSpec::Not(Box::new(FilterSpec::StemIs(String::from("mod")))),
+                FilterSpec::FuzzyName { query: String::from("lbrs"), max_distance: 4 },
+                FilterSpec::HasAncestor(String::from("s?c")),
+                FilterSpec::Not(Box::new(FilterSpec::OlderThanSibling(String::from("Cargo.toml")))),
+                FilterSpec::Not(Box::new(FilterSpec::OlderThan(24 * 60 * 60))),
+                FilterSpec::Not(Box::new(FilterSpec::SizeLessThanOrEq(10))),
+                FilterSpec::Expression(String::from("-size +0")),
+                FilterSpec::ContentMatches { pattern: String::from("^"), search_compressed: true },
+                FilterSpec::LineCount(String::from("+0")),
+                FilterSpec::Utf8 { valid: true, sample: Some(4096) },
+                FilterSpec::Not(Box::new(FilterSpec::Git(GitState::Untracked))),
+            ],
+            actions: vec![ActionSpec::Print],
+        }
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let spec = example();
+        let json = serde_json::to_string_pretty(&spec.to_json()).unwrap();
+        assert_eq!(spec, SearchSpec::from_json(&json).unwrap());
+        let unlimited = SearchSpec::new(String::from("."), UNLIMITED_DEPTH);
+        let json = unlimited.to_json().to_string();
+        assert!(!json.contains("\"depth\""));
+        assert_eq!(unlimited, SearchSpec::from_json(&json).unwrap());
+    }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn toml_round_trip() {
+        let spec = example();
+        let toml = spec.to_toml().unwrap();
+        assert_eq!(spec, SearchSpec::from_toml(&toml).unwrap());
+        let spec = SearchSpec::from_toml("root = \"src\"\nfilters = [{ name_contains = \"lib\" }]\n").unwrap();
+        assert_eq!(UNLIMITED_DEPTH, spec.depth);
+        assert_eq!(vec![FilterSpec::NameContains(String::from("lib"))], spec.filters);
+        assert!(SearchSpec::from_toml("root = 1").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "regex")]
+    fn run_spec() {
+        use crate::{dir, file};
+        let fs = dir! {
+            "src" => {
+                file!("lib.rs" => "a\n".repeat(50)),
+                file!("main.rs" => "a\n".repeat(50)),
+                file!("mod.rs" => "a\n\n"),
+                file!("x.rs" => "a\n".repeat(50)),
+            },
+        };
+        let spec = example();
+        let mut result = Finder::from_spec(&spec).unwrap().file_system(fs).find(spec.depth).unwrap();
+        result.sort();
+        assert_eq!(vec!["src/lib.rs".to_string(), "src/main.rs".to_string()], result);
+        // `run()` searches the disk.
+        let dir = std::env::temp_dir().join(format!("ffind-spec-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("lib.rs"), "a\n").unwrap();
+        let mut spec = SearchSpec::new(dir.to_str().unwrap().to_string(), 1);
+        spec.filters.push(FilterSpec::HasExtension { ext: String::from(".rs"), case_sensitive: true });
+        spec.actions.push(ActionSpec::Print);
+        assert_eq!(vec![dir.join("lib.rs").to_str().unwrap().to_string()], spec.run().unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_specs() {
+        assert!(SearchSpec::from_json(r#"{"depth": 1}"#).is_err());
+        assert!(SearchSpec::from_json(r#"{"root": ".", "filters": [{"bogus": 1}]}"#).is_err());
+        assert!(SearchSpec::from_json(r#"{"root": ".", "actions": ["delete"]}"#).is_err());
+        assert!(SearchSpec::from_json(r#"{"root": ".", "metadata": "sometimes"}"#).is_err());
+        assert!(SearchSpec::from_json(r#"{"root": ".", "filters": [{"git": "stashed"}]}"#).is_err());
+        let spec = SearchSpec::from_json(r#"{"root": ".", "filters": [{"matches_regex": "("}]}"#).unwrap();
+        assert!(Finder::from_spec(&spec).is_err());
+        // Plain text is matched without a regex, in every build.
+        assert!(FilterSpec::MatchesRegex(String::from("lib")).to_filter().is_ok());
+    }
+
+}
@@ -0,0 +1,230 @@
+/*
+Answering searches from the Spotlight index on macOS.
+
+Spotlight keeps an index of the names, sizes and other attributes of the
+files of every indexed volume. 'search()' translates the filters of a search
+into a Spotlight query, asks mdfind(1) which files of the roots match it, and
+checks those with the filters of the 'Finder' like a walk would, so results
+are the same as walking the tree, only instant.
+
+Names, extensions, sizes and regexes that are plain text translate, in any
+combination with 'All' and 'Any'; the other filters only narrow the files
+that mdfind returns. When
+nothing translates, or a root isn't indexed (see 'mdutil -s'), the search
+isn't answered here and should walk the tree instead. Like queries answered
+by the daemon, searches answered by Spotlight aren't affected by ignore files.
+
+mdfind and mdutil only exist on macOS.
+
+ */
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use crate::{file_id, filter, Finder};
+use crate::spec::{FilterSpec, SearchSpec};
+use crate::trace::{self, Level};
+
+
+/// Translates `filters` into a Spotlight query matching at least the files
+/// that pass them all, or `None` if they don't narrow the search.
+pub fn query_of(filters: &[FilterSpec]) -> Option<String> {
+    let terms: Vec<String> = filters.iter().filter_map(term_of).collect();
+    match terms.len() {
+        0 => None,
+        1 => terms.into_iter().next(),
+        _ => Some(terms.iter().map(|term| format!("({})", term)).collect::<Vec<_>>().join(" && ")),
+    }
+}
+
+fn term_of(filter: &FilterSpec) -> Option<String> {
+    match filter {
+        FilterSpec::SizeLessThanOrEq(bytes) => Some(format!("kMDItemFSSize <= {}", bytes)),
+        FilterSpec::SizeGreaterThanOrEq(bytes) => Some(format!("kMDItemFSSize >= {}", bytes)),
+        FilterSpec::HasExtension { ext, case_sensitive } =>
+            Some(name_is(&format!("*{}", quote(ext)), !case_sensitive)),
+        FilterSpec::NameContains(text) => Some(name_is(&format!("*{}*", quote(text)), false)),
+        FilterSpec::NameStartsWith(prefix) => Some(name_is(&format!("{}*", quote(prefix)), false)),
+        FilterSpec::NameEndsWith(suffix) => Some(name_is(&format!("*{}", quote(suffix)), false)),
+        FilterSpec::StemIs(stem) => Some(format!("({}) || ({})", name_is(&quote(stem), false), name_is(&format!("{}.*", quote(stem)), false))),
+        FilterSpec::MatchesRegex(pattern) if filter::is_literal(pattern) =>
+            Some(name_is(&format!("*{}*", quote(pattern)), false)),
+        FilterSpec::MatchesGlob { glob, full_path: false, case_insensitive } =>
+            Some(name_is(&wildcards_of(glob), *case_insensitive)),
+        FilterSpec::All(filters) => query_of(filters),
+        FilterSpec::Any(filters) => {
+            let terms = filters.iter().map(term_of).collect::<Option<Vec<_>>>()?;
+            match terms.len() {
+                0 => None,
+                _ => Some(terms.iter().map(|term| format!("({})", term)).collect::<Vec<_>>().join(" || ")),
+            }
+        }
+        _ => None,
+    }
+}
+
+fn name_is(pattern: &str, case_insensitive: bool) -> String {
+    format!("kMDItemFSName == \"{}\"{}", pattern, if case_insensitive { "c" } else { "" })
+}
+
+/// Escapes `text` for a quoted Spotlight value.
+fn quote(text: &str) -> String {
+    text.chars().fold(String::new(), |mut quoted, c| {
+        if matches!(c, '"' | '\\' | '*') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+        quoted
+    })
+}
+
+/// A Spotlight pattern matching at least the names of `glob` (see the `glob`
+/// module): Spotlight only has `*`, so every other wildcard becomes one too.
+fn wildcards_of(glob: &str) -> String {
+    let mut pattern = String::new();
+    let mut chars = glob.chars();
+    while let Some(c) = chars.next() {
+        let literal = match c {
+            '*' | '?' => None,
+            '[' => {
+                chars.by_ref().find(|&c| c == ']');
+                None
+            }
+            '{' => {
+                chars.by_ref().find(|&c| c == '}');
+                None
+            }
+            '\\' => chars.next(),
+            c => Some(c),
+        };
+        match literal {
+            Some(c) => pattern.push_str(&quote(&c.to_string())),
+            None if !pattern.ends_with('*') || pattern.ends_with("\\*") => pattern.push('*'),
+            None => {}
+        }
+    }
+    pattern
+}
+
+/// Runs the search of `finder`, as described by `spec`, with mdfind. Returns
+/// `None` if it can't be answered by Spotlight, see the module documentation.
+pub fn search(finder: &Finder, spec: &SearchSpec) -> Option<Result<Vec<String>, Error>> {
+    let query = query_of(&spec.filters)?;
+    if let Some(root) = finder.roots.iter().find(|root| !is_indexed(root)) {
+        trace::event(Level::Info, || format!("{} isn't indexed by Spotlight", root));
+        return None;
+    }
+    trace::event(Level::Info, || format!("asking Spotlight for {}", query));
+    let mut result = Vec::new();
+    let mut seen = HashSet::new();
+    for root in &finder.roots {
+        let found = match mdfind(root, &query) {
+            Ok(found) => found,
+            Err(e) => return Some(Err(e)),
+        };
+        for path in matches_below(finder, spec, root, found) {
+            let file = finder.file_info(path);
+            if finder.dedup && file_id(&file).is_some_and(|id| !seen.insert(id)) {
+                continue;
+            }
+            result.push(file.into_path_string());
+        }
+    }
+    finder.rank(&mut result);
+    Some(Ok(result))
+}
+
+/// Returns true if Spotlight indexes the volume of `root`.
+fn is_indexed(root: &str) -> bool {
+    match Command::new("mdutil").args(["-s", root]).output() {
+        Ok(output) => output.status.success() && String::from_utf8_lossy(&output.stdout).contains("Indexing enabled"),
+        Err(_) => false,
+    }
+}
+
+/// The absolute paths of the files below `root` matching `query`.
+fn mdfind(root: &str, query: &str) -> Result<Vec<PathBuf>, Error> {
+    let output = Command::new("mdfind").args(["-0", "-onlyin", root, query]).output()?;
+    if !output.status.success() {
+        return Err(Error::other(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+    Ok(output.stdout
+        .split(|&b| b == 0)
+        .filter(|path| !path.is_empty())
+        .map(|path| PathBuf::from(String::from_utf8_lossy(path).into_owned()))
+        .collect())
+}
+
+/// The paths of `found`, absolute paths below `root`, as a walk of `root`
+/// would find them, like `./src/lib.rs` for a root `.`, which are within
+/// the depth of `spec` and pass the filters of `finder`.
+fn matches_below(finder: &Finder, spec: &SearchSpec, root: &str, found: Vec<PathBuf>) -> Vec<PathBuf> {
+    let canonical = match fs::canonicalize(root) {
+        Ok(canonical) => canonical,
+        Err(_) => return Vec::new(),
+    };
+    found.into_iter()
+        .filter_map(|path| {
+            // A file directly in a root is at depth 1 (see `Finder::find()`).
+            let relative = path.strip_prefix(&canonical).ok()?;
+            let depth = relative.components().count() as u32;
+            if !(spec.min_depth..=spec.depth).contains(&depth) {
+                return None;
+            }
+            let path = Path::new(root).join(relative);
+            let file = finder.file_info(path.clone());
+            let meta = file.metadata()?;
+            if !(meta.is_file() || meta.is_dir() && spec.include_dirs) || !finder.meets_filter_criteria(&file) {
+                return None;
+            }
+            Some(path)
+        })
+        .collect()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn filters_translate_to_queries() {
+        let ext = || FilterSpec::HasExtension { ext: String::from(".rs"), case_sensitive: false };
+        let glob = |glob: &str| FilterSpec::MatchesGlob { glob: String::from(glob), full_path: false, case_insensitive: false };
+        assert_eq!(Some(String::from("kMDItemFSName == \"*.rs\"c")), query_of(&[ext()]));
+        assert_eq!(Some(String::from("(kMDItemFSName == \"*.rs\"c) && (kMDItemFSSize >= 1024)")),
+                   query_of(&[ext(), FilterSpec::SizeGreaterThanOrEq(1024)]));
+        assert_eq!(Some(String::from("(kMDItemFSName == \"test_*.py\") || (kMDItemFSName == \"*_test.go\")")),
+                   query_of(&[FilterSpec::Any(vec![glob("test_?*.py"), glob("*_test.go")])]));
+        assert_eq!(Some(String::from("kMDItemFSName == \"*.*\\*\"")), query_of(&[glob("*.[ch]\\*")]));
+        assert_eq!(Some(String::from("(kMDItemFSName == \"lib\") || (kMDItemFSName == \"lib.*\")")),
+                   query_of(&[FilterSpec::StemIs(String::from("lib"))]));
+        assert_eq!(Some(String::from("kMDItemFSName == \"*report*\"")),
+                   query_of(&[FilterSpec::MatchesRegex(String::from("report"))]));
+
+        // Filters Spotlight doesn't know only narrow what it returns.
+        let regex = || FilterSpec::MatchesRegex(String::from("^src/"));
+        assert_eq!(query_of(&[ext()]), query_of(&[ext(), regex()]));
+        assert_eq!(None, query_of(&[regex()]));
+        assert_eq!(None, query_of(&[FilterSpec::Any(vec![ext(), regex()])]));
+        assert_eq!(None, query_of(&[FilterSpec::Not(Box::new(ext()))]));
+        assert_eq!(None, query_of(&[]));
+    }
+
+    #[test]
+    fn results_are_paths_from_the_roots() {
+        let mut spec = SearchSpec::new(String::from("src"), 1);
+        spec.filters.push(FilterSpec::HasExtension { ext: String::from(".rs"), case_sensitive: true });
+        let finder = Finder::from_spec(&spec).unwrap();
+        let canonical = fs::canonicalize("src").unwrap();
+        let found = vec![canonical.join("lib.rs"), canonical.join("bin"), canonical.join("testfs.rs"), PathBuf::from("/elsewhere/a.rs")];
+        let mut expected = vec![PathBuf::from("src/lib.rs"), PathBuf::from("src/testfs.rs")];
+        let mut result = matches_below(&finder, &spec, "src", found);
+        expected.sort();
+        result.sort();
+        assert_eq!(expected, result);
+    }
+
+}
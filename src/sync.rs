@@ -0,0 +1,132 @@
+/*
+Plans to make one tree match another.
+
+From the 'Comparison' of the matches of a search in two trees (see the
+'manifest' module), a 'SyncPlan' lists the operations that would make the
+second tree match the first for those files, like rsync --delete restricted
+to them: copying the files only in the first tree or differing, and deleting
+the files only in the second. Plans are only ever written out, as JSON or as
+a shell script to review and run; nothing is copied or deleted here.
+
+ */
+
+use std::path::Path;
+use serde_json::{json, Value};
+use crate::manifest::Comparison;
+
+
+/// Something to do to the second tree, by path relative to the roots.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operation {
+    /// Copy the file from the first tree over the second.
+    Copy(String),
+    /// Delete the file from the second tree.
+    Delete(String),
+}
+
+/// The operations making the tree `to` match the tree `from`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncPlan {
+    pub from: String,
+    pub to: String,
+    pub operations: Vec<Operation>,
+}
+
+impl SyncPlan {
+
+    /// Plans to bring `to` in line with `from`, given how `to` compares to
+    /// `from`: what `comparison` has as removed (only in `from`) or changed
+    /// is copied, what it has as added (only in `to`) is deleted.
+    pub fn new(from: &str, to: &str, comparison: &Comparison) -> SyncPlan {
+        let mut copies: Vec<&String> = comparison.removed.iter().chain(&comparison.changed).collect();
+        copies.sort();
+        let operations = copies.into_iter()
+            .map(|path| Operation::Copy(path.clone()))
+            .chain(comparison.added.iter().map(|path| Operation::Delete(path.clone())))
+            .collect();
+        SyncPlan { from: from.to_string(), to: to.to_string(), operations }
+    }
+
+    /// Every operation as a JSON object, like `{"op":"copy","from":..,"to":..}`
+    /// or `{"op":"delete","path":..}`, with full paths.
+    pub fn to_json(&self) -> Vec<Value> {
+        self.operations.iter()
+            .map(|operation| match operation {
+                Operation::Copy(path) => json!({
+                    "op": "copy",
+                    "from": join(&self.from, path),
+                    "to": join(&self.to, path),
+                }),
+                Operation::Delete(path) => json!({
+                    "op": "delete",
+                    "path": join(&self.to, path),
+                }),
+            })
+            .collect()
+    }
+
+    /// The plan as a POSIX shell script, stopping at the first failure.
+    pub fn to_shell(&self) -> String {
+        let mut script = format!("#!/bin/sh\n# Makes {} match {}.\nset -e\n", comment(&self.to), comment(&self.from));
+        for operation in &self.operations {
+            match operation {
+                Operation::Copy(path) => {
+                    let to = join(&self.to, path);
+                    if let Some(parent) = Path::new(path).parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                        script.push_str(&format!("mkdir -p -- {}\n", quote(&join(&self.to, &parent.to_string_lossy()))));
+                    }
+                    script.push_str(&format!("cp -p -- {} {}\n", quote(&join(&self.from, path)), quote(&to)));
+                }
+                Operation::Delete(path) => script.push_str(&format!("rm -f -- {}\n", quote(&join(&self.to, path)))),
+            }
+        }
+        script
+    }
+}
+
+fn join(root: &str, path: &str) -> String {
+    Path::new(root).join(path).to_string_lossy().into_owned()
+}
+
+/// Quotes `text` for the shell.
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+/// Escapes `text` to write it in a comment, which a newline would end.
+fn comment(text: &str) -> String {
+    text.escape_debug().to_string()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn plans_copies_and_deletes() {
+        let comparison = Comparison {
+            added: vec![String::from("extra.txt")],
+            removed: vec![String::from("sub/new.txt")],
+            changed: vec![String::from("it's.txt")],
+        };
+        let plan = SyncPlan::new("a", "b", &comparison);
+        assert_eq!(vec![Operation::Copy(String::from("it's.txt")), Operation::Copy(String::from("sub/new.txt")),
+                        Operation::Delete(String::from("extra.txt"))], plan.operations);
+        assert_eq!("#!/bin/sh\n# Makes b match a.\nset -e\n\
+                    cp -p -- 'a/it'\\''s.txt' 'b/it'\\''s.txt'\n\
+                    mkdir -p -- 'b/sub'\ncp -p -- 'a/sub/new.txt' 'b/sub/new.txt'\n\
+                    rm -f -- 'b/extra.txt'\n", plan.to_shell());
+        // Nor can a root be taken for options.
+        let options = SyncPlan::new("-a", "-rf", &comparison).to_shell();
+        assert!(options.contains("\ncp -p -- '-a/sub/new.txt' '-rf/sub/new.txt'\n"));
+        assert!(options.ends_with("\nrm -f -- '-rf/extra.txt'\n"));
+        // A root can't end the comment naming it.
+        let hostile = SyncPlan::new("a", "b\nrm -rf ~", &comparison);
+        assert!(hostile.to_shell().starts_with("#!/bin/sh\n# Makes b\\nrm -rf ~ match a.\nset -e\n"));
+        let json: Vec<String> = plan.to_json().iter().map(|op| op.to_string()).collect();
+        assert_eq!(r#"{"op":"copy","from":"a/sub/new.txt","to":"b/sub/new.txt"}"#, json[1]);
+        assert_eq!(r#"{"op":"delete","path":"b/extra.txt"}"#, json[2]);
+    }
+
+}
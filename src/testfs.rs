@@ -0,0 +1,178 @@
+/*
+Declarative in-memory trees for tests.
+
+The 'dir!' macro builds a 'MemoryFileSystem' (see the 'vfs' module) from a
+description of its tree, which a 'Finder' can then search through
+'Finder::file_system()'. Tests written this way don't depend on whatever
+happens to be on disk.
+
+    use rustfind::{dir, file, Finder};
+    use rustfind::testfs::Entry;
+
+    let fs = dir! {
+        file!("Cargo.toml", 120),
+        "src" => {
+            file!("lib.rs" => "pub mod a;"),
+            file!("a.rs"),
+            Entry::symlink("main.rs", "lib.rs"),
+        },
+    };
+    let found = Finder::new(String::from("src")).file_system(fs).find(1).unwrap();
+
+Inside 'dir!', "name" => { ... } is a directory and anything else is an
+expression giving an 'Entry', like 'file!(...)' or 'Entry::symlink(...)'.
+
+ */
+
+use std::path::{Path, PathBuf};
+use crate::vfs::MemoryFileSystem;
+
+
+/// A file, directory or symlink in a tree described with `dir!`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    name: String,
+    kind: Kind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Kind {
+    File(Vec<u8>),
+    Dir(Vec<Entry>),
+    Symlink(PathBuf),
+}
+
+impl Entry {
+
+    pub fn file(name: impl Into<String>, contents: impl Into<Vec<u8>>) -> Entry {
+        Entry { name: name.into(), kind: Kind::File(contents.into()) }
+    }
+
+    pub fn dir(name: impl Into<String>, entries: Vec<Entry>) -> Entry {
+        Entry { name: name.into(), kind: Kind::Dir(entries) }
+    }
+
+    /// A symlink pointing to `target`, relative to the directory it is in.
+    pub fn symlink(name: impl Into<String>, target: impl AsRef<Path>) -> Entry {
+        Entry { name: name.into(), kind: Kind::Symlink(target.as_ref().to_path_buf()) }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Builds a `MemoryFileSystem` whose root directory holds `entries`. This is
+/// what `dir!` expands to.
+pub fn tree(entries: Vec<Entry>) -> MemoryFileSystem {
+    let mut fs = MemoryFileSystem::new();
+    add_entries(&mut fs, Path::new(""), entries);
+    fs
+}
+
+fn add_entries(fs: &mut MemoryFileSystem, dir: &Path, entries: Vec<Entry>) {
+    for entry in entries {
+        let path = dir.join(&entry.name);
+        match entry.kind {
+            Kind::File(contents) => {
+                fs.add_file(&path, contents);
+            }
+            Kind::Dir(entries) => {
+                fs.add_dir(&path);
+                add_entries(fs, &path, entries);
+            }
+            Kind::Symlink(target) => {
+                fs.add_symlink(&path, target);
+            }
+        }
+    }
+}
+
+
+/// Builds a `MemoryFileSystem` from a description of its tree, see the
+/// `testfs` module.
+///
+/// ```
+/// use rustfind::{dir, file, Finder};
+///
+/// let fs = dir! { "src" => { file!("lib.rs", 100), file!("notes.txt") } };
+/// let found = Finder::new(String::from("src"))
+///     .file_system(fs)
+///     .has_extension(String::from(".rs"))
+///     .find(1)
+///     .unwrap();
+/// assert_eq!(vec!["src/lib.rs"], found);
+/// ```
+#[macro_export]
+macro_rules! dir {
+    ($($entries:tt)*) => {
+        $crate::testfs::tree($crate::__testfs_entries!([] $($entries)*))
+    };
+}
+
+/// A file entry for `dir!`: `file!("a.rs")` is empty, `file!("a.rs", 100)`
+/// holds 100 zero bytes and `file!("a.rs" => "fn a() {}")` holds the given
+/// contents.
+#[macro_export]
+macro_rules! file {
+    ($name:expr) => {
+        $crate::testfs::Entry::file($name, ::std::vec::Vec::new())
+    };
+    ($name:expr => $contents:expr) => {
+        $crate::testfs::Entry::file($name, $contents)
+    };
+    ($name:expr, $size:expr) => {
+        $crate::testfs::Entry::file($name, ::std::vec![0u8; $size])
+    };
+}
+
+/// Collects the entries of a `dir!` into a `Vec`, one at a time.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __testfs_entries {
+    ([$($done:expr),*]) => {
+        ::std::vec![$($done),*]
+    };
+    ([$($done:expr),*] $name:literal => { $($inner:tt)* } $(, $($rest:tt)*)?) => {
+        $crate::__testfs_entries!(
+            [$($done,)* $crate::testfs::Entry::dir($name, $crate::__testfs_entries!([] $($inner)*))]
+            $($($rest)*)?)
+    };
+    ([$($done:expr),*] $entry:expr $(, $($rest:tt)*)?) => {
+        $crate::__testfs_entries!([$($done,)* $entry] $($($rest)*)?)
+    };
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::filter::MetadataMode;
+    use crate::vfs::FileSystem;
+
+    #[test]
+    fn builds_nested_trees() {
+        let fs = dir! {
+            file!("Cargo.toml", 120),
+            "src" => {
+                file!("lib.rs" => "pub mod a;"),
+                "a" => { file!("mod.rs") },
+                "empty" => {},
+                Entry::symlink("main.rs", "lib.rs"),
+            },
+        };
+        let meta = |path: &str| fs.metadata(Path::new(path), MetadataMode::Follow).unwrap();
+        assert_eq!(120, meta("Cargo.toml").len());
+        assert_eq!(10, meta("src/main.rs").len());
+        assert!(meta("src/a/mod.rs").is_empty());
+        assert!(meta("src/empty").is_dir());
+        assert_eq!(4, fs.read_dir(Path::new("src")).unwrap().len());
+    }
+
+    #[test]
+    fn empty_tree() {
+        let fs = dir! {};
+        assert!(fs.read_dir(Path::new(".")).unwrap().is_empty());
+    }
+
+}
@@ -0,0 +1,169 @@
+/*
+Throttling the IO of searches.
+
+Background scans, e.g. from cron on a production host, shouldn't starve the
+interactive workloads sharing the disk. With 'Finder::throttle()', a search
+reads at most the given number of bytes per second: contents read by filters
+count as they are read, and every directory listed counts as one block of
+'DIR_READ_COST' bytes. Short bursts are let through before the search slows
+down.
+
+'set_idle_priority()' additionally makes the whole process yield the CPU and
+the disk to everything else, with renice(1) and ionice(1).
+
+ */
+
+use std::io::{self, Error, Read};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::filter::MetadataMode;
+use crate::vfs::{DirEntries, FileSystem, Metadata};
+
+
+/// What listing a directory counts as, in bytes.
+pub const DIR_READ_COST: u64 = 4096;
+
+/// How much reading may get ahead of the rate before it is slowed down.
+const BURST: Duration = Duration::from_millis(100);
+
+/// Limits a rate of bytes per second, shared by everything reading through it.
+#[derive(Debug)]
+pub struct RateLimit {
+    bytes_per_second: u64,
+    /// When everything read so far would have been read at the rate.
+    caught_up: Mutex<Instant>,
+}
+
+impl RateLimit {
+
+    pub fn new(bytes_per_second: u64) -> RateLimit {
+        RateLimit { bytes_per_second: bytes_per_second.max(1), caught_up: Mutex::new(Instant::now()) }
+    }
+
+    /// Accounts for `bytes` read, sleeping as long as reading is ahead of
+    /// the rate.
+    pub fn charge(&self, bytes: u64) {
+        let wait = {
+            let mut caught_up = self.caught_up.lock().unwrap();
+            let now = Instant::now();
+            if *caught_up < now {
+                *caught_up = now;
+            }
+            *caught_up += Duration::from_secs_f64(bytes as f64 / self.bytes_per_second as f64);
+            caught_up.saturating_duration_since(now + BURST)
+        };
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+}
+
+/// A `FileSystem` reading from another one within a `RateLimit`.
+pub struct Throttled {
+    inner: Arc<dyn FileSystem>,
+    limit: Arc<RateLimit>,
+}
+
+impl Throttled {
+    pub fn new(inner: Arc<dyn FileSystem>, bytes_per_second: u64) -> Throttled {
+        Throttled { inner, limit: Arc::new(RateLimit::new(bytes_per_second)) }
+    }
+}
+
+impl FileSystem for Throttled {
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>, Error> {
+        self.limit.charge(DIR_READ_COST);
+        self.inner.read_dir(path)
+    }
+
+    fn read_dir_iter(&self, path: &Path) -> Result<DirEntries<'_>, Error> {
+        self.limit.charge(DIR_READ_COST);
+        self.inner.read_dir_iter(path)
+    }
+
+    /// Metadata is cached by the system and small, so it isn't counted.
+    fn metadata(&self, path: &Path, mode: MetadataMode) -> Result<Metadata, Error> {
+        self.inner.metadata(path, mode)
+    }
+
+    fn open(&self, path: &Path) -> Result<Box<dyn Read + Send>, Error> {
+        Ok(Box::new(ThrottledRead { inner: self.inner.open(path)?, limit: self.limit.clone() }))
+    }
+
+    fn id(&self, path: &Path, mode: MetadataMode) -> Option<(u64, u64)> {
+        self.inner.id(path, mode)
+    }
+
+    fn fs_type(&self, path: &Path) -> Option<String> {
+        self.inner.fs_type(path)
+    }
+}
+
+struct ThrottledRead {
+    inner: Box<dyn Read + Send>,
+    limit: Arc<RateLimit>,
+}
+
+impl Read for ThrottledRead {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.limit.charge(n as u64);
+        Ok(n)
+    }
+}
+
+/// Gives this process the lowest CPU priority and the idle IO scheduling
+/// class, so it only gets the disk when nothing else wants it. Needs
+/// renice(1), and ionice(1) from util-linux for the IO class. WASI has
+/// neither processes nor priorities.
+pub fn set_idle_priority() -> Result<(), Error> {
+    if cfg!(target_os = "wasi") {
+        return Err(Error::new(io::ErrorKind::Unsupported, "WASI has no process priorities."));
+    }
+    let pid = std::process::id().to_string();
+    run(Command::new("renice").args(["-n", "19", "-p", &pid]))?;
+    if cfg!(target_os = "linux") {
+        run(Command::new("ionice").args(["-c", "3", "-p", &pid]))?;
+    }
+    Ok(())
+}
+
+fn run(command: &mut Command) -> Result<(), Error> {
+    let output = command.output()?;
+    if !output.status.success() {
+        return Err(Error::other(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vfs::MemoryFileSystem;
+
+    #[test]
+    fn reads_are_slowed_down_to_the_rate() {
+        let mut fs = MemoryFileSystem::new();
+        fs.add_file("big.bin", vec![0; 300_000]);
+        let throttled = Throttled::new(Arc::new(fs), 1_000_000);
+        let start = Instant::now();
+        let mut contents = Vec::new();
+        throttled.open(Path::new("big.bin")).unwrap().read_to_end(&mut contents).unwrap();
+        assert_eq!(300_000, contents.len());
+        // 300ms worth of reading, less the burst.
+        assert!(start.elapsed() >= Duration::from_millis(190));
+    }
+
+    #[test]
+    fn bursts_are_not_slowed_down() {
+        let limit = RateLimit::new(1_000_000);
+        let start = Instant::now();
+        limit.charge(50_000);
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+
+}
@@ -0,0 +1,174 @@
+/*
+Where the time of a search goes.
+
+A search that is slow on some tree is slow for one of a few reasons: huge
+directories take long to list, network filesystems take long to look every
+entry up, filters reading contents take long to decide, or whatever takes
+the matches can't keep up. 'Timings', given to 'Finder::timings()', adds up
+the time spent in each of these phases, see 'Phase', and which directories
+it was spent in, so that the slowest ones can be excluded or their filters
+tuned.
+
+Listing a directory is charged to it, looking up and filtering its entries
+too. Timing every entry costs a little, so it is off by default.
+
+ */
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::vfs::DirEntries;
+
+
+/// What a search spends time on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Listing directories.
+    ReadDir,
+    /// Looking up the entries listed.
+    Stat,
+    /// Running the filters on them.
+    Filter,
+    /// Handing out the matches.
+    Output,
+}
+
+impl Phase {
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Phase::ReadDir => "readdir",
+            Phase::Stat => "stat",
+            Phase::Filter => "filter",
+            Phase::Output => "output",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// The time a search spends in every phase and directory. Like `FilterStats`,
+/// it is a cheap handle: clone it, hand one copy to the `Finder` and read the
+/// other once the search is done.
+#[derive(Debug, Clone, Default)]
+pub struct Timings {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    phases: [Duration; 4],
+    dirs: HashMap<PathBuf, Duration>,
+    entries: usize,
+}
+
+/// What `Timings` recorded, see `Timings::report()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimingReport {
+    /// The time spent in every phase, in the order of `Phase`.
+    pub phases: Vec<(Phase, Duration)>,
+    pub directories: usize,
+    pub entries: usize,
+    /// The directories most time was spent in, the slowest first.
+    pub hot_spots: Vec<(PathBuf, Duration)>,
+}
+
+impl Timings {
+
+    pub fn new() -> Timings {
+        Timings::default()
+    }
+
+    /// Runs `f`, charging the time it takes to `phase` and, if given, to the
+    /// directory `dir`.
+    pub(crate) fn time<T>(&self, phase: Phase, dir: Option<&Path>, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.record(phase, dir, start.elapsed());
+        result
+    }
+
+    pub(crate) fn record(&self, phase: Phase, dir: Option<&Path>, elapsed: Duration) {
+        let entries = if phase == Phase::Stat { 1 } else { 0 };
+        self.add(phase, dir, entries, elapsed);
+    }
+
+    /// Charges `elapsed` to looking up `entries` entries of `dir` at once.
+    pub(crate) fn record_lookups(&self, dir: &Path, entries: usize, elapsed: Duration) {
+        self.add(Phase::Stat, Some(dir), entries, elapsed);
+    }
+
+    fn add(&self, phase: Phase, dir: Option<&Path>, entries: usize, elapsed: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.phases[phase.index()] += elapsed;
+        inner.entries += entries;
+        if let Some(dir) = dir {
+            match inner.dirs.get_mut(dir) {
+                Some(spent) => *spent += elapsed,
+                None => {
+                    inner.dirs.insert(dir.to_path_buf(), elapsed);
+                }
+            }
+        }
+    }
+
+    /// Times reading `entries`, the entries of the directory `dir`, as they
+    /// are read.
+    pub(crate) fn entries<'a>(&self, dir: &Path, entries: DirEntries<'a>) -> DirEntries<'a> {
+        let (timings, dir) = (self.clone(), dir.to_path_buf());
+        let mut entries = entries;
+        Box::new(std::iter::from_fn(move || timings.time(Phase::ReadDir, Some(&dir), || entries.next())))
+    }
+
+    /// The time recorded so far, with the `hot_spots` directories most of it
+    /// was spent in.
+    pub fn report(&self, hot_spots: usize) -> TimingReport {
+        let inner = self.inner.lock().unwrap();
+        let phases = [Phase::ReadDir, Phase::Stat, Phase::Filter, Phase::Output].iter()
+            .map(|&phase| (phase, inner.phases[phase.index()]))
+            .collect();
+        let mut dirs: Vec<_> = inner.dirs.iter().map(|(dir, &spent)| (dir.clone(), spent)).collect();
+        dirs.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        let directories = dirs.len();
+        dirs.truncate(hot_spots);
+        TimingReport { phases, directories, entries: inner.entries, hot_spots: dirs }
+    }
+}
+
+impl TimingReport {
+
+    /// The time spent in all phases.
+    pub fn total(&self) -> Duration {
+        self.phases.iter().map(|(_, spent)| *spent).sum()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn charges_phases_and_dirs() {
+        let timings = Timings::new();
+        let ms = Duration::from_millis;
+        timings.record(Phase::Stat, Some(Path::new("a")), ms(3));
+        timings.record(Phase::Filter, Some(Path::new("a")), ms(2));
+        timings.record(Phase::Stat, Some(Path::new("b")), ms(7));
+        timings.record(Phase::Output, None, ms(1));
+        let entries: DirEntries = Box::new(vec![Ok(PathBuf::from("c/1")), Ok(PathBuf::from("c/2"))].into_iter());
+        assert_eq!(2, timings.entries(Path::new("c"), entries).count());
+
+        let report = timings.report(2);
+        assert_eq!(vec![Phase::ReadDir, Phase::Stat, Phase::Filter, Phase::Output],
+                   report.phases.iter().map(|&(phase, _)| phase).collect::<Vec<_>>());
+        assert_eq!((ms(10), ms(2), ms(1)), (report.phases[1].1, report.phases[2].1, report.phases[3].1));
+        assert_eq!((3, 2), (report.directories, report.entries));
+        assert_eq!(vec![(PathBuf::from("b"), ms(7)), (PathBuf::from("a"), ms(5))], report.hot_spots);
+        assert!(report.total() >= ms(13));
+    }
+
+}
@@ -0,0 +1,186 @@
+/*
+Structured diagnostics for embedders.
+
+The 'Finder' reports what it is doing (directories read, files rejected, how
+long a search took) as events and spans sent to a global 'Subscriber', instead
+of printing anything itself. Nothing is reported until a subscriber is set with
+'set_subscriber()'. The model follows the 'tracing' crate: events have a level
+and happen inside (possibly nested) named spans.
+
+Everything in here compiles down to nothing unless the 'trace' feature (on by
+default) is enabled.
+
+ */
+
+use std::fmt;
+use std::sync::OnceLock;
+use std::time::Duration;
+#[cfg(feature = "trace")]
+use std::time::Instant;
+
+
+/// The verbosity of an event, from most to least important.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Receives the events and spans of every search in the process.
+pub trait Subscriber: Send + Sync {
+
+    /// Returns true if events of the given `level` should be reported at all.
+    /// Messages of disabled events are never formatted.
+    fn enabled(&self, level: Level) -> bool;
+
+    /// Called for every enabled event. `span` is the innermost span the event
+    /// happened in, if any.
+    fn event(&self, level: Level, span: Option<&'static str>, message: &str);
+
+    /// Called when a span is entered.
+    fn enter(&self, _span: &'static str) {}
+
+    /// Called when a span is exited, with the time spent inside of it.
+    fn exit(&self, _span: &'static str, _elapsed: Duration) {}
+}
+
+static SUBSCRIBER: OnceLock<Box<dyn Subscriber>> = OnceLock::new();
+
+/// Sets the global subscriber. It can only be set once per process; later calls
+/// return the rejected subscriber back as an error.
+pub fn set_subscriber(subscriber: impl Subscriber + 'static) -> Result<(), Box<dyn Subscriber>> {
+    SUBSCRIBER.set(Box::new(subscriber))
+}
+
+
+/// A subscriber printing events up to `max_level` to stderr, e.g.
+/// `DEBUG find: reading directory src`.
+pub struct StderrSubscriber {
+    pub max_level: Level,
+}
+
+impl Subscriber for StderrSubscriber {
+    fn enabled(&self, level: Level) -> bool {
+        level <= self.max_level
+    }
+
+    fn event(&self, level: Level, span: Option<&'static str>, message: &str) {
+        match span {
+            Some(span) => eprintln!("{} {}: {}", level, span, message),
+            None => eprintln!("{} {}", level, message),
+        }
+    }
+
+    fn exit(&self, span: &'static str, elapsed: Duration) {
+        if self.enabled(Level::Trace) {
+            eprintln!("{} {}: done in {:?}", Level::Trace, span, elapsed);
+        }
+    }
+}
+
+
+#[cfg(feature = "trace")]
+thread_local! {
+    static CURRENT_SPAN: std::cell::Cell<Option<&'static str>> = const { std::cell::Cell::new(None) };
+}
+
+/// Returns true if an event of the given level would be reported.
+#[inline]
+pub(crate) fn enabled(level: Level) -> bool {
+    #[cfg(feature = "trace")]
+    {
+        SUBSCRIBER.get().is_some_and(|s| s.enabled(level))
+    }
+    #[cfg(not(feature = "trace"))]
+    {
+        let _ = level;
+        false
+    }
+}
+
+/// Reports an event. `message` is only called if the event is enabled.
+#[inline]
+pub(crate) fn event(level: Level, message: impl FnOnce() -> String) {
+    #[cfg(feature = "trace")]
+    {
+        if let Some(subscriber) = SUBSCRIBER.get() {
+            if subscriber.enabled(level) {
+                let span = CURRENT_SPAN.with(|current| current.get());
+                subscriber.event(level, span, &message());
+            }
+        }
+    }
+    #[cfg(not(feature = "trace"))]
+    {
+        let _ = (level, message);
+    }
+}
+
+/// A span that is exited when dropped.
+pub(crate) struct Span {
+    #[cfg(feature = "trace")]
+    entered: Option<(&'static str, Option<&'static str>, Instant)>,
+}
+
+/// Enters the span `name` until the returned guard is dropped.
+#[inline]
+pub(crate) fn span(name: &'static str) -> Span {
+    #[cfg(feature = "trace")]
+    {
+        let entered = SUBSCRIBER.get().map(|subscriber| {
+            subscriber.enter(name);
+            let parent = CURRENT_SPAN.with(|current| current.replace(Some(name)));
+            (name, parent, Instant::now())
+        });
+        Span { entered }
+    }
+    #[cfg(not(feature = "trace"))]
+    {
+        let _ = name;
+        Span {}
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        #[cfg(feature = "trace")]
+        {
+            if let (Some((name, parent, start)), Some(subscriber)) = (self.entered, SUBSCRIBER.get()) {
+                CURRENT_SPAN.with(|current| current.set(parent));
+                subscriber.exit(name, start.elapsed());
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn levels_are_ordered_by_verbosity() {
+        let subscriber = StderrSubscriber { max_level: Level::Debug };
+        assert!(subscriber.enabled(Level::Warn));
+        assert!(subscriber.enabled(Level::Debug));
+        assert!(!subscriber.enabled(Level::Trace));
+        assert_eq!("DEBUG", Level::Debug.to_string());
+    }
+
+}
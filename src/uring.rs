@@ -0,0 +1,412 @@
+/*
+An io_uring backed filesystem on Linux (experimental).
+
+Looking up the metadata of every entry one system call at a time leaves fast
+NVMe drives mostly idle. 'UringFileSystem' submits the statx(2) of all the
+entries of a directory at once through an io_uring, as soon as the directory
+is listed, so the kernel works on them in parallel, and answers the metadata
+requests of the search from those results.
+
+io_uring has no operation to list directories, so they are still listed with
+getdents64(2), see the 'linux' module, and entirely rather than as they are
+needed. Everything else, and every request the ring can't answer, goes
+through 'LinuxFileSystem'.
+
+The ring is set up with the raw io_uring_setup(2) and io_uring_enter(2)
+system calls (Linux 5.6 or later for statx), which container runtimes and
+sysctls often disable; 'UringFileSystem::new()' fails then.
+
+Only available with the 'io-uring' feature, on Linux.
+
+ */
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs;
+use std::io::{Error, ErrorKind, Read};
+use std::os::raw::{c_int, c_long, c_void};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU32, Ordering};
+use crate::filter::MetadataMode;
+use crate::mmap;
+use crate::linux::{self, LinuxFileSystem, Statx, AT_FDCWD, AT_STATX_DONT_SYNC, STATX_MASK};
+use crate::trace::{self, Level};
+use crate::vfs::{FileSystem, Metadata};
+
+
+const SYS_IO_URING_SETUP: c_long = 425;
+const SYS_IO_URING_ENTER: c_long = 426;
+const IORING_OFF_SQ_RING: i64 = 0;
+const IORING_OFF_CQ_RING: i64 = 0x8000000;
+const IORING_OFF_SQES: i64 = 0x10000000;
+const IORING_ENTER_GETEVENTS: u32 = 1;
+const IORING_OP_STATX: u8 = 21;
+const PROT_READ: c_int = 1;
+const PROT_WRITE: c_int = 2;
+const MAP_SHARED: c_int = 1;
+const MAP_POPULATE: c_int = 0x8000;
+
+/// How many requests are in flight at once.
+const RING_ENTRIES: u32 = 256;
+
+extern "C" {
+    fn mmap(addr: *mut c_void, len: usize, prot: c_int, flags: c_int, fd: c_int, offset: i64) -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+}
+
+/// `struct io_sqring_offsets` from `<linux/io_uring.h>`.
+#[repr(C)]
+#[derive(Default)]
+struct SqOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+/// `struct io_cqring_offsets`.
+#[repr(C)]
+#[derive(Default)]
+struct CqOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+/// `struct io_uring_params`.
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqOffsets,
+    cq_off: CqOffsets,
+}
+
+/// `struct io_uring_sqe`, as used for statx.
+#[repr(C)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    /// The `struct statx` to fill in.
+    off: u64,
+    /// The path.
+    addr: u64,
+    /// The statx mask.
+    len: u32,
+    /// The statx flags.
+    op_flags: u32,
+    user_data: u64,
+    pad: [u64; 3],
+}
+
+/// `struct io_uring_cqe`.
+#[repr(C)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+/// A shared memory mapping of a ring.
+struct Mmap {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Mmap {
+    fn new(fd: c_int, len: usize, offset: i64) -> Result<Mmap, Error> {
+        // SAFETY: a new shared mapping of the ring, unmapped on drop.
+        let ptr = unsafe { mmap(ptr::null_mut(), len, PROT_READ | PROT_WRITE, MAP_SHARED | MAP_POPULATE, fd, offset) };
+        if ptr as isize == -1 {
+            return Err(Error::last_os_error());
+        }
+        Ok(Mmap { ptr: ptr as *mut u8, len })
+    }
+
+    /// The u32 at `offset`, shared with the kernel.
+    fn atomic(&self, offset: u32) -> &AtomicU32 {
+        // SAFETY: offsets come from the kernel and are aligned within the mapping.
+        unsafe { &*(self.ptr.add(offset as usize) as *const AtomicU32) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        // SAFETY: unmapping what `new()` mapped.
+        unsafe { munmap(self.ptr as *mut c_void, self.len) };
+    }
+}
+
+/// An io_uring, with its submission and completion queues.
+struct Ring {
+    fd: fs::File,
+    params: Params,
+    sq: Mmap,
+    cq: Mmap,
+    sqes: Mmap,
+}
+
+// SAFETY: the mappings belong to the ring, which is used by one thread at a
+// time behind a mutex.
+unsafe impl Send for Ring {}
+
+impl Ring {
+
+    fn new(entries: u32) -> Result<Ring, Error> {
+        let mut params = Params::default();
+        // SAFETY: `params` is a `struct io_uring_params` for the kernel to fill in.
+        let fd = unsafe { linux::syscall(SYS_IO_URING_SETUP, entries, &mut params as *mut Params) };
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+        // SAFETY: the ring's file descriptor is ours to close.
+        let fd = unsafe { fs::File::from_raw_fd(fd as c_int) };
+        let raw = fd.as_raw_fd();
+        let sq = Mmap::new(raw, (params.sq_off.array + params.sq_entries * 4) as usize, IORING_OFF_SQ_RING)?;
+        let cq = Mmap::new(raw, (params.cq_off.cqes + params.cq_entries * std::mem::size_of::<Cqe>() as u32) as usize, IORING_OFF_CQ_RING)?;
+        let sqes = Mmap::new(raw, params.sq_entries as usize * std::mem::size_of::<Sqe>(), IORING_OFF_SQES)?;
+        Ok(Ring { fd, params, sq, cq, sqes })
+    }
+
+    fn enter(&self, to_submit: u32, min_complete: u32, flags: u32) -> Result<u32, Error> {
+        loop {
+            // SAFETY: no signal mask is passed.
+            let n = unsafe {
+                linux::syscall(SYS_IO_URING_ENTER, self.fd.as_raw_fd(), to_submit, min_complete, flags, ptr::null::<c_void>(), 0usize)
+            };
+            if n >= 0 {
+                return Ok(n as u32);
+            }
+            let e = Error::last_os_error();
+            if e.kind() != ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+    }
+
+    /// Gets the statx of `paths` with `flags`, returning `None` for the ones
+    /// that failed.
+    fn statx_all(&mut self, paths: &[CString], flags: c_int) -> Result<Vec<Option<Metadata>>, Error> {
+        let mut metadata = Vec::with_capacity(paths.len());
+        for chunk in paths.chunks(self.params.sq_entries as usize) {
+            // SAFETY: `struct statx` is plain integers.
+            let mut bufs: Vec<Statx> = (0..chunk.len()).map(|_| unsafe { std::mem::zeroed() }).collect();
+            let done = self.submit_and_wait(chunk, flags, &mut bufs);
+            let done = match done {
+                Ok(done) => done,
+                Err(e) => {
+                    // Requests may still be in flight, writing to the buffers.
+                    std::mem::forget(bufs);
+                    return Err(e);
+                }
+            };
+            metadata.extend(done.iter().zip(&bufs).map(|(&ok, buf)| if ok { Some(linux::metadata(buf)) } else { None }));
+        }
+        Ok(metadata)
+    }
+
+    fn submit_and_wait(&mut self, paths: &[CString], flags: c_int, bufs: &mut [Statx]) -> Result<Vec<bool>, Error> {
+        let off = &self.params.sq_off;
+        let mask = self.sq.atomic(off.ring_mask).load(Ordering::Relaxed);
+        let sq_tail = self.sq.atomic(off.tail);
+        let mut tail = sq_tail.load(Ordering::Acquire);
+        for (i, (path, buf)) in paths.iter().zip(bufs.iter_mut()).enumerate() {
+            let index = tail & mask;
+            // SAFETY: `index` is within the submission queue entries, which
+            // the kernel is done with since every earlier request completed.
+            unsafe {
+                ptr::write(self.sqes.ptr.cast::<Sqe>().add(index as usize), Sqe {
+                    opcode: IORING_OP_STATX,
+                    flags: 0,
+                    ioprio: 0,
+                    fd: AT_FDCWD,
+                    off: buf as *mut Statx as u64,
+                    addr: path.as_ptr() as u64,
+                    len: STATX_MASK,
+                    op_flags: flags as u32,
+                    user_data: i as u64,
+                    pad: [0; 3],
+                });
+                *self.sq.ptr.add(off.array as usize).cast::<u32>().add(index as usize) = index;
+            }
+            tail = tail.wrapping_add(1);
+        }
+        sq_tail.store(tail, Ordering::Release);
+
+        let submitted = self.enter(paths.len() as u32, 0, 0)?;
+        if submitted as usize != paths.len() {
+            return Err(Error::other(format!("io_uring took {} of {} requests", submitted, paths.len())));
+        }
+        let off = &self.params.cq_off;
+        let mask = self.cq.atomic(off.ring_mask).load(Ordering::Relaxed);
+        let cq_head = self.cq.atomic(off.head);
+        let mut done = vec![false; paths.len()];
+        let mut completed = 0;
+        while completed < paths.len() {
+            let mut head = cq_head.load(Ordering::Relaxed);
+            let tail = self.cq.atomic(off.tail).load(Ordering::Acquire);
+            while head != tail {
+                // SAFETY: entries between head and tail were written by the kernel.
+                let cqe = unsafe { &*self.cq.ptr.add(off.cqes as usize).cast::<Cqe>().add((head & mask) as usize) };
+                if let Some(ok) = done.get_mut(cqe.user_data as usize) {
+                    *ok = cqe.res == 0;
+                }
+                head = head.wrapping_add(1);
+                completed += 1;
+            }
+            cq_head.store(head, Ordering::Release);
+            if completed < paths.len() {
+                self.enter(0, 1, IORING_ENTER_GETEVENTS)?;
+            }
+        }
+        Ok(done)
+    }
+}
+
+/// The real filesystem, looking up the metadata of directory entries in
+/// batches through an io_uring.
+pub struct UringFileSystem {
+    linux: LinuxFileSystem,
+    /// `None` once the ring failed.
+    ring: Mutex<Option<Ring>>,
+    /// The metadata of listed entries, until it is asked for.
+    prefetched: Mutex<HashMap<PathBuf, Metadata>>,
+}
+
+impl UringFileSystem {
+
+    /// Sets up the ring, failing if io_uring isn't available.
+    pub fn new() -> Result<UringFileSystem, Error> {
+        Ok(UringFileSystem {
+            linux: LinuxFileSystem::new(),
+            ring: Mutex::new(Some(Ring::new(RING_ENTRIES)?)),
+            prefetched: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Looks up the metadata of `paths`, following symlinks like searches do.
+    fn prefetch(&self, paths: &[PathBuf]) {
+        let mut ring = self.ring.lock().unwrap();
+        let ring_ref = match ring.as_mut() {
+            Some(ring) => ring,
+            None => return,
+        };
+        let (paths, names): (Vec<_>, Vec<_>) = paths.iter()
+            .filter_map(|path| Some((path, CString::new(path.as_os_str().as_bytes()).ok()?)))
+            .unzip();
+        match ring_ref.statx_all(&names, AT_STATX_DONT_SYNC) {
+            Ok(metadata) => {
+                let mut prefetched = self.prefetched.lock().unwrap();
+                for (path, metadata) in paths.into_iter().zip(metadata) {
+                    if let Some(metadata) = metadata {
+                        prefetched.insert(path.clone(), metadata);
+                    }
+                }
+            }
+            Err(e) => {
+                trace::event(Level::Warn, || format!("io_uring failed, looking up metadata one file at a time: {}", e));
+                // Requests may still write to paths the kernel was given.
+                std::mem::forget(names);
+                *ring = None;
+            }
+        }
+    }
+}
+
+impl FileSystem for UringFileSystem {
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>, Error> {
+        let entries = self.linux.read_dir(path)?;
+        self.prefetch(&entries);
+        Ok(entries)
+    }
+
+    fn metadata(&self, path: &Path, mode: MetadataMode) -> Result<Metadata, Error> {
+        if mode == MetadataMode::Follow {
+            if let Some(metadata) = self.prefetched.lock().unwrap().remove(path) {
+                return Ok(metadata);
+            }
+        }
+        self.linux.metadata(path, mode)
+    }
+
+    fn open(&self, path: &Path) -> Result<Box<dyn Read + Send>, Error> {
+        self.linux.open(path)
+    }
+
+    fn read_at(&self, path: &Path, offset: u64, len: usize) -> Result<Vec<u8>, Error> {
+        self.linux.read_at(path, offset, len)
+    }
+
+    fn map(&self, path: &Path) -> Result<Option<mmap::Mmap>, Error> {
+        self.linux.map(path)
+    }
+
+    fn fs_type(&self, path: &Path) -> Option<String> {
+        self.linux.fs_type(path)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Finder;
+    use crate::vfs::StdFileSystem;
+
+    #[test]
+    fn batched_metadata_like_std() {
+        let uring = match UringFileSystem::new() {
+            Ok(uring) => uring,
+            // Not available here, nothing to test.
+            Err(_) => return,
+        };
+        let entries = uring.read_dir(Path::new("src")).unwrap();
+        assert!(!uring.prefetched.lock().unwrap().is_empty());
+        for entry in &entries {
+            let meta = uring.metadata(entry, MetadataMode::Follow).unwrap();
+            let expected = StdFileSystem.metadata(entry, MetadataMode::Follow).unwrap();
+            assert_eq!(expected.file_type(), meta.file_type());
+            assert_eq!(expected.id(), meta.id());
+            assert_eq!(expected.modified(), meta.modified());
+        }
+        assert!(uring.prefetched.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn searches_find_the_same_files() {
+        let uring = match UringFileSystem::new() {
+            Ok(uring) => uring,
+            Err(_) => return,
+        };
+        let mut found = Finder::new(String::from("src")).file_system(uring).find(99).unwrap();
+        let mut expected = Finder::new(String::from("src")).find(99).unwrap();
+        found.sort();
+        expected.sort();
+        assert_eq!(expected, found);
+    }
+
+}
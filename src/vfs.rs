@@ -0,0 +1,535 @@
+/*
+The filesystems a 'Finder' can search.
+
+Everything a search needs from a filesystem goes through the 'FileSystem'
+trait: listing directories, looking up metadata and opening files. Searches
+use the real filesystem ('StdFileSystem') unless told otherwise with
+'Finder::file_system()'. 'MemoryFileSystem' keeps a whole tree in memory,
+which makes searches testable without touching the disk, and other backends
+(remote trees, archives, ...) can be plugged in the same way.
+
+ */
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Error, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+use crate::filter::MetadataMode;
+use crate::fstype;
+use crate::mmap::{self, Mmap};
+
+
+/// What kind of entry a path is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    File,
+    Dir,
+    Symlink,
+    /// Anything else, e.g. a socket or a device.
+    Other,
+}
+
+/// The metadata of a file, as far as filters are concerned.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metadata {
+    file_type: FileType,
+    len: u64,
+    modified: Option<SystemTime>,
+    id: Option<(u64, u64)>,
+    permissions: Option<u32>,
+    allocated: Option<u64>,
+    owner: Option<(u32, u32)>,
+}
+
+impl Metadata {
+
+    pub fn new(file_type: FileType, len: u64, modified: Option<SystemTime>) -> Metadata {
+        Metadata { file_type, len, modified, id: None, permissions: None, allocated: None, owner: None }
+    }
+
+    /// Sets the device and inode numbers identifying this file, used to report
+    /// a file reachable through several paths only once.
+    pub fn with_id(mut self, dev: u64, ino: u64) -> Metadata {
+        self.id = Some((dev, ino));
+        self
+    }
+
+    /// Sets the Unix permission bits of this file, like `0o644`.
+    pub fn with_permissions(mut self, mode: u32) -> Metadata {
+        self.permissions = Some(mode & 0o7777);
+        self
+    }
+
+    /// Sets the space the file takes on disk, in bytes.
+    pub fn with_allocated(mut self, bytes: u64) -> Metadata {
+        self.allocated = Some(bytes);
+        self
+    }
+
+    /// Sets the user and group ids of the owner of this file.
+    pub fn with_owner(mut self, uid: u32, gid: u32) -> Metadata {
+        self.owner = Some((uid, gid));
+        self
+    }
+
+    pub fn file_type(&self) -> FileType {
+        self.file_type
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.file_type == FileType::File
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.file_type == FileType::Dir
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.file_type == FileType::Symlink
+    }
+
+    /// The size of the file in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The time of the last modification, if the filesystem has one.
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.modified
+    }
+
+    /// The device and inode numbers of the file, if the filesystem has them.
+    pub fn id(&self) -> Option<(u64, u64)> {
+        self.id
+    }
+
+    /// The Unix permission bits of the file, if the filesystem has them.
+    pub fn permissions(&self) -> Option<u32> {
+        self.permissions
+    }
+
+    /// The user and group ids of the owner, if the filesystem has them.
+    pub fn owner(&self) -> Option<(u32, u32)> {
+        self.owner
+    }
+
+    /// The space the file takes on disk in bytes, which is less than its size
+    /// if it is sparse or compressed, and more if its last block isn't full.
+    /// Filesystems that don't know it report the size.
+    pub fn allocated(&self) -> u64 {
+        self.allocated.unwrap_or(self.len)
+    }
+}
+
+impl From<&fs::Metadata> for Metadata {
+    fn from(meta: &fs::Metadata) -> Metadata {
+        let file_type = if meta.file_type().is_symlink() {
+            FileType::Symlink
+        } else if meta.is_dir() {
+            FileType::Dir
+        } else if meta.is_file() {
+            FileType::File
+        } else {
+            FileType::Other
+        };
+        let metadata = Metadata::new(file_type, meta.len(), meta.modified().ok());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            metadata.with_id(meta.dev(), meta.ino())
+                .with_permissions(meta.mode())
+                .with_allocated(meta.blocks() * 512)
+                .with_owner(meta.uid(), meta.gid())
+        }
+        #[cfg(not(unix))]
+        metadata
+    }
+}
+
+
+/// The entries of a directory, as listed by `FileSystem::read_dir_iter()`.
+pub type DirEntries<'a> = Box<dyn Iterator<Item = Result<PathBuf, Error>> + Send + 'a>;
+
+/// A filesystem a `Finder` can search.
+pub trait FileSystem: Send + Sync {
+
+    /// Returns the paths of the entries in the directory at `path`, each joined
+    /// onto `path` as given, like `fs::read_dir()` does.
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>, Error>;
+
+    /// Like `read_dir()`, returning the entries one at a time, so that huge
+    /// directories don't have to be listed in memory at once. By default, the
+    /// whole listing of `read_dir()` is returned entry by entry.
+    fn read_dir_iter(&self, path: &Path) -> Result<DirEntries<'_>, Error> {
+        Ok(Box::new(self.read_dir(path)?.into_iter().map(Ok)))
+    }
+
+    /// Returns the metadata of the file at `path`. Symlinks are followed or not
+    /// depending on `mode`.
+    fn metadata(&self, path: &Path, mode: MetadataMode) -> Result<Metadata, Error>;
+
+    /// Opens the file at `path` for reading.
+    fn open(&self, path: &Path) -> Result<Box<dyn Read + Send>, Error>;
+
+    /// Maps the contents of the file at `path` into memory, if it is worth it
+    /// and this filesystem can, see the `mmap` module. By default, files are
+    /// never mapped and are read with `open()` instead.
+    fn map(&self, _path: &Path) -> Result<Option<Mmap>, Error> {
+        Ok(None)
+    }
+
+    /// Reads up to `len` bytes of the file at `path` from `offset` on, like
+    /// its last few kilobytes. By default, the file is read with `open()`,
+    /// skipping what comes before `offset`.
+    fn read_at(&self, path: &Path, offset: u64, len: usize) -> Result<Vec<u8>, Error> {
+        let mut file = self.open(path)?;
+        io::copy(&mut (&mut file).take(offset), &mut io::sink())?;
+        let mut buf = Vec::with_capacity(len);
+        file.take(len as u64).read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Looks up what identifies the file at `path`, like its device and
+    /// inode numbers, for filesystems whose `metadata()` doesn't have it
+    /// because it costs more, like the file index of Windows, which needs
+    /// the file opened. By default, there is nothing more to look up.
+    fn id(&self, _path: &Path, _mode: MetadataMode) -> Option<(u64, u64)> {
+        None
+    }
+
+    /// The type of the filesystem the directory at `path` is on, like `nfs`,
+    /// see the `fstype` module. By default, it isn't known.
+    fn fs_type(&self, _path: &Path) -> Option<String> {
+        None
+    }
+}
+
+/// The real filesystem, through `std::fs`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdFileSystem;
+
+impl FileSystem for StdFileSystem {
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>, Error> {
+        fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect()
+    }
+
+    fn read_dir_iter(&self, path: &Path) -> Result<DirEntries<'_>, Error> {
+        Ok(Box::new(fs::read_dir(path)?.map(|entry| entry.map(|entry| entry.path()))))
+    }
+
+    fn metadata(&self, path: &Path, mode: MetadataMode) -> Result<Metadata, Error> {
+        let meta = match mode {
+            MetadataMode::Follow => fs::metadata(path)?,
+            MetadataMode::NoFollow => fs::symlink_metadata(path)?,
+        };
+        Ok(Metadata::from(&meta))
+    }
+
+    fn open(&self, path: &Path) -> Result<Box<dyn Read + Send>, Error> {
+        Ok(Box::new(fs::File::open(path)?))
+    }
+
+    /// Maps regular files of at least `mmap::MIN_MAP_SIZE` bytes, on local
+    /// filesystems and on Unix only.
+    fn map(&self, path: &Path) -> Result<Option<Mmap>, Error> {
+        if !cfg!(unix) {
+            return Ok(None);
+        }
+        let file = fs::File::open(path)?;
+        let meta = file.metadata()?;
+        if !meta.is_file() || meta.len() < mmap::MIN_MAP_SIZE || mmap::is_special(&file) {
+            return Ok(None);
+        }
+        Mmap::map(&file, meta.len() as usize).map(Some)
+    }
+
+    /// Seeks to `offset` rather than reading up to it.
+    fn read_at(&self, path: &Path, offset: u64, len: usize) -> Result<Vec<u8>, Error> {
+        let mut file = fs::File::open(path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut buf = Vec::with_capacity(len);
+        file.take(len as u64).read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// The volume serial number and file index on Windows.
+    #[cfg(windows)]
+    fn id(&self, path: &Path, mode: MetadataMode) -> Option<(u64, u64)> {
+        crate::windows::file_index(path, mode).ok()
+    }
+
+    fn fs_type(&self, path: &Path) -> Option<String> {
+        fstype::of(path).ok()
+    }
+}
+
+
+/// A tree kept entirely in memory. Paths are relative to the root of the tree;
+/// `.`, an empty path and `/` all name the root, which always exists. Entries
+/// are listed in lexicographic order.
+///
+/// ```
+/// use rustfind::Finder;
+/// use rustfind::vfs::MemoryFileSystem;
+///
+/// let mut fs = MemoryFileSystem::new();
+/// fs.add_file("src/lib.rs", "pub mod a;").add_file("src/a.rs", vec![0; 100]);
+/// let found = Finder::new(String::from("src"))
+///     .file_system(fs)
+///     .size_greater_than_or_eq(50)
+///     .find(1)
+///     .unwrap();
+/// assert_eq!(vec!["src/a.rs"], found);
+/// ```
+#[derive(Debug, Clone)]
+pub struct MemoryFileSystem {
+    nodes: BTreeMap<PathBuf, Node>,
+    next_id: u64,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    kind: NodeKind,
+    modified: SystemTime,
+    id: u64,
+}
+
+#[derive(Debug, Clone)]
+enum NodeKind {
+    File(Vec<u8>),
+    Dir,
+    Symlink(PathBuf),
+}
+
+/// How many symlinks are followed when resolving a path before giving up, as
+/// in Linux.
+const MAX_SYMLINKS: usize = 40;
+
+impl MemoryFileSystem {
+
+    /// Creates a tree with nothing but its root directory.
+    pub fn new() -> MemoryFileSystem {
+        let mut fs = MemoryFileSystem { nodes: BTreeMap::new(), next_id: 0 };
+        fs.insert(PathBuf::new(), NodeKind::Dir);
+        fs
+    }
+
+    /// Adds a directory at `path`, along with any missing parent directories.
+    pub fn add_dir(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        let path = normalize(path.as_ref());
+        for dir in path.ancestors() {
+            if !self.nodes.contains_key(dir) {
+                self.insert(dir.to_path_buf(), NodeKind::Dir);
+            }
+        }
+        self
+    }
+
+    /// Adds a file at `path` with the given contents, replacing any file that
+    /// was there. Missing parent directories are added too.
+    pub fn add_file(&mut self, path: impl AsRef<Path>, contents: impl Into<Vec<u8>>) -> &mut Self {
+        self.add_entry(path.as_ref(), NodeKind::File(contents.into()))
+    }
+
+    /// Adds a symlink at `path` pointing to `target`. A relative `target` is
+    /// relative to the directory of the symlink, as on disk.
+    pub fn add_symlink(&mut self, path: impl AsRef<Path>, target: impl AsRef<Path>) -> &mut Self {
+        self.add_entry(path.as_ref(), NodeKind::Symlink(target.as_ref().to_path_buf()))
+    }
+
+    /// Sets the modification time of the entry at `path`. Entries are created
+    /// with the current time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no entry at `path`.
+    pub fn set_modified(&mut self, path: impl AsRef<Path>, modified: SystemTime) -> &mut Self {
+        let path = path.as_ref();
+        match self.nodes.get_mut(&normalize(path)) {
+            Some(node) => node.modified = modified,
+            None => panic!("No file {} in the memory filesystem", path.display()),
+        }
+        self
+    }
+
+    fn add_entry(&mut self, path: &Path, kind: NodeKind) -> &mut Self {
+        let path = normalize(path);
+        if let Some(parent) = path.parent() {
+            self.add_dir(parent);
+        }
+        self.insert(path, kind);
+        self
+    }
+
+    fn insert(&mut self, path: PathBuf, kind: NodeKind) {
+        self.next_id += 1;
+        self.nodes.insert(path, Node { kind, modified: SystemTime::now(), id: self.next_id });
+    }
+
+    /// Resolves `path` to the key of its node, following symlinks on the way
+    /// and, if `follow` is set, a symlink at the very end too.
+    fn resolve(&self, path: &Path, follow: bool, hops: &mut usize) -> Result<PathBuf, Error> {
+        let components: Vec<Component> = path.components().collect();
+        let mut resolved = PathBuf::new();
+        for (i, component) in components.iter().enumerate() {
+            match component {
+                Component::Normal(name) => resolved.push(name),
+                Component::ParentDir => {
+                    resolved.pop();
+                    continue;
+                }
+                _ => continue,
+            }
+            let last = i == components.len() - 1;
+            match self.nodes.get(&resolved).map(|node| &node.kind) {
+                None => return Err(not_found(path)),
+                Some(NodeKind::Symlink(target)) if follow || !last => {
+                    *hops += 1;
+                    if *hops > MAX_SYMLINKS {
+                        return Err(Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("Too many levels of symbolic links: {}", path.display())));
+                    }
+                    resolved.pop();
+                    resolved = self.resolve(&resolved.join(target), true, hops)?;
+                }
+                Some(NodeKind::File(_)) | Some(NodeKind::Symlink(_)) if !last => {
+                    return Err(Error::new(
+                        io::ErrorKind::NotADirectory,
+                        format!("Not a directory: {}", path.display())));
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(resolved)
+    }
+
+    fn node(&self, path: &Path, follow: bool) -> Result<&Node, Error> {
+        let resolved = self.resolve(path, follow, &mut 0)?;
+        self.nodes.get(&resolved).ok_or_else(|| not_found(path))
+    }
+}
+
+impl Default for MemoryFileSystem {
+    fn default() -> Self {
+        MemoryFileSystem::new()
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>, Error> {
+        let dir = self.resolve(path, true, &mut 0)?;
+        match self.nodes.get(&dir).map(|node| &node.kind) {
+            Some(NodeKind::Dir) => Ok(self.nodes.keys()
+                .filter(|key| key.parent() == Some(dir.as_path()))
+                .filter_map(|key| key.file_name())
+                .map(|name| path.join(name))
+                .collect()),
+            Some(_) => Err(Error::new(
+                io::ErrorKind::NotADirectory,
+                format!("Not a directory: {}", path.display()))),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn metadata(&self, path: &Path, mode: MetadataMode) -> Result<Metadata, Error> {
+        let node = self.node(path, mode == MetadataMode::Follow)?;
+        let (file_type, len) = match &node.kind {
+            NodeKind::File(contents) => (FileType::File, contents.len() as u64),
+            NodeKind::Dir => (FileType::Dir, 0),
+            NodeKind::Symlink(target) => (FileType::Symlink, target.as_os_str().len() as u64),
+        };
+        Ok(Metadata::new(file_type, len, Some(node.modified)).with_id(0, node.id))
+    }
+
+    fn open(&self, path: &Path) -> Result<Box<dyn Read + Send>, Error> {
+        match &self.node(path, true)?.kind {
+            NodeKind::File(contents) => Ok(Box::new(io::Cursor::new(contents.clone()))),
+            _ => Err(Error::new(
+                io::ErrorKind::IsADirectory,
+                format!("Is a directory: {}", path.display()))),
+        }
+    }
+}
+
+/// Turns `path` into the key of its node: relative to the root, without any
+/// `.` or `..` components.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => normalized.push(name),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => {}
+        }
+    }
+    normalized
+}
+
+fn not_found(path: &Path) -> Error {
+    Error::new(
+        io::ErrorKind::NotFound,
+        format!("No such file or directory: {}", path.display()))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tree() -> MemoryFileSystem {
+        let mut fs = MemoryFileSystem::new();
+        fs.add_file("src/lib.rs", "pub mod a;")
+            .add_file("src/a/mod.rs", vec![0; 100])
+            .add_symlink("lib", "src/lib.rs")
+            .add_symlink("code", "./src")
+            .add_symlink("loop", "loop")
+            .add_dir("empty");
+        fs
+    }
+
+    #[test]
+    fn read_dir_keeps_the_given_path() {
+        let fs = tree();
+        assert_eq!(vec![PathBuf::from("./src/a"), PathBuf::from("./src/lib.rs")],
+                   fs.read_dir(Path::new("./src")).unwrap());
+        assert_eq!(5, fs.read_dir(Path::new(".")).unwrap().len());
+        assert_eq!(vec![PathBuf::from("code/a/mod.rs")], fs.read_dir(Path::new("code/a")).unwrap());
+        assert!(fs.read_dir(Path::new("empty")).unwrap().is_empty());
+        assert!(fs.read_dir(Path::new("src/lib.rs")).is_err());
+        assert!(fs.read_dir(Path::new("missing")).is_err());
+    }
+
+    #[test]
+    fn metadata_follows_symlinks() {
+        let fs = tree();
+        let file = fs.metadata(Path::new("lib"), MetadataMode::Follow).unwrap();
+        assert!(file.is_file());
+        assert_eq!(10, file.len());
+        assert_eq!(fs.metadata(Path::new("src/lib.rs"), MetadataMode::Follow).unwrap().id(), file.id());
+        let link = fs.metadata(Path::new("lib"), MetadataMode::NoFollow).unwrap();
+        assert!(link.is_symlink());
+        assert_eq!(100, fs.metadata(Path::new("code/a/mod.rs"), MetadataMode::NoFollow).unwrap().len());
+        assert!(fs.metadata(Path::new("loop"), MetadataMode::Follow).is_err());
+        assert!(fs.metadata(Path::new("loop"), MetadataMode::NoFollow).is_ok());
+    }
+
+    #[test]
+    fn open_reads_contents() {
+        let fs = tree();
+        let mut contents = String::new();
+        fs.open(Path::new("lib")).unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!("pub mod a;", contents);
+        assert!(fs.open(Path::new("src")).is_err());
+    }
+
+}
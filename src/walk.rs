@@ -0,0 +1,1255 @@
+/*
+Walking the trees of a search.
+
+A 'Finder' walks its roots breadth first by default: the entries of every
+directory are queued, and everything at one depth is looked at before going
+deeper. The queue then holds a whole level of the tree, which for directories
+with millions of entries is a lot of memory. 'Traversal::DepthFirst' keeps one
+open directory per level instead, reading entries as it goes, so memory only
+grows with the depth of the tree. Alternatively, a cap on the queue (see
+'Finder::max_queued()') keeps the breadth first order as long as the entries
+fit, and continues depth first below the directories that don't.
+
+Open directories hold file descriptors. A depth first walk keeps at most
+'Finder::max_open_dirs()' of them open, reading deeper directories entirely
+before going on, and when the process runs out of descriptors anyway (EMFILE)
+it reads its outermost open directories entirely and closes them, instead of
+failing the search.
+
+Directories with more than 'Finder::max_entries_per_dir()' entries, like
+maildirs or caches of millions of files, are skipped rather than walked, and
+recorded in a 'SkippedDirs' to report them after the search.
+
+A directory that can't be read, e.g. for lack of permissions, fails the
+search, except for 'Finder::find_outcome()': it goes on without it, and
+returns what it skipped and why with the matches in a 'FindOutcome'. With
+'Finder::on_error()', every search goes on, handing each path that can't be
+looked up or read to a callback as it is found.
+
+Unlike filters, which only decide what is reported, 'Finder::prune_if()'
+keeps the walk out of directories altogether, like find's '-prune'. A pruned
+directory is still reported itself if directories are.
+'Finder::prune_if_contains()' prunes the directories holding a marker file,
+and for a 'CACHEDIR.TAG' checks it is one per the Cache Directory Tagging
+spec (https://bford.info/cachedir/), i.e. that it starts with its signature,
+so that a file that happens to have that name doesn't hide a directory.
+
+Symlinks to directories are followed, so a link to one of its own parent
+directories, like 'a/up -> ..', would have the walk go round forever, and
+two such links would double the directories to walk at every turn. Every
+walk keeps the device and inode numbers of the directories on the path to
+the entry it looks at, and doesn't walk into a directory that is one of
+them, recording it as skipped with 'SkipReason::Loop'.
+
+'Finder::for_each_event()' walks depth first, handing out a 'WalkEvent' as it
+enters and leaves every directory besides the matches, like walkdir does with
+and without 'contents_first', for tree renderers and per-directory totals.
+
+How entries are read and looked up can be tuned to the storage searched,
+see 'TraversalTuning'. Every entry found is looked up, for its type and
+metadata, which on network filesystems waits for a round trip each time. A
+breadth first walk with 'stat_threads' above one looks up the entries of a
+directory on that many threads at once, a batch of 'readdir_batch' entries
+at a time, and queues them with their metadata, which takes more memory
+per queued entry. Spinning disks, where every lookup may seek, are best
+walked one entry at a time, as by default.
+
+Which one suits a tree isn't always known beforehand, a search may even
+cross from a local disk into a network mount. 'TraversalTuning::auto()'
+measures how long the lookups of every batch take: it starts with one
+thread, doubles them while a lookup takes more than half a millisecond on
+average, waiting on the storage, and halves them while it takes less than
+50 microseconds, answered from caches.
+
+A search given a 'Cancel' with 'Finder::cancel_with()' stops once it is
+cancelled from another thread, checking between entries, and fails with
+'io::ErrorKind::Interrupted' rather than returning the matches so far.
+
+ */
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::io::{self, Error, Read};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::{file_id, FileId, Finder};
+use crate::cache::{CachedEntry, Lookup, ResultCache};
+use crate::checkpoint::{Checkpoint, Pending};
+use crate::filter::{FileInfo, MetadataMode};
+use crate::ignore::IgnoreStack;
+use crate::snapshot;
+use crate::timings::Phase;
+use crate::trace::{self, Level};
+use crate::vfs::{DirEntries, FileSystem, Metadata};
+
+
+/// The order in which a `Finder` visits the entries of its roots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Traversal {
+    /// One depth at a time, queuing the entries of every directory.
+    #[default]
+    BreadthFirst,
+    /// Every directory entirely before its next sibling, reading entries
+    /// from open directories as they are needed.
+    DepthFirst,
+}
+
+/// How a walk reads and looks up entries, see `Finder::tuning()` and the
+/// module documentation. The defaults suit local disks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraversalTuning {
+    /// How many entries of a directory a breadth first walk reads before
+    /// looking them up. 0 is taken as 1.
+    pub readdir_batch: usize,
+    /// How many entries the queue of a breadth first walk has room for from
+    /// the start, rather than growing into as it fills.
+    pub queue_capacity: usize,
+    /// How many threads look up a batch of entries at once. With 1, entries
+    /// are looked up one at a time as they are walked.
+    pub stat_threads: usize,
+    /// Whether `stat_threads` is only the most threads to look up entries
+    /// on, starting with one and scaling with how long lookups take.
+    pub adaptive: bool,
+}
+
+impl TraversalTuning {
+
+    /// Looks up entries on up to `MAX_AUTO_THREADS` threads, as many as the
+    /// storage searched keeps busy, see the module documentation.
+    pub fn auto() -> TraversalTuning {
+        TraversalTuning { stat_threads: MAX_AUTO_THREADS, adaptive: true, ..TraversalTuning::default() }
+    }
+}
+
+impl Default for TraversalTuning {
+    fn default() -> TraversalTuning {
+        TraversalTuning { readdir_batch: 256, queue_capacity: 1024, stat_threads: 1, adaptive: false }
+    }
+}
+
+/// The most threads `TraversalTuning::auto()` looks up entries on.
+pub const MAX_AUTO_THREADS: usize = 32;
+
+/// The directories a search skipped because they had too many entries, see
+/// `Finder::max_entries_per_dir()`. Like `FilterStats`, it is a cheap handle:
+/// clone it, hand one copy to the `Finder` and read the other after the
+/// search completes.
+#[derive(Debug, Clone, Default)]
+pub struct SkippedDirs {
+    dirs: Arc<Mutex<Vec<PathBuf>>>,
+}
+
+impl SkippedDirs {
+
+    pub fn new() -> SkippedDirs {
+        SkippedDirs::default()
+    }
+
+    fn record(&self, dir: &Path) {
+        self.dirs.lock().unwrap().push(dir.to_path_buf());
+    }
+
+    /// The directories skipped so far, in the order they were found.
+    pub fn report(&self) -> Vec<PathBuf> {
+        self.dirs.lock().unwrap().clone()
+    }
+}
+
+/// Stops searches from another thread, see `Finder::cancel_with()`. Like
+/// `SkippedDirs`, it is a cheap handle: clone it, hand one copy to the
+/// `Finder` and cancel the search with the other.
+#[derive(Debug, Clone, Default)]
+pub struct Cancel {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Cancel {
+
+    pub fn new() -> Cancel {
+        Cancel::default()
+    }
+
+    /// Stops the searches of this handle, which then fail with
+    /// `io::ErrorKind::Interrupted`.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// What a search found, and what it left out, see `Finder::find_outcome()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FindOutcome {
+    pub matches: Vec<String>,
+    /// The entries left out, in the order they were found.
+    pub skipped: Vec<SkippedEntry>,
+    /// Whether files went unread because the scan budget ran out, so that
+    /// content filters may have missed matches, see `Finder::scan_budget()`.
+    pub truncated: bool,
+}
+
+/// An entry a search left out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedEntry {
+    pub path: PathBuf,
+    pub reason: SkipReason,
+}
+
+/// Why a search left out an entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SkipReason {
+    /// It couldn't be looked up or read, with the error why.
+    Inaccessible { kind: io::ErrorKind, message: String },
+    /// A directory with more entries than the given maximum, see
+    /// `Finder::max_entries_per_dir()`.
+    TooManyEntries(usize),
+    /// A directory that is also one of the directories it is in, reached
+    /// again through a symlink or a bind mount.
+    Loop,
+}
+
+/// What is called with the paths that can't be looked up or read, see
+/// `Finder::on_error()`.
+pub(crate) type OnError = Arc<dyn Fn(&Path, &Error) + Send + Sync>;
+
+/// The file tagging a cache directory, see `Finder::prune_if_contains()`.
+pub const CACHEDIR_TAG: &str = "CACHEDIR.TAG";
+
+/// How long a lookup takes on average beyond which adaptive walks look up
+/// more entries at once, like on network filesystems.
+const SLOW_LOOKUP: Duration = Duration::from_micros(500);
+
+/// How long a lookup takes on average below which adaptive walks look up
+/// fewer entries at once, since local disks and caches answer that fast.
+const FAST_LOOKUP: Duration = Duration::from_micros(50);
+
+/// What a `CACHEDIR.TAG` starts with.
+const CACHEDIR_SIGNATURE: &[u8] = b"Signature: 8a477f597d28d172789f06886806bc55";
+
+/// Tells the directories not to walk into, see `Finder::prune_if()`.
+pub(crate) type Prune = Arc<dyn Fn(&Path) -> bool + Send + Sync>;
+
+/// What a depth first walk goes through, see `Finder::for_each_event()`.
+/// Every `EnterDir` is followed by the events of the entries of the
+/// directory, then by its `LeaveDir`.
+#[derive(Clone, Copy)]
+pub enum WalkEvent<'a> {
+    /// A directory the walk goes into, before its entries.
+    EnterDir(&'a Path),
+    /// A directory whose entries have all been walked.
+    LeaveDir(&'a Path),
+    /// A match: a file or, with `Finder::include_dirs()`, a directory, which
+    /// comes before its `EnterDir`.
+    File(&'a FileInfo),
+}
+
+/// What is called with every match instead of collecting them, see
+/// `Finder::for_each()`, and with the directories walked into and out of.
+pub(crate) type Visitor<'a> = &'a mut dyn FnMut(WalkEvent) -> ControlFlow<()>;
+
+/// The ignore files applying to an entry, see the `ignore` module.
+pub(crate) type Ignores = Option<Arc<IgnoreStack>>;
+
+/// The device and inode numbers of the directories on the path to an entry,
+/// innermost first, to not walk into any of them again.
+pub(crate) type Ancestors = Option<Arc<Ancestor>>;
+
+pub(crate) struct Ancestor {
+    id: (u64, u64),
+    parent: Ancestors,
+}
+
+/// Returns true if the directory identified by `id` is in `ancestors`.
+fn is_ancestor(mut ancestors: &Ancestors, id: (u64, u64)) -> bool {
+    while let Some(ancestor) = ancestors {
+        if ancestor.id == id {
+            return true;
+        }
+        ancestors = &ancestor.parent;
+    }
+    false
+}
+
+/// An entry queued by a breadth first walk.
+struct Queued {
+    path: PathBuf,
+    depth: u32,
+    ignores: Ignores,
+    ancestors: Ancestors,
+    /// The index of the root the entry is below, for checkpoints.
+    root: usize,
+    known: Known,
+    /// The metadata of the entry, if it was looked up along with its
+    /// siblings, see `TraversalTuning::stat_threads`.
+    meta: Option<Result<Metadata, Error>>,
+}
+
+/// What the result cache has of a queued entry, see the `cache` module.
+enum Known {
+    /// Nothing yet, the entry is looked up.
+    Nothing,
+    /// The entry, since its directory didn't change.
+    Cached(CachedEntry),
+    /// Nothing that can be used for it and below it, since the ignore files
+    /// applying to them changed.
+    Stale,
+}
+
+/// A directory being read by a depth first walk.
+struct Frame<'a> {
+    dir: PathBuf,
+    entries: DirEntries<'a>,
+    /// Whether `entries` are still read from the open directory, rather than
+    /// from memory.
+    open: bool,
+    /// The depth of the entries.
+    depth: u32,
+    ignores: Ignores,
+    /// The directories the entries are in, `dir` first.
+    ancestors: Ancestors,
+}
+
+impl Frame<'_> {
+
+    /// Reads the remaining entries into memory, closing the directory.
+    fn close(&mut self) {
+        if self.open {
+            let rest: Vec<_> = self.entries.by_ref().collect();
+            self.entries = Box::new(rest.into_iter());
+            self.open = false;
+        }
+    }
+}
+
+/// The state of one search of a `Finder`.
+pub(crate) struct Walk<'a> {
+    finder: &'a Finder,
+    max_depth: u32,
+    print: bool,
+    seen: HashSet<FileId>,
+    result: Vec<String>,
+    /// How many matches to stop at, for pages.
+    limit: Option<usize>,
+    /// The entries left out, if the walk goes on without the ones it can't
+    /// read rather than failing.
+    skipped: Option<Vec<SkippedEntry>>,
+    visitor: Option<Visitor<'a>>,
+    /// Whether the visitor asked to stop.
+    stopped: bool,
+    /// The roots being walked, which matches are relative to.
+    roots: Vec<Arc<Path>>,
+    cache: Option<ResultCache>,
+    /// How many threads look up entries, if they adapt to how slow lookups
+    /// are, see `TraversalTuning::adaptive`.
+    threads: usize,
+}
+
+impl<'a> Walk<'a> {
+
+    pub(crate) fn new(finder: &'a Finder, max_depth: u32, print: bool) -> Walk<'a> {
+        Walk { finder, max_depth, print, seen: HashSet::new(), result: Vec::new(), limit: None, skipped: None, visitor: None, stopped: false, roots: Vec::new(), cache: None, threads: 1 }
+    }
+
+    /// Hands every match to `visitor` rather than collecting them, stopping
+    /// once it breaks. A depth first walk also hands it the directories it
+    /// enters and leaves.
+    pub(crate) fn visitor(mut self, visitor: Visitor<'a>) -> Walk<'a> {
+        self.visitor = Some(visitor);
+        self
+    }
+
+    /// Goes on without the entries that can't be read, recording them.
+    pub(crate) fn keep_going(mut self) -> Walk<'a> {
+        self.skipped = Some(Vec::new());
+        self
+    }
+
+    /// Walks the given roots, each with the ignore files applying to it, and
+    /// returns the matches with the entries skipped.
+    pub(crate) fn run(mut self, roots: Vec<(PathBuf, Ignores)>) -> Result<FindOutcome, Error> {
+        let finder = self.finder;
+        self.roots = roots.iter().map(|(root, _)| Arc::from(root.as_path())).collect();
+        match finder.traversal {
+            Traversal::BreadthFirst => {
+                let queue = match &finder.resume {
+                    Some(checkpoint) => self.restore(checkpoint, &roots)?,
+                    None => roots.into_iter()
+                        .enumerate()
+                        .map(|(root, (path, ignores))| Queued { path, depth: 0, ignores, ancestors: None, root, known: Known::Nothing, meta: None })
+                        .collect(),
+                };
+                // Checkpoints and capped queues leave parts of the walk for later.
+                if finder.resume.is_none() && finder.checkpoint.is_none() && finder.max_queued.is_none() {
+                    self.cache = finder.result_cache.as_ref().and_then(|dir| ResultCache::open(dir, finder, self.max_depth));
+                }
+                let rest = self.breadth_first(queue)?;
+                if let Some(cache) = self.cache.take().filter(|_| !self.stopped) {
+                    if let Err(e) = cache.save() {
+                        trace::event(Level::Warn, || format!("cannot save the result cache: {}", e));
+                    }
+                }
+                // A walk stopped early can still be resumed.
+                if let Some((file, _)) = finder.checkpoint.as_ref().filter(|_| rest.is_empty()) {
+                    match fs::remove_file(file) {
+                        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                        _ => {}
+                    }
+                }
+            }
+            Traversal::DepthFirst => {
+                for (index, (root, ignores)) in roots.into_iter().enumerate() {
+                    if self.stop_requested() {
+                        break;
+                    }
+                    self.depth_first(root, 0, index, ignores, None)?;
+                }
+            }
+        }
+        self.check_cancelled()?;
+        Ok(FindOutcome { matches: self.result, skipped: self.skipped.unwrap_or_default(), truncated: false })
+    }
+
+    /// Walks the roots from `cursor`, if any, until `limit` matches are found,
+    /// and returns them with where the walk stopped, unless it is complete.
+    pub(crate) fn page(mut self, roots: Vec<(PathBuf, Ignores)>, cursor: Option<&Checkpoint>, limit: usize) -> Result<(Vec<String>, Option<Checkpoint>), Error> {
+        let finder = self.finder;
+        if finder.traversal != Traversal::BreadthFirst || finder.max_queued.is_some() {
+            return Err(Error::new(io::ErrorKind::InvalidInput, "Pages need a breadth first traversal without a cap on the queue."));
+        }
+        self.roots = roots.iter().map(|(root, _)| Arc::from(root.as_path())).collect();
+        let queue = match cursor {
+            Some(cursor) => self.restore(cursor, &roots)?,
+            None => roots.into_iter()
+                .enumerate()
+                .map(|(root, (path, ignores))| Queued { path, depth: 0, ignores, ancestors: None, root, known: Known::Nothing, meta: None })
+                .collect(),
+        };
+        self.limit = Some(limit);
+        let queue = self.breadth_first(queue)?;
+        self.check_cancelled()?;
+        let next = if queue.is_empty() {
+            None
+        } else {
+            Some(self.checkpoint(&queue, Vec::new()))
+        };
+        Ok((self.result, next))
+    }
+
+    /// Walks the entries of `queue` and the ones below them, returning those
+    /// left once the limit of matches is reached.
+    fn breadth_first(&mut self, mut queue: VecDeque<Queued>) -> Result<VecDeque<Queued>, Error> {
+        let cap = self.finder.max_queued.unwrap_or(usize::MAX);
+        let tuning = self.finder.tuning;
+        let batch_size = tuning.readdir_batch.max(1);
+        queue.reserve(tuning.queue_capacity.min(cap).saturating_sub(queue.len()));
+        let mut saved = Instant::now();
+        while !queue.is_empty() {
+            if self.stop_requested() || self.limit.is_some_and(|limit| self.result.len() >= limit) {
+                break;
+            }
+            if let Some((file, interval)) = &self.finder.checkpoint {
+                if saved.elapsed() >= *interval {
+                    self.save(file, &queue)?;
+                    saved = Instant::now();
+                }
+            }
+            let Queued { path, depth, ignores, ancestors, root, known, meta } = queue.pop_front().unwrap();
+            let walk = match &known {
+                Known::Cached(entry) => self.replay(&path, depth, root, entry).then_some(ancestors),
+                _ => self.visit(&path, depth, root, &ignores, &ancestors, meta),
+            };
+            let ancestors = match walk {
+                Some(ancestors) => ancestors,
+                None => continue,
+            };
+            let finder = self.finder;
+            let trusted = !matches!(known, Known::Stale);
+            let stale = match self.cache.as_mut().map(|cache| cache.lookup(&path, finder.file_system.as_ref(), finder.ignore_files, trusted)) {
+                Some(Lookup::Hit(entries)) => {
+                    let ignores = if finder.ignore_files { finder.read_ignore_file(&path, ignores) } else { ignores };
+                    for entry in entries {
+                        queue.push_back(Queued { path: path.join(&entry.name), depth: depth + 1, ignores: ignores.clone(), ancestors: ancestors.clone(), root, known: Known::Cached(entry), meta: None });
+                    }
+                    continue;
+                }
+                Some(Lookup::Miss { stale }) => stale,
+                None => false,
+            };
+            let (entries, ignores) = match self.read_dir(&path, ignores) {
+                Ok(read) => read,
+                Err(e) => {
+                    self.skip_or_fail(&path, e)?;
+                    continue;
+                }
+            };
+            let mut entries = entries;
+            loop {
+                let mut batch = Vec::new();
+                let mut failed = None;
+                for entry in entries.by_ref().take(batch_size) {
+                    match entry {
+                        Ok(entry) => batch.push(entry),
+                        Err(e) => {
+                            failed = Some(e);
+                            break;
+                        }
+                    }
+                }
+                let complete = failed.is_none() && batch.len() == batch_size;
+                let looked_up = self.look_up_all(&path, &batch);
+                for (entry, meta) in batch.into_iter().zip(looked_up) {
+                    if queue.len() < cap {
+                        let known = if stale { Known::Stale } else { Known::Nothing };
+                        queue.push_back(Queued { path: entry, depth: depth + 1, ignores: ignores.clone(), ancestors: ancestors.clone(), root, known, meta });
+                    } else {
+                        self.depth_first(entry, depth + 1, root, ignores.clone(), ancestors.clone())?;
+                    }
+                }
+                if let Some(e) = failed {
+                    self.skip_or_fail(&path, e)?;
+                }
+                if !complete {
+                    break;
+                }
+            }
+        }
+        Ok(queue)
+    }
+
+    /// Saves the state of a breadth first walk with `queue` left to `file`.
+    fn save(&self, file: &Path, queue: &VecDeque<Queued>) -> Result<(), Error> {
+        trace::event(Level::Debug, || format!("saving a checkpoint with {} entries queued", queue.len()));
+        self.checkpoint(queue, self.result.clone()).save(file)
+    }
+
+    /// The state of a breadth first walk with `queue` left and `results`
+    /// found.
+    fn checkpoint(&self, queue: &VecDeque<Queued>, results: Vec<String>) -> Checkpoint {
+        Checkpoint {
+            roots: self.finder.roots.clone(),
+            depth: self.max_depth,
+            pending: queue.iter().map(|entry| Pending { path: entry.path.clone(), depth: entry.depth, root: entry.root }).collect(),
+            results,
+        }
+    }
+
+    /// Takes up the walk saved in `checkpoint`, returning its queue.
+    fn restore(&mut self, checkpoint: &Checkpoint, roots: &[(PathBuf, Ignores)]) -> Result<VecDeque<Queued>, Error> {
+        let finder = self.finder;
+        if checkpoint.roots != finder.roots || checkpoint.depth != self.max_depth {
+            return Err(Error::new(io::ErrorKind::InvalidInput, format!(
+                "The checkpoint is of another search, of {} up to depth {}.", checkpoint.roots.join(", "), checkpoint.depth)));
+        }
+        trace::event(Level::Info, || format!("resuming with {} entries queued", checkpoint.pending.len()));
+        for result in &checkpoint.results {
+            if let Some(id) = file_id(&finder.file_info(PathBuf::from(result))) {
+                self.seen.insert(id);
+            }
+        }
+        self.result = checkpoint.results.clone();
+        // Siblings share the ignore files and ancestors of their directory.
+        let mut below: HashMap<(usize, PathBuf), (Ignores, Ancestors)> = HashMap::new();
+        checkpoint.pending.iter().map(|entry| {
+            let (root, root_ignores) = roots.get(entry.root).ok_or_else(|| Error::new(
+                io::ErrorKind::InvalidData, "Invalid checkpoint: pending entry of an unknown root."))?;
+            let (ignores, ancestors) = match entry.path.parent() {
+                Some(dir) if entry.depth > 0 => below.entry((entry.root, dir.to_path_buf()))
+                    .or_insert_with(|| (self.ignores_below(root, root_ignores.clone(), dir), self.ancestors_below(root, dir)))
+                    .clone(),
+                _ => (root_ignores.clone(), None),
+            };
+            Ok(Queued { path: entry.path.clone(), depth: entry.depth, ignores, ancestors, root: entry.root, known: Known::Nothing, meta: None })
+        }).collect()
+    }
+
+    /// The ignore files applying to the entries of `dir`, a directory below
+    /// `root`: the ones of `root`, of `dir` and of every directory between.
+    fn ignores_below(&self, root: &Path, mut ignores: Ignores, dir: &Path) -> Ignores {
+        if !self.finder.ignore_files {
+            return ignores;
+        }
+        let relative = match dir.strip_prefix(root) {
+            Ok(relative) => relative,
+            Err(_) => return ignores,
+        };
+        let mut current = root.to_path_buf();
+        ignores = self.finder.read_ignore_file(&current, ignores);
+        for component in relative.components() {
+            current.push(component);
+            ignores = self.finder.read_ignore_file(&current, ignores);
+        }
+        ignores
+    }
+
+    /// The directories from `root` to `dir`, a directory below it, as
+    /// ancestors of the entries of `dir`.
+    fn ancestors_below(&self, root: &Path, dir: &Path) -> Ancestors {
+        let relative = dir.strip_prefix(root).ok()?;
+        let mut current = root.to_path_buf();
+        let mut ancestors = self.ancestors(&current, None);
+        for component in relative.components() {
+            current.push(component);
+            ancestors = self.ancestors(&current, ancestors);
+        }
+        ancestors
+    }
+
+    /// `ancestors` with the directory at `path` added, if it can be told
+    /// apart from others.
+    fn ancestors(&self, path: &Path, ancestors: Ancestors) -> Ancestors {
+        let file_system = self.finder.file_system.as_ref();
+        let id = file_system.metadata(path, MetadataMode::Follow).ok()
+            .and_then(|meta| meta.id().or_else(|| file_system.id(path, MetadataMode::Follow)));
+        match id {
+            Some(id) => Some(Arc::new(Ancestor { id, parent: ancestors })),
+            None => ancestors,
+        }
+    }
+
+    fn depth_first(&mut self, path: PathBuf, depth: u32, root: usize, ignores: Ignores, ancestors: Ancestors) -> Result<(), Error> {
+        let ancestors = match self.visit(&path, depth, root, &ignores, &ancestors, None) {
+            Some(ancestors) => ancestors,
+            None => return Ok(()),
+        };
+        let mut stack = Vec::new();
+        match self.open_dir(&path, depth + 1, ignores, ancestors, &mut stack) {
+            Ok(frame) => stack.push(frame),
+            Err(e) => return self.skip_or_fail(&path, e),
+        }
+        self.event(WalkEvent::EnterDir(&path));
+        while let Some(frame) = stack.last_mut() {
+            if self.stop_requested() {
+                break;
+            }
+            let entry = match frame.entries.next() {
+                Some(Ok(entry)) => entry,
+                Some(Err(e)) => {
+                    let dir = stack.pop().unwrap().dir;
+                    self.skip_or_fail(&dir, e)?;
+                    self.event(WalkEvent::LeaveDir(&dir));
+                    continue;
+                }
+                None => {
+                    let dir = stack.pop().unwrap().dir;
+                    self.event(WalkEvent::LeaveDir(&dir));
+                    continue;
+                }
+            };
+            let (depth, ignores, ancestors) = (frame.depth, frame.ignores.clone(), frame.ancestors.clone());
+            if let Some(ancestors) = self.visit(&entry, depth, root, &ignores, &ancestors, None).filter(|_| !self.stopped) {
+                match self.open_dir(&entry, depth + 1, ignores, ancestors, &mut stack) {
+                    Ok(frame) => {
+                        stack.push(frame);
+                        self.event(WalkEvent::EnterDir(&entry));
+                    }
+                    Err(e) => self.skip_or_fail(&entry, e)?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts reading the directory at `path` for a depth first walk, whose
+    /// open directories are on `stack`. Keeps the directory open only if
+    /// fewer than `Finder::max_open_dirs()` are, and closes the outermost ones
+    /// if there are no file descriptors left to open it.
+    fn open_dir(&mut self, path: &Path, depth: u32, ignores: Ignores, ancestors: Ancestors, stack: &mut [Frame<'a>]) -> Result<Frame<'a>, Error> {
+        loop {
+            match self.read_dir(path, ignores.clone()) {
+                Ok((entries, ignores)) => {
+                    let mut frame = Frame { dir: path.to_path_buf(), entries, open: true, depth, ignores, ancestors };
+                    if stack.iter().filter(|frame| frame.open).count() >= self.finder.max_open_dirs {
+                        frame.close();
+                    }
+                    return Ok(frame);
+                }
+                Err(e) if too_many_open_files(&e) => match stack.iter_mut().find(|frame| frame.open) {
+                    Some(outermost) => {
+                        trace::event(Level::Warn, || format!("out of file descriptors reading {}, closing a directory", path.display()));
+                        outermost.close();
+                    }
+                    None => return Err(e),
+                },
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Looks up the metadata of `paths`, the entries of `dir`, on the threads
+    /// of `TraversalTuning::stat_threads`, if there are several or they
+    /// adapt. Otherwise, they are looked up as they are visited.
+    fn look_up_all(&mut self, dir: &Path, paths: &[PathBuf]) -> Vec<Option<Result<Metadata, Error>>> {
+        let finder = self.finder;
+        let tuning = finder.tuning;
+        let threads = if tuning.adaptive { self.threads } else { tuning.stat_threads };
+        if !tuning.adaptive && threads <= 1 || paths.len() < 2 {
+            return paths.iter().map(|_| None).collect();
+        }
+        let (file_system, retries) = (finder.file_system.as_ref(), finder.retries);
+        let look_up = move |path: &PathBuf| Some(retries.run(|| file_system.metadata(path, MetadataMode::Follow)));
+        let start = Instant::now();
+        let looked_up: Vec<_> = if threads <= 1 {
+            paths.iter().map(look_up).collect()
+        } else {
+            thread::scope(|scope| {
+                let lookups: Vec<_> = paths.chunks(paths.len().div_ceil(threads))
+                    .map(|chunk| scope.spawn(move || chunk.iter().map(look_up).collect::<Vec<_>>()))
+                    .collect();
+                lookups.into_iter().flat_map(|lookup| lookup.join().unwrap()).collect()
+            })
+        };
+        let elapsed = start.elapsed();
+        if let Some(timings) = &finder.timings {
+            timings.record_lookups(dir, paths.len(), elapsed);
+        }
+        if tuning.adaptive {
+            self.threads = adapt(threads, tuning.stat_threads, paths.len(), elapsed);
+            if self.threads != threads {
+                trace::event(Level::Debug, || format!("looking up entries on {} threads", self.threads));
+            }
+        }
+        looked_up
+    }
+
+    /// Reports `path`, found `depth` levels below the root at index `root`
+    /// in the directories of `ancestors`, if it matches, and returns the
+    /// ancestors of its entries if it is a directory to read next. Looks up
+    /// its metadata unless given it in `looked_up`.
+    fn visit(&mut self, path: &Path, depth: u32, root: usize, ignores: &Ignores, ancestors: &Ancestors, looked_up: Option<Result<Metadata, Error>>) -> Option<Ancestors> {
+        let finder = self.finder;
+        let lookup = || finder.retries.run(|| finder.file_system.metadata(path, MetadataMode::Follow));
+        let looked_up = match (looked_up, &finder.timings) {
+            (Some(looked_up), _) => looked_up,
+            (None, Some(timings)) => timings.time(Phase::Stat, path.parent().filter(|_| depth > 0), lookup),
+            (None, None) => lookup(),
+        };
+        let meta = match looked_up {
+            Ok(meta) => meta,
+            Err(e) => {
+                if let Some(on_error) = &finder.on_error {
+                    on_error(path, &e);
+                }
+                if let Some(metrics) = finder.metrics.as_ref().filter(|_| e.kind() != io::ErrorKind::NotFound) {
+                    metrics.error();
+                }
+                // Entries gone since they were listed, or dangling symlinks,
+                // aren't worth reporting.
+                if e.kind() != io::ErrorKind::NotFound {
+                    self.skip(path, SkipReason::Inaccessible { kind: e.kind(), message: e.to_string() });
+                }
+                return None;
+            }
+        };
+        if let Some(ignores) = ignores {
+            if ignores.is_ignored(path, meta.is_dir()) {
+                trace::event(Level::Debug, || format!("ignoring {}", path.display()));
+                return None;
+            }
+        }
+        if let Some(usage) = finder.usage.as_ref().filter(|_| meta.is_file()) {
+            usage.record(meta.allocated());
+        }
+        let matched = match depth >= finder.min_depth && (meta.is_file() || meta.is_dir() && finder.include_dirs) {
+            true => self.report(path.to_path_buf(), depth, root),
+            false => None,
+        };
+        let mut dir = meta.is_dir() && depth < self.max_depth;
+        let id = meta.id().or_else(|| if dir { finder.file_system.id(path, MetadataMode::Follow) } else { None });
+        if dir && id.is_some_and(|id| is_ancestor(ancestors, id)) {
+            trace::event(Level::Warn, || format!("skipping {}, which loops back to a directory it is in", path.display()));
+            self.skip(path, SkipReason::Loop);
+            dir = false;
+        }
+        if let Some(cache) = &mut self.cache {
+            cache.record(path, matched, dir);
+        }
+        if !dir || self.pruned(path, depth) {
+            return None;
+        }
+        match id {
+            Some(id) => Some(Some(Arc::new(Ancestor { id, parent: ancestors.clone() }))),
+            None => Some(ancestors.clone()),
+        }
+    }
+
+    /// Like `visit()`, for an entry of a directory answered from the result
+    /// cache: reports it if it matched, without looking it up.
+    fn replay(&mut self, path: &Path, depth: u32, root: usize, entry: &CachedEntry) -> bool {
+        if entry.matched {
+            let file = self.finder.file_info(path.to_path_buf()).found_below(self.roots[root].clone(), depth);
+            self.emit(file, entry.id.clone());
+        }
+        entry.dir && !self.pruned(path, depth)
+    }
+
+    /// Returns true if a callback of `Finder::prune_if()`, a marker of
+    /// `Finder::prune_if_contains()`, a rule of `Finder::excludes()`, a
+    /// duplicate mount of `Finder::dedup_mounts()`, a type of
+    /// `Finder::exclude_fs_type()`, a snapshot of `Finder::skip_snapshots()`
+    /// or a filter nothing below it can pass keeps the walk out of the
+    /// directory at `path`, `depth` levels below its root.
+    fn pruned(&self, path: &Path, depth: u32) -> bool {
+        let finder = self.finder;
+        let pruned = finder.prune.iter().any(|prune| prune(path))
+            || finder.prune_markers.iter().any(|marker| contains_marker(finder.file_system.as_ref(), path, marker))
+            || finder.excludes.is_excluded(path, depth, finder.file_system.as_ref())
+            || finder.duplicate_mounts.as_ref().is_some_and(|mounts| mounts.contains(path))
+            || !finder.excluded_fs_types.is_empty() && finder.file_system.fs_type(path).is_some_and(|fs_type| finder.excluded_fs_types.contains(&fs_type))
+            || finder.skip_snapshots && depth > 0 && snapshot::is_snapshot(finder.file_system.as_ref(), path)
+            || finder.filters.iter().any(|filter| !filter.may_match_below(path));
+        if pruned {
+            trace::event(Level::Debug, || format!("pruning {}", path.display()));
+        }
+        pruned
+    }
+
+    /// Reports the entry at `path` if it passes the filters, and returns
+    /// whether it did, with what identifies it if matches are deduplicated.
+    fn report(&mut self, path: PathBuf, depth: u32, root: usize) -> Option<Option<FileId>> {
+        let finder = self.finder;
+        let file = finder.file_info(path).found_below(self.roots[root].clone(), depth);
+        let matches = match &finder.timings {
+            Some(timings) => timings.time(Phase::Filter, file.path().parent().filter(|_| depth > 0), || finder.meets_filter_criteria(&file)),
+            None => finder.meets_filter_criteria(&file),
+        };
+        if !matches {
+            return None;
+        }
+        let id = if self.finder.dedup { file_id(&file) } else { None };
+        self.emit(file, id.clone());
+        Some(id)
+    }
+
+    /// Hands out the match `file`, unless one of the same `id` already was.
+    fn emit(&mut self, file: FileInfo, id: Option<FileId>) {
+        if let Some(id) = id {
+            if !self.seen.insert(id) {
+                trace::event(Level::Debug, || format!("{} was already found", file.path_str()));
+                return;
+            }
+        }
+        if let Some(metrics) = &self.finder.metrics {
+            metrics.matched(1);
+        }
+        match self.finder.timings.clone() {
+            Some(timings) => timings.time(Phase::Output, None, || self.hand_out(file)),
+            None => self.hand_out(file),
+        }
+    }
+
+    /// Hands the match `file` to the visitor, or prints and collects it.
+    fn hand_out(&mut self, file: FileInfo) {
+        if self.visitor.is_some() {
+            self.event(WalkEvent::File(&file));
+            return;
+        }
+        if self.print {
+            println!("matching file: {}", file.path_str());
+        }
+        self.result.push(file.into_path_string());
+    }
+
+    /// Returns true if the walk is to stop, because the visitor broke or the
+    /// search was cancelled.
+    fn stop_requested(&mut self) -> bool {
+        if self.finder.cancel.as_ref().is_some_and(Cancel::is_cancelled) {
+            self.stopped = true;
+        }
+        self.stopped
+    }
+
+    /// Fails if the search was cancelled, rather than returning what it found
+    /// until then.
+    fn check_cancelled(&self) -> Result<(), Error> {
+        match self.finder.cancel.as_ref().is_some_and(Cancel::is_cancelled) {
+            true => Err(Error::new(io::ErrorKind::Interrupted, "The search was cancelled.")),
+            false => Ok(()),
+        }
+    }
+
+    /// Hands `event` to the visitor, if any, stopping the walk once it breaks.
+    fn event(&mut self, event: WalkEvent) {
+        if let Some(visitor) = &mut self.visitor {
+            self.stopped = visitor(event).is_break();
+        }
+    }
+
+    /// Starts reading the directory at `path`, and adds its ignore file to
+    /// the ones applying to its entries.
+    fn read_dir(&mut self, path: &Path, ignores: Ignores) -> Result<(DirEntries<'a>, Ignores), Error> {
+        let finder = self.finder;
+        trace::event(Level::Debug, || format!("reading directory {}", path.display()));
+        let open = || finder.retries.run(|| finder.file_system.read_dir_iter(path));
+        let mut entries = match &finder.timings {
+            Some(timings) => timings.entries(path, timings.time(Phase::ReadDir, Some(path), open)?),
+            None => open()?,
+        };
+        if let Some(metrics) = &finder.metrics {
+            metrics.directory_scanned();
+        }
+        if let Some((max, skipped)) = &finder.max_entries_per_dir {
+            let first: Vec<_> = entries.by_ref().take(max + 1).collect();
+            if first.len() > *max {
+                trace::event(Level::Warn, || format!("skipping {}, which has more than {} entries", path.display(), max));
+                skipped.record(path);
+                self.skip(path, SkipReason::TooManyEntries(*max));
+                return Ok((Box::new(std::iter::empty()), ignores));
+            }
+            entries = Box::new(first.into_iter());
+        }
+        let ignores = if finder.ignore_files { finder.read_ignore_file(path, ignores) } else { ignores };
+        Ok((entries, ignores))
+    }
+
+    /// Records that `path` was left out, if the walk keeps track of it.
+    fn skip(&mut self, path: &Path, reason: SkipReason) {
+        if let Some(cache) = &mut self.cache {
+            cache.forget(path);
+        }
+        if let Some(skipped) = &mut self.skipped {
+            skipped.push(SkippedEntry { path: path.to_path_buf(), reason });
+        }
+    }
+
+    /// Leaves out the directory at `path`, which failed to be read with `e`,
+    /// if the walk keeps going without such entries, or else fails with `e`.
+    /// Running out of file descriptors always fails.
+    fn skip_or_fail(&mut self, path: &Path, e: Error) -> Result<(), Error> {
+        if self.skipped.is_none() && self.finder.on_error.is_none() || too_many_open_files(&e) {
+            return Err(e);
+        }
+        trace::event(Level::Warn, || format!("cannot read {}: {}", path.display(), e));
+        if let Some(on_error) = &self.finder.on_error {
+            on_error(path, &e);
+        }
+        if let Some(metrics) = &self.finder.metrics {
+            metrics.error();
+        }
+        self.skip(path, SkipReason::Inaccessible { kind: e.kind(), message: e.to_string() });
+        Ok(())
+    }
+}
+
+/// How many threads to look up entries on next, after `lookups` of them took
+/// `elapsed` on `threads`: twice as many if they waited on the storage, half
+/// as many if they were answered from caches, between 1 and `max`.
+fn adapt(threads: usize, max: usize, lookups: usize, elapsed: Duration) -> usize {
+    let latency = elapsed.mul_f64(threads as f64 / lookups.max(1) as f64);
+    if latency > SLOW_LOOKUP {
+        (threads * 2).min(max.max(1))
+    } else if latency < FAST_LOOKUP {
+        (threads / 2).max(1)
+    } else {
+        threads
+    }
+}
+
+/// Returns true if the directory at `dir` of `file_system` contains a file
+/// named `marker`, which for a `CACHEDIR.TAG` has to start with the signature
+/// of the Cache Directory Tagging spec.
+pub(crate) fn contains_marker(file_system: &dyn FileSystem, dir: &Path, marker: &str) -> bool {
+    let path = dir.join(marker);
+    if marker != CACHEDIR_TAG {
+        return file_system.metadata(&path, MetadataMode::NoFollow).is_ok();
+    }
+    let mut signature = [0; CACHEDIR_SIGNATURE.len()];
+    file_system.open(&path).and_then(|mut file| file.read_exact(&mut signature)).is_ok()
+        && signature == CACHEDIR_SIGNATURE
+}
+
+/// Returns true if `e` is EMFILE or ENFILE, which have the same numbers on
+/// every unix.
+fn too_many_open_files(e: &Error) -> bool {
+    cfg!(unix) && matches!(e.raw_os_error(), Some(23) | Some(24))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Read;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::{dir, file};
+    use crate::testfs::Entry;
+    use crate::vfs::{FileSystem, MemoryFileSystem, Metadata};
+
+    fn finder() -> Finder {
+        let fs = dir! {
+            file!("a.rs"),
+            "b" => { file!("c.rs"), "d" => { file!("e.rs") } },
+            file!("f.rs"),
+        };
+        Finder::new(String::from(".")).file_system(fs)
+    }
+
+    #[test]
+    fn traversal_orders() {
+        assert_eq!(vec!["./a.rs", "./f.rs", "./b/c.rs", "./b/d/e.rs"], finder().find(99).unwrap());
+        assert_eq!(vec!["./a.rs", "./b/c.rs", "./b/d/e.rs", "./f.rs"],
+                   finder().traversal(Traversal::DepthFirst).find(99).unwrap());
+        assert_eq!(vec!["./a.rs", "./b/c.rs", "./f.rs"], finder().traversal(Traversal::DepthFirst).find(2).unwrap());
+    }
+
+    /// A tree of nested directories that fails with EMFILE when more than
+    /// `limit` of them are open at once.
+    struct FewDescriptors {
+        fs: MemoryFileSystem,
+        open: Arc<AtomicUsize>,
+        limit: usize,
+    }
+
+    /// Counts an open directory until dropped.
+    struct Descriptor(Arc<AtomicUsize>);
+
+    impl Drop for Descriptor {
+        fn drop(&mut self) {
+            self.0.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    impl FileSystem for FewDescriptors {
+        fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>, Error> {
+            self.fs.read_dir(path)
+        }
+
+        fn read_dir_iter(&self, path: &Path) -> Result<DirEntries<'_>, Error> {
+            if self.open.fetch_add(1, Ordering::SeqCst) >= self.limit {
+                self.open.fetch_sub(1, Ordering::SeqCst);
+                return Err(Error::from_raw_os_error(24));
+            }
+            let descriptor = Descriptor(self.open.clone());
+            Ok(Box::new(self.fs.read_dir(path)?.into_iter().map(move |entry| {
+                let _open = &descriptor;
+                Ok(entry)
+            })))
+        }
+
+        fn metadata(&self, path: &Path, mode: MetadataMode) -> Result<Metadata, Error> {
+            self.fs.metadata(path, mode)
+        }
+
+        fn open(&self, path: &Path) -> Result<Box<dyn Read + Send>, Error> {
+            self.fs.open(path)
+        }
+    }
+
+    #[test]
+    fn depth_first_within_few_descriptors() {
+        let mut fs = MemoryFileSystem::new();
+        fs.add_file("a/b/c/d/e/f.rs", "").add_file("a/b/c/g.rs", "").add_file("a/h.rs", "");
+        let search = |limit, max_open_dirs| Finder::new(String::from("."))
+            .file_system(FewDescriptors { fs: fs.clone(), open: Arc::new(AtomicUsize::new(0)), limit })
+            .traversal(Traversal::DepthFirst)
+            .max_open_dirs(max_open_dirs)
+            .find(99);
+        let expected = vec!["./a/b/c/d/e/f.rs", "./a/b/c/g.rs", "./a/h.rs"];
+        assert_eq!(expected, search(2, 100).unwrap());
+        assert_eq!(expected, search(2, 1).unwrap());
+        assert_eq!(expected, search(100, 100).unwrap());
+        assert!(search(0, 100).is_err());
+    }
+
+    #[test]
+    fn crowded_dirs_are_skipped() {
+        let fs = dir! {
+            file!("a.rs"),
+            "b" => { file!("c.rs") },
+            "cache" => { file!("1"), file!("2"), file!("3"), file!("4") },
+        };
+        for traversal in [Traversal::BreadthFirst, Traversal::DepthFirst] {
+            let skipped = SkippedDirs::new();
+            let found = Finder::new(String::from(".")).file_system(fs.clone())
+                .traversal(traversal)
+                .max_entries_per_dir(3, &skipped)
+                .find(99)
+                .unwrap();
+            assert_eq!(vec!["./a.rs", "./b/c.rs"], found);
+            assert_eq!(vec![PathBuf::from("./cache")], skipped.report());
+        }
+        let skipped = SkippedDirs::new();
+        assert_eq!(4, finder().max_entries_per_dir(3, &skipped).find(99).unwrap().len());
+        assert!(skipped.report().is_empty());
+    }
+
+    #[test]
+    fn symlink_loops_are_walked_once() {
+        let fs = dir! {
+            file!("a.rs"),
+            "b" => { file!("c.rs"), Entry::symlink("up", ".."), Entry::symlink("up2", "..") },
+            "d" => { Entry::symlink("b", "../b") },
+        };
+        for traversal in [Traversal::BreadthFirst, Traversal::DepthFirst] {
+            let outcome = Finder::new(String::from(".")).file_system(fs.clone())
+                .traversal(traversal)
+                .dedup(false)
+                .find_outcome(crate::UNLIMITED_DEPTH)
+                .unwrap();
+            let mut found = outcome.matches;
+            found.sort();
+            // A symlink to a directory that isn't one of its own parents is still walked.
+            assert_eq!(vec!["./a.rs", "./b/c.rs", "./d/b/c.rs"], found);
+            let mut loops: Vec<_> = outcome.skipped.into_iter()
+                .inspect(|skipped| assert_eq!(SkipReason::Loop, skipped.reason))
+                .map(|skipped| skipped.path)
+                .collect();
+            loops.sort();
+            let expected: Vec<_> = ["./b/up", "./b/up2", "./d/b/up", "./d/b/up2"].iter().map(PathBuf::from).collect();
+            assert_eq!(expected, loops);
+        }
+    }
+
+    /// A tree whose `locked` directory can't be read, nor the metadata of
+    /// its `secret` file looked up.
+    struct Locked {
+        fs: MemoryFileSystem,
+    }
+
+    fn denied() -> Error {
+        Error::new(io::ErrorKind::PermissionDenied, "permission denied")
+    }
+
+    impl FileSystem for Locked {
+        fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>, Error> {
+            if path.ends_with("locked") {
+                return Err(denied());
+            }
+            self.fs.read_dir(path)
+        }
+
+        fn metadata(&self, path: &Path, mode: MetadataMode) -> Result<Metadata, Error> {
+            if path.ends_with("secret") {
+                return Err(denied());
+            }
+            self.fs.metadata(path, mode)
+        }
+
+        fn open(&self, path: &Path) -> Result<Box<dyn Read + Send>, Error> {
+            self.fs.open(path)
+        }
+    }
+
+    #[test]
+    fn outcomes_carry_what_was_skipped() {
+        let mut fs = MemoryFileSystem::new();
+        fs.add_file("a.rs", "").add_file("locked/b.rs", "").add_file("secret", "");
+        for i in 1..=5 {
+            fs.add_file(format!("cache/{}", i), "");
+        }
+        let finder = || Finder::new(String::from(".")).file_system(Locked { fs: fs.clone() });
+        assert_eq!(io::ErrorKind::PermissionDenied, finder().find(99).unwrap_err().kind());
+        for traversal in [Traversal::BreadthFirst, Traversal::DepthFirst] {
+            let outcome = finder().traversal(traversal).max_entries_per_dir(4, &SkippedDirs::new()).find_outcome(99).unwrap();
+            assert_eq!(vec!["./a.rs"], outcome.matches);
+            let mut skipped = outcome.skipped.clone();
+            skipped.sort_by(|a, b| a.path.cmp(&b.path));
+            let denied = || SkipReason::Inaccessible { kind: io::ErrorKind::PermissionDenied, message: String::from("permission denied") };
+            assert_eq!(vec![
+                SkippedEntry { path: PathBuf::from("./cache"), reason: SkipReason::TooManyEntries(4) },
+                SkippedEntry { path: PathBuf::from("./locked"), reason: denied() },
+                SkippedEntry { path: PathBuf::from("./secret"), reason: denied() },
+            ], skipped);
+            assert!(!outcome.truncated);
+        }
+        let budget = crate::budget::ScanBudget::new(0);
+        let fs = dir! { file!("a.txt" => "x"), file!("b.txt" => "x") };
+        let outcome = Finder::new(String::from(".")).file_system(fs).scan_budget(&budget).valid_utf8().find_outcome(99).unwrap();
+        assert!(outcome.truncated);
+    }
+
+    #[test]
+    fn errors_are_handed_to_the_callback() {
+        let mut fs = MemoryFileSystem::new();
+        fs.add_file("a.rs", "").add_file("locked/b.rs", "").add_file("secret", "");
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let seen = errors.clone();
+        let found = Finder::new(String::from("."))
+            .file_system(Locked { fs })
+            .on_error(move |path, e| seen.lock().unwrap().push((path.to_path_buf(), e.kind())))
+            .find(99)
+            .unwrap();
+        assert_eq!(vec!["./a.rs"], found);
+        let mut errors = errors.lock().unwrap().clone();
+        errors.sort();
+        assert_eq!(vec![
+            (PathBuf::from("./locked"), io::ErrorKind::PermissionDenied),
+            (PathBuf::from("./secret"), io::ErrorKind::PermissionDenied),
+        ], errors);
+    }
+
+    #[test]
+    fn capped_queue_continues_depth_first() {
+        // Only 'a.rs' fits in the queue, 'b' and 'f.rs' are walked right away.
+        assert_eq!(vec!["./b/c.rs", "./b/d/e.rs", "./f.rs", "./a.rs"], finder().max_queued(1).find(99).unwrap());
+        let mut found = finder().max_queued(0).find(99).unwrap();
+        found.sort();
+        let mut expected = finder().find(99).unwrap();
+        expected.sort();
+        assert_eq!(expected, found);
+    }
+
+    /// A tree whose `mnt` directory is an NFS mount.
+    struct Mounted {
+        fs: MemoryFileSystem,
+    }
+
+    impl FileSystem for Mounted {
+        fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>, Error> {
+            self.fs.read_dir(path)
+        }
+
+        fn metadata(&self, path: &Path, mode: MetadataMode) -> Result<Metadata, Error> {
+            self.fs.metadata(path, mode)
+        }
+
+        fn open(&self, path: &Path) -> Result<Box<dyn Read + Send>, Error> {
+            self.fs.open(path)
+        }
+
+        fn fs_type(&self, path: &Path) -> Option<String> {
+            Some(String::from(if path.starts_with("./mnt") { "nfs" } else { "ext4" }))
+        }
+    }
+
+    #[test]
+    fn excluded_fs_types_are_pruned() {
+        let mut fs = MemoryFileSystem::new();
+        fs.add_file("a.rs", "").add_file("mnt/b.rs", "").add_file("mnt/c/d.rs", "");
+        let finder = || Finder::new(String::from(".")).file_system(Mounted { fs: fs.clone() });
+        assert_eq!(vec!["./a.rs"], finder().exclude_fs_type("NFS").find(99).unwrap());
+        assert_eq!(3, finder().exclude_fs_type("tmpfs").find(99).unwrap().len());
+        assert!(finder().exclude_fs_type("ext4").find(99).unwrap().is_empty());
+    }
+
+    #[test]
+    fn tuned_walks_find_the_same() {
+        let tuning = TraversalTuning { readdir_batch: 2, queue_capacity: 0, stat_threads: 3, adaptive: false };
+        assert_eq!(finder().find(99).unwrap(), finder().tuning(tuning).find(99).unwrap());
+        assert_eq!(finder().max_queued(1).find(99).unwrap(), finder().tuning(tuning).max_queued(1).find(99).unwrap());
+
+        let mut fs = MemoryFileSystem::new();
+        fs.add_file("a.rs", "").add_file("locked/b.rs", "").add_file("secret", "").add_file("z.rs", "");
+        let outcome = Finder::new(String::from(".")).file_system(Locked { fs }).tuning(tuning).find_outcome(99).unwrap();
+        assert_eq!(vec!["./a.rs", "./z.rs"], outcome.matches);
+        let skipped: Vec<_> = outcome.skipped.iter().map(|entry| entry.path.to_str().unwrap()).collect();
+        assert_eq!(vec!["./locked", "./secret"], skipped);
+    }
+
+    #[test]
+    fn threads_follow_latency() {
+        let ms = Duration::from_millis;
+        assert_eq!(2, adapt(1, 32, 100, ms(100)));
+        assert_eq!(32, adapt(32, 32, 100, ms(10)));
+        assert_eq!(8, adapt(16, 32, 100, Duration::from_micros(200)));
+        assert_eq!(16, adapt(16, 32, 1000, ms(10)));
+        assert_eq!(1, adapt(1, 32, 100, Duration::ZERO));
+        assert_eq!(finder().find(99).unwrap(), finder().tuning(TraversalTuning::auto()).find(99).unwrap());
+    }
+
+}
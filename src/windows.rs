@@ -0,0 +1,245 @@
+/*
+Fast enumeration of directories on Windows.
+
+Listing a directory on Windows already returns the attributes, size and
+modification time of its entries, but a search then asks for every entry's
+metadata again, which opens each file. 'WindowsFileSystem' lists directories
+with FindFirstFileExW, asking for the basic information only (no 8.3 names)
+and fetching entries in large batches, and keeps what the listing returned
+to answer the metadata requests that follow. This typically halves the time
+of a search on NTFS.
+
+Symlinks and junctions are reparse points whose listing describes the link,
+not the file it points to, so their metadata is still looked up separately,
+through 'std::fs'.
+
+Used by default on Windows.
+
+ */
+
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io::{Error, Read};
+use std::os::raw::{c_int, c_void};
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use crate::filter::MetadataMode;
+use crate::vfs::{FileSystem, FileType, Metadata, StdFileSystem};
+
+
+type Handle = *mut c_void;
+
+const INVALID_HANDLE_VALUE: Handle = -1isize as Handle;
+const FIND_EX_INFO_BASIC: c_int = 1;
+const FIND_EX_SEARCH_NAME_MATCH: c_int = 0;
+const FIND_FIRST_EX_LARGE_FETCH: u32 = 2;
+const FILE_ATTRIBUTE_DIRECTORY: u32 = 0x10;
+const FILE_ATTRIBUTE_REPARSE_POINT: u32 = 0x400;
+const ERROR_NO_MORE_FILES: i32 = 18;
+const FILE_SHARE_READ: u32 = 1;
+const FILE_SHARE_WRITE: u32 = 2;
+const FILE_SHARE_DELETE: u32 = 4;
+const OPEN_EXISTING: u32 = 3;
+const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+const FILE_FLAG_OPEN_REPARSE_POINT: u32 = 0x0020_0000;
+
+/// 100ns intervals between 1601-01-01, where `FILETIME` starts, and the
+/// unix epoch.
+const FILETIME_UNIX_EPOCH: u64 = 116_444_736_000_000_000;
+
+#[repr(C)]
+struct FileTime {
+    low: u32,
+    high: u32,
+}
+
+/// `WIN32_FIND_DATAW` from `<minwinbase.h>`.
+#[repr(C)]
+struct FindData {
+    file_attributes: u32,
+    creation_time: FileTime,
+    last_access_time: FileTime,
+    last_write_time: FileTime,
+    file_size_high: u32,
+    file_size_low: u32,
+    reserved0: u32,
+    reserved1: u32,
+    file_name: [u16; 260],
+    alternate_file_name: [u16; 14],
+}
+
+/// `BY_HANDLE_FILE_INFORMATION` from `<fileapi.h>`.
+#[repr(C)]
+struct FileInformation {
+    file_attributes: u32,
+    creation_time: FileTime,
+    last_access_time: FileTime,
+    last_write_time: FileTime,
+    volume_serial_number: u32,
+    file_size_high: u32,
+    file_size_low: u32,
+    number_of_links: u32,
+    file_index_high: u32,
+    file_index_low: u32,
+}
+
+#[link(name = "kernel32")]
+extern "system" {
+    fn FindFirstFileExW(file_name: *const u16, info_level: c_int, find_data: *mut FindData,
+                        search_op: c_int, search_filter: *mut c_void, additional_flags: u32) -> Handle;
+    fn FindNextFileW(find: Handle, find_data: *mut FindData) -> i32;
+    fn FindClose(find: Handle) -> i32;
+    fn CreateFileW(file_name: *const u16, desired_access: u32, share_mode: u32, security_attributes: *mut c_void,
+                   creation_disposition: u32, flags_and_attributes: u32, template_file: Handle) -> Handle;
+    fn GetFileInformationByHandle(file: Handle, information: *mut FileInformation) -> i32;
+    fn CloseHandle(object: Handle) -> i32;
+}
+
+/// The real filesystem, taking the metadata of entries from the listings of
+/// their directories.
+#[derive(Debug, Default)]
+pub struct WindowsFileSystem {
+    /// The metadata of listed entries, until it is asked for.
+    listed: Mutex<HashMap<PathBuf, Metadata>>,
+}
+
+impl WindowsFileSystem {
+    pub fn new() -> WindowsFileSystem {
+        WindowsFileSystem::default()
+    }
+}
+
+impl FileSystem for WindowsFileSystem {
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>, Error> {
+        let pattern: Vec<u16> = path.join("*").as_os_str().encode_wide().chain(Some(0)).collect();
+        // SAFETY: `FindData` is plain integers.
+        let mut data: FindData = unsafe { std::mem::zeroed() };
+        // SAFETY: `pattern` is NUL terminated and `data` is a `WIN32_FIND_DATAW`.
+        let find = unsafe {
+            FindFirstFileExW(pattern.as_ptr(), FIND_EX_INFO_BASIC, &mut data, FIND_EX_SEARCH_NAME_MATCH,
+                             std::ptr::null_mut(), FIND_FIRST_EX_LARGE_FETCH)
+        };
+        if find == INVALID_HANDLE_VALUE {
+            return Err(Error::last_os_error());
+        }
+        let mut entries = Vec::new();
+        let mut listed = Vec::new();
+        let result = loop {
+            let len = data.file_name.iter().position(|&c| c == 0).unwrap_or(data.file_name.len());
+            let name = OsString::from_wide(&data.file_name[..len]);
+            if name != "." && name != ".." {
+                let entry = path.join(name);
+                if data.file_attributes & FILE_ATTRIBUTE_REPARSE_POINT == 0 {
+                    listed.push((entry.clone(), metadata(&data)));
+                }
+                entries.push(entry);
+            }
+            // SAFETY: `find` is open and `data` is a `WIN32_FIND_DATAW`.
+            if unsafe { FindNextFileW(find, &mut data) } == 0 {
+                let e = Error::last_os_error();
+                break if e.raw_os_error() == Some(ERROR_NO_MORE_FILES) { Ok(entries) } else { Err(e) };
+            }
+        };
+        // SAFETY: `find` is open, and closed only here.
+        unsafe { FindClose(find) };
+        self.listed.lock().unwrap().extend(listed);
+        result
+    }
+
+    fn metadata(&self, path: &Path, mode: MetadataMode) -> Result<Metadata, Error> {
+        if let Some(metadata) = self.listed.lock().unwrap().remove(path) {
+            return Ok(metadata);
+        }
+        StdFileSystem.metadata(path, mode)
+    }
+
+    fn open(&self, path: &Path) -> Result<Box<dyn Read + Send>, Error> {
+        StdFileSystem.open(path)
+    }
+
+    fn read_at(&self, path: &Path, offset: u64, len: usize) -> Result<Vec<u8>, Error> {
+        StdFileSystem.read_at(path, offset, len)
+    }
+
+    fn id(&self, path: &Path, mode: MetadataMode) -> Option<(u64, u64)> {
+        StdFileSystem.id(path, mode)
+    }
+
+    fn fs_type(&self, path: &Path) -> Option<String> {
+        StdFileSystem.fs_type(path)
+    }
+}
+
+/// The volume serial number and file index of the file at `path`, which
+/// identify it like device and inode numbers do on unix. The file, or the
+/// symlink itself with `MetadataMode::NoFollow`, is opened to look them up.
+pub(crate) fn file_index(path: &Path, mode: MetadataMode) -> Result<(u64, u64), Error> {
+    let name: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut flags = FILE_FLAG_BACKUP_SEMANTICS;
+    if mode == MetadataMode::NoFollow {
+        flags |= FILE_FLAG_OPEN_REPARSE_POINT;
+    }
+    // SAFETY: `name` is NUL terminated. Asking for no access, only the
+    // attributes can be read, which is enough for directories too.
+    let file = unsafe {
+        CreateFileW(name.as_ptr(), 0, FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE, std::ptr::null_mut(),
+                    OPEN_EXISTING, flags, std::ptr::null_mut())
+    };
+    if file == INVALID_HANDLE_VALUE {
+        return Err(Error::last_os_error());
+    }
+    // SAFETY: `FileInformation` is plain integers.
+    let mut info: FileInformation = unsafe { std::mem::zeroed() };
+    // SAFETY: `file` is open and `info` is a `BY_HANDLE_FILE_INFORMATION`.
+    let result = if unsafe { GetFileInformationByHandle(file, &mut info) } == 0 {
+        Err(Error::last_os_error())
+    } else {
+        Ok((info.volume_serial_number as u64, (info.file_index_high as u64) << 32 | info.file_index_low as u64))
+    };
+    // SAFETY: `file` is open, and closed only here.
+    unsafe { CloseHandle(file) };
+    result
+}
+
+/// The metadata of an entry that isn't a reparse point, from its listing.
+fn metadata(data: &FindData) -> Metadata {
+    let file_type = if data.file_attributes & FILE_ATTRIBUTE_DIRECTORY != 0 { FileType::Dir } else { FileType::File };
+    let len = ((data.file_size_high as u64) << 32) | data.file_size_low as u64;
+    let intervals = ((data.last_write_time.high as u64) << 32) | data.last_write_time.low as u64;
+    let modified = if intervals >= FILETIME_UNIX_EPOCH {
+        Some(SystemTime::UNIX_EPOCH + Duration::from_nanos((intervals - FILETIME_UNIX_EPOCH) * 100))
+    } else {
+        SystemTime::UNIX_EPOCH.checked_sub(Duration::from_nanos((FILETIME_UNIX_EPOCH - intervals) * 100))
+    };
+    Metadata::new(file_type, len, modified)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn listed_metadata_like_std() {
+        let windows = WindowsFileSystem::new();
+        let mut entries = windows.read_dir(Path::new("src")).unwrap();
+        let mut expected = StdFileSystem.read_dir(Path::new("src")).unwrap();
+        entries.sort();
+        expected.sort();
+        assert_eq!(expected, entries);
+        for entry in &entries {
+            let meta = windows.metadata(entry, MetadataMode::Follow).unwrap();
+            let expected = StdFileSystem.metadata(entry, MetadataMode::Follow).unwrap();
+            assert_eq!(expected.file_type(), meta.file_type());
+            assert_eq!(expected.modified(), meta.modified());
+            if meta.is_file() {
+                assert_eq!(expected.len(), meta.len());
+            }
+        }
+        assert!(windows.listed.lock().unwrap().is_empty());
+        assert!(windows.read_dir(Path::new("non_existing_dir")).is_err());
+    }
+
+}
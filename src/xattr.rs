@@ -0,0 +1,88 @@
+/*
+Extended attributes of files on Linux.
+
+Some of what security reviews look for isn't in the metadata of files but in
+their extended attributes: POSIX ACLs in 'system.posix_acl_access', file
+capabilities in 'security.capability'. 'get()' reads one, through
+getxattr(2), or lgetxattr(2) for the symlink itself.
+
+Like for the 'linux' module, the system calls are declared here rather than
+taken from a crate.
+
+ */
+
+use std::ffi::CString;
+use std::io::{self, Error};
+use std::os::raw::{c_char, c_void};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use crate::filter::MetadataMode;
+
+
+const ENODATA: i32 = 61;
+const ENOTSUP: i32 = 95;
+const ERANGE: i32 = 34;
+
+extern "C" {
+    fn getxattr(path: *const c_char, name: *const c_char, value: *mut c_void, size: usize) -> isize;
+    fn lgetxattr(path: *const c_char, name: *const c_char, value: *mut c_void, size: usize) -> isize;
+}
+
+/// The value of the extended attribute `name` of `path`, or `None` if it
+/// doesn't have it or the filesystem has no extended attributes. Symlinks are
+/// followed unless `mode` is `NoFollow`.
+pub fn get(path: &Path, name: &str, mode: MetadataMode) -> Result<Option<Vec<u8>>, Error> {
+    let invalid = |_| Error::new(io::ErrorKind::InvalidInput, "path or name with a NUL byte");
+    let path = CString::new(path.as_os_str().as_bytes()).map_err(invalid)?;
+    let name = CString::new(name).map_err(invalid)?;
+    let call = |value: *mut c_void, size: usize| unsafe {
+        match mode {
+            MetadataMode::Follow => getxattr(path.as_ptr(), name.as_ptr(), value, size),
+            MetadataMode::NoFollow => lgetxattr(path.as_ptr(), name.as_ptr(), value, size),
+        }
+    };
+    loop {
+        // Asks for the size first; the attribute may grow in between, in
+        // which case it is asked for again.
+        let size = call(std::ptr::null_mut(), 0);
+        if size < 0 {
+            return absent_or(Error::last_os_error());
+        }
+        let mut value = vec![0u8; size as usize];
+        let read = call(value.as_mut_ptr() as *mut c_void, value.len());
+        if read >= 0 {
+            value.truncate(read as usize);
+            return Ok(Some(value));
+        }
+        let e = Error::last_os_error();
+        if e.raw_os_error() != Some(ERANGE) {
+            return absent_or(e);
+        }
+    }
+}
+
+fn absent_or(e: Error) -> Result<Option<Vec<u8>>, Error> {
+    match e.raw_os_error() {
+        Some(ENODATA) | Some(ENOTSUP) => Ok(None),
+        _ => Err(e),
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn reads_attributes() {
+        let dir = std::env::temp_dir().join(format!("ffind-xattr-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("a.txt");
+        fs::write(&file, "").unwrap();
+        assert_eq!(None, get(&file, "user.ffind.missing", MetadataMode::Follow).unwrap());
+        assert_eq!(io::ErrorKind::NotFound, get(&dir.join("missing"), "user.ffind", MetadataMode::Follow).unwrap_err().kind());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+}
@@ -0,0 +1,280 @@
+/*
+XXH3, to tell the contents of files apart fast.
+
+The 64-bit XXH3 of xxHash 0.8, with no seed and the default secret, as
+'xxhsum -H3' prints it. It is no cryptographic hash: files made to collide
+can, but files that happen to be different practically never do, and it
+hashes many times faster than SHA-256, which makes it a fit for finding
+copies.
+
+'Xxh3' is streaming like 'Sha256': it keeps the input it can't hash yet, at
+most one stripe of 64 bytes past the first 240, since the last stripe and
+short inputs are hashed differently.
+
+ */
+
+use std::io::{self, Read};
+
+
+const PRIME32_1: u64 = 0x9E37_79B1;
+const PRIME32_2: u64 = 0x85EB_CA77;
+const PRIME32_3: u64 = 0xC2B2_AE3D;
+const PRIME64_1: u64 = 0x9E37_79B1_85EB_CA87;
+const PRIME64_2: u64 = 0xC2B2_AE3D_27D4_EB4F;
+const PRIME64_3: u64 = 0x1656_67B1_9E37_79F9;
+const PRIME64_4: u64 = 0x85EB_CA77_C2B2_AE63;
+const PRIME64_5: u64 = 0x27D4_EB2F_1656_67C5;
+
+const SECRET: [u8; 192] = [
+    0xb8, 0xfe, 0x6c, 0x39, 0x23, 0xa4, 0x4b, 0xbe, 0x7c, 0x01, 0x81, 0x2c, 0xf7, 0x21, 0xad, 0x1c,
+    0xde, 0xd4, 0x6d, 0xe9, 0x83, 0x90, 0x97, 0xdb, 0x72, 0x40, 0xa4, 0xa4, 0xb7, 0xb3, 0x67, 0x1f,
+    0xcb, 0x79, 0xe6, 0x4e, 0xcc, 0xc0, 0xe5, 0x78, 0x82, 0x5a, 0xd0, 0x7d, 0xcc, 0xff, 0x72, 0x21,
+    0xb8, 0x08, 0x46, 0x74, 0xf7, 0x43, 0x24, 0x8e, 0xe0, 0x35, 0x90, 0xe6, 0x81, 0x3a, 0x26, 0x4c,
+    0x3c, 0x28, 0x52, 0xbb, 0x91, 0xc3, 0x00, 0xcb, 0x88, 0xd0, 0x65, 0x8b, 0x1b, 0x53, 0x2e, 0xa3,
+    0x71, 0x64, 0x48, 0x97, 0xa2, 0x0d, 0xf9, 0x4e, 0x38, 0x19, 0xef, 0x46, 0xa9, 0xde, 0xac, 0xd8,
+    0xa8, 0xfa, 0x76, 0x3f, 0xe3, 0x9c, 0x34, 0x3f, 0xf9, 0xdc, 0xbb, 0xc7, 0xc7, 0x0b, 0x4f, 0x1d,
+    0x8a, 0x51, 0xe0, 0x4b, 0xcd, 0xb4, 0x59, 0x31, 0xc8, 0x9f, 0x7e, 0xc9, 0xd9, 0x78, 0x73, 0x64,
+    0xea, 0xc5, 0xac, 0x83, 0x34, 0xd3, 0xeb, 0xc3, 0xc5, 0x81, 0xa0, 0xff, 0xfa, 0x13, 0x63, 0xeb,
+    0x17, 0x0d, 0xdd, 0x51, 0xb7, 0xf0, 0xda, 0x49, 0xd3, 0x16, 0x55, 0x26, 0x29, 0xd4, 0x68, 0x9e,
+    0x2b, 0x16, 0xbe, 0x58, 0x7d, 0x47, 0xa1, 0xfc, 0x8f, 0xf8, 0xb8, 0xd1, 0x7a, 0xd0, 0x31, 0xce,
+    0x45, 0xcb, 0x3a, 0x8f, 0x95, 0x16, 0x04, 0x28, 0xaf, 0xd7, 0xfb, 0xca, 0xbb, 0x4b, 0x40, 0x7e,
+];
+
+const STRIPE_LEN: usize = 64;
+/// How many stripes are accumulated before the accumulators are scrambled.
+const STRIPES_PER_BLOCK: usize = (SECRET.len() - STRIPE_LEN) / 8;
+/// The longest input hashed without stripes.
+const MIDSIZE_MAX: usize = 240;
+
+/// An XXH3 hash being computed.
+#[derive(Debug, Clone)]
+pub struct Xxh3 {
+    acc: [u64; 8],
+    /// The input not hashed yet: all of it up to `MIDSIZE_MAX` bytes, then
+    /// what is left past the last stripe hashed.
+    pending: Vec<u8>,
+    /// The last stripe hashed, which the last stripe may overlap.
+    last: [u8; STRIPE_LEN],
+    stripes: usize,
+    len: u64,
+}
+
+impl Default for Xxh3 {
+    fn default() -> Xxh3 {
+        Xxh3::new()
+    }
+}
+
+impl Xxh3 {
+
+    pub fn new() -> Xxh3 {
+        Xxh3 {
+            acc: [PRIME32_3, PRIME64_1, PRIME64_2, PRIME64_3, PRIME64_4, PRIME32_2, PRIME64_5, PRIME32_1],
+            pending: Vec::new(),
+            last: [0; STRIPE_LEN],
+            stripes: 0,
+            len: 0,
+        }
+    }
+
+    /// Hashes everything read from `reader`.
+    pub fn read(mut reader: impl Read) -> io::Result<u64> {
+        let mut xxh = Xxh3::new();
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => return Ok(xxh.finish()),
+                Ok(n) => xxh.update(&buf[..n]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.len += data.len() as u64;
+        self.pending.extend_from_slice(data);
+        if self.len <= MIDSIZE_MAX as u64 {
+            return;
+        }
+        // Every stripe but the one the input ends in, which is hashed last.
+        let mut start = 0;
+        while self.pending.len() - start > STRIPE_LEN {
+            let stripe = &self.pending[start..start + STRIPE_LEN];
+            accumulate(&mut self.acc, stripe, &SECRET[self.stripes * 8..]);
+            self.stripes += 1;
+            if self.stripes == STRIPES_PER_BLOCK {
+                scramble(&mut self.acc);
+                self.stripes = 0;
+            }
+            start += STRIPE_LEN;
+        }
+        if start > 0 {
+            self.last.copy_from_slice(&self.pending[start - STRIPE_LEN..start]);
+            self.pending.drain(..start);
+        }
+    }
+
+    pub fn finish(self) -> u64 {
+        let input = &self.pending[..];
+        let len = input.len() as u64;
+        match self.len {
+            0 => xxh64_avalanche(read64(&SECRET[56..]) ^ read64(&SECRET[64..])),
+            1..=3 => {
+                let combined = (input[0] as u64) << 16 | (input[input.len() >> 1] as u64) << 24 | input[input.len() - 1] as u64 | len << 8;
+                xxh64_avalanche(combined ^ (read32(&SECRET) ^ read32(&SECRET[4..])))
+            }
+            4..=8 => {
+                let input64 = read32(&input[input.len() - 4..]).wrapping_add(read32(input) << 32);
+                rrmxmx(input64 ^ (read64(&SECRET[8..]) ^ read64(&SECRET[16..])), len)
+            }
+            9..=16 => {
+                let lo = read64(input) ^ (read64(&SECRET[24..]) ^ read64(&SECRET[32..]));
+                let hi = read64(&input[input.len() - 8..]) ^ (read64(&SECRET[40..]) ^ read64(&SECRET[48..]));
+                avalanche(len.wrapping_add(lo.swap_bytes()).wrapping_add(hi).wrapping_add(fold(lo, hi)))
+            }
+            17..=128 => {
+                let mut acc = len.wrapping_mul(PRIME64_1);
+                let pairs = (input.len() - 1) / 32;
+                for i in (0..=pairs).rev() {
+                    acc = acc.wrapping_add(mix16(&input[16 * i..], &SECRET[32 * i..]));
+                    acc = acc.wrapping_add(mix16(&input[input.len() - 16 * (i + 1)..], &SECRET[32 * i + 16..]));
+                }
+                avalanche(acc)
+            }
+            129..=240 => {
+                let mut acc = len.wrapping_mul(PRIME64_1);
+                for i in 0..8 {
+                    acc = acc.wrapping_add(mix16(&input[16 * i..], &SECRET[16 * i..]));
+                }
+                acc = avalanche(acc);
+                for i in 8..input.len() / 16 {
+                    acc = acc.wrapping_add(mix16(&input[16 * i..], &SECRET[16 * (i - 8) + 3..]));
+                }
+                avalanche(acc.wrapping_add(mix16(&input[input.len() - 16..], &SECRET[136 - 17..])))
+            }
+            _ => {
+                let mut acc = self.acc;
+                let mut stripe = [0; STRIPE_LEN];
+                stripe[..STRIPE_LEN - input.len()].copy_from_slice(&self.last[input.len()..]);
+                stripe[STRIPE_LEN - input.len()..].copy_from_slice(input);
+                accumulate(&mut acc, &stripe, &SECRET[SECRET.len() - STRIPE_LEN - 7..]);
+                let mut result = self.len.wrapping_mul(PRIME64_1);
+                for i in 0..4 {
+                    result = result.wrapping_add(fold(acc[2 * i] ^ read64(&SECRET[11 + 16 * i..]), acc[2 * i + 1] ^ read64(&SECRET[19 + 16 * i..])));
+                }
+                avalanche(result)
+            }
+        }
+    }
+}
+
+fn accumulate(acc: &mut [u64; 8], stripe: &[u8], secret: &[u8]) {
+    for i in 0..8 {
+        let value = read64(&stripe[8 * i..]);
+        let key = value ^ read64(&secret[8 * i..]);
+        acc[i ^ 1] = acc[i ^ 1].wrapping_add(value);
+        acc[i] = acc[i].wrapping_add((key & 0xFFFF_FFFF).wrapping_mul(key >> 32));
+    }
+}
+
+fn scramble(acc: &mut [u64; 8]) {
+    for (i, acc) in acc.iter_mut().enumerate() {
+        *acc = (*acc ^ (*acc >> 47) ^ read64(&SECRET[SECRET.len() - STRIPE_LEN + 8 * i..])).wrapping_mul(PRIME32_1);
+    }
+}
+
+fn mix16(input: &[u8], secret: &[u8]) -> u64 {
+    fold(read64(input) ^ read64(secret), read64(&input[8..]) ^ read64(&secret[8..]))
+}
+
+/// The 128-bit product of `a` and `b`, its halves xored together.
+fn fold(a: u64, b: u64) -> u64 {
+    let product = a as u128 * b as u128;
+    product as u64 ^ (product >> 64) as u64
+}
+
+fn avalanche(mut h: u64) -> u64 {
+    h ^= h >> 37;
+    h = h.wrapping_mul(0x1656_6791_9E37_79F9);
+    h ^ (h >> 32)
+}
+
+fn xxh64_avalanche(mut h: u64) -> u64 {
+    h ^= h >> 33;
+    h = h.wrapping_mul(PRIME64_2);
+    h ^= h >> 29;
+    h = h.wrapping_mul(PRIME64_3);
+    h ^ (h >> 32)
+}
+
+fn rrmxmx(mut h: u64, len: u64) -> u64 {
+    h ^= h.rotate_left(49) ^ h.rotate_left(24);
+    h = h.wrapping_mul(0x9FB2_1C65_1E98_DF25);
+    h ^= (h >> 35).wrapping_add(len);
+    h = h.wrapping_mul(0x9FB2_1C65_1E98_DF25);
+    h ^ (h >> 28)
+}
+
+fn read64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7]])
+}
+
+fn read32(bytes: &[u8]) -> u64 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as u64
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn xxh3(data: &[u8]) -> u64 {
+        Xxh3::read(data).unwrap()
+    }
+
+    /// `len` bytes counting up to 250 and over again, like the inputs the
+    /// expected hashes were computed for with libxxhash 0.8.
+    fn input(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    /// Lengths on either side of every way XXH3 hashes inputs, with their
+    /// hashes.
+    const EXPECTED: [(usize, u64); 11] = [
+        (0, 0x2d06_8005_38d3_94c2),
+        (3, 0x5f42_99fc_161c_9cbb),
+        (6, 0xa658_4d1d_9a6a_e704),
+        (12, 0x5ace_6a51_1c10_894b),
+        (17, 0x9ef3_41a9_9de3_7328),
+        (100, 0x004e_4f92_1a64_bd1c),
+        (200, 0xf42a_8864_feaf_0703),
+        (241, 0x02e8_cd95_421c_6d02),
+        (1024, 0xe5d7_8baf_a45b_2aa5),
+        (1025, 0xe95c_4228_8f28_186e),
+        (4096, 0x7135_ffa5_04f1_bc71),
+    ];
+
+    #[test]
+    fn known_hashes() {
+        assert_eq!(0x78af_5f94_892f_3950, xxh3(b"abc"));
+        for &(len, hash) in EXPECTED.iter() {
+            assert_eq!(hash, xxh3(&input(len)), "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn hashes_the_same_in_pieces() {
+        for &(len, hash) in EXPECTED.iter() {
+            let input = input(len);
+            for piece in [1, 7, 63, 64, 65, 240, 241, 1000] {
+                let mut xxh = Xxh3::new();
+                for chunk in input.chunks(piece) {
+                    xxh.update(chunk);
+                }
+                assert_eq!(hash, xxh.finish(), "{} bytes in pieces of {}", len, piece);
+            }
+        }
+    }
+
+}
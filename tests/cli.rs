@@ -0,0 +1,93 @@
+// Runs the rustfind program itself, for what only shows in how it exits.
+#![cfg(feature = "cli")]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+
+/// A directory of `count` files named `0.log`, `1.log`, ... removed once
+/// dropped.
+struct Tree(PathBuf);
+
+impl Tree {
+    fn new(name: &str, count: usize) -> Tree {
+        let dir = std::env::temp_dir().join(format!("ffind-cli-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for i in 0..count {
+            fs::write(dir.join(format!("{}.log", i)), "").unwrap();
+        }
+        Tree(dir)
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for Tree {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// The exit code of rustfind run with `args`.
+fn exit_code(args: &[&str]) -> i32 {
+    let output = Command::new(env!("CARGO_BIN_EXE_rustfind")).args(args).output().unwrap();
+    output.status.code().unwrap()
+}
+
+/// What rustfind run with `args` wrote to stderr.
+fn stderr(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_rustfind")).args(args).output().unwrap();
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn fails_past_the_most_matches() {
+    let tree = Tree::new("threshold", 3);
+    let root = tree.path().to_str().unwrap();
+    assert_eq!(0, exit_code(&["--extension", ".log", "--fail-if-more-than", "3", root]));
+    assert_eq!(1, exit_code(&["--extension", ".log", "--fail-if-more-than", "2", root]));
+    assert_eq!(0, exit_code(&["--extension", ".txt", "--fail-if-any", root]));
+    assert_eq!(1, exit_code(&["--extension", ".log", "--fail-if-any", root]));
+}
+
+#[test]
+fn counts_the_matches_reported() {
+    let tree = Tree::new("reported", 3);
+    let root = tree.path().to_str().unwrap();
+    // Searched twice, the files are found 6 times, but reported 3 times.
+    assert_eq!(0, exit_code(&["--extension", ".log", "--fail-if-more-than", "3", root, root]));
+    assert_eq!(1, exit_code(&["--extension", ".log", "--no-dedup", "--fail-if-more-than", "5", root, root]));
+    assert_eq!(0, exit_code(&["--extension", ".log", "--no-dedup", "--fail-if-more-than", "6", root, root]));
+    assert_eq!(1, exit_code(&["--fail-if-more-than", "x", root]));
+}
+
+#[test]
+fn profiles_with_and_without_a_name() {
+    let tree = Tree::new("profile", 1);
+    let root = tree.path().to_str().unwrap();
+    // Without a NAME, --profile reports where the search spent its time.
+    assert!(stderr(&["--profile", "--extension", ".log", root]).starts_with("PHASE"));
+    assert!(stderr(&[root, "--profile"]).starts_with("PHASE"));
+    assert_eq!("", stderr(&["--profile", "dev", "--extension", ".log", root]));
+    assert!(stderr(&["--profile", "bogus", root]).contains("Unknown profile 'bogus'"));
+}
+
+#[test]
+#[cfg(feature = "remote")]
+fn changes_only_local_files() {
+    let tree = Tree::new("remote", 1);
+    fs::write(tree.path().join("0.log"), "a\n").unwrap();
+    // The local files at the remote path are left alone, without connecting.
+    let root = format!("unreachable.invalid:{}", tree.path().to_str().unwrap());
+    let error = stderr(&["--replace", "a=>b", "--apply", &root]);
+    assert!(error.contains("--replace only changes local files"), "{}", error);
+    assert_eq!("a\n", fs::read_to_string(tree.path().join("0.log")).unwrap());
+    for args in [["--rename", "log=>txt"], ["--move-to", "/tmp"]] {
+        let error = stderr(&[args[0], args[1], "--apply", &root]);
+        assert!(error.contains("only move local files"), "{}", error);
+        assert!(tree.path().join("0.log").exists());
+    }
+}